@@ -0,0 +1,4 @@
+-- Administrative account deactivation, distinct from temporary lockout
+ALTER TABLE users ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE INDEX IF NOT EXISTS idx_users_is_active ON users(is_active);
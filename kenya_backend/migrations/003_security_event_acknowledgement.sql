@@ -0,0 +1,6 @@
+-- Acknowledgement workflow for the admin alert feed
+ALTER TABLE security_events ADD COLUMN acknowledged_by TEXT REFERENCES users (id);
+ALTER TABLE security_events ADD COLUMN acknowledged_at TEXT;
+ALTER TABLE security_events ADD COLUMN resolution_note TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_security_events_acknowledged_at ON security_events(acknowledged_at);
@@ -0,0 +1,4 @@
+-- Severity for prioritising alerts, with existing events treated as informational
+ALTER TABLE security_events ADD COLUMN severity TEXT NOT NULL DEFAULT 'info';
+
+CREATE INDEX IF NOT EXISTS idx_security_events_severity ON security_events(severity);
@@ -0,0 +1,5 @@
+-- GeoIP enrichment of login attempts (NULL when unresolved)
+ALTER TABLE login_attempts ADD COLUMN country_code TEXT;
+ALTER TABLE login_attempts ADD COLUMN city TEXT;
+ALTER TABLE login_attempts ADD COLUMN asn INTEGER;
+ALTER TABLE login_attempts ADD COLUMN asn_org TEXT;
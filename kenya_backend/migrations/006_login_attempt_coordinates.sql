@@ -0,0 +1,5 @@
+-- Approximate coordinates of login attempts, for impossible-travel checks
+ALTER TABLE login_attempts ADD COLUMN latitude REAL;
+ALTER TABLE login_attempts ADD COLUMN longitude REAL;
+
+CREATE INDEX IF NOT EXISTS idx_login_attempts_user_success ON login_attempts(user_id, success, timestamp);
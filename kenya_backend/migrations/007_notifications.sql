@@ -0,0 +1,14 @@
+-- Outbox of user-facing security notifications awaiting delivery
+CREATE TABLE IF NOT EXISTS notifications (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    metadata TEXT, -- JSON data
+    created_at TEXT NOT NULL,
+    delivered_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_notifications_user_id ON notifications(user_id);
+CREATE INDEX IF NOT EXISTS idx_notifications_delivered_at ON notifications(delivered_at);
@@ -0,0 +1,9 @@
+-- One-time emergency access credentials, provisioned from the CLI.
+-- passphrase_hash is cleared when the credential is used.
+CREATE TABLE IF NOT EXISTS break_glass_credentials (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    passphrase_hash TEXT,
+    provisioned_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
@@ -0,0 +1,28 @@
+-- One row per issued session. A session is live until it is revoked or
+-- reaches expires_at, and jti identifies the token issued with it.
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    jti TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    user_agent TEXT,
+    created_at TEXT NOT NULL,
+    last_activity_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    step_up_at TEXT,
+    revoked_at TEXT,
+    revoked_by TEXT,
+    revoke_reason TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
+
+-- Token IDs refused even though their signature and expiry still check out
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti TEXT PRIMARY KEY NOT NULL,
+    session_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    revoked_at TEXT NOT NULL,
+    revoked_by TEXT
+);
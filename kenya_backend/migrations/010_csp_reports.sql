@@ -0,0 +1,21 @@
+-- Content-Security-Policy violations reported by browsers. Identical reports
+-- share one row: count and last_seen_at grow while the dedup window is open.
+CREATE TABLE IF NOT EXISTS csp_reports (
+    id TEXT PRIMARY KEY NOT NULL,
+    fingerprint TEXT NOT NULL,
+    document_uri TEXT NOT NULL,
+    violated_directive TEXT NOT NULL,
+    effective_directive TEXT,
+    blocked_uri TEXT,
+    source_file TEXT,
+    line_number INTEGER,
+    column_number INTEGER,
+    disposition TEXT,
+    user_agent TEXT,
+    ip_address TEXT NOT NULL,
+    first_seen_at TEXT NOT NULL,
+    last_seen_at TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE INDEX IF NOT EXISTS idx_csp_reports_fingerprint ON csp_reports(fingerprint, last_seen_at);
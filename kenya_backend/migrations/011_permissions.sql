@@ -0,0 +1,15 @@
+-- Bumped whenever a user's permissions change. Tokens carry the version they
+-- were issued under and are refused once it is stale.
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
+
+-- Per-user grants (granted = TRUE) and withdrawals (granted = FALSE) that
+-- take precedence over the defaults of the user's role
+CREATE TABLE IF NOT EXISTS user_permissions (
+    user_id TEXT NOT NULL,
+    permission TEXT NOT NULL,
+    granted BOOLEAN NOT NULL,
+    updated_by TEXT,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, permission),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
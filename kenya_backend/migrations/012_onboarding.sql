@@ -0,0 +1,7 @@
+-- Set the first time a user replaces their temporary password. Accounts that
+-- already hold a password of their own count as onboarded when it was last set.
+ALTER TABLE users ADD COLUMN onboarded_at TEXT;
+
+UPDATE users
+SET onboarded_at = COALESCE(password_changed_at, created_at)
+WHERE is_temporary_password = FALSE;
@@ -0,0 +1,21 @@
+-- Client addresses are stored in canonical form from now on: no port, no
+-- IPv6 brackets, lowercase. Bring existing rows into line so a lookup by
+-- address finds them, then index both tables on it.
+UPDATE login_attempts
+SET ip_address = CASE
+    WHEN ip_address LIKE '[%]%' THEN lower(substr(ip_address, 2, instr(ip_address, ']') - 2))
+    WHEN ip_address LIKE '%.%:%' AND ip_address NOT LIKE '%:%:%' THEN substr(ip_address, 1, instr(ip_address, ':') - 1)
+    ELSE lower(trim(ip_address))
+END
+WHERE ip_address IS NOT NULL;
+
+UPDATE security_events
+SET ip_address = CASE
+    WHEN ip_address LIKE '[%]%' THEN lower(substr(ip_address, 2, instr(ip_address, ']') - 2))
+    WHEN ip_address LIKE '%.%:%' AND ip_address NOT LIKE '%:%:%' THEN substr(ip_address, 1, instr(ip_address, ':') - 1)
+    ELSE lower(trim(ip_address))
+END
+WHERE ip_address IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_login_attempts_ip_address ON login_attempts(ip_address, timestamp);
+CREATE INDEX IF NOT EXISTS idx_security_events_ip_address ON security_events(ip_address, timestamp);
@@ -0,0 +1,6 @@
+-- Keyed hash of the enrolled TOTP secret, so one authenticator can't back two
+-- accounts. Existing enrolments are fingerprinted at startup, since the key
+-- lives in the environment rather than the database.
+ALTER TABLE users ADD COLUMN two_fa_fingerprint TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_two_fa_fingerprint ON users(two_fa_fingerprint);
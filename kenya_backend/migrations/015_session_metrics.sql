@@ -0,0 +1,5 @@
+-- Capacity gauges on /api/health/details count live and recent sessions by
+-- time range, and these keep each count to an index range scan.
+CREATE INDEX IF NOT EXISTS idx_sessions_expires_at ON sessions(expires_at);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_created_at ON sessions(created_at);
@@ -0,0 +1,16 @@
+-- Outbound webhook deliveries that failed permanently: retries ran out or
+-- the receiver refused the request. Kept as sent, for administrators to inspect.
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    delivery_id TEXT PRIMARY KEY NOT NULL,
+    destination TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    url TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_status INTEGER,
+    last_error TEXT NOT NULL,
+    first_attempt_at TEXT NOT NULL,
+    failed_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_dead_letters_failed_at ON webhook_dead_letters(failed_at);
@@ -0,0 +1,17 @@
+-- Organization each account belongs to, such as a county office or headquarters.
+-- Accounts without one follow the global security settings.
+ALTER TABLE users ADD COLUMN organization TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_users_organization ON users(organization);
+
+-- Per-organization overrides of the global security settings. A NULL column
+-- falls back to the global value.
+CREATE TABLE IF NOT EXISTS org_security_policies (
+    organization TEXT PRIMARY KEY NOT NULL,
+    session_timeout_minutes INTEGER,
+    max_failed_attempts INTEGER,
+    require_two_fa BOOLEAN,
+    password_max_age_days INTEGER,
+    updated_by TEXT,
+    updated_at TEXT NOT NULL
+);
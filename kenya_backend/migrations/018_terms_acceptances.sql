@@ -0,0 +1,23 @@
+-- Each user's acceptance of each version of the acceptable-use terms, kept
+-- as evidence. Rows are never changed or removed, which the triggers below enforce.
+CREATE TABLE IF NOT EXISTS terms_acceptances (
+    user_id TEXT NOT NULL,
+    version TEXT NOT NULL,
+    accepted_at TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    user_agent TEXT,
+    PRIMARY KEY (user_id, version),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE TRIGGER IF NOT EXISTS terms_acceptances_no_update
+BEFORE UPDATE ON terms_acceptances
+BEGIN
+    SELECT RAISE(ABORT, 'terms acceptances are immutable');
+END;
+
+CREATE TRIGGER IF NOT EXISTS terms_acceptances_no_delete
+BEFORE DELETE ON terms_acceptances
+BEGIN
+    SELECT RAISE(ABORT, 'terms acceptances are immutable');
+END;
@@ -0,0 +1,10 @@
+-- The effective non-secret configuration, recorded at startup whenever it
+-- differs from the newest row, so administrators can see what changed and when.
+CREATE TABLE IF NOT EXISTS config_snapshots (
+    id TEXT PRIMARY KEY NOT NULL,
+    config_hash TEXT NOT NULL,
+    config TEXT NOT NULL,
+    recorded_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_config_snapshots_recorded_at ON config_snapshots(recorded_at);
@@ -0,0 +1,16 @@
+-- Admin actions waiting for approval through a one-time link sent to one
+-- admin. Only the SHA-256 of each link's token is kept.
+CREATE TABLE IF NOT EXISTS pending_admin_actions (
+    id TEXT PRIMARY KEY NOT NULL,
+    action TEXT NOT NULL,
+    target_user_id TEXT NOT NULL,
+    admin_id TEXT NOT NULL,
+    requested_by TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (target_user_id) REFERENCES users (id),
+    FOREIGN KEY (admin_id) REFERENCES users (id),
+    FOREIGN KEY (requested_by) REFERENCES users (id)
+);
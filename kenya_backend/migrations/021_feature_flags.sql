@@ -0,0 +1,9 @@
+-- Runtime overrides of feature flags, set by administrators. A flag without
+-- a row takes its default from the configuration.
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name TEXT PRIMARY KEY NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    updated_at TEXT NOT NULL,
+    updated_by TEXT,
+    FOREIGN KEY (updated_by) REFERENCES users (id)
+);
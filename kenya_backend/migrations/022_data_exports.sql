@@ -0,0 +1,26 @@
+-- Copies of a user's personal data (data subject access requests). Each
+-- document is stored in chunks, written and read back in sequence order.
+-- Only the SHA-256 of each export's download token is kept.
+CREATE TABLE IF NOT EXISTS data_exports (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    requested_by TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    completed_at TEXT,
+    chunks INTEGER NOT NULL DEFAULT 0,
+    size_bytes INTEGER NOT NULL DEFAULT 0,
+    downloads INTEGER NOT NULL DEFAULT 0,
+    last_downloaded_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (requested_by) REFERENCES users (id)
+);
+
+CREATE TABLE IF NOT EXISTS data_export_chunks (
+    export_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    PRIMARY KEY (export_id, seq),
+    FOREIGN KEY (export_id) REFERENCES data_exports (id)
+);
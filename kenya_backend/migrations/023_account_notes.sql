@@ -0,0 +1,47 @@
+-- Helpdesk notes on user accounts, kept for accountability. A note's text,
+-- author and time never change and notes are never removed, which the
+-- triggers below enforce. One that no longer applies is struck through.
+CREATE TABLE IF NOT EXISTS account_notes (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    author_id TEXT NOT NULL,
+    note TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    pinned BOOLEAN NOT NULL DEFAULT FALSE,
+    struck_at TEXT,
+    struck_by TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (author_id) REFERENCES users (id),
+    FOREIGN KEY (struck_by) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_account_notes_user ON account_notes (user_id, created_at);
+
+CREATE TRIGGER IF NOT EXISTS account_notes_no_edit
+BEFORE UPDATE OF id, user_id, author_id, note, created_at, pinned ON account_notes
+BEGIN
+    SELECT RAISE(ABORT, 'account notes are append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS account_notes_no_unstrike
+BEFORE UPDATE OF struck_at, struck_by ON account_notes
+WHEN OLD.struck_at IS NOT NULL
+BEGIN
+    SELECT RAISE(ABORT, 'account notes are append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS account_notes_no_delete
+BEFORE DELETE ON account_notes
+BEGIN
+    SELECT RAISE(ABORT, 'account notes are append-only');
+END;
+
+-- Freeform labels on accounts, such as "on-leave" or "contractor"
+CREATE TABLE IF NOT EXISTS user_tags (
+    user_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (user_id, tag),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_tags_tag ON user_tags (tag);
@@ -0,0 +1,21 @@
+-- Email addresses on accounts. Reset links are only sent to an address
+-- that has been verified.
+ALTER TABLE users ADD COLUMN email TEXT;
+ALTER TABLE users ADD COLUMN email_verified_at TEXT;
+
+-- One-time password reset tokens, emailed to the owner or handed to an
+-- administrator to pass on. Only the SHA-256 of each token is kept.
+CREATE TABLE IF NOT EXISTS password_resets (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    issued_by TEXT,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (issued_by) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_password_resets_user_id ON password_resets(user_id);
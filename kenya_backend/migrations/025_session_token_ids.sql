@@ -0,0 +1,7 @@
+-- sessions.jti now names the session's current token. Re-issuing a token
+-- replaces it, so the previous token stops matching. token_family stays the
+-- same across re-issues and identifies every token descended from one sign-in.
+ALTER TABLE sessions ADD COLUMN token_family TEXT;
+UPDATE sessions SET token_family = id WHERE token_family IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_sessions_jti ON sessions(jti);
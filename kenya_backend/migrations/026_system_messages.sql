@@ -0,0 +1,15 @@
+-- Notices shown on the login screen of every frontend, e.g. planned
+-- maintenance. A message is shown from starts_at until ends_at.
+CREATE TABLE IF NOT EXISTS system_messages (
+    id TEXT PRIMARY KEY NOT NULL,
+    body TEXT NOT NULL,
+    severity TEXT NOT NULL DEFAULT 'info',
+    starts_at TEXT NOT NULL,
+    ends_at TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (created_by) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_system_messages_ends_at ON system_messages(ends_at);
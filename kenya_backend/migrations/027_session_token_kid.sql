@@ -0,0 +1,4 @@
+-- ID of the key that signed the session's current token, so a JWT secret
+-- migration can tell how many live sessions still depend on the old key.
+-- NULL until the session is next used.
+ALTER TABLE sessions ADD COLUMN token_kid TEXT;
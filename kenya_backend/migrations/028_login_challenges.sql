@@ -0,0 +1,17 @@
+-- Second-factor challenges handed out when a password checks out for a 2FA
+-- account. Only the keyed digest of each challenge token is kept, and the code
+-- is sent with the token instead of the password again.
+CREATE TABLE IF NOT EXISTS login_challenges (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    token_digest TEXT NOT NULL UNIQUE,
+    session_id TEXT NOT NULL,
+    sign_out_other_sessions INTEGER NOT NULL DEFAULT 0,
+    failed_codes INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_challenges_user_id ON login_challenges(user_id);
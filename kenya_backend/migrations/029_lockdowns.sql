@@ -0,0 +1,28 @@
+-- Emergency lockdowns. One administrator requests a lockdown and it starts
+-- once a second one approves it, bumping the auth epoch: sessions created in
+-- an earlier epoch stop working. A lockdown ends at ends_at or when an
+-- administrator lifts it, whichever comes first.
+CREATE TABLE IF NOT EXISTS lockdowns (
+    id TEXT PRIMARY KEY NOT NULL,
+    reason TEXT NOT NULL,
+    duration_minutes INTEGER NOT NULL,
+    requested_by TEXT NOT NULL,
+    requested_at TEXT NOT NULL,
+    request_expires_at TEXT NOT NULL,
+    approved_by TEXT,
+    auth_epoch INTEGER UNIQUE,
+    started_at TEXT,
+    ends_at TEXT,
+    ended_at TEXT,
+    ended_by TEXT,
+    FOREIGN KEY (requested_by) REFERENCES users (id),
+    FOREIGN KEY (approved_by) REFERENCES users (id),
+    FOREIGN KEY (ended_by) REFERENCES users (id)
+);
+
+-- Epoch the session was created in, or 0 for sessions from before any lockdown
+ALTER TABLE sessions ADD COLUMN auth_epoch INTEGER NOT NULL DEFAULT 0;
+
+-- Keyed digest of the code emailed to an account without 2FA that signs in
+-- during a lockdown. NULL for challenges answered with the account's own 2FA
+ALTER TABLE login_challenges ADD COLUMN email_code_digest TEXT;
@@ -0,0 +1,23 @@
+-- Client applications registered by administrators. A client picks its app
+-- with `client_id` at login and `X-Client-Id` on every request, and its tokens
+-- carry the app's audience and only work for requests from the same app.
+-- The built-in `default` app, for clients that send no ID, is not stored.
+CREATE TABLE IF NOT EXISTS client_apps (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    -- JSON array of browser origins allowed besides the configured CORS origins
+    allowed_origins TEXT NOT NULL DEFAULT '[]',
+    token_audience TEXT NOT NULL UNIQUE,
+    rate_limit_tier TEXT NOT NULL DEFAULT 'standard',
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (created_by) REFERENCES users (id)
+);
+
+-- App the session signed in through, so renewed tokens keep its audience
+ALTER TABLE sessions ADD COLUMN client_id TEXT NOT NULL DEFAULT 'default';
+
+-- App the login started from, for the session the challenge completes
+ALTER TABLE login_challenges ADD COLUMN client_id TEXT NOT NULL DEFAULT 'default';
@@ -0,0 +1,9 @@
+-- Privacy mode stores truncated addresses, and the full one is kept in raw_ip
+-- for incident response until the retention purge clears it
+ALTER TABLE login_attempts ADD COLUMN raw_ip TEXT;
+ALTER TABLE security_events ADD COLUMN raw_ip TEXT;
+-- Coarse location of login attempts: the county, state or province
+ALTER TABLE login_attempts ADD COLUMN region TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_login_attempts_raw_ip ON login_attempts(timestamp) WHERE raw_ip IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_security_events_raw_ip ON security_events(timestamp) WHERE raw_ip IS NOT NULL;
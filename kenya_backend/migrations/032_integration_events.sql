@@ -0,0 +1,13 @@
+-- Events pushed by integrations (the HR system), by the ID the source gave
+-- them, so a redelivered event is answered from here instead of applied twice.
+-- A row without an outcome is still being applied. Events that were rejected
+-- are not kept, so the source can send them again once the cause is fixed.
+CREATE TABLE IF NOT EXISTS processed_integration_events (
+    source TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    user_id TEXT,
+    outcome TEXT,
+    processed_at TEXT NOT NULL,
+    PRIMARY KEY (source, event_id)
+);
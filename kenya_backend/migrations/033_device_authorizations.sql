@@ -0,0 +1,17 @@
+-- Kiosk sign-ins approved from a device the account is already signed in
+-- on. The kiosk shows the user code and polls with its polling token, of
+-- which only the SHA-256 is kept.
+CREATE TABLE IF NOT EXISTS device_authorizations (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_code TEXT NOT NULL,
+    polling_token_hash TEXT NOT NULL UNIQUE,
+    client_id TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    user_id TEXT,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    decided_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_authorizations_user_code ON device_authorizations(user_code);
@@ -0,0 +1,12 @@
+-- Indexes for the admin user listing's security-state filters and sort orders.
+-- The last_login index is on the expression the listing sorts by, so accounts
+-- that never signed in are ordered without a separate sort step.
+CREATE INDEX IF NOT EXISTS idx_users_role ON users(role);
+CREATE INDEX IF NOT EXISTS idx_users_is_locked ON users(is_locked);
+CREATE INDEX IF NOT EXISTS idx_users_two_fa_enabled ON users(two_fa_enabled);
+CREATE INDEX IF NOT EXISTS idx_users_is_temporary_password ON users(is_temporary_password);
+CREATE INDEX IF NOT EXISTS idx_users_created_at ON users(created_at);
+CREATE INDEX IF NOT EXISTS idx_users_last_login ON users(COALESCE(last_login, '1970-01-01T00:00:00+00:00'));
+
+-- Live session counts per listed account
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id_expires_at ON sessions(user_id, expires_at);
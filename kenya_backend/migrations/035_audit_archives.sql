@@ -0,0 +1,12 @@
+-- Manifest of the monthly files old security events and login attempts are
+-- moved into, one row per month. Counts and hash describe the file as the
+-- last archival run left it, and a later run for the same month adds to it.
+CREATE TABLE IF NOT EXISTS audit_archives (
+    month TEXT PRIMARY KEY NOT NULL, -- YYYY-MM
+    path TEXT NOT NULL,
+    security_events INTEGER NOT NULL,
+    login_attempts INTEGER NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    sha256 TEXT NOT NULL,
+    archived_at TEXT NOT NULL
+);
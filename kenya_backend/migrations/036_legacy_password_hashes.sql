@@ -0,0 +1,7 @@
+-- Which scheme each password hash is in. Accounts imported from the legacy
+-- county portal keep their bcrypt hash, marked bcrypt_legacy, until the
+-- first sign-in re-hashes it with Argon2.
+ALTER TABLE users ADD COLUMN hash_scheme TEXT NOT NULL DEFAULT 'argon2';
+UPDATE users SET hash_scheme = 'bcrypt' WHERE password_hash LIKE '$2%';
+
+CREATE INDEX IF NOT EXISTS idx_users_hash_scheme ON users(hash_scheme);
@@ -0,0 +1,10 @@
+-- Compare-and-set locks for one-off startup work that only one of several
+-- instances sharing the database should do. A holder's lock lapses at
+-- expires_at, so an instance that dies holding it doesn't block the rest.
+CREATE TABLE IF NOT EXISTS bootstrap_lock (
+    name TEXT PRIMARY KEY NOT NULL,
+    holder TEXT,
+    expires_at TEXT
+);
+
+INSERT OR IGNORE INTO bootstrap_lock (name) VALUES ('default_user');
@@ -0,0 +1,23 @@
+-- Two-person secret rotation. A staged secret is kept only as its SHA-256
+-- fingerprint, with the hash of the token a second administrator confirms it
+-- with. active_secrets holds the fingerprint of each secret servers start with.
+CREATE TABLE IF NOT EXISTS secret_stages (
+    id TEXT PRIMARY KEY NOT NULL,
+    kind TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    staged_by TEXT NOT NULL,
+    staged_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    confirmed_by TEXT,
+    confirmed_at TEXT,
+    activated_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_secret_stages_fingerprint ON secret_stages(kind, fingerprint);
+
+CREATE TABLE IF NOT EXISTS active_secrets (
+    kind TEXT PRIMARY KEY NOT NULL,
+    fingerprint TEXT NOT NULL,
+    activated_at TEXT NOT NULL
+);
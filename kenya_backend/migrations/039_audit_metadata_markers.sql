@@ -0,0 +1,6 @@
+-- Metadata that failed to serialize used to be stored as an empty string,
+-- which reads back as no JSON at all. Mark those events the way failures are
+-- marked now, so the loss is visible.
+UPDATE security_events
+SET metadata = '{"_error":"metadata_serialization_failed"}'
+WHERE metadata = '';
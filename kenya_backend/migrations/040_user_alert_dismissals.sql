@@ -0,0 +1,11 @@
+-- Alerts a user has dismissed from their own feed (`GET /api/auth/my-alerts`),
+-- one row per security event. The events themselves are left as they are,
+-- an administrator's acknowledgement is separate. No foreign key to the
+-- event, which the audit archive moves out long after it leaves the feed.
+CREATE TABLE IF NOT EXISTS user_alert_dismissals (
+    user_id TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    dismissed_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, event_id),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
@@ -0,0 +1,13 @@
+-- Critical security notifications the in-memory delivery queue couldn't
+-- hold, because it was full of others also waiting on a stuck receiver.
+-- They are written here instead of being dropped, and moved back into the
+-- queue once it has room.
+CREATE TABLE IF NOT EXISTS pending_notifications (
+    id TEXT PRIMARY KEY NOT NULL,
+    destination TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    data TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_notifications_created_at ON pending_notifications(created_at);
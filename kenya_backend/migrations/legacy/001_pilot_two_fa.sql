@@ -0,0 +1,28 @@
+-- Brings forward databases created before the 2FA columns existed: those made
+-- by build_script.ps1 from create_db.sql, and early pilots that ran the first
+-- 001_initial. Applied before the regular migrations, and only to those shapes.
+ALTER TABLE users ADD COLUMN two_fa_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN two_fa_secret TEXT;
+ALTER TABLE users ADD COLUMN two_fa_backup_codes TEXT;
+ALTER TABLE users ADD COLUMN two_fa_enabled_at TEXT;
+
+-- The indexes 001_initial creates, which those schemas never had
+CREATE INDEX IF NOT EXISTS idx_users_username ON users(username);
+CREATE INDEX IF NOT EXISTS idx_users_session_token ON users(session_token);
+CREATE INDEX IF NOT EXISTS idx_login_attempts_timestamp ON login_attempts(timestamp);
+CREATE INDEX IF NOT EXISTS idx_login_attempts_user_id ON login_attempts(user_id);
+CREATE INDEX IF NOT EXISTS idx_security_events_timestamp ON security_events(timestamp);
+CREATE INDEX IF NOT EXISTS idx_security_events_user_id ON security_events(user_id);
+CREATE INDEX IF NOT EXISTS idx_security_events_event_type ON security_events(event_type);
+
+-- Timestamps written by SQLite's datetime('now') (2024-03-01 08:15:00), in
+-- the RFC 3339 form the application writes, so range queries compare them
+-- correctly
+UPDATE users SET created_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', created_at) WHERE created_at GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
+UPDATE users SET updated_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', updated_at) WHERE updated_at GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
+UPDATE users SET last_login = strftime('%Y-%m-%dT%H:%M:%S+00:00', last_login) WHERE last_login GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
+UPDATE users SET lockout_expiry = strftime('%Y-%m-%dT%H:%M:%S+00:00', lockout_expiry) WHERE lockout_expiry GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
+UPDATE users SET password_changed_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', password_changed_at) WHERE password_changed_at GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
+UPDATE users SET session_expires_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', session_expires_at) WHERE session_expires_at GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
+UPDATE login_attempts SET timestamp = strftime('%Y-%m-%dT%H:%M:%S+00:00', timestamp) WHERE timestamp GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
+UPDATE security_events SET timestamp = strftime('%Y-%m-%dT%H:%M:%S+00:00', timestamp) WHERE timestamp GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9] [0-9][0-9]:[0-9][0-9]:[0-9][0-9]*';
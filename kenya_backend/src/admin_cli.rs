@@ -0,0 +1,249 @@
+//! `kenya_backend admin <command>`: database maintenance from the command line.
+//!
+//! `backup --out <path>` snapshots the database, and is safe while the server
+//! runs. `restore --in <path>` replaces the database with a backup and
+//! refuses while any server has it open. `verify-export <bundle.zip>
+//! [--public-key <file>]` checks a signed audit export bundle, by default
+//! against the public half of the configured `AUDIT_SIGNING_KEY_PATH` key.
+//! `archive-audit --before <YYYY-MM-DD>` moves security events and login
+//! attempts from before that day into the monthly files in `AUDIT_ARCHIVE_DIR`,
+//! and is safe while the server runs. `import-legacy --in <legacy.csv>` creates
+//! accounts from the legacy county portal's `username,bcrypt_hash,org` export.
+//! `stage-secret --kind jwt --from-file <path> --as <admin>` stages a new JWT
+//! secret, and `confirm-secret --token <token> --as <admin>` lets a second
+//! administrator approve it; both read the administrator's password from stdin.
+
+use chrono::{NaiveDate, Utc};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::models::context::RequestContext;
+use crate::models::user::UserRole;
+use crate::services::audit_archive_service::AuditArchiveService;
+use crate::services::audit_bundle::{parse_public_key, verify_bundle, BundleSigner};
+use crate::services::backup_service::{restore, snapshot, sqlite_path};
+use crate::services::legacy_import::LegacyImportService;
+use crate::services::password_service::PasswordService;
+use crate::services::secret_rotation::{SecretKind, SecretRotationService};
+use crate::utils::clock::SystemClock;
+use crate::utils::database::run_migrations;
+
+const USAGE: &str = "usage: kenya_backend admin backup --out <path> | admin restore --in <path> \
+                     | admin verify-export <bundle.zip> [--public-key <file>] \
+                     | admin archive-audit --before <YYYY-MM-DD> | admin import-legacy --in <legacy.csv> \
+                     | admin stage-secret --kind jwt --from-file <path> --as <admin> \
+                     | admin confirm-secret --token <token> --as <admin>";
+
+/// Run the admin command in `args` (everything after `admin`)
+pub async fn run(args: &[String], config: &AppConfig) -> std::io::Result<()> {
+    match args {
+        [command, flag, path] if command == "backup" && flag == "--out" => backup(config, PathBuf::from(path)).await,
+        [command, flag, path] if command == "restore" && flag == "--in" => restore_from(config, PathBuf::from(path)).await,
+        [command, bundle] if command == "verify-export" => verify_export(config, Path::new(bundle), None),
+        [command, bundle, flag, key] if command == "verify-export" && flag == "--public-key" => {
+            verify_export(config, Path::new(bundle), Some(Path::new(key)))
+        }
+        [command, flag, date] if command == "archive-audit" && flag == "--before" => archive_audit(config, date).await,
+        [command, flag, path] if command == "import-legacy" && flag == "--in" => import_legacy(config, Path::new(path)).await,
+        [command, kind_flag, kind, file_flag, path, as_flag, admin]
+            if command == "stage-secret" && kind_flag == "--kind" && file_flag == "--from-file" && as_flag == "--as" =>
+        {
+            stage_secret(config, kind, Path::new(path), admin).await
+        }
+        [command, token_flag, token, as_flag, admin]
+            if command == "confirm-secret" && token_flag == "--token" && as_flag == "--as" =>
+        {
+            confirm_secret(config, token, admin).await
+        }
+        _ => Err(std::io::Error::other(USAGE)),
+    }
+}
+
+async fn backup(config: &AppConfig, out: PathBuf) -> std::io::Result<()> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Failed to connect to database: {}", e)))?;
+
+    let backup = snapshot(&pool, &out, Utc::now())
+        .await
+        .map_err(|e| std::io::Error::other(format!("Backup failed: {}", e)))?;
+    pool.close().await;
+
+    println!("Backup written to {} ({} bytes)", backup.path.display(), backup.size_bytes);
+    println!("SHA-256: {}", backup.sha256);
+    Ok(())
+}
+
+async fn archive_audit(config: &AppConfig, before: &str) -> std::io::Result<()> {
+    let before = NaiveDate::parse_from_str(before, "%Y-%m-%d")
+        .ok()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|midnight| midnight.and_utc())
+        .ok_or_else(|| std::io::Error::other(format!("--before takes a date like 2025-01-01, not {:?}", before)))?;
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Failed to connect to database: {}", e)))?;
+
+    let archived = AuditArchiveService::new(pool.clone(), PathBuf::from(&config.audit_archive_dir), Arc::new(SystemClock))
+        .archive_before(before)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Archival failed: {}", e)))?;
+    pool.close().await;
+
+    if archived.is_empty() {
+        println!("Nothing before {} left to archive", before.date_naive());
+    }
+    for archive in archived {
+        println!(
+            "{}: {} security events, {} login attempts in {} ({} bytes)",
+            archive.month, archive.security_events, archive.login_attempts, archive.path, archive.size_bytes
+        );
+        println!("SHA-256: {}", archive.sha256);
+    }
+    Ok(())
+}
+
+async fn import_legacy(config: &AppConfig, csv: &Path) -> std::io::Result<()> {
+    let csv = std::fs::read_to_string(csv)?;
+    // The accounts need the columns this binary's server would have added at startup
+    let pool = migrated_pool(config).await?;
+
+    let report = LegacyImportService::new(pool.clone(), Arc::new(SystemClock))
+        .import(&csv)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Import failed, no accounts were created: {}", e)))?;
+    pool.close().await;
+
+    for skipped in &report.skipped {
+        println!("Line {} ({}): skipped, {}", skipped.line, skipped.username, skipped.problem.message());
+    }
+    println!("Imported {} accounts, skipped {} lines", report.imported, report.skipped.len());
+    println!(
+        "{} imported accounts are still on their legacy bcrypt hash until they next sign in",
+        report.unmigrated
+    );
+    Ok(())
+}
+
+async fn stage_secret(config: &AppConfig, kind: &str, path: &Path, admin: &str) -> std::io::Result<()> {
+    let kind: SecretKind = kind.parse().map_err(std::io::Error::other)?;
+    // Editors leave a trailing newline the env var won't have
+    let material = std::fs::read_to_string(path)?.trim_end_matches(['\r', '\n']).to_string();
+    let pool = migrated_pool(config).await?;
+    let staged_by = sign_in_admin(&pool, admin).await?;
+
+    let (stage, token) = SecretRotationService::new(pool.clone(), Arc::new(SystemClock))
+        .stage(&RequestContext::new("cli", None), kind, &material, staged_by)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Staging failed: {}", e)))?;
+    pool.close().await;
+
+    println!("Staged a new {} with fingerprint {}", kind.env_var(), stage.short_fingerprint());
+    println!("Activation token: {}", token);
+    println!(
+        "Another administrator must run `kenya_backend admin confirm-secret --token <token> --as <admin>` before {}.",
+        stage.expires_at.to_rfc3339()
+    );
+    println!("Once confirmed, set {} to the new secret and restart the servers.", kind.env_var());
+    Ok(())
+}
+
+async fn confirm_secret(config: &AppConfig, token: &str, admin: &str) -> std::io::Result<()> {
+    let pool = migrated_pool(config).await?;
+    let confirmed_by = sign_in_admin(&pool, admin).await?;
+
+    let stage = SecretRotationService::new(pool.clone(), Arc::new(SystemClock))
+        .confirm(&RequestContext::new("cli", None), token, confirmed_by)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Confirmation failed: {}", e)))?;
+    pool.close().await;
+
+    println!("Confirmed the new {} with fingerprint {}", stage.kind.env_var(), stage.short_fingerprint());
+    println!("Servers accept it from their next restart.");
+    Ok(())
+}
+
+/// A connection with the tables this binary's server would have added at startup
+async fn migrated_pool(config: &AppConfig) -> std::io::Result<SqlitePool> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Failed to connect to database: {}", e)))?;
+    run_migrations(&pool)
+        .await
+        .map_err(|e| std::io::Error::other(format!("Failed to run migrations: {}", e)))?;
+    Ok(pool)
+}
+
+/// The id of `username`, an active administrator, once their password is
+/// read from stdin
+async fn sign_in_admin(pool: &SqlitePool, username: &str) -> std::io::Result<Uuid> {
+    eprint!("Password for {}: ", username);
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+
+    let admin: Option<(Uuid, String)> =
+        sqlx::query_as("SELECT id, password_hash FROM users WHERE username = ? AND role = ? AND is_active = TRUE")
+            .bind(username)
+            .bind(UserRole::Admin.as_str())
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| std::io::Error::other(format!("Failed to look up {}: {}", username, e)))?;
+    match admin {
+        Some((id, hash)) if PasswordService::new().verify_password(password, &hash).unwrap_or(false) => Ok(id),
+        _ => Err(std::io::Error::other(format!("{} is not an active administrator with that password", username))),
+    }
+}
+
+async fn restore_from(config: &AppConfig, backup: PathBuf) -> std::io::Result<()> {
+    let db_path = sqlite_path(&config.database_url)
+        .ok_or_else(|| std::io::Error::other("DATABASE_URL is not a SQLite database file"))?;
+
+    let previous = restore(&db_path, &backup, Utc::now())
+        .await
+        .map_err(|e| std::io::Error::other(format!("Restore failed: {}", e)))?;
+
+    println!("Restored {} from {}", db_path.display(), backup.display());
+    if let Some(previous) = previous {
+        println!("The replaced database was kept as {}", previous.display());
+    }
+    Ok(())
+}
+
+fn verify_export(config: &AppConfig, bundle: &Path, public_key: Option<&Path>) -> std::io::Result<()> {
+    let public_key = match public_key {
+        Some(path) => parse_public_key(&std::fs::read_to_string(path)?),
+        None => {
+            let key_path = config.audit_signing_key_path.as_deref().ok_or_else(|| {
+                std::io::Error::other("Pass --public-key or set AUDIT_SIGNING_KEY_PATH")
+            })?;
+            BundleSigner::from_pem_file(Path::new(key_path)).and_then(|signer| parse_public_key(&signer.public_key()))
+        }
+    }
+    .map_err(|e| std::io::Error::other(format!("Cannot use public key: {}", e)))?;
+
+    let manifest = verify_bundle(&std::fs::read(bundle)?, &public_key)
+        .map_err(|e| std::io::Error::other(format!("Verification FAILED: {}", e)))?;
+
+    println!("Verified {} ({})", bundle.display(), manifest.format);
+    println!("Data file: {} ({} rows, {} bytes)", manifest.data_file, manifest.rows, manifest.size_bytes);
+    println!("SHA-256: {}", manifest.sha256);
+    match (manifest.first_event_at, manifest.last_event_at) {
+        (Some(first), Some(last)) => println!("Events from {} to {}", first.to_rfc3339(), last.to_rfc3339()),
+        _ => println!("No events"),
+    }
+    println!("Filters: {}", manifest.filters);
+    println!("Generated at {} by {}", manifest.generated_at.to_rfc3339(), manifest.generated_by);
+    println!("Signed by {}", manifest.public_key);
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+use crate::config::AppConfig;
+use crate::services::auth_service::AuthService;
+use crate::services::client_app_service::ClientRegistry;
+use crate::services::feature_flags::FeatureFlags;
+use crate::services::geoip_service::GeoIpService;
+use crate::services::lockdown_service::LockdownService;
+use crate::services::lockout_exemptions::LockoutExemptions;
+use crate::services::password_dictionary::PasswordDictionary;
+use crate::services::password_service::PasswordService;
+use crate::services::shared_state::{InProcessBackend, SharedStateBackend};
+use crate::services::throttle_state::ThrottleState;
+use crate::services::token_service::TokenService;
+use crate::services::webhook_service::Notifier;
+use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::database::ReadPool;
+
+/// Builds the `AuthService` from the configuration in one place: every
+/// sub-service gets its settings from `config`, and anything the server
+/// shares with other components, or a test wants to replace, can be handed
+/// in first. What isn't handed in is built from `config`: the system clock,
+/// an in-process store, no notifications, GeoIP disabled.
+pub struct AuthServiceBuilder<'a> {
+    db_pool: SqlitePool,
+    config: &'a AppConfig,
+    clock: Arc<dyn Clock>,
+    read_pool: Option<ReadPool>,
+    geoip: Option<Arc<GeoIpService>>,
+    shared_state: Option<Arc<dyn SharedStateBackend>>,
+    throttle: Option<Arc<ThrottleState>>,
+    lockout_exemptions: Option<Arc<LockoutExemptions>>,
+    notifier: Option<Arc<dyn Notifier>>,
+    features: Option<Arc<FeatureFlags>>,
+    lockdown: Option<Arc<LockdownService>>,
+    clients: Option<Arc<ClientRegistry>>,
+}
+
+impl<'a> AuthServiceBuilder<'a> {
+    pub fn new(db_pool: SqlitePool, config: &'a AppConfig) -> Self {
+        Self {
+            db_pool,
+            config,
+            clock: Arc::new(SystemClock),
+            read_pool: None,
+            geoip: None,
+            shared_state: None,
+            throttle: None,
+            lockout_exemptions: None,
+            notifier: None,
+            features: None,
+            lockdown: None,
+            clients: None,
+        }
+    }
+
+    /// Time source for every sub-service
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Replica for listings and dashboard counts
+    pub fn with_read_pool(mut self, read_pool: ReadPool) -> Self {
+        self.read_pool = Some(read_pool);
+        self
+    }
+
+    pub fn with_geoip(mut self, geoip: Arc<GeoIpService>) -> Self {
+        self.geoip = Some(geoip);
+        self
+    }
+
+    /// Store for the counters every instance must agree on
+    pub fn with_shared_state(mut self, backend: Arc<dyn SharedStateBackend>) -> Self {
+        self.shared_state = Some(backend);
+        self
+    }
+
+    /// Failure counters shared with the rate limiting middleware; built over
+    /// the shared state store when not given
+    pub fn with_throttle_state(mut self, throttle: Arc<ThrottleState>) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Accounts and networks whose failures don't lock out; parsed from
+    /// `config` when not given, and handed to the default throttle too
+    pub fn with_lockout_exemptions(mut self, exemptions: Arc<LockoutExemptions>) -> Self {
+        self.lockout_exemptions = Some(exemptions);
+        self
+    }
+
+    /// Where security events and hashing failures are sent
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    pub fn with_feature_flags(mut self, features: Arc<FeatureFlags>) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    pub fn with_lockdown(mut self, lockdown: Arc<LockdownService>) -> Self {
+        self.lockdown = Some(lockdown);
+        self
+    }
+
+    pub fn with_client_registry(mut self, clients: Arc<ClientRegistry>) -> Self {
+        self.clients = Some(clients);
+        self
+    }
+
+    pub fn build(self) -> AuthService {
+        let config = self.config;
+        let clock = self.clock;
+        let db_pool = self.db_pool;
+
+        let shared_state = self.shared_state.unwrap_or_else(|| Arc::new(InProcessBackend::default()));
+        let lockout_exemptions = self.lockout_exemptions.unwrap_or_else(|| {
+            Arc::new(config.lockout_exemptions().unwrap_or_else(|e| {
+                log::error!("Ignoring the lockout exemptions: {}", e);
+                LockoutExemptions::default()
+            }))
+        });
+        let throttle = self.throttle.unwrap_or_else(|| {
+            Arc::new(
+                ThrottleState::default()
+                    .with_backend(shared_state.clone())
+                    .with_failure_delay(config.failure_delay()),
+            )
+        });
+        let features = self
+            .features
+            .unwrap_or_else(|| Arc::new(FeatureFlags::new(db_pool.clone(), clock.clone(), config.feature_defaults)));
+        let lockdown = self.lockdown.unwrap_or_else(|| {
+            Arc::new(LockdownService::new(db_pool.clone(), clock.clone()).with_default_minutes(config.lockdown_duration_minutes))
+        });
+        let clients = self.clients.unwrap_or_else(|| Arc::new(ClientRegistry::new(db_pool.clone(), clock.clone())));
+        let geoip = self
+            .geoip
+            .unwrap_or_else(|| Arc::new(GeoIpService::disabled().with_privacy_mode(config.geoip_privacy_mode)));
+
+        let common_passwords = PasswordDictionary::load_or_embedded(config.password_dictionary_path.as_deref());
+        let mut password_service = PasswordService::new().with_common_passwords(Arc::new(common_passwords));
+        if let Some(notifier) = &self.notifier {
+            password_service = password_service.with_webhooks(notifier.clone());
+        }
+        let token_service = TokenService::new(config.security_config(), clock.clone());
+
+        let mut auth_service = AuthService::new(db_pool, password_service, token_service, geoip, clock)
+            .with_two_fa_issuer(&config.two_fa_issuer)
+            .with_feature_flags(features)
+            .with_lockdown(lockdown)
+            .with_client_registry(clients)
+            .with_throttle_state(throttle)
+            .with_lockout_exemptions(lockout_exemptions)
+            .with_shared_state(shared_state)
+            .with_login_concurrency(config.login_concurrency)
+            .with_bot_heuristics(config.login_honeypot_enabled, config.login_min_fill_ms)
+            .with_failed_login_digest(config.failed_login_digest_quiet_minutes)
+            .with_text_limits(config.text_limits)
+            .with_raw_ip_retention(config.raw_ip_retention_hours)
+            .with_public_base_url(&config.public_base_url)
+            .with_password_reset_url(&config.frontend_password_reset_url)
+            .with_break_glass_enabled(config.break_glass_enabled)
+            .with_phase_budgets(config.phase_budgets);
+        if let Some(read_pool) = self.read_pool {
+            auth_service = auth_service.with_read_pool(read_pool);
+        }
+        if let Some(notifier) = self.notifier {
+            auth_service = auth_service.with_webhooks(notifier);
+        }
+        auth_service
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth::AuthError;
+    use crate::models::context::RequestContext;
+    use crate::models::user::{LoginRequest, TwoFAQrRequest, UserRole};
+    use crate::services::shared_state::SharedStateError;
+    use crate::services::webhook_service::{Urgency, SIEM_DESTINATION};
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Keeps what would have been sent to the SIEM
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent: Mutex<Vec<(String, String)>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn has_destination(&self, name: &str) -> bool {
+            name == SIEM_DESTINATION
+        }
+
+        fn notify(self: Arc<Self>, destination: &str, event_type: &str, _data: serde_json::Value, _urgency: Urgency) {
+            self.sent.lock().unwrap().push((destination.to_string(), event_type.to_string()));
+        }
+    }
+
+    /// Entries that never expire, so nothing depends on the real time
+    #[derive(Default)]
+    struct MemoryStore {
+        entries: Mutex<HashMap<String, u64>>,
+    }
+
+    #[async_trait]
+    impl SharedStateBackend for MemoryStore {
+        fn name(&self) -> &'static str {
+            "memory"
+        }
+
+        async fn increment(&self, key: &str, _ttl: Duration) -> Result<u64, SharedStateError> {
+            let mut entries = self.entries.lock().unwrap();
+            let value = entries.entry(key.to_string()).or_default();
+            *value += 1;
+            Ok(*value)
+        }
+
+        async fn count(&self, key: &str) -> Result<u64, SharedStateError> {
+            Ok(self.entries.lock().unwrap().get(key).copied().unwrap_or(0))
+        }
+
+        async fn hold(&self, key: &str, ttl: Duration) -> Result<Duration, SharedStateError> {
+            self.entries.lock().unwrap().entry(key.to_string()).or_insert(1);
+            Ok(ttl)
+        }
+
+        async fn remaining(&self, key: &str) -> Result<Option<Duration>, SharedStateError> {
+            Ok(self.entries.lock().unwrap().contains_key(key).then_some(Duration::from_secs(60)))
+        }
+
+        async fn remove(&self, key: &str) -> Result<(), SharedStateError> {
+            self.entries.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        async fn count_keys(&self, prefix: &str) -> Result<usize, SharedStateError> {
+            Ok(self.entries.lock().unwrap().keys().filter(|key| key.starts_with(prefix)).count())
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_built_service_authenticates_with_mocked_components() {
+        let pool = test_pool().await;
+        let config = AppConfig { two_fa_issuer: "Kenya FSFVI Staging".to_string(), ..AppConfig::test_config() };
+        let clock = Arc::new(MockClock::new());
+        let notifier = Arc::new(RecordingNotifier::default());
+        let store = Arc::new(MemoryStore::default());
+        let auth_service = AuthServiceBuilder::new(pool.clone(), &config)
+            .with_clock(clock.clone())
+            .with_notifier(notifier.clone())
+            .with_shared_state(store.clone())
+            .build();
+        let user = insert_user(&pool, clock.clone(), "builder_officer", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        let ctx = RequestContext::new("197.232.61.4", Some("test-agent"));
+
+        // The configured three failures lock the account, counted in the store
+        // and reported to the notifier
+        for _ in 0..config.max_failed_login_attempts {
+            let wrong = LoginRequest { password: "NotThePassw0rd!".to_string(), ..user.login_request() };
+            assert!(matches!(auth_service.authenticate(&ctx, wrong).await, Err(AuthError::InvalidCredentials)));
+        }
+        assert!(matches!(auth_service.authenticate(&ctx, user.login_request()).await, Err(AuthError::AccountLocked)));
+        let counter = "throttle:count:username:builder_officer";
+        assert_eq!(store.count(counter).await.unwrap(), 3);
+        // At least one event per failure, and the lockout
+        let sent = notifier.sent.lock().unwrap().clone();
+        assert!(sent.len() >= 4, "{:?}", sent);
+        assert!(sent.iter().all(|(destination, event_type)| destination == SIEM_DESTINATION && event_type == "security_event"));
+
+        // The lockout ends on the mock clock, and the TOTP code is for its time
+        clock.advance(chrono::Duration::minutes(config.lockout_duration_minutes + 1));
+        let response = auth_service.authenticate(&ctx, user.login_request()).await.unwrap();
+        assert!(!response.token.is_empty());
+        assert_eq!(store.count(counter).await.unwrap(), 0);
+
+        // Authenticator apps are given the configured issuer. The next TOTP
+        // window, so the code isn't the one just signed in with.
+        clock.advance(chrono::Duration::seconds(30));
+        let qr = auth_service
+            .redisplay_two_fa_qr(
+                &ctx,
+                user.id,
+                TwoFAQrRequest { password: TEST_PASSWORD.to_string(), totp_code: user.totp().unwrap() },
+            )
+            .await
+            .unwrap();
+        assert!(qr.otpauth_url.starts_with("otpauth://totp/Kenya FSFVI Staging:builder_officer?"), "{}", qr.otpauth_url);
+    }
+}
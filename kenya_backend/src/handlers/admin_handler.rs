@@ -0,0 +1,3934 @@
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError, Result};
+use chrono::Duration;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::handlers::auth_handler::{
+    authorized, data_export_response, invalid_request, AppState,
+};
+use crate::middleware::admin_reads::record_read_rows;
+use crate::middleware::authorization::RouteTable;
+use crate::models::admin::{
+    AcknowledgeEventRequest, AddAccountNoteRequest, AdminActionRequest, AuditEventsQuery, ConfigHistoryQuery, CspReportsQuery, DeadLettersQuery, EventStatsQuery, ExportFormat,
+    IpActivityQuery, LockUserRequest, LockdownRequestBody, MaintenanceToggleRequest, SetOrganizationRequest, SetPermissionsRequest, SetUserTagsRequest, SystemMessageRequest, UsersQuery,
+    ClientAppRequest, NewClientAppRequest,
+};
+use crate::models::audit_event::{AuditEventType, UnknownEventType};
+use crate::models::auth::{AuthError, Severity};
+use crate::models::context::{normalize_ip, RequestContext};
+use crate::models::pagination::ListParams;
+use crate::models::permission::Permission;
+use crate::models::policy::{is_valid_organization, SetOrgPolicyRequest};
+use crate::models::user::UserRole;
+use crate::services::account_notes_service::{normalize_tag, MAX_TAGS_PER_USER, MAX_TAG_CHARS};
+use crate::services::audit_bundle::BundleContents;
+use crate::services::audit_service::{events_csv, Acknowledgement, AuditFilter, AUDIT_SORT};
+use crate::services::auth_service::{UserFilter, USER_SORT};
+use crate::services::client_app_service::DEFAULT_CLIENT_ID;
+use crate::services::feature_flags::Feature;
+use crate::services::lockdown_service::LockdownRequest;
+use crate::services::webhook_service::NotificationQueueDepth;
+
+/// Toggle maintenance mode endpoint
+pub async fn set_maintenance_mode(
+    req: HttpRequest,
+    ctx: RequestContext,
+    toggle_request: web::Json<MaintenanceToggleRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    let toggle = toggle_request.into_inner();
+    if let Err(errors) = toggle.validate() {
+        return Ok(invalid_request("Invalid maintenance request", &errors));
+    }
+
+    let was_enabled = data.maintenance.is_enabled();
+    if toggle.enabled {
+        data.maintenance.enable(toggle.message.clone(), toggle.eta);
+    } else {
+        data.maintenance.disable();
+    }
+
+    log::warn!(
+        "Maintenance mode {} by {} from IP: {}",
+        if toggle.enabled { "enabled" } else { "disabled" },
+        admin.username,
+        ctx.ip_address
+    );
+
+    data.auth_service.audit_service().log_security_event(
+        &ctx,
+        Some(admin_id),
+        AuditEventType::MaintenanceModeChanged,
+        &format!(
+            "Maintenance mode {} by {}",
+            if toggle.enabled { "enabled" } else { "disabled" },
+            admin.username
+        ),
+        true,
+        Severity::Warning,
+        Some(json!({
+            "previously_enabled": was_enabled,
+            "enabled": toggle.enabled,
+            "message": toggle.message,
+            "eta": toggle.eta.map(|eta| eta.to_rfc3339()),
+        })),
+    ).await.unwrap_or_else(|e| log::error!("Failed to log maintenance toggle: {}", e));
+
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": if toggle.enabled { "Maintenance mode enabled" } else { "Maintenance mode disabled" },
+        "data": {
+            "enabled": data.maintenance.is_enabled(),
+            "notice": data.maintenance.notice(),
+        }
+    })))
+}
+
+/// Feature flags with their defaults and any admin overrides
+pub async fn list_feature_flags(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true,
+        "data": data.features.list()
+    })))
+}
+
+/// Override feature flags (`{"login_bot_checks": false}`); takes effect at
+/// once here and within 30 seconds on other instances
+pub async fn set_feature_flags(
+    req: HttpRequest,
+    ctx: RequestContext,
+    values: web::Json<BTreeMap<String, bool>>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    let mut flags = BTreeMap::new();
+    let mut unknown = Vec::new();
+    for (name, enabled) in values.into_inner() {
+        match name.parse::<Feature>() {
+            Ok(feature) => {
+                flags.insert(feature, enabled);
+            }
+            Err(_) => unknown.push(name),
+        }
+    }
+    if !unknown.is_empty() || flags.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Give one or more known feature flags",
+            "error_type": "UnknownFeatureFlag",
+            "unknown": unknown,
+            "known": Feature::ALL.map(Feature::as_str),
+        })));
+    }
+
+    let changes = match data.features.set(&flags, admin_id).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            log::error!("Failed to update feature flags: {}", e);
+            return Ok(AuthError::from(e).error_response());
+        }
+    };
+    if !changes.is_empty() {
+        log::warn!("Feature flags changed by {} from IP {}: {:?}", admin.username, ctx.ip_address, changes);
+        // Switching a check off can weaken the deployment; say so in the log at once
+        data.posture.run(&data.features);
+        data.auth_service.audit_service().log_security_event(
+            &ctx,
+            Some(admin_id),
+            AuditEventType::FeatureFlagsChanged,
+            &format!(
+                "Feature flags changed by {}: {}",
+                admin.username,
+                changes
+                    .iter()
+                    .map(|change| format!("{} {}", change.flag.as_str(), if change.to { "on" } else { "off" }))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            true,
+            Severity::Warning,
+            Some(json!({ "changes": changes })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log feature flag change: {}", e));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": format!("{} feature flag(s) changed", changes.len()),
+        "data": data.features.list()
+    })))
+}
+
+/// Settings combinations that weaken the deployment, most severe first,
+/// checked against the configuration and the feature flags in force now
+pub async fn security_posture(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let findings = data.posture.run(&data.features);
+    let count = |severity: Severity| findings.iter().filter(|f| f.severity == severity).count();
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true,
+        "data": {
+            "critical": count(Severity::Critical),
+            "warning": count(Severity::Warning),
+            "info": count(Severity::Info),
+            "findings": findings,
+        }
+    })))
+}
+
+/// Every route under `/api` with what it requires of the caller, and any
+/// declaration that leaves a route less protected than its scope demands
+pub async fn list_routes(req: HttpRequest, routes: web::Data<RouteTable>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let entries: Vec<_> = routes
+        .routes()
+        .iter()
+        .map(|route| {
+            json!({
+                "method": route.method.as_str(),
+                "pattern": route.pattern,
+                "protection": route.access.to_string(),
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true,
+        "data": {
+            "total": entries.len(),
+            "routes": entries,
+            "findings": routes.findings(),
+        }
+    })))
+}
+
+/// The default client app and every registered one
+pub async fn list_client_apps(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true,
+        "data": data.clients.list()
+    })))
+}
+
+/// Register a client app; it can sign in at once here and within 30
+/// seconds on other instances
+pub async fn create_client_app(
+    req: HttpRequest,
+    ctx: RequestContext,
+    app_request: web::Json<NewClientAppRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    let request = app_request.into_inner();
+    if let Err(errors) = request.validate() {
+        return Ok(invalid_request("Invalid client app", &errors));
+    }
+    let NewClientAppRequest { id, app } = request;
+    let draft = match app.into_draft() {
+        Ok(draft) => draft,
+        Err(message) => return Ok(invalid_origins(&message)),
+    };
+    if data.clients.get(&id).is_some() {
+        return Ok(client_app_conflict("A client app with this ID already exists"));
+    }
+    if data.clients.audience_taken(&draft.token_audience, None) {
+        return Ok(client_app_conflict("Another client app already uses this token audience"));
+    }
+
+    match data.clients.create(&id, &draft, admin_id).await {
+        Ok(app) => {
+            log::warn!("Client app {} registered by {} from IP {}", app.id, admin.username, ctx.ip_address);
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::ClientAppCreated,
+                &format!("Client app {} registered by {}", app.id, admin.username),
+                true,
+                Severity::Warning,
+                Some(json!({ "client_app": app })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log client app registration: {}", e));
+
+            Ok(HttpResponse::Created().json(json!({
+                "success": true,
+                "message": "Client app registered",
+                "data": app
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to register client app {}: {}", id, e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Replace a registered client app's settings. Disabling an app sends its
+/// clients back to the default app; tokens already issued to it stop working.
+pub async fn update_client_app(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<String>,
+    app_request: web::Json<ClientAppRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let id = path.into_inner();
+    if id == DEFAULT_CLIENT_ID {
+        return Ok(default_client_app_is_built_in());
+    }
+
+    let request = app_request.into_inner();
+    if let Err(errors) = request.validate() {
+        return Ok(invalid_request("Invalid client app", &errors));
+    }
+    let draft = match request.into_draft() {
+        Ok(draft) => draft,
+        Err(message) => return Ok(invalid_origins(&message)),
+    };
+    if data.clients.audience_taken(&draft.token_audience, Some(&id)) {
+        return Ok(client_app_conflict("Another client app already uses this token audience"));
+    }
+
+    match data.clients.update(&id, &draft).await {
+        Ok(Some((previous, app))) => {
+            log::warn!("Client app {} changed by {} from IP {}", app.id, admin.username, ctx.ip_address);
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::ClientAppUpdated,
+                &format!("Client app {} changed by {}", app.id, admin.username),
+                true,
+                Severity::Warning,
+                Some(json!({ "previous": previous, "client_app": app })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log client app change: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Client app updated",
+                "data": app
+            })))
+        }
+        Ok(None) => Ok(client_app_not_found()),
+        Err(e) => {
+            log::error!("Failed to update client app {}: {}", id, e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Remove a registered client app; its clients fall back to the default app
+pub async fn delete_client_app(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let id = path.into_inner();
+    if id == DEFAULT_CLIENT_ID {
+        return Ok(default_client_app_is_built_in());
+    }
+
+    match data.clients.delete(&id).await {
+        Ok(Some(app)) => {
+            log::warn!("Client app {} removed by {} from IP {}", app.id, admin.username, ctx.ip_address);
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::ClientAppDeleted,
+                &format!("Client app {} removed by {}", app.id, admin.username),
+                true,
+                Severity::Warning,
+                Some(json!({ "client_app": app })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log client app removal: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Client app removed"
+            })))
+        }
+        Ok(None) => Ok(client_app_not_found()),
+        Err(e) => {
+            log::error!("Failed to remove client app {}: {}", id, e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+fn invalid_origins(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": "Invalid client app",
+        "errors": { "allowed_origins": [message] }
+    }))
+}
+
+fn client_app_conflict(message: &str) -> HttpResponse {
+    HttpResponse::Conflict().json(json!({
+        "success": false,
+        "message": message,
+        "error_type": "ClientAppConflict"
+    }))
+}
+
+fn client_app_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({
+        "success": false,
+        "message": "Client app not found"
+    }))
+}
+
+fn default_client_app_is_built_in() -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": format!("The {} client app is built in and can't be changed", DEFAULT_CLIENT_ID),
+        "error_type": "ClientAppBuiltIn"
+    }))
+}
+
+/// Every login screen notice, past, current and scheduled
+pub async fn list_system_messages(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.system_messages.list().await {
+        Ok(messages) => {
+            record_read_rows(&req, messages.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": messages
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list system messages: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Add a login screen notice endpoint
+pub async fn create_system_message(
+    req: HttpRequest,
+    ctx: RequestContext,
+    message_request: web::Json<SystemMessageRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    let request = message_request.into_inner();
+    if let Err(errors) = request.validate() {
+        return Ok(invalid_request("Invalid system message", &errors));
+    }
+    let draft = match request.into_draft(data.auth_service.now()) {
+        Ok(draft) => draft,
+        Err(message) => return Ok(invalid_window(message)),
+    };
+
+    match data.system_messages.create(&draft, admin_id).await {
+        Ok(message) => {
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::SystemMessageCreated,
+                &format!("System message {} added by {}", message.id, admin.username),
+                true,
+                Severity::Info,
+                Some(json!({
+                    "message_id": message.id.to_string(),
+                    "body": message.body,
+                    "severity": message.severity,
+                    "starts_at": message.starts_at.to_rfc3339(),
+                    "ends_at": message.ends_at.to_rfc3339(),
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log system message creation: {}", e));
+
+            Ok(HttpResponse::Created().json(json!({
+                "success": true,
+                "message": "System message added",
+                "data": message
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to add system message: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Replace a login screen notice's text and window endpoint
+pub async fn update_system_message(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    message_request: web::Json<SystemMessageRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let message_id = path.into_inner();
+
+    let request = message_request.into_inner();
+    if let Err(errors) = request.validate() {
+        return Ok(invalid_request("Invalid system message", &errors));
+    }
+    let draft = match request.into_draft(data.auth_service.now()) {
+        Ok(draft) => draft,
+        Err(message) => return Ok(invalid_window(message)),
+    };
+
+    match data.system_messages.update(message_id, &draft).await {
+        Ok(Some((previous, message))) => {
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::SystemMessageUpdated,
+                &format!("System message {} changed by {}", message.id, admin.username),
+                true,
+                Severity::Info,
+                Some(json!({
+                    "message_id": message.id.to_string(),
+                    "previous_body": previous.body,
+                    "body": message.body,
+                    "severity": message.severity,
+                    "starts_at": message.starts_at.to_rfc3339(),
+                    "ends_at": message.ends_at.to_rfc3339(),
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log system message change: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "System message updated",
+                "data": message
+            })))
+        }
+        Ok(None) => Ok(system_message_not_found()),
+        Err(e) => {
+            log::error!("Failed to update system message {}: {}", message_id, e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Remove a login screen notice endpoint
+pub async fn delete_system_message(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let message_id = path.into_inner();
+
+    match data.system_messages.delete(message_id).await {
+        Ok(Some(message)) => {
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::SystemMessageDeleted,
+                &format!("System message {} removed by {}", message.id, admin.username),
+                true,
+                Severity::Info,
+                Some(json!({
+                    "message_id": message.id.to_string(),
+                    "body": message.body,
+                    "created_by": message.created_by.to_string(),
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log system message removal: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "System message removed"
+            })))
+        }
+        Ok(None) => Ok(system_message_not_found()),
+        Err(e) => {
+            log::error!("Failed to remove system message {}: {}", message_id, e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// A message window that ends before it starts, reported like a failed field validation
+fn invalid_window(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": "Invalid system message",
+        "errors": { "ends_at": [message] }
+    }))
+}
+
+fn system_message_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({
+        "success": false,
+        "message": "System message not found"
+    }))
+}
+
+/// Snapshot the database into the backup directory endpoint
+pub async fn create_backup(req: HttpRequest, ctx: RequestContext, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    match data.backups.create_backup().await {
+        Ok(backup) => {
+            log::warn!(
+                "Database backup {} taken by {} from IP: {}",
+                backup.path.display(),
+                admin.username,
+                ctx.ip_address
+            );
+
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::DatabaseBackup,
+                &format!("Database backup taken by {}", admin.username),
+                true,
+                Severity::Warning,
+                Some(json!({
+                    "path": backup.path.display().to_string(),
+                    "sha256": backup.sha256,
+                    "size_bytes": backup.size_bytes,
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log database backup: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Backup created",
+                "data": backup
+            })))
+        }
+        Err(e) => {
+            log::error!("Database backup requested by {} failed: {}", admin.username, e);
+            Ok(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": "Backup failed"
+            })))
+        }
+    }
+}
+
+/// Account status changes an administrator can make
+#[derive(Debug, Clone, Copy)]
+enum AccountAction {
+    Lock,
+    Unlock,
+    Deactivate,
+    Activate,
+}
+
+impl AccountAction {
+    fn event_type(self) -> AuditEventType {
+        match self {
+            AccountAction::Lock => AuditEventType::AccountLocked,
+            AccountAction::Unlock => AuditEventType::AccountUnlocked,
+            AccountAction::Deactivate => AuditEventType::AccountDeactivated,
+            AccountAction::Activate => AuditEventType::AccountActivated,
+        }
+    }
+
+    /// Taking access away is critical; restoring it is worth a look
+    fn severity(self) -> Severity {
+        match self {
+            AccountAction::Lock | AccountAction::Deactivate => Severity::Critical,
+            AccountAction::Unlock | AccountAction::Activate => Severity::Warning,
+        }
+    }
+
+    fn past_tense(self) -> &'static str {
+        match self {
+            AccountAction::Lock => "locked",
+            AccountAction::Unlock => "unlocked",
+            AccountAction::Deactivate => "deactivated",
+            AccountAction::Activate => "activated",
+        }
+    }
+}
+
+/// Lock a user account endpoint; the account's session is revoked immediately
+pub async fn lock_user(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    lock_request: web::Json<LockUserRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let lock = lock_request.into_inner();
+    if let Err(errors) = lock.validate() {
+        return Ok(invalid_request("Invalid lock request", &errors));
+    }
+
+    change_account_status(&req, &ctx, path.into_inner(), AccountAction::Lock, lock, &data).await
+}
+
+/// Unlock a user account endpoint
+pub async fn unlock_user(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    change_account_status(&req, &ctx, path.into_inner(), AccountAction::Unlock, LockUserRequest::default(), &data).await
+}
+
+/// Deactivate a user account endpoint; the account's session is revoked immediately
+pub async fn deactivate_user(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    change_account_status(&req, &ctx, path.into_inner(), AccountAction::Deactivate, LockUserRequest::default(), &data).await
+}
+
+/// Reactivate a user account endpoint
+pub async fn activate_user(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    change_account_status(&req, &ctx, path.into_inner(), AccountAction::Activate, LockUserRequest::default(), &data).await
+}
+
+async fn change_account_status(
+    req: &HttpRequest,
+    ctx: &RequestContext,
+    target_id: Uuid,
+    action: AccountAction,
+    lock: LockUserRequest,
+    data: &web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    // An admin locking themselves out would leave nobody to undo it
+    if target_id == admin_id && matches!(action, AccountAction::Lock | AccountAction::Deactivate) {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Administrators cannot lock or deactivate their own account"
+        })));
+    }
+
+    let locked_until = lock.duration_minutes.map(|minutes| data.auth_service.now() + Duration::minutes(minutes));
+    let result = match action {
+        AccountAction::Lock => data.auth_service.lock_user(ctx, target_id, locked_until).await,
+        AccountAction::Unlock => data.auth_service.unlock_user(target_id).await,
+        AccountAction::Deactivate => data.auth_service.set_user_active(target_id, false).await,
+        AccountAction::Activate => data.auth_service.set_user_active(target_id, true).await,
+    };
+
+    match result {
+        Ok(()) => {
+            log::warn!(
+                "User {} {} by {} from IP: {}",
+                target_id,
+                action.past_tense(),
+                admin.username,
+                ctx.ip_address
+            );
+
+            data.auth_service.audit_service().log_security_event(
+                ctx,
+                Some(admin_id),
+                action.event_type(),
+                &format!("User {} {} by {}", target_id, action.past_tense(), admin.username),
+                true,
+                action.severity(),
+                Some(json!({
+                    "target_user_id": target_id.to_string(),
+                    "reason": lock.reason,
+                    "locked_until": locked_until.map(|until| until.to_rfc3339()),
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log account status change: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": format!("User {}", action.past_tense())
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to change status of user {}: {}", target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Page shown when an action link is opened without a session; the link
+/// stays unused until it is opened again while signed in
+pub(crate) const ACTION_LINK_SIGN_IN_PAGE: &str = "<!DOCTYPE html>\n<html lang=\"en\">\n<head><meta charset=\"utf-8\">\
+<title>Sign in to continue</title></head>\n<body>\n<h1>Sign in to continue</h1>\n\
+<p>This link approves an administrative action on the Kenya FSFVI platform. Sign in as the administrator \
+it was sent to, then open the link again. It works once and expires 15 minutes after it was sent.</p>\n\
+</body>\n</html>\n";
+
+/// Send an administrator a one-time link that unlocks an account or resets
+/// its 2FA when they open it, e.g. from email on a phone
+pub async fn request_admin_action(
+    req: HttpRequest,
+    ctx: RequestContext,
+    action_request: web::Json<AdminActionRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    let action_request = action_request.into_inner();
+    let approver_id = action_request.approver_id.unwrap_or(admin_id);
+    match data.auth_service.user_permissions(approver_id).await {
+        Ok(approver) if approver.effective.contains(Permission::UserManage) => {}
+        Ok(_) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "The approver must be allowed to manage users",
+                "error_type": "ApproverNotPermitted"
+            })))
+        }
+        Err(e) => return Ok(e.error_response()),
+    }
+
+    match data
+        .auth_service
+        .request_admin_action(&ctx, admin_id, &admin.username, action_request.action, action_request.target_user_id, approver_id)
+        .await
+    {
+        Ok(pending) => Ok(HttpResponse::Accepted().json(json!({
+            "success": true,
+            "message": "Action link sent",
+            "data": pending
+        }))),
+        Err(e) => {
+            log::error!("Failed to send admin action link: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Carry out the action behind a one-time link. Without a session the route
+/// guard serves `ACTION_LINK_SIGN_IN_PAGE` and leaves the link unused.
+pub async fn confirm_admin_action(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    match data.auth_service.confirm_admin_action(&ctx, admin_id, &admin.username, &path.into_inner()).await {
+        Ok(pending) => Ok(HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .insert_header((header::REFERRER_POLICY, "no-referrer"))
+            .json(json!({
+                "success": true,
+                "message": "Action carried out",
+                "data": pending
+            }))),
+        Err(e) => Ok(e.error_response()),
+    }
+}
+
+/// Show a user's permissions endpoint
+pub async fn get_user_permissions(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.auth_service.user_permissions(path.into_inner()).await {
+        Ok(permissions) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": permissions
+        }))),
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to load permissions: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Replace a user's permission overrides endpoint; the user's tokens stop working at once
+pub async fn set_user_permissions(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    permissions_request: web::Json<SetPermissionsRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let target_id = path.into_inner();
+
+    let request = permissions_request.into_inner();
+    if let Some(permission) = request.duplicate() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": format!("Permission {} is overridden more than once", permission)
+        })));
+    }
+
+    let previous = match data.auth_service.user_permissions(target_id).await {
+        Ok(previous) => previous,
+        Err(AuthError::UserNotFound) => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "User not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to load permissions of user {}: {}", target_id, e);
+            return Ok(e.error_response());
+        }
+    };
+
+    match data.auth_service.set_user_permissions(admin_id, target_id, &request.overrides).await {
+        Ok(updated) => {
+            log::warn!(
+                "Permissions of user {} changed by {} from IP: {}",
+                target_id,
+                admin.username,
+                ctx.ip_address
+            );
+
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::PermissionsChanged,
+                &format!("Permissions of user {} changed by {}", target_id, admin.username),
+                true,
+                Severity::Critical,
+                Some(json!({
+                    "target_user_id": target_id.to_string(),
+                    "previous_overrides": previous.overrides,
+                    "overrides": updated.overrides,
+                    "previous_effective": previous.effective,
+                    "effective": updated.effective,
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log permission change: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Permissions updated",
+                "data": updated
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to change permissions of user {}: {}", target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+fn invalid_organization_response() -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": "Organization must be 1-100 letters, digits, spaces, dots, dashes or underscores"
+    }))
+}
+
+/// List every organization security policy endpoint
+pub async fn list_org_policies(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.auth_service.policies().list().await {
+        Ok(policies) => {
+            record_read_rows(&req, policies.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": policies
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list organization policies: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Show an organization's security policy overrides and the settings in force endpoint
+pub async fn get_org_policy(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+    let organization = path.into_inner();
+    if !is_valid_organization(&organization) {
+        return Ok(invalid_organization_response());
+    }
+
+    match data.auth_service.org_policy(&organization).await {
+        Ok(policy) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": policy
+        }))),
+        Err(e) => {
+            log::error!("Failed to load policy of organization {}: {}", organization, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Replace an organization's security policy overrides endpoint; applies to
+/// its members from their next request
+pub async fn set_org_policy(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<String>,
+    policy_request: web::Json<SetOrgPolicyRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let organization = path.into_inner();
+    if !is_valid_organization(&organization) {
+        return Ok(invalid_organization_response());
+    }
+    if let Err(errors) = policy_request.validate() {
+        return Ok(invalid_request("Invalid policy", &errors));
+    }
+
+    let result = async {
+        let previous = data.auth_service.org_policy(&organization).await?;
+        data.auth_service.policies().set(&organization, &policy_request, admin_id).await?;
+        let updated = data.auth_service.org_policy(&organization).await?;
+        Ok::<_, AuthError>((previous, updated))
+    }
+    .await;
+
+    match result {
+        Ok((previous, updated)) => {
+            log::warn!(
+                "Security policy of organization {} changed by {} from IP: {}",
+                organization,
+                admin.username,
+                ctx.ip_address
+            );
+
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::OrgPolicyChanged,
+                &format!("Security policy of organization {} changed by {}", organization, admin.username),
+                true,
+                Severity::Critical,
+                Some(json!({
+                    "organization": organization,
+                    "previous_effective": previous.effective,
+                    "effective": updated.effective,
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log policy change: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Policy updated",
+                "data": updated
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to change policy of organization {}: {}", organization, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Move a user into or out of an organization endpoint
+pub async fn set_user_organization(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    organization_request: web::Json<SetOrganizationRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let target_id = path.into_inner();
+    let organization = organization_request.into_inner().organization;
+    if organization.as_deref().is_some_and(|name| !is_valid_organization(name)) {
+        return Ok(invalid_organization_response());
+    }
+
+    match data.auth_service.set_user_organization(target_id, organization.as_deref()).await {
+        Ok(previous) => {
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::UserOrganizationChanged,
+                &format!("Organization of user {} changed by {}", target_id, admin.username),
+                true,
+                Severity::Warning,
+                Some(json!({
+                    "target_user_id": target_id.to_string(),
+                    "previous_organization": previous,
+                    "organization": organization,
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log organization change: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Organization updated",
+                "data": { "organization": organization }
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to change organization of user {}: {}", target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// List a user's live sessions endpoint
+pub async fn list_user_sessions(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.auth_service.user_sessions(path.into_inner()).await {
+        Ok(sessions) => {
+            record_read_rows(&req, sessions.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": sessions
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to list sessions: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// End every live session of a user endpoint
+pub async fn terminate_user_sessions(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let target_id = path.into_inner();
+
+    match data.auth_service.terminate_user_sessions(admin_id, target_id).await {
+        Ok(terminated) => {
+            log_sessions_terminated(&ctx, &data, admin_id, &admin.username, target_id, &terminated).await;
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": format!("{} session(s) terminated", terminated.len()),
+                "data": { "terminated": terminated }
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to terminate sessions of user {}: {}", target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// End one live session endpoint
+pub async fn terminate_session(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let session_id = path.into_inner();
+
+    match data.auth_service.terminate_session(admin_id, &session_id).await {
+        Ok(target_id) => {
+            let terminated = [session_id];
+            log_sessions_terminated(&ctx, &data, admin_id, &admin.username, target_id, &terminated).await;
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Session terminated",
+                "data": { "terminated": terminated }
+            })))
+        }
+        Err(AuthError::SessionExpired) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "No live session with that ID"
+        }))),
+        Err(e) => {
+            log::error!("Failed to terminate session: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Revoke one token by its ID (`jti`), e.g. one reported leaked, leaving
+/// its session live. Revoking it again changes nothing.
+pub async fn revoke_token(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let jti = path.into_inner();
+
+    match data.auth_service.revoke_token(&ctx, admin_id, &admin.username, &jti).await {
+        Ok(revoked) => {
+            log::warn!("Token {} of user {} revoked by {} from IP: {}", jti, revoked.user_id, admin.username, ctx.ip_address);
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": if revoked.newly_revoked { "Token revoked" } else { "Token was already revoked" },
+                "data": revoked
+            })))
+        }
+        Err(e) => {
+            log::warn!("Failed to revoke token {}: {}", jti, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+async fn log_sessions_terminated(
+    ctx: &RequestContext,
+    data: &web::Data<AppState>,
+    admin_id: Uuid,
+    admin_username: &str,
+    target_id: Uuid,
+    session_ids: &[String],
+) {
+    log::warn!(
+        "{} session(s) of user {} terminated by {} from IP: {}",
+        session_ids.len(),
+        target_id,
+        admin_username,
+        ctx.ip_address
+    );
+
+    data.auth_service.audit_service().log_security_event(
+        ctx,
+        Some(admin_id),
+        AuditEventType::SessionsTerminated,
+        &format!("{} session(s) of user {} terminated by {}", session_ids.len(), target_id, admin_username),
+        true,
+        Severity::Critical,
+        Some(json!({
+            "target_user_id": target_id.to_string(),
+            "admin_username": admin_username,
+            "session_ids": session_ids,
+        })),
+    ).await.unwrap_or_else(|e| log::error!("Failed to log session termination: {}", e));
+}
+
+/// The lockdown in force and the request waiting for approval, if any
+pub async fn lockdown_status(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.lockdown.pending().await {
+        Ok(pending) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": {
+                "active": data.lockdown.active(),
+                "pending": pending,
+                "auth_epoch": data.lockdown.epoch(),
+                "default_duration_minutes": data.lockdown.default_minutes(),
+            }
+        }))),
+        Err(e) => {
+            log::error!("Failed to read the lockdown state: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Request an emergency lockdown; `202` while it waits for a second
+/// administrator, whose own request starts it with `200`
+pub async fn request_lockdown(
+    req: HttpRequest,
+    ctx: RequestContext,
+    lockdown_request: web::Json<LockdownRequestBody>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    if let Err(errors) = lockdown_request.validate() {
+        return Ok(invalid_request("Invalid lockdown request", &errors));
+    }
+
+    let LockdownRequestBody { reason, duration_minutes } = lockdown_request.into_inner();
+    let duration_minutes = duration_minutes.unwrap_or_else(|| data.lockdown.default_minutes());
+    match data.auth_service.request_lockdown(&ctx, admin_id, &admin.username, &reason, duration_minutes).await {
+        Ok(LockdownRequest::Pending(lockdown)) => Ok(HttpResponse::Accepted().json(json!({
+            "success": true,
+            "message": "Lockdown requested; it starts once another administrator requests it too",
+            "data": lockdown
+        }))),
+        Ok(LockdownRequest::Started(lockdown)) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Lockdown started; every session was ended",
+            "data": lockdown
+        }))),
+        Ok(LockdownRequest::AwaitingApproval(lockdown)) => Ok(HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": "Your lockdown request is waiting for another administrator",
+            "error_type": "LockdownAwaitingApproval",
+            "data": lockdown
+        }))),
+        Ok(LockdownRequest::AlreadyActive(lockdown)) => Ok(HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": "A lockdown is already in force",
+            "error_type": "LockdownActive",
+            "data": lockdown
+        }))),
+        Err(e) => {
+            log::error!("Failed to request a lockdown: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// End the lockdown in force, or withdraw the request waiting for approval
+pub async fn lift_lockdown(req: HttpRequest, ctx: RequestContext, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    match data.auth_service.lift_lockdown(&ctx, admin_id, &admin.username).await {
+        Ok(lockdown) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": if lockdown.started_at.is_some() { "Lockdown ended" } else { "Lockdown request withdrawn" },
+            "data": lockdown
+        }))),
+        Err(e) => {
+            if !matches!(e, AuthError::LockdownNotFound) {
+                log::error!("Failed to lift the lockdown: {}", e);
+            }
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Security event listing endpoint, paged and sorted by `ListParams`
+pub async fn list_audit_events(
+    req: HttpRequest,
+    query: web::Query<AuditEventsQuery>,
+    params: ListParams,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let filter = match audit_filter(&query) {
+        Ok(filter) => filter,
+        Err(unknown) => return Ok(unknown_event_type(&unknown)),
+    };
+    let page = match params.resolve(&AUDIT_SORT) {
+        Ok(page) => page,
+        Err(e) => return Ok(e.error_response()),
+    };
+
+    if !query.include_archives.unwrap_or(false) {
+        return match data.auth_service.audit_service().list_events_page(&filter, &page).await {
+            Ok(events) => {
+                record_read_rows(&req, events.items.len());
+                Ok(HttpResponse::Ok().json(json!({
+                    "success": true,
+                    "data": events
+                })))
+            }
+            Err(e) => {
+                log::error!("Failed to list security events: {}", e);
+                Ok(AuthError::from(e).error_response())
+            }
+        };
+    }
+
+    if !data.audit_archives.reads_enabled() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Reading audit archives is turned off; set AUDIT_ARCHIVE_READS to allow it",
+            "error_type": "ArchiveReadsDisabled"
+        })));
+    }
+    let archives = match data.audit_archives.archives_between(filter.since, filter.until).await {
+        Ok(archives) => archives,
+        Err(e) => {
+            log::error!("Failed to read the audit archive manifest: {}", e);
+            return Ok(AuthError::from(e).error_response());
+        }
+    };
+    let paths: Vec<PathBuf> = archives.iter().map(|archive| PathBuf::from(&archive.path)).collect();
+    match data.auth_service.audit_service().list_events_page_with_archives(&filter, &page, &paths).await {
+        Ok(events) => {
+            record_read_rows(&req, events.items.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": events,
+                "archives": archives.iter().map(|archive| &archive.month).collect::<Vec<_>>()
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list security events: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// The filter an audit listing or export asked for
+fn audit_filter(query: &AuditEventsQuery) -> Result<AuditFilter, UnknownEventType> {
+    Ok(AuditFilter {
+        unacknowledged_only: query.unacknowledged.unwrap_or(false),
+        severity: query.severity,
+        event_type: query.event_type.as_deref().map(str::parse).transpose()?,
+        include_reads: query.include_reads.unwrap_or(false),
+        since: query.from,
+        until: query.to,
+    })
+}
+
+/// An event type filter naming no event type, answered with the ones there are
+fn unknown_event_type(unknown: &UnknownEventType) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": "Unknown event type",
+        "errors": { "event_type": [unknown.to_string()] },
+        "valid_event_types": AuditEventType::filterable().collect::<Vec<_>>()
+    }))
+}
+
+/// Download recent security events as CSV or JSON, newest first, with the
+/// same filters as the listing. `?bundle=true` wraps the file in a zip with a
+/// manifest signed by the audit signing key, for handing over as evidence.
+pub async fn export_audit_events(
+    req: HttpRequest,
+    ctx: RequestContext,
+    query: web::Query<AuditEventsQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    let filter = match audit_filter(&query) {
+        Ok(filter) => filter,
+        Err(unknown) => return Ok(unknown_event_type(&unknown)),
+    };
+
+    // Refused up front, so an unusable key never yields an unsigned bundle
+    let signer = match (query.bundle.unwrap_or(false), data.audit_signing.signer()) {
+        (false, _) => None,
+        (true, Ok(signer)) => Some(signer),
+        (true, Err(reason)) => {
+            return Ok(HttpResponse::ServiceUnavailable().json(json!({
+                "success": false,
+                "message": format!("Signed audit bundles are unavailable: {}", reason),
+                "error_type": "AuditSigningUnavailable"
+            })));
+        }
+    };
+
+    let limit = query.limit.unwrap_or(1000).clamp(1, 10_000);
+    let format = query.format.unwrap_or_default();
+    let audit = data.auth_service.audit_service();
+
+    let mut events = match audit.list_events(limit, &filter).await {
+        Ok(events) => events,
+        Err(e) => {
+            log::error!("Failed to export security events: {}", e);
+            return Ok(AuthError::from(e).error_response());
+        }
+    };
+    audit.truncate_exported_ips(&mut events);
+
+    let (file_name, content_type, body) = match format {
+        ExportFormat::Csv => ("security-events.csv", "text/csv; charset=utf-8", events_csv(&events).into_bytes()),
+        ExportFormat::Json => match serde_json::to_vec_pretty(&events) {
+            Ok(body) => ("security-events.json", "application/json", body),
+            Err(e) => return Ok(AuthError::from(e).error_response()),
+        },
+    };
+    let filters = json!({
+        "unacknowledged_only": filter.unacknowledged_only,
+        "severity": filter.severity,
+        "event_type": filter.event_type,
+        "include_reads": filter.include_reads,
+        "from": filter.since,
+        "to": filter.until,
+        "limit": limit
+    });
+
+    let (file_name, content_type, body, sha256) = match signer {
+        None => (file_name, content_type, body, None),
+        Some(signer) => {
+            let contents = BundleContents {
+                data_file: file_name,
+                data: &body,
+                rows: events.len(),
+                first_event_at: events.iter().map(|e| e.timestamp).min(),
+                last_event_at: events.iter().map(|e| e.timestamp).max(),
+                filters: filters.clone(),
+                generated_at: data.auth_service.now(),
+                generated_by: &admin.username,
+            };
+            match signer.bundle(&contents) {
+                Ok((bundle, manifest)) => ("security-events.zip", "application/zip", bundle, Some(manifest.sha256)),
+                Err(e) => {
+                    log::error!("Failed to build audit export bundle: {}", e);
+                    return Ok(AuthError::InternalError(e.to_string()).error_response());
+                }
+            }
+        }
+    };
+
+    record_read_rows(&req, events.len());
+    audit.log_security_event(
+        &ctx,
+        Some(admin_id),
+        AuditEventType::AuditExported,
+        &format!("Admin {} exported {} security events", admin.username, events.len()),
+        true,
+        Severity::Info,
+        Some(json!({
+            "rows": events.len(),
+            "unacknowledged_only": filter.unacknowledged_only,
+            "severity": filter.severity,
+            "event_type": filter.event_type,
+            "include_reads": filter.include_reads,
+            "from": filter.since,
+            "to": filter.until,
+            "format": format,
+            "signed_bundle": sha256.is_some(),
+            "data_sha256": sha256,
+        })),
+    ).await.unwrap_or_else(|e| log::error!("Failed to log audit export: {}", e));
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", file_name)))
+        .insert_header(("Cache-Control", "no-store"))
+        .body(body))
+}
+
+/// Export one user's personal data for a data subject access request.
+/// Returns a download link that works for `DATA_EXPORT_TTL_MINUTES`.
+pub async fn export_user_data(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, _) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    Ok(data_export_response(data.auth_service.request_data_export(&ctx, admin_id, path.into_inner()).await))
+}
+
+/// Issue a one-time password reset link for a user who can't receive the
+/// emailed one. It goes back to the administrator to pass on out of band and
+/// is redeemed at `POST /api/auth/password-reset/confirm` like an emailed link.
+pub async fn issue_password_reset_link(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let target_id = path.into_inner();
+
+    match data.auth_service.issue_password_reset_link(&ctx, admin_id, &admin.username, target_id).await {
+        Ok((reset, reset_url, token)) => {
+            log::warn!("Password reset link for user {} issued to {} from IP: {}", target_id, admin.username, ctx.ip_address);
+
+            Ok(HttpResponse::Created()
+                .insert_header((header::CACHE_CONTROL, "no-store"))
+                .json(json!({
+                    "success": true,
+                    "message": "Password reset link issued. Pass it to the user through a trusted channel; it works once",
+                    "data": {
+                        "reset_url": reset_url,
+                        "token": token,
+                        "expires_at": reset.expires_at,
+                    }
+                })))
+        }
+        Err(e) => {
+            log::warn!("Failed to issue a password reset link for user {}: {}", target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Everything one client address did, across login attempts and security
+/// events, newest first, with a correlation summary
+pub async fn audit_by_ip(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<IpActivityQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let Some(ip_address) = normalize_ip(&path.into_inner()) else {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Not an IP address"
+        })));
+    };
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    match data.auth_service.audit_service().ip_activity(&ip_address, limit, offset).await {
+        Ok(activity) => {
+            record_read_rows(&req, activity.entries.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": activity
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to look up activity of {}: {}", ip_address, e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Browser CSP violation listing endpoint
+pub async fn list_csp_reports(
+    req: HttpRequest,
+    query: web::Query<CspReportsQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    match data.csp_reports.list(limit).await {
+        Ok(reports) => {
+            record_read_rows(&req, reports.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": reports
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list CSP reports: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Outbound webhook deliveries that failed permanently, newest first
+pub async fn list_webhook_dead_letters(
+    req: HttpRequest,
+    query: web::Query<DeadLettersQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match data.webhooks.dead_letters(limit, offset).await {
+        Ok(dead_letters) => {
+            record_read_rows(&req, dead_letters.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": dead_letters
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list webhook dead letters: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// The running instance's effective configuration, secrets left out
+pub async fn get_config(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let (config, config_hash) = data.config_snapshots.current();
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true,
+        "data": {
+            "config_hash": config_hash,
+            "config": config
+        }
+    })))
+}
+
+/// Live sessions still on tokens signed with the previous JWT secret, to
+/// watch a secret migration before its deadline
+pub async fn jwt_migration_status(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.auth_service.jwt_migration_status().await {
+        Ok(status) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": status
+        }))),
+        Err(e) => {
+            log::error!("Failed to report JWT migration status: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Recorded configurations, newest first, each with the settings that changed
+/// from the one before
+pub async fn config_history(
+    req: HttpRequest,
+    query: web::Query<ConfigHistoryQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    match data.config_snapshots.history(limit).await {
+        Ok(history) => {
+            record_read_rows(&req, history.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": history
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list configuration history: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// User listing endpoint, narrowed by the security-state filters of
+/// `UsersQuery` and a `?q=` search, paged and sorted by `ListParams`
+pub async fn list_users(
+    req: HttpRequest,
+    query: web::Query<UsersQuery>,
+    params: ListParams,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let query = query.into_inner();
+    if let Err(errors) = query.validate() {
+        return Ok(invalid_request("Invalid user listing parameters", &errors));
+    }
+    let tag = match query.tag.as_deref().map(normalize_tag) {
+        None => None,
+        Some(Some(tag)) => Some(tag),
+        Some(None) => return Ok(invalid_tag_response()),
+    };
+    let role = match query.role.as_deref().map(UserRole::from_name) {
+        None => None,
+        Some(Some(role)) => Some(role),
+        Some(None) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Unknown role",
+                "errors": { "role": ["Role must be admin or kenya_government"] }
+            })))
+        }
+    };
+    let page = match params.resolve(&USER_SORT) {
+        Ok(page) => page,
+        Err(e) => return Ok(e.error_response()),
+    };
+
+    let filter = UserFilter {
+        onboarded: query.onboarded,
+        tag,
+        locked: query.locked,
+        two_fa_enabled: query.two_fa_enabled,
+        temporary_password: query.temporary_password,
+        organization: query.organization,
+        role,
+        active: query.active,
+        search: query.q,
+    };
+    match data.auth_service.list_users(&filter, &page).await {
+        Ok(users) => {
+            record_read_rows(&req, users.items.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": users
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to list users: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+fn invalid_tag_response() -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": format!(
+            "Tags must be 1-{} letters, digits, dashes, underscores, dots or colons, at most {} per account",
+            MAX_TAG_CHARS, MAX_TAGS_PER_USER
+        )
+    }))
+}
+
+/// One account with its tags and helpdesk notes endpoint
+pub async fn get_user_detail(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.auth_service.admin_user_detail(path.into_inner()).await {
+        Ok(detail) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": detail
+        }))),
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to load user: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// List the helpdesk notes on an account endpoint
+pub async fn list_account_notes(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.auth_service.account_notes(path.into_inner()).await {
+        Ok(notes) => {
+            record_read_rows(&req, notes.len());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": notes
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to list account notes: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Add a helpdesk note to an account endpoint. Notes can't be edited or
+/// deleted afterwards, only struck through.
+pub async fn add_account_note(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    note_request: web::Json<AddAccountNoteRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let target_id = path.into_inner();
+
+    let request = note_request.into_inner();
+    if let Err(errors) = request.validate() {
+        return Ok(invalid_request("Invalid note", &errors));
+    }
+
+    match data.auth_service.add_account_note(admin_id, target_id, &request.note, request.pinned).await {
+        Ok(note) => {
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::AccountNoteAdded,
+                &format!("Note added to user {} by {}", target_id, admin.username),
+                true,
+                Severity::Info,
+                Some(json!({
+                    "target_user_id": target_id.to_string(),
+                    "note_id": note.id.to_string(),
+                    "pinned": note.pinned,
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log account note: {}", e));
+
+            Ok(HttpResponse::Created().json(json!({
+                "success": true,
+                "message": "Note added",
+                "data": note
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to add note to user {}: {}", target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Strike through a helpdesk note endpoint; the note stays readable
+pub async fn strike_account_note(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<(Uuid, Uuid)>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let (target_id, note_id) = path.into_inner();
+
+    match data.auth_service.strike_account_note(admin_id, target_id, note_id).await {
+        Ok((note, struck)) => {
+            if struck {
+                data.auth_service.audit_service().log_security_event(
+                    &ctx,
+                    Some(admin_id),
+                    AuditEventType::AccountNoteStruck,
+                    &format!("Note on user {} struck through by {}", target_id, admin.username),
+                    true,
+                    Severity::Info,
+                    Some(json!({
+                        "target_user_id": target_id.to_string(),
+                        "note_id": note_id.to_string(),
+                    })),
+                ).await.unwrap_or_else(|e| log::error!("Failed to log struck account note: {}", e));
+            }
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": if struck { "Note struck through" } else { "Note was already struck through" },
+                "data": note
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to strike note {} on user {}: {}", note_id, target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Replace the tags on an account endpoint
+pub async fn set_user_tags(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    tags_request: web::Json<SetUserTagsRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+    let target_id = path.into_inner();
+
+    let Some(tags) = tags_request.tags.iter().map(|tag| normalize_tag(tag)).collect::<Option<BTreeSet<_>>>() else {
+        return Ok(invalid_tag_response());
+    };
+    if tags.len() > MAX_TAGS_PER_USER {
+        return Ok(invalid_tag_response());
+    }
+    let tags: Vec<String> = tags.into_iter().collect();
+
+    match data.auth_service.set_user_tags(target_id, &tags).await {
+        Ok(previous) => {
+            data.auth_service.audit_service().log_security_event(
+                &ctx,
+                Some(admin_id),
+                AuditEventType::UserTagsChanged,
+                &format!("Tags of user {} changed by {}", target_id, admin.username),
+                true,
+                Severity::Info,
+                Some(json!({
+                    "target_user_id": target_id.to_string(),
+                    "previous_tags": previous,
+                    "tags": tags,
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log tag change: {}", e));
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Tags updated",
+                "data": { "tags": tags }
+            })))
+        }
+        Err(AuthError::UserNotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to change tags of user {}: {}", target_id, e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Security dashboard summary endpoint
+pub async fn audit_summary(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let audit = data.auth_service.audit_service();
+    let summary = async {
+        let unacknowledged_alerts = audit.count_unacknowledged_alerts().await?;
+        let unreviewed_break_glass = audit.unreviewed_break_glass_events().await?;
+        let overdue_onboarding = data.auth_service.overdue_onboarding_count().await?;
+        let unmigrated_legacy_hashes = data.auth_service.unmigrated_legacy_hashes().await?;
+        Ok::<_, AuthError>((unacknowledged_alerts, unreviewed_break_glass, overdue_onboarding, unmigrated_legacy_hashes))
+    };
+
+    match summary.await {
+        Ok((unacknowledged_alerts, unreviewed_break_glass, overdue_onboarding, unmigrated_legacy_hashes)) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": {
+                "unacknowledged_alerts": unacknowledged_alerts,
+                "unreviewed_break_glass": unreviewed_break_glass,
+                "overdue_onboarding": overdue_onboarding,
+                "unmigrated_legacy_hashes": unmigrated_legacy_hashes,
+                "security_posture_findings": data.posture.run(&data.features).len(),
+            }
+        }))),
+        Err(e) => {
+            log::error!("Failed to summarise security events: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Security event statistics endpoint
+pub async fn event_stats(
+    req: HttpRequest,
+    query: web::Query<EventStatsQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    let window = query.window.unwrap_or_default();
+    match data.auth_service.audit_service().event_stats(window, query.group_by).await {
+        Ok(mut stats) => {
+            stats.token_validations = Some(data.auth_service.verify_counts());
+            stats.login_queue = Some(data.auth_service.login_queue_depth());
+            stats.throttled = Some(data.throttle.blocked_counts().await);
+            stats.cors_rejections = Some(data.cors_rejections.total());
+            stats.db_busy_retries = Some(data.auth_service.busy_retry_counts());
+            stats.hashing_failures = Some(data.auth_service.hashing_failures());
+            stats.clock_skew_tolerated = Some(data.auth_service.clock_skew_tolerated());
+            stats.event_queues = Some(data.auth_service.event_queue_depth());
+            stats.notification_queue = notification_queue_depth(&data).await;
+            stats.operation_timings = Some(data.auth_service.operation_timings());
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": stats
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to compute security event statistics: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+/// Outbound notification queues, left out when the pending table can't be counted
+async fn notification_queue_depth(data: &AppState) -> Option<NotificationQueueDepth> {
+    match data.webhooks.queue_depth().await {
+        Ok(depth) => Some(depth),
+        Err(e) => {
+            log::error!("Failed to count pending notifications: {}", e);
+            None
+        }
+    }
+}
+
+/// Capacity gauges for this instance: sessions, tokens, pool and memory use
+pub async fn health_details(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Err(response) = authorized(&req) {
+        return Ok(*response);
+    }
+
+    match data.auth_service.health_details().await {
+        Ok(mut details) => {
+            details.notification_queue = notification_queue_depth(&data).await;
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": details
+            })))
+        }
+        Err(e) => {
+            log::error!("Failed to compute health details: {}", e);
+            Ok(e.error_response())
+        }
+    }
+}
+
+/// Acknowledge a security event endpoint
+pub async fn acknowledge_audit_event(
+    req: HttpRequest,
+    ctx: RequestContext,
+    path: web::Path<Uuid>,
+    ack_request: Option<web::Json<AcknowledgeEventRequest>>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (admin_id, admin) = match authorized(&req) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(*response),
+    };
+
+    let ack = ack_request.map(|body| body.into_inner()).unwrap_or_default();
+    if let Err(errors) = ack.validate() {
+        return Ok(invalid_request("Invalid acknowledgement request", &errors));
+    }
+
+    let event_id = path.into_inner();
+    let outcome = data.auth_service.audit_service().acknowledge_event(
+        &ctx,
+        event_id,
+        admin_id,
+        ack.resolution_note.as_deref(),
+    ).await;
+
+    match outcome {
+        Ok(Acknowledgement::Recorded(event)) => {
+            log::info!("Security event {} acknowledged by {}", event_id, admin.username);
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Event acknowledged",
+                "data": event
+            })))
+        }
+        Ok(Acknowledgement::AlreadyAcknowledged(event)) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Event was already acknowledged",
+            "data": event
+        }))),
+        Ok(Acknowledgement::NotFound) => Ok(HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Security event not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to acknowledge security event {}: {}", event_id, e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use chrono::{TimeZone, Utc};
+    use sha2::Digest;
+    use sqlx::SqlitePool;
+    use std::sync::Arc;
+
+    use crate::models::auth::{SecurityConfig, MAX_USER_AGENT_LENGTH};
+    use crate::models::permission::PermissionSet;
+    use crate::models::user::{LoginRequest, User, UserRole};
+    use crate::utils::clock::SystemClock;
+    use crate::services::audit_bundle::{parse_public_key, verify_bundle};
+    use crate::services::config_snapshot_service::ConfigSnapshotService;
+    use crate::services::session_events::MAX_QUEUED_EVENTS;
+    use crate::services::session_service::SessionService;
+    use crate::services::client_app_service::DEFAULT_CLIENT_ID;
+    use crate::services::webhook_service::MAX_QUEUED_NOTIFICATIONS;
+    use crate::services::token_service::{TokenService, DEFAULT_TOKEN_AUDIENCE};
+    use crate::test_support::{bearer, TestApp, TEST_PASSWORD};
+
+    /// A second live session for the account, as from another device
+    async fn second_session(pool: &SqlitePool, user_id: Uuid) -> (String, String) {
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let session_id = TokenService::generate_session_id();
+        let issued = TokenService::new(SecurityConfig::default(), Arc::new(SystemClock))
+            .issue_token(&user, &session_id, PermissionSet::for_role(&user.role), Duration::hours(1), DEFAULT_TOKEN_AUDIENCE)
+            .unwrap();
+        SessionService::new(pool.clone(), Arc::new(SystemClock))
+            .create(
+                &RequestContext::new("10.0.0.9", Some("other-device")),
+                user_id,
+                &session_id,
+                &issued.jti,
+                &issued.kid,
+                Utc::now() + Duration::hours(1),
+                0,
+                DEFAULT_CLIENT_ID,
+            )
+            .await
+            .unwrap();
+        (session_id, issued.token)
+    }
+
+    #[actix_web::test]
+    async fn test_admin_terminates_one_of_two_sessions() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("incident_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let target = app.create_user("field_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let first_token = app.login_as(&target, "10.0.0.2").await;
+        let (second_id, second_token) = second_session(&app.pool, target.id).await;
+        let sessions_uri = format!("/api/admin/users/{}/sessions", target.id);
+
+        // Admin role alone isn't enough
+        let resp = app.call(bearer(test::TestRequest::get().uri(&sessions_uri), &admin_token)).await;
+        assert_eq!(resp.status(), 403);
+
+        let admin_token = app.step_up(&admin_token, &admin).await;
+        let listed = app.call_json(bearer(test::TestRequest::get().uri(&sessions_uri), &admin_token)).await;
+        assert_eq!(listed["data"].as_array().unwrap().len(), 2);
+
+        let first_id = listed["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|session| session["session_id"].as_str().unwrap().to_string())
+            .find(|id| *id != second_id)
+            .unwrap();
+        let terminate = bearer(test::TestRequest::delete().uri(&format!("/api/admin/sessions/{}", first_id)), &admin_token);
+        assert_eq!(app.call(terminate).await.status(), 200);
+
+        // Only the terminated session stops working
+        let verify = |token: &str| bearer(test::TestRequest::get().uri("/api/auth/verify"), token);
+        assert_eq!(app.call(verify(&first_token)).await.status(), 401);
+        assert_eq!(app.call(verify(&second_token)).await.status(), 200);
+        assert!(matches!(
+            app.auth_service().validate_session(&first_token).await,
+            Err(AuthError::SessionExpired)
+        ));
+
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let termination = events.iter().find(|e| e.event_type == "SESSIONS_TERMINATED").unwrap();
+        assert_eq!(termination.user_id, Some(admin.id));
+        assert_eq!(termination.severity, Severity::Critical);
+        let details = termination.details.as_ref().unwrap();
+        assert_eq!(details["target_user_id"], target.id.to_string());
+        assert_eq!(details["admin_username"], "incident_admin");
+        assert_eq!(details["session_ids"], json!([first_id]));
+    }
+
+    #[actix_web::test]
+    async fn test_admin_revokes_one_token_by_its_id() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("leak_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let target = app.create_user("leaky_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let leaked = app.login_as(&target, "10.0.0.2").await;
+        let tokens = TokenService::new(SecurityConfig::default(), Arc::new(SystemClock));
+        let leaked_claims = tokens.validate_token(&leaked).unwrap();
+        let revoke_with = |token: &str, jti: &str| {
+            bearer(test::TestRequest::delete().uri(&format!("/api/admin/tokens/{}", jti)), token)
+        };
+        let verify = |token: &str| bearer(test::TestRequest::get().uri("/api/auth/verify"), token);
+
+        assert_eq!(app.call(revoke_with(&admin_token, &leaked_claims.jti)).await.status(), 403);
+        let admin_token = app.step_up(&admin_token, &admin).await;
+        let revoke = |jti: &str| revoke_with(&admin_token, jti);
+        assert_eq!(app.call(revoke("not-a-token-id")).await.status(), 404);
+
+        let body = app.call_json(revoke(&leaked_claims.jti)).await;
+        assert_eq!(body["data"]["newly_revoked"], true);
+        assert_eq!(body["data"]["session_id"], leaked_claims.session_id);
+        assert_eq!(body["data"]["user_id"], target.id.to_string());
+        assert_eq!(app.call(verify(&leaked)).await.status(), 401);
+
+        // The session itself is still live, though only a fresh sign-in can use it now
+        let live: bool = sqlx::query_scalar("SELECT revoked_at IS NULL FROM sessions WHERE id = ?")
+            .bind(&leaked_claims.session_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert!(live);
+        let body = app.call_json(revoke(&leaked_claims.jti)).await;
+        assert_eq!(body["data"]["newly_revoked"], false);
+
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let revocations: Vec<_> = events.iter().filter(|e| e.event_type == "TOKEN_REVOKED").collect();
+        assert_eq!(revocations.len(), 1);
+        assert_eq!(revocations[0].user_id, Some(admin.id));
+        assert_eq!(revocations[0].severity, Severity::Critical);
+        assert_eq!(revocations[0].details.as_ref().unwrap()["jti"], leaked_claims.jti);
+    }
+
+    #[actix_web::test]
+    async fn test_jwt_migration_reports_sessions_still_on_the_previous_key() {
+        // Sessions from `second_session` are signed with the default secret, here the previous one
+        let previous_secret = SecurityConfig::default().jwt_secret;
+        let deadline = Utc::now() + Duration::minutes(10);
+        let app = TestApp::spawn_with(SecurityConfig {
+            jwt_secret: "freshly-rotated-jwt-secret".to_string(),
+            jwt_previous_secret: Some(previous_secret.clone()),
+            jwt_migration_deadline: Some(deadline),
+            ..SecurityConfig::default()
+        })
+        .await;
+        let admin = app.create_user("migrating_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("migrating_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let (_, old_token) = second_session(&app.pool, officer.id).await;
+        let status = || async {
+            let body = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/jwt-migration"), &admin_token)).await;
+            body["data"].clone()
+        };
+        let verify = || bearer(test::TestRequest::get().uri("/api/auth/verify"), &old_token);
+
+        let report = status().await;
+        assert_eq!(report["current_kid"], crate::services::token_service::key_id("freshly-rotated-jwt-secret"));
+        assert_eq!(report["previous_kid"], crate::services::token_service::key_id(&previous_secret));
+        assert_eq!(report["deadline"], json!(deadline));
+        assert_eq!((report["current_key_sessions"].as_i64(), report["previous_key_sessions"].as_i64()), (Some(1), Some(1)));
+        assert_eq!(report["previous_key_verifications"], 0);
+
+        // Sessions from before key IDs get theirs on next use
+        sqlx::query("UPDATE sessions SET token_kid = NULL WHERE user_id = ?").bind(officer.id).execute(&app.pool).await.unwrap();
+        assert_eq!(status().await["unrecorded_sessions"], 1);
+        assert_eq!(app.call(verify()).await.status(), 200);
+        let report = status().await;
+        assert_eq!((report["previous_key_sessions"].as_i64(), report["unrecorded_sessions"].as_i64()), (Some(1), Some(0)));
+        assert_eq!(report["previous_key_verifications"], 1);
+
+        // Past the deadline the old key is refused although still configured
+        app.clock.advance(Duration::minutes(10));
+        assert_eq!(app.call(verify()).await.status(), 401);
+        assert_eq!(status().await["previous_key_verifications"], 1);
+    }
+
+    #[actix_web::test]
+    async fn test_backup_requires_step_up_and_reports_checksum() {
+        // VACUUM INTO from an in-memory database stays in memory, so this needs a real file
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SqlitePool::connect(&format!("sqlite:{}?mode=rwc", dir.path().join("live.db").display()))
+            .await
+            .unwrap();
+        crate::utils::database::run_migrations(&pool).await.unwrap();
+        let app = TestApp::spawn_on(pool, SecurityConfig::default()).await;
+        let admin = app.create_user("backup_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("backup_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let backup = |token: &str| bearer(test::TestRequest::post().uri("/api/admin/backup"), token);
+
+        assert_eq!(app.call(backup(&officer_token)).await.status(), 403);
+        assert_eq!(app.call(backup(&admin_token)).await.status(), 403);
+
+        let admin_token = app.step_up(&admin_token, &admin).await;
+        let body = app.call_json(backup(&admin_token)).await;
+        assert_eq!(body["success"], true);
+        let path = std::path::PathBuf::from(body["data"]["path"].as_str().unwrap());
+        let contents = std::fs::read(&path).unwrap();
+        assert_eq!(body["data"]["size_bytes"], contents.len());
+        let sha256: String = sha2::Sha256::digest(&contents).iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(body["data"]["sha256"], sha256);
+
+        // The snapshot is a working copy of the live database
+        let copy = SqlitePool::connect(&format!("sqlite:{}", path.display())).await.unwrap();
+        let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(&copy).await.unwrap();
+        assert_eq!(users, 2);
+
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let logged = events.iter().find(|e| e.event_type == "DATABASE_BACKUP").unwrap();
+        assert_eq!(logged.user_id, Some(admin.id));
+        assert_eq!(logged.details.as_ref().unwrap()["sha256"], sha256);
+    }
+
+    #[actix_web::test]
+    async fn test_health_details_are_admin_only_and_cached() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("capacity_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("capacity_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let details = || test::TestRequest::get().uri("/api/health/details");
+
+        assert_eq!(app.call(details()).await.status(), 401);
+        assert_eq!(app.call(bearer(details(), &officer_token)).await.status(), 403);
+
+        let body = app.call_json(bearer(details(), &admin_token)).await;
+        let data = &body["data"];
+        assert_eq!(data["active_sessions"], 2);
+        assert_eq!(data["tokens_issued_last_hour"], 2);
+        assert_eq!(data["blacklist_size"], 0);
+        assert!(data["uptime_seconds"].as_i64().unwrap() >= 0);
+        let pool = &data["db_pool"];
+        assert!(pool["size"].as_u64().unwrap() >= 1);
+        assert_eq!(pool["in_use"].as_u64().unwrap() + pool["idle"].as_u64().unwrap(), pool["size"].as_u64().unwrap());
+        let keys = [
+            "throttle_counters",
+            "verify_failure_ips",
+            "event_streams",
+            "failed_login_digests",
+            "password_change_challenges",
+            "download_tokens",
+            "total",
+        ];
+        for key in keys {
+            assert!(data["ephemeral_store"][key].is_u64(), "missing ephemeral_store.{}", key);
+        }
+        assert_eq!(data["ephemeral_store"]["backend"], "in_process");
+        assert_eq!(data["event_queues"]["capacity"], MAX_QUEUED_EVENTS);
+        assert_eq!(data["event_queues"]["queued"], 0);
+        assert_eq!(data["notification_queue"]["capacity"], MAX_QUEUED_NOTIFICATIONS);
+        assert_eq!(data["notification_queue"]["pending"], 0);
+
+        // Figures are reused until they are 15 seconds old
+        let logout = bearer(test::TestRequest::post().uri("/api/auth/logout"), &officer_token);
+        assert_eq!(app.call(logout).await.status(), 200);
+        let cached = app.call_json(bearer(details(), &admin_token)).await;
+        assert_eq!(cached["data"]["computed_at"], data["computed_at"]);
+        assert_eq!(cached["data"]["active_sessions"], 2);
+
+        app.clock.advance(Duration::seconds(16));
+        let fresh = app.call_json(bearer(details(), &admin_token)).await;
+        assert_eq!(fresh["data"]["active_sessions"], 1);
+        assert_eq!(fresh["data"]["blacklist_size"], 1);
+        assert_eq!(fresh["data"]["uptime_seconds"].as_i64().unwrap(), data["uptime_seconds"].as_i64().unwrap() + 16);
+
+        // The public health check exposes none of it
+        let health = app.call_json(test::TestRequest::get().uri("/api/health")).await;
+        for key in ["active_sessions", "tokens_issued_last_hour", "blacklist_size", "uptime_seconds", "db_pool", "ephemeral_store"] {
+            assert!(health.get(key).is_none(), "/api/health exposes {}", key);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_webhook_dead_letters_are_listed_for_auditors() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("webhook_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("webhook_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        sqlx::query(
+            "INSERT INTO webhook_dead_letters (delivery_id, destination, event_type, url, payload, attempts,
+                                               last_status, last_error, first_attempt_at, failed_at)
+             VALUES ('d-1', 'siem', 'security_event', 'https://siem.example/hook', '{}', 5, 503,
+                     'Receiver answered 503 Service Unavailable', ?, ?)",
+        )
+        .bind(Utc::now())
+        .bind(Utc::now())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        let dead_letters = |token: &str| bearer(test::TestRequest::get().uri("/api/admin/webhooks/dead-letters"), token);
+
+        assert_eq!(app.call(dead_letters(&officer_token)).await.status(), 403);
+
+        let body = app.call_json(dead_letters(&admin_token)).await;
+        let listed = body["data"].as_array().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["delivery_id"], "d-1");
+        assert_eq!(listed[0]["attempts"], 5);
+        assert_eq!(listed[0]["last_status"], 503);
+    }
+
+    #[actix_web::test]
+    async fn test_users_pending_onboarding_are_listed_and_counted() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("onboarding_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("new_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET is_temporary_password = TRUE, onboarded_at = NULL, created_at = ? WHERE id = ?")
+            .bind(Utc::now() - Duration::days(8))
+            .bind(officer.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let pending = || bearer(test::TestRequest::get().uri("/api/admin/users?onboarded=false"), &admin_token);
+        let summary = || bearer(test::TestRequest::get().uri("/api/admin/audit/summary"), &admin_token);
+
+        let listed = app.call_json(pending()).await;
+        let usernames: Vec<_> = listed["data"]["items"].as_array().unwrap().iter().map(|u| u["username"].clone()).collect();
+        assert_eq!(usernames, vec![json!("new_officer")]);
+        assert!(listed["data"]["items"][0]["onboarded_at"].is_null());
+        assert_eq!(app.call_json(summary()).await["data"]["overdue_onboarding"], 1);
+
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let change = bearer(test::TestRequest::post().uri("/api/auth/change-password"), &officer_token).set_json(json!({
+            "current_password": TEST_PASSWORD,
+            "new_password": "FreshPassw0rd654!",
+            "confirm_password": "FreshPassw0rd654!",
+        }));
+        assert_eq!(app.call(change).await.status(), 200);
+
+        assert!(app.call_json(pending()).await["data"]["items"].as_array().unwrap().is_empty());
+        assert_eq!(app.call_json(summary()).await["data"]["overdue_onboarding"], 0);
+        let onboarded = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/users?onboarded=true"), &admin_token)).await;
+        let officer_entry = onboarded["data"]["items"].as_array().unwrap().iter().find(|u| u["username"] == "new_officer").unwrap();
+        assert!(officer_entry["onboarded_at"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn test_user_directory_filters_combine_and_search() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("directory_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let nakuru_locked = app.create_user("nakuru_locked", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let nakuru_2fa = app.create_user("nakuru_2fa", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        let mombasa_locked = app.create_user("mombasa_locked", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let lock_lapsed = app.create_user("lock_lapsed", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let update = |sql: &'static str, id: Uuid| {
+            let pool = app.pool.clone();
+            async move { sqlx::query(sql).bind(id).execute(&pool).await.unwrap() }
+        };
+        for user in [&nakuru_locked, &nakuru_2fa] {
+            update("UPDATE users SET organization = 'Nakuru County' WHERE id = ?", user.id).await;
+        }
+        update("UPDATE users SET organization = 'Mombasa County', email = 'Coast.Desk@example.go.ke' WHERE id = ?", mombasa_locked.id).await;
+        for user in [&nakuru_locked, &mombasa_locked] {
+            update("UPDATE users SET is_locked = TRUE, lockout_expiry = NULL WHERE id = ?", user.id).await;
+        }
+        update("UPDATE users SET is_locked = TRUE, lockout_expiry = '2020-01-01T00:00:00+00:00' WHERE id = ?", lock_lapsed.id).await;
+        update("UPDATE users SET is_temporary_password = TRUE WHERE id = ?", nakuru_2fa.id).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let list = |query: &str| bearer(test::TestRequest::get().uri(&format!("/api/admin/users?sort=username&{}", query)), &admin_token);
+        let usernames = |body: serde_json::Value| -> Vec<String> {
+            body["data"]["items"].as_array().unwrap().iter().map(|u| u["username"].as_str().unwrap().to_string()).collect()
+        };
+
+        let cases: &[(&str, &[&str])] = &[
+            ("locked=true", &["mombasa_locked", "nakuru_locked"]),
+            ("locked=true&two_fa_enabled=false&organization=Nakuru%20County", &["nakuru_locked"]),
+            ("locked=false&role=kenya_government", &["lock_lapsed", "nakuru_2fa"]),
+            ("two_fa_enabled=true&temporary_password=true", &["nakuru_2fa"]),
+            ("role=admin&active=true", &["directory_admin"]),
+            ("active=false", &[]),
+            // Substrings of the username or email, ignoring case
+            ("q=NAKURU", &["nakuru_2fa", "nakuru_locked"]),
+            ("q=coast.desk&locked=true", &["mombasa_locked"]),
+            ("q=nakuru&locked=true&organization=Mombasa%20County", &[]),
+            // LIKE wildcards are searched for literally
+            ("q=%25", &[]),
+            ("q=n_kuru", &[]),
+        ];
+        for (query, expected) in cases {
+            let body = app.call_json(list(query)).await;
+            assert_eq!(usernames(body.clone()), *expected, "{}", query);
+            assert_eq!(body["data"]["total"], expected.len(), "{}", query);
+        }
+
+        // Listed accounts carry their live session count
+        let body = app.call_json(list("role=admin")).await;
+        assert_eq!(body["data"]["items"][0]["active_sessions"], 1);
+        assert_eq!(app.call_json(list("q=lapsed")).await["data"]["items"][0]["active_sessions"], 0);
+
+        for query in ["role=superuser", "q=", "locked=maybe"] {
+            assert_eq!(app.call(list(query)).await.status(), 400, "{}", query);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_user_directory_sorts_by_last_login_across_pages() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("sorting_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let now = Utc::now();
+        for (username, last_login) in [
+            ("recent_officer", Some(now - Duration::hours(1))),
+            ("never_officer", None),
+            ("older_officer", Some(now - Duration::days(3))),
+            ("unseen_officer", None),
+        ] {
+            let user = app.create_user(username, UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+            sqlx::query("UPDATE users SET last_login = ? WHERE id = ?").bind(last_login).bind(user.id).execute(&app.pool).await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut uri = "/api/admin/users?q=officer&sort=last_login&per_page=1".to_string();
+        loop {
+            let body = app.call_json(bearer(test::TestRequest::get().uri(&uri), &admin_token)).await;
+            seen.extend(body["data"]["items"].as_array().unwrap().iter().map(|u| u["username"].as_str().unwrap().to_string()));
+            match body["data"]["next_cursor"].as_str() {
+                Some(cursor) => uri = format!("/api/admin/users?q=officer&sort=last_login&per_page=1&cursor={}", cursor),
+                None => break,
+            }
+        }
+        // Most recent first, then the accounts that never signed in, in ID order
+        assert_eq!(&seen[..2], ["recent_officer", "older_officer"]);
+        let mut never: Vec<_> = seen[2..].to_vec();
+        never.sort();
+        assert_eq!(never, ["never_officer", "unseen_officer"]);
+    }
+
+    #[actix_web::test]
+    async fn test_users_are_filtered_by_tag_and_notes_are_append_only() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("helpdesk_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let on_leave = app.create_user("on_leave_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let present = app.create_user("present_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&present, "10.0.0.2").await;
+        let tags_uri = |id: Uuid| format!("/api/admin/users/{}/tags", id);
+        let set_tags = |id: Uuid, tags: serde_json::Value| {
+            bearer(test::TestRequest::put().uri(&tags_uri(id)), &admin_token).set_json(json!({ "tags": tags }))
+        };
+        let tagged = |tag: &str| {
+            bearer(test::TestRequest::get().uri(&format!("/api/admin/users?tag={}", tag)), &admin_token)
+        };
+
+        let stored = app.call_json(set_tags(on_leave.id, json!(["On-Leave", "contractor", "on-leave"]))).await;
+        assert_eq!(stored["data"]["tags"], json!(["contractor", "on-leave"]));
+        assert_eq!(app.call(set_tags(present.id, json!(["contractor"]))).await.status(), 200);
+        assert_eq!(app.call(set_tags(present.id, json!(["not a tag"]))).await.status(), 400);
+
+        let listed = app.call_json(tagged("on-leave")).await;
+        let usernames: Vec<_> = listed["data"]["items"].as_array().unwrap().iter().map(|u| u["username"].clone()).collect();
+        assert_eq!(usernames, vec![json!("on_leave_officer")]);
+        assert_eq!(listed["data"]["items"][0]["tags"], json!(["contractor", "on-leave"]));
+        assert_eq!(app.call_json(tagged("CONTRACTOR")).await["data"]["items"].as_array().unwrap().len(), 2);
+        assert!(app.call_json(tagged("nobody")).await["data"]["items"].as_array().unwrap().is_empty());
+        assert_eq!(app.call(tagged("%3Cscript%3E")).await.status(), 400);
+
+        // Tags are for administrators; the account's own view doesn't carry them
+        let own = app.call_json(bearer(test::TestRequest::get().uri("/api/auth/verify"), &officer_token)).await;
+        assert!(own.to_string().contains("present_officer"));
+        assert!(!own.to_string().contains("contractor"));
+        let forbidden = bearer(test::TestRequest::put().uri(&tags_uri(on_leave.id)), &officer_token)
+            .set_json(json!({ "tags": [] }));
+        assert_eq!(app.call(forbidden).await.status(), 403);
+
+        let notes_uri = format!("/api/admin/users/{}/notes", on_leave.id);
+        let add = |note: &str, pinned: bool| {
+            bearer(test::TestRequest::post().uri(&notes_uri), &admin_token).set_json(json!({ "note": note, "pinned": pinned }))
+        };
+        let locked = app.call_json(add("Account locked on request", false)).await;
+        assert_eq!(app.call(add("On leave until March", true)).await.status(), 201);
+        assert_eq!(app.call(add("", false)).await.status(), 400);
+        let note_id = locked["data"]["id"].as_str().unwrap().to_string();
+
+        // There is no way to edit or delete a note, only to strike it through
+        let note_uri = format!("{}/{}", notes_uri, note_id);
+        assert_eq!(app.call(bearer(test::TestRequest::put().uri(&note_uri), &admin_token)).await.status(), 404);
+        assert_eq!(app.call(bearer(test::TestRequest::delete().uri(&note_uri), &admin_token)).await.status(), 404);
+        let strike = || bearer(test::TestRequest::post().uri(&format!("{}/strike", note_uri)), &admin_token);
+        let struck = app.call_json(strike()).await;
+        assert_eq!(struck["data"]["note"], "Account locked on request");
+        assert!(struck["data"]["struck_at"].is_string());
+        assert_eq!(app.call(strike()).await.status(), 200);
+        let unknown = format!("{}/{}/strike", notes_uri, Uuid::new_v4());
+        assert_eq!(app.call(bearer(test::TestRequest::post().uri(&unknown), &admin_token)).await.status(), 404);
+
+        let detail = app.call_json(bearer(test::TestRequest::get().uri(&format!("/api/admin/users/{}", on_leave.id)), &admin_token)).await;
+        assert_eq!(detail["data"]["user"]["tags"], json!(["contractor", "on-leave"]));
+        let notes = detail["data"]["notes"].as_array().unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0]["note"], "On leave until March");
+        assert_eq!(notes[0]["pinned"], true);
+        assert_eq!(notes[1]["author_id"], admin.id.to_string());
+
+        // Two additions, one strike (the repeat isn't logged again) and two tag changes
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let count = |event_type: &str| events.iter().filter(|e| e.event_type == event_type).count();
+        assert_eq!(count("ACCOUNT_NOTE_ADDED"), 2);
+        assert_eq!(count("ACCOUNT_NOTE_STRUCK"), 1);
+        assert_eq!(count("USER_TAGS_CHANGED"), 2);
+        let added = events.iter().find(|e| e.event_type == "ACCOUNT_NOTE_ADDED").unwrap();
+        assert_eq!(added.user_id, Some(admin.id));
+        assert_eq!(added.details.as_ref().unwrap()["target_user_id"], on_leave.id.to_string());
+    }
+
+    #[actix_web::test]
+    async fn test_config_and_its_history_are_shown_to_auditors() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("config_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("config_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+
+        // An earlier startup with a longer session timeout, then this one
+        let mut earlier = crate::config::AppConfig::test_config();
+        earlier.session_timeout_minutes = 60;
+        ConfigSnapshotService::new(app.pool.clone(), app.clock.clone(), earlier.snapshot())
+            .record_current()
+            .await
+            .unwrap();
+        app.clock.advance(Duration::minutes(5));
+        assert!(app.data.config_snapshots.record_current().await.unwrap());
+
+        let config = |token: &str| bearer(test::TestRequest::get().uri("/api/admin/config"), token);
+        assert_eq!(app.call(config(&officer_token)).await.status(), 403);
+        let body = app.call_json(config(&admin_token)).await;
+        assert_eq!(body["data"]["config"]["security"]["session_timeout_minutes"], 15);
+        assert!(!body.to_string().contains("super-secret-jwt-value"));
+
+        let body = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/config/history"), &admin_token)).await;
+        let history = body["data"].as_array().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["config_hash"], app.data.config_snapshots.current().1);
+        assert_eq!(
+            history[0]["changes"],
+            json!([{ "setting": "security.session_timeout_minutes", "from": 60, "to": 15 }])
+        );
+        assert_eq!(history[1]["changes"], json!([]));
+    }
+
+    #[actix_web::test]
+    async fn test_action_links_work_once_for_their_admin_within_fifteen_minutes() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("link_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let other_admin = app.create_user("link_other_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let locked = app.create_user("link_locked", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let enrolled = app.create_user("link_enrolled", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let other_token = app.login_as(&other_admin, "10.0.0.2").await;
+        app.data.auth_service.lock_user(&RequestContext::new("10.0.0.1", None), locked.id, None).await.unwrap();
+
+        let send_link = |action: &str, target: Uuid, token: &str| {
+            bearer(test::TestRequest::post().uri("/api/admin/actions"), token)
+                .set_json(json!({ "action": action, "target_user_id": target }))
+        };
+        let latest_link = || async {
+            let metadata: String = sqlx::query_scalar(
+                "SELECT metadata FROM notifications WHERE user_id = ? AND kind = 'ADMIN_ACTION_LINK' \
+                 ORDER BY created_at DESC LIMIT 1",
+            )
+            .bind(admin.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+            let link = serde_json::from_str::<serde_json::Value>(&metadata).unwrap()["link"].as_str().unwrap().to_string();
+            link[link.find("/api/").unwrap()..].to_string()
+        };
+        let pool = &app.pool;
+        let is_locked = |id: Uuid| async move {
+            sqlx::query_scalar::<_, bool>("SELECT is_locked FROM users WHERE id = ?")
+                .bind(id)
+                .fetch_one(pool)
+                .await
+                .unwrap()
+        };
+
+        let response = app.call(send_link("unlock", locked.id, &admin_token)).await;
+        assert_eq!(response.status(), 202);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        let unlock_link = latest_link().await;
+        let token = unlock_link.rsplit('/').next().unwrap();
+        assert!(!body.to_string().contains(token));
+
+        // No session: a sign-in page, and the link is still good afterwards
+        let response = app.call(test::TestRequest::get().uri(&unlock_link)).await;
+        assert_eq!(response.status(), 401);
+        assert!(response.headers().get("content-type").unwrap().to_str().unwrap().starts_with("text/html"));
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+
+        let response = app.call(bearer(test::TestRequest::get().uri(&unlock_link), &other_token)).await;
+        assert_eq!(response.status(), 403);
+        assert!(is_locked(locked.id).await);
+
+        let response = app.call(bearer(test::TestRequest::get().uri(&unlock_link), &admin_token)).await;
+        assert_eq!(response.status(), 200);
+        assert!(!is_locked(locked.id).await);
+
+        let response = app.call(bearer(test::TestRequest::get().uri(&unlock_link), &admin_token)).await;
+        assert_eq!(response.status(), 409);
+
+        assert_eq!(app.call(send_link("reset_two_fa", enrolled.id, &admin_token)).await.status(), 202);
+        let reset_link = latest_link().await;
+        app.clock.advance(Duration::minutes(16));
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let response = app.call(bearer(test::TestRequest::get().uri(&reset_link), &admin_token)).await;
+        assert_eq!(response.status(), 410);
+        let two_fa_enabled: bool = sqlx::query_scalar("SELECT two_fa_enabled FROM users WHERE id = ?")
+            .bind(enrolled.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert!(two_fa_enabled);
+
+        let response = app.call(bearer(test::TestRequest::get().uri("/api/admin/actions/confirm/not-a-token"), &admin_token)).await;
+        assert_eq!(response.status(), 404);
+
+        let events: Vec<String> = sqlx::query_scalar(
+            "SELECT event_type FROM security_events WHERE event_type LIKE 'ADMIN_ACTION_%' ORDER BY rowid",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            events,
+            [
+                "ADMIN_ACTION_LINK_SENT",
+                "ADMIN_ACTION_LINK_WRONG_ADMIN",
+                "ADMIN_ACTION_CONFIRMED",
+                "ADMIN_ACTION_LINK_REUSED",
+                "ADMIN_ACTION_LINK_SENT",
+                "ADMIN_ACTION_LINK_EXPIRED",
+                "ADMIN_ACTION_LINK_INVALID",
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_feature_flag_flipped_by_an_admin_changes_login_at_once() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("flags_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("flags_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let trapped_login = || {
+            test::TestRequest::post()
+                .uri("/api/auth/login")
+                .peer_addr("10.0.0.2:4000".parse().unwrap())
+                .set_json(json!({ "username": officer.username, "password": TEST_PASSWORD, "website": "autofill" }))
+        };
+        let set_flags = |flags: serde_json::Value| {
+            bearer(test::TestRequest::put().uri("/api/admin/features"), &admin_token).set_json(flags)
+        };
+
+        // A password manager filling the honeypot locks the officer out
+        assert_eq!(app.call(trapped_login()).await.status(), 401);
+
+        let officer_token = app.login_as(&officer, "10.0.0.3").await;
+        let response = app.call(bearer(test::TestRequest::put().uri("/api/admin/features"), &officer_token)
+            .set_json(json!({ "login_bot_checks": false })))
+            .await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(app.call(set_flags(json!({ "captcha": false }))).await.status(), 400);
+
+        let body = app.call_json(set_flags(json!({ "login_bot_checks": false, "impossible_travel": true }))).await;
+        assert_eq!(body["message"], "1 feature flag(s) changed");
+        let flags = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/features"), &admin_token)).await;
+        assert_eq!(
+            flags["data"].as_array().unwrap().iter().map(|f| (f["name"].clone(), f["enabled"].clone())).collect::<Vec<_>>(),
+            vec![(json!("impossible_travel"), json!(true)), (json!("login_bot_checks"), json!(false))]
+        );
+        assert_eq!(app.call(trapped_login()).await.status(), 200);
+
+        let changes: Vec<serde_json::Value> = app
+            .auth_service()
+            .audit_service()
+            .get_recent_events(50, false, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.event_type == "FEATURE_FLAGS_CHANGED")
+            .map(|e| e.details.unwrap()["changes"].clone())
+            .collect();
+        assert_eq!(changes, vec![json!([{ "flag": "login_bot_checks", "from": true, "to": false }])]);
+    }
+
+    #[actix_web::test]
+    async fn test_security_posture_follows_the_feature_flags() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("posture_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let posture = || bearer(test::TestRequest::get().uri("/api/admin/security-posture"), &admin_token);
+        let finding_ids = |body: &serde_json::Value| {
+            body["data"]["findings"].as_array().unwrap().iter().map(|f| f["id"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        // The test configuration has a short JWT secret and no GeoIP database
+        let body = app.call_json(posture()).await;
+        assert_eq!(finding_ids(&body), vec!["short_jwt_secret", "impossible_travel_without_geoip"]);
+        assert_eq!(body["data"]["warning"], 2);
+        assert_eq!(body["data"]["critical"], 0);
+        assert_eq!(body["data"]["findings"][0]["severity"], "warning");
+
+        let flip = bearer(test::TestRequest::put().uri("/api/admin/features"), &admin_token)
+            .set_json(json!({ "login_bot_checks": false, "impossible_travel": false }));
+        assert_eq!(app.call(flip).await.status(), 200);
+
+        let body = app.call_json(posture()).await;
+        assert_eq!(finding_ids(&body), vec!["short_jwt_secret", "login_bot_checks_off"]);
+        let summary = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/audit/summary"), &admin_token)).await;
+        assert_eq!(summary["data"]["security_posture_findings"], 2);
+    }
+
+    #[actix_web::test]
+    async fn test_route_inventory_lists_each_route_with_its_protection() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("inventory_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("inventory_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let inventory = |token: &str| bearer(test::TestRequest::get().uri("/api/admin/routes"), token);
+
+        let body = app.call_json(inventory(&admin_token)).await;
+        let routes = body["data"]["routes"].as_array().unwrap();
+        assert_eq!(body["data"]["total"], routes.len());
+        assert_eq!(body["data"]["findings"], json!([]));
+        let protection = |method: &str, pattern: &str| {
+            routes.iter().find(|r| r["method"] == method && r["pattern"] == pattern).map(|r| r["protection"].clone())
+        };
+        assert_eq!(protection("GET", "/api/admin/routes"), Some(json!("permission:audit_read")));
+        assert_eq!(protection("POST", "/api/auth/login"), Some(json!("public")));
+        assert_eq!(protection("POST", "/api/auth/2fa/disable"), Some(json!("session -kiosk")));
+
+        assert_eq!(app.call(inventory(&officer_token)).await.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn test_hostile_login_text_is_stored_and_exported_safely() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("export_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("export_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        let mut user_agent = br#"=HYPERLINK("http://evil.example","x")"#.to_vec();
+        user_agent.extend_from_slice(b"\t\xff");
+        user_agent.extend(std::iter::repeat_n(b'A', 1 << 20));
+        let response = app
+            .call(
+                test::TestRequest::post()
+                    .uri("/api/auth/login")
+                    .peer_addr("41.90.12.7:4000".parse().unwrap())
+                    .insert_header((
+                        actix_web::http::header::USER_AGENT,
+                        actix_web::http::header::HeaderValue::from_bytes(&user_agent).unwrap(),
+                    ))
+                    .set_json(json!({ "username": "\u{1b}[2Jghost\u{1b}]0;pwned\u{7}", "password": "WrongPassw0rd!!" })),
+            )
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let (username, stored_agent): (String, String) =
+            sqlx::query_as("SELECT username, user_agent FROM login_attempts WHERE ip_address = '41.90.12.7'")
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        assert_eq!(username, "[2Jghost]0;pwned");
+        assert!(stored_agent.starts_with("=HYPERLINK(\"http://evil.example\",\"x\")\u{FFFD}AAA"));
+        assert_eq!(stored_agent.chars().count(), MAX_USER_AGENT_LENGTH);
+        let events = app.auth_service().audit_service().get_recent_events(10, false, None).await.unwrap();
+        let attempt = events.iter().find(|e| e.event_type == "LOGIN_ATTEMPT").unwrap();
+        assert_eq!(attempt.description, "Login attempt for user: [2Jghost]0;pwned");
+        assert_eq!(attempt.details.as_ref().unwrap()["username"], "[2Jghost]0;pwned");
+
+        let export = |token: &str| bearer(test::TestRequest::get().uri("/api/admin/audit/export"), token);
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        assert_eq!(app.call(export(&officer_token)).await.status(), 403);
+
+        let response = app.call(export(&app.login_as(&admin, "10.0.0.1").await)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("content-type").unwrap(), "text/csv; charset=utf-8");
+        let csv = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        let mut lines = csv.split("\r\n");
+        assert_eq!(
+            lines.next(),
+            Some("id,timestamp,event_type,severity,success,user_id,ip_address,user_agent,description")
+        );
+        assert!(!csv.chars().any(|c| c.is_control() && c != '\r' && c != '\n'));
+        let row = lines.find(|line| line.contains("LOGIN_ATTEMPT") && line.contains("41.90.12.7")).unwrap();
+        assert!(row.contains(r#","'=HYPERLINK(""http://evil.example"",""x"")"#));
+        assert!(row.ends_with(r#","Login attempt for user: [2Jghost]0;pwned""#));
+
+        // Exporting is itself audited
+        let events = app.auth_service().audit_service().get_recent_events(10, false, None).await.unwrap();
+        assert!(events.iter().any(|e| e.event_type == "AUDIT_EXPORTED" && e.user_id == Some(admin.id)));
+    }
+
+    #[actix_web::test]
+    async fn test_signed_audit_bundle_verifies_with_the_public_key() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("evidence_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("evidence_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let export = |uri: &str, token: &str| bearer(test::TestRequest::get().uri(uri), token);
+        let bundle_uri = "/api/admin/audit/export?format=json&bundle=true";
+
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        assert_eq!(app.call(export(bundle_uri, &officer_token)).await.status(), 403);
+
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let response = app.call(export("/api/admin/audit/export?format=json", &admin_token)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("content-type").unwrap(), "application/json");
+        let events: serde_json::Value = test::read_body_json(response).await;
+        assert!(events.as_array().unwrap().iter().any(|e| e["event_type"] == "LOGIN_ATTEMPT"));
+
+        let response = app.call(export(bundle_uri, &admin_token)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("content-type").unwrap(), "application/zip");
+        let bundle = test::read_body(response).await;
+        let public_key = parse_public_key(&app.data.audit_signing.signer().unwrap().public_key()).unwrap();
+        let manifest = verify_bundle(&bundle, &public_key).unwrap();
+        assert_eq!(manifest.data_file, "security-events.json");
+        assert_eq!(manifest.generated_by, "evidence_admin");
+        assert!(manifest.rows > 0);
+        assert!(manifest.first_event_at <= manifest.last_event_at);
+
+        // Another key does not vouch for it
+        let other_key = parse_public_key(&crate::services::audit_bundle::test_signer().public_key()).unwrap();
+        assert!(verify_bundle(&bundle, &other_key).is_err());
+
+        let events = app.auth_service().audit_service().get_recent_events(10, false, None).await.unwrap();
+        let exported = events.iter().find(|e| e.event_type == "AUDIT_EXPORTED" && e.user_id == Some(admin.id)).unwrap();
+        let details = exported.details.as_ref().unwrap();
+        assert_eq!(details["signed_bundle"], true);
+        assert_eq!(details["data_sha256"], manifest.sha256);
+    }
+
+    #[actix_web::test]
+    async fn test_audit_by_ip_isolates_addresses_and_correlates() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("ip_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let victim = app.create_user("ip_victim", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let other = app.create_user("ip_other", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let auth_service = app.auth_service();
+        let attempt = |ip: &'static str, username: &str, password: &str| {
+            let request = LoginRequest {
+                username: username.to_string(),
+                password: password.to_string(),
+                two_fa_code: None,
+                website: None,
+                form_issued_at: None,
+                sign_out_other_sessions: false,
+                client_id: None,
+            };
+            async move { auth_service.authenticate(&RequestContext::new(ip, None), request).await }
+        };
+
+        // The attacker's address, sometimes recorded with its source port
+        for _ in 0..5 {
+            let _ = attempt("41.90.12.7:52311", &victim.username, "WrongPassw0rd!!").await;
+        }
+        let _ = attempt("41.90.12.7", "ghost", "WrongPassw0rd!!").await;
+        attempt("41.90.12.7", &other.username, TEST_PASSWORD).await.unwrap();
+        // An unrelated office address
+        let _ = attempt("2001:db8::5", &other.username, "WrongPassw0rd!!").await;
+        let admin_token = app.login_as(&admin, "2001:db8::5").await;
+
+        let by_ip = |path: &str| bearer(test::TestRequest::get().uri(&format!("/api/admin/audit/by-ip/{}", path)), &admin_token);
+
+        let attacker = app.call_json(by_ip("41.90.12.7")).await;
+        let summary = &attacker["data"]["summary"];
+        assert_eq!(summary["login_failures"], 6);
+        assert_eq!(summary["login_successes"], 1);
+        assert_eq!(summary["usernames_targeted"], json!(["ghost", "ip_other", "ip_victim"]));
+        assert_eq!(summary["locked_accounts"].as_array().unwrap().len(), 1);
+        assert_eq!(summary["locked_accounts"][0]["user_id"], victim.id.to_string());
+        let entries = attacker["data"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len() as i64, attacker["data"]["total"].as_i64().unwrap());
+        assert_eq!(entries.iter().filter(|e| e["source"] == "login_attempt").count(), 7);
+        assert!(entries.iter().any(|e| e["event_type"] == "ACCOUNT_LOCKOUT"));
+        assert!(entries.iter().all(|e| e["username"] != "ip_admin"));
+
+        // Written differently, the office address still resolves to its own rows only
+        let office = app.call_json(by_ip("2001:DB8:0::5")).await;
+        let summary = &office["data"]["summary"];
+        assert_eq!(summary["login_failures"], 1);
+        assert_eq!(summary["login_successes"], 1);
+        assert_eq!(summary["usernames_targeted"], json!(["ip_admin", "ip_other"]));
+        assert!(summary["locked_accounts"].as_array().unwrap().is_empty());
+
+        let page = app.call_json(by_ip("41.90.12.7?limit=3&offset=2")).await;
+        assert_eq!(page["data"]["entries"].as_array().unwrap().len(), 3);
+        assert_eq!(page["data"]["entries"][0]["id"], entries[2]["id"]);
+        assert_eq!(app.call(by_ip("not-an-ip")).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_csp_reports_are_collected_deduplicated_and_listed() {
+        let app = TestApp::spawn().await;
+
+        let legacy = json!({
+            "csp-report": {
+                "document-uri": "https://fsfvi.go.ke/dashboard",
+                "violated-directive": "script-src-elem",
+                "effective-directive": "script-src-elem",
+                "blocked-uri": "https://cdn.example.com/widget.js",
+                "disposition": "enforce"
+            }
+        });
+        let reporting_api = json!([{
+            "type": "csp-violation",
+            "url": "https://fsfvi.go.ke/reports",
+            "body": {
+                "documentURL": "https://fsfvi.go.ke/reports",
+                "effectiveDirective": "img-src",
+                "blockedURL": "https://tracker.example.com/pixel.gif",
+                "disposition": "enforce"
+            }
+        }]);
+        let post = |content_type: &str, body: String| {
+            test::TestRequest::post()
+                .uri("/api/csp-report")
+                .insert_header(("Content-Type", content_type.to_string()))
+                .set_payload(body)
+        };
+
+        // The same legacy report twice, then one in the Reporting API shape
+        for _ in 0..2 {
+            let resp = app.call(post("application/csp-report", legacy.to_string())).await;
+            assert_eq!(resp.status(), 204);
+        }
+        let resp = app.call(post("application/reports+json", reporting_api.to_string())).await;
+        assert_eq!(resp.status(), 204);
+
+        // Garbage and oversized bodies are dropped quietly
+        let resp = app.call(post("application/csp-report", "{not json".to_string())).await;
+        assert_eq!(resp.status(), 204);
+        let oversized = "x".repeat(64 * 1024);
+        let resp = app.call(post("application/csp-report", oversized)).await;
+        assert_eq!(resp.status(), 204);
+
+        // Listing is for administrators only
+        let resp = app.call(test::TestRequest::get().uri("/api/admin/csp-reports")).await;
+        assert_eq!(resp.status(), 401);
+
+        let admin = app.create_user("csp_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let listed = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/csp-reports"), &admin_token)).await;
+        let reports = listed["data"].as_array().unwrap();
+        assert_eq!(reports.len(), 2);
+
+        let legacy_row = reports.iter().find(|r| r["violated_directive"] == "script-src-elem").unwrap();
+        assert_eq!(legacy_row["count"], 2);
+        assert_eq!(legacy_row["blocked_uri"], "https://cdn.example.com/widget.js");
+        let reporting_row = reports.iter().find(|r| r["violated_directive"] == "img-src").unwrap();
+        assert_eq!(reporting_row["count"], 1);
+        assert_eq!(reporting_row["document_uri"], "https://fsfvi.go.ke/reports");
+    }
+
+    #[actix_web::test]
+    async fn test_permission_overrides_take_precedence_and_revoke_immediately() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("permissions_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("audit_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let auditor = app.create_user("read_only_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let admin_token = app.step_up(&admin_token, &admin).await;
+
+        let set_overrides = |user_id: Uuid, overrides: serde_json::Value| {
+            bearer(test::TestRequest::put().uri(&format!("/api/admin/users/{}/permissions", user_id)), &admin_token)
+                .set_json(json!({ "overrides": overrides }))
+        };
+        let audit = |token: &str| bearer(test::TestRequest::get().uri("/api/admin/audit"), token);
+
+        // The government role alone can't read the audit feed
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let resp = app.call(audit(&officer_token)).await;
+        assert_eq!(resp.status(), 403);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["required_permission"], "audit_read");
+
+        // A grant adds to the role defaults, and the old token stops working at once
+        let granted = app
+            .call_json(set_overrides(officer.id, json!([{ "permission": "audit_read", "granted": true }])))
+            .await;
+        assert_eq!(granted["data"]["effective"], json!(["audit_read", "data_upload"]));
+        let verify = bearer(test::TestRequest::get().uri("/api/auth/verify"), &officer_token);
+        assert_eq!(app.call(verify).await.status(), 401);
+
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        assert_eq!(app.call(audit(&officer_token)).await.status(), 200);
+
+        // Withdrawing it revokes access on the very next request
+        assert_eq!(app.call(set_overrides(officer.id, json!([]))).await.status(), 200);
+        assert_eq!(app.call(audit(&officer_token)).await.status(), 401);
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        assert_eq!(app.call(audit(&officer_token)).await.status(), 403);
+
+        // A withdrawal beats the admin role's defaults
+        let withdrawn = set_overrides(auditor.id, json!([{ "permission": "user_manage", "granted": false }]));
+        assert_eq!(app.call(withdrawn).await.status(), 200);
+        let auditor_token = app.login_as(&auditor, "10.0.0.3").await;
+        assert_eq!(app.call(audit(&auditor_token)).await.status(), 200);
+        let unlock_uri = format!("/api/admin/users/{}/unlock", officer.id);
+        let unlock = bearer(test::TestRequest::post().uri(&unlock_uri), &auditor_token);
+        assert_eq!(app.call(unlock).await.status(), 403);
+
+        // Naming a permission twice is refused
+        let duplicated = set_overrides(
+            officer.id,
+            json!([
+                { "permission": "audit_read", "granted": true },
+                { "permission": "audit_read", "granted": false }
+            ]),
+        );
+        assert_eq!(app.call(duplicated).await.status(), 400);
+
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let changes: Vec<_> = events.iter().filter(|e| e.event_type == "PERMISSIONS_CHANGED").collect();
+        assert_eq!(changes.len(), 3);
+        assert!(changes.iter().all(|e| e.user_id == Some(admin.id) && e.severity == Severity::Critical));
+    }
+
+    #[actix_web::test]
+    async fn test_sessions_expire_under_their_organization_policy() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("policy_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let county = app.create_user("county_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let hq = app.create_user("hq_service", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+
+        let set_policy = |token: &str, organization: &str, policy: serde_json::Value| {
+            bearer(test::TestRequest::put().uri(&format!("/api/admin/org-policies/{}", organization)), token)
+                .set_json(policy)
+        };
+        let set_organization = |token: &str, user_id: Uuid, organization: &str| {
+            bearer(test::TestRequest::put().uri(&format!("/api/admin/users/{}/organization", user_id)), token)
+                .set_json(json!({ "organization": organization }))
+        };
+
+        // Policy changes need a recent step-up
+        let policy = json!({ "session_timeout_minutes": 10 });
+        assert_eq!(app.call(set_policy(&admin_token, "kisumu-county", policy.clone())).await.status(), 403);
+        let admin_token = app.step_up(&admin_token, &admin).await;
+
+        let updated = app.call_json(set_policy(&admin_token, "kisumu-county", policy)).await;
+        assert_eq!(updated["data"]["effective"]["session_timeout_minutes"], 10);
+        let policy = json!({ "session_timeout_minutes": 120 });
+        assert_eq!(app.call(set_policy(&admin_token, "nairobi-hq", policy)).await.status(), 200);
+        let out_of_range = json!({ "session_timeout_minutes": 0 });
+        assert_eq!(app.call(set_policy(&admin_token, "nairobi-hq", out_of_range)).await.status(), 400);
+        assert_eq!(app.call(set_organization(&admin_token, county.id, "kisumu-county")).await.status(), 200);
+        assert_eq!(app.call(set_organization(&admin_token, hq.id, "nairobi-hq")).await.status(), 200);
+
+        let shown = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/org-policies/nairobi-hq"), &admin_token)).await;
+        assert_eq!(shown["data"]["overrides"]["session_timeout_minutes"], 120);
+        assert_eq!(shown["data"]["effective"]["max_failed_attempts"], SecurityConfig::default().max_failed_attempts);
+
+        let county_token = app.login_as(&county, "10.0.0.2").await;
+        let hq_token = app.login_as(&hq, "10.0.0.3").await;
+        let verify = |token: &str| bearer(test::TestRequest::get().uri("/api/auth/verify"), token);
+        assert_eq!(app.call(verify(&county_token)).await.status(), 200);
+        assert_eq!(app.call(verify(&hq_token)).await.status(), 200);
+
+        // Past the county's ten minutes, but well within headquarters' two hours
+        app.clock.advance(Duration::minutes(15));
+        assert_eq!(app.call(verify(&county_token)).await.status(), 401);
+        assert_eq!(app.call(verify(&hq_token)).await.status(), 200);
+
+        // Past the global thirty minutes too
+        app.clock.advance(Duration::minutes(30));
+        assert_eq!(app.call(verify(&hq_token)).await.status(), 200);
+
+        // Shortening the timeout ends live sessions that are already older
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let admin_token = app.step_up(&admin_token, &admin).await;
+        let policy = json!({ "session_timeout_minutes": 30 });
+        assert_eq!(app.call(set_policy(&admin_token, "nairobi-hq", policy)).await.status(), 200);
+        assert_eq!(app.call(verify(&hq_token)).await.status(), 401);
+
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let changes: Vec<_> = events.iter().filter(|e| e.event_type == "ORG_POLICY_CHANGED").collect();
+        assert_eq!(changes.len(), 3);
+        let shortened = changes
+            .iter()
+            .filter_map(|e| e.details.as_ref())
+            .find(|details| details["effective"]["session_timeout_minutes"] == 30)
+            .unwrap();
+        assert_eq!(shortened["organization"], "nairobi-hq");
+        assert_eq!(shortened["previous_effective"]["session_timeout_minutes"], 120);
+        assert_eq!(events.iter().filter(|e| e.event_type == "USER_ORGANIZATION_CHANGED").count(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_organization_requiring_two_fa_holds_back_permissions() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("policy_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let enrolled = app.create_user("enrolled_admin", UserRole::Admin, TEST_PASSWORD, true).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let admin_token = app.step_up(&admin_token, &admin).await;
+
+        let policy = bearer(test::TestRequest::put().uri("/api/admin/org-policies/Mombasa%20County"), &admin_token)
+            .set_json(json!({ "require_two_fa": true }));
+        assert_eq!(app.call(policy).await.status(), 200);
+        for user in [&admin, &enrolled] {
+            app.auth_service().set_user_organization(user.id, Some("Mombasa County")).await.unwrap();
+        }
+
+        // Without 2FA the account can still sign in and see its profile, but nothing more
+        let audit = |token: &str| bearer(test::TestRequest::get().uri("/api/admin/audit"), token);
+        let verified = app.call_json(bearer(test::TestRequest::get().uri("/api/auth/verify"), &admin_token)).await;
+        assert_eq!(verified["data"]["user"]["two_fa_required"], true);
+        let resp = app.call(audit(&admin_token)).await;
+        assert_eq!(resp.status(), 403);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_type"], "TwoFactorEnrollmentRequired");
+
+        let enrolled_token = app.login_as(&enrolled, "10.0.0.2").await;
+        assert_eq!(app.call(audit(&enrolled_token)).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_personal_data_exports_hold_only_their_subject() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("dsar_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let subject = app.create_user("dsar_subject", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let bystander = app.create_user("dsar_bystander", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let subject_token = app.login_as(&subject, "10.0.0.1").await;
+        let bystander_token = app.login_as(&bystander, "10.0.0.2").await;
+        let admin_token = app.login_as(&admin, "10.0.0.3").await;
+
+        let request_export = |uri: &str, token: &str| bearer(test::TestRequest::post().uri(uri), token);
+        let download_path = |body: &serde_json::Value| {
+            let url = body["data"]["download_url"].as_str().unwrap();
+            url[url.find("/api/").unwrap()..].to_string()
+        };
+
+        let response = app.call(request_export("/api/auth/me/data-export", &subject_token)).await;
+        assert_eq!(response.status(), 201);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        let subject_download = download_path(&body);
+
+        // Anyone holding the link can download it; no session is needed
+        let response = app.call(test::TestRequest::get().uri(&subject_download)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+        let document: serde_json::Value = test::read_body_json(response).await;
+        let keys: Vec<&str> = document.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            [
+                "export_id", "format", "generated_at", "login_attempts", "security_events", "sessions",
+                "terms_acceptances", "user",
+            ]
+        );
+        assert_eq!(document["format"], "kenya-fsfvi-personal-data/1");
+        assert_eq!(document["user"]["id"], subject.id.to_string());
+        assert_eq!(document["user"]["username"], "dsar_subject");
+        for secret in ["password_hash", "two_fa_secret", "two_fa_backup_codes", "two_fa_fingerprint", "session_token"] {
+            assert!(document["user"].get(secret).is_none(), "{}", secret);
+        }
+        assert_eq!(document["login_attempts"].as_array().unwrap().len(), 1);
+        assert_eq!(document["login_attempts"][0]["ip_address"], "10.0.0.1");
+        assert_eq!(document["sessions"].as_array().unwrap().len(), 1);
+        assert!(document["sessions"][0].get("jti").is_none());
+        let text = document.to_string();
+        for other in ["dsar_bystander", "dsar_admin", "10.0.0.2", "10.0.0.3"] {
+            assert!(!text.contains(other), "{}", other);
+        }
+        assert!(!text.contains(&bystander.id.to_string()) && !text.contains(&admin.id.to_string()));
+
+        // Exporting someone else's data takes an administrator
+        let admin_uri = format!("/api/admin/users/{}/data-export", bystander.id);
+        assert_eq!(app.call(request_export(&admin_uri, &subject_token)).await.status(), 403);
+        assert_eq!(app.call(request_export(&admin_uri, &bystander_token)).await.status(), 403);
+        let response = app.call(request_export(&admin_uri, &admin_token)).await;
+        assert_eq!(response.status(), 201);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        let document = app.call_json(test::TestRequest::get().uri(&download_path(&body))).await;
+        assert_eq!(document["user"]["username"], "dsar_bystander");
+        assert!(!document.to_string().contains("dsar_subject"));
+        let missing = format!("/api/admin/users/{}/data-export", Uuid::new_v4());
+        assert_eq!(app.call(request_export(&missing, &admin_token)).await.status(), 404);
+
+        app.clock.advance(Duration::minutes(31));
+        assert_eq!(app.call(test::TestRequest::get().uri(&subject_download)).await.status(), 410);
+        assert_eq!(app.call(test::TestRequest::get().uri("/api/auth/data-exports/not-a-token")).await.status(), 404);
+
+        let events: Vec<(String, Option<Uuid>)> = sqlx::query_as(
+            "SELECT event_type, user_id FROM security_events WHERE event_type LIKE 'DATA_EXPORT_%' ORDER BY rowid",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            events,
+            [
+                ("DATA_EXPORT_REQUESTED".to_string(), Some(subject.id)),
+                ("DATA_EXPORT_DOWNLOADED".to_string(), Some(subject.id)),
+                ("DATA_EXPORT_REQUESTED".to_string(), Some(admin.id)),
+                ("DATA_EXPORT_DOWNLOADED".to_string(), Some(bystander.id)),
+                ("DATA_EXPORT_LINK_EXPIRED".to_string(), Some(subject.id)),
+                ("DATA_EXPORT_LINK_INVALID".to_string(), None),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_admin_reset_link_needs_step_up_and_redeems_like_an_emailed_one() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("helpdesk_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("reset_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let stranded = app.create_user("stranded_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET email = 'stranded@agriculture.go.ke', email_verified_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(stranded.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let issue = |token: &str| {
+            bearer(test::TestRequest::post().uri(&format!("/api/admin/users/{}/reset-link", stranded.id)), token)
+        };
+        let confirm = |token: &str, new_password: &str| {
+            test::TestRequest::post().uri("/api/auth/password-reset/confirm").set_json(json!({
+                "token": token,
+                "new_password": new_password,
+                "confirm_password": new_password,
+            }))
+        };
+
+        assert_eq!(app.call(issue(&officer_token)).await.status(), 403);
+        let res = app.call(issue(&admin_token)).await;
+        assert_eq!(res.status(), 403);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error_type"], "StepUpRequired");
+
+        // An emailed link is superseded by the one the administrator is given
+        let request = test::TestRequest::post().uri("/api/auth/password-reset").set_json(json!({ "username": "stranded_user" }));
+        assert_eq!(app.call(request).await.status(), 202);
+        let metadata: String = sqlx::query_scalar("SELECT metadata FROM notifications WHERE user_id = ? AND kind = 'PASSWORD_RESET'")
+            .bind(stranded.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        let emailed_link = serde_json::from_str::<serde_json::Value>(&metadata).unwrap()["link"].as_str().unwrap().to_string();
+        let emailed_token = emailed_link.rsplit('=').next().unwrap().to_string();
+
+        let admin_token = app.step_up(&admin_token, &admin).await;
+        let res = app.call(issue(&admin_token)).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers().get("cache-control").unwrap(), "no-store");
+        let body: serde_json::Value = test::read_body_json(res).await;
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+        assert!(body["data"]["reset_url"].as_str().unwrap().ends_with(&format!("?token={}", token)));
+
+        assert_eq!(app.call(confirm(&emailed_token, "FreshPassw0rd654!")).await.status(), 410);
+        assert_eq!(app.call(confirm("not-a-token", "FreshPassw0rd654!")).await.status(), 404);
+        assert_eq!(app.call(confirm(&token, "FreshPassw0rd654!")).await.status(), 200);
+        assert_eq!(app.call(confirm(&token, "OtherPassw0rd321!")).await.status(), 409);
+        let login = LoginRequest { password: "FreshPassw0rd654!".to_string(), ..stranded.login_request() };
+        app.data.auth_service.authenticate(&RequestContext::new("10.0.0.3", None), login).await.unwrap();
+
+        // A link nobody used in time stops working
+        let res = app.call(issue(&admin_token)).await;
+        let body: serde_json::Value = test::read_body_json(res).await;
+        let late_token = body["data"]["token"].as_str().unwrap().to_string();
+        app.clock.advance(Duration::minutes(31));
+        assert_eq!(app.call(confirm(&late_token, "OtherPassw0rd321!")).await.status(), 410);
+
+        // Deactivated accounts get no links
+        app.data.auth_service.set_user_active(stranded.id, false).await.unwrap();
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let admin_token = app.step_up(&admin_token, &admin).await;
+        assert_eq!(app.call(issue(&admin_token)).await.status(), 403);
+
+        let (severity, metadata): (String, String) = sqlx::query_as(
+            "SELECT severity, metadata FROM security_events WHERE event_type = 'PASSWORD_RESET_LINK_ISSUED' ORDER BY rowid LIMIT 1",
+        )
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(severity, "warning");
+        let details: serde_json::Value = serde_json::from_str(&metadata).unwrap();
+        assert_eq!(details["issued_by"], json!(admin.id));
+        assert_eq!(details["channel"], "admin_assisted");
+        assert!(!details.to_string().contains(&token));
+    }
+
+    #[actix_web::test]
+    async fn test_system_messages_are_managed_by_admins_and_shown_within_their_window() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("notice_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("notice_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let now = app.auth_service().now();
+        let create = |token: &str, body: serde_json::Value| {
+            bearer(test::TestRequest::post().uri("/api/admin/system-messages"), token).set_json(body)
+        };
+        let active = || async {
+            let res = app.call(test::TestRequest::get().uri("/api/system-messages")).await;
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers().get("cache-control").unwrap(), "public, max-age=60");
+            let body: serde_json::Value = test::read_body_json(res).await;
+            body["data"].as_array().unwrap().iter().map(|message| message["body"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        let notice = json!({ "body": "Maintenance **Saturday 20:00-22:00**", "ends_at": now + Duration::minutes(30) });
+        assert_eq!(app.call(create(&officer_token, notice.clone())).await.status(), 403);
+        let body = app.call_json(create(&admin_token, notice)).await;
+        assert_eq!(body["data"]["severity"], "info");
+        let notice_id = body["data"]["id"].as_str().unwrap().to_string();
+        app.call_json(create(&admin_token, json!({
+            "body": "Degraded service, see [status](https://status.kenya.fsfvi.ai)",
+            "severity": "critical",
+            "starts_at": now + Duration::minutes(10),
+            "ends_at": now + Duration::minutes(20),
+        })))
+        .await;
+
+        // Markup that could render as anything but text is refused, as are empty windows
+        for rejected in [
+            json!({ "body": "<b>Maintenance</b>", "ends_at": now + Duration::hours(1) }),
+            json!({ "body": "![banner](https://kenya.fsfvi.ai/banner.png)", "ends_at": now + Duration::hours(1) }),
+            json!({ "body": "[status](javascript:alert(1))", "ends_at": now + Duration::hours(1) }),
+            json!({ "body": "x".repeat(501), "ends_at": now + Duration::hours(1) }),
+            json!({ "body": "", "ends_at": now + Duration::hours(1) }),
+            json!({ "body": "Backwards", "starts_at": now + Duration::hours(2), "ends_at": now + Duration::hours(1) }),
+        ] {
+            let res = app.call(create(&admin_token, rejected.clone())).await;
+            assert_eq!(res.status(), 400, "{}", rejected);
+        }
+
+        assert_eq!(active().await, vec!["Maintenance **Saturday 20:00-22:00**"]);
+        app.clock.advance(Duration::minutes(15));
+        assert_eq!(
+            active().await,
+            vec!["Degraded service, see [status](https://status.kenya.fsfvi.ai)", "Maintenance **Saturday 20:00-22:00**"]
+        );
+
+        // Edits show at once, without waiting for the cache
+        let edit = bearer(test::TestRequest::put().uri(&format!("/api/admin/system-messages/{}", notice_id)), &admin_token)
+            .set_json(json!({ "body": "Maintenance moved to Sunday", "severity": "warning", "ends_at": now + Duration::minutes(30) }));
+        assert_eq!(app.call(edit).await.status(), 200);
+        assert_eq!(active().await[1], "Maintenance moved to Sunday");
+
+        app.clock.advance(Duration::minutes(10));
+        assert_eq!(active().await, vec!["Maintenance moved to Sunday"]);
+        app.clock.advance(Duration::minutes(5));
+        assert!(active().await.is_empty());
+
+        // Expired messages stay listed for administrators until removed
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let body = app.call_json(bearer(test::TestRequest::get().uri("/api/admin/system-messages"), &admin_token)).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+        let delete = || bearer(test::TestRequest::delete().uri(&format!("/api/admin/system-messages/{}", notice_id)), &admin_token);
+        assert_eq!(app.call(delete()).await.status(), 200);
+        assert_eq!(app.call(delete()).await.status(), 404);
+
+        let events: Vec<AuditEventType> = app
+            .auth_service()
+            .audit_service()
+            .get_recent_events(50, false, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.event_type)
+            .filter(|event_type| event_type.as_str().starts_with("SYSTEM_MESSAGE_"))
+            .collect();
+        assert_eq!(events, vec!["SYSTEM_MESSAGE_DELETED", "SYSTEM_MESSAGE_UPDATED", "SYSTEM_MESSAGE_CREATED", "SYSTEM_MESSAGE_CREATED"]);
+    }
+
+    #[actix_web::test]
+    async fn test_admin_reads_are_audited_with_dashboard_reads_sampled() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("reading_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("reading_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let reads = || async {
+            app.auth_service()
+                .audit_service()
+                .get_recent_events(200, false, None)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|event| event.event_type == "ADMIN_READ")
+                .map(|event| event.details.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // One read event per export, refused attempts leaving none
+        let export = |token: &str| bearer(test::TestRequest::get().uri("/api/admin/audit/export?format=json&limit=5&token=s3cret"), token);
+        assert_eq!(app.call(export(&officer_token)).await.status(), 403);
+        assert!(reads().await.is_empty());
+        let mut exported = Vec::new();
+        for _ in 0..2 {
+            let response = app.call(export(&admin_token)).await;
+            assert_eq!(response.status(), 200);
+            exported = test::read_body_json::<Vec<serde_json::Value>, _>(response).await;
+        }
+        let events = reads().await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["endpoint"], "/api/admin/audit/export");
+        assert_eq!(events[0]["query"], json!({ "format": "json", "limit": "5", "token": "<redacted>" }));
+        assert_eq!(events[0]["rows"], exported.len());
+        assert_eq!(events[0]["sample_rate"], 1);
+
+        // Reads about one account name it
+        let notes_uri = format!("/api/admin/users/{}/notes", officer.id);
+        assert_eq!(app.call(bearer(test::TestRequest::get().uri(&notes_uri), &admin_token)).await.status(), 200);
+        let events = reads().await;
+        assert_eq!(events[0]["endpoint"], "/api/admin/users/{id}/notes");
+        assert_eq!(events[0]["target_user_id"], officer.id.to_string());
+        assert_eq!(events[0]["rows"], 0);
+
+        // The dashboard polls statistics, so only one read in twenty is kept
+        for _ in 0..21 {
+            let stats = bearer(test::TestRequest::get().uri("/api/admin/stats/events?window=1h"), &admin_token);
+            assert_eq!(app.call(stats).await.status(), 200);
+        }
+        let stats_reads = reads().await.into_iter().filter(|event| event["endpoint"] == "/api/admin/stats/events").collect::<Vec<_>>();
+        assert_eq!(stats_reads.len(), 2);
+        assert!(stats_reads.iter().all(|event| event["sample_rate"] == 20));
+
+        // The audit listing leaves reads out unless asked, its own reads included
+        for (uri, listed_reads) in [("/api/admin/audit?per_page=100", 0), ("/api/admin/audit?per_page=100&include_reads=true", 6)] {
+            let body = app.call_json(bearer(test::TestRequest::get().uri(uri), &admin_token)).await;
+            let events = body["data"]["items"].as_array().unwrap();
+            assert_eq!(events.iter().filter(|event| event["event_type"] == "ADMIN_READ").count(), listed_reads);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_audit_listing_filters_by_event_type() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("filtering_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("filtering_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&admin, "10.0.0.1").await;
+        app.login_as(&officer, "10.0.0.2").await;
+        let list = |uri: &str| bearer(test::TestRequest::get().uri(uri), &token);
+
+        // Either case names the type
+        for uri in ["/api/admin/audit?event_type=LOGIN_ATTEMPT", "/api/admin/audit?event_type=login_attempt"] {
+            let body = app.call_json(list(uri)).await;
+            let events = body["data"]["items"].as_array().unwrap();
+            assert_eq!(events.len(), 2);
+            assert!(events.iter().all(|event| event["event_type"] == "LOGIN_ATTEMPT"));
+        }
+
+        // Naming reads lists them without include_reads
+        let body = app.call_json(list("/api/admin/audit?event_type=ADMIN_READ")).await;
+        assert!(!body["data"]["items"].as_array().unwrap().is_empty());
+
+        // Unknown names are refused with the valid ones, by the export too
+        for uri in ["/api/admin/audit?event_type=LOGIN_ATEMPT", "/api/admin/audit/export?event_type=LOGIN_ATEMPT"] {
+            let response = app.call(list(uri)).await;
+            assert_eq!(response.status(), 400);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["errors"]["event_type"][0], "unknown event type: LOGIN_ATEMPT");
+            let valid = body["valid_event_types"].as_array().unwrap();
+            assert_eq!(valid.len(), AuditEventType::filterable().count());
+            assert!(valid.contains(&json!("BREAK_GLASS_USED")));
+            assert!(!valid.contains(&json!("UNRECOGNIZED")));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_audit_listing_reads_archived_months_when_asked() {
+        // ATTACH from an in-memory database stays in memory too, so this needs a real file
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SqlitePool::connect(&format!("sqlite:{}?mode=rwc", dir.path().join("live.db").display()))
+            .await
+            .unwrap();
+        crate::utils::database::run_migrations(&pool).await.unwrap();
+        let app = TestApp::spawn_on(pool, SecurityConfig::default()).await;
+        let admin = app.create_user("archive_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let token = app.login_as(&admin, "10.0.0.1").await;
+        sqlx::query(
+            "INSERT INTO security_events (id, event_type, description, success, severity, timestamp) VALUES (?, 'LOGIN_ATTEMPT', 'Attempt from last year', FALSE, 'warning', ?)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(Utc.with_ymd_and_hms(2024, 11, 5, 12, 0, 0).unwrap())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        let archived = app.data.audit_archives.archive_before(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap()).await.unwrap();
+        assert_eq!(archived.len(), 1);
+        let list = |uri: &str| bearer(test::TestRequest::get().uri(uri), &token);
+        let descriptions = |body: &serde_json::Value| {
+            body["data"]["items"].as_array().unwrap().iter().map(|e| e["description"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        // The primary no longer has it
+        let body = app.call_json(list("/api/admin/audit?to=2024-12-01T00:00:00Z")).await;
+        assert_eq!(body["data"]["total"], 0);
+        assert!(body.get("archives").is_none());
+
+        let body = app.call_json(list("/api/admin/audit?include_archives=true&from=2024-11-01T00:00:00Z")).await;
+        assert_eq!(body["archives"], json!(["2024-11"]));
+        let listed = descriptions(&body);
+        assert_eq!(listed.last().map(String::as_str), Some("Attempt from last year"));
+        assert!(listed.len() > 1, "events in the primary are listed alongside");
+
+        // A range after the archived months reads no archive
+        let body = app.call_json(list("/api/admin/audit?include_archives=true&from=2025-01-01T00:00:00Z")).await;
+        assert_eq!(body["archives"], json!([]));
+        assert!(!descriptions(&body).contains(&"Attempt from last year".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn test_listings_cap_per_page_and_refuse_unknown_sort_fields() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("paging_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let token = app.login_as(&admin, "10.0.0.1").await;
+        let ctx = RequestContext::new("10.0.0.9", Some("test-agent"));
+        for i in 0..120 {
+            app.data.auth_service.audit_service()
+                .log_security_event(&ctx, None, AuditEventType::LoginAttempt, &format!("Attempt {}", i), false, Severity::Info, None)
+                .await
+                .unwrap();
+        }
+        let list = |uri: &str| bearer(test::TestRequest::get().uri(uri), &token);
+
+        let body = app.call_json(list("/api/admin/audit?per_page=1000")).await;
+        assert_eq!(body["data"]["items"].as_array().unwrap().len(), 100);
+        assert!(body["data"]["total"].as_i64().unwrap() > 120);
+        assert!(body["data"]["next_cursor"].is_string());
+
+        let body = app.call_json(list("/api/admin/audit?per_page=1000&page=2")).await;
+        let rest = body["data"]["items"].as_array().unwrap();
+        assert!(!rest.is_empty() && rest.len() < 100);
+        assert!(body["data"]["next_cursor"].is_null());
+
+        // Sort fields outside each listing's whitelist are refused with the ones it takes
+        let refused = [
+            ("/api/admin/audit?sort=severity", json!(["timestamp", "event_type"])),
+            ("/api/admin/users?sort=username;DROP%20TABLE%20users", json!(["created_at", "username", "last_login"])),
+        ];
+        for (uri, valid) in refused {
+            let response = app.call(list(uri)).await;
+            assert_eq!(response.status(), 400);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert!(body["errors"]["sort"][0].as_str().unwrap().starts_with("Unknown sort field"));
+            assert_eq!(body["valid_sort_fields"], valid);
+        }
+
+        let response = app.call(list("/api/admin/audit?cursor=not-a-cursor")).await;
+        assert_eq!(response.status(), 400);
+        assert_eq!(app.call(list("/api/admin/audit?per_page=many")).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_user_cursor_is_stable_while_the_table_changes() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("paging_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        for name in ["page_a", "page_b", "page_c", "page_d", "page_e"] {
+            app.create_user(name, UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        }
+        let token = app.login_as(&admin, "10.0.0.1").await;
+        let list = |uri: String| bearer(test::TestRequest::get().uri(&uri), &token);
+        let usernames = |body: &serde_json::Value| {
+            body["data"]["items"].as_array().unwrap().iter().map(|u| u["username"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        let first = app.call_json(list("/api/admin/users?sort=username&per_page=2".to_string())).await;
+        let mut seen = usernames(&first);
+        assert_eq!(seen, ["page_a", "page_b"]);
+        assert_eq!(first["data"]["total"], 6);
+
+        // Rows removed behind the cursor or added before it don't shift the next page
+        sqlx::query("DELETE FROM users WHERE username = 'page_a'").execute(&app.pool).await.unwrap();
+        app.create_user("page_0", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        app.create_user("page_f", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        let mut cursor = first["data"]["next_cursor"].as_str().unwrap().to_string();
+        loop {
+            let body = app.call_json(list(format!("/api/admin/users?sort=username&per_page=2&cursor={}", cursor))).await;
+            seen.extend(usernames(&body));
+            match body["data"]["next_cursor"].as_str() {
+                Some(next) => cursor = next.to_string(),
+                None => break,
+            }
+        }
+        assert_eq!(seen, ["page_a", "page_b", "page_c", "page_d", "page_e", "page_f", "paging_admin"]);
+
+        // A cursor taken in one order is refused in another
+        let reversed = list(format!("/api/admin/users?sort=username&direction=desc&cursor={}", cursor));
+        assert_eq!(app.call(reversed).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_client_apps_get_their_own_audience_origins_and_quota() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("clients_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let field_user = app.create_user("field_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let dash_user = app.create_user("dash_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+
+        let register = |body: serde_json::Value| {
+            bearer(test::TestRequest::post().uri("/api/admin/client-apps"), &admin_token).set_json(body)
+        };
+        let field = serde_json::json!({
+            "id": "field", "name": "Field app", "allowed_origins": ["https://field.fsfvi.ai"],
+            "token_audience": "fsfvi-field", "rate_limit_tier": "restricted",
+        });
+        assert_eq!(app.call(register(field.clone())).await.status(), 201);
+        assert_eq!(app.call(register(field)).await.status(), 409);
+        let taken = serde_json::json!({ "id": "other", "name": "Other", "token_audience": "fsfvi-field" });
+        assert_eq!(app.call(register(taken)).await.status(), 409);
+        let dashboard = serde_json::json!({
+            "id": "dashboard", "name": "Dashboard", "token_audience": "fsfvi-dashboard", "rate_limit_tier": "elevated",
+        });
+        assert_eq!(app.call(register(dashboard)).await.status(), 201);
+        let builtin = bearer(test::TestRequest::delete().uri("/api/admin/client-apps/default"), &admin_token);
+        assert_eq!(app.call(builtin).await.status(), 400);
+
+        let login = |user: &str, client_id: &str| {
+            test::TestRequest::post()
+                .uri("/api/v2/auth/login")
+                .peer_addr("10.0.0.2:4000".parse().unwrap())
+                .set_json(serde_json::json!({ "username": user, "password": TEST_PASSWORD, "client_id": client_id }))
+        };
+        let history = |token: &str, client_id: Option<&str>| {
+            let request = bearer(test::TestRequest::get().uri("/api/auth/login-history"), token);
+            match client_id {
+                Some(id) => request.insert_header(("X-Client-Id", id)),
+                None => request,
+            }
+        };
+        let limit = |response: &actix_web::dev::ServiceResponse<_>| {
+            response.headers().get("x-ratelimit-limit").and_then(|v| v.to_str().ok()).map(str::to_string)
+        };
+
+        let body = app.call_json(login(&field_user.username, "field")).await;
+        let field_token = body["data"]["token"].as_str().unwrap().to_string();
+        let body = app.call_json(login(&dash_user.username, "dashboard")).await;
+        let dash_token = body["data"]["token"].as_str().unwrap().to_string();
+
+        // A token is only accepted from the app it was issued to
+        let response = app.call(history(&field_token, Some("field"))).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(limit(&response).as_deref(), Some("250"));
+        assert_eq!(app.call(history(&field_token, Some("dashboard"))).await.status(), 401);
+        assert_eq!(app.call(history(&field_token, None)).await.status(), 401);
+        let response = app.call(history(&dash_token, Some("dashboard"))).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(limit(&response).as_deref(), Some("4000"));
+        let response = app.call(history(&admin_token, None)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(limit(&response).as_deref(), Some("1000"));
+
+        // The field app's origin passes CORS; the dashboard registered none
+        let preflight = |origin: &str| {
+            test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/api/auth/login-history")
+                .insert_header(("Origin", origin))
+                .insert_header(("Access-Control-Request-Method", "GET"))
+        };
+        let response = app.call(preflight("https://field.fsfvi.ai")).await;
+        assert_eq!(
+            response.headers().get("access-control-allow-origin").and_then(|v| v.to_str().ok()),
+            Some("https://field.fsfvi.ai")
+        );
+
+        // An unknown client signs in as the default app, and the fallback is recorded
+        let body = app.call_json(login(&field_user.username, "no-such-app")).await;
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+        assert_eq!(app.call(history(&token, None)).await.status(), 200);
+        let fallbacks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE event_type = 'UNKNOWN_CLIENT_APP'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(fallbacks, 1);
+    }
+}
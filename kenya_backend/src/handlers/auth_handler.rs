@@ -1,46 +1,91 @@
-use actix_web::{web, HttpRequest, HttpResponse, Result};
+use actix_web::{http::header, web, HttpMessage, HttpRequest, HttpResponse, ResponseError, Result};
+use futures_util::Stream;
 use serde_json::json;
-use std::sync::Mutex;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
+use validator::{Validate, ValidationErrors};
 
-use crate::models::auth::AuthError;
-use crate::models::user::{ChangePasswordRequest, LoginRequest, TwoFASetupRequest, TwoFAVerifyRequest, TwoFADisableRequest};
-use crate::services::auth_service::AuthService;
+use crate::handlers::well_known_handler::WellKnown;
+use crate::middleware::admin_reads::{AdminReadSampling, PermittedUser};
+use crate::middleware::authorization::{Caller, Principal, RouteAccess};
+use crate::middleware::maintenance::MaintenanceState;
+use crate::middleware::origin_guard::CorsRejections;
+use crate::models::auth::{bearer_challenge, AuthError, AuthResult};
+use crate::models::context::RequestContext;
+use crate::models::permission::Permission;
+use crate::models::user::{
+    AcceptTermsRequest, ChangePasswordRequest, ConfirmPasswordResetRequest, CredentialsLoginRequest, DeviceApprovalRequest, DevicePollRequest,
+    DownloadTokenRequest,
+    LockoutStatusQuery, LoginHistoryQuery,
+    LoginPasswordChangeRequest, LoginRequest, LoginStep, PasswordResetRequest, StepUpRequest, TwoFAQrRequest, TwoFASetupRequest, TwoFAVerifyRequest, TwoFADisableRequest,
+    TwoFactorLoginRequest, UserResponse,
+};
+use crate::services::api_key_service::{ApiKeyRejection, ApiKeys, API_KEY_HEADER};
+use crate::services::auth_service::{AuthService, PasswordRotation};
+use crate::services::audit_archive_service::AuditArchiveService;
+use crate::services::backup_service::BackupService;
+use crate::services::client_app_service::ClientRegistry;
+use crate::services::config_snapshot_service::ConfigSnapshotService;
+use crate::services::csp_report_service::CspReportService;
+use crate::services::audit_bundle::AuditSigning;
+use crate::services::data_export_service::DataExport;
+use crate::services::download_tokens::{DownloadResource, DOWNLOAD_TOKEN_PARAM, DOWNLOAD_TOKEN_TTL_SECONDS};
+use crate::services::feature_flags::FeatureFlags;
+use crate::services::health_monitor::{HealthMonitor, HealthState};
+use crate::services::lockdown_service::LockdownService;
+use crate::services::password_reset_service::{GENERIC_RESET_CHANNELS, PASSWORD_RESET_TTL_MINUTES};
+use crate::services::security_posture::SecurityPostureCheck;
+use crate::services::session_events::{SessionEvent, Subscription, HEARTBEAT_INTERVAL};
+use crate::services::session_service::STEP_UP_VALIDITY_MINUTES;
+use crate::services::system_message_service::SystemMessageService;
+use crate::services::throttle_state::ThrottleState;
+use crate::services::tracer::Tracer;
+use crate::services::user_alerts::ALERT_WINDOW_DAYS;
+use crate::services::webhook_service::WebhookService;
 
 /// Application state containing shared services
 pub struct AppState {
-    pub auth_service: Mutex<AuthService>,
-}
-
-/// Extract IP address from request
-fn get_client_ip(req: &HttpRequest) -> String {
-    // Check X-Forwarded-For header first (for proxy/load balancer setups)
-    if let Some(forwarded_for) = req.headers().get("X-Forwarded-For") {
-        if let Ok(forwarded_str) = forwarded_for.to_str() {
-            return forwarded_str.split(',').next().unwrap_or("unknown").trim().to_string();
-        }
-    }
-
-    // Check X-Real-IP header
-    if let Some(real_ip) = req.headers().get("X-Real-IP") {
-        if let Ok(real_ip_str) = real_ip.to_str() {
-            return real_ip_str.to_string();
-        }
-    }
-
-    // Fallback to connection info
-    req.connection_info()
-        .peer_addr()
-        .unwrap_or("unknown")
-        .to_string()
-}
-
-/// Extract user agent from request
-fn get_user_agent(req: &HttpRequest) -> Option<String> {
-    req.headers()
-        .get("User-Agent")
-        .and_then(|ua| ua.to_str().ok())
-        .map(|s| s.to_string())
+    pub auth_service: AuthService,
+    pub maintenance: Arc<MaintenanceState>,
+    /// Failure counters shared by the rate limiting middleware and `auth_service`
+    pub throttle: Arc<ThrottleState>,
+    pub csp_reports: CspReportService,
+    /// Notices for the login screen
+    pub system_messages: SystemMessageService,
+    /// Requests refused for their Origin, shared with the `OriginGuard` middleware
+    pub cors_rejections: Arc<CorsRejections>,
+    /// Why the server started with `--allow-degraded`; auth, admin and integration endpoints are off while set
+    pub degraded: Option<String>,
+    pub well_known: WellKnown,
+    pub backups: BackupService,
+    /// Monthly audit archive files, and whether the audit listing may read them
+    pub audit_archives: AuditArchiveService,
+    /// Signed outbound notifications, shared with the audit service
+    pub webhooks: Arc<WebhookService>,
+    /// The running configuration and its recorded history
+    pub config_snapshots: ConfigSnapshotService,
+    /// Runtime kill switches, shared with `auth_service`
+    pub features: Arc<FeatureFlags>,
+    /// Key for signed audit export bundles, or why there is none
+    pub audit_signing: AuditSigning,
+    /// Which admin reads the `AdminReadAudit` middleware records
+    pub admin_reads: AdminReadSampling,
+    /// Database health from the background probes, shared with the `HealthGate` middleware
+    pub health: Arc<HealthMonitor>,
+    /// Settings combinations that weaken the deployment
+    pub posture: SecurityPostureCheck,
+    /// Emergency lockdown state, shared with `auth_service`
+    pub lockdown: Arc<LockdownService>,
+    /// Registered client apps, shared with `auth_service` and the CORS and rate limiting middleware
+    pub clients: Arc<ClientRegistry>,
+    /// API keys integrations call their endpoints with
+    pub api_keys: ApiKeys,
+    /// Request traces, exported while an OTLP endpoint is configured
+    pub tracer: Arc<Tracer>,
+    /// Send the phase breakdown of 2FA setup and password changes in a `Server-Timing` header
+    pub server_timing: bool,
 }
 
 /// Extract JWT token from Authorization header
@@ -58,14 +103,40 @@ fn extract_token(req: &HttpRequest) -> Result<String, AuthError> {
     Ok(auth_header.trim_start_matches("Bearer ").to_string())
 }
 
+/// `400` for a request body that breaks its field constraints. Only the
+/// messages go back; the validator's params would echo submitted passwords.
+pub(crate) fn invalid_request(message: &str, errors: &ValidationErrors) -> HttpResponse {
+    let fields: serde_json::Map<String, serde_json::Value> = errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let messages = errors
+                .iter()
+                .map(|error| error.message.as_deref().unwrap_or(&error.code).to_string())
+                .collect::<Vec<_>>();
+            (field.to_string(), json!(messages))
+        })
+        .collect();
+
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": message,
+        "errors": fields
+    }))
+}
+
 /// Login endpoint
 pub async fn login(
-    req: HttpRequest,
+    ctx: RequestContext,
     login_request: web::Json<LoginRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let ip_address = get_client_ip(&req);
-    let user_agent = get_user_agent(&req);
+    // Checked before anything reaches the service, so oversized passwords are never hashed
+    if let Err(errors) = login_request.validate() {
+        return Ok(invalid_request("Invalid login request", &errors));
+    }
+
+    let ip_address = &ctx.ip_address;
 
     log::info!(
         "Login attempt for user: {} from IP: {}",
@@ -74,622 +145,2531 @@ pub async fn login(
     );
     log::debug!("Login request - password length: {}", login_request.password.len());
 
-    // Create a modified login request with client info
-    let mut login_req = login_request.into_inner();
-    login_req.ip_address = Some(ip_address.clone());
-    login_req.user_agent = user_agent;
-
     // Authenticate user
-    match data.auth_service.lock() {
-        Ok(mut auth_service) => {
-            match auth_service.authenticate(login_req, &ip_address).await {
-                Ok(login_response) => {
-                    log::info!(
-                        "Successful login for user: {} from IP: {}",
-                        login_response.user.username,
-                        ip_address
-                    );
-
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "Login successful",
-                        "data": login_response
-                    })))
-                }
-                Err(auth_error) => {
-                    log::warn!(
-                        "Failed login attempt from IP: {} - Error: {}",
-                        ip_address,
-                        auth_error
-                    );
-
-                    let (status_code, message) = match auth_error {
-                        AuthError::InvalidCredentials => (401, "Invalid username or password"),
-                        AuthError::AccountLocked => (423, "Account is temporarily locked due to too many failed attempts"),
-                        AuthError::TooManyAttempts => (429, "Too many login attempts. Please try again later"),
-                        _ => (500, "Internal server error"),
-                    };
-
-                    Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message,
-                            "error_type": format!("{:?}", auth_error)
-                        })))
-                }
-            }
+    match data.auth_service.authenticate(&ctx, login_request.into_inner()).await {
+        Ok(login_response) => {
+            log::info!(
+                "Successful login for user: {} from IP: {}",
+                login_response.user.username,
+                ip_address
+            );
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Login successful",
+                "data": login_response
+            })))
         }
-        Err(_) => {
-            log::error!("Failed to acquire auth service lock");
-            Ok(HttpResponse::InternalServerError().json(json!({
+        Err(auth_error) => {
+            log::warn!(
+                "Failed login attempt from IP: {} - Error: {}",
+                ip_address,
+                auth_error
+            );
+            Ok(login_failure(auth_error, "Invalid username or password"))
+        }
+    }
+}
+
+/// Response to a login step the service refused. `InvalidCredentials` is
+/// answered with `invalid_credentials`.
+fn login_failure(auth_error: AuthError, invalid_credentials: &str) -> HttpResponse {
+    let (status_code, message) = match auth_error {
+        AuthError::InvalidCredentials => (401, invalid_credentials),
+        AuthError::AccountLocked => (423, "Account is temporarily locked due to too many failed attempts"),
+        AuthError::AccountDisabled => (403, "Account has been deactivated"),
+        AuthError::TooManyAttempts => (429, "Too many login attempts. Please try again later"),
+        _ => return auth_error.error_response(),
+    };
+
+    let mut response = HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap());
+    if status_code == 401 {
+        response.insert_header((header::WWW_AUTHENTICATE, bearer_challenge(None)));
+    }
+    response.json(json!({
+        "success": false,
+        "message": message,
+        "error_type": auth_error.error_type()
+    }))
+}
+
+/// `POST /api/v2/auth/login`: the password step. `data.next` is `complete`
+/// with the session token, `two_factor` with the challenge token to send
+/// the code with, or `password_change` when a temporary or expired password
+/// must be replaced first.
+pub async fn login_v2(
+    ctx: RequestContext,
+    login_request: web::Json<CredentialsLoginRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = login_request.validate() {
+        return Ok(invalid_request("Invalid login request", &errors));
+    }
+
+    log::info!("Login attempt for user: {} from IP: {}", login_request.username, ctx.ip_address);
+
+    let request = login_request.into_inner().into();
+    match data.auth_service.authenticate_with(&ctx, request, PasswordRotation::Inline).await {
+        Ok(login_response) => {
+            let step = LoginStep::from(login_response);
+            let message = match step {
+                LoginStep::Complete(_) => "Login successful",
+                LoginStep::TwoFactor { .. } => "Enter your 2FA code to finish signing in",
+                LoginStep::PasswordChange { .. } => "Choose a new password to finish signing in",
+            };
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": message,
+                "data": step
+            })))
+        }
+        Err(auth_error) => {
+            log::warn!("Failed login attempt from IP: {} - Error: {}", ctx.ip_address, auth_error);
+            Ok(login_failure(auth_error, "Invalid username or password"))
+        }
+    }
+}
+
+/// `POST /api/v2/auth/login/2fa`: answer the challenge from `login_v2` with
+/// an authenticator or backup code
+pub async fn login_two_factor(
+    ctx: RequestContext,
+    two_factor_request: web::Json<TwoFactorLoginRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = two_factor_request.validate() {
+        return Ok(invalid_request("Invalid 2FA login request", &errors));
+    }
+
+    let TwoFactorLoginRequest { challenge_token, code } = two_factor_request.into_inner();
+    match data.auth_service.complete_two_factor_login(&ctx, &challenge_token, code, PasswordRotation::Inline).await {
+        Ok(login_response) => {
+            log::info!("2FA login completed for user: {} from IP: {}", login_response.user.username, ctx.ip_address);
+            let step = LoginStep::from(login_response);
+            let message = match step {
+                LoginStep::PasswordChange { .. } => "Choose a new password to finish signing in",
+                _ => "Login successful",
+            };
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": message,
+                "data": step
+            })))
+        }
+        Err(AuthError::InvalidToken) => Ok(HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, bearer_challenge(None)))
+            .json(json!({
                 "success": false,
-                "message": "Internal server error"
+                "message": "Login challenge has expired or was already used; sign in again",
+                "error_type": AuthError::InvalidToken.error_type()
+            }))),
+        Err(auth_error) => {
+            log::warn!("Failed 2FA login from IP: {} - Error: {}", ctx.ip_address, auth_error);
+            Ok(login_failure(auth_error, "Invalid 2FA code"))
+        }
+    }
+}
+
+/// `POST /api/v2/auth/login/change-password`: answer a `password_change`
+/// step with the current password and a new one, and sign in
+pub async fn login_change_password(
+    ctx: RequestContext,
+    password_request: web::Json<LoginPasswordChangeRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = password_request.validate() {
+        return Ok(invalid_request("Invalid password change request", &errors));
+    }
+
+    match data.auth_service.complete_password_change_login(&ctx, password_request.into_inner()).await {
+        Ok(login_response) => {
+            log::info!("Password changed at login for user: {} from IP: {}", login_response.user.username, ctx.ip_address);
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Password changed and login successful",
+                "data": LoginStep::from(login_response)
             })))
         }
+        Err(AuthError::InvalidToken) => Ok(HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, bearer_challenge(None)))
+            .json(json!({
+                "success": false,
+                "message": "Password change challenge has expired or was already used; sign in again",
+                "error_type": AuthError::InvalidToken.error_type()
+            }))),
+        Err(auth_error) => {
+            log::warn!("Failed password change at login from IP: {} - Error: {}", ctx.ip_address, auth_error);
+            Ok(login_failure(auth_error, "Current password is incorrect"))
+        }
+    }
+}
+
+/// Check a request against the `RouteAccess` of its route. Returns the
+/// signed-in caller for session routes, `None` for the rest; API key routes
+/// get the integration as an `ApiKeyCaller` in the request extensions.
+///
+/// On failure the returned `HttpResponse` is ready to be sent to the client.
+pub(crate) async fn authorize(
+    req: &HttpRequest,
+    data: &web::Data<AppState>,
+    access: RouteAccess,
+) -> Result<Option<Principal>, HttpResponse> {
+    if let Caller::ApiKey(scope) = access.caller {
+        let presented = req.headers().get(API_KEY_HEADER).and_then(|value| value.to_str().ok());
+        return match data.api_keys.authenticate(presented, scope) {
+            Ok(caller) => {
+                req.extensions_mut().insert(caller);
+                Ok(None)
+            }
+            Err(ApiKeyRejection::Unknown) => Err(HttpResponse::Unauthorized().json(json!({
+                "success": false,
+                "message": "A valid API key is required",
+                "error_type": "InvalidApiKey"
+            }))),
+            Err(ApiKeyRejection::OutOfScope) => {
+                log::warn!("API key used outside its scopes for {}", req.path());
+                Err(HttpResponse::Forbidden().json(json!({
+                    "success": false,
+                    "message": "This API key is not allowed to call this endpoint",
+                    "error_type": "ApiKeyScopeDenied",
+                    "required_scope": scope
+                })))
+            }
+        };
+    }
+    if access.caller != Caller::Session {
+        return Ok(None);
+    }
+
+    // A browser download has no header to send; its download token stands
+    // in for the session token, on the one route it was issued for
+    let (user_response, token) = match download_token(req, access) {
+        Some((resource, download_token)) => {
+            let user_response = data.auth_service
+                .redeem_download_token(&download_token, resource)
+                .await
+                .map_err(|auth_error| session_error_response(&auth_error))?;
+            (user_response, None)
+        }
+        None => {
+            let token = match extract_token(req) {
+                Ok(token) => token,
+                Err(_) => {
+                    return Err(match access.sign_in_page {
+                        Some(page) if !req.headers().contains_key(header::AUTHORIZATION) => HttpResponse::Unauthorized()
+                            .content_type("text/html; charset=utf-8")
+                            .insert_header((header::CACHE_CONTROL, "no-store"))
+                            .insert_header((header::REFERRER_POLICY, "no-referrer"))
+                            .body(page),
+                        _ => missing_token_response(),
+                    });
+                }
+            };
+
+            // A token only works for requests from the client app it was issued to
+            let client = data.clients.for_request(req.headers());
+            let user_response = data.auth_service
+                .validate_session_for_client(&token, &client)
+                .await
+                .map_err(|auth_error| session_error_response(&auth_error))?;
+            (user_response, Some(token))
+        }
+    };
+
+    if !access.pending_terms && !user_response.terms_accepted {
+        return Err(AuthError::TermsAcceptanceRequired.error_response());
+    }
+    if !access.temp_password && user_response.is_temporary_password {
+        return Err(AuthError::PasswordChangeRequired.error_response());
+    }
+    if user_response.kiosk && access.refuses_kiosk() {
+        log::warn!("Kiosk session of {} refused for {}", user_response.username, req.path());
+        return Err(AuthError::KioskRestricted.error_response());
+    }
+
+    let user_id = Uuid::parse_str(&user_response.id).map_err(|_| invalid_user_id_response())?;
+
+    if let Some(permission) = access.permission {
+        // The organization's policy holds every permission back until 2FA is enabled
+        if user_response.must_enroll_two_fa() {
+            return Err(HttpResponse::Forbidden().json(json!({
+                "success": false,
+                "message": "Your organization requires two-factor authentication. Enable it to continue",
+                "error_type": "TwoFactorEnrollmentRequired"
+            })));
+        }
+
+        if !user_response.permissions.contains(permission) {
+            log::warn!(
+                "User {} lacks permission {} for {}",
+                user_response.username,
+                permission,
+                req.path()
+            );
+            return Err(HttpResponse::Forbidden().json(json!({
+                "success": false,
+                "message": "You do not have permission to perform this action",
+                "error_type": "PermissionDenied",
+                "required_permission": permission
+            })));
+        }
+
+        req.extensions_mut().insert(PermittedUser { id: user_id, username: user_response.username.clone() });
+    }
+
+    if access.step_up {
+        let token = token.ok_or_else(missing_token_response)?;
+        match data.auth_service.require_step_up(&token).await {
+            Ok(()) => {}
+            Err(AuthError::StepUpRequired) => {
+                return Err(HttpResponse::Forbidden().json(json!({
+                    "success": false,
+                    "message": "Please confirm your password before performing this action",
+                    "error_type": "StepUpRequired"
+                })))
+            }
+            Err(auth_error) => return Err(auth_error.error_response()),
+        }
+    }
+
+    Ok(Some(Principal { id: user_id, user: user_response }))
+}
+
+/// The download token of a request without an `Authorization` header, to a
+/// route that takes one, and the resource it must be for
+fn download_token(req: &HttpRequest, access: RouteAccess) -> Option<(DownloadResource, String)> {
+    let resource = access.download.filter(|_| !access.step_up)?;
+    if req.headers().contains_key(header::AUTHORIZATION) {
+        return None;
+    }
+    let query = web::Query::<Vec<(String, String)>>::from_query(req.query_string()).ok()?;
+    let (_, token) = query.into_inner().into_iter().find(|(name, _)| name == DOWNLOAD_TOKEN_PARAM)?;
+    Some((resource, token))
+}
+
+/// The caller `RouteGuard` let through to a session route, as their user ID and profile.
+/// The refusal is boxed, since it is only built when a route is missing its guard.
+pub(crate) fn authorized(req: &HttpRequest) -> Result<(Uuid, UserResponse), Box<HttpResponse>> {
+    match req.extensions().get::<Principal>() {
+        Some(principal) => Ok((principal.id, principal.user.clone())),
+        None => Err(Box::new(missing_token_response())),
+    }
+}
+
+/// The user ID of the caller `RouteGuard` let through to a session route
+fn authorized_user_id(req: &HttpRequest) -> Result<Uuid, Box<HttpResponse>> {
+    authorized(req).map(|(user_id, _)| user_id)
+}
+
+/// `401` for a request without a usable bearer token
+fn missing_token_response() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, bearer_challenge(None)))
+        .json(json!({
+            "success": false,
+            "message": "Authorization token required"
+        }))
+}
+
+fn invalid_user_id_response() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({
+        "success": false,
+        "message": "Invalid user ID format"
+    }))
+}
+
+/// Map a session validation error to its HTTP response
+fn session_error_response(auth_error: &AuthError) -> HttpResponse {
+    let (status_code, message) = match auth_error {
+        AuthError::TokenExpired => (401, "Token has expired"),
+        AuthError::SessionExpired => (401, "Session has expired"),
+        AuthError::InvalidToken => (401, "Invalid token"),
+        AuthError::AccountDisabled => (403, "Account is locked or deactivated"),
+        _ => return auth_error.error_response(),
+    };
+
+    let mut response = HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap());
+    if status_code == 401 {
+        response.insert_header((header::WWW_AUTHENTICATE, bearer_challenge(auth_error.challenge_error())));
     }
+    response.json(json!({
+        "success": false,
+        "message": message
+    }))
 }
 
 /// Change password endpoint
 pub async fn change_password(
     req: HttpRequest,
+    ctx: RequestContext,
     password_request: web::Json<ChangePasswordRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let ip_address = get_client_ip(&req);
+    if let Err(errors) = password_request.validate() {
+        return Ok(invalid_request("Invalid password change request", &errors));
+    }
+
+    let ip_address = &ctx.ip_address;
     log::debug!("Password change request received from IP: {}", ip_address);
     log::debug!("Request data - current_password length: {}", password_request.current_password.len());
     log::debug!("Request data - new_password length: {}", password_request.new_password.len());
 
-    // Extract and validate token
-    let token = match extract_token(&req) {
-        Ok(token) => token,
-        Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(json!({
-                "success": false,
-                "message": "Authorization token required"
-            })));
-        }
-    };
-
     // Validate session and get user ID
-    let user_id = match data.auth_service.lock() {
-        Ok(auth_service) => {
-            match auth_service.validate_session(&token).await {
-                Ok(user_response) => {
-                    match Uuid::parse_str(&user_response.id) {
-                        Ok(id) => id,
-                        Err(_) => {
-                            return Ok(HttpResponse::InternalServerError().json(json!({
-                                "success": false,
-                                "message": "Invalid user ID format"
-                            })));
-                        }
-                    }
-                }
-                Err(auth_error) => {
-                    let (status_code, message) = match auth_error {
-                        AuthError::TokenExpired => (401, "Token has expired"),
-                        AuthError::SessionExpired => (401, "Session has expired"),
-                        AuthError::InvalidToken => (401, "Invalid token"),
-                        _ => (500, "Internal server error"),
-                    };
-
-                    return Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })));
-                }
-            }
-        }
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
-            })));
-        }
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
     };
 
     log::info!("Password change request for user ID: {} from IP: {}", user_id, ip_address);
 
     // Change password
-    match data.auth_service.lock() {
-        Ok(mut auth_service) => {
-            match auth_service.change_password(user_id, password_request.into_inner()).await {
-                Ok(_) => {
-                    log::info!("Password changed successfully for user ID: {}", user_id);
-
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "Password changed successfully"
-                    })))
-                }
-                Err(auth_error) => {
-                    log::warn!("Failed password change for user ID: {} - Error: {}", user_id, auth_error);
-
-                    let (status_code, message) = match auth_error {
-                        AuthError::InvalidCredentials => (400, "Current password is incorrect"),
-                        AuthError::PasswordMismatch => (400, "New passwords do not match"),
-                        AuthError::PasswordTooWeak => (400, "Password does not meet security requirements"),
-                        _ => (500, "Internal server error"),
-                    };
-
-                    Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })))
-                }
-            }
-        }
-        Err(_) => {
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
+    match data.auth_service.change_password(&ctx, user_id, password_request.into_inner()).await {
+        Ok(renewal) => {
+            log::info!("Password changed successfully for user ID: {}", user_id);
+
+            // The old token no longer validates; clients swap in the one returned here
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Password changed successfully",
+                "data": renewal
             })))
         }
+        Err(auth_error) => {
+            log::warn!("Failed password change for user ID: {} - Error: {}", user_id, auth_error);
+
+            let (status_code, message) = match auth_error {
+                AuthError::InvalidCredentials => (400, "Current password is incorrect"),
+                AuthError::PasswordMismatch => (400, "New passwords do not match"),
+                AuthError::PasswordTooWeak => (400, "Password does not meet security requirements"),
+                _ => return Ok(auth_error.error_response()),
+            };
+
+            Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
+                .json(json!({
+                    "success": false,
+                    "message": message
+                })))
+        }
+    }
+}
+
+/// Swap the bearer token for a new one on the same session, e.g. when the
+/// client suspects it was exposed. The old token stops working at once.
+pub async fn reissue_token(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let token = match extract_token(&req) {
+        Ok(token) => token,
+        Err(_) => return Ok(missing_token_response()),
+    };
+
+    match data.auth_service.reissue_token(&token).await {
+        Ok(renewal) => Ok(HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "success": true,
+                "message": "Token re-issued",
+                "data": renewal
+            }))),
+        Err(auth_error) => Ok(session_error_response(&auth_error)),
     }
 }
 
 /// Verify token endpoint
 pub async fn verify_token(
     req: HttpRequest,
+    ctx: RequestContext,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
     // Extract token
     let token = match extract_token(&req) {
         Ok(token) => token,
         Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(json!({
-                "success": false,
-                "message": "Authorization token required"
-            })));
+            return Ok(missing_token_response());
         }
     };
 
     // Validate session
-    match data.auth_service.lock() {
-        Ok(auth_service) => {
-            match auth_service.validate_session(&token).await {
-                Ok(user_response) => {
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "Token is valid",
-                        "data": {
-                            "user": user_response,
-                            "expires_in": 28800  // 8 hours in seconds (same as login)
-                        }
-                    })))
-                }
-                Err(auth_error) => {
-                    let (status_code, message) = match auth_error {
-                        AuthError::TokenExpired => (401, "Token has expired"),
-                        AuthError::SessionExpired => (401, "Session has expired"),
-                        AuthError::InvalidToken => (401, "Invalid token"),
-                        _ => (500, "Internal server error"),
-                    };
-
-                    Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })))
+    match data.auth_service.verify_session(&ctx, &token).await {
+        Ok(user_response) => {
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Token is valid",
+                "data": {
+                    "user": user_response,
+                    "expires_in": data.auth_service.token_lifetime_seconds()
                 }
-            }
-        }
-        Err(_) => {
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
             })))
         }
+        Err(auth_error) => Ok(session_error_response(&auth_error)),
     }
 }
 
 /// Logout endpoint
 pub async fn logout(
     req: HttpRequest,
+    ctx: RequestContext,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let ip_address = get_client_ip(&req);
+    let ip_address = &ctx.ip_address;
 
     // Extract token
     let token = match extract_token(&req) {
         Ok(token) => token,
         Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(json!({
-                "success": false,
-                "message": "Authorization token required"
-            })));
+            return Ok(missing_token_response());
         }
     };
 
     // Get user ID from token and logout
-    match data.auth_service.lock() {
-        Ok(mut auth_service) => {
-            match auth_service.validate_session(&token).await {
-                Ok(user_response) => {
-                    if let Ok(user_id) = Uuid::parse_str(&user_response.id) {
-                        match auth_service.logout(user_id).await {
-                            Ok(_) => {
-                                log::info!("User {} logged out from IP: {}", user_response.username, ip_address);
-
-                                Ok(HttpResponse::Ok().json(json!({
-                                    "success": true,
-                                    "message": "Logged out successfully"
-                                })))
-                            }
-                            Err(_) => {
-                                Ok(HttpResponse::InternalServerError().json(json!({
-                                    "success": false,
-                                    "message": "Failed to logout"
-                                })))
-                            }
-                        }
-                    } else {
-                        Ok(HttpResponse::BadRequest().json(json!({
+    match data.auth_service.validate_session(&token).await {
+        Ok(user_response) => {
+            if let Ok(user_id) = Uuid::parse_str(&user_response.id) {
+                match data.auth_service.logout(&ctx, user_id).await {
+                    Ok(_) => {
+                        log::info!("User {} logged out from IP: {}", user_response.username, ip_address);
+
+                        Ok(HttpResponse::Ok().json(json!({
+                            "success": true,
+                            "message": "Logged out successfully"
+                        })))
+                    }
+                    Err(_) => {
+                        Ok(HttpResponse::InternalServerError().json(json!({
                             "success": false,
-                            "message": "Invalid user ID"
+                            "message": "Failed to logout"
                         })))
                     }
                 }
-                Err(_) => {
-                    // Even if token validation fails, consider logout successful
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "Logged out successfully"
-                    })))
-                }
+            } else {
+                Ok(HttpResponse::BadRequest().json(json!({
+                    "success": false,
+                    "message": "Invalid user ID"
+                })))
             }
         }
         Err(_) => {
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
+            // Even if token validation fails, consider logout successful
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Logged out successfully"
             })))
         }
     }
 }
 
-/// Prepare 2FA setup endpoint - generates QR code and secret
-pub async fn prepare_two_fa_setup(
+/// Login history endpoint - the caller's own recent login attempts
+pub async fn login_history(
     req: HttpRequest,
+    query: web::Query<LoginHistoryQuery>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let ip_address = get_client_ip(&req);
-    
-    // Extract and validate token
-    let token = match extract_token(&req) {
-        Ok(token) => token,
-        Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(json!({
-                "success": false,
-                "message": "Authorization token required"
-            })));
-        }
+    // Validate session and get user ID
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
     };
 
-    // Validate session and get user ID
-    let user_id = match data.auth_service.lock() {
-        Ok(auth_service) => {
-            match auth_service.validate_session(&token).await {
-                Ok(user_response) => {
-                    match Uuid::parse_str(&user_response.id) {
-                        Ok(id) => id,
-                        Err(_) => {
-                            return Ok(HttpResponse::InternalServerError().json(json!({
-                                "success": false,
-                                "message": "Invalid user ID format"
-                            })));
-                        }
-                    }
-                }
-                Err(auth_error) => {
-                    let (status_code, message) = match auth_error {
-                        AuthError::TokenExpired => (401, "Token has expired"),
-                        AuthError::SessionExpired => (401, "Session has expired"),
-                        AuthError::InvalidToken => (401, "Invalid token"),
-                        _ => (500, "Internal server error"),
-                    };
-
-                    return Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })));
-                }
-            }
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+
+    match data.auth_service.get_login_history(user_id, limit).await {
+        Ok(attempts) => {
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Login history retrieved",
+                "data": attempts
+            })))
         }
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
-            })));
+        Err(auth_error) => {
+            log::error!("Failed to load login history for user ID: {} - Error: {}", user_id, auth_error);
+            Ok(auth_error.error_response())
         }
-    };
+    }
+}
 
-    // Prepare 2FA setup
-    match data.auth_service.lock() {
-        Ok(mut auth_service) => {
-            match auth_service.prepare_two_fa_setup(user_id).await {
-                Ok(setup_response) => {
-                    log::info!("2FA preparation successful for user ID: {}", user_id);
-
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "2FA preparation successful",
-                        "data": setup_response
-                    })))
-                }
-                Err(auth_error) => {
-                    log::warn!("Failed 2FA preparation for user ID: {} - Error: {}", user_id, auth_error);
+/// Current terms of use endpoint: the version to accept and whether the caller has
+pub async fn terms_status(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
 
-                    Ok(HttpResponse::InternalServerError().json(json!({
-                        "success": false,
-                        "message": "Internal server error"
-                    })))
-                }
-            }
-        }
-        Err(_) => {
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
-            })))
+    match data.auth_service.terms_status(user_id).await {
+        Ok(status) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": status
+        }))),
+        Err(auth_error) => {
+            log::error!("Failed to load terms status: {}", auth_error);
+            Ok(auth_error.error_response())
         }
     }
 }
 
-/// Setup 2FA endpoint
-pub async fn setup_two_fa(
+/// Accept the current terms of use endpoint
+pub async fn accept_terms(
     req: HttpRequest,
-    setup_request: web::Json<TwoFASetupRequest>,
+    ctx: RequestContext,
+    accept_request: web::Json<AcceptTermsRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let ip_address = get_client_ip(&req);
-    
-    // Extract and validate token
-    let token = match extract_token(&req) {
-        Ok(token) => token,
-        Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(json!({
-                "success": false,
-                "message": "Authorization token required"
-            })));
+    if let Err(errors) = accept_request.validate() {
+        return Ok(invalid_request("Invalid terms acceptance", &errors));
+    }
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
+
+    match data.auth_service.accept_terms(&ctx, user_id, accept_request.into_inner()).await {
+        Ok(status) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Terms accepted",
+            "data": status
+        }))),
+        Err(auth_error) => Ok(auth_error.error_response()),
+    }
+}
+
+/// Security checkup endpoint: the flags the dashboard's banners depend on,
+/// cacheable by the client for a minute
+pub async fn security_checkup(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
+
+    match data.auth_service.security_checkup(user_id).await {
+        Ok(checkup) => Ok(HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "private, max-age=60"))
+            .insert_header((header::VARY, "Authorization"))
+            .json(json!({
+                "success": true,
+                "data": checkup
+            }))),
+        Err(auth_error) => {
+            log::error!("Failed to run security checkup: {}", auth_error);
+            Ok(auth_error.error_response())
         }
+    }
+}
+
+/// The caller's own alerts: warning and critical events about their account
+/// from the last 30 days, a banner per kind and day
+pub async fn my_alerts(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
     };
 
-    // Validate session and get user ID
-    let user_id = match data.auth_service.lock() {
-        Ok(auth_service) => {
-            match auth_service.validate_session(&token).await {
-                Ok(user_response) => {
-                    match Uuid::parse_str(&user_response.id) {
-                        Ok(id) => id,
-                        Err(_) => {
-                            return Ok(HttpResponse::InternalServerError().json(json!({
-                                "success": false,
-                                "message": "Invalid user ID format"
-                            })));
-                        }
-                    }
+    match data.auth_service.my_alerts(user_id).await {
+        Ok(alerts) => Ok(HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "success": true,
+                "data": {
+                    "undismissed": alerts.iter().filter(|alert| !alert.dismissed).count(),
+                    "window_days": ALERT_WINDOW_DAYS,
+                    "alerts": alerts,
                 }
-                Err(auth_error) => {
-                    let (status_code, message) = match auth_error {
-                        AuthError::TokenExpired => (401, "Token has expired"),
-                        AuthError::SessionExpired => (401, "Session has expired"),
-                        AuthError::InvalidToken => (401, "Invalid token"),
-                        _ => (500, "Internal server error"),
-                    };
-
-                    return Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })));
-                }
-            }
-        }
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
-            })));
+            }))),
+        Err(auth_error) => {
+            log::error!("Failed to list user alerts: {}", auth_error);
+            Ok(auth_error.error_response())
         }
-    };
+    }
+}
 
-    log::info!("2FA setup request for user ID: {} from IP: {}", user_id, ip_address);
+/// Dismiss one of the caller's alerts, by its `id` or that of any event in
+/// it. Dismissing it again changes nothing.
+pub async fn dismiss_my_alert(req: HttpRequest, path: web::Path<Uuid>, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
 
-    // Setup 2FA
-    match data.auth_service.lock() {
-        Ok(mut auth_service) => {
-            match auth_service.setup_two_fa(user_id, setup_request.into_inner()).await {
-                Ok(setup_response) => {
-                    log::info!("2FA setup successful for user ID: {}", user_id);
-
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "2FA setup successful",
-                        "data": setup_response
-                    })))
-                }
-                Err(auth_error) => {
-                    log::warn!("Failed 2FA setup for user ID: {} - Error: {}", user_id, auth_error);
+    match data.auth_service.dismiss_my_alert(user_id, path.into_inner()).await {
+        Ok(alert) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Alert dismissed",
+            "data": alert
+        }))),
+        Err(auth_error) => Ok(auth_error.error_response()),
+    }
+}
 
-                    let (status_code, message) = match auth_error {
-                        AuthError::InvalidCredentials => (400, "Invalid TOTP code"),
-                        _ => (500, "Internal server error"),
-                    };
+/// Login challenge endpoint - public. The login page fetches it when it renders
+/// the form and sends `form_issued_at` back with the credentials.
+pub async fn login_challenge(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let (form_issued_at, min_fill_ms) = data.auth_service.login_challenge();
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(json!({
+            "success": true,
+            "data": {
+                "form_issued_at": form_issued_at,
+                "min_fill_ms": min_fill_ms,
+            }
+        })))
+}
 
-                    Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })))
-                }
+/// Lockout status endpoint - public, and deliberately unable to confirm that an account exists
+pub async fn lockout_status(
+    query: web::Query<LockoutStatusQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    match data.auth_service.lockout_status(&query.username).await {
+        Ok(unlocks_at) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": format!(
+                "If this account exists and is locked, it unlocks by {} UTC. \
+                 Accounts locked by an administrator stay locked until an administrator unlocks them.",
+                unlocks_at.format("%H:%M")
+            ),
+            "data": {
+                "unlocks_by": unlocks_at.to_rfc3339(),
             }
-        }
-        Err(_) => {
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
-            })))
+        }))),
+        Err(auth_error) => {
+            log::error!("Failed to check lockout status: {}", auth_error);
+            Ok(auth_error.error_response())
         }
     }
 }
 
-/// Verify 2FA during login endpoint
-pub async fn verify_two_fa(
+/// Forgot-password endpoint - public. Emails a reset link when the account has
+/// a verified address. Anyone but an administrator with `user_manage` gets the
+/// same answer for every username: the generic channel list.
+pub async fn request_password_reset(
     req: HttpRequest,
-    verify_request: web::Json<TwoFAVerifyRequest>,
+    ctx: RequestContext,
+    reset_request: web::Json<PasswordResetRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let ip_address = get_client_ip(&req);
-    
-    log::info!("2FA verification request from IP: {}", ip_address);
+    if let Err(errors) = reset_request.validate() {
+        return Ok(invalid_request("Invalid password reset request", &errors));
+    }
 
-    // Verify 2FA
-    match data.auth_service.lock() {
-        Ok(mut auth_service) => {
-            match auth_service.verify_two_fa(verify_request.into_inner()).await {
-                Ok(login_response) => {
-                    log::info!("2FA verification successful from IP: {}", ip_address);
-
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "2FA verification successful",
-                        "data": login_response
-                    })))
-                }
-                Err(auth_error) => {
-                    log::warn!("Failed 2FA verification from IP: {} - Error: {}", ip_address, auth_error);
+    // A token that is missing, invalid or without the permission is treated as no token
+    let admin = match req.headers().contains_key(header::AUTHORIZATION) {
+        true => authorize(&req, &data, RouteAccess::permission(Permission::UserManage)).await.ok().flatten(),
+        false => None,
+    };
 
-                    let (status_code, message) = match auth_error {
-                        AuthError::InvalidCredentials => (400, "Invalid 2FA code"),
-                        AuthError::InvalidToken => (400, "Invalid temporary token"),
-                        _ => (500, "Internal server error"),
-                    };
+    let channels = match data.auth_service.request_password_reset(&ctx, &reset_request.username).await {
+        Ok(channels) => channels,
+        Err(auth_error) => {
+            log::error!("Failed to handle password reset request: {}", auth_error);
+            return Ok(auth_error.error_response());
+        }
+    };
 
-                    Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })))
-                }
-            }
+    let (channels, generic) = match (admin, channels) {
+        (Some(_), Some(channels)) => (channels, false),
+        (Some(_), None) => return Ok(AuthError::UserNotFound.error_response()),
+        (None, _) => (GENERIC_RESET_CHANNELS.to_vec(), true),
+    };
+    Ok(HttpResponse::Accepted().json(json!({
+        "success": true,
+        "message": "If this account has a verified email address, a reset link is on its way. \
+                    Otherwise ask an administrator for a reset link.",
+        "data": {
+            "channels": channels,
+            "generic": generic,
+            "expires_in_minutes": PASSWORD_RESET_TTL_MINUTES,
         }
-        Err(_) => {
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
-            })))
+    })))
+}
+
+/// Set a new password with a one-time reset token, emailed or issued to an
+/// administrator. Every session of the account ends.
+pub async fn confirm_password_reset(
+    ctx: RequestContext,
+    confirm_request: web::Json<ConfirmPasswordResetRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = confirm_request.validate() {
+        return Ok(invalid_request("Invalid password reset", &errors));
+    }
+
+    match data.auth_service.complete_password_reset(&ctx, confirm_request.into_inner()).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Password reset. Sign in with your new password"
+        }))),
+        Err(auth_error) => {
+            log::warn!("Failed password reset from IP: {} - Error: {}", ctx.ip_address, auth_error);
+            Ok(auth_error.error_response())
         }
     }
 }
 
-/// Disable 2FA endpoint
-pub async fn disable_two_fa(
+/// Start a kiosk sign-in: the kiosk shows the user code, as text and a QR
+/// code, and polls with the polling token until it is approved
+pub async fn start_device_authorization(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let client = data.clients.for_request(req.headers());
+    match data.auth_service.start_device_authorization(&client).await {
+        Ok(started) => Ok(HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "success": true,
+                "message": "Approve this code from a device you are signed in on",
+                "data": started
+            }))),
+        Err(auth_error) => Ok(auth_error.error_response()),
+    }
+}
+
+/// Approve or deny the kiosk sign-in showing a user code, from a session
+/// that stepped up
+pub async fn approve_device_authorization(
     req: HttpRequest,
-    disable_request: web::Json<TwoFADisableRequest>,
+    ctx: RequestContext,
+    approval_request: web::Json<DeviceApprovalRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = approval_request.validate() {
+        return Ok(invalid_request("Invalid kiosk sign-in approval", &errors));
+    }
+    let (user_id, user) = match authorized(&req) {
+        Ok(caller) => caller,
+        Err(response) => return Ok(*response),
+    };
+
+    let approve = approval_request.approve;
+    match data.auth_service.decide_device_authorization(&ctx, user_id, &user.username, approval_request.into_inner()).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": if approve { "Kiosk sign-in approved" } else { "Kiosk sign-in denied" }
+        }))),
+        Err(auth_error) => Ok(auth_error.error_response()),
+    }
+}
+
+/// The kiosk's poll: its session once the sign-in is approved, otherwise
+/// whether it is still pending, was denied or has expired
+pub async fn poll_device_authorization(
+    req: HttpRequest,
+    ctx: RequestContext,
+    poll_request: web::Json<DevicePollRequest>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let ip_address = get_client_ip(&req);
-    
-    // Extract and validate token
+    if let Err(errors) = poll_request.validate() {
+        return Ok(invalid_request("Invalid kiosk sign-in poll", &errors));
+    }
+
+    let client = data.clients.for_request(req.headers());
+    match data.auth_service.poll_device_authorization(&ctx, &poll_request.polling_token, &client).await {
+        Ok(login_response) => {
+            log::info!("Kiosk signed in as {} from IP: {}", login_response.user.username, ctx.ip_address);
+            Ok(HttpResponse::Ok()
+                .insert_header((header::CACHE_CONTROL, "no-store"))
+                .json(json!({
+                    "success": true,
+                    "message": "Kiosk signed in",
+                    "data": login_response
+                })))
+        }
+        Err(auth_error) => Ok(auth_error.error_response()),
+    }
+}
+
+/// How often an open event stream re-checks its session
+const SESSION_EVENTS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Session event stream (Server-Sent Events): expiry warnings and revocations
+/// pushed to the dashboard instead of it polling `/api/auth/verify`
+pub async fn session_events(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
     let token = match extract_token(&req) {
         Ok(token) => token,
         Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(json!({
+            return Ok(missing_token_response());
+        }
+    };
+
+    let subscription = match data.auth_service.subscribe_session_events(&token).await {
+        Ok(Some(subscription)) => subscription,
+        Ok(None) => {
+            return Ok(HttpResponse::TooManyRequests().json(json!({
                 "success": false,
-                "message": "Authorization token required"
+                "message": "Too many open event streams for this account",
+                "error_type": "TooManyStreams"
             })));
         }
+        Err(auth_error) => return Ok(session_error_response(&auth_error)),
     };
 
-    // Validate session and get user ID
-    let user_id = match data.auth_service.lock() {
-        Ok(auth_service) => {
-            match auth_service.validate_session(&token).await {
-                Ok(user_response) => {
-                    match Uuid::parse_str(&user_response.id) {
-                        Ok(id) => id,
-                        Err(_) => {
-                            return Ok(HttpResponse::InternalServerError().json(json!({
-                                "success": false,
-                                "message": "Invalid user ID format"
-                            })));
+    log::debug!("Session event stream opened for user ID: {}", subscription.user_id);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(session_event_stream(data, subscription)))
+}
+
+struct EventStreamState {
+    data: web::Data<AppState>,
+    subscription: Subscription,
+    ticker: tokio::time::Interval,
+    /// Keep-alives, so a client that is gone stops taking them and is reaped
+    heartbeat: tokio::time::Interval,
+    /// Warnings already sent; each goes out once per stream
+    warned: HashSet<&'static str>,
+    pending: VecDeque<String>,
+    finished: bool,
+}
+
+/// Messages for one stream. It ends after `session_revoked`, or once the
+/// session is no longer live; the subscription drops with it.
+fn session_event_stream(
+    data: web::Data<AppState>,
+    subscription: Subscription,
+) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    let state = EventStreamState {
+        data,
+        subscription,
+        ticker: tokio::time::interval(SESSION_EVENTS_CHECK_INTERVAL),
+        heartbeat: tokio::time::interval_at(tokio::time::Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL),
+        warned: HashSet::new(),
+        pending: VecDeque::new(),
+        finished: false,
+    };
+
+    futures_util::stream::unfold(state, |mut state| async move {
+        // Asked for the next message, so the client took the last one
+        state.subscription.mark_read();
+        loop {
+            if let Some(message) = state.pending.pop_front() {
+                return Some((Ok(web::Bytes::from(message)), state));
+            }
+            if state.finished {
+                return None;
+            }
+
+            // Published events first, so a revocation is reported rather than
+            // found by the liveness check
+            tokio::select! {
+                biased;
+                event = state.subscription.recv() => match event {
+                    Some(event) => {
+                        state.finished = matches!(event, SessionEvent::SessionRevoked { .. });
+                        state.pending.push_back(event.to_sse());
+                    }
+                    None => state.finished = true,
+                },
+                _ = state.ticker.tick() => {
+                    let user_id = state.subscription.user_id;
+                    match state.data.auth_service.session_warnings(user_id, &state.subscription.session_id).await {
+                        Ok(Some(warnings)) => {
+                            for warning in warnings {
+                                if state.warned.insert(warning.name()) {
+                                    state.pending.push_back(warning.to_sse());
+                                }
+                            }
                         }
+                        Ok(None) => state.finished = true,
+                        Err(e) => log::error!("Failed to check session {} for warnings: {}", state.subscription.session_id, e),
                     }
                 }
-                Err(auth_error) => {
-                    let (status_code, message) = match auth_error {
-                        AuthError::TokenExpired => (401, "Token has expired"),
-                        AuthError::SessionExpired => (401, "Session has expired"),
-                        AuthError::InvalidToken => (401, "Invalid token"),
-                        _ => (500, "Internal server error"),
-                    };
-
-                    return Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })));
-                }
+                _ = state.heartbeat.tick() => state.pending.push_back(": keep-alive\n\n".to_string()),
             }
         }
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": "Internal server error"
-            })));
+    })
+}
+
+/// Prepare 2FA setup endpoint - generates QR code and secret
+pub async fn prepare_two_fa_setup(
+    req: HttpRequest,
+    ctx: RequestContext,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let ip_address = &ctx.ip_address;
+
+    // Validate session and get user ID
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
+
+    log::info!("2FA preparation request for user ID: {} from IP: {}", user_id, ip_address);
+
+    // Prepare 2FA setup
+    match data.auth_service.prepare_two_fa_setup(user_id).await {
+        Ok(setup_response) => {
+            log::info!("2FA preparation successful for user ID: {}", user_id);
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "2FA preparation successful",
+                "data": setup_response
+            })))
+        }
+        Err(auth_error) => {
+            log::warn!("Failed 2FA preparation for user ID: {} - Error: {}", user_id, auth_error);
+            Ok(auth_error.error_response())
         }
+    }
+}
+
+/// Show the QR code of the caller's active 2FA secret again
+pub async fn redisplay_two_fa_qr(
+    req: HttpRequest,
+    ctx: RequestContext,
+    qr_request: web::Json<TwoFAQrRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = qr_request.validate() {
+        return Ok(invalid_request("Invalid 2FA QR request", &errors));
+    }
+
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
+
+    match data.auth_service.redisplay_two_fa_qr(&ctx, user_id, qr_request.into_inner()).await {
+        Ok(qr) => Ok(HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "2FA enrollment details",
+            "data": qr
+        }))),
+        // The session itself is still good, so this isn't a 401
+        Err(AuthError::InvalidCredentials) => Ok(HttpResponse::Forbidden().json(json!({
+            "success": false,
+            "message": "Invalid password or 2FA code",
+            "error_type": "InvalidCredentials"
+        }))),
+        Err(auth_error) => {
+            log::warn!("Failed 2FA QR redisplay for user ID: {} - Error: {}", user_id, auth_error);
+            Ok(auth_error.error_response())
+        }
+    }
+}
+
+/// Setup 2FA endpoint
+pub async fn setup_two_fa(
+    req: HttpRequest,
+    ctx: RequestContext,
+    setup_request: web::Json<TwoFASetupRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = setup_request.validate() {
+        return Ok(invalid_request("Invalid 2FA setup request", &errors));
+    }
+
+    let ip_address = &ctx.ip_address;
+
+    // Validate session and get user ID
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
+
+    log::info!("2FA setup request for user ID: {} from IP: {}", user_id, ip_address);
+
+    // Setup 2FA
+    match data.auth_service.setup_two_fa(user_id, setup_request.into_inner()).await {
+        Ok(setup_response) => {
+            log::info!("2FA setup successful for user ID: {}", user_id);
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "2FA setup successful",
+                "data": setup_response
+            })))
+        }
+        Err(auth_error) => {
+            log::warn!("Failed 2FA setup for user ID: {} - Error: {}", user_id, auth_error);
+
+            let (status_code, message) = match auth_error {
+                AuthError::InvalidCredentials => (400, "Invalid TOTP code"),
+                _ => return Ok(auth_error.error_response()),
+            };
+
+            Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
+                .json(json!({
+                    "success": false,
+                    "message": message
+                })))
+        }
+    }
+}
+
+/// Verify 2FA during login endpoint
+pub async fn verify_two_fa(
+    ctx: RequestContext,
+    verify_request: web::Json<TwoFAVerifyRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = verify_request.validate() {
+        return Ok(invalid_request("Invalid 2FA verification request", &errors));
+    }
+
+    let ip_address = &ctx.ip_address;
+
+    log::info!("2FA verification request from IP: {}", ip_address);
+
+    // Verify 2FA
+    match data.auth_service.verify_two_fa(&ctx, verify_request.into_inner()).await {
+        Ok(login_response) => {
+            log::info!("2FA verification successful from IP: {}", ip_address);
+
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "2FA verification successful",
+                "data": login_response
+            })))
+        }
+        Err(auth_error) => {
+            log::warn!("Failed 2FA verification from IP: {} - Error: {}", ip_address, auth_error);
+
+            let (status_code, message) = match auth_error {
+                AuthError::InvalidCredentials => (400, "Invalid 2FA code"),
+                AuthError::InvalidToken => (400, "Invalid temporary token"),
+                _ => return Ok(auth_error.error_response()),
+            };
+
+            Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
+                .json(json!({
+                    "success": false,
+                    "message": message
+                })))
+        }
+    }
+}
+
+/// Disable 2FA endpoint
+pub async fn disable_two_fa(
+    req: HttpRequest,
+    ctx: RequestContext,
+    disable_request: web::Json<TwoFADisableRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = disable_request.validate() {
+        return Ok(invalid_request("Invalid 2FA disable request", &errors));
+    }
+
+    let ip_address = &ctx.ip_address;
+
+    // Validate session and get user ID
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
     };
 
     log::info!("2FA disable request for user ID: {} from IP: {}", user_id, ip_address);
 
     // Disable 2FA
-    match data.auth_service.lock() {
-        Ok(mut auth_service) => {
-            match auth_service.disable_two_fa(user_id, disable_request.into_inner()).await {
-                Ok(_) => {
-                    log::info!("2FA disabled successfully for user ID: {}", user_id);
-
-                    Ok(HttpResponse::Ok().json(json!({
-                        "success": true,
-                        "message": "2FA disabled successfully"
-                    })))
-                }
-                Err(auth_error) => {
-                    log::warn!("Failed to disable 2FA for user ID: {} - Error: {}", user_id, auth_error);
+    match data.auth_service.disable_two_fa(&ctx, user_id, disable_request.into_inner()).await {
+        Ok(_) => {
+            log::info!("2FA disabled successfully for user ID: {}", user_id);
 
-                    let (status_code, message) = match auth_error {
-                        AuthError::InvalidCredentials => (400, "Invalid password or 2FA code"),
-                        _ => (500, "Internal server error"),
-                    };
+            Ok(HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "2FA disabled successfully"
+            })))
+        }
+        Err(auth_error) => {
+            log::warn!("Failed to disable 2FA for user ID: {} - Error: {}", user_id, auth_error);
 
-                    Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
-                        .json(json!({
-                            "success": false,
-                            "message": message
-                        })))
+            let (status_code, message) = match auth_error {
+                AuthError::InvalidCredentials => (400, "Invalid password or 2FA code"),
+                _ => return Ok(auth_error.error_response()),
+            };
+
+            Ok(HttpResponse::build(actix_web::http::StatusCode::from_u16(status_code).unwrap())
+                .json(json!({
+                    "success": false,
+                    "message": message
+                })))
+        }
+    }
+}
+
+/// Export the caller's own personal data. Returns a download link that works
+/// for `DATA_EXPORT_TTL_MINUTES`.
+pub async fn export_my_data(req: HttpRequest, ctx: RequestContext, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let user_id = match authorized_user_id(&req) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(*response),
+    };
+
+    Ok(data_export_response(data.auth_service.request_data_export(&ctx, user_id, user_id).await))
+}
+
+/// `201` with a generated export and its download link
+pub(crate) fn data_export_response(result: AuthResult<(DataExport, String)>) -> HttpResponse {
+    match result {
+        Ok((export, download_url)) => HttpResponse::Created()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "success": true,
+                "message": "Data export ready",
+                "data": {
+                    "export": export,
+                    "download_url": download_url,
                 }
-            }
+            })),
+        Err(e) => {
+            log::error!("Failed to export personal data: {}", e);
+            e.error_response()
+        }
+    }
+}
+
+/// Download a personal data export. The link is the credential: no session
+/// is needed, and the document streams out chunk by chunk.
+pub async fn download_data_export(
+    ctx: RequestContext,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let export = match data.auth_service.open_data_export(&ctx, &path.into_inner()).await {
+        Ok(export) => export,
+        Err(e) => return Ok(e.error_response()),
+    };
+
+    let chunks = futures_util::stream::unfold((data, 0i64), move |(data, seq)| async move {
+        if seq >= export.chunks {
+            return None;
         }
+        let chunk = match data.auth_service.data_export_chunk(export.id, seq).await {
+            Ok(Some(content)) => Ok(web::Bytes::from(content)),
+            Ok(None) => Err(actix_web::error::ErrorInternalServerError("data export chunk missing")),
+            Err(e) => {
+                log::error!("Failed to read chunk {} of data export {}: {}", seq, export.id, e);
+                Err(actix_web::error::ErrorInternalServerError("data export unavailable"))
+            }
+        };
+        Some((chunk, (data, seq + 1)))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"personal-data-{}.json\"", export.id),
+        ))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .streaming(chunks))
+}
+
+/// Step-up re-authentication endpoint - re-enter the password (and 2FA code
+/// when enrolled) to unlock sensitive actions on the current session
+pub async fn step_up(
+    req: HttpRequest,
+    ctx: RequestContext,
+    step_up_request: web::Json<StepUpRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = step_up_request.validate() {
+        return Ok(invalid_request("Invalid step-up request", &errors));
+    }
+
+    let token = match extract_token(&req) {
+        Ok(token) => token,
         Err(_) => {
-            Ok(HttpResponse::InternalServerError().json(json!({
+            return Ok(missing_token_response());
+        }
+    };
+
+    match data.auth_service.step_up(&ctx, &token, step_up_request.into_inner()).await {
+        // The presented token no longer validates; clients swap in the one returned here
+        Ok(renewal) => Ok(HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "success": true,
+                "message": "Identity confirmed",
+                "data": {
+                    "valid_for_seconds": STEP_UP_VALIDITY_MINUTES * 60,
+                    "token": renewal.token,
+                    "expires_in": renewal.expires_in,
+                }
+            }))),
+        // The session itself is still good, so this isn't a 401
+        Err(AuthError::InvalidCredentials) => Ok(HttpResponse::Forbidden().json(json!({
+            "success": false,
+            "message": "Invalid password or 2FA code",
+            "error_type": "InvalidCredentials"
+        }))),
+        Err(auth_error) => Ok(session_error_response(&auth_error)),
+    }
+}
+
+/// Issue a token for one browser download of `resource`. Its
+/// `download_url` works once, for `DOWNLOAD_TOKEN_TTL_SECONDS`, and only
+/// while this session is live.
+pub async fn issue_download_token(
+    req: HttpRequest,
+    token_request: web::Json<DownloadTokenRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Err(errors) = token_request.validate() {
+        return Ok(invalid_request("Invalid download token request", &errors));
+    }
+    let resource = match token_request.resource.parse::<DownloadResource>() {
+        Ok(resource) => resource,
+        Err(message) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
                 "success": false,
-                "message": "Internal server error"
-            })))
+                "message": "Unknown download resource",
+                "errors": { "resource": [message] },
+                "valid_resources": DownloadResource::ALL
+            })));
         }
+    };
+
+    let token = match extract_token(&req) {
+        Ok(token) => token,
+        Err(_) => return Ok(missing_token_response()),
+    };
+
+    match data.auth_service.issue_download_token(&token, resource).await {
+        Ok(Some((download_token, expires_at))) => Ok(HttpResponse::Created()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "success": true,
+                "message": "Download token issued",
+                "data": {
+                    "resource": resource,
+                    "token": download_token,
+                    "download_url": format!("{}?{}={}", resource.path(), DOWNLOAD_TOKEN_PARAM, download_token),
+                    "expires_at": expires_at,
+                    "expires_in": DOWNLOAD_TOKEN_TTL_SECONDS,
+                }
+            }))),
+        Ok(None) => Ok(HttpResponse::Forbidden().json(json!({
+            "success": false,
+            "message": "You do not have permission to perform this action",
+            "error_type": "PermissionDenied",
+            "required_permission": resource.permission()
+        }))),
+        Err(auth_error) => Ok(session_error_response(&auth_error)),
     }
 }
 
 /// Health check endpoint
-pub async fn health_check() -> Result<HttpResponse> {
+pub async fn health_check(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let database = data.health.state();
     Ok(HttpResponse::Ok().json(json!({
-        "status": "healthy",
+        "status": if data.degraded.is_some() { "degraded" } else { database.as_str() },
+        "degraded_reason": data.degraded,
+        "database": database,
+        "database_since": data.health.since().to_rfc3339(),
+        "login_queue": data.auth_service.login_queue_depth(),
+        "event_streams": data.auth_service.open_event_streams(),
         "service": "kenya-fsfvi-auth",
         "timestamp": chrono::Utc::now().to_rfc3339(),
         "version": "1.0.0"
     })))
-}
\ No newline at end of file
+}
+
+/// Readiness probe for load balancers: 503 while the database is down or the
+/// server runs degraded, so traffic goes to instances that can serve it
+pub async fn readiness(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let database = data.health.state();
+    let ready = data.degraded.is_none() && database != HealthState::Down;
+    let mut response = if ready { HttpResponse::Ok() } else { HttpResponse::ServiceUnavailable() };
+    Ok(response.json(json!({
+        "ready": ready,
+        "status": if data.degraded.is_some() { "degraded" } else { database.as_str() },
+        "since": data.health.since().to_rfc3339(),
+    })))
+}
+
+/// Stand-in for every auth and admin endpoint while the server runs degraded
+pub async fn degraded_unavailable() -> Result<HttpResponse> {
+    Ok(HttpResponse::ServiceUnavailable().json(json!({
+        "success": false,
+        "message": "The service is running in degraded mode. Please try again later",
+        "error_code": "degraded",
+    })))
+}
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{read_body_json, TestRequest};
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    use crate::models::audit_event::AuditEventType;
+    use crate::models::auth::{MultipleLoginPolicy, SecurityConfig, Severity};
+    use crate::models::context::RequestContext;
+    use crate::models::user::UserRole;
+    use crate::services::auth_service::TWO_FA_QR_REDISPLAYS_PER_HOUR;
+    use crate::utils::clock::SystemClock;
+    use crate::services::session_events::MAX_EVENT_STREAMS_PER_USER;
+    use crate::services::session_service::STEP_UP_VALIDITY_MINUTES;
+    use crate::services::token_service::TokenService;
+    use crate::test_support::{bearer, next_event, TestApp, TEST_PASSWORD};
+
+    fn login(username: &str, password: &str) -> TestRequest {
+        login_with_code(username, password, None)
+    }
+
+    fn login_with_code(username: &str, password: &str, two_fa_code: Option<&str>) -> TestRequest {
+        TestRequest::post()
+            .uri("/api/auth/login")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .set_json(json!({ "username": username, "password": password, "two_fa_code": two_fa_code }))
+    }
+
+    #[actix_web::test]
+    async fn test_login_verify_and_session_expiry() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("e2e_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        let body = app.call_json(login(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["requires_two_fa"], false);
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+
+        let verify = || bearer(TestRequest::get().uri("/api/auth/verify"), &token);
+        assert_eq!(app.call(verify()).await.status(), 200);
+
+        let timeout = SecurityConfig::default().session_timeout_minutes;
+        app.clock.advance(Duration::minutes(timeout + 1));
+        assert_eq!(app.call(verify()).await.status(), 401);
+    }
+
+    fn login_with_fields(username: &str, password: &str, fields: serde_json::Value) -> TestRequest {
+        let mut body = json!({ "username": username, "password": password });
+        body.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        TestRequest::post()
+            .uri("/api/auth/login")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .set_json(body)
+    }
+
+    async fn bot_signals(auth_service: &crate::services::auth_service::AuthService) -> Vec<String> {
+        let events = auth_service.audit_service().get_recent_events(50, false, None).await.unwrap();
+        events
+            .iter()
+            .filter(|e| e.event_type == "BOT_SUSPECTED")
+            .map(|e| e.details.as_ref().unwrap()["signal"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_filled_honeypot_is_refused_like_a_wrong_password() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("honeypot_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        let response = app.call(login_with_fields(&user.username, TEST_PASSWORD, json!({ "website": "http://spam.example" }))).await;
+        assert_eq!(response.status(), 401);
+        let trapped: serde_json::Value = read_body_json(response).await;
+        let wrong_password: serde_json::Value =
+            read_body_json(app.call(login(&user.username, "WrongPassw0rd!!")).await).await;
+        assert_eq!(trapped, wrong_password);
+        assert_eq!(bot_signals(app.auth_service()).await, vec!["honeypot"]);
+
+        // Leaving the field out, or empty as the login page sends it, signs in as before
+        assert_eq!(app.call(login(&user.username, TEST_PASSWORD)).await.status(), 200);
+        let empty = login_with_fields(&user.username, TEST_PASSWORD, json!({ "website": "" }));
+        assert_eq!(app.call(empty).await.status(), 200);
+        assert_eq!(bot_signals(app.auth_service()).await.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_implausibly_fast_login_is_flagged_but_not_refused() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("fast_fill_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let challenge = || async {
+            let response = app.call(TestRequest::get().uri("/api/auth/login-challenge")).await;
+            assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body["data"]["min_fill_ms"], 1000);
+            body["data"]["form_issued_at"].clone()
+        };
+
+        let issued = challenge().await;
+        app.clock.advance(Duration::milliseconds(200));
+        let fast = login_with_fields(&user.username, TEST_PASSWORD, json!({ "form_issued_at": issued }));
+        assert_eq!(app.call(fast).await.status(), 200);
+        assert_eq!(bot_signals(app.auth_service()).await, vec!["too_fast"]);
+
+        // A person's pace, and no challenge at all, go unflagged
+        let issued = challenge().await;
+        app.clock.advance(Duration::seconds(5));
+        let paced = login_with_fields(&user.username, TEST_PASSWORD, json!({ "form_issued_at": issued }));
+        assert_eq!(app.call(paced).await.status(), 200);
+        assert_eq!(app.call(login(&user.username, TEST_PASSWORD)).await.status(), 200);
+        assert_eq!(bot_signals(app.auth_service()).await.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_step_up_replaces_the_token_it_was_sent_with() {
+        let app = TestApp::spawn_with(SecurityConfig {
+            multiple_login_policy: MultipleLoginPolicy::Additional,
+            ..SecurityConfig::default()
+        })
+        .await;
+        let admin = app.create_user("stepped_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let other_device = app.login_as(&admin, "10.0.0.2").await;
+        let before = app.login_as(&admin, "10.0.0.1").await;
+        let verify = |token: &str| bearer(TestRequest::get().uri("/api/auth/verify"), token);
+        let sessions_uri = format!("/api/admin/users/{}/sessions", admin.id);
+        let sessions = |token: &str| bearer(TestRequest::get().uri(&sessions_uri), token);
+
+        let step_up = bearer(TestRequest::post().uri("/api/auth/step-up"), &before)
+            .set_json(json!({ "password": TEST_PASSWORD }));
+        let response = app.call(step_up).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["data"]["valid_for_seconds"], STEP_UP_VALIDITY_MINUTES * 60);
+        assert!(body["data"]["expires_in"].as_i64().unwrap() > 0);
+        let after = body["data"]["token"].as_str().unwrap().to_string();
+
+        // A token captured before the step-up doesn't carry it, or validate at all
+        assert_eq!(app.call(verify(&before)).await.status(), 401);
+        assert_eq!(app.call(sessions(&before)).await.status(), 401);
+        assert_eq!(app.call(verify(&after)).await.status(), 200);
+        assert_eq!(app.call(sessions(&after)).await.status(), 200);
+
+        // The account's other sessions are left alone, and still need their own step-up
+        assert_eq!(app.call(verify(&other_device)).await.status(), 200);
+        assert_eq!(app.call_error(sessions(&other_device)).await, (403, "StepUpRequired".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn test_status_codes_separate_authentication_from_authorization() {
+        let app = TestApp::spawn().await;
+        let officer = app.create_user("status_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&officer, "10.0.0.2").await;
+        let challenge = |response: &actix_web::dev::ServiceResponse<_>| {
+            response.headers().get("www-authenticate").map(|v| v.to_str().unwrap().to_string())
+        };
+
+        // No token: a bare challenge
+        let response = app.call(TestRequest::get().uri("/api/auth/verify")).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(challenge(&response).as_deref(), Some(r#"Bearer realm="kenya-fsfvi""#));
+
+        // A token that doesn't validate
+        let response = app.call(bearer(TestRequest::get().uri("/api/auth/login-history"), "not-a-token")).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(challenge(&response).as_deref(), Some(r#"Bearer realm="kenya-fsfvi", error="invalid_token""#));
+
+        // Wrong password at login
+        let response = app.call(login(&officer.username, "WrongPassw0rd!!")).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(challenge(&response).as_deref(), Some(r#"Bearer realm="kenya-fsfvi""#));
+
+        // Authenticated, but the role doesn't grant the permission
+        let response = app.call(bearer(TestRequest::get().uri("/api/admin/audit"), &token)).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(challenge(&response), None);
+
+        // A wrong step-up password leaves the session intact
+        let step_up = bearer(TestRequest::post().uri("/api/auth/step-up"), &token)
+            .set_json(json!({ "password": "WrongPassw0rd!!" }));
+        let response = app.call(step_up).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(challenge(&response), None);
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &token)).await.status(), 200);
+
+        // A deactivated account is known but refused
+        app.auth_service().set_user_active(officer.id, false).await.unwrap();
+        let response = app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &token)).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(challenge(&response), None);
+
+        // The session has expired
+        let other = app.create_user("status_expired", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let other_token = app.login_as(&other, "10.0.0.3").await;
+        app.clock.advance(Duration::minutes(SecurityConfig::default().session_timeout_minutes + 1));
+        let response = app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &other_token)).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(challenge(&response).as_deref(), Some(r#"Bearer realm="kenya-fsfvi", error="invalid_token""#));
+    }
+
+    #[actix_web::test]
+    async fn test_current_terms_must_be_accepted_before_anything_else() {
+        let terms = |version: &str| SecurityConfig { terms_version: Some(version.to_string()), ..SecurityConfig::default() };
+        let app = TestApp::spawn_with(terms("2026-10")).await;
+        let user = app.create_user("terms_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        let body = app.call_json(login(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["terms_accepted"], false);
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+
+        // Everything but the terms themselves, verify and logout waits on acceptance
+        let blocked = [
+            bearer(TestRequest::get().uri("/api/auth/login-history"), &token),
+            bearer(TestRequest::get().uri("/api/auth/2fa/prepare"), &token),
+            bearer(TestRequest::post().uri("/api/auth/step-up"), &token).set_json(json!({ "password": TEST_PASSWORD })),
+            bearer(TestRequest::get().uri("/api/admin/audit"), &token),
+        ];
+        for request in blocked {
+            let response = app.call(request).await;
+            assert_eq!(response.status(), 403);
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body["error_type"], "TermsAcceptanceRequired");
+        }
+        let verify = app.call_json(bearer(TestRequest::get().uri("/api/auth/verify"), &token)).await;
+        assert_eq!(verify["data"]["user"]["terms_accepted"], false);
+        let status = app.call_json(bearer(TestRequest::get().uri("/api/auth/terms"), &token)).await;
+        assert_eq!(status["data"], json!({ "version": "2026-10", "accepted": false, "accepted_at": null }));
+
+        let accept = |version: &str| {
+            bearer(TestRequest::post().uri("/api/auth/terms/accept"), &token).set_json(json!({ "version": version }))
+        };
+        assert_eq!(app.call(accept("2026-09")).await.status(), 409);
+        let accepted = app.call_json(accept("2026-10")).await;
+        assert_eq!(accepted["data"]["accepted"], true);
+        // Accepting again keeps the original record
+        assert_eq!(app.call_json(accept("2026-10")).await["data"], accepted["data"]);
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/login-history"), &token)).await.status(), 200);
+
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let acceptances: Vec<_> = events.iter().filter(|e| e.event_type == "TERMS_ACCEPTED").collect();
+        assert_eq!(acceptances.len(), 1);
+        assert_eq!(acceptances[0].details.as_ref().unwrap()["version"], "2026-10");
+
+        // A new version asks again
+        let bumped = TestApp::spawn_on(app.pool.clone(), terms("2026-11")).await;
+        let token = bumped.login_as(&user, "10.0.0.4").await;
+        let response = bumped.call(bearer(TestRequest::get().uri("/api/auth/login-history"), &token)).await;
+        assert_eq!(response.status(), 403);
+
+        // The record can't be rewritten
+        let update = sqlx::query("UPDATE terms_acceptances SET version = '2026-11'").execute(&app.pool).await;
+        assert!(update.is_err());
+        assert!(sqlx::query("DELETE FROM terms_acceptances").execute(&app.pool).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_failed_sign_ins_reach_the_owner_as_a_dismissable_alert() {
+        let app = TestApp::spawn().await;
+        let owner = app.create_user("alerts_owner", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let other = app.create_user("alerts_other", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        for _ in 0..3 {
+            assert_eq!(app.call(login(&owner.username, "Wrong-password-123!")).await.status(), 401);
+        }
+
+        let body = app.call_json(login(&owner.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["undismissed_alerts"], 1);
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+        let checkup = app.call_json(bearer(TestRequest::get().uri("/api/auth/security-checkup"), &token)).await;
+        assert_eq!(checkup["data"]["undismissed_alerts"], 1);
+
+        let feed = app.call_json(bearer(TestRequest::get().uri("/api/auth/my-alerts"), &token)).await;
+        assert_eq!(feed["data"]["undismissed"], 1);
+        let alert = &feed["data"]["alerts"][0];
+        assert_eq!((alert["kind"].as_str(), alert["count"].as_u64()), (Some("failed_sign_in"), Some(3)));
+        assert_eq!(alert["severity"], "warning");
+        assert_eq!(alert["dismissed"], false);
+        let dismiss_uri = format!("/api/auth/my-alerts/{}/dismiss", alert["id"].as_str().unwrap());
+
+        // Another account can neither see nor dismiss it
+        let other_token = app.login_as(&other, "10.0.0.2").await;
+        let others = app.call_json(bearer(TestRequest::get().uri("/api/auth/my-alerts"), &other_token)).await;
+        assert_eq!(others["data"]["alerts"], json!([]));
+        let (status, error_type) = app.call_error(bearer(TestRequest::post().uri(&dismiss_uri), &other_token)).await;
+        assert_eq!((status, error_type.as_str()), (404, "AlertNotFound"));
+
+        let dismissed = app.call_json(bearer(TestRequest::post().uri(&dismiss_uri), &token)).await;
+        assert_eq!(dismissed["data"]["dismissed"], true);
+        let body = app.call_json(login(&owner.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["undismissed_alerts"], 0);
+        let token = body["data"]["token"].as_str().unwrap();
+        let feed = app.call_json(bearer(TestRequest::get().uri("/api/auth/my-alerts"), token)).await;
+        assert_eq!(feed["data"]["alerts"][0]["dismissed"], true);
+    }
+
+    #[actix_web::test]
+    async fn test_security_checkup_flags_each_account_state() {
+        let app = TestApp::spawn_with(SecurityConfig { password_max_age_days: 30, ..SecurityConfig::default() }).await;
+        let checkup = |token: &str| bearer(TestRequest::get().uri("/api/auth/security-checkup"), token);
+
+        let settled = app.create_user("checkup_settled", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let temporary = app.create_user("checkup_temporary", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET is_temporary_password = TRUE WHERE id = ?")
+            .bind(temporary.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let enrolled = app.create_user("checkup_enrolled", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        let short_of_codes = app.create_user("checkup_short_of_codes", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        sqlx::query(r#"UPDATE users SET two_fa_backup_codes = '["AAAA1111","BBBB2222"]' WHERE id = ?"#)
+            .bind(short_of_codes.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let alerted = app.create_user("checkup_alerted", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let ctx = RequestContext::new("41.90.12.7", None);
+        for event_type in [AuditEventType::ImpossibleTravel, AuditEventType::LoginFromUnexpectedCountry, AuditEventType::StepUpAuth] {
+            app.auth_service()
+                .audit_service()
+                .log_security_event(&ctx, Some(alerted.id), event_type, "Checkup fixture", true, Severity::Warning, None)
+                .await
+                .unwrap();
+        }
+
+        let response = app.call(checkup(&app.login_as(&settled, "10.0.0.5").await)).await;
+        assert_eq!(response.headers().get("cache-control").unwrap(), "private, max-age=60");
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(
+            body["data"],
+            json!({
+                "temporary_password": false,
+                "password_expired": false,
+                "two_fa_enabled": false,
+                "two_fa_required": false,
+                "backup_codes_remaining": 0,
+                "backup_codes_low": false,
+                "unacknowledged_sign_in_alerts": 0,
+                "undismissed_alerts": 0,
+                "terms_accepted": true,
+            })
+        );
+
+        let body = app.call_json(checkup(&app.login_as(&temporary, "10.0.0.5").await)).await;
+        assert_eq!(body["data"]["temporary_password"], true);
+
+        let body = app.call_json(checkup(&app.login_as(&enrolled, "10.0.0.5").await)).await;
+        assert_eq!(body["data"]["two_fa_enabled"], true);
+        assert_eq!(body["data"]["backup_codes_remaining"], 10);
+        assert_eq!(body["data"]["backup_codes_low"], false);
+
+        let body = app.call_json(checkup(&app.login_as(&short_of_codes, "10.0.0.5").await)).await;
+        assert_eq!(body["data"]["backup_codes_remaining"], 2);
+        assert_eq!(body["data"]["backup_codes_low"], true);
+
+        let body = app.call_json(checkup(&app.login_as(&alerted, "10.0.0.5").await)).await;
+        assert_eq!(body["data"]["unacknowledged_sign_in_alerts"], 2);
+        // Both are sign-ins from an unusual location, in one alert
+        assert_eq!(body["data"]["undismissed_alerts"], 1);
+
+        app.clock.advance(Duration::days(31));
+        let body = app.call_json(checkup(&app.login_as(&settled, "10.0.0.5").await)).await;
+        assert_eq!(body["data"]["password_expired"], true);
+
+        // Reachable before the terms are accepted, to say they aren't
+        let app = TestApp::spawn_with(SecurityConfig { terms_version: Some("2026-10".to_string()), ..SecurityConfig::default() }).await;
+        let pending = app.create_user("checkup_pending_terms", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let body = app.call_json(checkup(&app.login_as(&pending, "10.0.0.5").await)).await;
+        assert_eq!(body["data"]["terms_accepted"], false);
+    }
+
+    #[actix_web::test]
+    async fn test_deny_policy_refuses_a_second_login_until_asked_to_sign_out_the_other() {
+        let app = TestApp::spawn_with(SecurityConfig {
+            multiple_login_policy: MultipleLoginPolicy::Deny,
+            ..SecurityConfig::default()
+        })
+        .await;
+        let user = app.create_user("e2e_single_session", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let first = app.login_as(&user, "10.0.0.5").await;
+
+        let response = app.call(login(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(response.status(), 409);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["error_type"], "SESSION_EXISTS");
+        let existing = &body["data"]["existing_session"];
+        assert_eq!(existing["ip_address"], "10.0.0.5");
+        assert_eq!(existing["user_agent"], "test-agent");
+        assert!(existing["created_at"].is_string());
+        // The refusal leaves the other device signed in
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &first)).await.status(), 200);
+
+        let request = TestRequest::post()
+            .uri("/api/auth/login")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .set_json(json!({ "username": user.username, "password": TEST_PASSWORD, "sign_out_other_sessions": true }));
+        let body = app.call_json(request).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &first)).await.status(), 401);
+        let token = body["data"]["token"].as_str().unwrap();
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), token)).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_lockout_lifts_after_lockout_duration() {
+        let config = SecurityConfig::default();
+        let app = TestApp::spawn_with(config.clone()).await;
+        let user = app.create_user("e2e_lockout", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        for _ in 0..config.max_failed_attempts {
+            let response = app.call(login(&user.username, "WrongPassw0rd!!")).await;
+            assert_eq!(response.status(), 401);
+        }
+        assert_eq!(app.call(login(&user.username, TEST_PASSWORD)).await.status(), 423);
+
+        app.clock.advance(Duration::minutes(config.lockout_duration_minutes - 1));
+        assert_eq!(app.call(login(&user.username, TEST_PASSWORD)).await.status(), 423);
+
+        app.clock.advance(Duration::minutes(2));
+        assert_eq!(app.call(login(&user.username, TEST_PASSWORD)).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_two_fa_setup_replaces_session_and_guards_login() {
+        let app = TestApp::spawn().await;
+        let mut user = app.create_user("e2e_two_fa", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+
+        let prepared = app.call_json(bearer(TestRequest::get().uri("/api/auth/2fa/prepare"), &token)).await;
+        assert_eq!(prepared["success"], true);
+        user.two_fa_secret = Some(prepared["data"]["secret"].as_str().unwrap().to_string());
+
+        let setup = bearer(TestRequest::post().uri("/api/auth/2fa/setup"), &token)
+            .set_json(json!({ "totp_code": user.totp().unwrap() }));
+        let body = app.call_json(setup).await;
+        assert_eq!(body["data"]["enabled"], true);
+        let renewed = body["data"]["session"]["token"].as_str().unwrap().to_string();
+
+        let verify = |token: &str| bearer(TestRequest::get().uri("/api/auth/verify"), token);
+        assert_eq!(app.call(verify(&token)).await.status(), 401);
+        assert_eq!(app.call(verify(&renewed)).await.status(), 200);
+
+        // The password alone now only gets as far as the second factor
+        let body = app.call_json(login(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["requires_two_fa"], true);
+        assert_eq!(body["data"]["token"], "");
+
+        let code = user.totp().unwrap();
+        let body = app.call_json(login_with_code(&user.username, TEST_PASSWORD, Some(&code))).await;
+        assert_eq!(body["data"]["requires_two_fa"], false);
+        assert!(!body["data"]["token"].as_str().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_two_fa_qr_shows_the_stored_secret_a_few_times_an_hour() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("qr_again", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        let secret = user.two_fa_secret.clone().unwrap();
+        let token = app.login_as(&user, "10.0.0.1").await;
+        let qr = |token: &str, password: &str| {
+            bearer(TestRequest::post().uri("/api/auth/2fa/qr"), token)
+                .set_json(json!({ "password": password, "totp_code": user.totp().unwrap() }))
+        };
+
+        assert_eq!(app.call(qr(&token, "WrongPassw0rd!")).await.status(), 403);
+
+        let body = app.call_json(qr(&token, TEST_PASSWORD)).await;
+        assert_eq!(body["success"], true);
+        let url = body["data"]["otpauth_url"].as_str().unwrap();
+        assert!(url.starts_with("otpauth://totp/Kenya FSFVI Platform:qr_again?"));
+        assert!(url.contains(&format!("secret={}&", secret)));
+        assert!(body["data"]["qr_code"].as_str().unwrap().starts_with("data:image/png;base64,"));
+
+        // Nothing about the enrollment changed
+        let stored: (bool, Option<String>) = sqlx::query_as("SELECT two_fa_enabled, two_fa_secret FROM users WHERE id = ?")
+            .bind(user.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, (true, Some(secret.clone())));
+
+        let events = app.auth_service().audit_service().get_recent_events(50, false, None).await.unwrap();
+        let shown: Vec<_> = events.iter().filter(|e| e.event_type == "TWO_FA_QR_REDISPLAYED").collect();
+        assert_eq!(shown.len(), 2);
+        assert!(shown.iter().all(|e| e.severity == Severity::Warning));
+        assert_eq!(shown.iter().filter(|e| e.success).count(), 1);
+
+        for _ in 1..TWO_FA_QR_REDISPLAYS_PER_HOUR {
+            assert_eq!(app.call(qr(&token, TEST_PASSWORD)).await.status(), 200);
+        }
+        let limited = app.call(qr(&token, TEST_PASSWORD)).await;
+        assert_eq!(limited.status(), 429);
+        assert_eq!(read_body_json::<serde_json::Value, _>(limited).await["error_type"], "TwoFactorQrLimitReached");
+
+        app.clock.advance(Duration::minutes(61));
+        let token = app.login_as(&user, "10.0.0.1").await;
+        assert_eq!(app.call(qr(&token, TEST_PASSWORD)).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_two_fa_qr_with_corrupt_secret_asks_for_reenrollment() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("qr_corrupt", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+        let code = user.totp().unwrap();
+        sqlx::query("UPDATE users SET two_fa_secret = 'not base64!' WHERE id = ?")
+            .bind(user.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let request = bearer(TestRequest::post().uri("/api/auth/2fa/qr"), &token)
+            .set_json(json!({ "password": TEST_PASSWORD, "totp_code": code }));
+        let response = app.call(request).await;
+        assert_eq!(response.status(), 409);
+        assert_eq!(read_body_json::<serde_json::Value, _>(response).await["error_type"], "TwoFactorReenrollmentRequired");
+    }
+
+    #[actix_web::test]
+    async fn test_backup_code_signs_in_once() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("e2e_backup", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        let code = user.backup_codes[0].as_str();
+
+        let first = app.call(login_with_code(&user.username, TEST_PASSWORD, Some(code))).await;
+        assert_eq!(first.status(), 200);
+        let second = app.call(login_with_code(&user.username, TEST_PASSWORD, Some(code))).await;
+        assert_eq!(second.status(), 401);
+    }
+
+    fn login_v2(username: &str, password: &str) -> TestRequest {
+        TestRequest::post()
+            .uri("/api/v2/auth/login")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .set_json(json!({ "username": username, "password": password }))
+    }
+
+    fn login_two_factor(challenge_token: &serde_json::Value, code: &str) -> TestRequest {
+        TestRequest::post()
+            .uri("/api/v2/auth/login/2fa")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .set_json(json!({ "challenge_token": challenge_token, "code": code }))
+    }
+
+    #[actix_web::test]
+    async fn test_v2_login_without_two_fa_completes_in_one_step() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("v2_single", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        let wrong = app.call(login_v2(&user.username, "WrongPassw0rd!")).await;
+        assert_eq!(wrong.status(), 401);
+        assert_eq!(read_body_json::<serde_json::Value, _>(wrong).await["error_type"], "InvalidCredentials");
+
+        let body = app.call_json(login_v2(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["next"], "complete");
+        assert_eq!(body["data"]["user"]["username"], "v2_single");
+        assert!(body["data"].get("challenge_token").is_none());
+        let token = body["data"]["token"].as_str().unwrap();
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), token)).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_v2_login_with_two_fa_takes_the_code_with_the_challenge_token() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("v2_two_step", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+
+        let body = app.call_json(login_v2(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["next"], "two_factor");
+        assert!(body["data"].get("token").is_none());
+        let superseded = body["data"]["challenge_token"].clone();
+
+        // Entering the password again replaces the challenge
+        let body = app.call_json(login_v2(&user.username, TEST_PASSWORD)).await;
+        let challenge = body["data"]["challenge_token"].clone();
+        assert_ne!(challenge, superseded);
+        let stale = app.call(login_two_factor(&superseded, &user.totp().unwrap())).await;
+        assert_eq!(stale.status(), 401);
+        assert_eq!(read_body_json::<serde_json::Value, _>(stale).await["error_type"], "InvalidToken");
+
+        let wrong = app.call(login_two_factor(&challenge, "ZZZZZZZZ")).await;
+        assert_eq!(wrong.status(), 401);
+        assert_eq!(read_body_json::<serde_json::Value, _>(wrong).await["error_type"], "InvalidCredentials");
+
+        let body = app.call_json(login_two_factor(&challenge, &user.totp().unwrap())).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["next"], "complete");
+        let token = body["data"]["token"].as_str().unwrap();
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), token)).await.status(), 200);
+
+        // The challenge signs in once
+        let again = app.call(login_two_factor(&challenge, &user.backup_codes[0])).await;
+        assert_eq!(again.status(), 401);
+    }
+
+    fn login_change_password(challenge_token: &serde_json::Value, current: &str, new: &str) -> TestRequest {
+        TestRequest::post()
+            .uri("/api/v2/auth/login/change-password")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .set_json(json!({
+                "challenge_token": challenge_token,
+                "current_password": current,
+                "new_password": new,
+                "confirm_password": new,
+            }))
+    }
+
+    #[actix_web::test]
+    async fn test_v2_login_with_an_expired_password_changes_it_and_signs_in() {
+        let app = TestApp::spawn_with(SecurityConfig { password_max_age_days: 30, ..SecurityConfig::default() }).await;
+        let user = app.create_user("v2_expired", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET password_changed_at = ? WHERE id = ?")
+            .bind(Utc::now() - Duration::days(31))
+            .bind(user.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let new_password = "Fresh-Passw0rd-2026!";
+
+        let body = app.call_json(login_v2(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["next"], "password_change");
+        assert_eq!(body["data"]["reason"], "expired");
+        assert_eq!(body["data"]["expires_in"], 600);
+        assert!(body["data"].get("token").is_none());
+        let challenge = body["data"]["challenge_token"].clone();
+
+        // Refused new passwords leave the challenge open for another try
+        let weak = app.call(login_change_password(&challenge, TEST_PASSWORD, "short")).await;
+        assert_eq!(weak.status(), 400);
+        let reused = app.call(login_change_password(&challenge, TEST_PASSWORD, TEST_PASSWORD)).await;
+        assert_eq!(reused.status(), 400);
+        assert_eq!(read_body_json::<serde_json::Value, _>(reused).await["error_type"], "PasswordReused");
+        let wrong = app.call(login_change_password(&challenge, "WrongPassw0rd!", new_password)).await;
+        assert_eq!(wrong.status(), 401);
+
+        let body = app.call_json(login_change_password(&challenge, TEST_PASSWORD, new_password)).await;
+        assert_eq!(body["data"]["next"], "complete");
+        let token = body["data"]["token"].as_str().unwrap();
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), token)).await.status(), 200);
+
+        // The challenge works once, and only the new password signs in now
+        let again = app.call(login_change_password(&challenge, new_password, "Another-Passw0rd-2026!")).await;
+        assert_eq!(again.status(), 401);
+        assert_eq!(read_body_json::<serde_json::Value, _>(again).await["error_type"], "InvalidToken");
+        assert_eq!(app.call(login_v2(&user.username, TEST_PASSWORD)).await.status(), 401);
+        let body = app.call_json(login_v2(&user.username, new_password)).await;
+        assert_eq!(body["data"]["next"], "complete");
+
+        let events: Vec<String> = sqlx::query_scalar(
+            "SELECT event_type FROM security_events WHERE event_type LIKE '%PASSWORD_CHANGE%' ORDER BY rowid",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(events, ["LOGIN_PASSWORD_CHANGE_REQUIRED", "PASSWORD_CHANGE", "LOGIN_PASSWORD_CHANGED"]);
+    }
+
+    #[actix_web::test]
+    async fn test_password_change_challenge_expires() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("v2_temporary", UserRole::KenyaGovernment, TEST_PASSWORD, true).await;
+        sqlx::query("UPDATE users SET is_temporary_password = TRUE WHERE id = ?")
+            .bind(user.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // The second factor comes first
+        let body = app.call_json(login_v2(&user.username, TEST_PASSWORD)).await;
+        assert_eq!(body["data"]["next"], "two_factor");
+        let body = app.call_json(login_two_factor(&body["data"]["challenge_token"], &user.totp().unwrap())).await;
+        assert_eq!(body["data"]["next"], "password_change");
+        assert_eq!(body["data"]["reason"], "temporary");
+
+        app.clock.advance(Duration::minutes(11));
+        let late = app.call(login_change_password(&body["data"]["challenge_token"], TEST_PASSWORD, "Fresh-Passw0rd-2026!")).await;
+        assert_eq!(late.status(), 401);
+        assert_eq!(read_body_json::<serde_json::Value, _>(late).await["error_type"], "InvalidToken");
+    }
+
+    fn events(token: &str) -> TestRequest {
+        bearer(TestRequest::get().uri("/api/auth/events"), token)
+    }
+
+    #[actix_web::test]
+    async fn test_event_stream_reports_admin_termination_and_closes() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("e2e_events_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let user = app.create_user("e2e_events_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let token = app.login_as(&user, "10.0.0.2").await;
+
+        let response = app.call(events(&token)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("content-type").unwrap(), "text/event-stream");
+        assert_eq!(app.auth_service().open_event_streams(), 1);
+        let mut body = Box::pin(response.into_body());
+
+        let admin_token = app.step_up(&admin_token, &admin).await;
+        let terminate = bearer(
+            TestRequest::delete().uri(&format!("/api/admin/users/{}/sessions", user.id)),
+            &admin_token,
+        );
+        assert_eq!(app.call(terminate).await.status(), 200);
+
+        let event = next_event(&mut body).await.unwrap();
+        assert!(event.starts_with("event: session_revoked\n"));
+        assert!(event.contains(r#""reason":"terminated_by_admin""#));
+        assert_eq!(next_event(&mut body).await, None);
+
+        drop(body);
+        assert_eq!(app.auth_service().open_event_streams(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_event_stream_reports_session_replaced_by_new_login() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("e2e_events_replaced", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+
+        let mut body = Box::pin(app.call(events(&token)).await.into_body());
+        app.login_as(&user, "10.0.0.2").await;
+
+        let event = next_event(&mut body).await.unwrap();
+        assert!(event.starts_with("event: session_revoked\n"));
+        assert!(event.contains(r#""reason":"replaced""#));
+    }
+
+    #[actix_web::test]
+    async fn test_event_streams_are_capped_per_user() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("e2e_events_cap", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+
+        let mut open = Vec::new();
+        for _ in 0..MAX_EVENT_STREAMS_PER_USER {
+            let response = app.call(events(&token)).await;
+            assert_eq!(response.status(), 200);
+            open.push(response);
+        }
+        assert_eq!(app.call(events(&token)).await.status(), 429);
+
+        // A disconnected client frees its slot
+        open.pop();
+        assert_eq!(app.call(events(&token)).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_event_stream_warns_before_session_and_password_expire() {
+        let config = SecurityConfig { password_max_age_days: 30, ..SecurityConfig::default() };
+        let app = TestApp::spawn_with(config.clone()).await;
+        let user = app.create_user("e2e_events_expiry", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET password_changed_at = ? WHERE id = ?")
+            .bind(Utc::now() - Duration::days(25))
+            .bind(user.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let token = app.login_as(&user, "10.0.0.1").await;
+
+        app.clock.advance(Duration::minutes(config.session_timeout_minutes - 1));
+        let mut body = Box::pin(app.call(events(&token)).await.into_body());
+
+        let first = next_event(&mut body).await.unwrap();
+        let second = next_event(&mut body).await.unwrap();
+        assert!(first.starts_with("event: session_expiring\n"));
+        assert!(second.starts_with("event: password_expiring\n"));
+    }
+
+    async fn login_attempts_recorded(app: &TestApp<impl Sized>) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM login_attempts").fetch_one(&app.pool).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_invalid_login_is_refused_before_the_service() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("e2e_validation", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+
+        let res = app.call(login(&user.username, &"A1!a".repeat(200))).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["errors"]["password"][0], "Password must be between 8 and 512 characters");
+        assert!(!body.to_string().contains("A1!aA1!a"), "submitted password echoed back");
+
+        let body = app.call_json(login("e", TEST_PASSWORD)).await;
+        assert_eq!(body["errors"]["username"][0], "Username must be between 3 and 50 characters");
+
+        // Neither request was authenticated, so nothing was recorded or counted against the account
+        assert_eq!(login_attempts_recorded(&app).await, 0);
+        assert_eq!(app.call(login(&user.username, TEST_PASSWORD)).await.status(), 200);
+        assert_eq!(login_attempts_recorded(&app).await, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_oversized_new_password_is_refused_with_400() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("e2e_validation_change", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+
+        let oversized = "Xk7#".repeat(200);
+        let request = bearer(TestRequest::post().uri("/api/auth/change-password"), &token).set_json(json!({
+            "current_password": TEST_PASSWORD,
+            "new_password": oversized,
+            "confirm_password": oversized,
+        }));
+        let res = app.call(request).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["errors"]["new_password"][0], "New password must be at most 512 characters");
+
+        // The password is unchanged, so the session was not rotated
+        let verify = bearer(TestRequest::get().uri("/api/auth/verify"), &token);
+        assert_eq!(app.call(verify).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_parallel_writers_under_contention_get_no_server_errors() {
+        use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+        // A file database whose connections wait only briefly on each other's
+        // locks, so contention reaches the statement retries
+        let dir = tempfile::tempdir().unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("contended.db"))
+            .create_if_missing(true)
+            .busy_timeout(std::time::Duration::from_millis(50));
+        let pool = SqlitePoolOptions::new().max_connections(8).connect_with(options).await.unwrap();
+        crate::utils::database::run_migrations(&pool).await.unwrap();
+        let app = TestApp::spawn_on(pool, SecurityConfig::default()).await;
+
+        // Sign everyone in first; logins queue for password hashing, which isn't the point here
+        let mut requests = Vec::new();
+        for i in 0..12 {
+            let user = app.create_user(&format!("contended_{}", i), UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+            let token = app.login_as(&user, &format!("10.1.0.{}", i)).await;
+            let request = if i % 2 == 0 {
+                TestRequest::post().uri("/api/auth/logout")
+            } else {
+                TestRequest::get().uri("/api/auth/verify")
+            };
+            requests.push(bearer(request, &token).peer_addr(format!("10.1.0.{}:4000", i).parse().unwrap()));
+        }
+        let responses = futures_util::future::join_all(requests.into_iter().map(|request| app.call(request))).await;
+
+        let statuses: Vec<u16> = responses.iter().map(|response| response.status().as_u16()).collect();
+        assert!(statuses.iter().all(|status| *status == 200), "{:?}", statuses);
+        assert_eq!(app.auth_service().busy_retry_counts().exhausted, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_password_reset_request_reveals_channels_only_to_admins() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("reset_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let verified = app.create_user("reset_verified", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let unverified = app.create_user("reset_unverified", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let no_email = app.create_user("reset_no_email", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET email = username || '@agriculture.go.ke' WHERE id IN (?, ?)")
+            .bind(verified.id)
+            .bind(unverified.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE users SET email_verified_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(verified.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let officer_token = app.login_as(&no_email, "10.0.0.2").await;
+        let request_reset = |username: &str| {
+            TestRequest::post().uri("/api/auth/password-reset").set_json(json!({ "username": username }))
+        };
+
+        // Anonymous callers, and signed-in ones without user_manage, can't tell the accounts apart
+        let mut answers = Vec::new();
+        for username in ["reset_verified", "reset_unverified", "reset_no_email", "reset_nobody"] {
+            let res = app.call(request_reset(username)).await;
+            assert_eq!(res.status(), 202);
+            answers.push(read_body_json::<serde_json::Value, _>(res).await);
+        }
+        let res = app.call(bearer(request_reset("reset_verified"), &officer_token)).await;
+        answers.push(read_body_json(res).await);
+        assert!(answers.iter().all(|answer| *answer == answers[0]), "{answers:?}");
+        assert_eq!(answers[0]["data"]["channels"], json!(["email", "admin_assisted"]));
+        assert_eq!(answers[0]["data"]["generic"], true);
+
+        // Only the verified address was sent links, one per request
+        let emailed: Vec<(uuid::Uuid, i64)> = sqlx::query_as(
+            "SELECT user_id, COUNT(*) FROM notifications WHERE kind = 'PASSWORD_RESET' GROUP BY user_id",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(emailed, [(verified.id, 2)]);
+
+        let admin_view = |username: &str| bearer(request_reset(username), &admin_token);
+        let body = app.call_json(admin_view("reset_verified")).await;
+        assert_eq!(body["data"]["channels"], json!(["email", "admin_assisted"]));
+        assert_eq!(body["data"]["generic"], false);
+        let body = app.call_json(admin_view("reset_unverified")).await;
+        assert_eq!(body["data"]["channels"], json!(["admin_assisted"]));
+        app.data.auth_service.set_user_active(no_email.id, false).await.unwrap();
+        let body = app.call_json(admin_view("reset_no_email")).await;
+        assert_eq!(body["data"]["channels"], json!([]));
+        assert_eq!(app.call(admin_view("reset_nobody")).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_emailed_reset_link_sets_a_new_password_once() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("reset_owner", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET email = 'owner@agriculture.go.ke', email_verified_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(user.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let session = app.login_as(&user, "10.0.0.1").await;
+
+        let request = TestRequest::post().uri("/api/auth/password-reset").set_json(json!({ "username": user.username }));
+        assert_eq!(app.call(request).await.status(), 202);
+        let metadata: String = sqlx::query_scalar("SELECT metadata FROM notifications WHERE user_id = ? AND kind = 'PASSWORD_RESET'")
+            .bind(user.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        let link = serde_json::from_str::<serde_json::Value>(&metadata).unwrap()["link"].as_str().unwrap().to_string();
+        assert!(link.starts_with("http://localhost:3000/reset-password?token="), "{link}");
+        let token = link.rsplit('=').next().unwrap().to_string();
+
+        let confirm = |new_password: &str, confirm_password: &str| {
+            TestRequest::post().uri("/api/auth/password-reset/confirm").set_json(json!({
+                "token": token,
+                "new_password": new_password,
+                "confirm_password": confirm_password,
+            }))
+        };
+        // A mismatched confirmation leaves the token usable
+        assert_eq!(app.call(confirm("FreshPassw0rd654!", "FreshPassw0rd655!")).await.status(), 400);
+        assert_eq!(app.call(confirm("FreshPassw0rd654!", "FreshPassw0rd654!")).await.status(), 200);
+
+        // Every session ended and only the new password signs in
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &session)).await.status(), 401);
+        assert_eq!(app.call(login(&user.username, TEST_PASSWORD)).await.status(), 401);
+        assert_eq!(app.call(login(&user.username, "FreshPassw0rd654!")).await.status(), 200);
+
+        let res = app.call(confirm("OtherPassw0rd321!", "OtherPassw0rd321!")).await;
+        assert_eq!(res.status(), 409);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["error_type"], "PasswordResetUsed");
+
+        let events: Vec<String> = sqlx::query_scalar(
+            "SELECT event_type FROM security_events WHERE event_type LIKE 'PASSWORD_RESET%' ORDER BY rowid",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(events, ["PASSWORD_RESET_REQUESTED", "PASSWORD_RESET_COMPLETED", "PASSWORD_RESET_LINK_REUSED"]);
+    }
+
+    #[actix_web::test]
+    async fn test_reissued_token_retires_the_one_it_replaces() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("reissue_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let original = app.login_as(&user, "10.0.0.1").await;
+        let verify = |token: &str| bearer(TestRequest::get().uri("/api/auth/verify"), token);
+        let reissue = |token: &str| bearer(TestRequest::post().uri("/api/auth/token/reissue"), token);
+
+        let res = app.call(reissue(&original)).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get("cache-control").unwrap(), "no-store");
+        let body: serde_json::Value = read_body_json(res).await;
+        let reissued = body["data"]["token"].as_str().unwrap().to_string();
+
+        // Same session, new token ID: only the new token matches it
+        let tokens = TokenService::new(SecurityConfig::default(), Arc::new(SystemClock));
+        let (before, after) = (tokens.validate_token(&original).unwrap(), tokens.validate_token(&reissued).unwrap());
+        assert_eq!(before.session_id, after.session_id);
+        assert_ne!(before.jti, after.jti);
+        let (jti, family): (String, String) = sqlx::query_as("SELECT jti, token_family FROM sessions WHERE id = ?")
+            .bind(&after.session_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(jti, after.jti);
+        assert!(!family.is_empty());
+
+        assert_eq!(app.call(verify(&original)).await.status(), 401);
+        assert_eq!(app.call(reissue(&original)).await.status(), 401);
+        assert_eq!(app.call(verify(&reissued)).await.status(), 200);
+
+        // Nothing was blacklisted to retire the old token
+        let revoked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM revoked_tokens").fetch_one(&app.pool).await.unwrap();
+        assert_eq!(revoked, 0);
+
+        // Signing out ends the session, whichever token it holds
+        assert_eq!(app.call(bearer(TestRequest::post().uri("/api/auth/logout"), &reissued)).await.status(), 200);
+        assert_eq!(app.call(verify(&reissued)).await.status(), 401);
+    }
+
+    fn device_request(path: &str, body: serde_json::Value) -> TestRequest {
+        TestRequest::post().uri(&format!("/api/auth/device/{}", path)).set_json(body)
+    }
+
+    #[actix_web::test]
+    async fn test_kiosk_signs_in_once_approved_from_a_signed_in_session() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("kiosk_owner", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let phone = app.login_as(&user, "10.0.0.1").await;
+
+        let body = app.call_json(device_request("start", json!({}))).await;
+        assert_eq!(body["data"]["expires_in"], 120);
+        let user_code = body["data"]["user_code"].as_str().unwrap().to_string();
+        let polling_token = body["data"]["polling_token"].as_str().unwrap().to_string();
+        assert_eq!(user_code.len(), 9);
+        let poll = || device_request("poll", json!({ "polling_token": polling_token }));
+        assert_eq!(app.call_error(poll()).await, (400, "DeviceAuthorizationPending".to_string()));
+
+        // Approving takes a recent step-up; the code is accepted however it is typed
+        let approve = bearer(device_request("approve", json!({ "user_code": user_code.to_lowercase() })), &phone);
+        assert_eq!(app.call_error(approve).await, (403, "StepUpRequired".to_string()));
+        let phone = app.step_up(&phone, &user).await;
+        let approve = bearer(device_request("approve", json!({ "user_code": user_code.to_lowercase() })), &phone);
+        assert_eq!(app.call(approve).await.status(), 200);
+
+        let body = app.call_json(poll()).await;
+        assert_eq!(body["data"]["user"]["kiosk"], true);
+        assert_eq!(body["data"]["expires_in"], 15 * 60);
+        let kiosk = body["data"]["token"].as_str().unwrap().to_string();
+        assert_eq!(app.call_error(poll()).await, (409, "DeviceCodeUsed".to_string()));
+
+        // The kiosk token carries the claim, also once re-issued
+        let tokens = TokenService::new(SecurityConfig::default(), Arc::new(SystemClock));
+        assert!(tokens.validate_token(&kiosk).unwrap().kiosk);
+        assert!(!tokens.validate_token(&phone).unwrap().kiosk);
+        let res = app.call(bearer(TestRequest::post().uri("/api/auth/token/reissue"), &kiosk)).await;
+        let body: serde_json::Value = read_body_json(res).await;
+        let kiosk = body["data"]["token"].as_str().unwrap().to_string();
+        assert!(tokens.validate_token(&kiosk).unwrap().kiosk);
+
+        // Everyday routes answer the kiosk; the account's security settings don't
+        let verify = |token: &str| bearer(TestRequest::get().uri("/api/auth/verify"), token);
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/login-history"), &kiosk)).await.status(), 200);
+        for path in ["/api/auth/me/data-export", "/api/auth/step-up", "/api/auth/device/approve"] {
+            let request = bearer(TestRequest::post().uri(path).set_json(json!({})), &kiosk);
+            assert_eq!(app.call_error(request).await, (403, "KioskRestricted".to_string()), "{path}");
+        }
+        // The phone stays signed in alongside it
+        assert_eq!(app.call(verify(&phone)).await.status(), 200);
+
+        let events: Vec<String> = sqlx::query_scalar(
+            "SELECT event_type FROM security_events WHERE event_type IN ('DEVICE_LOGIN_APPROVED', 'KIOSK_SESSION_STARTED') ORDER BY rowid",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(events, ["DEVICE_LOGIN_APPROVED", "KIOSK_SESSION_STARTED"]);
+
+        // However busy, the kiosk session ends 15 minutes after it started
+        app.clock.advance(Duration::minutes(14));
+        assert_eq!(app.call(verify(&kiosk)).await.status(), 200);
+        app.clock.advance(Duration::minutes(1));
+        assert_eq!(app.call(verify(&kiosk)).await.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_kiosk_codes_expire_and_can_be_denied() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("kiosk_denier", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let phone = app.login_as(&user, "10.0.0.1").await;
+        let phone = app.step_up(&phone, &user).await;
+        let app = &app;
+        let start = || async move {
+            let body = app.call_json(device_request("start", json!({}))).await;
+            (body["data"]["user_code"].as_str().unwrap().to_string(), body["data"]["polling_token"].as_str().unwrap().to_string())
+        };
+        let approve = |user_code: &str, approve: bool| {
+            bearer(device_request("approve", json!({ "user_code": user_code, "approve": approve })), &phone)
+        };
+        let poll = |polling_token: &str| device_request("poll", json!({ "polling_token": polling_token }));
+
+        // Two minutes to approve
+        let (late_code, late_token) = start().await;
+        app.clock.advance(Duration::minutes(2));
+        assert_eq!(app.call_error(approve(&late_code, true)).await, (410, "DeviceCodeExpired".to_string()));
+        assert_eq!(app.call_error(poll(&late_token)).await, (410, "DeviceCodeExpired".to_string()));
+        assert_eq!(app.call_error(approve("BCDF-GHJK", true)).await, (404, "DeviceCodeInvalid".to_string()));
+        assert_eq!(app.call_error(poll("not-a-polling-token")).await, (404, "DeviceCodeInvalid".to_string()));
+
+        // A denied code tells the kiosk so, and can't be approved after all
+        let (user_code, polling_token) = start().await;
+        assert_eq!(app.call(approve(&user_code, false)).await.status(), 200);
+        assert_eq!(app.call_error(poll(&polling_token)).await, (403, "DeviceLoginDenied".to_string()));
+        assert_eq!(app.call_error(approve(&user_code, true)).await, (409, "DeviceCodeUsed".to_string()));
+        assert_eq!(app.call_error(poll(&polling_token)).await, (403, "DeviceLoginDenied".to_string()));
+
+        let sessions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE user_id = ? AND revoked_at IS NULL")
+            .bind(user.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(sessions, 1);
+        let denied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE event_type = 'DEVICE_LOGIN_DENIED'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(denied, 1);
+    }
+
+    fn download_token_request(resource: &str) -> TestRequest {
+        TestRequest::post().uri("/api/auth/download-token").set_json(json!({ "resource": resource }))
+    }
+
+    #[actix_web::test]
+    async fn test_download_token_fetches_its_export_once_while_the_session_lasts() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("download_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let officer = app.create_user("download_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let issue = {
+            let (app, admin_token) = (&app, &admin_token);
+            move || async move { app.call_json(bearer(download_token_request("audit_export"), admin_token)).await }
+        };
+        let download = |uri: &str| TestRequest::get().uri(uri);
+
+        let issued = issue().await;
+        assert_eq!(issued["data"]["expires_in"], 60);
+        let dt = issued["data"]["token"].as_str().unwrap().to_string();
+        let url = issued["data"]["download_url"].as_str().unwrap().to_string();
+        assert_eq!(url, format!("/api/admin/audit/export?dt={}", dt));
+
+        // Bound to its resource: no other route takes it, and trying doesn't use it up
+        for other in ["/api/admin/audit", "/api/admin/users", "/api/health/details", "/api/auth/login-history"] {
+            assert_eq!(app.call(download(&format!("{}?dt={}", other, dt))).await.status(), 401, "{}", other);
+        }
+
+        let response = app.call(download(&format!("{}&format=json", url))).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("content-type").unwrap(), "application/json");
+        // Single use
+        assert_eq!(app.call(download(&url)).await.status(), 401);
+
+        // Good for a minute
+        let url = issue().await["data"]["download_url"].as_str().unwrap().to_string();
+        app.clock.advance(Duration::seconds(61));
+        assert_eq!(app.call(download(&url)).await.status(), 401);
+
+        // Only for what the session could fetch itself
+        let officer_token = app.login_as(&officer, "10.0.0.2").await;
+        let refused = app.call_error(bearer(download_token_request("audit_export"), &officer_token)).await;
+        assert_eq!(refused, (403, "PermissionDenied".to_string()));
+        let unknown = app.call(bearer(download_token_request("backup"), &admin_token)).await;
+        assert_eq!(unknown.status(), 400);
+
+        // And only while the session it was issued on is live
+        let url = issue().await["data"]["download_url"].as_str().unwrap().to_string();
+        assert_eq!(app.call(bearer(TestRequest::post().uri("/api/auth/logout"), &admin_token)).await.status(), 200);
+        assert_eq!(app.call(download(&url)).await.status(), 401);
+
+        // One export made it out, recorded against the administrator
+        let exported: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE event_type = 'AUDIT_EXPORTED' AND user_id = ?")
+            .bind(admin.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(exported, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_sessions_survive_a_backwards_clock_step_within_the_leeway() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("skewed_clock", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+        let verify = || bearer(TestRequest::get().uri("/api/auth/verify"), &token);
+
+        let leeway = SecurityConfig::default().jwt_iat_leeway_seconds;
+        app.clock.advance(Duration::seconds(-leeway));
+        assert_eq!(app.call(verify()).await.status(), 200);
+        assert_eq!(app.auth_service().clock_skew_tolerated(), 1);
+
+        app.clock.advance(Duration::seconds(-1));
+        assert_eq!(app.call(verify()).await.status(), 401);
+        assert_eq!(app.auth_service().clock_skew_tolerated(), 1);
+
+        // A sign-in after the step is issued by the clock as it now reads
+        let fresh = app.login_as(&user, "10.0.0.1").await;
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &fresh)).await.status(), 200);
+        assert_eq!(app.auth_service().clock_skew_tolerated(), 1);
+    }
+}
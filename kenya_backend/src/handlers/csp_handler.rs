@@ -0,0 +1,32 @@
+use actix_web::{web, HttpResponse, Result};
+use futures_util::StreamExt;
+
+use crate::handlers::auth_handler::AppState;
+use crate::models::context::RequestContext;
+use crate::services::csp_report_service::{parse_csp_reports, MAX_CSP_REPORT_BYTES};
+
+/// Browser CSP violation report endpoint.
+///
+/// Unauthenticated, so it always answers 204: oversized, malformed and
+/// unstorable reports are dropped rather than surfaced to the reporter.
+pub async fn csp_report(ctx: RequestContext, mut payload: web::Payload, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let Ok(chunk) = chunk else {
+            return Ok(HttpResponse::NoContent().finish());
+        };
+        if body.len() + chunk.len() > MAX_CSP_REPORT_BYTES {
+            log::debug!("Dropping oversized CSP report from IP: {}", ctx.ip_address);
+            return Ok(HttpResponse::NoContent().finish());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    for violation in parse_csp_reports(&body) {
+        if let Err(e) = data.csp_reports.record(&ctx, &violation).await {
+            log::error!("Failed to store CSP report: {}", e);
+        }
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,253 @@
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, ResponseError, Result};
+use serde_json::json;
+
+use crate::handlers::auth_handler::AppState;
+use crate::models::context::RequestContext;
+use crate::models::integration::{HrEventBatch, HrEventStatus, MAX_HR_EVENTS_PER_BATCH};
+use crate::services::api_key_service::ApiKeyCaller;
+
+/// HR system joiner, leaver and transfer events endpoint.
+///
+/// Events are applied in order, each on its own: one that can't be applied
+/// doesn't hold back the rest. The answer is `200` when every event was
+/// applied or already had been, and `422` with each event's result otherwise.
+pub async fn hr_events(
+    req: HttpRequest,
+    ctx: RequestContext,
+    batch: web::Json<HrEventBatch>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let Some(source) = req.extensions().get::<ApiKeyCaller>().map(|caller| caller.source.clone()) else {
+        return Ok(HttpResponse::Unauthorized().json(json!({
+            "success": false,
+            "message": "A valid API key is required",
+            "error_type": "InvalidApiKey"
+        })));
+    };
+    let events = batch.into_inner().events;
+    if events.is_empty() || events.len() > MAX_HR_EVENTS_PER_BATCH {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": format!("A delivery must carry between 1 and {} events", MAX_HR_EVENTS_PER_BATCH)
+        })));
+    }
+
+    let mut results = Vec::with_capacity(events.len());
+    for event in &events {
+        match data.auth_service.apply_hr_event(&ctx, &source, event).await {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                log::error!("Failed to apply HR event {} from {}: {}", event.event_id, source, e);
+                return Ok(e.error_response());
+            }
+        }
+    }
+
+    let count = |status| results.iter().filter(|result| result.status == status).count();
+    let (applied, duplicates, rejected) = (count(HrEventStatus::Applied), count(HrEventStatus::Duplicate), count(HrEventStatus::Rejected));
+    log::info!("HR delivery from {}: {} applied, {} duplicate, {} rejected", source, applied, duplicates, rejected);
+
+    let message = if rejected == 0 { "Events processed" } else { "Some events were rejected" };
+    let body = json!({
+        "success": rejected == 0,
+        "message": message,
+        "data": {
+            "applied": applied,
+            "duplicates": duplicates,
+            "rejected": rejected,
+            "results": results,
+        }
+    });
+    Ok(if rejected == 0 { HttpResponse::Ok().json(body) } else { HttpResponse::UnprocessableEntity().json(body) })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use chrono::Utc;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use crate::models::user::UserRole;
+    use crate::test_support::{bearer, TestApp, TEST_API_KEY, TEST_API_KEY_SOURCE, TEST_PASSWORD};
+
+    fn deliver(events: Value) -> TestRequest {
+        TestRequest::post()
+            .uri("/api/integrations/hr/events")
+            .insert_header(("X-Api-Key", TEST_API_KEY))
+            .set_json(json!({ "events": events }))
+    }
+
+    fn joined(event_id: &str, username: &str, organization: &str) -> Value {
+        json!({
+            "event_id": event_id,
+            "type": "employee_joined",
+            "username": username,
+            "email": format!("{}@health.go.ke", username),
+            "organization": organization,
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_each_event_type_applies_once_however_often_it_is_delivered() {
+        let app = TestApp::spawn().await;
+        sqlx::query("INSERT INTO org_security_policies (organization, updated_at) VALUES ('Ministry of Health', ?)")
+            .bind(Utc::now())
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let member = app.create_user("agriculture_member", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        app.auth_service().set_user_organization(member.id, Some("Ministry of Agriculture")).await.unwrap();
+
+        let joiner = json!([joined("hr-1", "new_joiner", "Ministry of Health")]);
+        let response = app.call(deliver(joiner.clone())).await;
+        assert_eq!(response.status(), 200);
+        let body: Value = actix_web::test::read_body_json(response).await;
+        assert_eq!(body["data"]["results"][0]["status"], "applied");
+        let user_id = body["data"]["results"][0]["user_id"].clone();
+
+        // Redelivered, the joiner is recognised and nothing is created or sent twice
+        let replay = app.call_json(deliver(joiner)).await;
+        assert_eq!(replay["data"]["results"][0]["status"], "duplicate");
+        assert_eq!(replay["data"]["results"][0]["user_id"], user_id);
+        let user_id = Uuid::parse_str(user_id.as_str().unwrap()).unwrap();
+        let (organization, temporary, verified): (Option<String>, bool, bool) = sqlx::query_as(
+            "SELECT organization, is_temporary_password, email_verified_at IS NOT NULL FROM users WHERE username = 'new_joiner'",
+        )
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!((organization.as_deref(), temporary, verified), (Some("Ministry of Health"), true, true));
+        let invitations: Vec<String> = sqlx::query_scalar("SELECT metadata FROM notifications WHERE user_id = ? AND kind = 'INVITATION'")
+            .bind(user_id)
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(invitations.len(), 1);
+
+        // The invitation link sets the first password
+        let link = serde_json::from_str::<Value>(&invitations[0]).unwrap()["link"].as_str().unwrap().to_string();
+        let token = link.rsplit('=').next().unwrap();
+        let confirm = TestRequest::post().uri("/api/auth/password-reset/confirm").set_json(json!({
+            "token": token,
+            "new_password": "FreshPassw0rd654!",
+            "confirm_password": "FreshPassw0rd654!",
+        }));
+        assert_eq!(app.call(confirm).await.status(), 200);
+        let login = TestRequest::post()
+            .uri("/api/auth/login")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .set_json(json!({ "username": "new_joiner", "password": "FreshPassw0rd654!" }));
+        let session = app.call_json(login).await["data"]["token"].as_str().unwrap().to_string();
+
+        let transfer = json!([{ "event_id": "hr-2", "type": "employee_transferred", "username": "new_joiner", "organization": "Ministry of Agriculture" }]);
+        assert_eq!(app.call_json(deliver(transfer.clone())).await["data"]["results"][0]["status"], "applied");
+        assert_eq!(app.call_json(deliver(transfer)).await["data"]["results"][0]["status"], "duplicate");
+        let organization: Option<String> = sqlx::query_scalar("SELECT organization FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(organization.as_deref(), Some("Ministry of Agriculture"));
+
+        let leaver = json!([{ "event_id": "hr-3", "type": "employee_left", "username": "new_joiner" }]);
+        assert_eq!(app.call_json(deliver(leaver.clone())).await["data"]["results"][0]["status"], "applied");
+        assert_eq!(app.call_json(deliver(leaver)).await["data"]["results"][0]["status"], "duplicate");
+        let active: bool = sqlx::query_scalar("SELECT is_active FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert!(!active);
+        // A deactivated account is refused as such, ahead of its revoked sessions
+        assert_eq!(app.call(bearer(TestRequest::get().uri("/api/auth/verify"), &session)).await.status(), 403);
+
+        // Each change audited once, under the source's tag
+        let audited: Vec<(String, String)> = sqlx::query_as(
+            "SELECT event_type, metadata FROM security_events WHERE event_type LIKE 'HR_%' ORDER BY rowid",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        let types: Vec<&str> = audited.iter().map(|(event_type, _)| event_type.as_str()).collect();
+        assert_eq!(types, ["HR_ACCOUNT_PROVISIONED", "HR_ACCOUNT_TRANSFERRED", "HR_ACCOUNT_DEPROVISIONED"]);
+        for (_, metadata) in &audited {
+            assert_eq!(serde_json::from_str::<Value>(metadata).unwrap()["source"], TEST_API_KEY_SOURCE);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_events_that_cannot_apply_are_reported_per_event() {
+        let app = TestApp::spawn().await;
+        let existing = app.create_user("existing_staff", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        app.auth_service().set_user_organization(existing.id, Some("Ministry of Agriculture")).await.unwrap();
+
+        let events = json!([
+            joined("hr-10", "newcomer", "Ministry of Tourism"),
+            joined("hr-11", "existing_staff", "Ministry of Agriculture"),
+            { "event_id": "hr-12", "type": "employee_left", "username": "nobody_here" },
+            { "event_id": "hr-13", "type": "employee_transferred", "username": "existing_staff", "organization": "Ministry of Tourism" },
+            { "event_id": "hr-14", "type": "employee_joined", "username": "bad_email", "email": "not-an-address", "organization": "Ministry of Agriculture" },
+            joined("hr-15", "second_joiner", "Ministry of Agriculture"),
+        ]);
+        let response = app.call(deliver(events)).await;
+        assert_eq!(response.status(), 422);
+        let body: Value = actix_web::test::read_body_json(response).await;
+        let outcomes: Vec<(&str, &str)> = body["data"]["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| (result["status"].as_str().unwrap(), result["error_type"].as_str().unwrap_or("")))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("rejected", "UnknownOrganization"),
+                ("rejected", "UsernameTaken"),
+                ("rejected", "UnknownUser"),
+                ("rejected", "UnknownOrganization"),
+                ("rejected", "InvalidEvent"),
+                ("applied", ""),
+            ]
+        );
+        assert_eq!((body["data"]["applied"].as_u64(), body["data"]["rejected"].as_u64()), (Some(1), Some(5)));
+        let rejected: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE event_type = 'HR_EVENT_REJECTED'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(rejected, 5);
+
+        // A rejected event isn't recorded, so it applies once its organization exists
+        sqlx::query("INSERT INTO org_security_policies (organization, updated_at) VALUES ('Ministry of Tourism', ?)")
+            .bind(Utc::now())
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let retry = app.call(deliver(json!([joined("hr-10", "newcomer", "Ministry of Tourism")]))).await;
+        assert_eq!(retry.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_events_need_the_hr_api_key() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("hr_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let session = app.login_as(&admin, "10.0.0.1").await;
+        let events = json!({ "events": [joined("hr-20", "sneaky", "Ministry of Agriculture")] });
+
+        let unsigned = TestRequest::post().uri("/api/integrations/hr/events").set_json(&events);
+        let wrong_key = TestRequest::post()
+            .uri("/api/integrations/hr/events")
+            .insert_header(("X-Api-Key", "not-the-key"))
+            .set_json(&events);
+        let session_only = bearer(TestRequest::post().uri("/api/integrations/hr/events"), &session).set_json(&events);
+        for request in [unsigned, wrong_key, session_only] {
+            let body = app.call_json(request).await;
+            assert_eq!(body["error_type"], "InvalidApiKey");
+        }
+        let created: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE username = 'sneaky'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(created, 0);
+    }
+}
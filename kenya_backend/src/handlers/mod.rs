@@ -1 +1,6 @@
-pub mod auth_handler;
\ No newline at end of file
+pub mod auth_handler;
+pub mod admin_handler;
+pub mod csp_handler;
+pub mod system_message_handler;
+pub mod well_known_handler;
+pub mod integration_handler;
@@ -0,0 +1,35 @@
+use actix_web::http::header;
+use actix_web::{web, HttpResponse, ResponseError, Result};
+use serde_json::json;
+
+use crate::handlers::auth_handler::AppState;
+use crate::models::auth::AuthError;
+use crate::services::system_message_service::SYSTEM_MESSAGES_CACHE_SECONDS;
+
+/// Notices for the login screen endpoint.
+///
+/// Unauthenticated, so only what the login screen shows is returned: no
+/// authors, and nothing outside its window.
+pub async fn active_system_messages(data: web::Data<AppState>) -> Result<HttpResponse> {
+    match data.system_messages.active().await {
+        Ok(messages) => Ok(HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, format!("public, max-age={}", SYSTEM_MESSAGES_CACHE_SECONDS)))
+            .json(json!({
+                "success": true,
+                "data": messages
+                    .iter()
+                    .map(|message| json!({
+                        "id": message.id,
+                        "body": message.body,
+                        "severity": message.severity,
+                        "starts_at": message.starts_at,
+                        "ends_at": message.ends_at,
+                    }))
+                    .collect::<Vec<_>>()
+            }))),
+        Err(e) => {
+            log::error!("Failed to load system messages: {}", e);
+            Ok(AuthError::from(e).error_response())
+        }
+    }
+}
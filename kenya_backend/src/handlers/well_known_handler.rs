@@ -0,0 +1,68 @@
+use actix_web::{http::header, web, HttpResponse, Result};
+
+use crate::handlers::auth_handler::AppState;
+
+/// How long clients and proxies may cache security.txt
+const SECURITY_TXT_MAX_AGE_SECONDS: u32 = 86_400;
+
+/// Content served under `/.well-known`, fixed at startup
+pub struct WellKnown {
+    /// Rendered security.txt; the endpoint answers 404 when none is configured
+    pub security_txt: Option<String>,
+    /// Frontend page password managers are sent to by `/.well-known/change-password`
+    pub change_password_url: String,
+}
+
+/// `GET /.well-known/security.txt` (RFC 9116)
+pub async fn security_txt(data: web::Data<AppState>) -> Result<HttpResponse> {
+    Ok(match &data.well_known.security_txt {
+        Some(body) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .insert_header((header::CACHE_CONTROL, format!("public, max-age={}", SECURITY_TXT_MAX_AGE_SECONDS)))
+            .body(body.clone()),
+        None => HttpResponse::NotFound().finish(),
+    })
+}
+
+/// `GET /.well-known/change-password`: redirect to the frontend's
+/// change-password page (W3C "A Well-Known URL for Changing Passwords")
+pub async fn change_password_redirect(data: web::Data<AppState>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, data.well_known.change_password_url.clone()))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{read_body, TestRequest};
+
+    use crate::test_support::TestApp;
+
+    #[actix_web::test]
+    async fn test_security_txt_is_served_as_plain_text() {
+        let app = TestApp::spawn().await;
+
+        let response = app.call(TestRequest::get().uri("/.well-known/security.txt")).await;
+        assert_eq!(response.status(), 200);
+        let headers = response.headers();
+        assert_eq!(headers.get("content-type").unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(headers.get("cache-control").unwrap(), "public, max-age=86400");
+
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.starts_with("Contact: mailto:security@kenya.fsfvi.ai\n"));
+        assert!(body.contains("\nExpires: "));
+        assert!(body.contains("\nPolicy: https://kenya.fsfvi.ai/security-policy\n"));
+        assert!(body.ends_with("\nPreferred-Languages: en, sw\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_change_password_redirects_to_frontend() {
+        let app = TestApp::spawn().await;
+
+        let response = app.call(TestRequest::get().uri("/.well-known/change-password")).await;
+        assert_eq!(response.status(), 302);
+        assert_eq!(response.headers().get("location").unwrap(), "https://kenya.fsfvi.ai/change-password");
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+    }
+}
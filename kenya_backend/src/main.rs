@@ -1,27 +1,79 @@
+mod admin_cli;
+mod bootstrap;
 mod config;
 mod handlers;
 mod middleware;
 mod models;
 mod services;
 mod utils;
+#[cfg(test)]
+mod test_support;
 
-use actix_cors::Cors;
+use actix_web::body::MessageBody;
+use actix_web::dev::{HttpServiceFactory, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Condition;
 use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
 use env_logger::Env;
-use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
-use std::sync::Mutex;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use crate::bootstrap::AuthServiceBuilder;
 use crate::config::AppConfig;
+use crate::handlers::admin_handler::{
+    acknowledge_audit_event, activate_user, audit_by_ip, audit_summary, confirm_admin_action, config_history, create_backup, deactivate_user, event_stats, export_audit_events,
+    list_feature_flags, set_feature_flags,
+    get_config, get_org_policy, get_user_permissions, health_details, list_audit_events, list_csp_reports, list_org_policies, list_user_sessions, list_users,
+    list_webhook_dead_letters, lock_user, request_admin_action, set_maintenance_mode, set_org_policy, set_user_organization, set_user_permissions, terminate_session,
+    terminate_user_sessions, unlock_user, export_user_data, get_user_detail, list_account_notes, add_account_note, strike_account_note, set_user_tags,
+    issue_password_reset_link, revoke_token, list_system_messages, create_system_message, update_system_message, delete_system_message,
+    jwt_migration_status, security_posture, lockdown_status, request_lockdown, lift_lockdown, ACTION_LINK_SIGN_IN_PAGE,
+    list_client_apps, create_client_app, update_client_app, delete_client_app, list_routes,
+};
 use crate::handlers::auth_handler::{
-    change_password, health_check, login, logout, verify_token, 
-    prepare_two_fa_setup, setup_two_fa, verify_two_fa, disable_two_fa, AppState,
+    change_password, degraded_unavailable, health_check, readiness, lockout_status, login, login_challenge, login_history, logout, session_events,
+    security_checkup, my_alerts, dismiss_my_alert, step_up, terms_status, accept_terms, verify_token, prepare_two_fa_setup, redisplay_two_fa_qr, setup_two_fa, verify_two_fa, disable_two_fa, export_my_data, download_data_export,
+    request_password_reset, confirm_password_reset, reissue_token, start_device_authorization, approve_device_authorization, poll_device_authorization, login_v2, login_two_factor, login_change_password, issue_download_token, AppState,
+};
+use crate::handlers::csp_handler::csp_report;
+use crate::handlers::integration_handler::hr_events;
+use crate::handlers::system_message_handler::active_system_messages;
+use crate::handlers::well_known_handler::{change_password_redirect, security_txt, WellKnown};
+use crate::middleware::admin_reads::{AdminReadAudit, AdminReadSampling};
+use crate::middleware::authorization::{DeclaredRoutesOnly, RouteAccess, RouteGuard, RouteScope, RouteTable};
+use crate::middleware::health_gate::HealthGate;
+use crate::middleware::maintenance::{MaintenanceMode, MaintenanceState};
+use crate::middleware::origin_guard::{
+    check_scopes, CorsPolicy, CorsRejections, CorsScope, OriginGuard, ADMIN_CORS, CLIENT_CORS, CORS_SCOPES, INTEGRATION_CORS, PUBLIC_CORS,
 };
-use crate::middleware::security::{RequestLogging, SecurityHeaders};
-use crate::models::auth::SecurityConfig;
+use crate::middleware::request_context::RequestContextMiddleware;
+use crate::middleware::request_tracing::RequestTracing;
+use crate::middleware::server_timing::ServerTiming;
+use crate::middleware::security::{RateLimiting, RateLimits, RequestLogging, SecurityHeaders};
+use crate::models::context::{install_trusted_proxies, RequestContext};
+use crate::models::permission::Permission;
+use crate::models::security_txt::EXPIRY_WARNING_DAYS;
+use crate::services::api_key_service::{ApiKeyScope, ApiKeys};
+use crate::services::audit_archive_service::AuditArchiveService;
+use crate::services::audit_bundle::AuditSigning;
+use crate::services::backup_service::{sqlite_path, BackupService, ServerLock};
+use crate::services::break_glass_service::BREAK_GLASS_USERNAME;
 use crate::services::{
-    auth_service::AuthService, password_service::PasswordService, token_service::TokenService,
+    audit_service::RAW_IP_PURGE_INTERVAL_SECONDS, client_app_service::ClientRegistry, config_snapshot_service::ConfigSnapshotService,
+    csp_report_service::CspReportService, download_tokens::DownloadResource,
+    feature_flags::FeatureFlags, geoip_service::GeoIpService, health_monitor::{HealthMonitor, PROBE_INTERVAL},
+    lockdown_service::{LockdownService, LOCKDOWN_REFRESH_SECONDS}, password_rules,
+    secret_rotation::{SecretCheck, SecretKind, SecretRotationService},
+    security_posture::SecurityPostureCheck, session_events::HEARTBEAT_INTERVAL, shared_state,
+    system_message_service::SystemMessageService,
+    throttle_state::ThrottleState, tracer::Tracer,
+    webhook_service::WebhookService,
 };
+use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::database::{clock_skew, run_migrations, ReadPool, CLOCK_SKEW_WARNING_SECONDS};
+use crate::utils::schema_check::check_schema;
+use crate::utils::self_test::secret_fingerprint;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -33,118 +85,852 @@ async fn main() -> std::io::Result<()> {
 
     log::info!("🇰🇪 Starting Kenya FSFVI Authentication Server");
 
+    // Break-glass: start with a mismatched schema, serving only health checks
+    let allow_degraded = std::env::args().any(|arg| arg == "--allow-degraded");
+    // Create or re-arm the break-glass account, print its passphrase and exit
+    let provision_break_glass = std::env::args().any(|arg| arg == "--provision-break-glass");
+
     // Load configuration
     let config = AppConfig::from_env();
+    log::info!("Effective configuration: {}", config.redacted_summary());
+
+    // `kenya_backend admin ...` runs one maintenance command instead of the server
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("admin") {
+        return admin_cli::run(&args[2..], &config).await;
+    }
+
+    // A malformed origin, or scope rules browsers refuse, would otherwise only
+    // surface as browsers failing opaquely
+    let cors = match check_scopes(&CORS_SCOPES)
+        .and_then(|()| CorsPolicy::new(config.cors_origins.clone(), config.cors_reject_with_json))
+        .and_then(|policy| policy.with_admin_origins(config.cors_admin_origins.clone()))
+    {
+        Ok(policy) => Arc::new(policy),
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
 
     // Initialize database
-    let database_url = config.database_url;
+    let database_url = config.database_url.clone();
     log::info!("Connecting to database: {}", database_url);
 
+    // Held for the life of the process so `admin restore` can't swap the file underneath us
+    let _server_lock = match sqlite_path(&database_url) {
+        Some(path) => Some(ServerLock::shared(&path).map_err(|e| std::io::Error::other(e.to_string()))?),
+        None => None,
+    };
+
     let db_pool = SqlitePoolOptions::new()
         .max_connections(10)
         .connect(&database_url)
         .await
         .expect("Failed to connect to database");
 
-    // Run migrations
+    // Run migrations, then make sure the schema is the one this binary was built for
     log::info!("Running database migrations...");
-    run_initial_migration(&db_pool)
-        .await
-        .expect("Failed to run migrations");
+    let schema_result = match run_migrations(&db_pool).await {
+        Ok(()) => check_schema(&db_pool).await.map_err(|e| e.to_string()),
+        Err(e) => Err(format!("Failed to run migrations: {}", e)),
+    };
+    let degraded = match schema_result {
+        Ok(()) => None,
+        Err(reason) if allow_degraded => {
+            log::error!("{}", reason);
+            log::warn!("Starting in degraded mode (--allow-degraded): auth and admin endpoints are disabled");
+            Some(reason)
+        }
+        Err(reason) => {
+            log::error!("{}", reason);
+            return Err(std::io::Error::other(format!(
+                "{}. Run the matching release, or start with --allow-degraded to serve health checks only",
+                reason
+            )));
+        }
+    };
+
+    // Listings, dashboard counts and exports read from the replica when one
+    // is configured. It connects lazily, so a replica that is down at startup
+    // only sends those reads to the primary until it comes back.
+    let read_pool = match &config.database_read_url {
+        Some(read_url) => match SqlitePoolOptions::new().max_connections(10).connect_lazy(read_url) {
+            Ok(replica) => {
+                log::info!("Sending listing, dashboard and export reads to the read replica");
+                ReadPool::with_replica(db_pool.clone(), replica)
+            }
+            Err(e) => {
+                log::warn!("DATABASE_READ_URL is not usable ({}), reading from the primary", e);
+                ReadPool::primary_only(db_pool.clone())
+            }
+        },
+        None => ReadPool::primary_only(db_pool.clone()),
+    };
 
     // Initialize services
-    let security_config = SecurityConfig {
-        jwt_secret: config.jwt_secret,
-        jwt_expiration_hours: 8, // 8 hours
-        password_salt_rounds: 12,
-        session_timeout_minutes: 30,
-        require_password_change: true,
-        ..Default::default()
+    let security_config = config.security_config();
+
+    log::info!("Effective security configuration: {}", security_config.redacted_summary());
+    let jwt_secret_fingerprint = secret_fingerprint(&security_config.jwt_secret);
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
+    // The previous JWT secret only serves a migration, so it may not stay
+    // configured past the deadline that retires it
+    match config.jwt_migration_deadline(clock.now()) {
+        Ok(Some(deadline)) => log::warn!(
+            "JWT secret migration under way: tokens signed with the previous key are accepted until {}",
+            deadline.to_rfc3339()
+        ),
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Invalid JWT migration configuration: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    }
+
+    // Expiries and windows are decided by this process's clock alone, but a
+    // host that disagrees with the database points at a clock worth fixing
+    match clock_skew(&db_pool, clock.as_ref()).await {
+        Ok(skew) if skew.num_seconds().abs() > CLOCK_SKEW_WARNING_SECONDS => log::warn!(
+            "Application clock is {}ms {} the database's; check NTP on this host",
+            skew.num_milliseconds().abs(),
+            if skew.num_milliseconds() > 0 { "ahead of" } else { "behind" }
+        ),
+        Ok(_) => {}
+        Err(e) => log::warn!("Could not compare the application clock with the database's: {}", e),
+    }
+
+    // A changed JWT secret needs a stage confirmed by a second administrator;
+    // a production server refuses to start with one that has none
+    if degraded.is_none() {
+        let rotation = SecretRotationService::new(db_pool.clone(), clock.clone());
+        match rotation.check_configured(SecretKind::Jwt, &config.jwt_secret, config.production).await {
+            Ok(SecretCheck::Unchanged) => {}
+            Ok(SecretCheck::Adopted) => log::info!("Recorded JWT secret {} as the one in use", jwt_secret_fingerprint),
+            Ok(SecretCheck::Activated(stage)) => log::warn!(
+                "Started with the new JWT secret {}, staged and confirmed by two administrators",
+                stage.short_fingerprint()
+            ),
+            Ok(SecretCheck::Unguarded) => log::warn!(
+                "JWT_SECRET changed to {} without a confirmed stage; a production server would refuse to start",
+                jwt_secret_fingerprint
+            ),
+            Err(e) => {
+                log::error!("{}", e);
+                return Err(std::io::Error::other(e.to_string()));
+            }
+        }
+    }
+
+    // A stale security.txt misleads researchers, so an expired one stops startup
+    let security_txt = match config.security_txt(clock.now()) {
+        Ok(security_txt) => security_txt,
+        Err(e) => {
+            log::error!("Invalid security.txt configuration: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    match &security_txt {
+        Some(file) if file.expires_within(clock.now(), EXPIRY_WARNING_DAYS) => {
+            log::warn!("security.txt expires at {}; renew SECURITY_TXT_EXPIRES", file.expires.to_rfc3339());
+        }
+        Some(_) => {}
+        None => log::info!("SECURITY_CONTACT not set; /.well-known/security.txt is not served"),
+    }
+
+    // GeoIP enrichment is optional; a missing or unreadable database only disables it
+    let geoip = match GeoIpService::open(
+        config.geoip_city_db_path.as_deref(),
+        config.geoip_asn_db_path.as_deref(),
+        config.geoip_allowed_countries.clone(),
+    ) {
+        Ok(geoip) => geoip.with_max_travel_speed(config.impossible_travel_max_kmh),
+        Err(e) => {
+            log::warn!("GeoIP database could not be loaded, enrichment disabled: {}", e);
+            GeoIpService::disabled()
+        }
+    };
+    let geoip = geoip.with_privacy_mode(config.geoip_privacy_mode);
+    if geoip.is_enabled() {
+        log::info!("GeoIP enrichment enabled");
+    }
+    if geoip.privacy_mode() {
+        log::info!(
+            "GeoIP privacy mode: storing truncated addresses and coarse locations, full addresses for {} hours",
+            config.raw_ip_retention_hours
+        );
+    }
+
+    // Failure counters every instance shares, in Redis when REDIS_URL is set
+    let shared_state = match shared_state::connect(config.redis_url.as_deref()).await {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        }
     };
+    log::info!("Shared state backend: {}", shared_state.name());
+    if config.instance_count > 1 {
+        if config.redis_url.is_none() {
+            log::warn!(
+                "INSTANCE_COUNT={} without REDIS_URL: login failure counters, blocks and token guessing detection are per instance, \
+                 so each instance allows the full budget",
+                config.instance_count
+            );
+        }
+        // Neither is shared through the backend, whichever it is
+        log::warn!(
+            "INSTANCE_COUNT={}: per-minute request quotas are per instance, and session event streams need sticky sessions \
+             (a revocation on another instance reaches an open stream at its next liveness check, not at once)",
+            config.instance_count
+        );
+    }
+
+    // Forwarding headers name the client only when one of these proxies sent them
+    match config.trusted_proxies() {
+        Ok(proxies) => {
+            if proxies.is_empty() {
+                log::info!("No TRUSTED_PROXIES: clients are identified by their connection, X-Forwarded-For is ignored");
+            }
+            install_trusted_proxies(proxies);
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    }
+
+    // Monitoring probes and break-glass workstations that failed logins don't lock out
+    let lockout_exemptions = match config.lockout_exemptions() {
+        Ok(exemptions) => Arc::new(exemptions),
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    if !lockout_exemptions.is_empty() {
+        let networks = lockout_exemptions.networks().iter().map(ToString::to_string).collect::<Vec<_>>();
+        log::warn!(
+            "Lockout exemptions: accounts {:?} (while tagged monitoring), from networks {:?} (anywhere when none)",
+            lockout_exemptions.usernames(),
+            networks
+        );
+    }
+
+    // One set of failure counters for the middleware and the login path
+    let throttle = Arc::new(
+        ThrottleState::default()
+            .with_backend(shared_state.clone())
+            .with_failure_delay(config.failure_delay()),
+    );
+
+    let webhooks = Arc::new(WebhookService::new(
+        db_pool.clone(),
+        config.webhook_destinations.clone(),
+        config.webhook_retry_policy(),
+        clock.clone(),
+    ));
+    for destination in &config.webhook_destinations {
+        log::info!("Webhook destination {} -> {}", destination.name, destination.url);
+    }
+    for key in &config.api_keys {
+        let scopes = key.scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>();
+        log::info!("API key {} allowed {:?}", key.name, scopes);
+    }
 
-    let password_service = PasswordService::new();
-    let token_service = TokenService::new(security_config);
-    let auth_service = AuthService::new(db_pool.clone(), password_service, token_service);
+    // Flags start from the configured defaults and follow admin overrides without a restart
+    let features = Arc::new(FeatureFlags::new(db_pool.clone(), clock.clone(), config.feature_defaults));
+    if degraded.is_none() {
+        if let Err(e) = features.refresh().await {
+            log::error!("Failed to load feature flags, using the configured defaults: {}", e);
+        }
+        features.clone().spawn_refresh();
+    }
+
+    // Lockdowns are shared through the database; each instance keeps the current one in memory
+    let lockdown = Arc::new(
+        LockdownService::new(db_pool.clone(), clock.clone()).with_default_minutes(config.lockdown_duration_minutes),
+    );
+    if degraded.is_none() {
+        if let Err(e) = lockdown.refresh().await {
+            log::error!("Failed to load the lockdown state: {}", e);
+        }
+    }
+
+    // Client apps follow admin changes without a restart, like the feature flags
+    let clients = Arc::new(ClientRegistry::new(db_pool.clone(), clock.clone()));
+    if degraded.is_none() {
+        if let Err(e) = clients.refresh().await {
+            log::error!("Failed to load client apps; only the default app is available: {}", e);
+        }
+        clients.clone().spawn_refresh();
+    }
+
+    // Settings that weaken the deployment are logged, and can keep a production server from starting
+    let posture = SecurityPostureCheck::new(config.clone());
+    let findings = posture.run(&features);
+    if let Some(reason) = posture.blocking_reason(&findings) {
+        log::error!("{}", reason);
+        return Err(std::io::Error::other(reason));
+    }
+
+    let auth_service = AuthServiceBuilder::new(db_pool.clone(), &config)
+        .with_clock(clock)
+        .with_read_pool(read_pool)
+        .with_geoip(Arc::new(geoip))
+        .with_shared_state(shared_state)
+        .with_throttle_state(throttle.clone())
+        .with_lockout_exemptions(lockout_exemptions)
+        .with_notifier(webhooks.clone())
+        .with_feature_flags(features.clone())
+        .with_lockdown(lockdown.clone())
+        .with_client_registry(clients.clone())
+        .build();
+
+    // Request bodies are checked against the policy the password service enforces
+    password_rules::install(auth_service.password_policy().clone());
+
+    // Refuse to serve traffic if any crypto primitive misbehaves
+    log::info!("Running startup crypto self-test...");
+    if let Err(e) = auth_service.crypto_self_test() {
+        log::error!("Startup self-test failed: {}", e);
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    log::info!("Startup crypto self-test passed (JWT secret fingerprint: {})", jwt_secret_fingerprint);
+
+    if provision_break_glass {
+        if let Some(reason) = &degraded {
+            return Err(std::io::Error::other(format!("Cannot provision break-glass access: {}", reason)));
+        }
+        let ctx = RequestContext::new("cli", None);
+        let passphrase = auth_service
+            .provision_break_glass(&ctx)
+            .await
+            .map_err(|e| std::io::Error::other(format!("Failed to provision break-glass access: {}", e)))?;
+
+        log::warn!("Break-glass credential provisioned for user: {}", BREAK_GLASS_USERNAME);
+        println!("Break-glass username:   {}", BREAK_GLASS_USERNAME);
+        println!("Break-glass passphrase: {}", passphrase);
+        println!("The passphrase works once, and only while BREAK_GLASS_ENABLED=true. Store it offline.");
+        return Ok(());
+    }
+    if config.break_glass_enabled {
+        log::warn!("Break-glass sign-in is ACTIVATED (BREAK_GLASS_ENABLED); unset it once the emergency is over");
+    }
 
     // Initialize default government user if none exists
     log::info!("Initializing default user if needed...");
-    if let Err(e) = auth_service.initialize_default_user().await {
-        log::error!("Failed to initialize default user: {}", e);
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to initialize default user: {}", e),
-        ));
+    if degraded.is_some() {
+        log::warn!("Skipping default user initialization in degraded mode");
+    } else {
+        match auth_service.initialize_default_user().await {
+            Ok(Some(temp_password)) => {
+                log::warn!("Default user created with temporary password: {}", temp_password);
+                log::warn!("IMPORTANT: Change this password immediately after first login!");
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("Failed to initialize default user: {}", e);
+                return Err(std::io::Error::other(format!(
+                    "Failed to initialize default user: {}",
+                    e
+                )));
+            }
+        }
+    }
+    if degraded.is_none() {
+        match auth_service.backfill_two_fa_fingerprints().await {
+            Ok(0) => {}
+            Ok(count) => log::info!("Fingerprinted {} existing 2FA enrolments", count),
+            Err(e) => log::error!("Failed to fingerprint existing 2FA enrolments: {}", e),
+        }
+    }
+
+    // Keep a history of the effective configuration so behaviour changes can be traced
+    let config_snapshots = ConfigSnapshotService::new(db_pool.clone(), Arc::new(SystemClock), config.snapshot());
+    if degraded.is_none() {
+        match config_snapshots.record_current().await {
+            Ok(true) => log::info!("Configuration changed since the last startup; snapshot {} recorded", config_snapshots.current().1),
+            Ok(false) => {}
+            Err(e) => log::error!("Failed to record configuration snapshot: {}", e),
+        }
+    }
+
+    // Maintenance mode starts from the env default and is toggled at runtime by admins
+    let maintenance = Arc::new(MaintenanceState::new(config.maintenance_mode));
+    if config.maintenance_mode {
+        log::warn!("Starting in maintenance mode: logins and password changes are disabled");
+    }
+
+    // A configured key that can't be used turns signed exports off, loudly
+    let audit_signing = AuditSigning::load(config.audit_signing_key_path.as_deref());
+    match (&audit_signing, &config.audit_signing_key_path) {
+        (AuditSigning::Ready(signer), _) => {
+            log::info!("Signed audit export bundles enabled (public key {})", signer.public_key())
+        }
+        (AuditSigning::Unavailable(reason), Some(_)) => {
+            log::error!("Signed audit export bundles are disabled: {}", reason)
+        }
+        (AuditSigning::Unavailable(_), None) => {}
+    }
+
+    let backups = BackupService::new(
+        db_pool.clone(),
+        PathBuf::from(&config.backup_dir),
+        config.backup_retention,
+        Arc::new(SystemClock),
+    );
+    if config.backup_interval_minutes > 0 && degraded.is_none() {
+        log::info!(
+            "Backing up to {} every {} minutes, keeping {}",
+            config.backup_dir,
+            config.backup_interval_minutes,
+            config.backup_retention
+        );
+        backups
+            .clone()
+            .spawn_schedule(std::time::Duration::from_secs(config.backup_interval_minutes * 60));
+    }
+
+    let audit_archives = AuditArchiveService::new(db_pool.clone(), PathBuf::from(&config.audit_archive_dir), Arc::new(SystemClock))
+        .with_reads_enabled(config.audit_archive_reads);
+    if config.audit_active_window_days > 0 && degraded.is_none() {
+        log::info!(
+            "Archiving audit rows older than {} days to {} monthly",
+            config.audit_active_window_days,
+            config.audit_archive_dir
+        );
+        audit_archives.clone().spawn_schedule(config.audit_active_window_days);
+    }
+
+    // Database health starts out healthy; the probes below keep it current
+    let health = Arc::new(HealthMonitor::new(Arc::new(SystemClock)).with_webhooks(webhooks.clone()));
+
+    // Requests are traced only while a collector is configured
+    let tracer = Arc::new(Tracer::new(config.otel_exporter_otlp_endpoint.as_deref(), config.otel_traces_sample_rate));
+    if tracer.is_enabled() {
+        log::info!("Exporting request traces over OTLP, sampling {} of new traces", config.otel_traces_sample_rate);
+        tracer.clone().spawn_export();
     }
 
     // Create application state
     let app_state = web::Data::new(AppState {
-        auth_service: Mutex::new(auth_service),
+        auth_service,
+        maintenance: maintenance.clone(),
+        throttle: throttle.clone(),
+        csp_reports: CspReportService::new(db_pool.clone()),
+        system_messages: SystemMessageService::new(db_pool.clone(), Arc::new(SystemClock)),
+        cors_rejections: Arc::new(CorsRejections::default()),
+        degraded: degraded.clone(),
+        well_known: WellKnown {
+            security_txt: security_txt.map(|file| file.render()),
+            change_password_url: config.frontend_change_password_url.clone(),
+        },
+        backups,
+        audit_archives,
+        webhooks,
+        config_snapshots,
+        features,
+        audit_signing,
+        admin_reads: AdminReadSampling::new(config.admin_read_sample_rate),
+        health,
+        posture,
+        lockdown,
+        clients,
+        api_keys: ApiKeys::new(&config.api_keys),
+        tracer,
+        server_timing: config.server_timing_enabled,
     });
 
+    // Probe the database every few seconds; while it is down, API requests get 503 at once
+    if degraded.is_none() {
+        let health_state = app_state.clone();
+        let probe_pool = db_pool.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(PROBE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                health_state.health.check(&probe_pool, health_state.auth_service.audit_service()).await;
+            }
+        });
+    }
+
+    // Failed sign-in digests whose accounts have gone quiet are sent once a minute
+    let digest_state = app_state.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            let sent = digest_state.auth_service.flush_quiet_failed_login_digests().await;
+            if sent > 0 {
+                log::info!("Sent {} failed sign-in digests", sent);
+            }
+        }
+    });
+
+    // Event streams whose client stopped reading, even the keep-alives, are
+    // closed within 30 seconds
+    let reap_state = app_state.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let reaped = reap_state.auth_service.reap_idle_event_streams();
+            if reaped > 0 {
+                log::info!("Closed {} event streams whose clients stopped reading", reaped);
+            }
+        }
+    });
+
+    // Critical notifications a full queue couldn't hold go back into it once there is room
+    if degraded.is_none() {
+        let pending_webhooks = app_state.webhooks.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                ticker.tick().await;
+                match pending_webhooks.retry_pending().await {
+                    Ok(0) => {}
+                    Ok(moved) => log::info!("Queued {} pending critical notifications again", moved),
+                    Err(e) => log::error!("Failed to retry pending notifications: {}", e),
+                }
+            }
+        });
+    }
+
+    // Full client addresses kept by privacy mode are cleared once their retention
+    // is up; runs whatever the mode, so turning it off doesn't strand any
+    if degraded.is_none() {
+        let purge_state = app_state.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(RAW_IP_PURGE_INTERVAL_SECONDS));
+            loop {
+                ticker.tick().await;
+                match purge_state.auth_service.audit_service().purge_raw_ips().await {
+                    Ok(0) => {}
+                    Ok(purged) => log::info!("Cleared full client addresses from {} rows", purged),
+                    Err(e) => log::error!("Failed to purge full client addresses: {}", e),
+                }
+            }
+        });
+    }
+
+    // Lockdowns started or lifted on other instances apply here within seconds,
+    // and one whose time is up is ended
+    if degraded.is_none() {
+        let lockdown_state = app_state.clone();
+        tokio::spawn(async move {
+            let ctx = RequestContext::new("system", None);
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(LOCKDOWN_REFRESH_SECONDS));
+            loop {
+                ticker.tick().await;
+                if let Err(e) = lockdown_state.auth_service.end_expired_lockdowns(&ctx).await {
+                    log::error!("Failed to refresh the lockdown state: {}", e);
+                }
+            }
+        });
+    }
+
+    // Quotas are shared across workers; token verification polling and CSP reports get their own buckets
+    let rate_limits = Arc::new(
+        RateLimits::new(config.rate_limit_per_minute)
+            .with_bucket("/api/auth/verify", config.verify_rate_limit_per_minute)
+            .with_bucket("/api/csp-report", config.csp_report_rate_limit_per_minute)
+            .with_soft_warnings(config.rate_limit_soft_warnings),
+    );
+
+    // Every route declares its access, and none may declare less than its
+    // scope demands; a production server refuses to start otherwise
+    let layers = ScopeLayers::new(&app_state, rate_limits.clone(), cors.clone());
+    let (_, routes) = api_scope(app_state.degraded.is_none(), &layers);
+    let route_findings = routes.findings();
+    for finding in &route_findings {
+        log::error!("Route inventory: {}", finding);
+    }
+    if config.production && !route_findings.is_empty() {
+        return Err(std::io::Error::other(format!(
+            "{} route(s) are less protected than their scope requires; see GET /api/admin/routes",
+            route_findings.len()
+        )));
+    }
+
     // Get server configuration from config
     let host = config.host;
     let port = config.port;
 
     log::info!("🚀 Server starting on {}:{}", host, port);
     log::info!("🔒 Security features enabled:");
-    log::info!("   ✓ JWT authentication with 8-hour expiration");
+    log::info!("   ✓ JWT authentication with {}-hour expiration", config.jwt_expiration_hours);
     log::info!("   ✓ Argon2 password hashing");
     log::info!("   ✓ Rate limiting and security headers");
     log::info!("   ✓ Comprehensive audit logging");
-    log::info!("   ✓ Session management with 30-minute timeout");
-    log::info!("   ✓ Account lockout after 5 failed attempts");
+    log::info!("   ✓ Session management with {}-minute timeout", config.session_timeout_minutes);
+    log::info!("   ✓ Account lockout after {} failed attempts", config.max_failed_login_attempts);
+    log::info!("   ✓ Up to {} concurrent password verifications during login", config.login_concurrency);
 
     // Start HTTP server
-    let cors_origins = config.cors_origins.clone();
-    HttpServer::new(move || {
-        // CORS configuration - restrict to Kenya frontend only
-        let mut cors = Cors::default();
-        for origin in &cors_origins {
-            cors = cors.allowed_origin(origin);
-        }
-        let cors = cors
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
-            .allowed_headers(vec!["Authorization", "Content-Type", "X-Requested-With"])
-            .max_age(3600)
-            .supports_credentials();
-
-        App::new()
-            .app_data(app_state.clone())
-            .wrap(cors)
-            .wrap(SecurityHeaders)
-            .wrap(RequestLogging)
-            .service(
-                web::scope("/api")
-                    .service(
-                        web::scope("/auth")
-                            .route("/login", web::post().to(login))
-                            .route("/change-password", web::post().to(change_password))
-                            .route("/verify", web::get().to(verify_token))
-                            .route("/logout", web::post().to(logout))
-                            .route("/2fa/prepare", web::get().to(prepare_two_fa_setup))
-                            .route("/2fa/setup", web::post().to(setup_two_fa))
-                            .route("/2fa/verify", web::post().to(verify_two_fa))
-                            .route("/2fa/disable", web::post().to(disable_two_fa)),
-                    )
-                    .route("/health", web::get().to(health_check)),
-            )
-    })
-    .bind((host, port))?
-    .run()
-    .await
+    HttpServer::new(move || build_app(app_state.clone(), rate_limits.clone(), cors.clone()))
+        .bind((host, port))?
+        .run()
+        .await
 }
 
-// Utility functions for database initialization
-async fn run_initial_migration(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    let migration_sql = include_str!("../migrations/001_initial.sql");
+/// What each API scope's middleware is built from
+struct ScopeLayers {
+    maintenance: Arc<MaintenanceState>,
+    health: Arc<HealthMonitor>,
+    rate_limits: Arc<RateLimits>,
+    throttle: Arc<ThrottleState>,
+    clients: Arc<ClientRegistry>,
+    cors: Arc<CorsPolicy>,
+    cors_rejections: Arc<CorsRejections>,
+}
 
-    // Split the SQL into individual statements and execute them
-    for statement in migration_sql.split(';') {
-        let statement = statement.trim();
-        if !statement.is_empty() {
-            sqlx::query(statement).execute(pool).await?;
+impl ScopeLayers {
+    fn new(app_state: &AppState, rate_limits: Arc<RateLimits>, cors: Arc<CorsPolicy>) -> Self {
+        Self {
+            maintenance: app_state.maintenance.clone(),
+            health: app_state.health.clone(),
+            rate_limits,
+            throttle: app_state.throttle.clone(),
+            clients: app_state.clients.clone(),
+            // Origins of registered client apps are allowed alongside the configured ones
+            cors: Arc::new(cors.as_ref().clone().with_clients(app_state.clients.clone())),
+            cors_rejections: app_state.cors_rejections.clone(),
         }
     }
+}
+
+/// Wrap a scope in the middleware every API scope runs behind, innermost
+/// first: its route guard, maintenance, the health gate, rate limits, then
+/// CORS with the scope's own rules and the guard that reports rejections.
+/// CORS stays outside the rest so browsers can read their 401s and 429s.
+macro_rules! layered {
+    ($scope:expr, $guard:expr, $layers:expr, $cors:expr) => {
+        $scope
+            .wrap($guard)
+            .wrap(MaintenanceMode::new($layers.maintenance.clone()))
+            .wrap(HealthGate::new($layers.health.clone()))
+            .wrap(RateLimiting::new($layers.rate_limits.clone(), $layers.throttle.clone()).with_clients($layers.clients.clone()))
+            .wrap($layers.cors.cors($cors))
+            .wrap(OriginGuard::new($layers.cors.clone(), $cors, $layers.cors_rejections.clone()))
+    };
+}
+
+/// The full application: middleware chain and routes. Shared with the test
+/// harness so tests exercise the same stack the server runs.
+fn build_app(
+    app_state: web::Data<AppState>,
+    rate_limits: Arc<RateLimits>,
+    cors: Arc<CorsPolicy>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let serve_auth = app_state.degraded.is_none();
+    let tracer = app_state.tracer.clone();
+    let server_timing = app_state.server_timing;
+    let layers = ScopeLayers::new(&app_state, rate_limits, cors);
+    let (api, _) = api_scope(serve_auth, &layers);
+
+    App::new()
+        .app_data(app_state)
+        // Route guards, maintenance, health, rate limits and CORS wrap each scope; see `layered!`
+        .wrap(SecurityHeaders)
+        .wrap(RequestLogging)
+        .wrap(Condition::new(server_timing, ServerTiming))
+        .wrap(Condition::new(tracer.is_enabled(), RequestTracing::new(tracer)))
+        .wrap(RequestContextMiddleware)
+        .service(api)
+        .service(layered!(
+            web::scope("/.well-known")
+                .route("/security.txt", web::get().to(security_txt))
+                .route("/change-password", web::get().to(change_password_redirect)),
+            RouteGuard::new(Arc::default()),
+            layers,
+            PUBLIC_CORS
+        ))
+}
+
+/// The `/api` scope, and the table of what each of its routes requires of
+/// the caller. Every child scope runs behind its own `layered!` stack, so
+/// each can allow its own methods and origins. The table is also app data,
+/// for `GET /api/admin/routes`.
+fn api_scope(serve_auth: bool, layers: &ScopeLayers) -> (impl HttpServiceFactory, RouteTable) {
+    let mut routes = RouteTable::default();
+    let api = web::scope("/api");
+    let api = if serve_auth {
+        // The detailed report is an admin read; its scope goes ahead of
+        // `/health`, which would otherwise take its requests
+        let details = RouteScope::new("/api", "/health/details")
+            .get("", health_details, RouteAccess::permission(Permission::AuditRead));
+        let csp = RouteScope::new("/api", "/csp-report").post("", csp_report, RouteAccess::public());
+        let messages = RouteScope::new("/api", "/system-messages").get("", active_system_messages, RouteAccess::public());
+
+        api.service(layered_scope(details, &mut routes, layers, ADMIN_CORS))
+            .service(layered_scope(health_routes(), &mut routes, layers, PUBLIC_CORS))
+            .service(layered_scope(csp, &mut routes, layers, CLIENT_CORS))
+            .service(layered_scope(messages, &mut routes, layers, CLIENT_CORS))
+            .service(layered_scope(auth_routes(), &mut routes, layers, CLIENT_CORS))
+            .service(layered_scope(auth_v2_routes(), &mut routes, layers, CLIENT_CORS))
+            .service(admin_routes(&mut routes, layers))
+            .service(layered_scope(integration_routes(), &mut routes, layers, INTEGRATION_CORS))
+    } else {
+        api.service(layered_scope(health_routes(), &mut routes, layers, PUBLIC_CORS))
+            .service(degraded_routes("/auth", layers, CLIENT_CORS))
+            .service(degraded_routes("/admin", layers, ADMIN_CORS))
+            .service(degraded_routes("/integrations", layers, INTEGRATION_CORS))
+    };
+    // Anything registered around `RouteScope` is refused rather than served unguarded
+    let api = api
+        .app_data(web::Data::new(routes.clone()))
+        .wrap(DeclaredRoutesOnly::new(Arc::new(routes.clone())));
+    (api, routes)
+}
+
+/// Register `scope`'s routes in `routes` and put it behind its guard and
+/// the `layered!` stack with the CORS rules `cors`
+fn layered_scope(scope: RouteScope, routes: &mut RouteTable, layers: &ScopeLayers, cors: CorsScope) -> impl HttpServiceFactory {
+    let guard = scope.guard();
+    layered!(scope.register(routes), guard, layers, cors)
+}
+
+/// Liveness and readiness, for load balancers and status pages anywhere
+fn health_routes() -> RouteScope {
+    RouteScope::new("/api", "/health")
+        .get("", health_check, RouteAccess::public())
+        .get("/ready", readiness, RouteAccess::public())
+}
+
+fn auth_routes() -> RouteScope {
+    // Still open on a temporary password or before the terms are accepted,
+    // so the account can get past them
+    let onboarding = RouteAccess::session().allow_temp_password().allow_pending_terms();
+    // The account's own security settings are out of a shared kiosk's reach
+    let own_security = RouteAccess::session().deny_kiosk();
+
+    RouteScope::new("/api", "/auth")
+        .post("/login", login, RouteAccess::public())
+        .post("/change-password", change_password, own_security.allow_temp_password())
+        .get("/verify", verify_token, RouteAccess::own_token())
+        .post("/token/reissue", reissue_token, RouteAccess::own_token())
+        .post("/logout", logout, RouteAccess::own_token())
+        .get("/login-history", login_history, RouteAccess::session())
+        .get("/lockout-status", lockout_status, RouteAccess::public())
+        .post("/password-reset", request_password_reset, RouteAccess::public())
+        .post("/password-reset/confirm", confirm_password_reset, RouteAccess::public())
+        .get("/login-challenge", login_challenge, RouteAccess::public())
+        .get("/events", session_events, RouteAccess::own_token())
+        .post("/step-up", step_up, own_security)
+        .post("/download-token", issue_download_token, own_security)
+        .get("/security-checkup", security_checkup, onboarding)
+        .get("/my-alerts", my_alerts, own_security)
+        .post("/my-alerts/{id}/dismiss", dismiss_my_alert, own_security)
+        .post("/me/data-export", export_my_data, own_security)
+        .get("/data-exports/{token}", download_data_export, RouteAccess::public())
+        .get("/terms", terms_status, onboarding)
+        .post("/terms/accept", accept_terms, onboarding)
+        .get("/2fa/prepare", prepare_two_fa_setup, own_security)
+        .post("/2fa/setup", setup_two_fa, own_security)
+        .post("/2fa/qr", redisplay_two_fa_qr, own_security)
+        .post("/2fa/verify", verify_two_fa, RouteAccess::public())
+        .post("/2fa/disable", disable_two_fa, own_security)
+        .post("/device/start", start_device_authorization, RouteAccess::public())
+        .post("/device/approve", approve_device_authorization, RouteAccess::session().with_step_up())
+        .post("/device/poll", poll_device_authorization, RouteAccess::public())
+}
+
+/// Login split into its password, second-factor and password change steps
+fn auth_v2_routes() -> RouteScope {
+    RouteScope::new("/api", "/v2/auth")
+        .post("/login", login_v2, RouteAccess::public())
+        .post("/login/2fa", login_two_factor, RouteAccess::public())
+        .post("/login/change-password", login_change_password, RouteAccess::public())
+}
+
+/// The admin endpoints, with admin reads sampled into the audit log inside the usual stack
+fn admin_routes(routes: &mut RouteTable, layers: &ScopeLayers) -> impl HttpServiceFactory {
+    let audit_read = RouteAccess::permission(Permission::AuditRead);
+    let audit_export = RouteAccess::permission(Permission::AuditExport);
+    let user_manage = RouteAccess::permission(Permission::UserManage);
+    let session_terminate = RouteAccess::permission(Permission::SessionTerminate).with_step_up();
+    let maintenance_manage = RouteAccess::permission(Permission::MaintenanceManage);
+
+    let scope = RouteScope::new("/api", "/admin")
+        .post("/maintenance", set_maintenance_mode, maintenance_manage)
+        .post("/backup", create_backup, RouteAccess::permission(Permission::BackupManage).with_step_up())
+        .get("/users", list_users, user_manage)
+        .get("/users/{id}", get_user_detail, user_manage)
+        .get("/users/{id}/notes", list_account_notes, user_manage)
+        .post("/users/{id}/notes", add_account_note, user_manage)
+        .post("/users/{id}/notes/{note_id}/strike", strike_account_note, user_manage)
+        .put("/users/{id}/tags", set_user_tags, user_manage)
+        .post("/users/{id}/lock", lock_user, user_manage)
+        .post("/users/{id}/unlock", unlock_user, user_manage)
+        .post("/users/{id}/deactivate", deactivate_user, user_manage)
+        .post("/users/{id}/activate", activate_user, user_manage)
+        .get("/users/{id}/permissions", get_user_permissions, user_manage)
+        .put("/users/{id}/permissions", set_user_permissions, user_manage.with_step_up())
+        .put("/users/{id}/organization", set_user_organization, user_manage.with_step_up())
+        .post("/users/{id}/data-export", export_user_data, audit_export)
+        .post("/users/{id}/reset-link", issue_password_reset_link, user_manage.with_step_up())
+        .get("/users/{id}/sessions", list_user_sessions, session_terminate)
+        .delete("/users/{id}/sessions", terminate_user_sessions, session_terminate)
+        .delete("/sessions/{session_id}", terminate_session, session_terminate)
+        .delete("/tokens/{jti}", revoke_token, session_terminate)
+        .get("/lockdown", lockdown_status, audit_read)
+        .post("/lockdown", request_lockdown, session_terminate)
+        .delete("/lockdown", lift_lockdown, session_terminate)
+        .get("/org-policies", list_org_policies, user_manage)
+        .get("/org-policies/{organization}", get_org_policy, user_manage)
+        .put("/org-policies/{organization}", set_org_policy, user_manage.with_step_up())
+        .get("/csp-reports", list_csp_reports, audit_read)
+        .get("/webhooks/dead-letters", list_webhook_dead_letters, audit_read)
+        .post("/actions", request_admin_action, user_manage)
+        .get("/actions/confirm/{token}", confirm_admin_action, user_manage.with_sign_in_page(ACTION_LINK_SIGN_IN_PAGE))
+        .get("/system-messages", list_system_messages, maintenance_manage)
+        .post("/system-messages", create_system_message, maintenance_manage)
+        .put("/system-messages/{id}", update_system_message, maintenance_manage)
+        .delete("/system-messages/{id}", delete_system_message, maintenance_manage)
+        .get("/features", list_feature_flags, maintenance_manage)
+        .put("/features", set_feature_flags, maintenance_manage)
+        .get("/client-apps", list_client_apps, maintenance_manage)
+        .post("/client-apps", create_client_app, maintenance_manage)
+        .put("/client-apps/{id}", update_client_app, maintenance_manage)
+        .delete("/client-apps/{id}", delete_client_app, maintenance_manage)
+        .get("/config", get_config, audit_read)
+        .get("/config/history", config_history, audit_read)
+        .get("/jwt-migration", jwt_migration_status, audit_read)
+        .get("/security-posture", security_posture, audit_read)
+        .get("/routes", list_routes, audit_read)
+        .get("/audit", list_audit_events, audit_read)
+        .get("/audit/summary", audit_summary, audit_read)
+        .get("/audit/export", export_audit_events, audit_export.with_download_token(DownloadResource::AuditExport))
+        .get("/audit/by-ip/{ip}", audit_by_ip, audit_read)
+        .post("/audit/{id}/acknowledge", acknowledge_audit_event, audit_read)
+        .get("/stats/events", event_stats, audit_read);
+    let guard = scope.guard();
+    layered!(scope.register(routes).wrap(AdminReadAudit), guard, layers, ADMIN_CORS)
+}
+
+/// Endpoints other systems call with an API key instead of a session
+fn integration_routes() -> RouteScope {
+    RouteScope::new("/api", "/integrations").post("/hr/events", hr_events, RouteAccess::api_key(ApiKeyScope::HrEvents))
+}
 
-    Ok(())
+/// Every auth, admin and integration path answers 503 while the schema
+/// doesn't match, under the CORS rules the scope would normally have
+fn degraded_routes(path: &str, layers: &ScopeLayers, cors: CorsScope) -> impl HttpServiceFactory {
+    layered!(
+        web::scope(path).default_service(web::to(degraded_unavailable)),
+        RouteGuard::new(Arc::default()),
+        layers,
+        cors
+    )
 }
@@ -0,0 +1,221 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web, Error, HttpMessage, HttpRequest,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::{json, Map, Value};
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use uuid::Uuid;
+
+use crate::handlers::auth_handler::AppState;
+use crate::models::audit_event::AuditEventType;
+use crate::models::context::RequestContext;
+use crate::services::download_tokens::DOWNLOAD_TOKEN_PARAM;
+use crate::utils::sanitize;
+
+/// Audit one in this many reads of a dashboard endpoint
+pub const DEFAULT_DASHBOARD_SAMPLE_RATE: u64 = 20;
+
+/// Dashboard endpoints polled often enough that their reads are sampled
+const DASHBOARD_ENDPOINTS: [&str; 2] = ["/api/admin/audit/summary", "/api/admin/stats/events"];
+
+/// `GET` endpoints under `/admin` that carry out an action rather than show data
+const NOT_READS: [&str; 1] = ["/api/admin/actions/confirm/{token}"];
+
+/// Query parameters whose values never reach the audit log
+const SECRET_PARAMS: [&str; 4] = ["token", "password", "secret", "code"];
+
+const MAX_LOGGED_PARAMS: usize = 20;
+
+/// Who the permission check let through, for the `ADMIN_READ` event of the request
+pub struct PermittedUser {
+    pub id: Uuid,
+    pub username: String,
+}
+
+/// Rows a handler returned, for the `ADMIN_READ` event of its request
+struct ReadRows(usize);
+
+/// Note how many rows the read in `req` returned
+pub fn record_read_rows(req: &HttpRequest, rows: usize) {
+    req.extensions_mut().insert(ReadRows(rows));
+}
+
+/// Which admin reads make it into the audit log: every one, except one in
+/// `dashboard_sample_rate` reads of each dashboard endpoint
+pub struct AdminReadSampling {
+    dashboard_sample_rate: u64,
+    counters: [AtomicU64; DASHBOARD_ENDPOINTS.len()],
+}
+
+impl Default for AdminReadSampling {
+    fn default() -> Self {
+        Self::new(DEFAULT_DASHBOARD_SAMPLE_RATE)
+    }
+}
+
+impl AdminReadSampling {
+    pub fn new(dashboard_sample_rate: u64) -> Self {
+        Self { dashboard_sample_rate: dashboard_sample_rate.max(1), counters: Default::default() }
+    }
+
+    /// Count a read of `endpoint`. Returns the rate it is sampled at when
+    /// this read should be audited.
+    fn sample(&self, endpoint: &str) -> Option<u64> {
+        match DASHBOARD_ENDPOINTS.iter().position(|dashboard| *dashboard == endpoint) {
+            None => Some(1),
+            Some(i) => {
+                let previous = self.counters[i].fetch_add(1, Ordering::Relaxed);
+                previous.is_multiple_of(self.dashboard_sample_rate).then_some(self.dashboard_sample_rate)
+            }
+        }
+    }
+}
+
+/// Admin read audit middleware - records an `ADMIN_READ` event for each
+/// successful `GET` under the admin scope that passed a permission check
+pub struct AdminReadAudit;
+
+impl<S, B> Transform<S, ServiceRequest> for AdminReadAudit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AdminReadAuditMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AdminReadAuditMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AdminReadAuditMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AdminReadAuditMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let is_read = req.method() == Method::GET;
+
+        Box::pin(async move {
+            let res = svc.call(req).await?;
+            if is_read && res.status().is_success() {
+                record_read(res.request()).await;
+            }
+            Ok(res)
+        })
+    }
+}
+
+async fn record_read(request: &HttpRequest) {
+    let Some(data) = request.app_data::<web::Data<AppState>>() else {
+        return;
+    };
+    let endpoint = request.match_pattern().unwrap_or_else(|| request.path().to_string());
+    if NOT_READS.contains(&endpoint.as_str()) {
+        return;
+    }
+    let (admin, rows, ctx) = {
+        let extensions = request.extensions();
+        (
+            extensions.get::<PermittedUser>().map(|user| (user.id, user.username.clone())),
+            extensions.get::<ReadRows>().map(|rows| rows.0),
+            extensions.get::<RequestContext>().cloned(),
+        )
+    };
+    let (Some((admin_id, username)), Some(ctx)) = (admin, ctx) else {
+        return;
+    };
+    let Some(sample_rate) = data.admin_reads.sample(&endpoint) else {
+        return;
+    };
+    let target_user_id = endpoint
+        .starts_with("/api/admin/users/{id}")
+        .then(|| request.match_info().get("id").and_then(|id| Uuid::parse_str(id).ok()))
+        .flatten();
+
+    data.auth_service.audit_service().log_security_event(
+        &ctx,
+        Some(admin_id),
+        AuditEventType::AdminRead,
+        &format!("Admin {} read {}", username, endpoint),
+        true,
+        AuditEventType::AdminRead.default_severity(),
+        Some(json!({
+            "endpoint": endpoint,
+            "query": sanitized_query(request.query_string()),
+            "target_user_id": target_user_id,
+            "rows": rows,
+            "sample_rate": sample_rate,
+        })),
+    ).await.unwrap_or_else(|e| log::error!("Failed to log admin read: {}", e));
+}
+
+/// Query parameters as the audit log keeps them: secret-looking values
+/// replaced, everything else cleaned and cut short
+fn sanitized_query(query_string: &str) -> Value {
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(query_string)
+        .map(|query| query.into_inner())
+        .unwrap_or_default();
+    let params = pairs
+        .into_iter()
+        .take(MAX_LOGGED_PARAMS)
+        .map(|(name, value)| {
+            let name = sanitize::text(&name, 64);
+            let lowered = name.to_ascii_lowercase();
+            let value = if lowered == DOWNLOAD_TOKEN_PARAM || SECRET_PARAMS.iter().any(|secret| lowered.contains(secret)) {
+                "<redacted>".to_string()
+            } else {
+                sanitize::text(&value, 200)
+            };
+            (name, Value::String(value))
+        })
+        .collect::<Map<_, _>>();
+    Value::Object(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_dashboard_reads_are_sampled() {
+        let sampling = AdminReadSampling::new(3);
+        let sampled = (0..7).map(|_| sampling.sample("/api/admin/stats/events")).collect::<Vec<_>>();
+        assert_eq!(sampled, vec![Some(3), None, None, Some(3), None, None, Some(3)]);
+
+        // Each dashboard endpoint keeps its own count
+        assert_eq!(sampling.sample("/api/admin/audit/summary"), Some(3));
+        assert!((0..5).all(|_| sampling.sample("/api/admin/audit") == Some(1)));
+    }
+
+    #[test]
+    fn test_query_is_sanitized() {
+        let query = sanitized_query("limit=10&severity=critical&reset_token=abc123&note=%1B%5B31mred");
+        assert_eq!(query, json!({ "limit": "10", "severity": "critical", "reset_token": "<redacted>", "note": "[31mred" }));
+        assert_eq!(sanitized_query(""), json!({}));
+    }
+}
@@ -0,0 +1,690 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web, Error, FromRequest, Handler, HttpMessage, HttpResponse, Responder, Scope,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::json;
+use std::{
+    fmt,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+};
+use uuid::Uuid;
+
+use crate::handlers::auth_handler::{authorize, AppState};
+use crate::models::permission::Permission;
+use crate::models::user::UserResponse;
+use crate::services::api_key_service::ApiKeyScope;
+use crate::services::download_tokens::DownloadResource;
+
+/// Who may call a route without a session of their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Caller {
+    /// Anyone; the handler decides what an anonymous caller gets
+    Anyone,
+    /// A bearer token the handler checks itself, because it verifies,
+    /// renews or ends the token, or follows its session
+    OwnToken,
+    /// A live session, checked before the handler runs
+    Session,
+    /// An integration's API key in the `X-Api-Key` header, allowed this scope
+    ApiKey(ApiKeyScope),
+}
+
+/// What a route requires of its caller, declared where the route is
+/// registered and enforced by `RouteGuard` before the handler runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteAccess {
+    pub caller: Caller,
+    /// Held by the session's token; also requires 2FA when the caller's organization does
+    pub permission: Option<Permission>,
+    /// The session stepped up through `POST /api/auth/step-up` within the last few minutes
+    pub step_up: bool,
+    /// Open to accounts still on a temporary password
+    pub temp_password: bool,
+    /// Open to accounts yet to accept the current terms of use
+    pub pending_terms: bool,
+    /// Open to sessions signed in on a shared kiosk. Routes that need a
+    /// permission or step-up never are, whatever this says.
+    pub kiosk: bool,
+    /// Served instead of the JSON `401` to callers without an `Authorization` header
+    pub sign_in_page: Option<&'static str>,
+    /// A `?dt=` download token for this resource stands in for the
+    /// `Authorization` header, for downloads a browser opens directly
+    pub download: Option<DownloadResource>,
+}
+
+impl RouteAccess {
+    const fn new(caller: Caller) -> Self {
+        Self {
+            caller,
+            permission: None,
+            step_up: false,
+            temp_password: false,
+            pending_terms: false,
+            kiosk: true,
+            sign_in_page: None,
+            download: None,
+        }
+    }
+
+    pub const fn public() -> Self {
+        Self::new(Caller::Anyone)
+    }
+
+    pub const fn own_token() -> Self {
+        Self::new(Caller::OwnToken)
+    }
+
+    /// Any signed-in account that has changed its temporary password and accepted the terms
+    pub const fn session() -> Self {
+        Self::new(Caller::Session)
+    }
+
+    /// An integration whose API key is allowed `scope`, with no session of its own
+    pub const fn api_key(scope: ApiKeyScope) -> Self {
+        Self::new(Caller::ApiKey(scope))
+    }
+
+    pub const fn permission(permission: Permission) -> Self {
+        Self { permission: Some(permission), ..Self::new(Caller::Session) }
+    }
+
+    pub const fn with_step_up(self) -> Self {
+        Self { step_up: true, ..self }
+    }
+
+    pub const fn allow_temp_password(self) -> Self {
+        Self { temp_password: true, ..self }
+    }
+
+    pub const fn allow_pending_terms(self) -> Self {
+        Self { pending_terms: true, ..self }
+    }
+
+    /// Refuse kiosk sessions, for routes that change the account's security
+    pub const fn deny_kiosk(self) -> Self {
+        Self { kiosk: false, ..self }
+    }
+
+    /// Whether a kiosk session is refused
+    pub const fn refuses_kiosk(self) -> bool {
+        !self.kiosk || self.step_up || self.permission.is_some()
+    }
+
+    pub const fn with_sign_in_page(self, page: &'static str) -> Self {
+        Self { sign_in_page: Some(page), ..self }
+    }
+
+    /// Also accept a download token for `resource`. Never honoured on a
+    /// step-up route, which needs the session token itself.
+    pub const fn with_download_token(self, resource: DownloadResource) -> Self {
+        Self { download: Some(resource), ..self }
+    }
+}
+
+/// One line per route in the inventory, e.g. `permission:user_manage +step_up`
+impl fmt::Display for RouteAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.caller, self.permission) {
+            (Caller::Anyone, _) => f.write_str("public")?,
+            (Caller::OwnToken, _) => f.write_str("own_token")?,
+            (Caller::Session, Some(permission)) => write!(f, "permission:{}", permission.as_str())?,
+            (Caller::Session, None) => f.write_str("session")?,
+            (Caller::ApiKey(scope), _) => write!(f, "api_key:{}", scope.as_str())?,
+        }
+        if self.caller != Caller::Session {
+            return Ok(());
+        }
+        for (flag, set) in [
+            ("+step_up", self.step_up),
+            ("+temp_password", self.temp_password),
+            ("+pending_terms", self.pending_terms),
+            ("-kiosk", !self.kiosk),
+        ] {
+            if set {
+                write!(f, " {}", flag)?;
+            }
+        }
+        if let Some(resource) = self.download {
+            write!(f, " +download:{}", resource)?;
+        }
+        if self.sign_in_page.is_some() {
+            f.write_str(" +sign_in_page")?;
+        }
+        Ok(())
+    }
+}
+
+/// A path prefix, what its routes must require, and the check that they do
+type ScopeMinimum = (&'static str, &'static str, fn(&RouteAccess) -> bool);
+
+/// What every route under a path prefix must at least require. A route
+/// that declares less is reported by `RouteTable::findings`.
+const SCOPE_MINIMUMS: &[ScopeMinimum] = &[
+    ("/api/admin/", "a permission", |access| access.caller == Caller::Session && access.permission.is_some()),
+    ("/api/integrations/", "an API key", |access| matches!(access.caller, Caller::ApiKey(_))),
+];
+
+/// One registered route and what it requires
+#[derive(Debug, Clone)]
+pub struct RouteEntry {
+    pub method: Method,
+    /// Full pattern, as `HttpRequest::match_pattern` reports it
+    pub pattern: String,
+    pub access: RouteAccess,
+}
+
+/// Every route registered through `RouteScope`, in registration order
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    routes: Vec<RouteEntry>,
+}
+
+impl RouteTable {
+    pub fn routes(&self) -> &[RouteEntry] {
+        &self.routes
+    }
+
+    /// Whether any method of `pattern` declares its access
+    pub fn declares(&self, pattern: &str) -> bool {
+        self.routes.iter().any(|route| route.pattern == pattern)
+    }
+
+    /// Declarations that leave a route open to less than its scope demands,
+    /// or that can't be honoured as written. Empty when the table is sound.
+    pub fn findings(&self) -> Vec<String> {
+        let mut findings = Vec::new();
+        for (i, route) in self.routes.iter().enumerate() {
+            let name = format!("{} {}", route.method, route.pattern);
+            if self.routes[..i].iter().any(|other| other.method == route.method && other.pattern == route.pattern) {
+                findings.push(format!("{} is declared twice", name));
+            }
+            for (prefix, minimum, meets) in SCOPE_MINIMUMS {
+                if route.pattern.starts_with(prefix) && !meets(&route.access) {
+                    findings.push(format!("{} must require {} but is {}", name, minimum, route.access));
+                }
+            }
+            if route.access.download.is_some() && route.access.permission.is_none() {
+                findings.push(format!("{} takes download tokens without requiring a permission", name));
+            }
+            if route.access.sign_in_page.is_some() && route.access.caller != Caller::Session {
+                findings.push(format!("{} has a sign-in page but needs no session", name));
+            }
+        }
+        findings
+    }
+
+    pub fn access(&self, method: &Method, pattern: &str) -> Option<RouteAccess> {
+        self.routes
+            .iter()
+            .find(|route| route.method == *method && route.pattern == pattern)
+            .map(|route| route.access)
+    }
+}
+
+/// A `web::scope` whose routes each declare their `RouteAccess`
+pub struct RouteScope {
+    prefix: String,
+    scope: Scope,
+    routes: Vec<RouteEntry>,
+}
+
+impl RouteScope {
+    /// A scope at `path` under `parent`, e.g. `("/api", "/auth")`
+    pub fn new(parent: &str, path: &str) -> Self {
+        Self { prefix: format!("{}{}", parent, path), scope: web::scope(path), routes: Vec::new() }
+    }
+
+    pub fn route<F, Args>(mut self, method: Method, path: &str, handler: F, access: RouteAccess) -> Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.routes.push(RouteEntry { method: method.clone(), pattern: format!("{}{}", self.prefix, path), access });
+        self.scope = self.scope.route(path, web::method(method).to(handler));
+        self
+    }
+
+    pub fn get<F, Args>(self, path: &str, handler: F, access: RouteAccess) -> Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::GET, path, handler, access)
+    }
+
+    pub fn post<F, Args>(self, path: &str, handler: F, access: RouteAccess) -> Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::POST, path, handler, access)
+    }
+
+    pub fn put<F, Args>(self, path: &str, handler: F, access: RouteAccess) -> Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::PUT, path, handler, access)
+    }
+
+    pub fn delete<F, Args>(self, path: &str, handler: F, access: RouteAccess) -> Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::DELETE, path, handler, access)
+    }
+
+    /// A `RouteGuard` over this scope's routes alone, for wrapping the scope
+    /// itself once registered
+    pub fn guard(&self) -> RouteGuard {
+        RouteGuard::new(Arc::new(RouteTable { routes: self.routes.clone() }))
+    }
+
+    /// Add the routes to `table` and hand back the scope for the app
+    pub fn register(self, table: &mut RouteTable) -> Scope {
+        table.routes.extend(self.routes);
+        self.scope
+    }
+}
+
+/// The signed-in caller `RouteGuard` let through to a session route
+pub struct Principal {
+    pub id: Uuid,
+    pub user: UserResponse,
+}
+
+/// Route guard middleware - checks each request against the `RouteAccess`
+/// of the route it matches before the handler runs, and leaves the caller it
+/// let through in the request extensions. Paths outside the table pass untouched.
+pub struct RouteGuard {
+    routes: Arc<RouteTable>,
+}
+
+impl RouteGuard {
+    pub fn new(routes: Arc<RouteTable>) -> Self {
+        Self { routes }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RouteGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RouteGuardMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RouteGuardMiddleware {
+            service: Rc::new(service),
+            routes: self.routes.clone(),
+        }))
+    }
+}
+
+pub struct RouteGuardMiddleware<S> {
+    service: Rc<S>,
+    routes: Arc<RouteTable>,
+}
+
+impl<S, B> Service<ServiceRequest> for RouteGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let access = req
+            .match_pattern()
+            .and_then(|pattern| self.routes.access(req.method(), &pattern));
+
+        Box::pin(async move {
+            if let (Some(access), Some(data)) = (access, req.app_data::<web::Data<AppState>>().cloned()) {
+                match authorize(req.request(), &data, access).await {
+                    Ok(Some(principal)) => {
+                        req.extensions_mut().insert(principal);
+                    }
+                    Ok(None) => {}
+                    Err(response) => return Ok(req.into_response(response).map_into_right_body()),
+                }
+            }
+
+            let res = svc.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+/// Deny-by-default for a whole scope: a request that matched a route the
+/// table declares nothing for is refused and logged, so a route registered
+/// without `RouteScope` never runs unguarded. Requests that matched no
+/// route at all pass on to be answered `404`.
+pub struct DeclaredRoutesOnly {
+    routes: Arc<RouteTable>,
+}
+
+impl DeclaredRoutesOnly {
+    pub fn new(routes: Arc<RouteTable>) -> Self {
+        Self { routes }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for DeclaredRoutesOnly
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = DeclaredRoutesOnlyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DeclaredRoutesOnlyMiddleware { service: Rc::new(service), routes: self.routes.clone() }))
+    }
+}
+
+pub struct DeclaredRoutesOnlyMiddleware<S> {
+    service: Rc<S>,
+    routes: Arc<RouteTable>,
+}
+
+impl<S, B> Service<ServiceRequest> for DeclaredRoutesOnlyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Some(pattern) = req.match_pattern().filter(|pattern| !self.routes.declares(pattern)) {
+            log::error!(
+                "Refused {} {}: the route declares no access; register it through RouteScope",
+                req.method(),
+                pattern
+            );
+            let response = HttpResponse::Forbidden().json(json!({
+                "success": false,
+                "message": "This endpoint is not available",
+                "error_code": "route_not_declared",
+            }));
+            return Box::pin(ready(Ok(req.into_response(response).map_into_right_body())));
+        }
+
+        let svc = self.service.clone();
+        Box::pin(async move { Ok(svc.call(req).await?.map_into_left_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{read_body_json, TestRequest};
+
+    use crate::models::user::UserRole;
+    use crate::test_support::{bearer, TestApp, TEST_PASSWORD};
+
+    /// Who the matrix sends each request as
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum As {
+        Anonymous,
+        TempPassword,
+        Viewer,
+        Admin,
+        ElevatedAdmin,
+        /// A viewer's session on a shared kiosk
+        Kiosk,
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Outcome {
+        /// Reached the handler, whatever it then made of the empty request
+        Through,
+        Unauthorized,
+        Forbidden(String),
+    }
+
+    /// What the guard does with a request from `caller` to a route with `access`
+    fn expected(access: RouteAccess, caller: As) -> Outcome {
+        match (access.caller, caller) {
+            (Caller::Anyone, _) => Outcome::Through,
+            (_, As::Anonymous) => Outcome::Unauthorized,
+            // A bearer token is no API key
+            (Caller::ApiKey(_), _) => Outcome::Unauthorized,
+            (Caller::OwnToken, _) => Outcome::Through,
+            (Caller::Session, As::TempPassword) if !access.temp_password => Outcome::Forbidden("PasswordChangeRequired".to_string()),
+            (Caller::Session, As::Kiosk) if access.refuses_kiosk() => Outcome::Forbidden("KioskRestricted".to_string()),
+            (Caller::Session, As::TempPassword | As::Viewer) if access.permission.is_some() => {
+                Outcome::Forbidden("PermissionDenied".to_string())
+            }
+            (Caller::Session, As::TempPassword | As::Viewer | As::Admin) if access.step_up => Outcome::Forbidden("StepUpRequired".to_string()),
+            (Caller::Session, _) => Outcome::Through,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_every_route_answers_each_caller_as_its_access_declares() {
+        let app = TestApp::spawn().await;
+        let temporary = app.create_user("matrix_temporary", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET is_temporary_password = TRUE WHERE id = ?")
+            .bind(temporary.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let viewer = app.create_user("matrix_viewer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let admin = app.create_user("matrix_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let elevated = app.create_user("matrix_elevated", UserRole::Admin, TEST_PASSWORD, false).await;
+        let kiosk = app.create_user("matrix_kiosk", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let callers = [
+            (As::Anonymous, None),
+            (As::TempPassword, Some(&temporary)),
+            (As::Viewer, Some(&viewer)),
+            (As::Admin, Some(&admin)),
+            (As::ElevatedAdmin, Some(&elevated)),
+            (As::Kiosk, Some(&kiosk)),
+        ];
+        let placeholder = uuid::Uuid::new_v4().to_string();
+
+        let (_, table) = crate::api_scope(true, &app.scope_layers());
+        assert!(!table.routes().is_empty());
+        let mut mismatches = Vec::new();
+        for (i, (caller, user)) in callers.into_iter().enumerate() {
+            let mut token = None;
+            for (j, route) in table.routes().iter().enumerate() {
+                if let (Some(user), None) = (user, &token) {
+                    let mut fresh = match caller {
+                        As::Kiosk => app.kiosk_login_as(user, "10.0.0.1").await,
+                        _ => app.login_as(user, "10.0.0.1").await,
+                    };
+                    if caller == As::ElevatedAdmin {
+                        fresh = app.step_up(&fresh, user).await;
+                    }
+                    token = Some(fresh);
+                }
+
+                let uri = route
+                    .pattern
+                    .split('/')
+                    .map(|segment| if segment.starts_with('{') { placeholder.as_str() } else { segment })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                // A client of its own for each request, so the 401s don't add up to a lockout
+                let mut request = TestRequest::default()
+                    .method(route.method.clone())
+                    .uri(&uri)
+                    .peer_addr(format!("10.{}.{}.{}:4000", i + 1, j / 200, j % 200).parse().unwrap());
+                if let Some(token) = &token {
+                    request = bearer(request, token);
+                }
+
+                let response = app.call(request).await;
+                let observed = match response.status().as_u16() {
+                    401 => Outcome::Unauthorized,
+                    403 => {
+                        let body: serde_json::Value = read_body_json(response).await;
+                        Outcome::Forbidden(body["error_type"].as_str().unwrap_or_default().to_string())
+                    }
+                    _ => Outcome::Through,
+                };
+                let expected = expected(route.access, caller);
+                if observed != expected {
+                    mismatches.push(format!("{} {} as {:?}: expected {:?}, got {:?}", route.method, route.pattern, caller, expected, observed));
+                }
+
+                // Logging out or re-issuing the token ends the session it was sent with
+                if route.access.caller == Caller::OwnToken {
+                    token = None;
+                }
+            }
+        }
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_session_token_is_never_taken_from_the_query_string() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("query_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let token = app.login_as(&admin, "10.0.0.1").await;
+        let token = app.step_up(&token, &admin).await;
+        let placeholder = uuid::Uuid::new_v4().to_string();
+        let query = ["token", "access_token", "jwt", "authorization", "bearer", "dt"]
+            .map(|name| format!("{}={}", name, token))
+            .join("&");
+
+        let (_, table) = crate::api_scope(true, &app.scope_layers());
+        let mut accepted = Vec::new();
+        for (j, route) in table.routes().iter().enumerate().filter(|(_, route)| route.access.caller != Caller::Anyone) {
+            let path = route
+                .pattern
+                .split('/')
+                .map(|segment| if segment.starts_with('{') { placeholder.as_str() } else { segment })
+                .collect::<Vec<_>>()
+                .join("/");
+            let request = TestRequest::default()
+                .method(route.method.clone())
+                .uri(&format!("{}?{}", path, query))
+                .peer_addr(format!("10.9.{}.{}:4000", j / 200, j % 200).parse().unwrap());
+            let status = app.call(request).await.status();
+            if status != 401 {
+                accepted.push(format!("{} {} answered {}", route.method, route.pattern, status));
+            }
+        }
+        assert!(accepted.is_empty(), "{}", accepted.join("\n"));
+
+        // The token still works where it belongs
+        let verify = bearer(TestRequest::get().uri("/api/auth/verify"), &token);
+        assert_eq!(app.call(verify).await.status(), 200);
+    }
+
+    /// The route inventory as `GET /api/admin/routes` lists it, one
+    /// `METHOD pattern protection` line per route. A route added, removed or
+    /// given different access must be updated here on purpose.
+    const ROUTE_INVENTORY: &str = include_str!("../../test-data/route_inventory.txt");
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_each_route_is_declared_once_and_as_its_scope_demands() {
+        let app = TestApp::spawn().await;
+        let (_, table) = crate::api_scope(true, &app.scope_layers());
+        assert_eq!(table.findings(), Vec::<String>::new());
+
+        let inventory: Vec<String> = table
+            .routes()
+            .iter()
+            .map(|route| format!("{} {} {}", route.method, route.pattern, route.access))
+            .collect();
+        assert_eq!(inventory, ROUTE_INVENTORY.lines().collect::<Vec<_>>(), "update test-data/route_inventory.txt");
+
+        // Degraded, only the health checks are left to guard
+        let (_, degraded) = crate::api_scope(false, &app.scope_layers());
+        assert!(degraded.routes().iter().all(|route| route.access == RouteAccess::public()));
+    }
+
+    #[test]
+    fn test_findings_report_routes_declared_below_their_scope() {
+        let mut table = RouteTable::default();
+        RouteScope::new("/api", "/admin")
+            .get("/widgets", ok, RouteAccess::session())
+            .get("/widgets/{id}", ok, RouteAccess::permission(Permission::AuditRead))
+            .get("/widgets/{id}", ok, RouteAccess::permission(Permission::AuditRead))
+            .register(&mut table);
+        RouteScope::new("/api", "/integrations")
+            .post("/widgets", ok, RouteAccess::public())
+            .register(&mut table);
+        RouteScope::new("/api", "/auth")
+            .get("/report", ok, RouteAccess::session().with_download_token(DownloadResource::AuditExport))
+            .get("/landing", ok, RouteAccess::own_token().with_sign_in_page("<html></html>"))
+            .register(&mut table);
+
+        assert_eq!(
+            table.findings(),
+            vec![
+                "GET /api/admin/widgets must require a permission but is session",
+                "GET /api/admin/widgets/{id} is declared twice",
+                "POST /api/integrations/widgets must require an API key but is public",
+                "GET /api/auth/report takes download tokens without requiring a permission",
+                "GET /api/auth/landing has a sign-in page but needs no session",
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_routes_registered_around_route_scope_are_refused() {
+        let mut table = RouteTable::default();
+        let declared = RouteScope::new("/api", "/widgets").get("", ok, RouteAccess::public()).register(&mut table);
+        let service = actix_web::test::init_service(
+            actix_web::App::new().service(
+                web::scope("/api")
+                    .service(declared)
+                    .route("/unannotated", web::get().to(ok))
+                    .service(web::scope("/extra").route("/unannotated", web::post().to(ok)))
+                    .wrap(DeclaredRoutesOnly::new(Arc::new(table))),
+            ),
+        )
+        .await;
+        let call = |request: TestRequest| actix_web::test::call_service(&service, request.to_request());
+
+        assert_eq!(call(TestRequest::get().uri("/api/widgets")).await.status(), 200);
+        for request in [TestRequest::get().uri("/api/unannotated"), TestRequest::post().uri("/api/extra/unannotated")] {
+            let response = call(request).await;
+            assert_eq!(response.status(), 403);
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body["error_code"], "route_not_declared");
+        }
+        // A path no route matches is still just missing
+        assert_eq!(call(TestRequest::get().uri("/api/nowhere")).await.status(), 404);
+    }
+}
@@ -0,0 +1,98 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    Error, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::json;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::services::health_monitor::{HealthMonitor, HealthState, PROBE_INTERVAL};
+
+/// Endpoints that keep answering while the database is down, so load
+/// balancers and operators can see why
+const ALWAYS_SERVED: &[&str] = &["/api/health", "/api/health/ready"];
+
+/// Whether a request is answered with 503 while the service is down
+fn is_gated(path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    path.starts_with("/api/") && !ALWAYS_SERVED.contains(&path)
+}
+
+/// Health gate middleware - while the database is down, answers API requests
+/// with 503 at once instead of letting each one wait for the pool to time out
+pub struct HealthGate {
+    health: Arc<HealthMonitor>,
+}
+
+impl HealthGate {
+    pub fn new(health: Arc<HealthMonitor>) -> Self {
+        Self { health }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for HealthGate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HealthGateMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HealthGateMiddleware {
+            service: Rc::new(service),
+            health: self.health.clone(),
+        }))
+    }
+}
+
+pub struct HealthGateMiddleware<S> {
+    service: Rc<S>,
+    health: Arc<HealthMonitor>,
+}
+
+impl<S, B> Service<ServiceRequest> for HealthGateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let health = self.health.clone();
+
+        Box::pin(async move {
+            if health.state() == HealthState::Down && is_gated(req.path()) {
+                let response = HttpResponse::ServiceUnavailable()
+                    .insert_header((header::RETRY_AFTER, PROBE_INTERVAL.as_secs().to_string()))
+                    .json(json!({
+                        "success": false,
+                        "message": "The service is temporarily unavailable. Please try again shortly",
+                        "error_code": "service_down",
+                        "since": health.since().to_rfc3339(),
+                    }));
+
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let res = svc.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
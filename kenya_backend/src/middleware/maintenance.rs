@@ -0,0 +1,293 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Method},
+    Error, HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// Retry-After sent when the toggle request gave no ETA
+const DEFAULT_RETRY_AFTER_SECONDS: i64 = 120;
+
+/// Endpoints that start new sessions or change credentials; blocked during maintenance
+const BLOCKED_ROUTES: &[&str] = &[
+    "/api/auth/login",
+    "/api/auth/2fa/verify",
+    "/api/auth/change-password",
+    "/api/auth/device/approve",
+    "/api/auth/device/poll",
+    "/api/auth/password-reset/confirm",
+    "/api/v2/auth/login",
+    "/api/v2/auth/login/2fa",
+    "/api/v2/auth/login/change-password",
+];
+
+/// Operator-supplied details shown to clients while maintenance is on
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MaintenanceNotice {
+    pub message: Option<String>,
+    pub eta: Option<DateTime<Utc>>,
+}
+
+/// Process-wide maintenance flag, shared between the middleware and the admin endpoint
+#[derive(Debug, Default)]
+pub struct MaintenanceState {
+    enabled: AtomicBool,
+    notice: RwLock<MaintenanceNotice>,
+}
+
+impl MaintenanceState {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            notice: RwLock::new(MaintenanceNotice::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Turn maintenance on with an optional message and ETA
+    pub fn enable(&self, message: Option<String>, eta: Option<DateTime<Utc>>) {
+        if let Ok(mut notice) = self.notice.write() {
+            *notice = MaintenanceNotice { message, eta };
+        }
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::SeqCst);
+        if let Ok(mut notice) = self.notice.write() {
+            *notice = MaintenanceNotice::default();
+        }
+    }
+
+    pub fn notice(&self) -> MaintenanceNotice {
+        self.notice.read().map(|n| n.clone()).unwrap_or_default()
+    }
+
+    /// Seconds clients should wait before retrying
+    fn retry_after_seconds(&self) -> i64 {
+        self.notice()
+            .eta
+            .map(|eta| (eta - Utc::now()).num_seconds().max(1))
+            .unwrap_or(DEFAULT_RETRY_AFTER_SECONDS)
+    }
+}
+
+/// Whether a request is refused while maintenance mode is on
+fn is_blocked(method: &Method, path: &str) -> bool {
+    method == Method::POST && BLOCKED_ROUTES.contains(&path.trim_end_matches('/'))
+}
+
+/// Maintenance mode middleware - rejects new logins and credential changes with 503
+pub struct MaintenanceMode {
+    state: Arc<MaintenanceState>,
+}
+
+impl MaintenanceMode {
+    pub fn new(state: Arc<MaintenanceState>) -> Self {
+        Self { state }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for MaintenanceMode
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MaintenanceModeMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MaintenanceModeMiddleware {
+            service: Rc::new(service),
+            state: self.state.clone(),
+        }))
+    }
+}
+
+pub struct MaintenanceModeMiddleware<S> {
+    service: Rc<S>,
+    state: Arc<MaintenanceState>,
+}
+
+impl<S, B> Service<ServiceRequest> for MaintenanceModeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let state = self.state.clone();
+
+        Box::pin(async move {
+            if state.is_enabled() && is_blocked(req.method(), req.path()) {
+                let notice = state.notice();
+                log::info!("Maintenance mode: rejected {} {}", req.method(), req.path());
+
+                let response = HttpResponse::ServiceUnavailable()
+                    .insert_header((header::RETRY_AFTER, state.retry_after_seconds().to_string()))
+                    .json(json!({
+                        "success": false,
+                        "message": notice
+                            .message
+                            .clone()
+                            .unwrap_or_else(|| "The service is undergoing maintenance. Please try again later".to_string()),
+                        "error_code": "maintenance",
+                        "eta": notice.eta.map(|eta| eta.to_rfc3339()),
+                    }));
+
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let res = svc.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    macro_rules! maintenance_app {
+        ($state:expr) => {
+            test::init_service(
+                App::new().wrap(MaintenanceMode::new($state.clone())).service(
+                    web::scope("/api")
+                        .service(
+                            web::scope("/auth")
+                                .route("/login", web::post().to(ok))
+                                .route("/change-password", web::post().to(ok))
+                                .route("/verify", web::get().to(ok))
+                                .route("/logout", web::post().to(ok))
+                                .route("/2fa/verify", web::post().to(ok))
+                                .route("/device/start", web::post().to(ok))
+                                .route("/device/approve", web::post().to(ok))
+                                .route("/device/poll", web::post().to(ok))
+                                .route("/password-reset/confirm", web::post().to(ok)),
+                        )
+                        .service(
+                            web::scope("/v2/auth")
+                                .route("/login", web::post().to(ok))
+                                .route("/login/2fa", web::post().to(ok))
+                                .route("/login/change-password", web::post().to(ok)),
+                        )
+                        .route("/health", web::get().to(ok)),
+                ),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn test_allowed_and_blocked_matrix() {
+        let state = Arc::new(MaintenanceState::new(false));
+        let app = maintenance_app!(state);
+
+        let matrix = [
+            (Method::POST, "/api/auth/login", true),
+            (Method::POST, "/api/auth/2fa/verify", true),
+            (Method::POST, "/api/auth/change-password", true),
+            (Method::POST, "/api/auth/device/approve", true),
+            (Method::POST, "/api/auth/device/poll", true),
+            (Method::POST, "/api/auth/password-reset/confirm", true),
+            (Method::POST, "/api/v2/auth/login", true),
+            (Method::POST, "/api/v2/auth/login/2fa", true),
+            (Method::POST, "/api/v2/auth/login/change-password", true),
+            (Method::GET, "/api/auth/verify", false),
+            (Method::POST, "/api/auth/logout", false),
+            (Method::POST, "/api/auth/device/start", false),
+            (Method::GET, "/api/health", false),
+        ];
+
+        // Everything passes while maintenance is off
+        for (method, path, _) in &matrix {
+            let req = test::TestRequest::default().method(method.clone()).uri(path).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 200, "{} {} with maintenance off", method, path);
+        }
+
+        state.enable(Some("Database migration".to_string()), None);
+
+        for (method, path, blocked) in &matrix {
+            let req = test::TestRequest::default().method(method.clone()).uri(path).to_request();
+            let resp = test::call_service(&app, req).await;
+            let expected = if *blocked { 503 } else { 200 };
+            assert_eq!(resp.status(), expected, "{} {} with maintenance on", method, path);
+        }
+
+        state.disable();
+
+        let req = test::TestRequest::post().uri("/api/auth/login").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_blocked_response_carries_retry_after_and_notice() {
+        let state = Arc::new(MaintenanceState::new(false));
+        let app = maintenance_app!(state);
+
+        let eta = Utc::now() + chrono::Duration::minutes(10);
+        state.enable(Some("Upgrading database".to_string()), Some(eta));
+
+        let req = test::TestRequest::post().uri("/api/auth/login").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503);
+
+        let retry_after: i64 = resp
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .expect("Retry-After header");
+        assert!(retry_after > 500 && retry_after <= 600);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "maintenance");
+        assert_eq!(body["message"], "Upgrading database");
+    }
+
+    #[actix_web::test]
+    async fn test_env_default_enables_maintenance() {
+        let state = Arc::new(MaintenanceState::new(true));
+        let app = maintenance_app!(state);
+
+        let req = test::TestRequest::post().uri("/api/auth/login").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503);
+        assert_eq!(
+            resp.headers().get(header::RETRY_AFTER).unwrap(),
+            &DEFAULT_RETRY_AFTER_SECONDS.to_string()
+        );
+    }
+}
@@ -1 +1,8 @@
-pub mod security;
\ No newline at end of file
+pub mod security;
+pub mod maintenance;pub mod request_context;
+pub mod origin_guard;
+pub mod admin_reads;
+pub mod health_gate;
+pub mod authorization;
+pub mod request_tracing;
+pub mod server_timing;
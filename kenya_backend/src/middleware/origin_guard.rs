@@ -0,0 +1,553 @@
+use actix_cors::Cors;
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Method, Uri},
+    Error, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::services::client_app_service::{ClientRegistry, CLIENT_ID_HEADER};
+
+/// Shortest gap between two warnings about the same rejected origin
+const REJECTION_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Distinct origins remembered for log throttling before the memory is reset
+const MAX_TRACKED_ORIGINS: usize = 1000;
+
+/// A configured CORS origin the server refuses to start with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsConfigError {
+    /// Not of the form `scheme://host[:port]`
+    InvalidOrigin(String),
+    /// `*` can't be combined with credentialed requests, which the API always allows
+    WildcardWithCredentials,
+    /// A scope's rules allow any origin and credentials together
+    WildcardScopeWithCredentials(&'static str),
+}
+
+impl fmt::Display for CorsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorsConfigError::InvalidOrigin(origin) => write!(
+                f,
+                "CORS origin {:?} is invalid: expected http(s)://host[:port] with no path or trailing slash",
+                origin
+            ),
+            CorsConfigError::WildcardWithCredentials => {
+                write!(f, "CORS origin \"*\" cannot be used because the API allows credentials")
+            }
+            CorsConfigError::WildcardScopeWithCredentials(scope) => {
+                write!(f, "CORS rules for the {} scope allow any origin together with credentials", scope)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CorsConfigError {}
+
+/// Which origins a scope accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeOrigins {
+    /// Every origin, answered with `*`
+    Any,
+    /// `CORS_ORIGINS` and the origins of the enabled client apps
+    Clients,
+    /// `CORS_ADMIN_ORIGINS`
+    Admin,
+    /// No browser origin: the scope is for other servers
+    None,
+}
+
+/// The CORS rules of one route scope
+#[derive(Debug, Clone, Copy)]
+pub struct CorsScope {
+    /// Named in startup errors and rejection warnings
+    pub name: &'static str,
+    pub methods: &'static [&'static str],
+    pub origins: ScopeOrigins,
+    pub credentials: bool,
+    /// How long browsers may cache a preflight
+    pub max_age_seconds: usize,
+}
+
+/// Health checks and `/.well-known`: readable from anywhere, never with credentials
+pub const PUBLIC_CORS: CorsScope = CorsScope {
+    name: "public",
+    methods: &["GET"],
+    origins: ScopeOrigins::Any,
+    credentials: false,
+    max_age_seconds: 86400,
+};
+
+/// Sign-in, the signed-in account's own endpoints, and what every page loads
+pub const CLIENT_CORS: CorsScope = CorsScope {
+    name: "client",
+    methods: &["GET", "POST"],
+    origins: ScopeOrigins::Clients,
+    credentials: true,
+    max_age_seconds: 3600,
+};
+
+/// The admin endpoints. Preflights are cached briefly so a narrowed
+/// `CORS_ADMIN_ORIGINS` takes hold soon after a restart.
+pub const ADMIN_CORS: CorsScope = CorsScope {
+    name: "admin",
+    methods: &["GET", "POST", "PUT", "DELETE"],
+    origins: ScopeOrigins::Admin,
+    credentials: true,
+    max_age_seconds: 600,
+};
+
+/// Endpoints other systems call with an API key
+pub const INTEGRATION_CORS: CorsScope = CorsScope {
+    name: "integration",
+    methods: &["POST"],
+    origins: ScopeOrigins::None,
+    credentials: false,
+    max_age_seconds: 0,
+};
+
+/// Every scope's rules, checked together at startup
+pub const CORS_SCOPES: [CorsScope; 4] = [PUBLIC_CORS, CLIENT_CORS, ADMIN_CORS, INTEGRATION_CORS];
+
+/// Refuse rules that browsers would reject outright: any origin with credentials
+pub fn check_scopes(scopes: &[CorsScope]) -> Result<(), CorsConfigError> {
+    match scopes.iter().find(|scope| scope.origins == ScopeOrigins::Any && scope.credentials) {
+        Some(scope) => Err(CorsConfigError::WildcardScopeWithCredentials(scope.name)),
+        None => Ok(()),
+    }
+}
+
+/// Origins allowed to call the API from a browser: the configured ones,
+/// checked once at startup, and those of the enabled client apps. Each
+/// scope's `CorsScope` picks which of them it accepts.
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    origins: Vec<String>,
+    admin_origins: Vec<String>,
+    /// Answer requests from unlisted origins with a JSON 403 instead of
+    /// leaving them to the CORS layer
+    reject_with_json: bool,
+    clients: Option<Arc<ClientRegistry>>,
+}
+
+impl CorsPolicy {
+    pub fn new(origins: Vec<String>, reject_with_json: bool) -> Result<Self, CorsConfigError> {
+        for origin in &origins {
+            validate_origin(origin)?;
+        }
+        Ok(Self { admin_origins: origins.clone(), origins, reject_with_json, clients: None })
+    }
+
+    /// Allow only `origins` on the admin scope instead of the general ones
+    pub fn with_admin_origins(mut self, origins: Vec<String>) -> Result<Self, CorsConfigError> {
+        for origin in &origins {
+            validate_origin(origin)?;
+        }
+        self.admin_origins = origins;
+        Ok(self)
+    }
+
+    /// Also allow the origins of the apps in `clients`, as they are at each request
+    pub fn with_clients(mut self, clients: Arc<ClientRegistry>) -> Self {
+        self.clients = Some(clients);
+        self
+    }
+
+    pub fn allows(&self, scope: &CorsScope, origin: &str) -> bool {
+        match scope.origins {
+            ScopeOrigins::Any => true,
+            ScopeOrigins::Clients => {
+                self.origins.iter().any(|allowed| allowed == origin)
+                    || self.clients.as_ref().is_some_and(|clients| clients.allows_origin(origin))
+            }
+            ScopeOrigins::Admin => self.admin_origins.iter().any(|allowed| allowed == origin),
+            ScopeOrigins::None => false,
+        }
+    }
+
+    /// The CORS layer for a scope with the rules `scope`
+    pub fn cors(&self, scope: CorsScope) -> Cors {
+        let cors = Cors::default()
+            .allowed_methods(scope.methods.iter().copied())
+            .allowed_headers(vec!["Authorization", "Content-Type", "X-Requested-With", "X-Request-Id", CLIENT_ID_HEADER])
+            // The frontend backs off using the rate limit headers
+            .expose_headers(vec![
+                "X-Request-Id",
+                "Retry-After",
+                "X-RateLimit-Limit",
+                "X-RateLimit-Remaining",
+                "X-RateLimit-Reset",
+                "X-RateLimit-Warning",
+            ])
+            .max_age(scope.max_age_seconds);
+        let cors = match scope.origins {
+            ScopeOrigins::Any => cors.allow_any_origin().send_wildcard(),
+            _ => {
+                let policy = self.clone();
+                cors.allowed_origin_fn(move |origin, _| origin.to_str().is_ok_and(|origin| policy.allows(&scope, origin)))
+            }
+        };
+        if scope.credentials {
+            cors.supports_credentials()
+        } else {
+            cors
+        }
+    }
+}
+
+/// Check that `origin` is of the form browsers send, `scheme://host[:port]`
+pub(crate) fn validate_origin(origin: &str) -> Result<(), CorsConfigError> {
+    if origin.trim() == "*" {
+        return Err(CorsConfigError::WildcardWithCredentials);
+    }
+    let invalid = || CorsConfigError::InvalidOrigin(origin.to_string());
+
+    let uri: Uri = origin.parse().map_err(|_| invalid())?;
+    let scheme = uri.scheme_str().filter(|s| matches!(*s, "http" | "https")).ok_or_else(invalid)?;
+    let authority = uri.authority().filter(|a| !a.host().is_empty() && !a.as_str().contains('@')).ok_or_else(invalid)?;
+
+    // Browsers send exactly scheme://host[:port]; anything more never matches
+    if format!("{}://{}", scheme, authority) != origin {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// Counts requests refused for their Origin, and throttles the warnings about them
+#[derive(Debug, Default)]
+pub struct CorsRejections {
+    total: AtomicU64,
+    /// Per origin: when it was last logged, and rejections since then
+    last_logged: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl CorsRejections {
+    /// Rejections since the process started
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Count a rejection. Returns how many went unlogged since the last
+    /// warning for this origin when one is due now, `None` otherwise.
+    fn record(&self, origin: &str) -> Option<u64> {
+        self.total.fetch_add(1, Ordering::Relaxed);
+
+        let mut last_logged = self.last_logged.lock().ok()?;
+        let now = Instant::now();
+        if last_logged.len() >= MAX_TRACKED_ORIGINS && !last_logged.contains_key(origin) {
+            last_logged.clear();
+        }
+
+        match last_logged.get_mut(origin) {
+            Some((logged_at, suppressed)) if now.duration_since(*logged_at) < REJECTION_LOG_INTERVAL => {
+                *suppressed += 1;
+                None
+            }
+            Some((logged_at, suppressed)) => {
+                let skipped = *suppressed;
+                *logged_at = now;
+                *suppressed = 0;
+                Some(skipped)
+            }
+            None => {
+                last_logged.insert(origin.to_string(), (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+/// Middleware in front of the CORS layer that makes Origin rejections visible:
+/// a throttled warning per origin, a counter, and optionally a JSON 403
+pub struct OriginGuard {
+    policy: Arc<CorsPolicy>,
+    scope: CorsScope,
+    rejections: Arc<CorsRejections>,
+}
+
+impl OriginGuard {
+    /// A guard for a scope with the rules `scope`, in front of `policy.cors(scope)`
+    pub fn new(policy: Arc<CorsPolicy>, scope: CorsScope, rejections: Arc<CorsRejections>) -> Self {
+        Self { policy, scope, rejections }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for OriginGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = OriginGuardMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(OriginGuardMiddleware {
+            service: Rc::new(service),
+            policy: self.policy.clone(),
+            scope: self.scope,
+            rejections: self.rejections.clone(),
+        }))
+    }
+}
+
+pub struct OriginGuardMiddleware<S> {
+    service: Rc<S>,
+    policy: Arc<CorsPolicy>,
+    scope: CorsScope,
+    rejections: Arc<CorsRejections>,
+}
+
+impl<S, B> Service<ServiceRequest> for OriginGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let policy = self.policy.clone();
+        let scope = self.scope;
+        let rejections = self.rejections.clone();
+
+        Box::pin(async move {
+            let origin = req
+                .headers()
+                .get(header::ORIGIN)
+                .map(|value| value.to_str().unwrap_or("<non-ascii>").to_string());
+
+            if let Some(origin) = origin.filter(|origin| !policy.allows(&scope, origin)) {
+                let preflight = req.method() == Method::OPTIONS
+                    && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+                if let Some(suppressed) = rejections.record(&origin) {
+                    log::warn!(
+                        "CORS rejection: origin={:?} scope={} method={} path={} preflight={} suppressed_since_last={}",
+                        origin,
+                        scope.name,
+                        req.method(),
+                        req.path(),
+                        preflight,
+                        suppressed
+                    );
+                }
+
+                if policy.reject_with_json && !preflight {
+                    let response = HttpResponse::Forbidden().json(json!({
+                        "success": false,
+                        "message": "Requests from this origin are not allowed",
+                        "error_code": "origin_not_allowed",
+                    }));
+                    return Ok(req.into_response(response).map_into_right_body());
+                }
+            }
+
+            let res = svc.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App};
+
+    use crate::test_support::{TestApp, TEST_ADMIN_ORIGIN, TEST_ORIGIN};
+
+    const ALLOWED: &str = "https://kenya.fsfvi.ai";
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    macro_rules! guarded_app {
+        ($policy:expr, $rejections:expr) => {
+            init_service(
+                App::new()
+                    .wrap($policy.cors(CLIENT_CORS))
+                    .wrap(OriginGuard::new(Arc::new($policy.clone()), CLIENT_CORS, $rejections.clone()))
+                    .route("/api/health", web::get().to(ok)),
+            )
+            .await
+        };
+    }
+
+    fn from_origin(origin: &str) -> TestRequest {
+        TestRequest::get().uri("/api/health").insert_header((header::ORIGIN, origin))
+    }
+
+    #[actix_web::test]
+    async fn test_allowed_origin_passes() {
+        let policy = CorsPolicy::new(vec![ALLOWED.to_string()], true).unwrap();
+        let rejections = Arc::new(CorsRejections::default());
+        let app = guarded_app!(policy, rejections);
+
+        let res = call_service(&app, from_origin(ALLOWED).to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ALLOWED);
+
+        let res = call_service(&app, TestRequest::get().uri("/api/health").to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(rejections.total(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_rejected_origin_is_counted_and_optionally_answered_with_json() {
+        let rejections = Arc::new(CorsRejections::default());
+
+        let quiet = CorsPolicy::new(vec![ALLOWED.to_string()], false).unwrap();
+        let app = guarded_app!(quiet, rejections);
+        let res = call_service(&app, from_origin("https://evil.example").to_request()).await;
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert_eq!(rejections.total(), 1);
+
+        let strict = CorsPolicy::new(vec![ALLOWED.to_string()], true).unwrap();
+        let app = guarded_app!(strict, rejections);
+        let res = call_service(&app, from_origin("https://evil.example").to_request()).await;
+        assert_eq!(res.status(), 403);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["error_code"], "origin_not_allowed");
+
+        // Preflights are still left to the CORS layer
+        let preflight = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/health")
+            .insert_header((header::ORIGIN, "https://evil.example"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        let res = call_service(&app, preflight).await;
+        assert_ne!(res.status(), 403);
+        assert_eq!(rejections.total(), 3);
+    }
+
+    #[test]
+    fn test_invalid_origins_are_refused_at_startup() {
+        assert_eq!(
+            CorsPolicy::new(vec![ALLOWED.to_string(), "*".to_string()], false).unwrap_err(),
+            CorsConfigError::WildcardWithCredentials
+        );
+        for origin in ["kenya.fsfvi.ai", "https://kenya.fsfvi.ai/", "https://kenya.fsfvi.ai/app", "ftp://kenya.fsfvi.ai", "https://user@kenya.fsfvi.ai", ""] {
+            assert_eq!(
+                CorsPolicy::new(vec![origin.to_string()], false).unwrap_err(),
+                CorsConfigError::InvalidOrigin(origin.to_string()),
+                "{:?} should be refused",
+                origin
+            );
+        }
+        assert!(CorsPolicy::new(vec!["http://localhost:3000".to_string(), ALLOWED.to_string()], false).is_ok());
+        let policy = CorsPolicy::new(vec![ALLOWED.to_string()], false).unwrap();
+        assert_eq!(
+            policy.with_admin_origins(vec!["*".to_string()]).unwrap_err(),
+            CorsConfigError::WildcardWithCredentials
+        );
+    }
+
+    #[test]
+    fn test_scopes_may_not_pair_any_origin_with_credentials() {
+        assert!(check_scopes(&CORS_SCOPES).is_ok());
+        let open_admin = CorsScope { origins: ScopeOrigins::Any, ..ADMIN_CORS };
+        assert_eq!(
+            check_scopes(&[PUBLIC_CORS, open_admin]).unwrap_err(),
+            CorsConfigError::WildcardScopeWithCredentials("admin")
+        );
+    }
+
+    fn preflight(uri: &str, origin: &str, method: &str) -> TestRequest {
+        TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri(uri)
+            .insert_header((header::ORIGIN, origin))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, method))
+    }
+
+    fn header_of<B>(res: &ServiceResponse<B>, name: header::HeaderName) -> Option<&str> {
+        res.headers().get(name).and_then(|value| value.to_str().ok())
+    }
+
+    #[actix_web::test]
+    async fn test_each_scope_answers_preflights_with_its_own_rules() {
+        let app = TestApp::spawn().await;
+        let user_uri = format!("/api/admin/users/{}", uuid::Uuid::new_v4());
+
+        // Health: any origin, GET only, no credentials, cached for a day
+        let res = app.call(preflight("/api/health", "https://status.example", "GET")).await;
+        assert!(res.status().is_success());
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_ALLOW_METHODS), Some("GET"));
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_MAX_AGE), Some("86400"));
+        assert!(header_of(&res, header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        assert!(!app.call(preflight("/api/health/ready", "https://status.example", "POST")).await.status().is_success());
+
+        // Sign-in: the frontend's origins, GET and POST, with credentials
+        let res = app.call(preflight("/api/auth/login", TEST_ORIGIN, "POST")).await;
+        assert!(res.status().is_success());
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some(TEST_ORIGIN));
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_ALLOW_CREDENTIALS), Some("true"));
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_MAX_AGE), Some("3600"));
+        let mut methods: Vec<_> = header_of(&res, header::ACCESS_CONTROL_ALLOW_METHODS).unwrap().split(", ").collect();
+        methods.sort_unstable();
+        assert_eq!(methods, ["GET", "POST"]);
+        assert!(!app.call(preflight("/api/auth/login", TEST_ORIGIN, "DELETE")).await.status().is_success());
+        assert!(!app.call(preflight("/api/auth/login", "https://status.example", "POST")).await.status().is_success());
+
+        // Admin: every method, but only from the admin origins
+        let res = app.call(preflight(&user_uri, TEST_ADMIN_ORIGIN, "DELETE")).await;
+        assert!(res.status().is_success());
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some(TEST_ADMIN_ORIGIN));
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_MAX_AGE), Some("600"));
+        let mut methods: Vec<_> = header_of(&res, header::ACCESS_CONTROL_ALLOW_METHODS).unwrap().split(", ").collect();
+        methods.sort_unstable();
+        assert_eq!(methods, ["DELETE", "GET", "POST", "PUT"]);
+        let res = app.call(preflight(&user_uri, TEST_ORIGIN, "GET")).await;
+        assert!(!res.status().is_success());
+        assert!(header_of(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert!(!app.call(preflight("/api/health/details", TEST_ORIGIN, "GET")).await.status().is_success());
+        assert!(app.call(preflight("/api/health/details", TEST_ADMIN_ORIGIN, "GET")).await.status().is_success());
+        // The sign-in scope doesn't take the admin origins either
+        assert!(!app.call(preflight("/api/auth/login", TEST_ADMIN_ORIGIN, "POST")).await.status().is_success());
+
+        // Integrations: no browser origin at all
+        assert!(!app.call(preflight("/api/integrations/hr/events", TEST_ORIGIN, "POST")).await.status().is_success());
+
+        // CORS sits outside the route guard, so browsers can read a 401
+        let res = app.call(TestRequest::get().uri("/api/admin/users").insert_header((header::ORIGIN, TEST_ADMIN_ORIGIN))).await;
+        assert_eq!(res.status(), 401);
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some(TEST_ADMIN_ORIGIN));
+        let res = app.call(TestRequest::get().uri("/api/health").insert_header((header::ORIGIN, "https://status.example"))).await;
+        assert_eq!(header_of(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+    }
+
+    #[test]
+    fn test_warnings_are_throttled_per_origin() {
+        let rejections = CorsRejections::default();
+        assert_eq!(rejections.record("https://a.example"), Some(0));
+        assert_eq!(rejections.record("https://a.example"), None);
+        assert_eq!(rejections.record("https://b.example"), Some(0));
+        assert_eq!(rejections.total(), 3);
+    }
+}
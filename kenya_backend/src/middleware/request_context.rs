@@ -0,0 +1,113 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use crate::models::context::RequestContext;
+
+/// Request context middleware - builds the `RequestContext` once per request
+/// and echoes its request ID in the `X-Request-Id` response header
+pub struct RequestContextMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestContextMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestContextService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestContextService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestContextService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestContextService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let context = RequestContext::from_http_request(req.request());
+        let request_id = context.request_id.clone();
+        req.extensions_mut().insert(context);
+
+        Box::pin(async move {
+            let mut res = svc.call(req).await?;
+
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    use crate::test_support::TestApp;
+
+    #[actix_web::test]
+    async fn test_context_reaches_response_header_and_audit_log() {
+        let app = TestApp::spawn().await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/auth/login")
+            .insert_header(("X-Request-Id", "edge-4d2c81"))
+            .peer_addr("197.232.61.4:4000".parse().unwrap())
+            .insert_header(("User-Agent", "kenya-dashboard/1.0"))
+            .set_json(serde_json::json!({ "username": "nobody_here", "password": "WrongPassw0rd!!" }));
+        let resp = app.call(req).await;
+
+        assert_eq!(resp.status(), 401);
+        assert_eq!(resp.headers().get("x-request-id").unwrap(), "edge-4d2c81");
+
+        let events = app.auth_service().audit_service().get_recent_events(1, false, None).await.unwrap();
+        assert_eq!(events[0].event_type, "LOGIN_ATTEMPT");
+        assert_eq!(events[0].ip_address.as_deref(), Some("197.232.61.4"));
+        assert_eq!(events[0].user_agent.as_deref(), Some("kenya-dashboard/1.0"));
+        assert_eq!(events[0].details.as_ref().unwrap()["request_id"], "edge-4d2c81");
+    }
+
+    #[actix_web::test]
+    async fn test_generated_request_id_is_echoed() {
+        async fn echo(ctx: RequestContext) -> actix_web::HttpResponse {
+            actix_web::HttpResponse::Ok().body(ctx.request_id)
+        }
+
+        let app = test::init_service(App::new().wrap(RequestContextMiddleware).route("/", web::get().to(echo))).await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+
+        let header = resp.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+        let body = test::read_body(resp).await;
+        assert_eq!(header.as_bytes(), &body[..]);
+    }
+}
@@ -0,0 +1,227 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::models::context::RequestContext;
+use crate::services::tracer::{ActiveTrace, Attribute, FinishedSpan, SpanKind, Tracer, TRACEPARENT_HEADER};
+
+/// Request tracing middleware - joins the caller's trace from `traceparent`
+/// or starts one, makes it current while the request is handled, and records
+/// a server span named after the route pattern. Only installed while an OTLP
+/// endpoint is configured.
+pub struct RequestTracing {
+    tracer: Arc<Tracer>,
+}
+
+impl RequestTracing {
+    pub fn new(tracer: Arc<Tracer>) -> Self {
+        Self { tracer }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware {
+            service: Rc::new(service),
+            tracer: self.tracer.clone(),
+        }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+    tracer: Arc<Tracer>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let tracer = self.tracer.clone();
+        let traceparent = req.headers().get(TRACEPARENT_HEADER).and_then(|v| v.to_str().ok());
+        let (context, parent_span_id) = tracer.start_request(traceparent);
+
+        let method = req.method().to_string();
+        let request_id = req.extensions().get::<RequestContext>().map(|ctx| ctx.request_id.clone());
+        let start = SystemTime::now();
+
+        Box::pin(async move {
+            let result = ActiveTrace::new(tracer.clone(), context).scope(svc.call(req)).await;
+
+            // Routing happens further in, so the pattern is only known now. The
+            // pattern, never the path: paths can carry tokens.
+            let (status, route) = match &result {
+                Ok(res) => (res.status(), res.request().match_pattern()),
+                Err(e) => (e.as_response_error().status_code(), None),
+            };
+            let route = route.unwrap_or_else(|| "unmatched".to_string());
+            let mut attributes = vec![
+                ("http.request.method", Attribute::Str(method.clone())),
+                ("http.route", Attribute::Str(route.clone())),
+                ("http.response.status_code", Attribute::Int(i64::from(status.as_u16()))),
+            ];
+            if let Some(request_id) = request_id {
+                attributes.push(("request.id", Attribute::Str(request_id)));
+            }
+            tracer.record(FinishedSpan {
+                context,
+                parent_span_id,
+                name: format!("{} {}", method, route),
+                kind: SpanKind::Server,
+                start,
+                end: SystemTime::now(),
+                attributes,
+                error: status.is_server_error(),
+            });
+
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpResponse, HttpServer};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    use crate::services::tracer::{ActiveTrace, TraceContext, Tracer};
+    use crate::services::webhook_service::{RetryPolicy, WebhookDestination, WebhookService};
+    use crate::test_support::TestApp;
+    use crate::utils::clock::SystemClock;
+    use crate::utils::database::test_pool;
+
+    const CALLER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    /// What the mock collector and webhook receiver saw: OTLP bodies and `traceparent` headers
+    #[derive(Default)]
+    struct Seen {
+        exports: Vec<Value>,
+        traceparents: Vec<String>,
+    }
+
+    /// A collector at `/v1/traces` and a webhook receiver at `/hook` in one server
+    async fn mock_collector() -> (String, Arc<Mutex<Seen>>) {
+        let seen: Arc<Mutex<Seen>> = Arc::default();
+        let state = seen.clone();
+        let server = HttpServer::new(move || {
+            let exports = state.clone();
+            let hooks = state.clone();
+            App::new()
+                .route(
+                    "/v1/traces",
+                    web::post().to(move |body: web::Json<Value>| {
+                        let exports = exports.clone();
+                        async move {
+                            exports.lock().unwrap().exports.push(body.into_inner());
+                            HttpResponse::Ok().finish()
+                        }
+                    }),
+                )
+                .route(
+                    "/hook",
+                    web::post().to(move |req: actix_web::HttpRequest| {
+                        let hooks = hooks.clone();
+                        async move {
+                            let traceparent = req.headers().get("traceparent").and_then(|v| v.to_str().ok()).unwrap_or_default();
+                            hooks.lock().unwrap().traceparents.push(traceparent.to_string());
+                            HttpResponse::Ok().finish()
+                        }
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (url, seen)
+    }
+
+    #[actix_web::test]
+    async fn test_trace_context_reaches_the_collector_and_outbound_calls() {
+        let (url, seen) = mock_collector().await;
+        let tracer = Arc::new(Tracer::new(Some(&url), 0.0));
+        let app = TestApp::spawn_traced(tracer.clone()).await;
+
+        // The caller sampled the trace, so it is recorded whatever the local rate
+        let request = test::TestRequest::post()
+            .uri("/api/auth/login")
+            .insert_header(("traceparent", CALLER))
+            .set_json(serde_json::json!({ "username": "traced_user", "password": "WrongPassw0rd!!" }));
+        assert_eq!(app.call(request).await.status(), 401);
+        assert!(tracer.flush().await.unwrap() >= 2);
+
+        let spans = exported_spans(&seen);
+        let server = spans.iter().find(|span| span["kind"] == 2).expect("no server span");
+        assert_eq!(server["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(server["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(server["name"], "POST /api/auth/login");
+        let service = spans.iter().find(|span| span["name"] == "AuthService::authenticate").expect("no service span");
+        assert_eq!(service["traceId"], server["traceId"]);
+        assert_eq!(service["parentSpanId"], server["spanId"]);
+        // Nothing from the request body is recorded
+        let exported = serde_json::to_string(&seen.lock().unwrap().exports).unwrap();
+        assert!(!exported.contains("WrongPassw0rd!!") && !exported.contains("traced_user"));
+
+        // Without a sampled caller, a rate of 0 records nothing
+        assert_eq!(app.call(test::TestRequest::get().uri("/api/health")).await.status(), 200);
+        assert_eq!(tracer.pending(), 0);
+
+        // Webhooks sent while handling a request carry its trace on
+        let webhooks = WebhookService::new(
+            test_pool().await,
+            vec![WebhookDestination { name: "siem".to_string(), url: format!("{}/hook", url), secret: "s".to_string() }],
+            RetryPolicy::default(),
+            Arc::new(SystemClock),
+        );
+        let (context, _) = tracer.start_request(Some(CALLER));
+        ActiveTrace::new(tracer.clone(), context)
+            .scope(webhooks.deliver("siem", "security_event", serde_json::json!({})))
+            .await
+            .unwrap();
+        let traceparent = seen.lock().unwrap().traceparents.pop().expect("no webhook received");
+        let sent = TraceContext::parse(&traceparent).unwrap();
+        assert_eq!(sent.trace_id, context.trace_id);
+        assert_ne!(sent.span_id, context.span_id);
+        assert!(sent.sampled);
+    }
+
+    fn exported_spans(seen: &Mutex<Seen>) -> Vec<Value> {
+        seen.lock()
+            .unwrap()
+            .exports
+            .iter()
+            .flat_map(|export| export["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().cloned().unwrap_or_default())
+            .collect()
+    }
+}
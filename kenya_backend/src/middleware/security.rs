@@ -1,14 +1,29 @@
 use actix_web::{
+    body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    http::header::{HeaderName, HeaderValue},
-    Error,
+    http::header::{self, HeaderName, HeaderValue},
+    Error, HttpMessage, HttpResponse,
 };
 use futures_util::future::LocalBoxFuture;
+use governor::{
+    clock::{Clock, DefaultClock},
+    middleware::{StateInformationMiddleware, StateSnapshot},
+    state::keyed::DefaultKeyedStateStore,
+    Quota, RateLimiter,
+};
+use serde_json::json;
 use std::{
     future::{ready, Ready},
+    num::NonZeroU32,
     rc::Rc,
+    sync::Arc,
+    time::Duration,
 };
 
+use crate::models::context::{client_network, RequestContext};
+use crate::services::client_app_service::{ClientRegistry, RateLimitTier};
+use crate::services::throttle_state::{Decision, ThrottleScope, ThrottleState};
+
 /// Security headers middleware
 pub struct SecurityHeaders;
 
@@ -80,10 +95,16 @@ where
             headers.insert(
                 HeaderName::from_static("content-security-policy"),
                 HeaderValue::from_static(
-                    "default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; connect-src 'self'; font-src 'self'; object-src 'none'; media-src 'self'; child-src 'none'; frame-ancestors 'none'; base-uri 'self'; form-action 'self'"
+                    "default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; connect-src 'self'; font-src 'self'; object-src 'none'; media-src 'self'; child-src 'none'; frame-ancestors 'none'; base-uri 'self'; form-action 'self'; report-uri /api/csp-report; report-to csp-endpoint"
                 ),
             );
 
+            // Where the report-to group above delivers violation reports
+            headers.insert(
+                HeaderName::from_static("reporting-endpoints"),
+                HeaderValue::from_static("csp-endpoint=\"/api/csp-report\""),
+            );
+
             headers.insert(
                 HeaderName::from_static("referrer-policy"),
                 HeaderValue::from_static("strict-origin-when-cross-origin"),
@@ -105,17 +126,160 @@ where
     }
 }
 
-/// Rate limiting middleware (basic implementation)
-pub struct RateLimiting {
-    max_requests_per_minute: u32,
+/// Per-client request limiter for one group of routes, reporting the
+/// client's remaining budget with each allowed request
+type ClientLimiter = RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock, StateInformationMiddleware>;
+
+/// Header with a hint to slow down, sent in soft mode once a client is in
+/// the last tenth of its budget
+const RATE_LIMIT_WARNING: HeaderName = HeaderName::from_static("x-ratelimit-warning");
+
+/// Request quotas shared by every worker's `RateLimiting` middleware.
+///
+/// Requests are counted per client IP. Routes given their own bucket are
+/// counted separately and don't use up the default quota. Requests from a
+/// client app outside the standard tier count against that tier's quota.
+pub struct RateLimits {
+    default: ClientLimiter,
+    /// Default quota of the other tiers, scaled from the standard one
+    tiers: Vec<(RateLimitTier, ClientLimiter)>,
+    buckets: Vec<(&'static str, ClientLimiter)>,
+    /// Warn clients nearing their limit before they get 429s
+    soft_warnings: bool,
 }
 
-impl RateLimiting {
+/// Where a client stands in the quota of the bucket serving its request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Budget {
+    /// Requests allowed per minute
+    limit: u32,
+    /// Requests the client can still make right now
+    remaining: u32,
+    /// Seconds until the whole quota is available again
+    reset_secs: u64,
+}
+
+impl Budget {
+    fn allowed(snapshot: &StateSnapshot) -> Self {
+        let quota = snapshot.quota();
+        let limit = quota.burst_size().get();
+        let remaining = snapshot.remaining_burst_capacity().min(limit);
+        Self { limit, remaining, reset_secs: ceil_secs(quota.replenish_interval() * (limit - remaining)) }
+    }
+
+    /// Refused: nothing remains, and the quota refills one request after `wait`
+    fn exhausted(quota: Quota, wait: Duration) -> Self {
+        let limit = quota.burst_size().get();
+        Self { limit, remaining: 0, reset_secs: ceil_secs(wait + quota.replenish_interval() * (limit - 1)) }
+    }
+
+    /// In the last tenth of the quota
+    fn nearly_spent(&self) -> bool {
+        self.remaining * 10 <= self.limit
+    }
+
+    fn headers(&self) -> [(HeaderName, HeaderValue); 3] {
+        [
+            (HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(self.limit)),
+            (HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(self.remaining)),
+            (HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(self.reset_secs)),
+        ]
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+impl RateLimits {
     pub fn new(max_requests_per_minute: u32) -> Self {
+        let tiers = RateLimitTier::ALL
+            .into_iter()
+            .filter(|tier| *tier != RateLimitTier::Standard)
+            .map(|tier| (tier, per_minute_limiter(tier.per_minute(max_requests_per_minute))))
+            .collect();
         Self {
-            max_requests_per_minute,
+            default: per_minute_limiter(max_requests_per_minute),
+            tiers,
+            buckets: Vec::new(),
+            soft_warnings: false,
         }
     }
+
+    /// Give `path` its own quota, separate from the default one
+    pub fn with_bucket(mut self, path: &'static str, max_requests_per_minute: u32) -> Self {
+        self.buckets.push((path, per_minute_limiter(max_requests_per_minute)));
+        self
+    }
+
+    /// Add `X-RateLimit-Warning` to responses once a client has used 90% of its quota
+    pub fn with_soft_warnings(mut self, enabled: bool) -> Self {
+        self.soft_warnings = enabled;
+        self
+    }
+
+    /// Count the request from a client app in `tier`. `Err` also carries the
+    /// seconds until the client may retry
+    fn check(&self, path: &str, client_ip: &str, tier: RateLimitTier) -> Result<Budget, (Budget, u64)> {
+        let path = path.trim_end_matches('/');
+        let limiter = self
+            .buckets
+            .iter()
+            .find(|(bucket_path, _)| *bucket_path == path)
+            .map(|(_, limiter)| limiter)
+            .or_else(|| self.tiers.iter().find(|(listed, _)| *listed == tier).map(|(_, limiter)| limiter))
+            .unwrap_or(&self.default);
+
+        // IPv6 clients share a budget across their /64
+        match limiter.check_key(&client_network(client_ip)) {
+            Ok(snapshot) => Ok(Budget::allowed(&snapshot)),
+            Err(not_until) => {
+                let wait = not_until.wait_time_from(DefaultClock::default().now());
+                Err((Budget::exhausted(not_until.quota(), wait), wait.as_secs().max(1)))
+            }
+        }
+    }
+
+    /// Rate limit headers for a response, plus the warning in soft mode
+    fn apply(&self, budget: &Budget, headers: &mut header::HeaderMap) {
+        for (name, value) in budget.headers() {
+            headers.insert(name, value);
+        }
+        if self.soft_warnings && budget.nearly_spent() {
+            let warning = format!("{} of {} requests left; slow down to avoid 429 responses", budget.remaining, budget.limit);
+            if let Ok(value) = HeaderValue::from_str(&warning) {
+                headers.insert(RATE_LIMIT_WARNING, value);
+            }
+        }
+    }
+}
+
+fn per_minute_limiter(max_requests_per_minute: u32) -> ClientLimiter {
+    let quota = Quota::per_minute(NonZeroU32::new(max_requests_per_minute).unwrap_or(NonZeroU32::MIN));
+    RateLimiter::keyed(quota).with_middleware::<StateInformationMiddleware>()
+}
+
+/// Routes whose failures `AuthService` already counts in the throttle state
+const SERVICE_COUNTED_PATHS: &[&str] = &["/api/auth/login", "/api/v2/auth/login", "/api/v2/auth/login/2fa"];
+
+/// Rate limiting middleware - answers 429 once a client exceeds its quota or
+/// the shared throttle state has blocked it, and counts 401s against the client
+pub struct RateLimiting {
+    limits: Arc<RateLimits>,
+    throttle: Arc<ThrottleState>,
+    clients: Option<Arc<ClientRegistry>>,
+}
+
+impl RateLimiting {
+    pub fn new(limits: Arc<RateLimits>, throttle: Arc<ThrottleState>) -> Self {
+        Self { limits, throttle, clients: None }
+    }
+
+    /// Count requests against the tier of the client app they name in `X-Client-Id`
+    pub fn with_clients(mut self, clients: Arc<ClientRegistry>) -> Self {
+        self.clients = Some(clients);
+        self
+    }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for RateLimiting
@@ -124,7 +288,7 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = RateLimitingMiddleware<S>;
@@ -133,14 +297,18 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(RateLimitingMiddleware {
             service: Rc::new(service),
-            max_requests: self.max_requests_per_minute,
+            limits: self.limits.clone(),
+            throttle: self.throttle.clone(),
+            clients: self.clients.clone(),
         }))
     }
 }
 
 pub struct RateLimitingMiddleware<S> {
     service: Rc<S>,
-    max_requests: u32,
+    limits: Arc<RateLimits>,
+    throttle: Arc<ThrottleState>,
+    clients: Option<Arc<ClientRegistry>>,
 }
 
 impl<S, B> Service<ServiceRequest> for RateLimitingMiddleware<S>
@@ -149,7 +317,7 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -157,20 +325,64 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
+        let limits = self.limits.clone();
+        let throttle = self.throttle.clone();
+        let tier = self
+            .clients
+            .as_ref()
+            .map(|clients| clients.for_request(req.headers()).rate_limit_tier)
+            .unwrap_or_default();
 
         Box::pin(async move {
-            // Basic rate limiting check would go here
-            // In a production environment, you'd integrate with Redis or similar
-
-            let client_ip = req.connection_info().peer_addr().unwrap_or("unknown").to_string();
+            let context_ip = req.extensions().get::<RequestContext>().map(|ctx| ctx.ip_address.clone());
+            let client_ip = context_ip
+                .unwrap_or_else(|| req.connection_info().peer_addr().unwrap_or("unknown").to_string());
+
+            let (budget, blocked) = match limits.check(req.path(), &client_ip, tier) {
+                Err((budget, retry_after)) => (budget, Decision::Throttle { retry_after_secs: retry_after }),
+                Ok(budget) => (budget, throttle.check(ThrottleScope::Ip, &client_ip).await),
+            };
+
+            match blocked {
+                Decision::Allow => {}
+                Decision::Throttle { retry_after_secs } => {
+                    log::warn!("Rate limit exceeded by {} on {}", client_ip, req.path());
+
+                    let mut response = HttpResponse::TooManyRequests()
+                        .insert_header((header::RETRY_AFTER, retry_after_secs.to_string()))
+                        .json(json!({
+                            "success": false,
+                            "message": "Too many requests. Please slow down and try again shortly",
+                            "error_code": "rate_limited",
+                        }));
+                    limits.apply(&budget, response.headers_mut());
+
+                    return Ok(req.into_response(response).map_into_right_body());
+                }
+                Decision::Lock { retry_after_secs } => {
+                    log::warn!("Locked client {} refused on {}", client_ip, req.path());
+
+                    let response = HttpResponse::build(actix_web::http::StatusCode::LOCKED)
+                        .insert_header((header::RETRY_AFTER, retry_after_secs.to_string()))
+                        .json(json!({
+                            "success": false,
+                            "message": "Account is temporarily locked due to too many failed attempts",
+                            "error_code": "AccountLocked",
+                        }));
+
+                    return Ok(req.into_response(response).map_into_right_body());
+                }
+            }
 
-            // For now, just log the request
-            log::debug!("Request from IP: {} to path: {}", client_ip, req.path());
+            let counted_by_service = SERVICE_COUNTED_PATHS.contains(&req.path().trim_end_matches('/'));
+            let mut res = svc.call(req).await?;
+            limits.apply(&budget, res.headers_mut());
 
-            // TODO: Implement actual rate limiting logic
-            // For production, consider using governor crate with Redis backend
+            if res.status() == actix_web::http::StatusCode::UNAUTHORIZED && !counted_by_service {
+                throttle.record_failure(ThrottleScope::Ip, &client_ip).await;
+            }
 
-            svc.call(req).await
+            Ok(res.map_into_left_body())
         })
     }
 }
@@ -227,6 +439,11 @@ where
                 .and_then(|ua| ua.to_str().ok())
                 .unwrap_or("unknown")
                 .to_string();
+            let request_id = req
+                .extensions()
+                .get::<RequestContext>()
+                .map(|ctx| ctx.request_id.clone())
+                .unwrap_or_else(|| "-".to_string());
 
             let result = svc.call(req).await;
 
@@ -236,25 +453,27 @@ where
                     let status = res.status();
 
                     log::info!(
-                        "{} {} {} - {} - {}ms - User-Agent: {}",
+                        "{} {} {} - {} - {}ms - User-Agent: {} - Request: {}",
                         client_ip,
                         method,
                         path,
                         status,
                         duration.as_millis(),
-                        user_agent
+                        user_agent,
+                        request_id
                     );
                 }
                 Err(err) => {
                     let duration = start_time.elapsed();
                     log::error!(
-                        "{} {} {} - ERROR: {} - {}ms - User-Agent: {}",
+                        "{} {} {} - ERROR: {} - {}ms - User-Agent: {} - Request: {}",
                         client_ip,
                         method,
                         path,
                         err,
                         duration.as_millis(),
-                        user_agent
+                        user_agent,
+                        request_id
                     );
                 }
             }
@@ -262,4 +481,149 @@ where
             result
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_verify_bucket_is_separate_from_default_quota() {
+        let limits = Arc::new(RateLimits::new(2).with_bucket("/api/auth/verify", 4));
+        let app = test::init_service(
+            App::new()
+                .wrap(RateLimiting::new(limits, Arc::new(ThrottleState::default())))
+                .route("/api/auth/verify", web::get().to(ok))
+                .route("/api/auth/login-history", web::get().to(ok)),
+        )
+        .await;
+
+        let get = |path: &str| {
+            test::TestRequest::get()
+                .uri(path)
+                .peer_addr("10.0.0.1:4000".parse().unwrap())
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            assert_eq!(test::call_service(&app, get("/api/auth/login-history")).await.status(), 200);
+        }
+        let limited = test::call_service(&app, get("/api/auth/login-history")).await;
+        assert_eq!(limited.status(), 429);
+        assert!(limited.headers().contains_key(header::RETRY_AFTER));
+
+        // Polling keeps working after the default quota is spent, up to its own limit
+        for _ in 0..4 {
+            assert_eq!(test::call_service(&app, get("/api/auth/verify")).await.status(), 200);
+        }
+        assert_eq!(test::call_service(&app, get("/api/auth/verify")).await.status(), 429);
+    }
+
+    async fn unauthorized() -> HttpResponse {
+        HttpResponse::Unauthorized().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_unauthorized_responses_count_against_the_client() {
+        use crate::services::throttle_state::{ScopeLimits, ThrottleConfig};
+        use std::time::Duration;
+
+        let throttle = Arc::new(ThrottleState::new(ThrottleConfig {
+            ip: ScopeLimits { max_failures: 2, window: Duration::from_secs(60), block_for: Duration::from_secs(60) },
+            ..ThrottleConfig::default()
+        }));
+        let app = test::init_service(
+            App::new()
+                .wrap(RateLimiting::new(Arc::new(RateLimits::new(100)), throttle.clone()))
+                .route("/api/auth/verify", web::get().to(unauthorized))
+                .route("/", web::get().to(ok)),
+        )
+        .await;
+
+        let get = |path: &str, ip: &str| test::TestRequest::get().uri(path).peer_addr(ip.parse().unwrap()).to_request();
+
+        for _ in 0..2 {
+            assert_eq!(test::call_service(&app, get("/api/auth/verify", "10.0.0.1:4000")).await.status(), 401);
+        }
+        assert_eq!(throttle.failures(ThrottleScope::Ip, "10.0.0.1").await, 2);
+
+        let throttled = test::call_service(&app, get("/", "10.0.0.1:4000")).await;
+        assert_eq!(throttled.status(), 429);
+        assert!(throttled.headers().contains_key(header::RETRY_AFTER));
+        assert_eq!(test::call_service(&app, get("/", "10.0.0.2:4000")).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_budget_headers_walk_a_bucket_from_full_to_empty() {
+        let limits = Arc::new(RateLimits::new(10).with_soft_warnings(true));
+        let app = test::init_service(
+            App::new().wrap(RateLimiting::new(limits, Arc::new(ThrottleState::default()))).route("/", web::get().to(ok)),
+        )
+        .await;
+        let header = |response: &ServiceResponse<_>, name: &str| {
+            response.headers().get(name).map(|value| value.to_str().unwrap().to_string())
+        };
+
+        // Ten a minute: one request back every six seconds
+        for used in 1..=10u64 {
+            let req = test::TestRequest::get().uri("/").peer_addr("10.0.0.1:4000".parse().unwrap()).to_request();
+            let response = test::call_service(&app, req).await;
+            assert_eq!(response.status(), 200);
+            assert_eq!(header(&response, "x-ratelimit-limit").as_deref(), Some("10"));
+            assert_eq!(header(&response, "x-ratelimit-remaining"), Some((10 - used).to_string()));
+            assert_eq!(header(&response, "x-ratelimit-reset"), Some((used * 6).to_string()));
+            // Only the last tenth of the budget carries the warning
+            assert_eq!(header(&response, "x-ratelimit-warning").is_some(), used >= 9, "after {} requests", used);
+        }
+
+        let req = test::TestRequest::get().uri("/").peer_addr("10.0.0.1:4000".parse().unwrap()).to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(header(&response, "x-ratelimit-remaining").as_deref(), Some("0"));
+        let retry_after: u64 = header(&response, "retry-after").unwrap().parse().unwrap();
+        let reset: u64 = header(&response, "x-ratelimit-reset").unwrap().parse().unwrap();
+        assert!(retry_after <= 6);
+        assert_eq!(reset, 60);
+    }
+
+    #[actix_web::test]
+    async fn test_no_warning_header_without_soft_mode() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RateLimiting::new(Arc::new(RateLimits::new(1)), Arc::new(ThrottleState::default())))
+                .route("/", web::get().to(ok)),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/").peer_addr("10.0.0.1:4000".parse().unwrap()).to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.headers().get("x-ratelimit-remaining").unwrap(), "0");
+        assert!(!response.headers().contains_key("x-ratelimit-warning"));
+    }
+
+    #[actix_web::test]
+    async fn test_client_app_tiers_have_quotas_of_their_own() {
+        let limits = RateLimits::new(8).with_bucket("/api/auth/verify", 3);
+        let spend = |tier: RateLimitTier, path: &str| (0..40).take_while(|_| limits.check(path, "10.0.0.1", tier).is_ok()).count();
+
+        assert_eq!(spend(RateLimitTier::Restricted, "/"), 2);
+        assert_eq!(spend(RateLimitTier::Standard, "/"), 8);
+        assert_eq!(spend(RateLimitTier::Elevated, "/"), 32);
+        // Routes with a bucket of their own keep it, whatever the tier
+        assert_eq!(spend(RateLimitTier::Elevated, "/api/auth/verify"), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_quotas_are_per_client() {
+        let limits = Arc::new(RateLimits::new(1));
+        let app = test::init_service(App::new().wrap(RateLimiting::new(limits, Arc::new(ThrottleState::default()))).route("/", web::get().to(ok))).await;
+
+        for ip in ["10.0.0.1:4000", "10.0.0.2:4000"] {
+            let req = test::TestRequest::get().uri("/").peer_addr(ip.parse().unwrap()).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use crate::services::phase_timings;
+
+/// Server-Timing middleware - collects the phase breakdown of the operations
+/// a request ran (2FA setup, password changes) and sends it in a
+/// `Server-Timing` header, so browser performance tools show where the time
+/// went. Only installed while `SERVER_TIMING_ENABLED` is set: the header
+/// tells any caller how long its password took to hash.
+pub struct ServerTiming;
+
+impl<S, B> Transform<S, ServiceRequest> for ServerTiming
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ServerTimingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ServerTimingMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct ServerTimingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ServerTimingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+
+        Box::pin(async move {
+            let (result, breakdowns) = phase_timings::collect(svc.call(req)).await;
+            let mut res = result?;
+            if !breakdowns.is_empty() {
+                let entries: Vec<String> = breakdowns.iter().map(|breakdown| breakdown.server_timing()).collect();
+                if let Ok(value) = HeaderValue::from_str(&entries.join(", ")) {
+                    res.headers_mut().insert(HeaderName::from_static("server-timing"), value);
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{read_body_json, TestRequest};
+    use serde_json::json;
+
+    use crate::models::user::UserRole;
+    use crate::test_support::{bearer, TestApp, TEST_PASSWORD};
+
+    /// Each `name;dur=…` entry of a `Server-Timing` header, in milliseconds
+    fn entries(header: &str) -> Vec<(String, f64)> {
+        header
+            .split(", ")
+            .map(|entry| {
+                let mut params = entry.split(';');
+                let name = params.next().unwrap().to_string();
+                let dur = params.find_map(|param| param.strip_prefix("dur=")).unwrap().parse().unwrap();
+                (name, dur)
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_measured_endpoints_send_their_phase_breakdown_when_enabled() {
+        let app = TestApp::spawn_with_server_timing().await;
+        let user = app.create_user("timed_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+
+        let prepare = app.call(bearer(TestRequest::get().uri("/api/auth/2fa/prepare"), &token)).await;
+        assert_eq!(prepare.status(), 200);
+        let header = prepare.headers().get("server-timing").expect("no Server-Timing header").to_str().unwrap();
+        let names: Vec<String> = entries(header).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["db", "rendering", "encoding", "total"]);
+        assert!(header.ends_with(r#"desc="prepare_two_fa_setup""#), "{}", header);
+
+        let change = bearer(TestRequest::post().uri("/api/auth/change-password"), &token).set_json(json!({
+            "current_password": TEST_PASSWORD,
+            "new_password": "Timed-Change-Passw0rd!",
+            "confirm_password": "Timed-Change-Passw0rd!",
+        }));
+        let response = app.call(change).await;
+        assert_eq!(response.status(), 200);
+        let header = response.headers().get("server-timing").unwrap().to_str().unwrap().to_string();
+        let body: serde_json::Value = read_body_json(response).await;
+        let mut timings = entries(&header);
+        let (total_name, total) = timings.pop().unwrap();
+        assert_eq!(total_name, "total");
+        let names: Vec<&str> = timings.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["db", "hashing"]);
+
+        // Nearly all of a password change is Argon2 and the database; what
+        // falls between phases is small next to them
+        let phases: f64 = timings.iter().map(|(_, dur)| dur).sum();
+        assert!(phases <= total + 0.05, "{}", header);
+        assert!(total - phases < (total * 0.25).max(10.0), "{}", header);
+
+        // Unmeasured endpoints carry nothing, and both operations were counted
+        let renewed = body["data"]["token"].as_str().unwrap();
+        let verify = app.call(bearer(TestRequest::get().uri("/api/auth/verify"), renewed)).await;
+        assert_eq!(verify.status(), 200);
+        assert!(verify.headers().get("server-timing").is_none());
+        let histograms = app.auth_service().operation_timings();
+        let totals: Vec<(&str, u64)> =
+            histograms.iter().filter(|h| h.phase == "total").map(|h| (h.operation, h.count)).collect();
+        assert_eq!(totals, [("prepare_two_fa_setup", 1), ("change_password", 1)]);
+    }
+
+    #[actix_web::test]
+    async fn test_no_header_unless_enabled() {
+        let app = TestApp::spawn().await;
+        let user = app.create_user("untimed_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let token = app.login_as(&user, "10.0.0.1").await;
+
+        let prepare = app.call(bearer(TestRequest::get().uri("/api/auth/2fa/prepare"), &token)).await;
+        assert_eq!(prepare.status(), 200);
+        assert!(prepare.headers().get("server-timing").is_none());
+        // The histograms are kept either way
+        assert_eq!(app.auth_service().operation_timings()[0].count, 1);
+    }
+}
@@ -0,0 +1,485 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::models::audit_event::AuditEventType;
+use crate::models::auth::Severity;
+use crate::models::permission::{Permission, PermissionOverride};
+use crate::models::user::UserResponse;
+use crate::services::account_notes_service::AccountNote;
+use crate::middleware::origin_guard::validate_origin;
+use crate::services::admin_action_service::LinkedAction;
+use crate::services::client_app_service::{ClientAppDraft, RateLimitTier};
+use crate::services::login_queue::LoginQueueDepth;
+use crate::services::phase_timings::TimingHistogram;
+use crate::services::session_events::EventQueueDepth;
+use crate::services::session_service::SessionGauges;
+use crate::services::system_message_service::{markup_problem, MessageDraft};
+use crate::services::throttle_state::ThrottleCounts;
+use crate::services::verify_monitor::VerifyCounts;
+use crate::services::webhook_service::NotificationQueueDepth;
+use crate::utils::db_retry::BusyRetryCounts;
+
+/// Maintenance mode toggle request
+#[derive(Debug, Deserialize, Validate)]
+pub struct MaintenanceToggleRequest {
+    pub enabled: bool,
+
+    #[validate(length(max = 500, message = "Maintenance message must be at most 500 characters"))]
+    pub message: Option<String>,
+
+    /// Expected end of the maintenance window, used for `Retry-After`
+    pub eta: Option<DateTime<Utc>>,
+}
+
+/// Emergency lockdown request. A second administrator approves with a
+/// request of their own, whose reason and duration are ignored.
+#[derive(Debug, Deserialize, Validate)]
+pub struct LockdownRequestBody {
+    #[validate(length(min = 1, max = 500, message = "Reason must be between 1 and 500 characters"))]
+    pub reason: String,
+
+    /// Omitted for the configured `LOCKDOWN_DURATION_MINUTES`
+    #[validate(range(min = 1, max = 1440, message = "Lockdown duration must be between 1 minute and 24 hours"))]
+    pub duration_minutes: Option<i64>,
+}
+
+/// Administrative account lock request
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct LockUserRequest {
+    /// Lock length; omitted for a lock that holds until an admin lifts it
+    #[validate(range(min = 1, max = 525600, message = "Lock duration must be between 1 minute and 1 year"))]
+    pub duration_minutes: Option<i64>,
+
+    #[validate(length(max = 500, message = "Reason must be at most 500 characters"))]
+    pub reason: Option<String>,
+}
+
+/// Security event acknowledgement request
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct AcknowledgeEventRequest {
+    #[validate(length(max = 1000, message = "Resolution note must be at most 1000 characters"))]
+    pub resolution_note: Option<String>,
+}
+
+/// Replacement set of per-user permission overrides; an empty list restores the role defaults
+#[derive(Debug, Deserialize)]
+pub struct SetPermissionsRequest {
+    pub overrides: Vec<PermissionOverride>,
+}
+
+impl SetPermissionsRequest {
+    /// A permission overridden more than once
+    pub fn duplicate(&self) -> Option<Permission> {
+        self.overrides
+            .iter()
+            .enumerate()
+            .find(|(i, o)| self.overrides[..*i].iter().any(|earlier| earlier.permission == o.permission))
+            .map(|(_, o)| o.permission)
+    }
+}
+
+/// Move a user into an organization, or out of any with `null`
+#[derive(Debug, Deserialize)]
+pub struct SetOrganizationRequest {
+    pub organization: Option<String>,
+}
+
+/// Audit listing query parameters
+#[derive(Debug, Deserialize)]
+pub struct AuditEventsQuery {
+    pub unacknowledged: Option<bool>,
+    pub severity: Option<Severity>,
+    pub limit: Option<i64>,
+    /// Export file format; CSV unless given
+    pub format: Option<ExportFormat>,
+    /// Export as a signed zip bundle instead of a bare file
+    pub bundle: Option<bool>,
+    /// Include `ADMIN_READ` events, which are left out unless asked for
+    pub include_reads: Option<bool>,
+    /// Only events of this type; parsed by the handler so unknown names get the list of valid ones
+    pub event_type: Option<String>,
+    /// Only events at or after this time, RFC 3339
+    pub from: Option<DateTime<Utc>>,
+    /// Only events before this time, RFC 3339
+    pub to: Option<DateTime<Utc>>,
+    /// Read the monthly archive files too, where `AUDIT_ARCHIVE_READS` allows it; listing only
+    pub include_archives: Option<bool>,
+}
+
+/// File format of an audit export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+/// User listing query parameters
+#[derive(Debug, Deserialize, Validate)]
+pub struct UsersQuery {
+    pub onboarded: Option<bool>,
+    /// Only accounts carrying this tag
+    pub tag: Option<String>,
+    pub locked: Option<bool>,
+    pub two_fa_enabled: Option<bool>,
+    pub temporary_password: Option<bool>,
+    #[validate(length(min = 1, max = 100, message = "Organization must be between 1 and 100 characters"))]
+    pub organization: Option<String>,
+    /// Stored role name, `admin` or `kenya_government`
+    pub role: Option<String>,
+    pub active: Option<bool>,
+    /// Substring of the username or email, ignoring case
+    #[validate(length(min = 1, max = 100, message = "Search must be between 1 and 100 characters"))]
+    pub q: Option<String>,
+}
+
+/// New helpdesk note on an account
+#[derive(Debug, Deserialize, Validate)]
+pub struct AddAccountNoteRequest {
+    #[validate(length(min = 1, max = 2000, message = "Note must be between 1 and 2000 characters"))]
+    pub note: String,
+
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// Replacement set of tags on an account; an empty list removes them all
+#[derive(Debug, Deserialize)]
+pub struct SetUserTagsRequest {
+    pub tags: Vec<String>,
+}
+
+/// A login screen notice, new or replacing an existing one
+#[derive(Debug, Deserialize, Validate)]
+pub struct SystemMessageRequest {
+    #[validate(length(min = 1, max = 500, message = "Message must be between 1 and 500 characters"))]
+    #[validate(custom(function = "validate_message_markup"))]
+    pub body: String,
+
+    #[serde(default)]
+    pub severity: Severity,
+
+    /// When the message starts showing; now unless given
+    pub starts_at: Option<DateTime<Utc>>,
+
+    pub ends_at: DateTime<Utc>,
+}
+
+impl SystemMessageRequest {
+    /// The message to store, its window starting at `now` unless given
+    pub fn into_draft(self, now: DateTime<Utc>) -> Result<MessageDraft, &'static str> {
+        let starts_at = self.starts_at.unwrap_or(now);
+        if self.ends_at <= starts_at {
+            return Err("ends_at must be after starts_at");
+        }
+        Ok(MessageDraft { body: self.body, severity: self.severity, starts_at, ends_at: self.ends_at })
+    }
+}
+
+fn validate_message_markup(body: &str) -> Result<(), validator::ValidationError> {
+    match markup_problem(body) {
+        Some(problem) => Err(validator::ValidationError::new("markup").with_message(problem.into())),
+        None => Ok(()),
+    }
+}
+
+/// A client app's settings, new or replacing an existing app's
+#[derive(Debug, Deserialize, Validate)]
+pub struct ClientAppRequest {
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+
+    /// Browser origins, as `https://host[:port]`
+    #[serde(default)]
+    #[validate(length(max = 20, message = "At most 20 origins can be allowed"))]
+    pub allowed_origins: Vec<String>,
+
+    #[validate(length(min = 1, max = 100, message = "Token audience must be between 1 and 100 characters"))]
+    pub token_audience: String,
+
+    #[serde(default)]
+    pub rate_limit_tier: RateLimitTier,
+
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl ClientAppRequest {
+    /// The settings to store, or why an origin can't be used
+    pub fn into_draft(self) -> Result<ClientAppDraft, String> {
+        for origin in &self.allowed_origins {
+            validate_origin(origin).map_err(|e| e.to_string())?;
+        }
+        Ok(ClientAppDraft {
+            name: self.name,
+            allowed_origins: self.allowed_origins,
+            token_audience: self.token_audience,
+            rate_limit_tier: self.rate_limit_tier,
+            enabled: self.enabled,
+        })
+    }
+}
+
+/// A client app to register under `id`
+#[derive(Debug, Deserialize, Validate)]
+pub struct NewClientAppRequest {
+    #[validate(length(min = 1, max = 64, message = "Client ID must be between 1 and 64 characters"))]
+    #[validate(custom(function = "validate_client_id"))]
+    pub id: String,
+
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub app: ClientAppRequest,
+}
+
+/// Client IDs travel in a header and in URLs, so they are kept to
+/// lowercase letters, digits, `-` and `_`
+fn validate_client_id(id: &str) -> Result<(), validator::ValidationError> {
+    if id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(validator::ValidationError::new("client_id")
+            .with_message("Client ID may only contain lowercase letters, digits, '-' and '_'".into()))
+    }
+}
+
+/// An account as shown to administrators, with its tags and notes
+#[derive(Debug, Serialize)]
+pub struct AdminUserDetail {
+    pub user: UserResponse,
+    pub notes: Vec<AccountNote>,
+}
+
+/// CSP report listing query parameters
+#[derive(Debug, Deserialize)]
+pub struct CspReportsQuery {
+    pub limit: Option<i64>,
+}
+
+/// Webhook dead-letter listing query parameters
+#[derive(Debug, Deserialize)]
+pub struct DeadLettersQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Request to send a one-time link that carries out an admin action
+#[derive(Debug, Deserialize)]
+pub struct AdminActionRequest {
+    pub action: LinkedAction,
+    pub target_user_id: Uuid,
+    /// Administrator the link goes to; the requester when omitted
+    pub approver_id: Option<Uuid>,
+}
+
+/// Configuration history query parameters
+#[derive(Debug, Deserialize)]
+pub struct ConfigHistoryQuery {
+    pub limit: Option<i64>,
+}
+
+/// Time window for event statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StatsWindow {
+    #[serde(rename = "1h")]
+    Hour,
+    #[default]
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl StatsWindow {
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            StatsWindow::Hour => chrono::Duration::hours(1),
+            StatsWindow::Day => chrono::Duration::hours(24),
+            StatsWindow::Week => chrono::Duration::days(7),
+            StatsWindow::Month => chrono::Duration::days(30),
+        }
+    }
+}
+
+/// Bucket size for time series statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsBucket {
+    Hour,
+    Day,
+}
+
+/// Event statistics query parameters
+#[derive(Debug, Deserialize)]
+pub struct EventStatsQuery {
+    pub window: Option<StatsWindow>,
+    pub group_by: Option<StatsBucket>,
+}
+
+/// Count for one grouping key
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct KeyCount {
+    pub key: String,
+    pub count: i64,
+}
+
+/// Security event counts over a time window
+#[derive(Debug, Serialize)]
+pub struct EventStats {
+    pub window: StatsWindow,
+    pub since: DateTime<Utc>,
+    pub by_event_type: Vec<KeyCount>,
+    pub by_failure_code: Vec<KeyCount>,
+    pub failed_login_distinct_ips: i64,
+    pub failed_login_distinct_usernames: i64,
+    /// Events per time bucket, oldest first; present when `group_by` was given
+    pub series: Option<Vec<KeyCount>>,
+    /// Token verification outcomes since startup. Successes are only sampled
+    /// into the event log, so these in-memory counts are the exact figures.
+    pub token_validations: Option<VerifyCounts>,
+    /// Login hashing queue occupancy at the time of the request
+    pub login_queue: Option<LoginQueueDepth>,
+    /// Keys the shared throttle state is blocking at the time of the request
+    pub throttled: Option<ThrottleCounts>,
+    /// Requests refused for an unlisted Origin since startup
+    pub cors_rejections: Option<u64>,
+    /// Database writes retried, or given up on, because SQLite reported them busy
+    pub db_busy_retries: Option<BusyRetryCounts>,
+    /// Passwords Argon2 failed to hash since startup; anything above zero is critical
+    pub hashing_failures: Option<u64>,
+    /// Tokens accepted since startup although issued ahead of the clock, within the leeway
+    pub clock_skew_tolerated: Option<u64>,
+    /// Session event stream queues at the time of the request
+    pub event_queues: Option<EventQueueDepth>,
+    /// Outbound notification queues at the time of the request
+    pub notification_queue: Option<NotificationQueueDepth>,
+    /// Phase durations of 2FA setup and password changes since startup, by operation and phase
+    pub operation_timings: Option<Vec<TimingHistogram>>,
+}
+
+/// Paging for the activity of one client address
+#[derive(Debug, Deserialize)]
+pub struct IpActivityQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// One row of the merged per-address view: a login attempt or another security event
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct IpActivityEntry {
+    /// `login_attempt` or `security_event`
+    pub source: String,
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub user_id: Option<Uuid>,
+    /// Username tried, for login attempts
+    pub username: Option<String>,
+    pub event_type: AuditEventType,
+    /// Failure reason for login attempts, description for security events
+    pub description: Option<String>,
+    pub success: bool,
+    pub user_agent: Option<String>,
+}
+
+/// An account targeted from an address and locked at or after the first attempt from it
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct LockedAccount {
+    pub user_id: Uuid,
+    pub username: String,
+    pub locked_at: DateTime<Utc>,
+}
+
+/// What an address did, at a glance
+#[derive(Debug, Serialize)]
+pub struct IpCorrelation {
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub login_successes: i64,
+    pub login_failures: i64,
+    /// Distinct usernames tried, known or not
+    pub usernames_targeted: Vec<String>,
+    pub locked_accounts: Vec<LockedAccount>,
+}
+
+/// Activity of one client address, newest first, with its correlation summary
+#[derive(Debug, Serialize)]
+pub struct IpActivity {
+    pub ip_address: String,
+    pub summary: IpCorrelation,
+    /// Entries across both sources, for paging
+    pub total: i64,
+    pub entries: Vec<IpActivityEntry>,
+}
+
+/// Database connection pool occupancy
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PoolUsage {
+    pub size: u32,
+    pub in_use: u32,
+    pub idle: u32,
+}
+
+/// Short-lived entries: throttle counters, verification failures, password
+/// change challenges and download tokens in the shared state backend, event
+/// streams and digests in this process's memory
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct EphemeralStoreUsage {
+    /// Shared state backend name: `in_process` or `redis`
+    pub backend: &'static str,
+    pub throttle_counters: usize,
+    pub verify_failure_ips: usize,
+    pub event_streams: usize,
+    /// Accounts with failed sign-ins waiting to go out as a digest
+    pub failed_login_digests: usize,
+    /// v2 logins waiting for a new password
+    pub password_change_challenges: usize,
+    /// Browser download tokens not yet used or expired
+    pub download_tokens: usize,
+    pub total: usize,
+}
+
+/// Progress of a JWT secret migration, served by `/api/admin/jwt-migration`
+#[derive(Debug, Clone, Serialize)]
+pub struct JwtMigrationStatus {
+    /// Key new tokens are signed with
+    pub current_kid: String,
+    /// Key being migrated away from; `None` when no migration is under way
+    pub previous_kid: Option<String>,
+    /// Tokens signed with the previous key are refused from then on
+    pub deadline: Option<DateTime<Utc>>,
+    /// Live sessions whose current token the current key signed
+    pub current_key_sessions: i64,
+    /// Live sessions whose current token the previous key signed; their
+    /// users are signed out at the deadline unless they come back before it
+    pub previous_key_sessions: i64,
+    /// Live sessions not used since key IDs were first recorded
+    pub unrecorded_sessions: i64,
+    /// Tokens this instance verified with the previous key since it started
+    pub previous_key_verifications: u64,
+}
+
+/// Capacity gauges for this instance, served by `/api/health/details`
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthDetails {
+    /// When the figures were computed; they are reused for a few seconds
+    pub computed_at: DateTime<Utc>,
+    pub uptime_seconds: i64,
+    #[serde(flatten)]
+    pub sessions: SessionGauges,
+    pub db_pool: PoolUsage,
+    pub ephemeral_store: EphemeralStoreUsage,
+    /// Events waiting for session event stream clients
+    pub event_queues: EventQueueDepth,
+    /// Notifications waiting for their receivers; filled in by the handler,
+    /// which holds the webhook service
+    pub notification_queue: Option<NotificationQueueDepth>,
+}
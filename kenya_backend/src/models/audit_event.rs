@@ -0,0 +1,568 @@
+use serde::{Deserialize, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef};
+use sqlx::Sqlite;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::models::auth::Severity;
+
+/// Kind of a security event, stored in `security_events.event_type` as its
+/// SCREAMING_SNAKE_CASE name.
+///
+/// Every variant needs a row in the README's event type table and a default
+/// severity; the tests below fail to compile until both are given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditEventType {
+    // Signing in and sessions
+    LoginAttempt,
+    Logout,
+    TokenValidation,
+    TokenGuessingSuspected,
+    TokenRevoked,
+    SessionReplaced,
+    StepUpAuth,
+    TwoFaAttempt,
+    TwoFaDisabled,
+    TwoFaQrRedisplayed,
+    BotSuspected,
+    LoginFromUnexpectedCountry,
+    ImpossibleTravel,
+    AccountLockout,
+    TermsAccepted,
+    DeviceLoginApproved,
+    DeviceLoginDenied,
+    KioskSessionStarted,
+    // Passwords
+    PasswordChange,
+    LoginPasswordChangeRequired,
+    LoginPasswordChanged,
+    PasswordResetRequested,
+    PasswordResetLinkIssued,
+    PasswordResetLinkInvalid,
+    PasswordResetLinkReused,
+    PasswordResetLinkExpired,
+    PasswordResetCompleted,
+    CredentialsRevoked,
+    // Break-glass account
+    BreakGlassUsed,
+    BreakGlassRefused,
+    BreakGlassProvisioned,
+    // Two-person secret rotation
+    SecretStaged,
+    SecretConfirmed,
+    SecretRejected,
+    SecretActivated,
+    // Account administration
+    AccountLocked,
+    AccountUnlocked,
+    AccountDeactivated,
+    AccountActivated,
+    PermissionsChanged,
+    UserOrganizationChanged,
+    UserTagsChanged,
+    AccountNoteAdded,
+    AccountNoteStruck,
+    SessionsTerminated,
+    OrgPolicyChanged,
+    // Accounts changed by the HR integration
+    HrAccountProvisioned,
+    HrAccountDeprovisioned,
+    HrAccountTransferred,
+    HrEventRejected,
+    // Emailed admin action links
+    AdminActionLinkSent,
+    AdminActionLinkInvalid,
+    AdminActionLinkWrongAdmin,
+    AdminActionLinkReused,
+    AdminActionLinkExpired,
+    AdminActionConfirmed,
+    // Personal data exports
+    DataExportRequested,
+    DataExportDownloaded,
+    DataExportLinkInvalid,
+    DataExportLinkExpired,
+    // Operations
+    MaintenanceModeChanged,
+    FeatureFlagsChanged,
+    LockdownRequested,
+    LockdownStarted,
+    LockdownEnded,
+    SystemMessageCreated,
+    SystemMessageUpdated,
+    SystemMessageDeleted,
+    ClientAppCreated,
+    ClientAppUpdated,
+    ClientAppDeleted,
+    UnknownClientApp,
+    DatabaseBackup,
+    HealthStateChanged,
+    NotificationDigestSent,
+    // The audit log itself
+    AuditExported,
+    SecurityEventAcknowledged,
+    AdminRead,
+    /// A stored name no variant matches, even after `from_stored` cleaned it up.
+    /// Only ever read back, never written or filtered on.
+    Unrecognized,
+}
+
+impl AuditEventType {
+    /// Every event type that can be written, in declaration order
+    pub const ALL: [AuditEventType; 79] = [
+        AuditEventType::LoginAttempt,
+        AuditEventType::Logout,
+        AuditEventType::TokenValidation,
+        AuditEventType::TokenGuessingSuspected,
+        AuditEventType::TokenRevoked,
+        AuditEventType::SessionReplaced,
+        AuditEventType::StepUpAuth,
+        AuditEventType::TwoFaAttempt,
+        AuditEventType::TwoFaDisabled,
+        AuditEventType::TwoFaQrRedisplayed,
+        AuditEventType::BotSuspected,
+        AuditEventType::LoginFromUnexpectedCountry,
+        AuditEventType::ImpossibleTravel,
+        AuditEventType::AccountLockout,
+        AuditEventType::TermsAccepted,
+        AuditEventType::DeviceLoginApproved,
+        AuditEventType::DeviceLoginDenied,
+        AuditEventType::KioskSessionStarted,
+        AuditEventType::PasswordChange,
+        AuditEventType::LoginPasswordChangeRequired,
+        AuditEventType::LoginPasswordChanged,
+        AuditEventType::PasswordResetRequested,
+        AuditEventType::PasswordResetLinkIssued,
+        AuditEventType::PasswordResetLinkInvalid,
+        AuditEventType::PasswordResetLinkReused,
+        AuditEventType::PasswordResetLinkExpired,
+        AuditEventType::PasswordResetCompleted,
+        AuditEventType::CredentialsRevoked,
+        AuditEventType::BreakGlassUsed,
+        AuditEventType::BreakGlassRefused,
+        AuditEventType::BreakGlassProvisioned,
+        AuditEventType::SecretStaged,
+        AuditEventType::SecretConfirmed,
+        AuditEventType::SecretRejected,
+        AuditEventType::SecretActivated,
+        AuditEventType::AccountLocked,
+        AuditEventType::AccountUnlocked,
+        AuditEventType::AccountDeactivated,
+        AuditEventType::AccountActivated,
+        AuditEventType::PermissionsChanged,
+        AuditEventType::UserOrganizationChanged,
+        AuditEventType::UserTagsChanged,
+        AuditEventType::AccountNoteAdded,
+        AuditEventType::AccountNoteStruck,
+        AuditEventType::SessionsTerminated,
+        AuditEventType::OrgPolicyChanged,
+        AuditEventType::HrAccountProvisioned,
+        AuditEventType::HrAccountDeprovisioned,
+        AuditEventType::HrAccountTransferred,
+        AuditEventType::HrEventRejected,
+        AuditEventType::AdminActionLinkSent,
+        AuditEventType::AdminActionLinkInvalid,
+        AuditEventType::AdminActionLinkWrongAdmin,
+        AuditEventType::AdminActionLinkReused,
+        AuditEventType::AdminActionLinkExpired,
+        AuditEventType::AdminActionConfirmed,
+        AuditEventType::DataExportRequested,
+        AuditEventType::DataExportDownloaded,
+        AuditEventType::DataExportLinkInvalid,
+        AuditEventType::DataExportLinkExpired,
+        AuditEventType::MaintenanceModeChanged,
+        AuditEventType::FeatureFlagsChanged,
+        AuditEventType::LockdownRequested,
+        AuditEventType::LockdownStarted,
+        AuditEventType::LockdownEnded,
+        AuditEventType::SystemMessageCreated,
+        AuditEventType::SystemMessageUpdated,
+        AuditEventType::SystemMessageDeleted,
+        AuditEventType::ClientAppCreated,
+        AuditEventType::ClientAppUpdated,
+        AuditEventType::ClientAppDeleted,
+        AuditEventType::UnknownClientApp,
+        AuditEventType::DatabaseBackup,
+        AuditEventType::HealthStateChanged,
+        AuditEventType::NotificationDigestSent,
+        AuditEventType::AuditExported,
+        AuditEventType::SecurityEventAcknowledged,
+        AuditEventType::AdminRead,
+        AuditEventType::Unrecognized,
+    ];
+
+    /// Event type name as stored in the database and shown to clients
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditEventType::LoginAttempt => "LOGIN_ATTEMPT",
+            AuditEventType::Logout => "LOGOUT",
+            AuditEventType::TokenValidation => "TOKEN_VALIDATION",
+            AuditEventType::TokenGuessingSuspected => "TOKEN_GUESSING_SUSPECTED",
+            AuditEventType::TokenRevoked => "TOKEN_REVOKED",
+            AuditEventType::SessionReplaced => "SESSION_REPLACED",
+            AuditEventType::StepUpAuth => "STEP_UP_AUTH",
+            AuditEventType::TwoFaAttempt => "TWO_FA_ATTEMPT",
+            AuditEventType::TwoFaDisabled => "TWO_FA_DISABLED",
+            AuditEventType::TwoFaQrRedisplayed => "TWO_FA_QR_REDISPLAYED",
+            AuditEventType::BotSuspected => "BOT_SUSPECTED",
+            AuditEventType::LoginFromUnexpectedCountry => "LOGIN_FROM_UNEXPECTED_COUNTRY",
+            AuditEventType::ImpossibleTravel => "IMPOSSIBLE_TRAVEL",
+            AuditEventType::AccountLockout => "ACCOUNT_LOCKOUT",
+            AuditEventType::TermsAccepted => "TERMS_ACCEPTED",
+            AuditEventType::DeviceLoginApproved => "DEVICE_LOGIN_APPROVED",
+            AuditEventType::DeviceLoginDenied => "DEVICE_LOGIN_DENIED",
+            AuditEventType::KioskSessionStarted => "KIOSK_SESSION_STARTED",
+            AuditEventType::PasswordChange => "PASSWORD_CHANGE",
+            AuditEventType::LoginPasswordChangeRequired => "LOGIN_PASSWORD_CHANGE_REQUIRED",
+            AuditEventType::LoginPasswordChanged => "LOGIN_PASSWORD_CHANGED",
+            AuditEventType::PasswordResetRequested => "PASSWORD_RESET_REQUESTED",
+            AuditEventType::PasswordResetLinkIssued => "PASSWORD_RESET_LINK_ISSUED",
+            AuditEventType::PasswordResetLinkInvalid => "PASSWORD_RESET_LINK_INVALID",
+            AuditEventType::PasswordResetLinkReused => "PASSWORD_RESET_LINK_REUSED",
+            AuditEventType::PasswordResetLinkExpired => "PASSWORD_RESET_LINK_EXPIRED",
+            AuditEventType::PasswordResetCompleted => "PASSWORD_RESET_COMPLETED",
+            AuditEventType::CredentialsRevoked => "CREDENTIALS_REVOKED",
+            AuditEventType::BreakGlassUsed => "BREAK_GLASS_USED",
+            AuditEventType::BreakGlassRefused => "BREAK_GLASS_REFUSED",
+            AuditEventType::BreakGlassProvisioned => "BREAK_GLASS_PROVISIONED",
+            AuditEventType::SecretStaged => "SECRET_STAGED",
+            AuditEventType::SecretConfirmed => "SECRET_CONFIRMED",
+            AuditEventType::SecretRejected => "SECRET_REJECTED",
+            AuditEventType::SecretActivated => "SECRET_ACTIVATED",
+            AuditEventType::AccountLocked => "ACCOUNT_LOCKED",
+            AuditEventType::AccountUnlocked => "ACCOUNT_UNLOCKED",
+            AuditEventType::AccountDeactivated => "ACCOUNT_DEACTIVATED",
+            AuditEventType::AccountActivated => "ACCOUNT_ACTIVATED",
+            AuditEventType::PermissionsChanged => "PERMISSIONS_CHANGED",
+            AuditEventType::UserOrganizationChanged => "USER_ORGANIZATION_CHANGED",
+            AuditEventType::UserTagsChanged => "USER_TAGS_CHANGED",
+            AuditEventType::AccountNoteAdded => "ACCOUNT_NOTE_ADDED",
+            AuditEventType::AccountNoteStruck => "ACCOUNT_NOTE_STRUCK",
+            AuditEventType::SessionsTerminated => "SESSIONS_TERMINATED",
+            AuditEventType::OrgPolicyChanged => "ORG_POLICY_CHANGED",
+            AuditEventType::HrAccountProvisioned => "HR_ACCOUNT_PROVISIONED",
+            AuditEventType::HrAccountDeprovisioned => "HR_ACCOUNT_DEPROVISIONED",
+            AuditEventType::HrAccountTransferred => "HR_ACCOUNT_TRANSFERRED",
+            AuditEventType::HrEventRejected => "HR_EVENT_REJECTED",
+            AuditEventType::AdminActionLinkSent => "ADMIN_ACTION_LINK_SENT",
+            AuditEventType::AdminActionLinkInvalid => "ADMIN_ACTION_LINK_INVALID",
+            AuditEventType::AdminActionLinkWrongAdmin => "ADMIN_ACTION_LINK_WRONG_ADMIN",
+            AuditEventType::AdminActionLinkReused => "ADMIN_ACTION_LINK_REUSED",
+            AuditEventType::AdminActionLinkExpired => "ADMIN_ACTION_LINK_EXPIRED",
+            AuditEventType::AdminActionConfirmed => "ADMIN_ACTION_CONFIRMED",
+            AuditEventType::DataExportRequested => "DATA_EXPORT_REQUESTED",
+            AuditEventType::DataExportDownloaded => "DATA_EXPORT_DOWNLOADED",
+            AuditEventType::DataExportLinkInvalid => "DATA_EXPORT_LINK_INVALID",
+            AuditEventType::DataExportLinkExpired => "DATA_EXPORT_LINK_EXPIRED",
+            AuditEventType::MaintenanceModeChanged => "MAINTENANCE_MODE_CHANGED",
+            AuditEventType::FeatureFlagsChanged => "FEATURE_FLAGS_CHANGED",
+            AuditEventType::LockdownRequested => "LOCKDOWN_REQUESTED",
+            AuditEventType::LockdownStarted => "LOCKDOWN_STARTED",
+            AuditEventType::LockdownEnded => "LOCKDOWN_ENDED",
+            AuditEventType::SystemMessageCreated => "SYSTEM_MESSAGE_CREATED",
+            AuditEventType::SystemMessageUpdated => "SYSTEM_MESSAGE_UPDATED",
+            AuditEventType::SystemMessageDeleted => "SYSTEM_MESSAGE_DELETED",
+            AuditEventType::ClientAppCreated => "CLIENT_APP_CREATED",
+            AuditEventType::ClientAppUpdated => "CLIENT_APP_UPDATED",
+            AuditEventType::ClientAppDeleted => "CLIENT_APP_DELETED",
+            AuditEventType::UnknownClientApp => "UNKNOWN_CLIENT_APP",
+            AuditEventType::DatabaseBackup => "DATABASE_BACKUP",
+            AuditEventType::HealthStateChanged => "HEALTH_STATE_CHANGED",
+            AuditEventType::NotificationDigestSent => "NOTIFICATION_DIGEST_SENT",
+            AuditEventType::AuditExported => "AUDIT_EXPORTED",
+            AuditEventType::SecurityEventAcknowledged => "SECURITY_EVENT_ACKNOWLEDGED",
+            AuditEventType::AdminRead => "ADMIN_READ",
+            AuditEventType::Unrecognized => "UNRECOGNIZED",
+        }
+    }
+
+    /// Severity of a routine occurrence. Writers raise it for failures and
+    /// other cases that need a closer look.
+    pub fn default_severity(self) -> Severity {
+        match self {
+            AuditEventType::LoginAttempt
+            | AuditEventType::Logout
+            | AuditEventType::TokenValidation
+            | AuditEventType::SessionReplaced
+            | AuditEventType::StepUpAuth
+            | AuditEventType::TwoFaAttempt
+            | AuditEventType::TermsAccepted
+            | AuditEventType::DeviceLoginApproved
+            | AuditEventType::KioskSessionStarted
+            | AuditEventType::PasswordChange
+            | AuditEventType::LoginPasswordChangeRequired
+            | AuditEventType::LoginPasswordChanged
+            | AuditEventType::PasswordResetRequested
+            | AuditEventType::PasswordResetLinkReused
+            | AuditEventType::PasswordResetLinkExpired
+            | AuditEventType::CredentialsRevoked
+            | AuditEventType::UserTagsChanged
+            | AuditEventType::AccountNoteAdded
+            | AuditEventType::AccountNoteStruck
+            | AuditEventType::HrAccountProvisioned
+            | AuditEventType::HrAccountTransferred
+            | AuditEventType::AdminActionLinkSent
+            | AuditEventType::AdminActionLinkExpired
+            | AuditEventType::DataExportRequested
+            | AuditEventType::DataExportDownloaded
+            | AuditEventType::DataExportLinkExpired
+            | AuditEventType::SystemMessageCreated
+            | AuditEventType::SystemMessageUpdated
+            | AuditEventType::SystemMessageDeleted
+            | AuditEventType::NotificationDigestSent
+            | AuditEventType::AuditExported
+            | AuditEventType::SecurityEventAcknowledged
+            | AuditEventType::AdminRead => Severity::Info,
+            AuditEventType::TokenGuessingSuspected
+            | AuditEventType::TwoFaQrRedisplayed
+            | AuditEventType::BotSuspected
+            | AuditEventType::LoginFromUnexpectedCountry
+            | AuditEventType::DeviceLoginDenied
+            | AuditEventType::PasswordResetLinkIssued
+            | AuditEventType::PasswordResetLinkInvalid
+            | AuditEventType::PasswordResetCompleted
+            | AuditEventType::BreakGlassRefused
+            | AuditEventType::BreakGlassProvisioned
+            | AuditEventType::AccountUnlocked
+            | AuditEventType::AccountActivated
+            | AuditEventType::UserOrganizationChanged
+            | AuditEventType::HrAccountDeprovisioned
+            | AuditEventType::HrEventRejected
+            | AuditEventType::AdminActionLinkInvalid
+            | AuditEventType::AdminActionLinkReused
+            | AuditEventType::AdminActionConfirmed
+            | AuditEventType::DataExportLinkInvalid
+            | AuditEventType::MaintenanceModeChanged
+            | AuditEventType::FeatureFlagsChanged
+            | AuditEventType::LockdownRequested
+            | AuditEventType::ClientAppCreated
+            | AuditEventType::ClientAppUpdated
+            | AuditEventType::ClientAppDeleted
+            | AuditEventType::UnknownClientApp
+            | AuditEventType::DatabaseBackup
+            | AuditEventType::HealthStateChanged
+            | AuditEventType::Unrecognized => Severity::Warning,
+            AuditEventType::TokenRevoked
+            | AuditEventType::TwoFaDisabled
+            | AuditEventType::ImpossibleTravel
+            | AuditEventType::AccountLockout
+            | AuditEventType::BreakGlassUsed
+            | AuditEventType::SecretStaged
+            | AuditEventType::SecretConfirmed
+            | AuditEventType::SecretRejected
+            | AuditEventType::SecretActivated
+            | AuditEventType::AccountLocked
+            | AuditEventType::AccountDeactivated
+            | AuditEventType::PermissionsChanged
+            | AuditEventType::SessionsTerminated
+            | AuditEventType::OrgPolicyChanged
+            | AuditEventType::AdminActionLinkWrongAdmin
+            | AuditEventType::LockdownStarted
+            | AuditEventType::LockdownEnded => Severity::Critical,
+        }
+    }
+
+    /// Event types an admin can filter the audit log by
+    pub fn filterable() -> impl Iterator<Item = AuditEventType> {
+        AuditEventType::ALL.into_iter().filter(|event_type| *event_type != AuditEventType::Unrecognized)
+    }
+
+    /// Read a name back from the database. Older rows may spell a name in
+    /// another case or with dashes or spaces; those still map to their
+    /// variant, and anything else is `Unrecognized` rather than an error,
+    /// so one odd row never breaks a listing.
+    pub fn from_stored(stored: &str) -> AuditEventType {
+        if let Ok(event_type) = stored.parse() {
+            return event_type;
+        }
+        let normalized = stored.trim().to_ascii_uppercase().replace(['-', ' ', '.'], "_");
+        normalized.parse().unwrap_or_else(|_| {
+            log::warn!("Unrecognized security event type in the database: {:?}", stored);
+            AuditEventType::Unrecognized
+        })
+    }
+}
+
+impl fmt::Display for AuditEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<&str> for AuditEventType {
+    fn eq(&self, name: &&str) -> bool {
+        self.as_str() == *name
+    }
+}
+
+/// An event type name this binary doesn't know
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownEventType(pub String);
+
+impl fmt::Display for UnknownEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown event type: {}", self.0)
+    }
+}
+
+impl FromStr for AuditEventType {
+    type Err = UnknownEventType;
+
+    /// Parse a filterable event type name, in SCREAMING_SNAKE_CASE or snake_case
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        AuditEventType::filterable()
+            .find(|event_type| event_type.as_str().eq_ignore_ascii_case(name))
+            .ok_or_else(|| UnknownEventType(name.to_string()))
+    }
+}
+
+impl sqlx::Type<Sqlite> for AuditEventType {
+    fn type_info() -> SqliteTypeInfo {
+        <str as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <str as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for AuditEventType {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> IsNull {
+        <&str as sqlx::Encode<'q, Sqlite>>::encode_by_ref(&self.as_str(), buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Sqlite> for AuditEventType {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let stored = <&str as sqlx::Decode<'r, Sqlite>>::decode(value)?;
+        Ok(AuditEventType::from_stored(stored))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The README's table of event types, which the audit filter documentation points to
+    const README: &str = include_str!("../../README.md");
+
+    #[test]
+    fn test_every_event_type_is_listed_documented_and_has_a_default_severity() {
+        // No wildcard arm: a new variant stops this compiling until it is
+        // given its place in ALL, which the loop below then checks against
+        // the README table and `default_severity`
+        let position = |event_type: AuditEventType| match event_type {
+            AuditEventType::LoginAttempt
+            | AuditEventType::Logout
+            | AuditEventType::TokenValidation
+            | AuditEventType::TokenGuessingSuspected
+            | AuditEventType::TokenRevoked
+            | AuditEventType::SessionReplaced
+            | AuditEventType::StepUpAuth
+            | AuditEventType::TwoFaAttempt
+            | AuditEventType::TwoFaDisabled
+            | AuditEventType::TwoFaQrRedisplayed
+            | AuditEventType::BotSuspected
+            | AuditEventType::LoginFromUnexpectedCountry
+            | AuditEventType::ImpossibleTravel
+            | AuditEventType::AccountLockout
+            | AuditEventType::TermsAccepted
+            | AuditEventType::DeviceLoginApproved
+            | AuditEventType::DeviceLoginDenied
+            | AuditEventType::KioskSessionStarted
+            | AuditEventType::PasswordChange
+            | AuditEventType::LoginPasswordChangeRequired
+            | AuditEventType::LoginPasswordChanged
+            | AuditEventType::PasswordResetRequested
+            | AuditEventType::PasswordResetLinkIssued
+            | AuditEventType::PasswordResetLinkInvalid
+            | AuditEventType::PasswordResetLinkReused
+            | AuditEventType::PasswordResetLinkExpired
+            | AuditEventType::PasswordResetCompleted
+            | AuditEventType::CredentialsRevoked
+            | AuditEventType::BreakGlassUsed
+            | AuditEventType::BreakGlassRefused
+            | AuditEventType::BreakGlassProvisioned
+            | AuditEventType::SecretStaged
+            | AuditEventType::SecretConfirmed
+            | AuditEventType::SecretRejected
+            | AuditEventType::SecretActivated
+            | AuditEventType::AccountLocked
+            | AuditEventType::AccountUnlocked
+            | AuditEventType::AccountDeactivated
+            | AuditEventType::AccountActivated
+            | AuditEventType::PermissionsChanged
+            | AuditEventType::UserOrganizationChanged
+            | AuditEventType::UserTagsChanged
+            | AuditEventType::AccountNoteAdded
+            | AuditEventType::AccountNoteStruck
+            | AuditEventType::SessionsTerminated
+            | AuditEventType::OrgPolicyChanged
+            | AuditEventType::HrAccountProvisioned
+            | AuditEventType::HrAccountDeprovisioned
+            | AuditEventType::HrAccountTransferred
+            | AuditEventType::HrEventRejected
+            | AuditEventType::AdminActionLinkSent
+            | AuditEventType::AdminActionLinkInvalid
+            | AuditEventType::AdminActionLinkWrongAdmin
+            | AuditEventType::AdminActionLinkReused
+            | AuditEventType::AdminActionLinkExpired
+            | AuditEventType::AdminActionConfirmed
+            | AuditEventType::DataExportRequested
+            | AuditEventType::DataExportDownloaded
+            | AuditEventType::DataExportLinkInvalid
+            | AuditEventType::DataExportLinkExpired
+            | AuditEventType::MaintenanceModeChanged
+            | AuditEventType::FeatureFlagsChanged
+            | AuditEventType::LockdownRequested
+            | AuditEventType::LockdownStarted
+            | AuditEventType::LockdownEnded
+            | AuditEventType::SystemMessageCreated
+            | AuditEventType::SystemMessageUpdated
+            | AuditEventType::SystemMessageDeleted
+            | AuditEventType::ClientAppCreated
+            | AuditEventType::ClientAppUpdated
+            | AuditEventType::ClientAppDeleted
+            | AuditEventType::UnknownClientApp
+            | AuditEventType::DatabaseBackup
+            | AuditEventType::HealthStateChanged
+            | AuditEventType::NotificationDigestSent
+            | AuditEventType::AuditExported
+            | AuditEventType::SecurityEventAcknowledged
+            | AuditEventType::AdminRead
+            | AuditEventType::Unrecognized => AuditEventType::ALL.iter().position(|listed| *listed == event_type),
+        };
+
+        for (i, event_type) in AuditEventType::ALL.into_iter().enumerate() {
+            // Declaration order with no gaps, so a variant missing from ALL shifts the rest
+            assert_eq!(event_type as usize, i);
+            assert_eq!(position(event_type), Some(i));
+            assert_eq!(serde_json::to_value(event_type).unwrap(), event_type.as_str());
+            if event_type == AuditEventType::Unrecognized {
+                continue;
+            }
+            let row = format!("| `{}` | {} |", event_type.as_str(), event_type.default_severity().as_str());
+            assert!(README.contains(&row), "README event type table lacks {}", row);
+            assert_eq!(event_type.as_str().parse::<AuditEventType>(), Ok(event_type));
+        }
+        assert_eq!(AuditEventType::ALL.last(), Some(&AuditEventType::Unrecognized));
+    }
+
+    #[test]
+    fn test_filter_names_parse_in_either_case() {
+        assert_eq!("two_fa_attempt".parse(), Ok(AuditEventType::TwoFaAttempt));
+        assert_eq!("ADMIN_READ".parse(), Ok(AuditEventType::AdminRead));
+        assert_eq!("LOGIN_ATEMPT".parse::<AuditEventType>(), Err(UnknownEventType("LOGIN_ATEMPT".to_string())));
+        assert!("UNRECOGNIZED".parse::<AuditEventType>().is_err());
+    }
+
+    #[test]
+    fn test_stored_names_map_to_variants() {
+        assert_eq!(AuditEventType::from_stored("LOGIN_ATTEMPT"), AuditEventType::LoginAttempt);
+        assert_eq!(AuditEventType::from_stored("login-attempt"), AuditEventType::LoginAttempt);
+        assert_eq!(AuditEventType::from_stored("Break Glass Used"), AuditEventType::BreakGlassUsed);
+        assert_eq!(AuditEventType::from_stored("SOMETHING_ELSE"), AuditEventType::Unrecognized);
+    }
+}
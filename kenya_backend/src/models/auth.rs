@@ -1,14 +1,29 @@
 use chrono::{DateTime, Utc};
+use actix_web::{http::{header, StatusCode}, HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use serde_json::json;
 use uuid::Uuid;
 
-/// JWT Claims structure
-#[derive(Debug, Serialize, Deserialize)]
+use crate::models::audit_event::AuditEventType;
+use crate::models::context::RequestContext;
+use crate::models::permission::PermissionSet;
+use crate::models::user::UserRole;
+use crate::services::login_queue::LOGIN_QUEUE_RETRY_AFTER_SECONDS;
+use crate::services::session_service::SessionRecord;
+use crate::utils::db_retry::{is_busy, BUSY_RETRY_AFTER_SECONDS};
+
+/// Claims schema version written into every token issued. Tokens without a
+/// `claims_version` predate it and are read by the legacy decoder.
+pub const CLAIMS_VERSION: u32 = 2;
+
+/// JWT Claims structure, schema version `CLAIMS_VERSION`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Claims {
+    pub claims_version: u32,  // Claims schema version
     pub sub: String,          // Subject (user ID)
     pub username: String,     // Username
-    pub role: String,         // User role
+    #[serde(with = "role_claim")]
+    pub role: UserRole,       // User role, as its snake_case name
     pub exp: usize,           // Expiration time
     pub iat: usize,           // Issued at
     pub iss: String,          // Issuer
@@ -16,70 +31,372 @@ pub struct Claims {
     pub jti: String,          // JWT ID
     pub session_id: String,   // Session identifier
     pub is_temp_password: bool, // Temporary password flag
+    pub perms: u32,           // Effective permission bitmask
+    pub org: Option<String>,  // Organization at issue
+    pub token_version: i64,   // users.token_version at issue
+    #[serde(default)]
+    pub kiosk: bool,          // Signed in on a shared kiosk
 }
 
-/// Authentication error types
-#[derive(Debug)]
+/// Roles in claims are their stored snake_case names, whatever the API's
+/// JSON uses, so verifiers in other languages can match on them
+mod role_claim {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::models::user::UserRole;
+
+    pub fn serialize<S: Serializer>(role: &UserRole, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(role.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<UserRole, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        UserRole::from_name(&name).ok_or_else(|| D::Error::unknown_variant(&name, &["kenya_government", "admin"]))
+    }
+}
+
+/// Realm named in `WWW-Authenticate` challenges
+const AUTH_REALM: &str = "kenya-fsfvi";
+
+/// `WWW-Authenticate` value for a `401` (RFC 6750 §3). `error` is left out
+/// when the request carried no token at all.
+pub fn bearer_challenge(error: Option<&str>) -> String {
+    match error {
+        Some(error) => format!("Bearer realm=\"{}\", error=\"{}\"", AUTH_REALM, error),
+        None => format!("Bearer realm=\"{}\"", AUTH_REALM),
+    }
+}
+
+/// Authentication error types.
+///
+/// Status codes follow one convention across the API: `401` (with a
+/// `WWW-Authenticate` challenge) means the caller must authenticate again,
+/// `403` means they are authenticated but not allowed, and `423` is kept for
+/// locked accounts.
+///
+/// `Display` (and the `#[source]` chain) is for server logs only; clients get
+/// the sanitized body from the `ResponseError` impl below.
+#[derive(Debug, thiserror::Error)]
 pub enum AuthError {
+    #[error("Invalid username or password")]
     InvalidCredentials,
+    #[error("Account is temporarily locked")]
     AccountLocked,
+    #[error("Account is locked or deactivated")]
+    AccountDisabled,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Authentication token has expired")]
     TokenExpired,
+    #[error("Invalid authentication token")]
     InvalidToken,
+    #[error("Password does not meet security requirements")]
     PasswordTooWeak,
+    #[error("Passwords do not match")]
     PasswordMismatch,
+    #[error("New password must be different from the current one")]
+    PasswordReused,
+    #[error("Two-factor secret is too short or too predictable")]
+    WeakTwoFactorSecret,
+    #[error("Two-factor secret is already enrolled on another account")]
+    TwoFactorSecretInUse,
+    #[error("The stored 2FA secret is unavailable. Disable 2FA and enroll again")]
+    TwoFactorReenrollmentRequired,
+    #[error("Too many failed login attempts")]
     TooManyAttempts,
+    #[error("2FA enrollment details were shown too often. Please try again later")]
+    TwoFactorQrLimitReached,
+    #[error("Session has expired")]
     SessionExpired,
+    #[error("Recent re-authentication required")]
+    StepUpRequired,
+    #[error("Please accept the current terms of use to continue")]
+    TermsAcceptanceRequired,
+    #[error("Please change your temporary password to continue")]
+    PasswordChangeRequired,
+    #[error("The terms of use have changed. Please review the current version")]
+    TermsVersionMismatch,
+    #[error("This account is already signed in on another device")]
+    SessionExists(Box<SessionRecord>),
+    #[error("This action link is not valid")]
+    ActionLinkInvalid,
+    #[error("This action link was sent to a different administrator")]
+    ActionLinkWrongAdmin,
+    #[error("This action link has already been used")]
+    ActionLinkUsed,
+    #[error("This action link has expired")]
+    ActionLinkExpired,
+    #[error("This download link is not valid")]
+    DataExportNotFound,
+    #[error("This download link has expired")]
+    DataExportExpired,
+    #[error("Account note not found")]
+    AccountNoteNotFound,
+    #[error("No alert of yours holds that event")]
+    AlertNotFound,
+    #[error("No session holds a token with that ID")]
+    TokenNotFound,
+    #[error("This password reset link is not valid")]
+    PasswordResetInvalid,
+    #[error("This password reset link has already been used")]
+    PasswordResetUsed,
+    #[error("This password reset link has expired")]
+    PasswordResetExpired,
+    #[error("Sign-ins need a second factor during the security lockdown, and this account has neither 2FA nor a verified email. Contact an administrator")]
+    LockdownSecondFactorUnavailable,
+    #[error("No lockdown is in force or waiting for approval")]
+    LockdownNotFound,
+    #[error("This sign-in code is not valid")]
+    DeviceCodeInvalid,
+    #[error("This sign-in code has expired. Start again on the kiosk")]
+    DeviceCodeExpired,
+    #[error("This sign-in code has already been used")]
+    DeviceCodeUsed,
+    #[error("Waiting for the sign-in to be approved")]
+    DeviceAuthorizationPending,
+    #[error("The sign-in was denied")]
+    DeviceLoginDenied,
+    #[error("This action isn't available on a shared kiosk")]
+    KioskRestricted,
+    #[error("Login queue is full")]
+    LoginQueueFull,
+    #[error("Unauthorized access")]
     Unauthorized,
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    /// Argon2 couldn't hash a password, which only misconfiguration causes
+    #[error("Password hashing is unavailable")]
+    HashingUnavailable,
+    #[error("Token encoding failed: {0}")]
+    TokenEncoding(#[source] jsonwebtoken::errors::Error),
+    #[error("QR code generation failed: {0}")]
+    QrCode(#[from] qrcode::types::QrError),
+    #[error("Image encoding failed: {0}")]
+    ImageEncoding(#[from] image::ImageError),
+    #[error("Serialization failed: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Internal error: {0}")]
     InternalError(String),
 }
 
-impl fmt::Display for AuthError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl AuthError {
+    /// Whether retrying later may succeed (database busy/locked, pool exhausted)
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AuthError::LoginQueueFull => true,
+            AuthError::Database(sqlx::Error::PoolTimedOut) => true,
+            AuthError::Database(error) => is_busy(error),
+            _ => false,
+        }
+    }
+
+    /// RFC 6750 error code for the `WWW-Authenticate` challenge of a `401`
+    pub fn challenge_error(&self) -> Option<&'static str> {
+        match self {
+            AuthError::TokenExpired | AuthError::InvalidToken | AuthError::SessionExpired => Some("invalid_token"),
+            _ => None,
+        }
+    }
+
+    /// Public error identifier; never carries driver or library details
+    pub fn error_type(&self) -> &'static str {
         match self {
-            AuthError::InvalidCredentials => write!(f, "Invalid username or password"),
-            AuthError::AccountLocked => write!(f, "Account is temporarily locked"),
-            AuthError::TokenExpired => write!(f, "Authentication token has expired"),
-            AuthError::InvalidToken => write!(f, "Invalid authentication token"),
-            AuthError::PasswordTooWeak => write!(f, "Password does not meet security requirements"),
-            AuthError::PasswordMismatch => write!(f, "Passwords do not match"),
-            AuthError::TooManyAttempts => write!(f, "Too many failed login attempts"),
-            AuthError::SessionExpired => write!(f, "Session has expired"),
-            AuthError::Unauthorized => write!(f, "Unauthorized access"),
-            AuthError::InternalError(msg) => write!(f, "Internal error: {}", msg),
+            AuthError::InvalidCredentials => "InvalidCredentials",
+            AuthError::AccountLocked => "AccountLocked",
+            AuthError::AccountDisabled => "AccountDisabled",
+            AuthError::UserNotFound => "UserNotFound",
+            AuthError::TokenExpired => "TokenExpired",
+            AuthError::InvalidToken => "InvalidToken",
+            AuthError::PasswordTooWeak => "PasswordTooWeak",
+            AuthError::PasswordMismatch => "PasswordMismatch",
+            AuthError::PasswordReused => "PasswordReused",
+            AuthError::WeakTwoFactorSecret => "WeakTwoFactorSecret",
+            AuthError::TwoFactorSecretInUse => "TwoFactorSecretInUse",
+            AuthError::TwoFactorReenrollmentRequired => "TwoFactorReenrollmentRequired",
+            AuthError::TooManyAttempts => "TooManyAttempts",
+            AuthError::TwoFactorQrLimitReached => "TwoFactorQrLimitReached",
+            AuthError::SessionExpired => "SessionExpired",
+            AuthError::StepUpRequired => "StepUpRequired",
+            AuthError::TermsAcceptanceRequired => "TermsAcceptanceRequired",
+            AuthError::PasswordChangeRequired => "PasswordChangeRequired",
+            AuthError::TermsVersionMismatch => "TermsVersionMismatch",
+            AuthError::SessionExists(_) => "SESSION_EXISTS",
+            AuthError::ActionLinkInvalid => "ActionLinkInvalid",
+            AuthError::ActionLinkWrongAdmin => "ActionLinkWrongAdmin",
+            AuthError::ActionLinkUsed => "ActionLinkUsed",
+            AuthError::ActionLinkExpired => "ActionLinkExpired",
+            AuthError::DataExportNotFound => "DataExportNotFound",
+            AuthError::DataExportExpired => "DataExportExpired",
+            AuthError::AccountNoteNotFound => "AccountNoteNotFound",
+            AuthError::AlertNotFound => "AlertNotFound",
+            AuthError::TokenNotFound => "TokenNotFound",
+            AuthError::PasswordResetInvalid => "PasswordResetInvalid",
+            AuthError::PasswordResetUsed => "PasswordResetUsed",
+            AuthError::PasswordResetExpired => "PasswordResetExpired",
+            AuthError::LockdownSecondFactorUnavailable => "LockdownSecondFactorUnavailable",
+            AuthError::LockdownNotFound => "LockdownNotFound",
+            AuthError::DeviceCodeInvalid => "DeviceCodeInvalid",
+            AuthError::DeviceCodeExpired => "DeviceCodeExpired",
+            AuthError::DeviceCodeUsed => "DeviceCodeUsed",
+            AuthError::DeviceAuthorizationPending => "DeviceAuthorizationPending",
+            AuthError::DeviceLoginDenied => "DeviceLoginDenied",
+            AuthError::KioskRestricted => "KioskRestricted",
+            AuthError::Unauthorized => "Unauthorized",
+            AuthError::HashingUnavailable => "HashingUnavailable",
+            _ if self.is_transient() => "ServiceUnavailable",
+            _ => "InternalError",
         }
     }
 }
 
-impl std::error::Error for AuthError {}
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::InvalidCredentials
+            | AuthError::TokenExpired
+            | AuthError::InvalidToken
+            | AuthError::SessionExpired
+            | AuthError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AuthError::AccountLocked => StatusCode::LOCKED,
+            AuthError::AccountDisabled
+            | AuthError::StepUpRequired
+            | AuthError::TermsAcceptanceRequired
+            | AuthError::PasswordChangeRequired
+            | AuthError::ActionLinkWrongAdmin
+            | AuthError::LockdownSecondFactorUnavailable
+            | AuthError::DeviceLoginDenied
+            | AuthError::KioskRestricted => StatusCode::FORBIDDEN,
+            AuthError::UserNotFound
+            | AuthError::ActionLinkInvalid
+            | AuthError::DataExportNotFound
+            | AuthError::AccountNoteNotFound
+            | AuthError::AlertNotFound
+            | AuthError::TokenNotFound
+            | AuthError::PasswordResetInvalid
+            | AuthError::LockdownNotFound
+            | AuthError::DeviceCodeInvalid => StatusCode::NOT_FOUND,
+            AuthError::ActionLinkExpired
+            | AuthError::DataExportExpired
+            | AuthError::PasswordResetExpired
+            | AuthError::DeviceCodeExpired => StatusCode::GONE,
+            AuthError::PasswordTooWeak
+            | AuthError::PasswordMismatch
+            | AuthError::PasswordReused
+            | AuthError::WeakTwoFactorSecret
+            | AuthError::DeviceAuthorizationPending => StatusCode::BAD_REQUEST,
+            AuthError::TwoFactorSecretInUse
+            | AuthError::TwoFactorReenrollmentRequired
+            | AuthError::TermsVersionMismatch
+            | AuthError::SessionExists(_)
+            | AuthError::ActionLinkUsed
+            | AuthError::PasswordResetUsed
+            | AuthError::DeviceCodeUsed => StatusCode::CONFLICT,
+            AuthError::TooManyAttempts | AuthError::TwoFactorQrLimitReached => StatusCode::TOO_MANY_REQUESTS,
+            AuthError::HashingUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ if self.is_transient() => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let message = if status == StatusCode::SERVICE_UNAVAILABLE {
+            "Service temporarily unavailable. Please try again shortly".to_string()
+        } else if status.is_server_error() {
+            "Internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+
+        let mut response = HttpResponse::build(status);
+        match self {
+            AuthError::LoginQueueFull => {
+                response.insert_header((header::RETRY_AFTER, LOGIN_QUEUE_RETRY_AFTER_SECONDS.to_string()));
+            }
+            AuthError::Database(error) if is_busy(error) => {
+                response.insert_header((header::RETRY_AFTER, BUSY_RETRY_AFTER_SECONDS.to_string()));
+            }
+            _ => {}
+        }
+        if status == StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, bearer_challenge(self.challenge_error())));
+        }
+        // The frontend offers to sign the other device out, so show which one it is
+        if let AuthError::SessionExists(session) = self {
+            return response.json(json!({
+                "success": false,
+                "message": message,
+                "error_type": self.error_type(),
+                "data": {
+                    "existing_session": {
+                        "ip_address": session.ip_address,
+                        "user_agent": session.user_agent,
+                        "created_at": session.created_at.to_rfc3339(),
+                        "last_activity_at": session.last_activity_at.to_rfc3339(),
+                        "device_known": session.device_known,
+                    }
+                }
+            }));
+        }
+        response.json(json!({
+            "success": false,
+            "message": message,
+            "error_type": self.error_type()
+        }))
+    }
+}
 
 /// Authentication result wrapper
 pub type AuthResult<T> = Result<T, AuthError>;
 
-/// Token validation result
+/// Token validation result. The account's name, role and organization come
+/// from the users table rather than the token, which may be hours old.
 #[derive(Debug)]
 pub struct TokenValidation {
     pub user_id: Uuid,
-    pub username: String,
-    pub role: String,
     pub session_id: String,
-    pub is_temp_password: bool,
-    pub expires_at: DateTime<Utc>,
+    pub jti: String,
+    pub permissions: PermissionSet,
+    pub token_version: i64,
+    /// ID of the key that verified the token
+    pub kid: String,
+    /// Token audience of the client app it was issued to
+    pub audience: String,
+    /// Issued to a shared kiosk, which sensitive endpoints refuse
+    pub kiosk: bool,
 }
 
-/// Rate limiting configuration
-#[derive(Debug, Clone)]
-pub struct RateLimitConfig {
-    pub max_attempts: u32,
-    pub window_seconds: u64,
-    pub lockout_duration_seconds: u64,
+/// What signing in does to an account's other live sessions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MultipleLoginPolicy {
+    /// End them, logging `SESSION_REPLACED` and notifying the owner
+    #[default]
+    Replace,
+    /// Keep them alongside the new one
+    Additional,
+    /// Refuse the login with `409 SESSION_EXISTS` unless the client asks to sign them out
+    Deny,
 }
 
-impl Default for RateLimitConfig {
-    fn default() -> Self {
-        RateLimitConfig {
-            max_attempts: 5,
-            window_seconds: 300, // 5 minutes
-            lockout_duration_seconds: 300, // 5 minutes
+impl MultipleLoginPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MultipleLoginPolicy::Replace => "replace",
+            MultipleLoginPolicy::Additional => "additional",
+            MultipleLoginPolicy::Deny => "deny",
+        }
+    }
+}
+
+impl std::str::FromStr for MultipleLoginPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "replace" => Ok(MultipleLoginPolicy::Replace),
+            "additional" => Ok(MultipleLoginPolicy::Additional),
+            "deny" => Ok(MultipleLoginPolicy::Deny),
+            other => Err(format!("unknown multiple login policy: {}", other)),
         }
     }
 }
@@ -88,22 +405,104 @@ impl Default for RateLimitConfig {
 #[derive(Debug, Clone)]
 pub struct SecurityConfig {
     pub jwt_secret: String,
+    /// Secret tokens were signed with before `jwt_secret`, still accepted
+    /// during a migration to the new one
+    pub jwt_previous_secret: Option<String>,
+    /// Tokens signed with `jwt_previous_secret` are refused from then on
+    pub jwt_migration_deadline: Option<DateTime<Utc>>,
     pub jwt_expiration_hours: i64,
-    pub password_salt_rounds: u32,
-    pub rate_limit: RateLimitConfig,
+    /// Seconds a token is still accepted after its `exp`
+    pub jwt_exp_leeway_seconds: i64,
+    /// Seconds a token's `iat` may lie ahead of this instance's clock, as
+    /// after the clock steps back; such tokens are accepted and counted
+    pub jwt_iat_leeway_seconds: i64,
     pub session_timeout_minutes: i64,
-    pub require_password_change: bool,
+    /// Consecutive failed logins that lock an account
+    pub max_failed_attempts: i32,
+    pub lockout_duration_minutes: i64,
+    /// Days a password stays current before users are warned to change it; 0 never ages
+    pub password_max_age_days: i64,
+    /// HMAC key for two-factor secret fingerprints; changing it orphans stored fingerprints
+    pub totp_fingerprint_key: String,
+    /// Acceptable-use terms every user must accept before using the platform;
+    /// a new version asks everyone again. `None` when there are no terms.
+    pub terms_version: Option<String>,
+    /// Tokens without a `claims_version` are accepted until then, so sessions
+    /// from before the versioned claims survive an upgrade. `None` refuses them.
+    pub legacy_claims_accepted_until: Option<DateTime<Utc>>,
+    /// Signing in while another session is live replaces it, adds to it or is refused
+    pub multiple_login_policy: MultipleLoginPolicy,
 }
 
 impl Default for SecurityConfig {
     fn default() -> Self {
         SecurityConfig {
             jwt_secret: "your-super-secret-jwt-key-change-this-in-production".to_string(),
+            jwt_previous_secret: None,
+            jwt_migration_deadline: None,
             jwt_expiration_hours: 8, // 8 hours
-            password_salt_rounds: 12,
-            rate_limit: RateLimitConfig::default(),
+            jwt_exp_leeway_seconds: 0,
+            jwt_iat_leeway_seconds: 60,
             session_timeout_minutes: 30,
-            require_password_change: true,
+            max_failed_attempts: 5,
+            lockout_duration_minutes: 5,
+            password_max_age_days: 0,
+            totp_fingerprint_key: "your-super-secret-jwt-key-change-this-in-production".to_string(),
+            terms_version: None,
+            legacy_claims_accepted_until: None,
+            multiple_login_policy: MultipleLoginPolicy::Replace,
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Render the security settings for logging with the JWT secret redacted
+    pub fn redacted_summary(&self) -> String {
+        format!(
+            "jwt_secret=<redacted fp:{}> jwt_previous_secret={} jwt_migration_deadline={:?} \
+             jwt_expiration_hours={} jwt_exp_leeway_seconds={} jwt_iat_leeway_seconds={} \
+             session_timeout_minutes={} max_failed_attempts={} lockout_duration_minutes={} \
+             password_max_age_days={} totp_fingerprint_key=<redacted fp:{}> terms_version={:?} \
+             legacy_claims_accepted_until={:?} multiple_login_policy={}",
+            crate::utils::self_test::secret_fingerprint(&self.jwt_secret),
+            self.jwt_previous_secret
+                .as_deref()
+                .map(|secret| format!("<redacted fp:{}>", crate::utils::self_test::secret_fingerprint(secret)))
+                .unwrap_or_else(|| "None".to_string()),
+            self.jwt_migration_deadline.map(|deadline| deadline.to_rfc3339()),
+            self.jwt_expiration_hours,
+            self.jwt_exp_leeway_seconds,
+            self.jwt_iat_leeway_seconds,
+            self.session_timeout_minutes,
+            self.max_failed_attempts,
+            self.lockout_duration_minutes,
+            self.password_max_age_days,
+            crate::utils::self_test::secret_fingerprint(&self.totp_fingerprint_key),
+            self.terms_version,
+            self.legacy_claims_accepted_until.map(|until| until.to_rfc3339()),
+            self.multiple_login_policy.as_str(),
+        )
+    }
+}
+
+/// Security event severity, so alerting and dashboards can prioritise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "severity", rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    /// Severity name as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
         }
     }
 }
@@ -113,18 +512,25 @@ impl Default for SecurityConfig {
 pub struct AuditLogEntry {
     pub id: Uuid,
     pub user_id: Option<Uuid>,
-    pub event_type: String,
+    pub event_type: AuditEventType,
     pub description: String,
     pub ip_address: Option<String>,
     pub user_agent: Option<String>,
     pub timestamp: DateTime<Utc>,
     pub success: bool,
+    pub severity: Severity,
     pub details: Option<serde_json::Value>,
+    // Acknowledgement by an admin reviewing the alert feed
+    pub acknowledged_by: Option<Uuid>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub resolution_note: Option<String>,
 }
 
+/// Maximum stored length of a client user agent string
+pub const MAX_USER_AGENT_LENGTH: usize = 512;
+
 /// Login attempt tracking
-#[derive(Debug)]
-#[allow(dead_code)] // Used by database and future features
+#[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct LoginAttempt {
     pub user_id: Option<Uuid>,
     pub username: String,
@@ -133,37 +539,138 @@ pub struct LoginAttempt {
     pub success: bool,
     pub timestamp: DateTime<Utc>,
     pub failure_reason: Option<String>,
+    // GeoIP enrichment, filled in when the attempt is recorded
+    pub country_code: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub asn_org: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
 }
 
-/// Password policy configuration
-#[derive(Debug, Clone)]
-pub struct PasswordPolicy {
-    pub min_length: usize,
-    pub require_uppercase: bool,
-    pub require_lowercase: bool,
-    pub require_numbers: bool,
-    pub require_special_chars: bool,
-    pub max_repeating_chars: usize,
-    pub forbidden_patterns: Vec<String>,
+impl LoginAttempt {
+    pub fn new(
+        ctx: &RequestContext,
+        user_id: Option<Uuid>,
+        username: &str,
+        success: bool,
+        failure_reason: Option<&str>,
+    ) -> Self {
+        LoginAttempt {
+            user_id,
+            username: username.to_string(),
+            ip_address: ctx.ip_address.clone(),
+            user_agent: ctx.user_agent.clone(),
+            success,
+            timestamp: Utc::now(),
+            failure_reason: failure_reason.map(|r| r.to_string()),
+            country_code: None,
+            region: None,
+            city: None,
+            asn: None,
+            asn_org: None,
+            latitude: None,
+            longitude: None,
+        }
+    }
 }
 
-impl Default for PasswordPolicy {
-    fn default() -> Self {
-        PasswordPolicy {
-            min_length: 12,
-            require_uppercase: true,
-            require_lowercase: true,
-            require_numbers: true,
-            require_special_chars: true,
-            max_repeating_chars: 3,
-            forbidden_patterns: vec![
-                "123".to_string(),
-                "abc".to_string(),
-                "password".to_string(),
-                "qwerty".to_string(),
-                "kenya".to_string(),
-                "government".to_string(),
-            ],
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database::test_pool;
+    use actix_web::body::to_bytes;
+
+    async fn body_text(error: &AuthError) -> String {
+        let body = to_bytes(error.error_response().into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
     }
-}
\ No newline at end of file
+
+    #[actix_web::test]
+    async fn test_database_error_body_hides_sql_details() {
+        let pool = test_pool().await;
+        let sqlx_error = sqlx::query("SELECT secret_column FROM missing_audit_table")
+            .execute(&pool)
+            .await
+            .unwrap_err();
+        let error = AuthError::from(sqlx_error);
+
+        // The details stay available to server logs...
+        assert!(error.to_string().contains("missing_audit_table"));
+        assert!(std::error::Error::source(&error).is_some());
+
+        // ...but never reach the client
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = body_text(&error).await;
+        assert!(!body.contains("missing_audit_table"));
+        assert!(!body.contains("secret_column"));
+        assert!(body.contains("\"error_type\":\"InternalError\""));
+    }
+
+    #[actix_web::test]
+    async fn test_transient_database_errors_map_to_503() {
+        let error = AuthError::Database(sqlx::Error::PoolTimedOut);
+        assert!(error.is_transient());
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body_text(&error).await.contains("ServiceUnavailable"));
+
+        assert!(!AuthError::Database(sqlx::Error::RowNotFound).is_transient());
+        assert!(!AuthError::InvalidCredentials.is_transient());
+    }
+
+    #[actix_web::test]
+    async fn test_hashing_failure_maps_to_503_without_retry_after() {
+        let error = AuthError::HashingUnavailable;
+        // Retrying won't help until the hasher is fixed
+        assert!(!error.is_transient());
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+        assert!(body_text(&error).await.contains("\"error_type\":\"HashingUnavailable\""));
+    }
+
+    #[actix_web::test]
+    async fn test_busy_database_asks_client_to_retry() {
+        use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("busy.db"))
+            .create_if_missing(true)
+            .busy_timeout(std::time::Duration::ZERO);
+        let pool = SqlitePoolOptions::new().max_connections(2).connect_with(options).await.unwrap();
+        sqlx::query("CREATE TABLE t (n INTEGER)").execute(&pool).await.unwrap();
+        let mut holder = pool.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *holder).await.unwrap();
+        let error = AuthError::from(sqlx::query("INSERT INTO t (n) VALUES (1)").execute(&pool).await.unwrap_err());
+
+        assert!(error.is_transient());
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(header::RETRY_AFTER).unwrap(),
+            &BUSY_RETRY_AFTER_SECONDS.to_string()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_full_login_queue_asks_client_to_retry() {
+        let response = AuthError::LoginQueueFull.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(header::RETRY_AFTER).unwrap(),
+            &LOGIN_QUEUE_RETRY_AFTER_SECONDS.to_string()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_client_errors_keep_their_message() {
+        let error = AuthError::AccountLocked;
+        assert_eq!(error.status_code(), StatusCode::LOCKED);
+        assert!(body_text(&error).await.contains("Account is temporarily locked"));
+
+        let internal = AuthError::InternalError("pool state: 3 idle".to_string());
+        assert!(!body_text(&internal).await.contains("pool state"));
+    }
+}
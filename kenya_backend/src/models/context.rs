@@ -0,0 +1,385 @@
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::future::{ready, Ready};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::models::auth::MAX_USER_AGENT_LENGTH;
+use crate::utils::sanitize;
+
+/// Header carrying the request ID, accepted from upstream proxies and echoed on responses
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest client-supplied request ID we'll adopt instead of generating our own
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+/// Per-request client details, built once by the `RequestContextMiddleware`
+/// and passed by reference into the service layer
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub request_id: String,
+    /// Canonical text of `ip`, or the raw value when it isn't an address
+    pub ip_address: String,
+    /// The client address, parsed once here; `None` when it isn't one
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    /// Preferred language from `Accept-Language`, e.g. `sw-KE`
+    #[allow(dead_code)]
+    pub locale: Option<String>,
+    pub received_at: DateTime<Utc>,
+}
+
+impl RequestContext {
+    /// Context for work not tied to a particular HTTP request
+    pub fn new(ip_address: &str, user_agent: Option<&str>) -> Self {
+        let ip = parse_ip(ip_address);
+        Self {
+            request_id: Uuid::new_v4().to_string(),
+            // Stored canonical so audit lookups by address find every row
+            ip_address: ip.map_or_else(|| ip_address.trim().to_string(), |ip| ip.to_string()),
+            ip,
+            // Attack tooling sometimes sends enormous headers; keep enough to fingerprint it
+            user_agent: user_agent.map(|ua| ua.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+            locale: None,
+            received_at: Utc::now(),
+        }
+    }
+
+    /// Build the context from an incoming request's headers and connection info
+    pub fn from_http_request(req: &HttpRequest) -> Self {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| is_acceptable_request_id(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        // Bytes that aren't UTF-8 are replaced rather than losing the whole header
+        let user_agent = req
+            .headers()
+            .get("User-Agent")
+            .map(|ua| sanitize::bytes(ua.as_bytes(), MAX_USER_AGENT_LENGTH));
+
+        let locale = req
+            .headers()
+            .get("Accept-Language")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|tag| tag.split(';').next().unwrap_or("").trim().to_string())
+            .filter(|tag| !tag.is_empty() && tag != "*");
+
+        Self {
+            request_id,
+            locale,
+            ..Self::new(&client_ip(req), user_agent.as_deref())
+        }
+    }
+}
+
+/// A client address as proxies and sockets write it: with or without a
+/// port, IPv6 with or without brackets and with any zone ID (`%eth0`)
+/// dropped. IPv4-mapped IPv6 comes back as plain IPv4. `None` when the input
+/// isn't an IP address.
+pub fn parse_ip(raw: &str) -> Option<IpAddr> {
+    let raw = raw.trim().trim_matches('"');
+    if let Ok(addr) = raw.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    let host = match raw.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, port) = bracketed.split_once(']')?;
+            if !port.is_empty() && port.strip_prefix(':').and_then(|port| port.parse::<u16>().ok()).is_none() {
+                return None;
+            }
+            host
+        }
+        None => raw,
+    };
+    // Zone IDs only follow IPv6 addresses, and say nothing about the client
+    let host = match host.split_once('%') {
+        Some((address, zone)) if address.contains(':') && !zone.is_empty() => address,
+        _ => host,
+    };
+    Some(host.parse::<IpAddr>().ok()?.to_canonical())
+}
+
+/// Canonical form of a client address: port, IPv6 brackets and zone ID
+/// dropped, IPv6 lowercased and compressed, IPv4-mapped IPv6 as plain IPv4.
+/// `None` when the input isn't an IP address.
+pub fn normalize_ip(raw: &str) -> Option<String> {
+    parse_ip(raw).map(|ip| ip.to_string())
+}
+
+/// What a client is counted as by the throttles and rate limits: an IPv4
+/// address on its own, an IPv6 address by the /64 it is in
+/// (`2c0f:fe38:2001:5a1::/64`). Carriers delegate a /64 to each subscriber,
+/// so moving around in it earns no fresh budget. Anything that isn't an
+/// address is returned as it is.
+pub fn client_network(ip_address: &str) -> String {
+    match parse_ip(ip_address) {
+        Some(IpAddr::V6(v6)) => {
+            let [a, b, c, d, ..] = v6.segments();
+            format!("{}/64", Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0))
+        }
+        Some(ip) => ip.to_string(),
+        None => ip_address.to_string(),
+    }
+}
+
+/// An address range in CIDR notation; a bare address is a range of one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(&network.octets(), &ip.octets(), self.prefix),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => prefix_matches(&network.octets(), &ip.octets(), self.prefix),
+            _ => false,
+        }
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.address.is_ipv4()
+    }
+
+    /// Prefix length; 32 or 128 for a single address
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    fn max_prefix(address: IpAddr) -> u8 {
+        if address.is_ipv4() { 32 } else { 128 }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address: IpAddr = address.trim().parse().map_err(|_| format!("{:?} is not an IP address or CIDR range", value))?;
+        let address = address.to_canonical();
+        let max_prefix = Self::max_prefix(address);
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("{:?} has an invalid prefix length", value))?,
+            None => max_prefix,
+        };
+        Ok(Self { address, prefix })
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == Self::max_prefix(self.address) {
+            write!(f, "{}", self.address)
+        } else {
+            write!(f, "{}/{}", self.address, self.prefix)
+        }
+    }
+}
+
+/// Whether the first `prefix` bits of `a` and `b` agree
+fn prefix_matches(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let whole = usize::from(prefix / 8);
+    let bits = prefix % 8;
+    a[..whole] == b[..whole] && (bits == 0 || (a[whole] ^ b[whole]) & (0xffu8 << (8 - bits)) == 0)
+}
+
+/// Proxies whose forwarding headers are believed, set once at startup
+static TRUSTED_PROXIES: OnceLock<Vec<IpNetwork>> = OnceLock::new();
+
+/// Believe `X-Forwarded-For` and `X-Real-IP` only from `proxies`. Only the
+/// first call takes effect; false for later ones.
+pub fn install_trusted_proxies(proxies: Vec<IpNetwork>) -> bool {
+    TRUSTED_PROXIES.set(proxies).is_ok()
+}
+
+/// The installed proxies; none before `install_trusted_proxies` is called
+fn trusted_proxies() -> &'static [IpNetwork] {
+    TRUSTED_PROXIES.get().map_or(&[], Vec::as_slice)
+}
+
+/// The client address of a request, by the installed trusted proxies
+fn client_ip(req: &HttpRequest) -> String {
+    client_ip_behind(req, trusted_proxies())
+}
+
+/// The client address of a request that may have come through `proxies`.
+/// Forwarding headers are only read when the connection itself is from one
+/// of them, since anyone else can write whatever they like there. Each
+/// proxy appends the address it saw to `X-Forwarded-For`, so the right-most
+/// hop that isn't one of ours is the client; the leftmost when all are.
+fn client_ip_behind(req: &HttpRequest, proxies: &[IpNetwork]) -> String {
+    let Some(peer) = req.peer_addr().map(|addr| addr.ip().to_canonical()) else {
+        return "unknown".to_string();
+    };
+    let trusted = |ip: IpAddr| proxies.iter().any(|network| network.contains(ip));
+    if !trusted(peer) {
+        return peer.to_string();
+    }
+
+    let hops: Vec<&str> = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    if hops.is_empty() {
+        return req
+            .headers()
+            .get("X-Real-IP")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_ip)
+            .unwrap_or(peer)
+            .to_string();
+    }
+
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        // A hop that isn't an address can't be told apart from a forgery;
+        // the last proxy that passed it on stands in for the client
+        let Some(ip) = parse_ip(hop) else { break };
+        client = ip;
+        if !trusted(ip) {
+            break;
+        }
+    }
+    client.to_string()
+}
+
+/// Upstream request IDs end up in logs and headers, so only plain tokens are kept
+fn is_acceptable_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Handlers take `RequestContext` as an argument. The middleware's context is
+/// reused when present so every consumer sees the same request ID.
+impl FromRequest for RequestContext {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let context = req
+            .extensions()
+            .get::<RequestContext>()
+            .cloned()
+            .unwrap_or_else(|| RequestContext::from_http_request(req));
+        ready(Ok(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_context_from_headers() {
+        let req = TestRequest::default()
+            .peer_addr("197.232.61.4:50412".parse().unwrap())
+            .insert_header(("User-Agent", "Mozilla/5.0"))
+            .insert_header(("Accept-Language", "sw-KE,sw;q=0.9,en;q=0.8"))
+            .insert_header((REQUEST_ID_HEADER, "lb-7f3a9c"))
+            .to_http_request();
+
+        let context = RequestContext::from_http_request(&req);
+        assert_eq!(context.ip_address, "197.232.61.4");
+        assert_eq!(context.user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert_eq!(context.locale.as_deref(), Some("sw-KE"));
+        assert_eq!(context.request_id, "lb-7f3a9c");
+    }
+
+    #[test]
+    fn test_ip_addresses_are_normalized() {
+        assert_eq!(normalize_ip(" 41.90.12.7 ").as_deref(), Some("41.90.12.7"));
+        assert_eq!(normalize_ip("41.90.12.7:52311").as_deref(), Some("41.90.12.7"));
+        assert_eq!(normalize_ip("2001:DB8:0:0::1").as_deref(), Some("2001:db8::1"));
+        assert_eq!(normalize_ip("[2001:db8::1]:443").as_deref(), Some("2001:db8::1"));
+        assert_eq!(normalize_ip("[2001:db8::1]").as_deref(), Some("2001:db8::1"));
+        assert_eq!(normalize_ip("::ffff:41.90.12.7").as_deref(), Some("41.90.12.7"));
+        assert_eq!(normalize_ip("fe80::1%eth0").as_deref(), Some("fe80::1"));
+        assert_eq!(normalize_ip("[fe80::1%eth0]:443").as_deref(), Some("fe80::1"));
+        assert_eq!(normalize_ip("[2001:db8::1]:https"), None);
+        assert_eq!(normalize_ip("41.90.12.7%eth0"), None);
+        assert_eq!(normalize_ip("unknown"), None);
+        assert_eq!(RequestContext::new("41.90.12.7:52311", None).ip_address, "41.90.12.7");
+        assert_eq!(RequestContext::new("[::ffff:41.90.12.7]:443", None).ip, Some("41.90.12.7".parse().unwrap()));
+        assert_eq!(RequestContext::new("unknown", None).ip_address, "unknown");
+        assert_eq!(RequestContext::new("unknown", None).ip, None);
+
+        assert_eq!(client_network("41.90.12.7:52311"), "41.90.12.7");
+        assert_eq!(client_network("2c0f:fe38:2001:5a1:8d2e::7"), "2c0f:fe38:2001:5a1::/64");
+        assert_eq!(client_network("[2C0F:FE38:2001:5A1::9%wlan0]:443"), "2c0f:fe38:2001:5a1::/64");
+        assert_eq!(client_network("unknown"), "unknown");
+    }
+
+    #[test]
+    fn test_forwarding_headers_are_only_believed_from_trusted_proxies() {
+        let proxies: Vec<IpNetwork> = ["10.0.0.0/24", "2001:db8:ffff::/48"].iter().map(|n| n.parse().unwrap()).collect();
+        let from = |peer: &str, forwarded: Option<&str>| {
+            let mut request = TestRequest::default().peer_addr(peer.parse().unwrap());
+            if let Some(forwarded) = forwarded {
+                request = request.insert_header(("X-Forwarded-For", forwarded));
+            }
+            client_ip_behind(&request.to_http_request(), &proxies)
+        };
+
+        // A client talking to us directly can't name another address
+        assert_eq!(from("197.232.61.4:50412", Some("41.90.12.7")), "197.232.61.4");
+        assert_eq!(from("[2c0f:fe38:2001:5a1::9]:443", Some("41.90.12.7")), "2c0f:fe38:2001:5a1::9");
+
+        // Behind our proxies, the right-most hop that isn't one of them
+        assert_eq!(from("10.0.0.5:4000", Some("197.232.61.4")), "197.232.61.4");
+        assert_eq!(from("10.0.0.5:4000", Some("6.6.6.6, 197.232.61.4")), "197.232.61.4");
+        assert_eq!(from("10.0.0.5:4000", Some("6.6.6.6, 197.232.61.4, 10.0.0.9")), "197.232.61.4");
+        assert_eq!(from("[2001:db8:ffff::2]:4000", Some("[2c0f:fe38:2001:5a1::9]:51000")), "2c0f:fe38:2001:5a1::9");
+        assert_eq!(from("10.0.0.5:4000", Some("::ffff:41.90.12.7")), "41.90.12.7");
+        assert_eq!(from("10.0.0.5:4000", Some("10.0.0.7, 10.0.0.9")), "10.0.0.7");
+        assert_eq!(from("10.0.0.5:4000", Some("unknown")), "10.0.0.5");
+        assert_eq!(from("10.0.0.5:4000", None), "10.0.0.5");
+
+        let real_ip = |peer: &str| {
+            let request = TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header(("X-Real-IP", "41.90.12.7"));
+            client_ip_behind(&request.to_http_request(), &proxies)
+        };
+        assert_eq!(real_ip("10.0.0.5:4000"), "41.90.12.7");
+        assert_eq!(real_ip("197.232.61.4:50412"), "197.232.61.4");
+
+        // With no proxies configured, headers are never believed
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.5:4000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "197.232.61.4"));
+        assert_eq!(client_ip_behind(&request.to_http_request(), &[]), "10.0.0.5");
+        assert_eq!(client_ip_behind(&TestRequest::default().to_http_request(), &proxies), "unknown");
+    }
+
+    #[test]
+    fn test_unsafe_request_id_is_replaced() {
+        let req = TestRequest::default()
+            .insert_header((REQUEST_ID_HEADER, "abc\" onload=alert(1)"))
+            .to_http_request();
+
+        let context = RequestContext::from_http_request(&req);
+        assert!(Uuid::parse_str(&context.request_id).is_ok());
+    }
+}
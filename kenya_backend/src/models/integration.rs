@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::policy::is_valid_organization;
+
+/// Most events one HR delivery may carry
+pub const MAX_HR_EVENTS_PER_BATCH: usize = 100;
+
+/// Longest event ID a source may give an event
+pub const MAX_EVENT_ID_LENGTH: usize = 100;
+
+/// Events pushed by the HR system in one delivery
+#[derive(Debug, Deserialize)]
+pub struct HrEventBatch {
+    pub events: Vec<HrEvent>,
+}
+
+/// A change to an employee, under the ID the HR system gave it. The same ID
+/// from the same source is only ever applied once.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HrEvent {
+    pub event_id: String,
+    #[serde(flatten)]
+    pub change: HrChange,
+}
+
+/// What happened to the employee, by the event's `type`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum HrChange {
+    /// Create an account, sent an invitation to choose its password
+    #[serde(rename = "employee_joined")]
+    Joined { username: String, email: String, organization: String },
+    /// Deactivate the account and end its sessions
+    #[serde(rename = "employee_left")]
+    Left { username: String },
+    /// Move the account to another organization
+    #[serde(rename = "employee_transferred")]
+    Transferred { username: String, organization: String },
+}
+
+impl HrChange {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HrChange::Joined { .. } => "employee_joined",
+            HrChange::Left { .. } => "employee_left",
+            HrChange::Transferred { .. } => "employee_transferred",
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        match self {
+            HrChange::Joined { username, .. }
+            | HrChange::Left { username }
+            | HrChange::Transferred { username, .. } => username,
+        }
+    }
+
+    pub fn organization(&self) -> Option<&str> {
+        match self {
+            HrChange::Joined { organization, .. } | HrChange::Transferred { organization, .. } => Some(organization),
+            HrChange::Left { .. } => None,
+        }
+    }
+}
+
+impl HrEvent {
+    /// Why the event can't be applied as sent, before anything is looked up
+    pub fn problem(&self) -> Option<&'static str> {
+        if self.event_id.is_empty() || self.event_id.chars().count() > MAX_EVENT_ID_LENGTH {
+            return Some("Event ID must be between 1 and 100 characters");
+        }
+        let username = self.change.username().chars().count();
+        if !(3..=50).contains(&username) {
+            return Some("Username must be between 3 and 50 characters");
+        }
+        if let HrChange::Joined { email, .. } = &self.change {
+            let valid = email.chars().count() <= 254
+                && email.split_once('@').is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'));
+            if !valid {
+                return Some("Email must be a valid address");
+            }
+        }
+        if self.change.organization().is_some_and(|name| !is_valid_organization(name)) {
+            return Some("Organization must be 1-100 letters, digits, spaces, dots, dashes or underscores");
+        }
+        None
+    }
+}
+
+/// Why an HR event was not applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HrEventRejection {
+    InvalidEvent(&'static str),
+    /// No organization by that name has a policy or any members
+    UnknownOrganization,
+    /// A joiner's username belongs to an existing account
+    UsernameTaken,
+    /// No account has the leaver's or transferee's username
+    UnknownUser,
+}
+
+impl HrEventRejection {
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            HrEventRejection::InvalidEvent(_) => "InvalidEvent",
+            HrEventRejection::UnknownOrganization => "UnknownOrganization",
+            HrEventRejection::UsernameTaken => "UsernameTaken",
+            HrEventRejection::UnknownUser => "UnknownUser",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            HrEventRejection::InvalidEvent(problem) => problem,
+            HrEventRejection::UnknownOrganization => "No organization by that name exists",
+            HrEventRejection::UsernameTaken => "An account with that username already exists",
+            HrEventRejection::UnknownUser => "No account has that username",
+        }
+    }
+}
+
+/// What became of one event of a delivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HrEventStatus {
+    Applied,
+    /// Already applied under the same event ID; nothing was changed
+    Duplicate,
+    Rejected,
+}
+
+/// One event's entry in the answer to a delivery
+#[derive(Debug, Clone, Serialize)]
+pub struct HrEventResult {
+    pub event_id: String,
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub status: HrEventStatus,
+    /// The account the event applied to
+    pub user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<&'static str>,
+}
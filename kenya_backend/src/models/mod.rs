@@ -1,2 +1,10 @@
 pub mod user;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod audit_event;
+pub mod admin;
+pub mod context;
+pub mod permission;
+pub mod security_txt;
+pub mod policy;
+pub mod pagination;
+pub mod integration;
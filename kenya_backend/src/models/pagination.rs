@@ -0,0 +1,354 @@
+use actix_web::{dev::Payload, http::StatusCode, web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::query::QueryAs;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::Sqlite;
+use std::cmp::Ordering;
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+/// Most items one page of any admin listing holds
+pub const MAX_PER_PAGE: i64 = 100;
+
+pub const DEFAULT_PER_PAGE: i64 = 50;
+
+/// Sort direction of a listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    fn sql(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+/// How a sort column's values are stored, so a cursor binds them back the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKind {
+    Text,
+    Timestamp,
+}
+
+/// A field a listing can be sorted by
+#[derive(Debug)]
+pub struct SortField {
+    /// Name clients pass as `sort`
+    pub name: &'static str,
+    /// Column it sorts on; never taken from the request
+    pub column: &'static str,
+    pub kind: SortKind,
+    pub default_direction: SortDirection,
+}
+
+/// The fields one listing can be sorted by, the first being its default,
+/// and the unique column that breaks ties between equal values
+#[derive(Debug)]
+pub struct Sortable {
+    pub fields: &'static [SortField],
+    pub id_column: &'static str,
+}
+
+impl Sortable {
+    fn field(&self, name: Option<&str>) -> Result<&'static SortField, ListError> {
+        match name {
+            None => Ok(&self.fields[0]),
+            Some(name) => self.fields.iter().find(|field| field.name == name).ok_or_else(|| ListError::UnknownSortField {
+                given: name.to_string(),
+                valid: self.fields.iter().map(|field| field.name).collect(),
+            }),
+        }
+    }
+}
+
+/// A sort column value of a listed row
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Text(String),
+    Timestamp(DateTime<Utc>),
+}
+
+/// A listed row that a later page can resume after
+pub trait Cursored {
+    /// Its value of the sort field `name`
+    fn sort_value(&self, name: &str) -> SortValue;
+    /// Its value of the listing's `id_column`
+    fn cursor_id(&self) -> Uuid;
+}
+
+/// Where a cursor resumes: after the row with this sort value and ID
+#[derive(Debug, Serialize, Deserialize)]
+struct CursorPosition {
+    sort: String,
+    direction: SortDirection,
+    value: String,
+    id: Uuid,
+}
+
+/// Paging, sorting and cursor query parameters shared by admin listings:
+/// `page` (from 1) or `cursor`, `per_page` (at most `MAX_PER_PAGE`), `sort`
+/// and `direction`. Other parameters are left to the listing's own query.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ListParams {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    /// `next_cursor` of the previous page; takes precedence over `page`
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+    pub direction: Option<SortDirection>,
+}
+
+impl ListParams {
+    /// Check the parameters against what the listing can be sorted by
+    pub fn resolve(&self, sortable: &'static Sortable) -> Result<PageRequest, ListError> {
+        let field = sortable.field(self.sort.as_deref())?;
+        let direction = self.direction.unwrap_or(field.default_direction);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+        let after = match self.cursor.as_deref() {
+            None => None,
+            Some(cursor) => {
+                let position = decode_cursor(cursor).ok_or(ListError::InvalidCursor)?;
+                // A cursor only means something in the order it was taken in
+                if position.sort != field.name || position.direction != direction {
+                    return Err(ListError::InvalidCursor);
+                }
+                let value = match field.kind {
+                    SortKind::Text => SortValue::Text(position.value),
+                    SortKind::Timestamp => SortValue::Timestamp(
+                        DateTime::parse_from_rfc3339(&position.value)
+                            .map_err(|_| ListError::InvalidCursor)?
+                            .with_timezone(&Utc),
+                    ),
+                };
+                Some((value, position.id))
+            }
+        };
+        let offset = match after {
+            Some(_) => 0,
+            None => (self.page.unwrap_or(1).max(1) - 1).saturating_mul(per_page),
+        };
+
+        Ok(PageRequest { sortable, field, direction, per_page, offset, after })
+    }
+}
+
+/// Admin listings take `ListParams` as an argument; malformed values are a
+/// 400 like any other invalid listing parameter
+impl FromRequest for ListParams {
+    type Error = ListError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            web::Query::<ListParams>::from_query(req.query_string())
+                .map(web::Query::into_inner)
+                .map_err(|e| ListError::Malformed(e.to_string())),
+        )
+    }
+}
+
+/// One page of a listing, resolved from `ListParams`. Builds the listing's
+/// ORDER BY, LIMIT and cursor condition from the whitelisted column; values
+/// from the request only ever reach the query as bound parameters.
+#[derive(Debug)]
+pub struct PageRequest {
+    sortable: &'static Sortable,
+    field: &'static SortField,
+    direction: SortDirection,
+    per_page: i64,
+    offset: i64,
+    after: Option<(SortValue, Uuid)>,
+}
+
+impl PageRequest {
+    /// Condition for rows after the cursor, if resuming from one; bind its
+    /// values with `bind_after`
+    pub fn after_condition(&self) -> Option<String> {
+        self.after.as_ref().map(|_| {
+            let (column, id_column) = (self.field.column, self.sortable.id_column);
+            let beyond = match self.direction {
+                SortDirection::Asc => ">",
+                SortDirection::Desc => "<",
+            };
+            format!("({column} {beyond} ? OR ({column} = ? AND {id_column} {beyond} ?))")
+        })
+    }
+
+    /// `ORDER BY ... LIMIT ? OFFSET ?`; bind its values with `bind_limit`
+    pub fn order_and_limit(&self) -> String {
+        let direction = self.direction.sql();
+        format!(
+            "ORDER BY {} {direction}, {} {direction} LIMIT ? OFFSET ?",
+            self.field.column, self.sortable.id_column
+        )
+    }
+
+    pub fn bind_after<'q, O>(&'q self, query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        match &self.after {
+            None => query,
+            Some((SortValue::Text(value), id)) => query.bind(value.as_str()).bind(value.as_str()).bind(*id),
+            Some((SortValue::Timestamp(value), id)) => query.bind(*value).bind(*value).bind(*id),
+        }
+    }
+
+    /// Binds one row more than the page holds, which tells `finish` whether another page follows
+    pub fn bind_limit<'q, O>(&self, query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        query.bind(self.per_page + 1).bind(self.offset)
+    }
+
+    /// Binds every row from the first through one past this page, for a
+    /// listing gathered from several sources and put together by `merge`
+    pub fn bind_limit_from_start<'q, O>(&self, query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        query.bind(self.offset + self.per_page + 1).bind(0)
+    }
+
+    /// Put together the page from rows several sources returned for
+    /// `bind_limit_from_start`, ordered as the database would order them
+    pub fn merge<R: Cursored>(&self, mut rows: Vec<R>, total: i64) -> Paginated<R> {
+        let name = self.field.name;
+        rows.sort_by(|a, b| {
+            let order = match (a.sort_value(name), b.sort_value(name)) {
+                (SortValue::Text(a), SortValue::Text(b)) => a.cmp(&b),
+                (SortValue::Timestamp(a), SortValue::Timestamp(b)) => a.cmp(&b),
+                _ => Ordering::Equal,
+            }
+            .then_with(|| a.cursor_id().cmp(&b.cursor_id()));
+            match self.direction {
+                SortDirection::Asc => order,
+                SortDirection::Desc => order.reverse(),
+            }
+        });
+        rows.drain(..(self.offset as usize).min(rows.len()));
+        self.finish(rows, total)
+    }
+
+    /// Turn the rows fetched with `bind_limit` into the page, with a cursor
+    /// to the next one when there is more
+    pub fn finish<R: Cursored>(&self, mut rows: Vec<R>, total: i64) -> Paginated<R> {
+        let more = rows.len() as i64 > self.per_page;
+        rows.truncate(self.per_page as usize);
+        let next_cursor = rows.last().filter(|_| more).map(|last| {
+            let value = match last.sort_value(self.field.name) {
+                SortValue::Text(value) => value,
+                SortValue::Timestamp(value) => value.to_rfc3339(),
+            };
+            encode_cursor(&CursorPosition {
+                sort: self.field.name.to_string(),
+                direction: self.direction,
+                value,
+                id: last.cursor_id(),
+            })
+        });
+        Paginated { items: rows, total, next_cursor }
+    }
+}
+
+/// A page of a listing: its items, how many match the listing's filters
+/// across all pages, and the cursor to pass for the next page
+#[derive(Debug, Clone, Serialize)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+fn encode_cursor(position: &CursorPosition) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(position).unwrap_or_default())
+}
+
+fn decode_cursor(cursor: &str) -> Option<CursorPosition> {
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()
+}
+
+/// Listing parameters that can't be served
+#[derive(Debug, thiserror::Error)]
+pub enum ListError {
+    #[error("Invalid listing parameters: {0}")]
+    Malformed(String),
+    #[error("Unknown sort field: {given}")]
+    UnknownSortField { given: String, valid: Vec<&'static str> },
+    #[error("Invalid cursor; start again from the first page")]
+    InvalidCursor,
+}
+
+impl ResponseError for ListError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({
+            "success": false,
+            "message": self.to_string(),
+        });
+        match self {
+            ListError::Malformed(_) => {}
+            ListError::UnknownSortField { valid, .. } => {
+                body["errors"] = json!({ "sort": [self.to_string()] });
+                body["valid_sort_fields"] = json!(valid);
+            }
+            ListError::InvalidCursor => body["errors"] = json!({ "cursor": [self.to_string()] }),
+        }
+        HttpResponse::BadRequest().json(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SORTABLE: Sortable = Sortable {
+        fields: &[
+            SortField { name: "created_at", column: "created_at", kind: SortKind::Timestamp, default_direction: SortDirection::Desc },
+            SortField { name: "username", column: "username", kind: SortKind::Text, default_direction: SortDirection::Asc },
+        ],
+        id_column: "id",
+    };
+
+    #[test]
+    fn test_per_page_is_capped_and_pages_start_at_one() {
+        let page = ListParams { per_page: Some(10_000), page: Some(3), ..ListParams::default() }.resolve(&SORTABLE).unwrap();
+        assert_eq!((page.per_page, page.offset), (MAX_PER_PAGE, 2 * MAX_PER_PAGE));
+        let page = ListParams { per_page: Some(0), page: Some(-4), ..ListParams::default() }.resolve(&SORTABLE).unwrap();
+        assert_eq!((page.per_page, page.offset), (1, 0));
+        assert_eq!(ListParams::default().resolve(&SORTABLE).unwrap().per_page, DEFAULT_PER_PAGE);
+    }
+
+    #[test]
+    fn test_sql_uses_whitelisted_columns_only() {
+        let page = ListParams { sort: Some("username".to_string()), ..ListParams::default() }.resolve(&SORTABLE).unwrap();
+        assert_eq!(page.order_and_limit(), "ORDER BY username ASC, id ASC LIMIT ? OFFSET ?");
+        assert_eq!(page.after_condition(), None);
+
+        let injected = ListParams { sort: Some("username; DROP TABLE users".to_string()), ..ListParams::default() };
+        assert!(matches!(injected.resolve(&SORTABLE), Err(ListError::UnknownSortField { .. })));
+    }
+
+    #[test]
+    fn test_cursors_only_resume_the_order_they_came_from() {
+        let cursor = encode_cursor(&CursorPosition {
+            sort: "created_at".to_string(),
+            direction: SortDirection::Desc,
+            value: "2026-10-16T08:00:00+00:00".to_string(),
+            id: Uuid::nil(),
+        });
+        let page = ListParams { cursor: Some(cursor.clone()), page: Some(5), ..ListParams::default() }.resolve(&SORTABLE).unwrap();
+        assert_eq!(page.offset, 0);
+        assert_eq!(page.after_condition().unwrap(), "(created_at < ? OR (created_at = ? AND id < ?))");
+
+        let reversed = ListParams { cursor: Some(cursor), direction: Some(SortDirection::Asc), ..ListParams::default() };
+        assert!(matches!(reversed.resolve(&SORTABLE), Err(ListError::InvalidCursor)));
+        let garbage = ListParams { cursor: Some("not-a-cursor".to_string()), ..ListParams::default() };
+        assert!(matches!(garbage.resolve(&SORTABLE), Err(ListError::InvalidCursor)));
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::models::user::UserRole;
+
+/// One capability checked by the admin and data endpoints.
+///
+/// The discriminant is the permission's bit in token claims, so existing
+/// variants must keep their position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Read the security event feed, its summary and statistics
+    AuditRead = 0,
+    /// Export security events in bulk and users' personal data
+    AuditExport = 1,
+    /// Lock, unlock, deactivate and activate accounts, and change their permissions
+    UserManage = 2,
+    /// List and end other users' sessions
+    SessionTerminate = 3,
+    /// Upload vulnerability datasets
+    DataUpload = 4,
+    /// Toggle maintenance mode and feature flags
+    MaintenanceManage = 5,
+    /// Take database backups, which contain every credential hash and 2FA secret
+    BackupManage = 6,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 7] = [
+        Permission::AuditRead,
+        Permission::AuditExport,
+        Permission::UserManage,
+        Permission::SessionTerminate,
+        Permission::DataUpload,
+        Permission::MaintenanceManage,
+        Permission::BackupManage,
+    ];
+
+    /// Permission name as stored in the database and shown to clients
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::AuditRead => "audit_read",
+            Permission::AuditExport => "audit_export",
+            Permission::UserManage => "user_manage",
+            Permission::SessionTerminate => "session_terminate",
+            Permission::DataUpload => "data_upload",
+            Permission::MaintenanceManage => "maintenance_manage",
+            Permission::BackupManage => "backup_manage",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << (self as u32)
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A permission name this binary doesn't know
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPermission(pub String);
+
+impl fmt::Display for UnknownPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown permission: {}", self.0)
+    }
+}
+
+impl FromStr for Permission {
+    type Err = UnknownPermission;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == name)
+            .ok_or_else(|| UnknownPermission(name.to_string()))
+    }
+}
+
+/// A set of permissions, carried in token claims as a bitmask and shown to
+/// clients as a list of names
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PermissionSet(u32);
+
+impl PermissionSet {
+    /// Rebuild a set from token claims, ignoring bits no permission uses
+    pub fn from_bits(bits: u32) -> Self {
+        let known = Permission::ALL.iter().fold(0, |mask, permission| mask | permission.bit());
+        Self(bits & known)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, permission: Permission) -> bool {
+        self.0 & permission.bit() != 0
+    }
+
+    /// What a role grants before any per-user override
+    pub fn for_role(role: &UserRole) -> Self {
+        match role {
+            UserRole::Admin => Permission::ALL.into_iter().collect(),
+            UserRole::KenyaGovernment => [Permission::DataUpload].into_iter().collect(),
+        }
+    }
+
+    /// Apply per-user overrides; an override always beats the role default
+    pub fn with_overrides(self, overrides: &[PermissionOverride]) -> Self {
+        overrides.iter().fold(self, |set, o| {
+            if o.granted {
+                Self(set.0 | o.permission.bit())
+            } else {
+                Self(set.0 & !o.permission.bit())
+            }
+        })
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Permission> {
+        Permission::ALL.into_iter().filter(move |permission| self.contains(*permission))
+    }
+}
+
+impl FromIterator<Permission> for PermissionSet {
+    fn from_iter<I: IntoIterator<Item = Permission>>(permissions: I) -> Self {
+        Self(permissions.into_iter().fold(0, |mask, permission| mask | permission.bit()))
+    }
+}
+
+impl Serialize for PermissionSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for PermissionSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<Permission>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// Grant or withdraw one permission for one user, whatever their role says
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionOverride {
+    pub permission: Permission,
+    pub granted: bool,
+}
+
+/// A user's permissions and where they come from, for administrators
+#[derive(Debug, Clone, Serialize)]
+pub struct UserPermissions {
+    pub role: UserRole,
+    pub role_defaults: PermissionSet,
+    pub overrides: Vec<PermissionOverride>,
+    pub effective: PermissionSet,
+    /// Bumped on every change; tokens carrying an older version are refused
+    pub token_version: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_defaults() {
+        let admin = PermissionSet::for_role(&UserRole::Admin);
+        assert!(Permission::ALL.iter().all(|p| admin.contains(*p)));
+
+        let government = PermissionSet::for_role(&UserRole::KenyaGovernment);
+        assert!(government.contains(Permission::DataUpload));
+        assert!(!government.contains(Permission::AuditRead));
+        assert!(!government.contains(Permission::UserManage));
+    }
+
+    #[test]
+    fn test_overrides_beat_role_defaults() {
+        let overrides = [
+            PermissionOverride { permission: Permission::AuditRead, granted: true },
+            PermissionOverride { permission: Permission::DataUpload, granted: false },
+        ];
+        let set = PermissionSet::for_role(&UserRole::KenyaGovernment).with_overrides(&overrides);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![Permission::AuditRead]);
+
+        let withdrawn = [PermissionOverride { permission: Permission::UserManage, granted: false }];
+        let admin = PermissionSet::for_role(&UserRole::Admin).with_overrides(&withdrawn);
+        assert!(!admin.contains(Permission::UserManage));
+        assert!(admin.contains(Permission::AuditRead));
+    }
+
+    #[test]
+    fn test_bitmask_round_trip_drops_unknown_bits() {
+        let set: PermissionSet = [Permission::AuditRead, Permission::SessionTerminate].into_iter().collect();
+        assert_eq!(PermissionSet::from_bits(set.bits()), set);
+        assert_eq!(PermissionSet::from_bits(set.bits() | 1 << 31), set);
+    }
+
+    #[test]
+    fn test_names_round_trip() {
+        for permission in Permission::ALL {
+            assert_eq!(permission.as_str().parse::<Permission>(), Ok(permission));
+        }
+        assert!("root".parse::<Permission>().is_err());
+        assert_eq!(
+            serde_json::to_value(PermissionSet::from_bits(0b101)).unwrap(),
+            serde_json::json!(["audit_read", "user_manage"])
+        );
+    }
+}
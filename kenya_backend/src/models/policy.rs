@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::models::auth::SecurityConfig;
+
+/// Longest organization name accepted
+pub const MAX_ORGANIZATION_LENGTH: usize = 100;
+
+/// Whether `name` is usable as an organization: 1-100 letters, digits,
+/// spaces, dots, dashes or underscores, not starting or ending with a space
+pub fn is_valid_organization(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_ORGANIZATION_LENGTH
+        && name.trim() == name
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, ' ' | '.' | '-' | '_'))
+}
+
+/// One organization's overrides of the global security settings. `None`
+/// fields follow the global value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct OrgSecurityPolicy {
+    pub organization: String,
+    pub session_timeout_minutes: Option<i64>,
+    pub max_failed_attempts: Option<i32>,
+    pub require_two_fa: Option<bool>,
+    pub password_max_age_days: Option<i64>,
+    /// Administrator who last changed the policy
+    pub updated_by: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Replacement overrides for an organization; omitted fields follow the global settings
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct SetOrgPolicyRequest {
+    #[validate(range(min = 1, max = 1440, message = "Session timeout must be between 1 minute and 24 hours"))]
+    pub session_timeout_minutes: Option<i64>,
+
+    #[validate(range(min = 1, max = 100, message = "Max failed attempts must be between 1 and 100"))]
+    pub max_failed_attempts: Option<i32>,
+
+    pub require_two_fa: Option<bool>,
+
+    /// 0 never ages
+    #[validate(range(min = 0, max = 3650, message = "Password max age must be between 0 and 3650 days"))]
+    pub password_max_age_days: Option<i64>,
+}
+
+/// Security settings in force for an account: its organization's overrides
+/// over the global configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EffectivePolicy {
+    pub session_timeout_minutes: i64,
+    pub max_failed_attempts: i32,
+    pub require_two_fa: bool,
+    pub password_max_age_days: i64,
+}
+
+impl EffectivePolicy {
+    /// The global settings, for accounts without an organization or whose
+    /// organization has no policy
+    pub fn global(config: &SecurityConfig) -> Self {
+        Self {
+            session_timeout_minutes: config.session_timeout_minutes,
+            max_failed_attempts: config.max_failed_attempts,
+            require_two_fa: false,
+            password_max_age_days: config.password_max_age_days,
+        }
+    }
+
+    /// These settings with `policy`'s overrides applied
+    pub fn with_overrides(self, policy: &OrgSecurityPolicy) -> Self {
+        Self {
+            session_timeout_minutes: policy.session_timeout_minutes.unwrap_or(self.session_timeout_minutes),
+            max_failed_attempts: policy.max_failed_attempts.unwrap_or(self.max_failed_attempts),
+            require_two_fa: policy.require_two_fa.unwrap_or(self.require_two_fa),
+            password_max_age_days: policy.password_max_age_days.unwrap_or(self.password_max_age_days),
+        }
+    }
+}
+
+/// An organization's stored overrides alongside the settings they produce
+#[derive(Debug, Serialize)]
+pub struct OrgPolicyView {
+    pub organization: String,
+    pub overrides: Option<OrgSecurityPolicy>,
+    pub effective: EffectivePolicy,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_replace_only_the_fields_they_set() {
+        let global = EffectivePolicy::global(&SecurityConfig::default());
+        let policy = OrgSecurityPolicy {
+            organization: "Kisumu County".to_string(),
+            session_timeout_minutes: Some(10),
+            max_failed_attempts: None,
+            require_two_fa: Some(true),
+            password_max_age_days: None,
+            updated_by: None,
+            updated_at: Utc::now(),
+        };
+
+        let effective = global.with_overrides(&policy);
+        assert_eq!(effective.session_timeout_minutes, 10);
+        assert!(effective.require_two_fa);
+        assert_eq!(effective.max_failed_attempts, global.max_failed_attempts);
+        assert_eq!(effective.password_max_age_days, global.password_max_age_days);
+    }
+
+    #[test]
+    fn test_organization_names() {
+        assert!(is_valid_organization("Nairobi HQ"));
+        assert!(is_valid_organization("county-47_kisumu.ops"));
+        assert!(!is_valid_organization(""));
+        assert!(!is_valid_organization(" padded "));
+        assert!(!is_valid_organization("slash/name"));
+        assert!(!is_valid_organization(&"x".repeat(MAX_ORGANIZATION_LENGTH + 1)));
+    }
+}
@@ -0,0 +1,109 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+/// Days before `Expires` from which startup warns that security.txt needs renewing
+pub const EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// Vulnerability disclosure details served as `/.well-known/security.txt` (RFC 9116)
+#[derive(Debug, Clone)]
+pub struct SecurityTxt {
+    /// `mailto:`, `https://` or `tel:` URIs, most preferred first
+    pub contacts: Vec<String>,
+    pub expires: DateTime<Utc>,
+    pub policy: Option<String>,
+    /// Language tags reports may be written in, e.g. `en`, `sw`
+    pub preferred_languages: Vec<String>,
+}
+
+impl SecurityTxt {
+    /// Check the file would be valid at `now`: at least one well-formed
+    /// contact, an HTTPS policy link and an expiry still in the future
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<(), String> {
+        if self.contacts.is_empty() {
+            return Err("security.txt needs at least one contact".to_string());
+        }
+        for contact in &self.contacts {
+            if !["mailto:", "https://", "tel:"].iter().any(|scheme| contact.starts_with(scheme)) {
+                return Err(format!("security contact {:?} must be a mailto:, https:// or tel: URI", contact));
+            }
+        }
+        if let Some(policy) = &self.policy {
+            if !policy.starts_with("https://") {
+                return Err(format!("security policy URL {:?} must use https://", policy));
+            }
+        }
+        if self.expires <= now {
+            return Err(format!("security.txt expired at {}", self.expires.to_rfc3339()));
+        }
+        Ok(())
+    }
+
+    /// Whether the file expires within `days` of `now`
+    pub fn expires_within(&self, now: DateTime<Utc>, days: i64) -> bool {
+        self.expires - now <= chrono::Duration::days(days)
+    }
+
+    /// The file body, one `Field: value` line per entry
+    pub fn render(&self) -> String {
+        let mut body = String::new();
+        for contact in &self.contacts {
+            body.push_str(&format!("Contact: {}\n", contact));
+        }
+        body.push_str(&format!("Expires: {}\n", self.expires.to_rfc3339_opts(SecondsFormat::Secs, true)));
+        if let Some(policy) = &self.policy {
+            body.push_str(&format!("Policy: {}\n", policy));
+        }
+        if !self.preferred_languages.is_empty() {
+            body.push_str(&format!("Preferred-Languages: {}\n", self.preferred_languages.join(", ")));
+        }
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn security_txt(expires: DateTime<Utc>) -> SecurityTxt {
+        SecurityTxt {
+            contacts: vec!["mailto:security@kenya.fsfvi.ai".to_string()],
+            expires,
+            policy: Some("https://kenya.fsfvi.ai/security-policy".to_string()),
+            preferred_languages: vec!["en".to_string(), "sw".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_render_lists_every_field() {
+        let expires = Utc.with_ymd_and_hms(2027, 6, 30, 0, 0, 0).unwrap();
+        assert_eq!(
+            security_txt(expires).render(),
+            "Contact: mailto:security@kenya.fsfvi.ai\n\
+             Expires: 2027-06-30T00:00:00Z\n\
+             Policy: https://kenya.fsfvi.ai/security-policy\n\
+             Preferred-Languages: en, sw\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_expired_and_malformed_files() {
+        let now = Utc::now();
+        assert!(security_txt(now + Duration::days(90)).validate(now).is_ok());
+        assert!(security_txt(now - Duration::seconds(1)).validate(now).is_err());
+
+        let mut no_scheme = security_txt(now + Duration::days(90));
+        no_scheme.contacts = vec!["security@kenya.fsfvi.ai".to_string()];
+        assert!(no_scheme.validate(now).is_err());
+
+        let mut plain_http = security_txt(now + Duration::days(90));
+        plain_http.policy = Some("http://kenya.fsfvi.ai/security-policy".to_string());
+        assert!(plain_http.validate(now).is_err());
+    }
+
+    #[test]
+    fn test_expiry_warning_window() {
+        let now = Utc::now();
+        assert!(security_txt(now + Duration::days(EXPIRY_WARNING_DAYS)).expires_within(now, EXPIRY_WARNING_DAYS));
+        assert!(!security_txt(now + Duration::days(EXPIRY_WARNING_DAYS + 1)).expires_within(now, EXPIRY_WARNING_DAYS));
+    }
+}
@@ -1,19 +1,41 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 use validator::Validate;
 
-/// User role enum - only Kenya Government allowed  
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
+use crate::models::permission::PermissionSet;
+use crate::services::login_challenge_service::LOGIN_CHALLENGE_TTL_MINUTES;
+use crate::services::password_change_challenge::PASSWORD_CHANGE_CHALLENGE_TTL_MINUTES;
+use crate::services::password_rules::validate_password_strength;
+
+/// User role enum - Kenya Government users, plus administrators of the platform
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "user_role", rename_all = "snake_case")]
 pub enum UserRole {
+    #[default]
     KenyaGovernment,
+    Admin,
 }
 
-impl Default for UserRole {
-    fn default() -> Self {
-        UserRole::KenyaGovernment
+impl UserRole {
+    /// Role name as stored in the database and JWT claims
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::KenyaGovernment => "kenya_government",
+            UserRole::Admin => "admin",
+        }
+    }
+
+    /// Role from its stored name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "kenya_government" => Some(UserRole::KenyaGovernment),
+            "admin" => Some(UserRole::Admin),
+            _ => None,
+        }
     }
 }
 
@@ -31,6 +53,7 @@ pub struct User {
     pub login_attempts: i32,
     pub is_locked: bool,
     pub lockout_expiry: Option<DateTime<Utc>>,
+    pub is_active: bool,
     pub password_changed_at: Option<DateTime<Utc>>,
     pub session_token: Option<String>,
     pub session_expires_at: Option<DateTime<Utc>>,
@@ -39,37 +62,126 @@ pub struct User {
     pub two_fa_secret: Option<String>,
     pub two_fa_backup_codes: Option<String>, // JSON array of backup codes
     pub two_fa_enabled_at: Option<DateTime<Utc>>,
+    /// Bumped when the user's permissions change, invalidating older tokens
+    pub token_version: i64,
+    /// When the user first replaced their temporary password
+    pub onboarded_at: Option<DateTime<Utc>>,
+    /// Organization whose security policy overrides apply; `None` follows the global settings
+    pub organization: Option<String>,
+}
+
+impl User {
+    /// Whether the account is locked at `now`.
+    ///
+    /// Failed-login lockouts carry an expiry; an administrative lock has none
+    /// and holds until it is lifted.
+    pub fn is_locked_at(&self, now: DateTime<Utc>) -> bool {
+        self.is_locked && self.lockout_expiry.is_none_or(|exp| exp > now)
+    }
 }
 
 /// User response model (without sensitive data)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserResponse {
     pub id: String,
     pub username: String,
     pub role: UserRole,
     pub is_temporary_password: bool,
+    pub onboarded_at: Option<String>,
     pub last_login: Option<String>,
     pub login_attempts: i32,
     pub is_locked: bool,
     pub lockout_expiry: Option<String>,
+    pub is_active: bool,
     // 2FA fields (excluding sensitive data)
     pub two_fa_enabled: bool,
     pub two_fa_enabled_at: Option<String>,
+    pub permissions: PermissionSet,
+    pub organization: Option<String>,
+    /// The organization's policy requires 2FA; until it is enabled the
+    /// account may sign in and enroll but not use its permissions
+    pub two_fa_required: bool,
+    /// The current terms of use are accepted, or there are none; until then
+    /// only the terms, verify and logout endpoints answer
+    pub terms_accepted: bool,
+    /// Signed in on a shared kiosk through an approval from another device;
+    /// sensitive endpoints refuse the session
+    #[serde(default)]
+    pub kiosk: bool,
+    /// Helpdesk tags, only in administrators' views of the account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Sessions not yet expired or revoked, only in the admin user listing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_sessions: Option<i64>,
+}
+
+impl UserResponse {
+    /// Show the permissions actually granted rather than the role defaults
+    pub fn with_permissions(mut self, permissions: PermissionSet) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Record whether the account's organization requires 2FA
+    pub fn with_two_fa_required(mut self, required: bool) -> Self {
+        self.two_fa_required = required;
+        self
+    }
+
+    /// Record whether the account has accepted the current terms of use
+    pub fn with_terms_accepted(mut self, accepted: bool) -> Self {
+        self.terms_accepted = accepted;
+        self
+    }
+
+    /// Record whether the session was issued to a shared kiosk
+    pub fn with_kiosk(mut self, kiosk: bool) -> Self {
+        self.kiosk = kiosk;
+        self
+    }
+
+    /// Include the account's helpdesk tags
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    /// Include how many live sessions the account has
+    pub fn with_active_sessions(mut self, count: i64) -> Self {
+        self.active_sessions = Some(count);
+        self
+    }
+
+    /// Required to enroll in 2FA before using any permission
+    pub fn must_enroll_two_fa(&self) -> bool {
+        self.two_fa_required && !self.two_fa_enabled
+    }
 }
 
 impl From<User> for UserResponse {
     fn from(user: User) -> Self {
+        let permissions = PermissionSet::for_role(&user.role);
         UserResponse {
             id: user.id.to_string(),
             username: user.username,
             role: user.role,
             is_temporary_password: user.is_temporary_password,
+            onboarded_at: user.onboarded_at.map(|dt| dt.to_rfc3339()),
             last_login: user.last_login.map(|dt| dt.to_rfc3339()),
             login_attempts: user.login_attempts,
             is_locked: user.is_locked,
             lockout_expiry: user.lockout_expiry.map(|dt| dt.to_rfc3339()),
+            is_active: user.is_active,
             two_fa_enabled: user.two_fa_enabled,
             two_fa_enabled_at: user.two_fa_enabled_at.map(|dt| dt.to_rfc3339()),
+            permissions,
+            organization: user.organization,
+            two_fa_required: false,
+            terms_accepted: true,
+            kiosk: false,
+            tags: None,
+            active_sessions: None,
         }
     }
 }
@@ -80,13 +192,94 @@ pub struct LoginRequest {
     #[validate(length(min = 3, max = 50, message = "Username must be between 3 and 50 characters"))]
     pub username: String,
 
-    #[validate(length(min = 8, message = "Password must be at least 8 characters"))]
+    #[validate(length(min = 8, max = 512, message = "Password must be between 8 and 512 characters"))]
     pub password: String,
 
-    pub user_agent: Option<String>,
-    pub ip_address: Option<String>,
     // 2FA code (optional for first step)
-    pub two_fa_code: Option<String>,
+    pub two_fa_code: Option<TwoFactorCode>,
+
+    /// Honeypot: hidden from people by the login page, so only bots fill it in
+    #[validate(length(max = 500, message = "Website must be at most 500 characters"))]
+    pub website: Option<String>,
+
+    /// Value from `GET /api/auth/login-challenge` when the form was rendered
+    #[validate(length(max = 200, message = "Form timestamp must be at most 200 characters"))]
+    pub form_issued_at: Option<String>,
+
+    /// End the account's other live sessions, e.g. after a `409 SESSION_EXISTS`
+    #[serde(default)]
+    pub sign_out_other_sessions: bool,
+
+    /// Registered client app signing in; its audience goes into the token
+    #[validate(length(min = 1, max = 64, message = "Client ID must be between 1 and 64 characters"))]
+    pub client_id: Option<String>,
+}
+
+/// Second-factor code as submitted by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TwoFactorCode {
+    /// 6-digit code from an authenticator app
+    Totp(String),
+    /// 8-character one-time backup code, upper-cased
+    Backup(String),
+}
+
+/// The code was neither a TOTP nor a backup code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedTwoFactorCode;
+
+impl fmt::Display for MalformedTwoFactorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("2FA code must be a 6-digit authenticator code or an 8-character backup code")
+    }
+}
+
+impl std::error::Error for MalformedTwoFactorCode {}
+
+impl TwoFactorCode {
+    /// Code type as recorded in audit metadata
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TwoFactorCode::Totp(_) => "totp",
+            TwoFactorCode::Backup(_) => "backup",
+        }
+    }
+}
+
+impl FromStr for TwoFactorCode {
+    type Err = MalformedTwoFactorCode;
+
+    /// Accepts codes pasted with spaces, and backup codes in any case
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let code: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+
+        if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+            Ok(TwoFactorCode::Totp(code))
+        } else if code.len() == 8 && code.chars().all(|c| c.is_ascii_alphanumeric()) {
+            Ok(TwoFactorCode::Backup(code.to_ascii_uppercase()))
+        } else {
+            Err(MalformedTwoFactorCode)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TwoFactorCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        input.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Login history query parameters
+#[derive(Debug, Deserialize)]
+pub struct LoginHistoryQuery {
+    pub limit: Option<i64>,
+}
+
+/// Lockout status query parameters
+#[derive(Debug, Deserialize)]
+pub struct LockoutStatusQuery {
+    pub username: String,
 }
 
 /// Login response model
@@ -98,6 +291,186 @@ pub struct LoginResponse {
     // 2FA status
     pub requires_two_fa: bool,
     pub two_fa_temp_token: Option<String>, // Temporary token for 2FA completion
+    /// Where the code comes from: `"authenticator"`, or `"email"` for an
+    /// account without 2FA signing in during a lockdown
+    pub two_fa_method: Option<&'static str>,
+    /// `false` until the user accepts the current terms through `POST /api/auth/terms/accept`
+    pub terms_accepted: bool,
+    /// Set instead of `token` when a v2 login must change its password first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_change_token: Option<String>,
+    /// Alerts in `GET /api/auth/my-alerts` the user hasn't dismissed; 0
+    /// until the sign-in completes
+    pub undismissed_alerts: usize,
+}
+
+/// `POST /api/v2/auth/login` request: the password step only. A 2FA
+/// account's code goes to `POST /api/v2/auth/login/2fa` with the challenge
+/// token this step returns.
+#[derive(Debug, Deserialize, Validate)]
+pub struct CredentialsLoginRequest {
+    #[validate(length(min = 3, max = 50, message = "Username must be between 3 and 50 characters"))]
+    pub username: String,
+
+    #[validate(length(min = 8, max = 512, message = "Password must be between 8 and 512 characters"))]
+    pub password: String,
+
+    /// Honeypot, as on `LoginRequest`
+    #[validate(length(max = 500, message = "Website must be at most 500 characters"))]
+    pub website: Option<String>,
+
+    #[validate(length(max = 200, message = "Form timestamp must be at most 200 characters"))]
+    pub form_issued_at: Option<String>,
+
+    #[serde(default)]
+    pub sign_out_other_sessions: bool,
+
+    #[validate(length(min = 1, max = 64, message = "Client ID must be between 1 and 64 characters"))]
+    pub client_id: Option<String>,
+}
+
+impl From<CredentialsLoginRequest> for LoginRequest {
+    fn from(request: CredentialsLoginRequest) -> Self {
+        LoginRequest {
+            username: request.username,
+            password: request.password,
+            two_fa_code: None,
+            website: request.website,
+            form_issued_at: request.form_issued_at,
+            sign_out_other_sessions: request.sign_out_other_sessions,
+            client_id: request.client_id,
+        }
+    }
+}
+
+/// `POST /api/v2/auth/login/change-password` request: the password the
+/// login was made with, again, and the new one
+#[derive(Debug, Deserialize, Validate)]
+pub struct LoginPasswordChangeRequest {
+    #[validate(length(max = 300, message = "Challenge token must be at most 300 characters"))]
+    pub challenge_token: String,
+
+    #[validate(length(min = 8, max = 512, message = "Current password must be between 8 and 512 characters"))]
+    pub current_password: String,
+
+    #[validate(length(max = 512, message = "New password must be at most 512 characters"))]
+    #[validate(custom(function = "validate_password_strength"))]
+    pub new_password: String,
+
+    #[validate(length(max = 512, message = "Password confirmation must be at most 512 characters"))]
+    pub confirm_password: String,
+}
+
+/// `POST /api/v2/auth/login/2fa` request
+#[derive(Debug, Deserialize, Validate)]
+pub struct TwoFactorLoginRequest {
+    #[validate(length(max = 100, message = "Challenge token must be at most 100 characters"))]
+    pub challenge_token: String,
+    /// Authenticator or backup code
+    pub code: TwoFactorCode,
+}
+
+/// A completed login's session token
+#[derive(Debug, Serialize)]
+pub struct TokenBundle {
+    pub token: String,
+    pub user: UserResponse,
+    pub expires_in: i64,
+    /// `false` until the user accepts the current terms through `POST /api/auth/terms/accept`
+    pub terms_accepted: bool,
+    /// As on `LoginResponse`
+    pub undismissed_alerts: usize,
+}
+
+/// Where a v2 login stands after a step, tagged by what the client does `next`
+#[derive(Debug, Serialize)]
+#[serde(tag = "next", rename_all = "snake_case")]
+pub enum LoginStep {
+    /// Signed in; nothing is left to do. Boxed, being far larger than the challenges.
+    Complete(Box<TokenBundle>),
+    /// Send a code with `challenge_token` to `POST /api/v2/auth/login/2fa`
+    /// within `expires_in` seconds. `method` says where the code comes from,
+    /// as on `LoginResponse::two_fa_method`.
+    TwoFactor { challenge_token: String, expires_in: i64, method: &'static str },
+    /// Send the password again with a new one and `challenge_token` to
+    /// `POST /api/v2/auth/login/change-password` within `expires_in` seconds.
+    /// `reason` is `temporary` or `expired`.
+    PasswordChange { challenge_token: String, expires_in: i64, reason: &'static str },
+}
+
+impl From<LoginResponse> for LoginStep {
+    fn from(response: LoginResponse) -> Self {
+        if let Some(challenge_token) = response.password_change_token {
+            return LoginStep::PasswordChange {
+                challenge_token,
+                expires_in: PASSWORD_CHANGE_CHALLENGE_TTL_MINUTES * 60,
+                reason: if response.user.is_temporary_password { "temporary" } else { "expired" },
+            };
+        }
+        match response.two_fa_temp_token {
+            Some(challenge_token) => LoginStep::TwoFactor {
+                challenge_token,
+                expires_in: LOGIN_CHALLENGE_TTL_MINUTES * 60,
+                method: response.two_fa_method.unwrap_or("authenticator"),
+            },
+            None => LoginStep::Complete(Box::new(TokenBundle {
+                token: response.token,
+                user: response.user,
+                expires_in: response.expires_in,
+                terms_accepted: response.terms_accepted,
+                undismissed_alerts: response.undismissed_alerts,
+            })),
+        }
+    }
+}
+
+/// The current terms of use and whether the caller has accepted them
+#[derive(Debug, Serialize)]
+pub struct TermsStatus {
+    /// `None` when there are no terms to accept
+    pub version: Option<String>,
+    pub accepted: bool,
+    pub accepted_at: Option<String>,
+}
+
+/// Acceptance of the terms version the client displayed
+#[derive(Debug, Deserialize, Validate)]
+pub struct AcceptTermsRequest {
+    #[validate(length(min = 1, max = 100, message = "Version must be between 1 and 100 characters"))]
+    pub version: String,
+}
+
+/// Account conditions the dashboard shows banners for, computed by
+/// `GET /api/auth/security-checkup`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecurityCheckup {
+    pub temporary_password: bool,
+    /// Older than the password max age in force for the account
+    pub password_expired: bool,
+    pub two_fa_enabled: bool,
+    /// The account's organization requires 2FA
+    pub two_fa_required: bool,
+    /// Unused backup codes; 0 without 2FA
+    pub backup_codes_remaining: usize,
+    /// 2FA is on and few backup codes are left
+    pub backup_codes_low: bool,
+    /// Impossible-travel and unexpected-country alerts about the account's
+    /// sign-ins that no administrator has acknowledged
+    pub unacknowledged_sign_in_alerts: i64,
+    /// Alerts in `GET /api/auth/my-alerts` the user hasn't dismissed
+    pub undismissed_alerts: usize,
+    pub terms_accepted: bool,
+}
+
+/// Fresh token issued when a session's authentication strength changes;
+/// the token it replaces stops validating immediately
+#[derive(Debug, Serialize)]
+pub struct SessionRenewal {
+    pub token: String,
+    pub expires_in: i64,
+    /// The new session's ID, kept out of responses
+    #[serde(skip)]
+    pub session_id: String,
 }
 
 /// 2FA Setup Request
@@ -114,6 +487,25 @@ pub struct TwoFASetupResponse {
     pub qr_code: String, // Base64 encoded QR code image
     pub backup_codes: Vec<String>,
     pub enabled: bool,
+    /// Replacement session, present once 2FA has been enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionRenewal>,
+}
+
+/// Request to show the enrollment QR code of an active 2FA secret again
+#[derive(Debug, Deserialize, Validate)]
+pub struct TwoFAQrRequest {
+    #[validate(length(min = 8, max = 512, message = "Password must be between 8 and 512 characters"))]
+    pub password: String,
+    #[validate(length(min = 6, max = 6, message = "TOTP code must be 6 digits"))]
+    pub totp_code: String,
+}
+
+/// Enrollment details for the account's existing 2FA secret
+#[derive(Debug, Serialize)]
+pub struct TwoFAQrResponse {
+    pub otpauth_url: String,
+    pub qr_code: String, // Base64 encoded QR code image
 }
 
 /// 2FA Verification Request
@@ -127,97 +519,136 @@ pub struct TwoFAVerifyRequest {
 /// 2FA Disable Request
 #[derive(Debug, Deserialize, Validate)]
 pub struct TwoFADisableRequest {
-    #[validate(length(min = 8, message = "Password must be at least 8 characters"))]
+    #[validate(length(min = 8, max = 512, message = "Password must be between 8 and 512 characters"))]
     pub password: String,
-    pub totp_code: Option<String>,
-    pub backup_code: Option<String>,
+    /// Authenticator or backup code; the old separate field names are still accepted
+    #[serde(alias = "totp_code", alias = "backup_code")]
+    pub two_fa_code: TwoFactorCode,
+}
+
+/// Step-up re-authentication request, required before sensitive admin actions
+#[derive(Debug, Deserialize, Validate)]
+pub struct StepUpRequest {
+    #[validate(length(max = 512, message = "Password must be at most 512 characters"))]
+    pub password: String,
+    /// Required when the account has 2FA enabled
+    pub two_fa_code: Option<TwoFactorCode>,
+}
+
+/// Download token request; `resource` names what the token fetches, e.g. `audit_export`
+#[derive(Debug, Deserialize, Validate)]
+pub struct DownloadTokenRequest {
+    #[validate(length(max = 64, message = "Resource must be at most 64 characters"))]
+    pub resource: String,
 }
 
 /// Change password request model
 #[derive(Debug, Deserialize, Validate)]
 pub struct ChangePasswordRequest {
-    #[validate(length(min = 8, message = "Current password must be at least 8 characters"))]
+    #[validate(length(min = 8, max = 512, message = "Current password must be between 8 and 512 characters"))]
     pub current_password: String,
 
-    #[validate(length(min = 12, message = "New password must be at least 12 characters"))]
+    #[validate(length(max = 512, message = "New password must be at most 512 characters"))]
     #[validate(custom(function = "validate_password_strength"))]
     pub new_password: String,
 
+    #[validate(length(max = 512, message = "Password confirmation must be at most 512 characters"))]
     pub confirm_password: String,
 }
 
-/// Password strength validation
-fn validate_password_strength(password: &str) -> Result<(), validator::ValidationError> {
-    let mut errors = Vec::new();
+/// Forgot-password request: which reset channels the account has, and the
+/// emailed link when it has a verified address
+#[derive(Debug, Deserialize, Validate)]
+pub struct PasswordResetRequest {
+    #[validate(length(min = 3, max = 50, message = "Username must be between 3 and 50 characters"))]
+    pub username: String,
+}
 
-    // Check length
-    if password.len() < 12 {
-        errors.push("Password must be at least 12 characters long");
-    }
+/// Set a new password with a one-time reset token, from any channel
+#[derive(Debug, Deserialize, Validate)]
+pub struct ConfirmPasswordResetRequest {
+    #[validate(length(min = 1, max = 128, message = "Reset token must be between 1 and 128 characters"))]
+    pub token: String,
 
-    // Check for uppercase
-    if !password.chars().any(|c| c.is_uppercase()) {
-        errors.push("Password must contain at least one uppercase letter");
-    }
+    #[validate(length(max = 512, message = "New password must be at most 512 characters"))]
+    #[validate(custom(function = "validate_password_strength"))]
+    pub new_password: String,
 
-    // Check for lowercase
-    if !password.chars().any(|c| c.is_lowercase()) {
-        errors.push("Password must contain at least one lowercase letter");
-    }
+    #[validate(length(max = 512, message = "Password confirmation must be at most 512 characters"))]
+    pub confirm_password: String,
+}
 
-    // Check for numbers
-    if !password.chars().any(|c| c.is_numeric()) {
-        errors.push("Password must contain at least one number");
+/// A kiosk sign-in waiting for approval from another device
+#[derive(Debug, Serialize)]
+pub struct DeviceAuthorizationStart {
+    /// Shown on the kiosk, as text and a QR code, for the account owner to approve
+    pub user_code: String,
+    /// Kept by the kiosk and sent to `POST /api/auth/device/poll`
+    pub polling_token: String,
+    pub expires_in: i64,
+    /// Seconds the kiosk should wait between polls
+    pub interval: i64,
+}
+
+/// Approve or deny a kiosk sign-in from a signed-in session
+#[derive(Debug, Deserialize, Validate)]
+pub struct DeviceApprovalRequest {
+    #[validate(length(min = 1, max = 16, message = "User code must be between 1 and 16 characters"))]
+    pub user_code: String,
+    /// `false` refuses the sign-in, e.g. when the code isn't the one on the kiosk in front of the user
+    #[serde(default = "approve_by_default")]
+    pub approve: bool,
+}
+
+fn approve_by_default() -> bool {
+    true
+}
+
+/// A kiosk asking whether its sign-in was approved
+#[derive(Debug, Deserialize, Validate)]
+pub struct DevicePollRequest {
+    #[validate(length(min = 1, max = 128, message = "Polling token must be between 1 and 128 characters"))]
+    pub polling_token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_six_digits_is_totp() {
+        assert_eq!("123456".parse(), Ok(TwoFactorCode::Totp("123456".to_string())));
     }
 
-    // Check for special characters
-    if !password.chars().any(|c| "!@#$%^&*()_+-=[]{}|;:,.<>?".contains(c)) {
-        errors.push("Password must contain at least one special character");
+    #[test]
+    fn test_eight_characters_is_backup_code_in_upper_case() {
+        assert_eq!("ab12cd34".parse(), Ok(TwoFactorCode::Backup("AB12CD34".to_string())));
+        assert_eq!("12345678".parse(), Ok(TwoFactorCode::Backup("12345678".to_string())));
     }
 
-    // Check for repeating characters
-    let mut chars: Vec<char> = password.chars().collect();
-    chars.dedup();
-    if chars.len() < password.len() * 3 / 4 {
-        errors.push("Password contains too many repeating characters");
+    #[test]
+    fn test_pasted_codes_lose_their_whitespace() {
+        assert_eq!(" 123 456\n".parse(), Ok(TwoFactorCode::Totp("123456".to_string())));
+        assert_eq!("ABCD EFGH".parse(), Ok(TwoFactorCode::Backup("ABCDEFGH".to_string())));
     }
 
-    // Check for common patterns
-    let lowercase = password.to_lowercase();
-    if lowercase.contains("123") || lowercase.contains("abc") ||
-       lowercase.contains("password") || lowercase.contains("qwerty") {
-        errors.push("Password contains common patterns");
+    #[test]
+    fn test_garbage_is_rejected() {
+        for input in ["", "12345", "1234567", "12345a", "ABCD-EFGH", "ABCDEFGHI", "１２３４５６"] {
+            assert_eq!(input.parse::<TwoFactorCode>(), Err(MalformedTwoFactorCode), "{:?}", input);
+        }
     }
 
-    if errors.is_empty() {
-        Ok(())
-    } else {
-        Err(validator::ValidationError::new("password_strength"))
+    #[test]
+    fn test_malformed_code_fails_deserialization_naming_formats() {
+        let error = serde_json::from_str::<LoginRequest>(
+            r#"{"username": "someone", "password": "Passw0rd!", "two_fa_code": "12-34"}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("6-digit authenticator code or an 8-character backup code"));
+
+        let request: TwoFADisableRequest =
+            serde_json::from_str(r#"{"password": "Passw0rd!", "backup_code": "abcd efgh"}"#).unwrap();
+        assert_eq!(request.two_fa_code, TwoFactorCode::Backup("ABCDEFGH".to_string()));
     }
 }
-
-/// Security event for logging
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SecurityEvent {
-    pub id: Uuid,
-    pub user_id: Option<Uuid>,
-    pub event_type: String,
-    pub description: String,
-    pub ip_address: Option<String>,
-    pub user_agent: Option<String>,
-    pub success: bool,
-    pub timestamp: DateTime<Utc>,
-    pub metadata: Option<serde_json::Value>,
-}
-
-/// Session information
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SessionInfo {
-    pub user_id: Uuid,
-    pub username: String,
-    pub role: UserRole,
-    pub is_temporary_password: bool,
-    pub expires_at: DateTime<Utc>,
-    pub ip_address: Option<String>,
-    pub user_agent: Option<String>,
-}
\ No newline at end of file
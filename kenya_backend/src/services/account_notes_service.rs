@@ -0,0 +1,229 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// Longest note, in characters
+pub const MAX_NOTE_CHARS: usize = 2000;
+
+/// Most tags one account can carry
+pub const MAX_TAGS_PER_USER: usize = 20;
+
+/// Longest tag, in characters
+pub const MAX_TAG_CHARS: usize = 32;
+
+/// A helpdesk note on an account
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AccountNote {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub author_id: Uuid,
+    pub note: String,
+    pub created_at: DateTime<Utc>,
+    pub pinned: bool,
+    /// Set once the note no longer applies; the text stays readable
+    pub struck_at: Option<DateTime<Utc>>,
+    pub struck_by: Option<Uuid>,
+}
+
+/// `tag` trimmed and lowercased, if what's left is 1 to `MAX_TAG_CHARS`
+/// letters, digits, dashes, underscores, dots or colons
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    let valid = (1..=MAX_TAG_CHARS).contains(&tag.chars().count())
+        && tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    valid.then_some(tag)
+}
+
+/// Administrative notes and tags on user accounts. Notes are only ever
+/// added or struck through; the database refuses edits and deletes.
+pub struct AccountNotesService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl AccountNotesService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// Add a note by `author_id` to the account
+    pub async fn add(&self, user_id: Uuid, author_id: Uuid, note: &str, pinned: bool) -> Result<AccountNote, sqlx::Error> {
+        let added = AccountNote {
+            id: Uuid::new_v4(),
+            user_id,
+            author_id,
+            note: note.to_string(),
+            created_at: self.clock.now(),
+            pinned,
+            struck_at: None,
+            struck_by: None,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO account_notes (id, user_id, author_id, note, created_at, pinned)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(added.id)
+        .bind(added.user_id)
+        .bind(added.author_id)
+        .bind(&added.note)
+        .bind(added.created_at)
+        .bind(added.pinned)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(added)
+    }
+
+    /// The account's notes, pinned ones first, then newest first
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<AccountNote>, sqlx::Error> {
+        sqlx::query_as::<_, AccountNote>(
+            "SELECT * FROM account_notes WHERE user_id = ? ORDER BY pinned DESC, created_at DESC, rowid DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// Strike through one of the account's notes. Returns the note and
+    /// whether this call struck it; striking it again changes nothing.
+    pub async fn strike(&self, user_id: Uuid, note_id: Uuid, admin_id: Uuid) -> Result<Option<(AccountNote, bool)>, sqlx::Error> {
+        let struck = sqlx::query(
+            "UPDATE account_notes SET struck_at = ?, struck_by = ? WHERE id = ? AND user_id = ? AND struck_at IS NULL",
+        )
+        .bind(self.clock.now())
+        .bind(admin_id)
+        .bind(note_id)
+        .bind(user_id)
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected()
+            > 0;
+
+        let note = sqlx::query_as::<_, AccountNote>("SELECT * FROM account_notes WHERE id = ? AND user_id = ?")
+            .bind(note_id)
+            .bind(user_id)
+            .fetch_optional(&self.db_pool)
+            .await?;
+        Ok(note.map(|note| (note, struck)))
+    }
+
+    /// The account's tags in alphabetical order
+    pub async fn tags(&self, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT tag FROM user_tags WHERE user_id = ? ORDER BY tag")
+            .bind(user_id)
+            .fetch_all(&self.db_pool)
+            .await
+    }
+
+    /// Replace the account's tags with `tags`, already normalized. Returns
+    /// the tags it had before.
+    pub async fn set_tags(&self, user_id: Uuid, tags: &[String]) -> Result<Vec<String>, sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let previous: Vec<String> = sqlx::query_scalar("SELECT tag FROM user_tags WHERE user_id = ? ORDER BY tag")
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM user_tags WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO user_tags (user_id, tag) VALUES (?, ?)")
+                .bind(user_id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_notes_are_append_only() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = AccountNotesService::new(pool.clone(), clock.clone());
+        let user_id = insert_user(&pool, clock.clone(), "on_leave", UserRole::KenyaGovernment, TEST_PASSWORD, false)
+            .await
+            .id;
+        let author_id = insert_user(&pool, clock.clone(), "helpdesk", UserRole::Admin, TEST_PASSWORD, false).await.id;
+
+        let first = service.add(user_id, author_id, "Locked on request", false).await.unwrap();
+        clock.advance(chrono::Duration::minutes(1));
+        let pinned = service.add(user_id, author_id, "On leave until March", true).await.unwrap();
+        clock.advance(chrono::Duration::minutes(1));
+        let latest = service.add(user_id, author_id, "Called to confirm", false).await.unwrap();
+        let order: Vec<_> = service.list(user_id).await.unwrap().into_iter().map(|note| note.id).collect();
+        assert_eq!(order, vec![pinned.id, latest.id, first.id]);
+
+        // The text, author and pin can't be changed and nothing can be removed
+        for statement in [
+            "UPDATE account_notes SET note = 'rewritten' WHERE id = ?",
+            "UPDATE account_notes SET author_id = id WHERE id = ?",
+            "UPDATE account_notes SET pinned = TRUE WHERE id = ?",
+            "DELETE FROM account_notes WHERE id = ?",
+        ] {
+            let refused = sqlx::query(statement).bind(first.id).execute(&pool).await.unwrap_err();
+            assert!(refused.to_string().contains("append-only"), "{statement}: {refused}");
+        }
+
+        let (struck, newly) = service.strike(user_id, first.id, author_id).await.unwrap().unwrap();
+        assert!(newly);
+        assert_eq!(struck.struck_by, Some(author_id));
+        assert_eq!(struck.note, "Locked on request");
+        let (again, newly) = service.strike(user_id, first.id, user_id).await.unwrap().unwrap();
+        assert!(!newly);
+        assert_eq!(again.struck_at, struck.struck_at);
+        assert_eq!(again.struck_by, Some(author_id));
+
+        // A struck note stays struck
+        let unstrike = sqlx::query("UPDATE account_notes SET struck_at = NULL, struck_by = NULL WHERE id = ?")
+            .bind(first.id)
+            .execute(&pool)
+            .await;
+        assert!(unstrike.is_err());
+
+        // Notes are only found under their own account
+        assert!(service.strike(author_id, pinned.id, author_id).await.unwrap().is_none());
+        assert_eq!(service.list(user_id).await.unwrap().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_tags_are_replaced_as_a_set() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = AccountNotesService::new(pool.clone(), clock.clone());
+        let user_id = insert_user(&pool, clock, "contractor", UserRole::KenyaGovernment, TEST_PASSWORD, false).await.id;
+
+        let tags = vec!["on-leave".to_string(), "contractor".to_string(), "contractor".to_string()];
+        assert!(service.set_tags(user_id, &tags).await.unwrap().is_empty());
+        assert_eq!(service.tags(user_id).await.unwrap(), vec!["contractor", "on-leave"]);
+
+        let previous = service.set_tags(user_id, &["vip".to_string()]).await.unwrap();
+        assert_eq!(previous, vec!["contractor", "on-leave"]);
+        assert_eq!(service.tags(user_id).await.unwrap(), vec!["vip"]);
+    }
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(normalize_tag("  On-Leave "), Some("on-leave".to_string()));
+        assert_eq!(normalize_tag("team:helpdesk_2.0"), Some("team:helpdesk_2.0".to_string()));
+        assert_eq!(normalize_tag(""), None);
+        assert_eq!(normalize_tag("two words"), None);
+        assert_eq!(normalize_tag("<script>"), None);
+        assert_eq!(normalize_tag(&"a".repeat(MAX_TAG_CHARS + 1)), None);
+    }
+}
@@ -0,0 +1,210 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// How long a one-time action link works after it is sent
+pub const ACTION_LINK_TTL_MINUTES: i64 = 15;
+
+/// Admin actions that can be approved from a one-time link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkedAction {
+    /// Lift the target account's lock
+    Unlock,
+    /// Turn off the target account's 2FA so it can enroll again
+    ResetTwoFa,
+}
+
+impl LinkedAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkedAction::Unlock => "unlock",
+            LinkedAction::ResetTwoFa => "reset_two_fa",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unlock" => Some(LinkedAction::Unlock),
+            "reset_two_fa" => Some(LinkedAction::ResetTwoFa),
+            _ => None,
+        }
+    }
+}
+
+/// An action waiting for `admin_id` to open its link
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingAction {
+    pub id: Uuid,
+    pub action: LinkedAction,
+    pub target_user_id: Uuid,
+    /// The only admin whose session can confirm it
+    pub admin_id: Uuid,
+    pub requested_by: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+/// What opening a link came to
+#[derive(Debug)]
+pub enum Redemption {
+    /// The action is now used and should be carried out
+    Confirmed(PendingAction),
+    /// No action has this token
+    Unknown,
+    /// Opened by an admin other than the one it was sent to; left unused
+    WrongAdmin(PendingAction),
+    AlreadyUsed(PendingAction),
+    Expired(PendingAction),
+}
+
+type PendingActionRow = (Uuid, String, Uuid, Uuid, Uuid, DateTime<Utc>, DateTime<Utc>, Option<DateTime<Utc>>);
+
+/// One-time links approving admin actions from outside a signed-in session,
+/// e.g. an unlock approved from email on a phone.
+///
+/// Each link carries a random token bound to one action and one admin. Only
+/// its hash is stored, and it works once, within `ACTION_LINK_TTL_MINUTES`.
+pub struct AdminActionService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl AdminActionService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// Store a pending action for `admin_id` to approve. Returns it with the
+    /// link token, which isn't kept and can't be recovered later.
+    pub async fn create(
+        &self,
+        action: LinkedAction,
+        target_user_id: Uuid,
+        admin_id: Uuid,
+        requested_by: Uuid,
+    ) -> Result<(PendingAction, String), sqlx::Error> {
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let now = self.clock.now();
+        let pending = PendingAction {
+            id: Uuid::new_v4(),
+            action,
+            target_user_id,
+            admin_id,
+            requested_by,
+            created_at: now,
+            expires_at: now + Duration::minutes(ACTION_LINK_TTL_MINUTES),
+            used_at: None,
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO pending_admin_actions (id, action, target_user_id, admin_id, requested_by, token_hash,
+                                               created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(pending.id)
+        .bind(action.as_str())
+        .bind(target_user_id)
+        .bind(admin_id)
+        .bind(requested_by)
+        .bind(token_hash(&token))
+        .bind(pending.created_at)
+        .bind(pending.expires_at)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok((pending, token))
+    }
+
+    /// Use the action behind `token` on behalf of `admin_id`. Only a
+    /// `Confirmed` outcome marks it used.
+    pub async fn redeem(&self, token: &str, admin_id: Uuid) -> Result<Redemption, sqlx::Error> {
+        let hash = token_hash(token);
+        let Some(pending) = self.find_by_hash(&hash).await? else {
+            return Ok(Redemption::Unknown);
+        };
+        if pending.admin_id != admin_id {
+            return Ok(Redemption::WrongAdmin(pending));
+        }
+        if pending.used_at.is_some() {
+            return Ok(Redemption::AlreadyUsed(pending));
+        }
+        let now = self.clock.now();
+        if now >= pending.expires_at {
+            return Ok(Redemption::Expired(pending));
+        }
+
+        // Two tabs opening the link at once: only one of them gets to use it
+        let used = sqlx::query("UPDATE pending_admin_actions SET used_at = ? WHERE token_hash = ? AND used_at IS NULL")
+            .bind(now)
+            .bind(&hash)
+            .execute(&self.db_pool)
+            .await?
+            .rows_affected()
+            > 0;
+        if !used {
+            return Ok(Redemption::AlreadyUsed(pending));
+        }
+        Ok(Redemption::Confirmed(PendingAction { used_at: Some(now), ..pending }))
+    }
+
+    /// Expire every unused link acting on `target_user_id`, as part of `tx`.
+    /// Returns how many there were.
+    pub async fn cancel_for_target_in(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        target_user_id: Uuid,
+    ) -> Result<u64, sqlx::Error> {
+        let now = self.clock.now();
+        let result = sqlx::query(
+            "UPDATE pending_admin_actions SET expires_at = ? WHERE target_user_id = ? AND used_at IS NULL AND expires_at > ?",
+        )
+        .bind(now)
+        .bind(target_user_id)
+        .bind(now)
+        .execute(&mut **tx)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn find_by_hash(&self, hash: &str) -> Result<Option<PendingAction>, sqlx::Error> {
+        let row: Option<PendingActionRow> = sqlx::query_as(
+            r#"
+            SELECT id, action, target_user_id, admin_id, requested_by, created_at, expires_at, used_at
+            FROM pending_admin_actions WHERE token_hash = ?
+            "#
+        )
+        .bind(hash)
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(row.and_then(|(id, action, target_user_id, admin_id, requested_by, created_at, expires_at, used_at)| {
+            Some(PendingAction {
+                id,
+                action: LinkedAction::from_name(&action)?,
+                target_user_id,
+                admin_id,
+                requested_by,
+                created_at,
+                expires_at,
+                used_at,
+            })
+        }))
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,122 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Header an integration sends its API key in
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// What an API key may be used for. Keys carry no user session, so each
+/// scope opens one integration endpoint and nothing else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    /// Push joiner, leaver and transfer events from the HR system
+    HrEvents,
+}
+
+impl ApiKeyScope {
+    pub const ALL: [ApiKeyScope; 1] = [ApiKeyScope::HrEvents];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiKeyScope::HrEvents => "hr_events",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.as_str() == name)
+    }
+}
+
+/// A configured API key: the system that holds it and what it may do
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    /// Source system tag recorded with everything done under the key
+    pub name: String,
+    pub secret: String,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+/// Why a request's API key was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyRejection {
+    /// No key was sent, or it matches no configured key
+    Unknown,
+    /// The key is valid but not for this endpoint
+    OutOfScope,
+}
+
+/// The integration a request's API key belongs to, left in the request
+/// extensions by `RouteGuard`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyCaller {
+    pub source: String,
+}
+
+struct StoredKey {
+    name: String,
+    digest: [u8; 32],
+    scopes: Vec<ApiKeyScope>,
+}
+
+/// The API keys integrations authenticate with. Only a SHA-256 of each
+/// secret is held, and presented keys are compared in constant time.
+#[derive(Default)]
+pub struct ApiKeys {
+    keys: Vec<StoredKey>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: &[ApiKey]) -> Self {
+        Self {
+            keys: keys
+                .iter()
+                .map(|key| StoredKey { name: key.name.clone(), digest: digest(&key.secret), scopes: key.scopes.clone() })
+                .collect(),
+        }
+    }
+
+    /// The integration `presented` belongs to, if it is a key allowed `scope`
+    pub fn authenticate(&self, presented: Option<&str>, scope: ApiKeyScope) -> Result<ApiKeyCaller, ApiKeyRejection> {
+        let presented = digest(presented.filter(|key| !key.is_empty()).ok_or(ApiKeyRejection::Unknown)?);
+        // Every key is compared, so the time taken doesn't tell which one matched
+        let matched = self
+            .keys
+            .iter()
+            .fold(None, |found, key| if bool::from(key.digest[..].ct_eq(&presented[..])) { Some(key) } else { found })
+            .ok_or(ApiKeyRejection::Unknown)?;
+
+        if matched.scopes.contains(&scope) {
+            Ok(ApiKeyCaller { source: matched.name.clone() })
+        } else {
+            Err(ApiKeyRejection::OutOfScope)
+        }
+    }
+}
+
+fn digest(secret: &str) -> [u8; 32] {
+    Sha256::digest(secret.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_authenticate_within_their_scopes() {
+        let keys = ApiKeys::new(&[
+            ApiKey { name: "workday".to_string(), secret: "hr-key".to_string(), scopes: vec![ApiKeyScope::HrEvents] },
+            ApiKey { name: "reporting".to_string(), secret: "other-key".to_string(), scopes: Vec::new() },
+        ]);
+
+        assert_eq!(
+            keys.authenticate(Some("hr-key"), ApiKeyScope::HrEvents),
+            Ok(ApiKeyCaller { source: "workday".to_string() })
+        );
+        assert_eq!(keys.authenticate(Some("other-key"), ApiKeyScope::HrEvents), Err(ApiKeyRejection::OutOfScope));
+        assert_eq!(keys.authenticate(Some("hr-key "), ApiKeyScope::HrEvents), Err(ApiKeyRejection::Unknown));
+        assert_eq!(keys.authenticate(Some(""), ApiKeyScope::HrEvents), Err(ApiKeyRejection::Unknown));
+        assert_eq!(keys.authenticate(None, ApiKeyScope::HrEvents), Err(ApiKeyRejection::Unknown));
+        assert_eq!(ApiKeyScope::from_name("hr_events"), Some(ApiKeyScope::HrEvents));
+    }
+}
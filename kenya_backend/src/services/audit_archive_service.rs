@@ -0,0 +1,419 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::services::backup_service::file_sha256;
+use crate::utils::clock::Clock;
+
+/// How often the scheduled archival looks for months past the active window
+pub const AUDIT_ARCHIVE_CHECK_INTERVAL_SECONDS: u64 = 24 * 3600;
+
+/// Rows moved per transaction, so the login path never waits long on the archival
+const DEFAULT_BATCH_ROWS: i64 = 5000;
+
+/// The high-volume audit tables, archived month by month
+const ARCHIVED_TABLES: [&str; 2] = ["security_events", "login_attempts"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// SQLite attaches in memory when the primary database is, leaving nothing on disk
+    #[error("Archive file {} would not be written; is the primary database in memory?", .0.display())]
+    NotWritten(PathBuf),
+}
+
+/// A month's archive file as recorded in the manifest
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AuditArchive {
+    /// `YYYY-MM`
+    pub month: String,
+    pub path: String,
+    pub security_events: i64,
+    pub login_attempts: i64,
+    pub size_bytes: i64,
+    /// Hex SHA-256 of the file when the last run for the month finished
+    pub sha256: String,
+    pub archived_at: DateTime<Utc>,
+}
+
+/// Moves security events and login attempts out of the primary database into
+/// one SQLite file per month, `audit-YYYY-MM.db` in `dir`, so the tables the
+/// login path writes to stay small. Rows are copied and deleted in batched
+/// transactions; an interrupted run leaves every row in exactly one place
+/// and the next run picks up where it stopped.
+#[derive(Clone)]
+pub struct AuditArchiveService {
+    db_pool: SqlitePool,
+    dir: PathBuf,
+    /// Whether the audit listing may read the archive files
+    reads_enabled: bool,
+    batch_rows: i64,
+    clock: Arc<dyn Clock>,
+}
+
+impl AuditArchiveService {
+    pub fn new(db_pool: SqlitePool, dir: PathBuf, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, dir, reads_enabled: false, batch_rows: DEFAULT_BATCH_ROWS, clock }
+    }
+
+    /// Let the audit listing read archive files when asked to
+    pub fn with_reads_enabled(mut self, enabled: bool) -> Self {
+        self.reads_enabled = enabled;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_batch_rows(mut self, batch_rows: i64) -> Self {
+        self.batch_rows = batch_rows;
+        self
+    }
+
+    pub fn reads_enabled(&self) -> bool {
+        self.reads_enabled
+    }
+
+    /// Archive every row older than `before`, into the file of the month it
+    /// belongs to. Returns the manifest entries of the months touched.
+    pub async fn archive_before(&self, before: DateTime<Utc>) -> Result<Vec<AuditArchive>, ArchiveError> {
+        let mut months = BTreeSet::new();
+        for table in ARCHIVED_TABLES {
+            let found: Vec<String> = sqlx::query_scalar(&format!(
+                "SELECT DISTINCT substr(timestamp, 1, 7) FROM {} WHERE timestamp < ?",
+                table
+            ))
+            .bind(before)
+            .fetch_all(&self.db_pool)
+            .await?;
+            months.extend(found);
+        }
+        if months.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut archived = Vec::with_capacity(months.len());
+        for month in months {
+            let Some((start, end)) = month_bounds(&month) else {
+                log::warn!("Not archiving audit rows with unreadable timestamps starting {:?}", month);
+                continue;
+            };
+            archived.push(self.archive_month(&month, start, end.min(before)).await?);
+        }
+        Ok(archived)
+    }
+
+    /// Move the rows from `start` up to `end` into `month`'s file and record it in the manifest
+    async fn archive_month(&self, month: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<AuditArchive, ArchiveError> {
+        let path = self.dir.join(format!("audit-{}.db", month));
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("ATTACH DATABASE ? AS archive")
+            .bind(path.to_string_lossy().into_owned())
+            .execute(&mut *conn)
+            .await?;
+        // Checked before any row moves, since rows moved into memory are lost on DETACH
+        let file: String = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'archive'")
+            .fetch_one(&mut *conn)
+            .await?;
+        let moved = if file.is_empty() {
+            Err(ArchiveError::NotWritten(path.clone()))
+        } else {
+            self.move_rows(&mut conn, start, end).await.map_err(ArchiveError::from)
+        };
+        let detached = sqlx::query("DETACH DATABASE archive").execute(&mut *conn).await;
+        match detached {
+            Ok(_) => drop(conn),
+            Err(e) => {
+                log::error!("Failed to detach {}: {}", path.display(), e);
+                // Don't hand a connection with the archive still attached back to the pool
+                drop(conn.detach());
+            }
+        }
+        let [security_events, login_attempts] = moved?;
+
+        let hashed = path.clone();
+        let (size_bytes, sha256) = tokio::task::spawn_blocking(move || file_sha256(&hashed))
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))??;
+        let archive = AuditArchive {
+            month: month.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            security_events,
+            login_attempts,
+            size_bytes: size_bytes as i64,
+            sha256,
+            archived_at: self.clock.now(),
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO audit_archives (month, path, security_events, login_attempts, size_bytes, sha256, archived_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (month) DO UPDATE SET path = excluded.path, security_events = excluded.security_events,
+                login_attempts = excluded.login_attempts, size_bytes = excluded.size_bytes,
+                sha256 = excluded.sha256, archived_at = excluded.archived_at
+            "#
+        )
+        .bind(&archive.month)
+        .bind(&archive.path)
+        .bind(archive.security_events)
+        .bind(archive.login_attempts)
+        .bind(archive.size_bytes)
+        .bind(&archive.sha256)
+        .bind(archive.archived_at)
+        .execute(&self.db_pool)
+        .await?;
+
+        log::info!(
+            "Archived audit rows for {} to {} ({} security events, {} login attempts, sha256 {})",
+            archive.month,
+            archive.path,
+            archive.security_events,
+            archive.login_attempts,
+            archive.sha256
+        );
+        Ok(archive)
+    }
+
+    /// Copy then delete the rows in range, a batch per transaction, on a
+    /// connection with the month's file attached as `archive`. Returns how
+    /// many rows of each table the file holds afterwards.
+    async fn move_rows(&self, conn: &mut SqliteConnection, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<[i64; 2], sqlx::Error> {
+        let mut counts = [0; 2];
+        for (table, count) in ARCHIVED_TABLES.into_iter().zip(counts.iter_mut()) {
+            let columns = archive_table(conn, table).await?;
+            // The batch is picked the same way twice inside one transaction,
+            // and only rows that made it into the archive are deleted
+            let batch = format!(
+                "SELECT rowid FROM main.{table} WHERE timestamp >= ? AND timestamp < ? ORDER BY timestamp, rowid LIMIT ?"
+            );
+            let copy = format!("INSERT OR IGNORE INTO archive.{table} ({columns}) SELECT {columns} FROM main.{table} WHERE rowid IN ({batch})");
+            let delete = format!("DELETE FROM main.{table} WHERE rowid IN ({batch}) AND id IN (SELECT id FROM archive.{table})");
+            loop {
+                let mut tx = conn.begin().await?;
+                sqlx::query(&copy).bind(start).bind(end).bind(self.batch_rows).execute(&mut *tx).await?;
+                let deleted = sqlx::query(&delete)
+                    .bind(start)
+                    .bind(end)
+                    .bind(self.batch_rows)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                tx.commit().await?;
+                if deleted == 0 {
+                    break;
+                }
+            }
+            *count = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM archive.{table}")).fetch_one(&mut *conn).await?;
+        }
+        Ok(counts)
+    }
+
+    /// Every archive file recorded in the manifest, oldest month first
+    pub async fn archives(&self) -> Result<Vec<AuditArchive>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM audit_archives ORDER BY month").fetch_all(&self.db_pool).await
+    }
+
+    /// The archived months with rows between `since` and `until`, either open-ended
+    pub async fn archives_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<AuditArchive>, sqlx::Error> {
+        let first = since.map(|since| since.format("%Y-%m").to_string());
+        let last = until.map(|until| until.format("%Y-%m").to_string());
+        Ok(self
+            .archives()
+            .await?
+            .into_iter()
+            .filter(|archive| first.as_ref().is_none_or(|first| archive.month >= *first))
+            .filter(|archive| last.as_ref().is_none_or(|last| archive.month <= *last))
+            .collect())
+    }
+
+    /// Once a day, archive the whole months older than `active_window_days`,
+    /// for as long as the server runs
+    pub fn spawn_schedule(self, active_window_days: i64) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(AUDIT_ARCHIVE_CHECK_INTERVAL_SECONDS));
+            loop {
+                ticker.tick().await;
+                let cutoff = month_start(self.clock.now() - chrono::Duration::days(active_window_days));
+                match self.archive_before(cutoff).await {
+                    Ok(archived) if archived.is_empty() => {}
+                    Ok(archived) => log::info!("Scheduled audit archival moved {} month(s) before {}", archived.len(), cutoff.to_rfc3339()),
+                    Err(e) => log::error!("Scheduled audit archival failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// Create the archived copy of `table` in the attached file, or add the
+/// columns the live table has gained since the file was started. Returns
+/// the live table's columns, comma-separated.
+async fn archive_table(conn: &mut SqliteConnection, table: &str) -> Result<String, sqlx::Error> {
+    sqlx::query(&format!("CREATE TABLE IF NOT EXISTS archive.{table} AS SELECT * FROM main.{table} WHERE FALSE"))
+        .execute(&mut *conn)
+        .await?;
+    let columns = |schema: &'static str| {
+        sqlx::query_scalar::<_, String>("SELECT name FROM pragma_table_info(?, ?) ORDER BY cid").bind(table).bind(schema)
+    };
+    let live = columns("main").fetch_all(&mut *conn).await?;
+    let archived = columns("archive").fetch_all(&mut *conn).await?;
+    for column in live.iter().filter(|column| !archived.contains(column)) {
+        sqlx::query(&format!("ALTER TABLE archive.{table} ADD COLUMN {column}")).execute(&mut *conn).await?;
+    }
+    sqlx::query(&format!("CREATE UNIQUE INDEX IF NOT EXISTS archive.idx_{table}_id ON {table}(id)"))
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(&format!("CREATE INDEX IF NOT EXISTS archive.idx_{table}_timestamp ON {table}(timestamp)"))
+        .execute(&mut *conn)
+        .await?;
+    Ok(live.join(", "))
+}
+
+/// Start of `month` (`YYYY-MM`) and of the month after
+fn month_bounds(month: &str) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let first = NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()?;
+    let next = first.checked_add_months(Months::new(1))?;
+    Some((first.and_hms_opt(0, 0, 0)?.and_utc(), next.and_hms_opt(0, 0, 0)?.and_utc()))
+}
+
+/// Midnight on the first of the month `at` falls in
+fn month_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(at.year(), at.month(), 1)
+        .and_then(|first| first.and_hms_opt(0, 0, 0))
+        .map(|first| first.and_utc())
+        .unwrap_or(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::pagination::{ListParams, PageRequest};
+    use crate::services::audit_service::{AuditFilter, AuditService, AUDIT_SORT};
+    use crate::services::geoip_service::GeoIpService;
+    use crate::test_support::MockClock;
+    use crate::utils::database::{run_migrations, test_pool};
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    async fn seed(pool: &SqlitePool, at: DateTime<Utc>, description: &str) {
+        sqlx::query(
+            "INSERT INTO security_events (id, event_type, description, success, severity, timestamp) VALUES (?, 'LOGIN_ATTEMPT', ?, FALSE, 'warning', ?)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(description)
+        .bind(at)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO login_attempts (id, username, success, timestamp) VALUES (?, 'officer', FALSE, ?)")
+            .bind(Uuid::new_v4())
+            .bind(at)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn count(pool: &SqlitePool, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table)).fetch_one(pool).await.unwrap()
+    }
+
+    fn page(cursor: Option<String>) -> PageRequest {
+        ListParams { per_page: Some(4), cursor, ..ListParams::default() }.resolve(&AUDIT_SORT).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_archived_months_leave_the_primary_and_stay_readable() {
+        // ATTACH from an in-memory database stays in memory too, so this needs a real file
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SqlitePool::connect(&format!("sqlite:{}?mode=rwc", dir.path().join("live.db").display()))
+            .await
+            .unwrap();
+        run_migrations(&pool).await.unwrap();
+        let clock = Arc::new(MockClock::new());
+        let archives = AuditArchiveService::new(pool.clone(), dir.path().to_path_buf(), clock.clone()).with_batch_rows(2);
+        let day = |month: u32, day: u32| Utc.with_ymd_and_hms(2024, month, day, 9, 30, 0).unwrap();
+        for i in 0..5 {
+            seed(&pool, day(11, 10 + i), &format!("November {}", i)).await;
+        }
+        for i in 0..3 {
+            seed(&pool, day(12, 1 + i), &format!("December {}", i)).await;
+            seed(&pool, day(12, 20 + i), &format!("Late December {}", i)).await;
+        }
+
+        // Only rows before the cutoff move, into the file of their month
+        let archived = archives.archive_before(day(12, 15)).await.unwrap();
+        assert_eq!(archived.iter().map(|a| a.month.as_str()).collect::<Vec<_>>(), ["2024-11", "2024-12"]);
+        assert_eq!((archived[0].security_events, archived[0].login_attempts), (5, 5));
+        assert_eq!((archived[1].security_events, archived[1].login_attempts), (3, 3));
+        assert_eq!(count(&pool, "security_events").await, 3);
+        assert_eq!(count(&pool, "login_attempts").await, 3);
+        for archive in &archived {
+            assert_eq!(archive.sha256, file_sha256(std::path::Path::new(&archive.path)).unwrap().1);
+        }
+
+        // A later run adds the rest of December to the same file
+        let archived = archives.archive_before(day(12, 31)).await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!((archived[0].security_events, archived[0].login_attempts), (6, 6));
+        assert_eq!(count(&pool, "security_events").await, 0);
+        assert_eq!(archives.archives().await.unwrap().len(), 2);
+        assert!(archives.archive_before(day(12, 31)).await.unwrap().is_empty());
+
+        // Reads across the primary and the archives page in one order
+        seed(&pool, day(12, 31), "New Year's Eve").await;
+        let audit = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), clock);
+        let paths: Vec<PathBuf> = archives.archives().await.unwrap().into_iter().map(|a| PathBuf::from(a.path)).collect();
+        let filter = AuditFilter { include_reads: true, ..AuditFilter::default() };
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let events = audit.list_events_page_with_archives(&filter, &page(cursor), &paths).await.unwrap();
+            assert_eq!(events.total, 12);
+            listed.extend(events.items.into_iter().map(|e| e.description));
+            cursor = events.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(listed.len(), 12);
+        assert_eq!(listed.first().unwrap(), "New Year's Eve");
+        assert_eq!(listed.last().unwrap(), "November 0");
+
+        // Date ranges narrow what each file contributes
+        let december = AuditFilter { since: Some(day(12, 1)), until: Some(day(12, 15)), ..filter };
+        let events = audit.list_events_page_with_archives(&december, &page(None), &paths).await.unwrap();
+        assert_eq!(events.total, 3);
+        let in_range = archives.archives_between(december.since, december.until).await.unwrap();
+        assert_eq!(in_range.iter().map(|a| a.month.as_str()).collect::<Vec<_>>(), ["2024-12"]);
+    }
+
+    #[actix_web::test]
+    async fn test_archiving_from_an_in_memory_database_fails_clearly() {
+        let pool = test_pool().await;
+        let dir = tempfile::tempdir().unwrap();
+        let archives = AuditArchiveService::new(pool.clone(), dir.path().to_path_buf(), Arc::new(MockClock::new()));
+        seed(&pool, Utc.with_ymd_and_hms(2024, 11, 10, 9, 30, 0).unwrap(), "November").await;
+
+        let err = archives.archive_before(Utc.with_ymd_and_hms(2024, 12, 1, 0, 0, 0).unwrap()).await.unwrap_err();
+        assert!(matches!(&err, ArchiveError::NotWritten(path) if path.ends_with("audit-2024-11.db")), "{}", err);
+        assert!(archives.archives().await.unwrap().is_empty());
+        assert_eq!(count(&pool, "security_events").await, 1);
+    }
+
+    #[test]
+    fn test_month_bounds() {
+        let (start, end) = month_bounds("2024-12").unwrap();
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 12, 1, 0, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(month_start(Utc.with_ymd_and_hms(2025, 3, 17, 8, 0, 0).unwrap()), Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap());
+        assert_eq!(month_bounds("garbage"), None);
+    }
+}
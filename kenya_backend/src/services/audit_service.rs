@@ -1,71 +1,329 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use serde_json::json;
-use sqlx::SqlitePool;
+use sqlx::query::QueryAs;
+use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions};
+use sqlx::{ConnectOptions, Connection, Sqlite, SqlitePool};
+use std::path::PathBuf;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::models::auth::AuditLogEntry;
+use crate::models::admin::{
+    EventStats, IpActivity, IpActivityEntry, IpCorrelation, KeyCount, LockedAccount, StatsBucket, StatsWindow,
+};
+use crate::models::audit_event::AuditEventType;
+use crate::models::auth::{AuditLogEntry, Severity};
+use crate::models::context::RequestContext;
+use crate::models::pagination::{Cursored, PageRequest, Paginated, SortDirection, SortField, SortKind, SortValue, Sortable};
+use crate::services::geoip_service::{truncate_ip, GeoIpService};
+use crate::services::webhook_service::{Notifier, Urgency, SIEM_DESTINATION};
+use crate::utils::clock::Clock;
+use crate::utils::database::ReadPool;
+use crate::utils::db_retry::BusyRetry;
+use crate::utils::sanitize::{self, TextLimits};
+
+/// Columns selected into an `AuditLogEntry`
+const AUDIT_ENTRY_COLUMNS: &str = "id, user_id, event_type, description, ip_address, user_agent, \
+     success, severity, timestamp, metadata as details, acknowledged_by, acknowledged_at, resolution_note";
+
+/// Stored as `{"_error": ...}` in place of metadata that couldn't be serialized
+pub const METADATA_SERIALIZATION_FAILED: &str = "metadata_serialization_failed";
+
+/// Default hours a full client address is kept in `raw_ip` under privacy mode
+pub const DEFAULT_RAW_IP_RETENTION_HOURS: i64 = 72;
+
+/// How often expired full addresses are cleared
+pub const RAW_IP_PURGE_INTERVAL_SECONDS: u64 = 3600;
+
+/// Upper bound on rows in each statistics grouping
+const MAX_STATS_GROUPS: i64 = 200;
+
+/// Every row of both tables for one address. Login attempts also write a
+/// `LOGIN_ATTEMPT` security event; only the `login_attempts` row is kept.
+const IP_ACTIVITY_ROWS: &str = r#"
+    SELECT 'login_attempt' AS source, id, timestamp, user_id, username, 'LOGIN_ATTEMPT' AS event_type,
+           failure_reason AS description, success, user_agent
+    FROM login_attempts WHERE ip_address = ?
+    UNION ALL
+    SELECT 'security_event' AS source, id, timestamp, user_id, NULL AS username, event_type,
+           description, success, user_agent
+    FROM security_events WHERE ip_address = ? AND event_type != 'LOGIN_ATTEMPT'
+"#;
+
+/// Outcome of acknowledging a security event
+#[derive(Debug)]
+pub enum Acknowledgement {
+    /// The event was acknowledged by this call
+    Recorded(AuditLogEntry),
+    /// The event had already been acknowledged; it is left untouched
+    AlreadyAcknowledged(AuditLogEntry),
+    NotFound,
+}
+
+/// Which security events a listing or export covers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditFilter {
+    /// Only events nobody has acknowledged
+    pub unacknowledged_only: bool,
+    pub severity: Option<Severity>,
+    pub event_type: Option<AuditEventType>,
+    /// Include `ADMIN_READ` events, which are left out unless asked for
+    pub include_reads: bool,
+    /// Only events at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only events before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Fields the security event listing can be sorted by, newest first by default
+pub const AUDIT_SORT: Sortable = Sortable {
+    fields: &[
+        SortField { name: "timestamp", column: "timestamp", kind: SortKind::Timestamp, default_direction: SortDirection::Desc },
+        SortField { name: "event_type", column: "event_type", kind: SortKind::Text, default_direction: SortDirection::Asc },
+    ],
+    id_column: "id",
+};
+
+impl Cursored for AuditLogEntry {
+    fn sort_value(&self, name: &str) -> SortValue {
+        match name {
+            "event_type" => SortValue::Text(self.event_type.as_str().to_string()),
+            _ => SortValue::Timestamp(self.timestamp),
+        }
+    }
+
+    fn cursor_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Conditions selecting the events an `AuditFilter` covers; bind them with `bind_filter`
+const AUDIT_FILTER_CONDITIONS: &str = "(? = FALSE OR acknowledged_at IS NULL)
+              AND (? IS NULL OR severity = ?)
+              AND (? IS NULL OR event_type = ?)
+              AND (? = TRUE OR event_type != 'ADMIN_READ')
+              AND (? IS NULL OR timestamp >= ?)
+              AND (? IS NULL OR timestamp < ?)";
+
+impl AuditFilter {
+    fn bind_filter<'q, O>(&self, query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        // Asking for reads by name includes them
+        let include_reads = self.include_reads || self.event_type == Some(AuditEventType::AdminRead);
+        query
+            .bind(self.unacknowledged_only)
+            .bind(self.severity.map(|s| s.as_str()))
+            .bind(self.severity.map(|s| s.as_str()))
+            .bind(self.event_type)
+            .bind(self.event_type)
+            .bind(include_reads)
+            .bind(self.since)
+            .bind(self.since)
+            .bind(self.until)
+            .bind(self.until)
+    }
+}
 
 /// Audit service for comprehensive security logging
 pub struct AuditService {
     db_pool: SqlitePool,
+    /// Listings, dashboard counts and exports; may lag behind `db_pool`
+    read_pool: ReadPool,
+    geoip: Arc<GeoIpService>,
+    /// Forwards warning and critical events when a SIEM destination is configured
+    webhooks: Option<Arc<dyn Notifier>>,
+    text_limits: TextLimits,
+    /// How long full client addresses stay in `raw_ip` under privacy mode
+    raw_ip_retention: chrono::Duration,
+    busy_retry: Arc<BusyRetry>,
+    clock: Arc<dyn Clock>,
 }
 
 impl AuditService {
-    pub fn new(db_pool: SqlitePool) -> Self {
-        Self { db_pool }
+    pub fn new(db_pool: SqlitePool, geoip: Arc<GeoIpService>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            read_pool: ReadPool::primary_only(db_pool.clone()),
+            db_pool,
+            geoip,
+            webhooks: None,
+            text_limits: TextLimits::default(),
+            raw_ip_retention: chrono::Duration::hours(DEFAULT_RAW_IP_RETENTION_HOURS),
+            busy_retry: Arc::new(BusyRetry::default()),
+            clock,
+        }
+    }
+
+    /// Count busy retries in `busy_retry`, shared with the other services
+    pub fn with_busy_retry(mut self, busy_retry: Arc<BusyRetry>) -> Self {
+        self.busy_retry = busy_retry;
+        self
+    }
+
+    /// Run listings, dashboard counts and exports through `read_pool`
+    pub fn with_read_pool(mut self, read_pool: ReadPool) -> Self {
+        self.read_pool = read_pool;
+        self
+    }
+
+    /// Cap the length of stored descriptions, user agents and metadata strings
+    pub fn with_text_limits(mut self, text_limits: TextLimits) -> Self {
+        self.text_limits = text_limits;
+        self
+    }
+
+    pub fn text_limits(&self) -> TextLimits {
+        self.text_limits
+    }
+
+    /// Keep full client addresses for `hours` before `purge_raw_ips` clears them
+    pub fn with_raw_ip_retention(mut self, hours: i64) -> Self {
+        self.raw_ip_retention = chrono::Duration::hours(hours);
+        self
+    }
+
+    /// Truncate the addresses of events recorded before privacy mode was on,
+    /// so an export holds nothing privacy mode wouldn't store
+    pub fn truncate_exported_ips(&self, events: &mut [AuditLogEntry]) {
+        if self.geoip.privacy_mode() {
+            for event in events {
+                event.ip_address = event.ip_address.as_deref().map(truncate_ip);
+            }
+        }
+    }
+
+    /// Clear full client addresses older than the retention from audit rows and
+    /// login history, leaving the truncated ones. Returns how many rows were cleared.
+    pub async fn purge_raw_ips(&self) -> Result<u64, sqlx::Error> {
+        let cutoff = self.clock.now() - self.raw_ip_retention;
+        let mut purged = 0;
+        for table in ["login_attempts", "security_events"] {
+            let purge = format!("UPDATE {} SET raw_ip = NULL WHERE raw_ip IS NOT NULL AND timestamp < ?", table);
+            purged += self
+                .busy_retry
+                .run(|| sqlx::query(&purge).bind(cutoff).execute(&self.db_pool))
+                .await?
+                .rows_affected();
+        }
+        Ok(purged)
+    }
+
+    /// Forward warning and critical events to the `siem` webhook destination, if there is one
+    pub fn with_webhooks(mut self, webhooks: Arc<dyn Notifier>) -> Self {
+        self.webhooks = Some(webhooks).filter(|w| w.has_destination(SIEM_DESTINATION));
+        self
     }
 
-    /// Log a security event
+    /// Log a security event; client details and the request ID come from `ctx`
+    #[allow(clippy::too_many_arguments)]
     pub async fn log_security_event(
         &self,
+        ctx: &RequestContext,
         user_id: Option<Uuid>,
-        event_type: &str,
+        event_type: AuditEventType,
         description: &str,
-        ip_address: Option<&str>,
-        user_agent: Option<&str>,
         success: bool,
+        severity: Severity,
         details: Option<serde_json::Value>,
     ) -> Result<(), sqlx::Error> {
         let event_id = Uuid::new_v4();
-        let now = Utc::now();
-        let metadata = details.map(|d| serde_json::to_string(&d).unwrap_or_default());
+        let now = self.clock.now();
+        // Descriptions and details quote usernames, user agents and other
+        // client-chosen text; clean it once here for every caller
+        let description = sanitize::text(description, self.text_limits.text);
+        let description = description.as_str();
+        let user_agent = ctx.user_agent.as_deref().map(|ua| sanitize::text(ua, self.text_limits.user_agent));
+        let details = details.map(|details| sanitize::json(details, self.text_limits.text));
 
-        sqlx::query!(
-            r#"
-            INSERT INTO security_events (id, user_id, event_type, description,
-                                       ip_address, user_agent, success, timestamp, metadata)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
-            "#,
-            event_id,
-            user_id,
-            event_type,
-            description,
-            ip_address,
-            user_agent,
-            success,
-            now,
-            metadata
-        )
-        .execute(&self.db_pool)
-        .await?;
+        let mut metadata = match details {
+            Some(serde_json::Value::Object(map)) => map,
+            Some(other) => {
+                let mut map = serde_json::Map::new();
+                map.insert("details".to_string(), other);
+                map
+            }
+            None => serde_json::Map::new(),
+        };
+        // Ties the event to the request's log lines and X-Request-Id header
+        metadata.insert("request_id".to_string(), json!(ctx.request_id));
+        // Attach the client's location so reviewers see more than a bare IP
+        if let Some(location) = self.geoip.stored_location(&ctx.ip_address) {
+            metadata.insert("geo".to_string(), metadata_value(&location));
+        }
+        let metadata = serde_json::Value::Object(metadata);
+        // Keys are sorted, so the stored text is canonical
+        let metadata_json = metadata.to_string();
+        let severity_name = severity.as_str();
+        let ip_address = self.geoip.stored_ip(&ctx.ip_address);
+        let raw_ip = self.geoip.raw_ip(&ctx.ip_address);
+
+        self.busy_retry
+            .run(|| {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO security_events (id, user_id, event_type, description,
+                                               ip_address, raw_ip, user_agent, success, severity, timestamp, metadata)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    "#,
+                    event_id,
+                    user_id,
+                    event_type,
+                    description,
+                    ip_address,
+                    raw_ip,
+                    user_agent,
+                    success,
+                    severity_name,
+                    now,
+                    metadata_json
+                )
+                .execute(&self.db_pool)
+            })
+            .await?;
+
+        if let (Some(webhooks), Severity::Warning | Severity::Critical) = (&self.webhooks, severity) {
+            webhooks.clone().notify(
+                SIEM_DESTINATION,
+                "security_event",
+                json!({
+                    "id": event_id,
+                    "event_type": event_type,
+                    "description": description,
+                    "user_id": user_id,
+                    "ip_address": ip_address,
+                    "success": success,
+                    "severity": severity_name,
+                    "timestamp": now,
+                    "metadata": metadata,
+                }),
+                if severity == Severity::Critical { Urgency::Critical } else { Urgency::Informational },
+            );
+        }
 
         // Also log to application logs for real-time monitoring
-        if success {
-            log::info!(
-                "SECURITY EVENT: {} - {} (User: {:?}, IP: {:?})",
+        match severity {
+            Severity::Critical => log::error!(
+                "SECURITY CRITICAL: {} - {} (User: {:?}, IP: {}, Request: {})",
                 event_type,
                 description,
                 user_id,
-                ip_address.unwrap_or("unknown")
-            );
-        } else {
-            log::warn!(
-                "SECURITY ALERT: {} - {} (User: {:?}, IP: {:?})",
+                ctx.ip_address,
+                ctx.request_id
+            ),
+            Severity::Warning => log::warn!(
+                "SECURITY ALERT: {} - {} (User: {:?}, IP: {}, Request: {})",
                 event_type,
                 description,
                 user_id,
-                ip_address.unwrap_or("unknown")
-            );
+                ctx.ip_address,
+                ctx.request_id
+            ),
+            Severity::Info => log::info!(
+                "SECURITY EVENT: {} - {} (User: {:?}, IP: {}, Request: {})",
+                event_type,
+                description,
+                user_id,
+                ctx.ip_address,
+                ctx.request_id
+            ),
         }
 
         Ok(())
@@ -74,26 +332,25 @@ impl AuditService {
     /// Log login attempt
     pub async fn log_login_attempt(
         &self,
+        ctx: &RequestContext,
         user_id: Option<Uuid>,
         username: &str,
-        ip_address: &str,
-        user_agent: Option<&str>,
         success: bool,
         failure_reason: Option<&str>,
     ) -> Result<(), sqlx::Error> {
         let details = json!({
             "username": username,
             "failure_reason": failure_reason,
-            "timestamp": Utc::now().to_rfc3339()
+            "timestamp": self.clock.now().to_rfc3339()
         });
 
         self.log_security_event(
+            ctx,
             user_id,
-            "LOGIN_ATTEMPT",
+            AuditEventType::LoginAttempt,
             &format!("Login attempt for user: {}", username),
-            Some(ip_address),
-            user_agent,
             success,
+            if success { Severity::Info } else { Severity::Warning },
             Some(details),
         )
         .await
@@ -102,26 +359,25 @@ impl AuditService {
     /// Log password change
     pub async fn log_password_change(
         &self,
+        ctx: &RequestContext,
         user_id: Uuid,
         username: &str,
-        ip_address: &str,
-        user_agent: Option<&str>,
         success: bool,
         was_temporary: bool,
     ) -> Result<(), sqlx::Error> {
         let details = json!({
             "username": username,
             "was_temporary_password": was_temporary,
-            "timestamp": Utc::now().to_rfc3339()
+            "timestamp": self.clock.now().to_rfc3339()
         });
 
         self.log_security_event(
+            ctx,
             Some(user_id),
-            "PASSWORD_CHANGE",
+            AuditEventType::PasswordChange,
             &format!("Password change for user: {}", username),
-            Some(ip_address),
-            user_agent,
             success,
+            if success { Severity::Info } else { Severity::Warning },
             Some(details),
         )
         .await
@@ -130,110 +386,1069 @@ impl AuditService {
     /// Log token validation
     pub async fn log_token_validation(
         &self,
+        ctx: &RequestContext,
         user_id: Option<Uuid>,
-        ip_address: &str,
-        user_agent: Option<&str>,
         success: bool,
         failure_reason: Option<&str>,
     ) -> Result<(), sqlx::Error> {
         let details = json!({
             "failure_reason": failure_reason,
-            "timestamp": Utc::now().to_rfc3339()
+            "timestamp": self.clock.now().to_rfc3339()
         });
 
         self.log_security_event(
+            ctx,
             user_id,
-            "TOKEN_VALIDATION",
+            AuditEventType::TokenValidation,
             "Token validation attempt",
-            Some(ip_address),
-            user_agent,
             success,
+            if success { Severity::Info } else { Severity::Warning },
             Some(details),
         )
         .await
     }
 
     /// Log logout
-    pub async fn log_logout(
+    pub async fn log_logout(&self, ctx: &RequestContext, user_id: Uuid, username: &str) -> Result<(), sqlx::Error> {
+        let details = json!({
+            "username": username,
+            "timestamp": self.clock.now().to_rfc3339()
+        });
+
+        self.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::Logout,
+            &format!("User logout: {}", username),
+            true,
+            Severity::Info,
+            Some(details),
+        )
+        .await
+    }
+
+    /// Log an automatic lockout after repeated failed logins
+    pub async fn log_account_lockout(
         &self,
+        ctx: &RequestContext,
         user_id: Uuid,
         username: &str,
-        ip_address: &str,
-        user_agent: Option<&str>,
+        failed_attempts: i32,
     ) -> Result<(), sqlx::Error> {
         let details = json!({
             "username": username,
-            "timestamp": Utc::now().to_rfc3339()
+            "failed_attempts": failed_attempts,
+            "timestamp": self.clock.now().to_rfc3339()
         });
 
         self.log_security_event(
+            ctx,
             Some(user_id),
-            "LOGOUT",
-            &format!("User logout: {}", username),
-            Some(ip_address),
-            user_agent,
+            AuditEventType::AccountLockout,
+            &format!("Account locked after {} failed logins: {}", failed_attempts, username),
+            false,
+            Severity::Critical,
+            Some(details),
+        )
+        .await
+    }
+
+    /// Log a second-factor check during login, noting which kind of code was tried
+    pub async fn log_two_fa_attempt(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        code_type: &str,
+        success: bool,
+    ) -> Result<(), sqlx::Error> {
+        let details = json!({
+            "username": username,
+            "code_type": code_type,
+            "timestamp": self.clock.now().to_rfc3339()
+        });
+
+        self.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::TwoFaAttempt,
+            &format!("2FA {} code attempt for user: {}", code_type, username),
+            success,
+            if success { Severity::Info } else { Severity::Warning },
+            Some(details),
+        )
+        .await
+    }
+
+    /// Log 2FA being turned off for an account
+    pub async fn log_two_fa_disabled(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        code_type: &str,
+    ) -> Result<(), sqlx::Error> {
+        let details = json!({
+            "username": username,
+            "code_type": code_type,
+            "timestamp": self.clock.now().to_rfc3339()
+        });
+
+        self.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::TwoFaDisabled,
+            &format!("Two-factor authentication disabled for user: {}", username),
             true,
+            Severity::Critical,
             Some(details),
         )
         .await
     }
 
-    /// Get recent security events for monitoring
-    pub async fn get_recent_events(&self, limit: i32) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
-        let events = sqlx::query_as::<_, AuditLogEntry>(
-            r#"
-            SELECT id, user_id, event_type, description, ip_address,
-                   user_agent, success, timestamp, metadata as details
-            FROM security_events
-            ORDER BY timestamp DESC
-            LIMIT ?
-            "#
+    /// Log a request to show an active 2FA secret's QR code again. Always at
+    /// least a warning: whoever sees the code can clone the authenticator.
+    pub async fn log_two_fa_qr_redisplayed(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        success: bool,
+    ) -> Result<(), sqlx::Error> {
+        let details = json!({
+            "username": username,
+            "timestamp": self.clock.now().to_rfc3339()
+        });
+
+        self.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::TwoFaQrRedisplayed,
+            &format!("2FA enrollment QR code requested again for user: {}", username),
+            success,
+            Severity::Warning,
+            Some(details),
         )
-        .bind(limit)
-        .fetch_all(&self.db_pool)
-        .await?;
+        .await
+    }
+
+    /// Log use of the break-glass account. Always critical: every use needs review.
+    pub async fn log_break_glass_used(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        session_expires_at: chrono::DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let details = json!({
+            "username": username,
+            "session_expires_at": session_expires_at.to_rfc3339(),
+            "timestamp": self.clock.now().to_rfc3339()
+        });
+
+        self.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::BreakGlassUsed,
+            &format!("Break-glass account {} used to sign in", username),
+            true,
+            Severity::Critical,
+            Some(details),
+        )
+        .await
+    }
+
+    /// `list_events` with `ADMIN_READ` events included
+    #[cfg(test)]
+    pub async fn get_recent_events(
+        &self,
+        limit: i64,
+        unacknowledged_only: bool,
+        severity: Option<Severity>,
+    ) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
+        let filter = AuditFilter { unacknowledged_only, severity, include_reads: true, ..AuditFilter::default() };
+        self.list_events(limit, &filter).await
+    }
+
+    /// Get recent security events for monitoring, newest first, narrowed by `filter`
+    pub async fn list_events(&self, limit: i64, filter: &AuditFilter) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM security_events WHERE {} ORDER BY timestamp DESC LIMIT ?",
+            AUDIT_ENTRY_COLUMNS, AUDIT_FILTER_CONDITIONS
+        );
+        let events = self
+            .read_pool
+            .run(|pool| filter.bind_filter(sqlx::query_as::<_, AuditLogEntry>(&query)).bind(limit).fetch_all(pool))
+            .await?;
 
         Ok(events)
     }
 
-    /// Get security events for a specific user
-    pub async fn get_user_events(
+    /// Count and page queries of the events `filter` covers
+    fn page_queries(page: &PageRequest) -> (String, String) {
+        let count = format!("SELECT COUNT(*) FROM security_events WHERE {}", AUDIT_FILTER_CONDITIONS);
+        let after = page.after_condition().map(|condition| format!("AND {}", condition)).unwrap_or_default();
+        let query = format!(
+            "SELECT {} FROM security_events WHERE {} {} {}",
+            AUDIT_ENTRY_COLUMNS,
+            AUDIT_FILTER_CONDITIONS,
+            after,
+            page.order_and_limit()
+        );
+        (count, query)
+    }
+
+    /// One page of the events `filter` covers, in the order `page` asks for
+    pub async fn list_events_page(&self, filter: &AuditFilter, page: &PageRequest) -> Result<Paginated<AuditLogEntry>, sqlx::Error> {
+        let (count, query) = Self::page_queries(page);
+        let (total,) = self
+            .read_pool
+            .run(|pool| filter.bind_filter(sqlx::query_as::<_, (i64,)>(&count)).fetch_one(pool))
+            .await?;
+
+        let events = self
+            .read_pool
+            .run(|pool| {
+                page.bind_limit(page.bind_after(filter.bind_filter(sqlx::query_as::<_, AuditLogEntry>(&query))))
+                    .fetch_all(pool)
+            })
+            .await?;
+
+        Ok(page.finish(events, total))
+    }
+
+    /// `list_events_page` over the primary database and the monthly archive
+    /// files at `archives` together. Each file is opened read-only and
+    /// queried in turn for everything up to the end of the page, so this is
+    /// much slower than the primary alone.
+    pub async fn list_events_page_with_archives(
+        &self,
+        filter: &AuditFilter,
+        page: &PageRequest,
+        archives: &[PathBuf],
+    ) -> Result<Paginated<AuditLogEntry>, sqlx::Error> {
+        let (count, query) = Self::page_queries(page);
+        let (mut total,) = self
+            .read_pool
+            .run(|pool| filter.bind_filter(sqlx::query_as::<_, (i64,)>(&count)).fetch_one(pool))
+            .await?;
+        let mut events = self
+            .read_pool
+            .run(|pool| {
+                page.bind_limit_from_start(page.bind_after(filter.bind_filter(sqlx::query_as::<_, AuditLogEntry>(&query))))
+                    .fetch_all(pool)
+            })
+            .await?;
+
+        for path in archives {
+            let mut conn = SqliteConnectOptions::new().filename(path).read_only(true).connect().await?;
+            let (archived,) = filter.bind_filter(sqlx::query_as::<_, (i64,)>(&count)).fetch_one(&mut conn).await?;
+            total += archived;
+            events.extend(
+                page.bind_limit_from_start(page.bind_after(filter.bind_filter(sqlx::query_as::<_, AuditLogEntry>(&query))))
+                    .fetch_all(&mut conn)
+                    .await?,
+            );
+            conn.close().await?;
+        }
+
+        Ok(page.merge(events, total))
+    }
+
+    /// Get a single security event
+    pub async fn get_event(&self, event_id: Uuid) -> Result<Option<AuditLogEntry>, sqlx::Error> {
+        sqlx::query_as::<_, AuditLogEntry>(&format!(
+            "SELECT {} FROM security_events WHERE id = ?",
+            AUDIT_ENTRY_COLUMNS
+        ))
+        .bind(event_id)
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Mark a security event as handled.
+    ///
+    /// Idempotent: acknowledging an event twice keeps the first acknowledgement.
+    /// A fresh acknowledgement is itself logged, referencing the original event.
+    pub async fn acknowledge_event(
+        &self,
+        ctx: &RequestContext,
+        event_id: Uuid,
+        admin_id: Uuid,
+        resolution_note: Option<&str>,
+    ) -> Result<Acknowledgement, sqlx::Error> {
+        let result = self
+            .busy_retry
+            .run(|| {
+                sqlx::query(
+                    r#"
+                    UPDATE security_events
+                    SET acknowledged_by = ?, acknowledged_at = ?, resolution_note = ?
+                    WHERE id = ? AND acknowledged_at IS NULL
+                    "#
+                )
+                .bind(admin_id)
+                .bind(self.clock.now())
+                .bind(resolution_note)
+                .bind(event_id)
+                .execute(&self.db_pool)
+            })
+            .await?;
+
+        let Some(event) = self.get_event(event_id).await? else {
+            return Ok(Acknowledgement::NotFound);
+        };
+
+        if result.rows_affected() == 0 {
+            return Ok(Acknowledgement::AlreadyAcknowledged(event));
+        }
+
+        self.log_security_event(
+            ctx,
+            Some(admin_id),
+            AuditEventType::SecurityEventAcknowledged,
+            &format!("Security event {} ({}) acknowledged", event_id, event.event_type),
+            true,
+            Severity::Info,
+            Some(json!({
+                "event_id": event_id.to_string(),
+                "event_type": event.event_type,
+                "resolution_note": resolution_note,
+            })),
+        )
+        .await?;
+
+        Ok(Acknowledgement::Recorded(event))
+    }
+
+    /// Warning and critical events nobody has acknowledged yet - the dashboard's open alerts
+    pub async fn count_unacknowledged_alerts(&self) -> Result<i64, sqlx::Error> {
+        self.read_pool
+            .run(|pool| {
+                sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE severity != 'info' AND acknowledged_at IS NULL")
+                    .fetch_one(pool)
+            })
+            .await
+    }
+
+    /// Successful events of one type for an account since `since`
+    pub async fn count_user_successes_since(
         &self,
         user_id: Uuid,
-        limit: i32,
-    ) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
-        let events = sqlx::query_as::<_, AuditLogEntry>(
+        event_type: AuditEventType,
+        since: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM security_events
+             WHERE user_id = ? AND event_type = ? AND success = TRUE AND timestamp > ?",
+        )
+        .bind(user_id)
+        .bind(event_type)
+        .bind(since)
+        .fetch_one(&self.db_pool)
+        .await
+    }
+
+    /// Events of the given types about a user that nobody has acknowledged yet
+    pub async fn count_unacknowledged_for_user(&self, user_id: Uuid, event_types: &[AuditEventType]) -> Result<i64, sqlx::Error> {
+        let placeholders = vec!["?"; event_types.len()].join(", ");
+        let sql = format!(
+            "SELECT COUNT(*) FROM security_events
+             WHERE user_id = ? AND acknowledged_at IS NULL AND event_type IN ({})",
+            placeholders
+        );
+        let mut query = sqlx::query_scalar(&sql).bind(user_id);
+        for event_type in event_types {
+            query = query.bind(*event_type);
+        }
+        query.fetch_one(&self.db_pool).await
+    }
+
+    /// Break-glass uses nobody has reviewed yet, newest first. The dashboard
+    /// keeps showing them until an admin acknowledges each one.
+    pub async fn unreviewed_break_glass_events(&self) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
+        let query = format!(
             r#"
-            SELECT id, user_id, event_type, description, ip_address,
-                   user_agent, success, timestamp, metadata as details
+            SELECT {}
             FROM security_events
-            WHERE user_id = ?
+            WHERE event_type = ? AND acknowledged_at IS NULL
             ORDER BY timestamp DESC
-            LIMIT ?
+            "#,
+            AUDIT_ENTRY_COLUMNS
+        );
+        self.read_pool
+            .run(|pool| sqlx::query_as::<_, AuditLogEntry>(&query).bind(AuditEventType::BreakGlassUsed).fetch_all(pool))
+            .await
+    }
+
+    /// Event counts over a window, for dashboards and scraping.
+    ///
+    /// Every grouping is capped at `MAX_STATS_GROUPS` rows.
+    pub async fn event_stats(
+        &self,
+        window: StatsWindow,
+        group_by: Option<StatsBucket>,
+    ) -> Result<EventStats, sqlx::Error> {
+        let since = self.clock.now() - window.duration();
+
+        let by_event_type = self
+            .read_pool
+            .run(|pool| {
+                sqlx::query_as::<_, KeyCount>(
+                    r#"
+                    SELECT event_type as key, COUNT(*) as count
+                    FROM security_events
+                    WHERE timestamp >= ?
+                    GROUP BY event_type
+                    ORDER BY count DESC, key
+                    LIMIT ?
+                    "#
+                )
+                .bind(since)
+                .bind(MAX_STATS_GROUPS)
+                .fetch_all(pool)
+            })
+            .await?;
+
+        let by_failure_code = self
+            .read_pool
+            .run(|pool| {
+                sqlx::query_as::<_, KeyCount>(
+                    r#"
+                    SELECT COALESCE(json_extract(metadata, '$.failure_reason'), 'unspecified') as key,
+                           COUNT(*) as count
+                    FROM security_events
+                    WHERE timestamp >= ? AND success = FALSE
+                    GROUP BY key
+                    ORDER BY count DESC, key
+                    LIMIT ?
+                    "#
+                )
+                .bind(since)
+                .bind(MAX_STATS_GROUPS)
+                .fetch_all(pool)
+            })
+            .await?;
+
+        let (failed_login_distinct_ips, failed_login_distinct_usernames): (i64, i64) = self
+            .read_pool
+            .run(|pool| {
+                sqlx::query_as(
+                    r#"
+                    SELECT COUNT(DISTINCT ip_address), COUNT(DISTINCT username)
+                    FROM login_attempts
+                    WHERE timestamp >= ? AND success = FALSE
+                    "#
+                )
+                .bind(since)
+                .fetch_one(pool)
+            })
+            .await?;
+
+        let series = match group_by {
+            Some(bucket) => {
+                // Timestamps are stored as UTC RFC 3339, so a prefix is the bucket
+                let (prefix_len, suffix) = match bucket {
+                    StatsBucket::Hour => (13, ":00:00Z"),
+                    StatsBucket::Day => (10, "T00:00:00Z"),
+                };
+                let series = self
+                    .read_pool
+                    .run(|pool| {
+                        sqlx::query_as::<_, KeyCount>(
+                            r#"
+                            SELECT substr(timestamp, 1, ?) || ? as key, COUNT(*) as count
+                            FROM security_events
+                            WHERE timestamp >= ?
+                            GROUP BY key
+                            ORDER BY key
+                            LIMIT ?
+                            "#
+                        )
+                        .bind(prefix_len)
+                        .bind(suffix)
+                        .bind(since)
+                        .bind(MAX_STATS_GROUPS * 4)
+                        .fetch_all(pool)
+                    })
+                    .await?;
+                Some(series)
+            }
+            None => None,
+        };
+
+        Ok(EventStats {
+            window,
+            since,
+            by_event_type,
+            by_failure_code,
+            failed_login_distinct_ips,
+            failed_login_distinct_usernames,
+            series,
+            token_validations: None,
+            login_queue: None,
+            throttled: None,
+            cors_rejections: None,
+            db_busy_retries: None,
+            hashing_failures: None,
+            clock_skew_tolerated: None,
+            event_queues: None,
+            notification_queue: None,
+            operation_timings: None,
+        })
+    }
+
+    /// Everything recorded for one client address, newest first, with a
+    /// summary of what it targeted. `ip_address` must already be normalized;
+    /// in privacy mode the activity is that of its whole stored network.
+    pub async fn ip_activity(&self, ip_address: &str, limit: i64, offset: i64) -> Result<IpActivity, sqlx::Error> {
+        let ip_address = self.geoip.stored_ip(ip_address);
+        let ip_address = ip_address.as_str();
+        let entries = sqlx::query_as::<_, IpActivityEntry>(&format!(
+            "SELECT * FROM ({}) ORDER BY timestamp DESC, id LIMIT ? OFFSET ?",
+            IP_ACTIVITY_ROWS
+        ))
+        .bind(ip_address)
+        .bind(ip_address)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let (total, first_seen, last_seen): (i64, Option<DateTime<Utc>>, Option<DateTime<Utc>>) = sqlx::query_as(&format!(
+            "SELECT COUNT(*), MIN(timestamp), MAX(timestamp) FROM ({})",
+            IP_ACTIVITY_ROWS
+        ))
+        .bind(ip_address)
+        .bind(ip_address)
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        let (login_successes, login_failures): (i64, i64) = sqlx::query_as(
+            "SELECT COALESCE(SUM(success), 0), COALESCE(SUM(NOT success), 0) FROM login_attempts WHERE ip_address = ?",
+        )
+        .bind(ip_address)
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        let usernames_targeted: Vec<String> =
+            sqlx::query_scalar("SELECT DISTINCT username FROM login_attempts WHERE ip_address = ? ORDER BY username")
+                .bind(ip_address)
+                .fetch_all(&self.db_pool)
+                .await?;
+
+        // Failed-login lockouts of accounts tried from here, whichever address tipped them over
+        let locked_accounts = sqlx::query_as::<_, LockedAccount>(
+            r#"
+            SELECT la.user_id AS user_id, MAX(la.username) AS username, MIN(se.timestamp) AS locked_at
+            FROM login_attempts la
+            JOIN security_events se ON se.user_id = la.user_id
+            WHERE la.ip_address = ? AND se.event_type = 'ACCOUNT_LOCKOUT' AND se.timestamp >= la.timestamp
+            GROUP BY la.user_id
+            ORDER BY locked_at
             "#
         )
-        .bind(user_id)
-        .bind(limit)
+        .bind(ip_address)
         .fetch_all(&self.db_pool)
         .await?;
 
-        Ok(events)
+        Ok(IpActivity {
+            ip_address: ip_address.to_string(),
+            summary: IpCorrelation {
+                first_seen,
+                last_seen,
+                login_successes,
+                login_failures,
+                usernames_targeted,
+                locked_accounts,
+            },
+            total,
+            entries,
+        })
     }
 
     /// Get failed login attempts in the last hour
+    #[cfg(test)]
     pub async fn get_recent_failed_logins(&self) -> Result<i64, sqlx::Error> {
+        let since = self.clock.now() - chrono::Duration::hours(1);
         let count: i32 = sqlx::query_scalar!(
             r#"
             SELECT COUNT(*) as count
             FROM security_events
             WHERE event_type = 'LOGIN_ATTEMPT'
             AND success = false
-            AND timestamp > datetime('now', '-1 hour')
-            "#
+            AND timestamp > ?
+            "#,
+            since
         )
         .fetch_one(&self.db_pool)
         .await?;
 
         Ok(count as i64)
     }
-}
\ No newline at end of file
+}
+/// Columns of the audit CSV export, in order
+const CSV_COLUMNS: [&str; 9] =
+    ["id", "timestamp", "event_type", "severity", "success", "user_id", "ip_address", "user_agent", "description"];
+
+/// `value` as event metadata. A value that can't be serialized is logged
+/// and stored as a marker, so the loss shows in the audit log.
+pub fn metadata_value<T: Serialize + ?Sized>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or_else(|e| {
+        log::error!("Audit metadata could not be serialized: {}", e);
+        json!({ "_error": METADATA_SERIALIZATION_FAILED })
+    })
+}
+
+/// Render events as CSV with every cell quoted and formula-neutralized, since
+/// user agents and descriptions carry text the client chose
+pub fn events_csv(events: &[AuditLogEntry]) -> String {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push_str("\r\n");
+    for event in events {
+        let cells = [
+            event.id.to_string(),
+            event.timestamp.to_rfc3339(),
+            event.event_type.to_string(),
+            event.severity.as_str().to_string(),
+            event.success.to_string(),
+            event.user_id.map(|id| id.to_string()).unwrap_or_default(),
+            event.ip_address.clone().unwrap_or_default(),
+            event.user_agent.clone().unwrap_or_default(),
+            event.description.clone(),
+        ];
+        csv.push_str(&cells.iter().map(|cell| sanitize::csv_field(cell)).collect::<Vec<_>>().join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClock;
+    use crate::utils::clock::SystemClock;
+    use crate::utils::database::test_pool;
+
+    fn client(ip_address: &str) -> RequestContext {
+        RequestContext::new(ip_address, None)
+    }
+
+    async fn insert_admin(pool: &SqlitePool) -> Uuid {
+        let admin_id = Uuid::new_v4();
+        let now = Utc::now();
+        sqlx::query(
+            "INSERT INTO users (id, username, password_hash, role, created_at, updated_at)
+             VALUES (?, 'audit_admin', 'x', 'admin', ?, ?)",
+        )
+        .bind(admin_id)
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await
+        .unwrap();
+        admin_id
+    }
+
+    async fn failed_login_event(service: &AuditService) -> Uuid {
+        service
+            .log_login_attempt(&client("10.0.0.5"), None, "intruder", false, Some("Unknown user"))
+            .await
+            .unwrap();
+        service.get_recent_events(1, false, None).await.unwrap()[0].id
+    }
+
+    #[actix_web::test]
+    async fn test_recent_failed_logins_follow_the_service_clock() {
+        let pool = test_pool().await;
+        // This host runs three hours ahead of the database
+        let clock = Arc::new(MockClock::new());
+        clock.advance(chrono::Duration::hours(3));
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), clock.clone());
+
+        service.log_login_attempt(&client("10.0.0.5"), None, "intruder", false, Some("Unknown user")).await.unwrap();
+        service.log_login_attempt(&client("10.0.0.5"), None, "intruder", true, None).await.unwrap();
+        assert_eq!(service.get_recent_failed_logins().await.unwrap(), 1);
+
+        clock.advance(chrono::Duration::minutes(59));
+        assert_eq!(service.get_recent_failed_logins().await.unwrap(), 1);
+        // An hour by the service clock, however far it is from the database's
+        clock.advance(chrono::Duration::minutes(2));
+        assert_eq!(service.get_recent_failed_logins().await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_double_acknowledgement_is_idempotent() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+        let admin_id = insert_admin(&pool).await;
+        let event_id = failed_login_event(&service).await;
+
+        let first = service
+            .acknowledge_event(&client("10.0.0.1"), event_id, admin_id, Some("Known scanner"))
+            .await
+            .unwrap();
+        assert!(matches!(first, Acknowledgement::Recorded(_)));
+
+        let second = service
+            .acknowledge_event(&client("10.0.0.1"), event_id, admin_id, Some("Second look"))
+            .await
+            .unwrap();
+        let Acknowledgement::AlreadyAcknowledged(event) = second else {
+            panic!("expected the event to be already acknowledged");
+        };
+        assert_eq!(event.acknowledged_by, Some(admin_id));
+        assert_eq!(event.resolution_note.as_deref(), Some("Known scanner"));
+
+        // Only the first acknowledgement is audited, and it references the event
+        let acknowledgements: Vec<AuditLogEntry> = service
+            .get_recent_events(50, false, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.event_type == "SECURITY_EVENT_ACKNOWLEDGED")
+            .collect();
+        assert_eq!(acknowledgements.len(), 1);
+        let details = acknowledgements[0].details.as_ref().unwrap();
+        assert_eq!(details["event_id"], event_id.to_string());
+    }
+
+    #[actix_web::test]
+    async fn test_unacknowledged_filter_and_alert_count() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+        let admin_id = insert_admin(&pool).await;
+
+        let handled = failed_login_event(&service).await;
+        let open = failed_login_event(&service).await;
+        assert_eq!(service.count_unacknowledged_alerts().await.unwrap(), 2);
+
+        service.acknowledge_event(&client("10.0.0.1"), handled, admin_id, None).await.unwrap();
+
+        let unacknowledged = service.get_recent_events(50, true, None).await.unwrap();
+        assert!(unacknowledged.iter().all(|e| e.acknowledged_at.is_none()));
+        assert!(unacknowledged.iter().any(|e| e.id == open));
+        assert!(!unacknowledged.iter().any(|e| e.id == handled));
+
+        let everything = service.get_recent_events(50, false, None).await.unwrap();
+        assert!(everything.iter().any(|e| e.id == handled));
+
+        assert_eq!(service.count_unacknowledged_alerts().await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_acknowledging_unknown_event() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+        let admin_id = insert_admin(&pool).await;
+
+        let outcome = service.acknowledge_event(&client("10.0.0.1"), Uuid::new_v4(), admin_id, None).await.unwrap();
+        assert!(matches!(outcome, Acknowledgement::NotFound));
+    }
+
+    #[actix_web::test]
+    async fn test_convenience_methods_assign_severity() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+        let user_id = insert_admin(&pool).await;
+
+        service.log_login_attempt(&client("10.0.0.5"), None, "someone", false, Some("Invalid password")).await.unwrap();
+        service.log_login_attempt(&client("10.0.0.5"), None, "someone", true, None).await.unwrap();
+        service.log_logout(&client("10.0.0.5"), user_id, "someone").await.unwrap();
+        service.log_account_lockout(&client("10.0.0.5"), user_id, "someone", 5).await.unwrap();
+        service.log_two_fa_disabled(&client("10.0.0.5"), user_id, "someone", "totp").await.unwrap();
+
+        let events = service.get_recent_events(50, false, None).await.unwrap();
+        let severity_of = |event_type: &str, success: bool| {
+            events
+                .iter()
+                .find(|e| e.event_type == event_type && e.success == success)
+                .map(|e| e.severity)
+        };
+
+        assert_eq!(severity_of("LOGIN_ATTEMPT", false), Some(Severity::Warning));
+        assert_eq!(severity_of("LOGIN_ATTEMPT", true), Some(Severity::Info));
+        assert_eq!(severity_of("LOGOUT", true), Some(Severity::Info));
+        assert_eq!(severity_of("ACCOUNT_LOCKOUT", false), Some(Severity::Critical));
+        assert_eq!(severity_of("TWO_FA_DISABLED", true), Some(Severity::Critical));
+    }
+
+    #[actix_web::test]
+    async fn test_severity_filter() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+        let user_id = insert_admin(&pool).await;
+
+        service.log_logout(&client("10.0.0.5"), user_id, "someone").await.unwrap();
+        service.log_login_attempt(&client("10.0.0.5"), None, "someone", false, None).await.unwrap();
+        service.log_account_lockout(&client("10.0.0.5"), user_id, "someone", 5).await.unwrap();
+
+        let critical = service.get_recent_events(50, false, Some(Severity::Critical)).await.unwrap();
+        assert_eq!(critical.len(), 1);
+        assert_eq!(critical[0].event_type, "ACCOUNT_LOCKOUT");
+
+        let warnings = service.get_recent_events(50, false, Some(Severity::Warning)).await.unwrap();
+        assert!(warnings.iter().all(|e| e.severity == Severity::Warning));
+        assert_eq!(warnings.len(), 1);
+
+        assert_eq!(service.get_recent_events(50, false, None).await.unwrap().len(), 3);
+        assert_eq!(service.count_unacknowledged_alerts().await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_events_carry_geo_metadata() {
+        use crate::services::geoip_service::TEST_DATABASE;
+
+        let geoip = GeoIpService::open(Some(TEST_DATABASE), Some(TEST_DATABASE), Vec::new()).unwrap();
+        let service = AuditService::new(test_pool().await, Arc::new(geoip), Arc::new(SystemClock));
+
+        service.log_login_attempt(&client("81.2.69.160"), None, "traveller", false, None).await.unwrap();
+        service.log_login_attempt(&client("192.168.1.20"), None, "insider", false, None).await.unwrap();
+
+        let events = service.get_recent_events(10, false, None).await.unwrap();
+        let details_for = |username: &str| {
+            events
+                .iter()
+                .find(|e| e.description.ends_with(username))
+                .and_then(|e| e.details.clone())
+                .unwrap()
+        };
+
+        let resolved = details_for("traveller");
+        assert_eq!(resolved["geo"]["country_code"], "GB");
+        assert_eq!(resolved["geo"]["city"], "London");
+        assert_eq!(resolved["geo"]["asn"], 20712);
+
+        assert!(details_for("insider").get("geo").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_nested_metadata_round_trips_as_canonical_json() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+        let ctx = client("10.0.0.5");
+        let details = json!({
+            "permissions": { "granted": ["audit_read", "audit_export"], "revoked": [] },
+            "target": { "username": "analyst", "org": { "county": "Kisumu", "level": 2 } },
+            "ratio": 0.75,
+            "notify": true,
+            "note": null,
+        });
+        service
+            .log_security_event(
+                &ctx,
+                None,
+                AuditEventType::PermissionsChanged,
+                "Permissions changed",
+                true,
+                Severity::Warning,
+                Some(details.clone()),
+            )
+            .await
+            .unwrap();
+
+        let mut expected = details;
+        expected["request_id"] = json!(ctx.request_id);
+        let events = service.get_recent_events(10, false, None).await.unwrap();
+        assert_eq!(events[0].details.as_ref(), Some(&expected));
+
+        // Keys in order, with no whitespace, whatever order the caller built them in
+        let stored: String = sqlx::query_scalar("SELECT metadata FROM security_events").fetch_one(&pool).await.unwrap();
+        assert_eq!(stored, expected.to_string());
+        assert!(stored.starts_with(r#"{"note":null,"notify":true,"permissions":{"granted":"#), "{}", stored);
+    }
+
+    #[actix_web::test]
+    async fn test_unserializable_metadata_is_stored_as_a_marker() {
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+
+        // JSON object keys must be strings
+        let unserializable: HashMap<(u8, u8), u8> = HashMap::from([((1, 2), 3)]);
+        let marker = json!({ "_error": METADATA_SERIALIZATION_FAILED });
+        assert_eq!(metadata_value(&unserializable), marker);
+
+        service
+            .log_security_event(
+                &client("10.0.0.5"),
+                None,
+                AuditEventType::PermissionsChanged,
+                "Permissions changed",
+                true,
+                Severity::Info,
+                Some(metadata_value(&unserializable)),
+            )
+            .await
+            .unwrap();
+        let events = service.get_recent_events(10, false, None).await.unwrap();
+        assert_eq!(events[0].details.as_ref().unwrap()["_error"], METADATA_SERIALIZATION_FAILED);
+
+        // Events from before, stored with empty metadata, get the same marker
+        sqlx::query(
+            "INSERT INTO security_events (id, event_type, description, success, severity, timestamp, metadata)
+             VALUES (?, 'LOGIN_ATTEMPT', 'emptied', TRUE, 'info', ?, '')",
+        )
+        .bind(Uuid::new_v4())
+        .bind(Utc::now())
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(include_str!("../../migrations/039_audit_metadata_markers.sql")).execute(&pool).await.unwrap();
+        let events = service.get_recent_events(10, false, None).await.unwrap();
+        let emptied = events.iter().find(|e| e.description == "emptied").unwrap();
+        assert_eq!(emptied.details.as_ref(), Some(&marker));
+    }
+
+    #[actix_web::test]
+    async fn test_privacy_mode_keeps_full_addresses_only_until_the_purge() {
+        use crate::services::geoip_service::TEST_DATABASE;
+
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let geoip = GeoIpService::open(Some(TEST_DATABASE), Some(TEST_DATABASE), Vec::new()).unwrap().with_privacy_mode(true);
+        let service = AuditService::new(pool.clone(), Arc::new(geoip), clock.clone()).with_raw_ip_retention(72);
+        let stored = |username: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_as::<_, (String, Option<String>, String)>(
+                    "SELECT ip_address, raw_ip, metadata FROM security_events WHERE description LIKE '%' || ?",
+                )
+                .bind(username)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+
+        service.log_login_attempt(&client("197.232.61.4"), None, "earlier", false, None).await.unwrap();
+        clock.advance(chrono::Duration::hours(71));
+        service.log_login_attempt(&client("2001:db8:85a3:8d3::7"), None, "later", false, None).await.unwrap();
+
+        let (ip_address, raw_ip, metadata) = stored("earlier").await;
+        assert_eq!(ip_address, "197.232.61.0");
+        assert_eq!(raw_ip.as_deref(), Some("197.232.61.4"));
+        let geo = &serde_json::from_str::<serde_json::Value>(&metadata).unwrap()["geo"];
+        assert_eq!(geo["country_code"], "KE");
+        assert_eq!(geo["region"], "Nairobi County");
+        assert!(geo["city"].is_null() && geo["asn"].is_null());
+        assert_eq!(stored("later").await.0, "2001:db8:85a3::");
+
+        // Only what is past the retention loses its full address
+        assert_eq!(service.purge_raw_ips().await.unwrap(), 0);
+        clock.advance(chrono::Duration::hours(2));
+        assert_eq!(service.purge_raw_ips().await.unwrap(), 1);
+        assert_eq!(stored("earlier").await.0, "197.232.61.0");
+        assert_eq!(stored("earlier").await.1, None);
+        assert_eq!(stored("later").await.1.as_deref(), Some("2001:db8:85a3:8d3::7"));
+
+        // Exports carry the truncated form, even of a row from before privacy mode
+        sqlx::query("UPDATE security_events SET ip_address = '197.232.61.9' WHERE description LIKE '%later'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut events = service.list_events(10, &AuditFilter::default()).await.unwrap();
+        service.truncate_exported_ips(&mut events);
+        let exported: Vec<_> = events.iter().map(|e| e.ip_address.as_deref()).collect();
+        assert_eq!(exported, vec![Some("197.232.61.0"), Some("197.232.61.0")]);
+    }
+
+    async fn seed_event(pool: &SqlitePool, event_type: &str, success: bool, failure_reason: Option<&str>, at: chrono::DateTime<Utc>) {
+        sqlx::query(
+            "INSERT INTO security_events (id, event_type, description, success, severity, timestamp, metadata)
+             VALUES (?, ?, 'seeded', ?, 'info', ?, ?)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(event_type)
+        .bind(success)
+        .bind(at)
+        .bind(failure_reason.map(|reason| json!({ "failure_reason": reason }).to_string()))
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn seed_failed_login(pool: &SqlitePool, username: &str, ip_address: &str, at: chrono::DateTime<Utc>) {
+        sqlx::query(
+            "INSERT INTO login_attempts (id, username, ip_address, success, timestamp) VALUES (?, ?, ?, FALSE, ?)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(username)
+        .bind(ip_address)
+        .bind(at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_event_stats_counts_within_window() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+        let now = Utc::now();
+
+        seed_event(&pool, "LOGIN_ATTEMPT", false, Some("Invalid password"), now - chrono::Duration::minutes(10)).await;
+        seed_event(&pool, "LOGIN_ATTEMPT", false, Some("Invalid password"), now - chrono::Duration::minutes(20)).await;
+        seed_event(&pool, "LOGIN_ATTEMPT", false, Some("Unknown user"), now - chrono::Duration::hours(3)).await;
+        seed_event(&pool, "LOGOUT", true, None, now - chrono::Duration::hours(5)).await;
+        // Outside a 24h window
+        seed_event(&pool, "LOGOUT", true, None, now - chrono::Duration::days(3)).await;
+
+        seed_failed_login(&pool, "alice", "10.0.0.1", now - chrono::Duration::minutes(10)).await;
+        seed_failed_login(&pool, "alice", "10.0.0.2", now - chrono::Duration::minutes(20)).await;
+        seed_failed_login(&pool, "bob", "10.0.0.2", now - chrono::Duration::hours(3)).await;
+        seed_failed_login(&pool, "carol", "10.0.0.3", now - chrono::Duration::days(3)).await;
+
+        let stats = service.event_stats(StatsWindow::Day, None).await.unwrap();
+        let count_of = |counts: &[KeyCount], key: &str| counts.iter().find(|c| c.key == key).map(|c| c.count);
+
+        assert_eq!(count_of(&stats.by_event_type, "LOGIN_ATTEMPT"), Some(3));
+        assert_eq!(count_of(&stats.by_event_type, "LOGOUT"), Some(1));
+        assert_eq!(count_of(&stats.by_failure_code, "Invalid password"), Some(2));
+        assert_eq!(count_of(&stats.by_failure_code, "Unknown user"), Some(1));
+        assert_eq!(stats.failed_login_distinct_ips, 2);
+        assert_eq!(stats.failed_login_distinct_usernames, 2);
+        assert!(stats.series.is_none());
+
+        let hour = service.event_stats(StatsWindow::Hour, None).await.unwrap();
+        assert_eq!(count_of(&hour.by_event_type, "LOGIN_ATTEMPT"), Some(2));
+        assert_eq!(count_of(&hour.by_event_type, "LOGOUT"), None);
+
+        let week = service.event_stats(StatsWindow::Week, None).await.unwrap();
+        assert_eq!(count_of(&week.by_event_type, "LOGOUT"), Some(2));
+        assert_eq!(week.failed_login_distinct_usernames, 3);
+    }
+
+    #[actix_web::test]
+    async fn test_event_stats_series_buckets() {
+        let pool = test_pool().await;
+        let service = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock));
+
+        // Either side of yesterday's midnight, which is both an hour and a day boundary
+        let boundary = (Utc::now() - chrono::Duration::days(1)).date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let before = boundary - chrono::Duration::minutes(1);
+        let after = boundary + chrono::Duration::seconds(1);
+        let later = boundary + chrono::Duration::minutes(61);
+        for at in [before, before, after, later] {
+            seed_event(&pool, "LOGIN_ATTEMPT", true, None, at).await;
+        }
+
+        let label = |at: chrono::DateTime<Utc>, format: &str| at.format(format).to_string();
+
+        let hourly = service.event_stats(StatsWindow::Week, Some(StatsBucket::Hour)).await.unwrap();
+        let series = hourly.series.unwrap();
+        let hour_count = |at| series.iter().find(|c| c.key == label(at, "%Y-%m-%dT%H:00:00Z")).map(|c| c.count);
+        assert_eq!(hour_count(before), Some(2));
+        assert_eq!(hour_count(after), Some(1));
+        assert_eq!(hour_count(later), Some(1));
+        assert_eq!(series.len(), 3);
+        assert!(series.windows(2).all(|pair| pair[0].key < pair[1].key));
+
+        let daily = service.event_stats(StatsWindow::Week, Some(StatsBucket::Day)).await.unwrap();
+        let series = daily.series.unwrap();
+        let day_count = |at| series.iter().find(|c| c.key == label(at, "%Y-%m-%dT00:00:00Z")).map(|c| c.count);
+        assert_eq!(day_count(before), Some(2));
+        assert_eq!(day_count(boundary), Some(2));
+    }
+}
@@ -1,24 +1,436 @@
-use chrono::{Duration, Utc};
-use sqlx::SqlitePool;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use sqlx::query::QueryAs;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{Sqlite, SqlitePool};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, OnceLock};
 use uuid::Uuid;
 
-use crate::models::auth::{AuthError, AuthResult};
+use crate::models::admin::{AdminUserDetail, EphemeralStoreUsage, HealthDetails, JwtMigrationStatus, PoolUsage};
+use crate::models::audit_event::AuditEventType;
+use crate::models::auth::{AuthError, AuthResult, LoginAttempt, MultipleLoginPolicy, Severity};
+use crate::models::context::RequestContext;
+use crate::models::integration::{HrChange, HrEvent, HrEventRejection, HrEventResult, HrEventStatus};
+use crate::models::pagination::{Cursored, PageRequest, Paginated, SortDirection, SortField, SortKind, SortValue, Sortable};
+use crate::models::permission::{PermissionOverride, PermissionSet, UserPermissions};
+use crate::models::policy::{EffectivePolicy, OrgPolicyView};
 use crate::models::user::{
-    ChangePasswordRequest, LoginRequest, LoginResponse, User, UserResponse,
-    TwoFASetupRequest, TwoFASetupResponse, TwoFAVerifyRequest, TwoFADisableRequest,
+    AcceptTermsRequest, ChangePasswordRequest, ConfirmPasswordResetRequest, DeviceApprovalRequest, DeviceAuthorizationStart, LoginPasswordChangeRequest, LoginRequest, LoginResponse, SecurityCheckup, SessionRenewal, TermsStatus, User, UserResponse, UserRole,
+    StepUpRequest, TwoFAQrRequest, TwoFAQrResponse, TwoFASetupRequest, TwoFASetupResponse, TwoFAVerifyRequest, TwoFADisableRequest, TwoFactorCode,
 };
+use crate::services::account_notes_service::{AccountNote, AccountNotesService, MAX_NOTE_CHARS};
+use crate::services::admin_action_service::{AdminActionService, LinkedAction, PendingAction, Redemption};
 use crate::services::audit_service::AuditService;
-use crate::services::password_service::PasswordService;
+use crate::services::bot_heuristics::{BotHeuristics, BotSignal, DEFAULT_MIN_FILL_MS};
+use crate::services::client_app_service::{ClientApp, ClientRegistry};
+use crate::services::data_export_service::{DataExport, DataExportService, Download};
+use crate::services::download_tokens::{DownloadGrant, DownloadResource, DownloadTokens};
+use crate::services::device_authorization_service::{
+    DeviceAuthorizationService, DeviceAuthorizationStatus, DeviceLookup, DEVICE_CODE_TTL_MINUTES, DEVICE_POLL_INTERVAL_SECONDS,
+    KIOSK_SESSION_MINUTES,
+};
+use crate::services::break_glass_service::{
+    BreakGlassCredential, BreakGlassService, BREAK_GLASS_MAX_SESSION_MINUTES, BREAK_GLASS_USERNAME,
+};
+use crate::services::geoip_service::{GeoFix, GeoIpService};
+use crate::services::integration_event_service::{Claim, IntegrationEventService};
+use crate::services::lockdown_service::{Lockdown, LockdownRequest, LockdownService};
+use crate::services::lockout_exemptions::{LockoutExemptions, MONITORING_TAG};
+use crate::services::login_challenge_service::{LoginChallengeService, LOGIN_CHALLENGE_TTL_MINUTES};
+use crate::services::login_queue::{LoginQueue, LoginQueueDepth, DEFAULT_LOGIN_QUEUE_WAIT};
+use crate::services::failed_login_digest::{DigestTrigger, FailedLoginDigest, FailedLoginDigests};
+use crate::services::feature_flags::{Feature, FeatureDefaults, FeatureFlags};
+use crate::services::notification_service::NotificationService;
+use crate::services::password_change_challenge::{PasswordChangeChallenge, PasswordChangeChallenges};
+use crate::services::password_reset_service::{PasswordReset, PasswordResetService, ResetChannel, ResetLookup};
+use crate::services::password_rules::PasswordPolicy;
+use crate::services::legacy_import;
+use crate::services::password_service::{is_bcrypt_hash, PasswordService, HASH_SCHEME_ARGON2};
+use crate::services::permission_service::PermissionService;
+use crate::services::phase_timings::{self, Operation, OperationTimings, Phase, PhaseBudgets, TimingHistogram};
+use crate::services::policy_resolver::PolicyResolver;
+use crate::services::session_events::{
+    EventQueueDepth, SessionEvent, Subscription, PASSWORD_EXPIRY_WARNING_DAYS, SESSION_EXPIRY_WARNING_MINUTES,
+};
+use crate::services::session_service::{RevokedToken, SessionRecord, SessionService};
+use crate::services::shared_state::SharedStateBackend;
+use crate::services::terms_service::TermsService;
+use crate::services::throttle_state::{Decision, ThrottleScope, ThrottleState};
 use crate::services::token_service::TokenService;
-use crate::services::two_fa_service::TwoFAService;
+use crate::services::tracer;
+use crate::services::two_fa_service::{TwoFAService, DEFAULT_ISSUER};
+use crate::services::user_alerts::{UserAlert, UserAlertService};
+use crate::services::verify_monitor::{VerifyCounts, VerifyMonitor, VerifyOutcome};
+use crate::services::webhook_service::Notifier;
+use crate::utils::clock::Clock;
+use crate::utils::constant_time::ct_eq_str;
+use crate::utils::database::ReadPool;
+use crate::utils::db_retry::{BusyRetry, BusyRetryCounts};
+use crate::utils::sanitize::{self, TextLimits};
+use crate::utils::self_test::{run_crypto_self_test, SelfTestError};
 
 /// Main authentication service
 pub struct AuthService {
     db_pool: SqlitePool,
-    password_service: PasswordService,
+    /// Account listings and dashboard counts; may lag behind `db_pool`
+    read_pool: ReadPool,
+    password_service: Arc<PasswordService>,
     token_service: TokenService,
     audit_service: AuditService,
+    notification_service: NotificationService,
+    /// Failed sign-ins waiting to go to their owners as one summary
+    failed_login_digests: FailedLoginDigests,
     two_fa_service: TwoFAService,
+    geoip: Arc<GeoIpService>,
+    verify_monitor: VerifyMonitor,
+    login_queue: LoginQueue,
+    /// Failure counters shared with the rate limiting middleware
+    throttle: Arc<ThrottleState>,
+    break_glass: BreakGlassService,
+    sessions: SessionService,
+    permissions: PermissionService,
+    /// Per-organization overrides of the session, lockout, 2FA and password age settings
+    policies: PolicyResolver,
+    terms: TermsService,
+    /// Actions waiting for an administrator to open their one-time link
+    admin_actions: AdminActionService,
+    /// Personal data exports waiting to be downloaded
+    data_exports: DataExportService,
+    account_notes: AccountNotesService,
+    /// Each user's own alert feed and the alerts they dismissed
+    user_alerts: UserAlertService,
+    /// One-time password reset tokens, emailed or handed to an administrator
+    password_resets: PasswordResetService,
+    /// Logins waiting for their second factor
+    login_challenges: LoginChallengeService,
+    /// Events the HR integration already delivered
+    integration_events: IntegrationEventService,
+    /// Kiosk sign-ins waiting for approval from another device
+    device_authorizations: DeviceAuthorizationService,
+    /// v2 logins waiting for a new password, in the shared state backend
+    password_changes: PasswordChangeChallenges,
+    /// Single-use tokens for browser downloads, in the shared state backend
+    download_tokens: DownloadTokens,
+    /// Prefix of links sent out of band, e.g. `https://api.kenya.fsfvi.ai`
+    public_base_url: String,
+    /// Frontend page password reset links open, e.g. `https://kenya.fsfvi.ai/reset-password`
+    password_reset_url: String,
+    /// Server-side activation flag for break-glass sign-in
+    break_glass_enabled: bool,
+    /// Honeypot and form-fill time checks on login submissions
+    bot_heuristics: BotHeuristics,
+    /// Runtime switches for the impossible travel and bot checks
+    features: Arc<FeatureFlags>,
+    /// Emergency lockdown state, shared with the admin endpoints
+    lockdown: Arc<LockdownService>,
+    /// Client apps and the audiences their tokens carry, shared with the CORS and rate limiting layers
+    clients: Arc<ClientRegistry>,
+    /// Monitoring accounts and source addresses whose failed sign-ins don't lock the account
+    lockout_exemptions: Arc<LockoutExemptions>,
+    /// Hash that honeypot hits are checked against, so they take as long as a
+    /// real wrong password. Computed on first use.
+    decoy_hash: OnceLock<String>,
+    /// Retries of writes SQLite reports busy, shared with the audit service
+    busy_retry: Arc<BusyRetry>,
+    /// Phase durations of 2FA setup and password changes
+    operation_timings: OperationTimings,
+    /// Time source for lockouts, sessions and tokens
+    clock: Arc<dyn Clock>,
+    started_at: DateTime<Utc>,
+    /// Last capacity gauges, reused until they are `HEALTH_DETAILS_TTL_SECONDS` old
+    health_details: Mutex<Option<HealthDetails>>,
+}
+
+/// Minimum time a lockout status lookup takes, so known and unknown usernames
+/// can't be told apart by response time
+const LOCKOUT_STATUS_MIN_DURATION: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Minimum time a password reset request takes, so accounts with and without
+/// a verified email, and unknown usernames, can't be told apart by response time
+const PASSWORD_RESET_REQUEST_MIN_DURATION: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Days a provisioned account may sit on its temporary password before it
+/// counts as overdue for onboarding
+pub const ONBOARDING_GRACE_DAYS: i64 = 7;
+
+/// Times an account may have its 2FA enrollment QR code shown again per hour
+pub const TWO_FA_QR_REDISPLAYS_PER_HOUR: i64 = 3;
+
+/// Failed sign-ins an address may have in its throttle window during a
+/// lockdown, a third of the default budget
+pub const LOCKDOWN_IP_FAILURE_LIMIT: u32 = 10;
+
+/// Backup codes left at which the security checkup asks the user to generate more
+pub const LOW_BACKUP_CODES: usize = 3;
+
+/// Tries at using a backup code while parallel sign-ins keep using others
+/// first; each lost try means another code was used, so an account's ten
+/// codes can't outlast it
+const BACKUP_CODE_CONSUME_ATTEMPTS: usize = 10;
+
+/// Username of the administrator created on first startup
+const DEFAULT_USERNAME: &str = "kenya_government";
+
+/// `bootstrap_lock` row held while the default user is created
+const DEFAULT_USER_LOCK: &str = "default_user";
+
+/// How long an instance may hold a bootstrap lock before another may take it over
+const BOOTSTRAP_LOCK_SECONDS: i64 = 300;
+
+/// Alerts about a user's own sign-ins counted by the security checkup
+const SIGN_IN_ALERT_EVENTS: &[AuditEventType] = &[AuditEventType::ImpossibleTravel, AuditEventType::LoginFromUnexpectedCountry];
+
+/// How long capacity gauges are served from cache before they are counted again
+pub const HEALTH_DETAILS_TTL_SECONDS: i64 = 15;
+
+/// Fields the admin user listing can be sorted by, oldest account first by default
+pub const USER_SORT: Sortable = Sortable {
+    fields: &[
+        SortField { name: "created_at", column: "created_at", kind: SortKind::Timestamp, default_direction: SortDirection::Asc },
+        SortField { name: "username", column: "username", kind: SortKind::Text, default_direction: SortDirection::Asc },
+        // Accounts that never signed in sort as if they last did at the epoch;
+        // the expression matches `idx_users_last_login` so the index serves it
+        SortField {
+            name: "last_login",
+            column: "COALESCE(last_login, '1970-01-01T00:00:00+00:00')",
+            kind: SortKind::Timestamp,
+            default_direction: SortDirection::Desc,
+        },
+    ],
+    id_column: "id",
+};
+
+/// Which accounts the admin user listing shows. Every filter given narrows
+/// the list further.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserFilter {
+    /// Accounts that have replaced their temporary password (`true`) or are still waiting to (`false`)
+    pub onboarded: Option<bool>,
+    /// Only accounts carrying this tag, already normalized
+    pub tag: Option<String>,
+    /// Locked right now, by failed logins or an administrator
+    pub locked: Option<bool>,
+    pub two_fa_enabled: Option<bool>,
+    pub temporary_password: Option<bool>,
+    pub organization: Option<String>,
+    pub role: Option<UserRole>,
+    pub active: Option<bool>,
+    /// Case-insensitive substring of the username or email
+    pub search: Option<String>,
+}
+
+/// A value one of a `UserFilter`'s conditions binds
+enum FilterValue {
+    Bool(bool),
+    Text(String),
+    Timestamp(DateTime<Utc>),
+}
+
+impl UserFilter {
+    /// A fixed condition for each filter given, with the values it binds.
+    /// Filters left out add nothing, so the conditions that remain can use
+    /// the indexes on their columns.
+    fn conditions(&self, now: DateTime<Utc>) -> Vec<(&'static str, Vec<FilterValue>)> {
+        let mut conditions = Vec::new();
+        match self.onboarded {
+            None => {}
+            Some(true) => conditions.push(("onboarded_at IS NOT NULL", vec![])),
+            Some(false) => conditions.push(("onboarded_at IS NULL AND is_temporary_password = TRUE", vec![])),
+        }
+        if let Some(tag) = &self.tag {
+            conditions.push(("id IN (SELECT user_id FROM user_tags WHERE tag = ?)", vec![FilterValue::Text(tag.clone())]));
+        }
+        match self.locked {
+            None => {}
+            Some(true) => conditions.push((
+                "is_locked = TRUE AND (lockout_expiry IS NULL OR lockout_expiry > ?)",
+                vec![FilterValue::Timestamp(now)],
+            )),
+            Some(false) => conditions.push((
+                "NOT (is_locked = TRUE AND (lockout_expiry IS NULL OR lockout_expiry > ?))",
+                vec![FilterValue::Timestamp(now)],
+            )),
+        }
+        if let Some(enabled) = self.two_fa_enabled {
+            conditions.push(("two_fa_enabled = ?", vec![FilterValue::Bool(enabled)]));
+        }
+        if let Some(temporary) = self.temporary_password {
+            conditions.push(("is_temporary_password = ?", vec![FilterValue::Bool(temporary)]));
+        }
+        if let Some(organization) = &self.organization {
+            conditions.push(("organization = ?", vec![FilterValue::Text(organization.clone())]));
+        }
+        if let Some(role) = &self.role {
+            conditions.push(("role = ?", vec![FilterValue::Text(role.as_str().to_string())]));
+        }
+        if let Some(active) = self.active {
+            conditions.push(("is_active = ?", vec![FilterValue::Bool(active)]));
+        }
+        if let Some(search) = &self.search {
+            let pattern = format!("%{}%", escape_like(search));
+            conditions.push((
+                "(username LIKE ? ESCAPE '\\' OR email LIKE ? ESCAPE '\\')",
+                vec![FilterValue::Text(pattern.clone()), FilterValue::Text(pattern)],
+            ));
+        }
+        conditions
+    }
+}
+
+/// `WHERE` clause of `conditions`, and their values in the order they bind
+fn where_clause(conditions: Vec<(&'static str, Vec<FilterValue>)>) -> (String, Vec<FilterValue>) {
+    if conditions.is_empty() {
+        return ("TRUE".to_string(), Vec::new());
+    }
+    let (clauses, values): (Vec<_>, Vec<_>) = conditions.into_iter().unzip();
+    (clauses.join(" AND "), values.into_iter().flatten().collect())
+}
+
+fn bind_filter<'q, O>(
+    query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>,
+    values: &'q [FilterValue],
+) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+    values.iter().fold(query, |query, value| match value {
+        FilterValue::Bool(value) => query.bind(*value),
+        FilterValue::Text(value) => query.bind(value.as_str()),
+        FilterValue::Timestamp(value) => query.bind(*value),
+    })
+}
+
+/// `text` with the `LIKE` wildcards and the escape character escaped
+fn escape_like(text: &str) -> String {
+    text.chars().fold(String::with_capacity(text.len()), |mut escaped, c| {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// A listed account with what the listing shows beside it, all read in the
+/// same query as the account itself
+#[derive(sqlx::FromRow)]
+struct DirectoryRow {
+    #[sqlx(flatten)]
+    user: User,
+    active_sessions: i64,
+    /// `tag,tag,...` in no particular order
+    tags: Option<String>,
+    /// `permission=granted,...`, `granted` being 1 or 0
+    permission_overrides: Option<String>,
+}
+
+/// The annotations of a `DirectoryRow`: live sessions, tags and permission overrides
+const DIRECTORY_ANNOTATIONS: &str = "
+    (SELECT COUNT(*) FROM sessions WHERE sessions.user_id = users.id AND revoked_at IS NULL AND expires_at > ?) AS active_sessions,
+    (SELECT GROUP_CONCAT(tag, ',') FROM user_tags WHERE user_tags.user_id = users.id) AS tags,
+    (SELECT GROUP_CONCAT(permission || '=' || granted, ',') FROM user_permissions WHERE user_permissions.user_id = users.id)
+        AS permission_overrides";
+
+impl DirectoryRow {
+    fn into_response(self) -> UserResponse {
+        let overrides: Vec<PermissionOverride> = self
+            .permission_overrides
+            .iter()
+            .flat_map(|overrides| overrides.split(','))
+            .filter_map(|entry| {
+                let (name, granted) = entry.split_once('=')?;
+                match name.parse() {
+                    Ok(permission) => Some(PermissionOverride { permission, granted: granted == "1" }),
+                    Err(e) => {
+                        log::warn!("Ignoring override for user {}: {}", self.user.id, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let mut tags: Vec<String> = self.tags.iter().flat_map(|tags| tags.split(',')).map(str::to_string).collect();
+        tags.sort();
+
+        let permissions = PermissionSet::for_role(&self.user.role).with_overrides(&overrides);
+        UserResponse::from(self.user)
+            .with_permissions(permissions)
+            .with_tags(tags)
+            .with_active_sessions(self.active_sessions)
+    }
+}
+
+impl Cursored for DirectoryRow {
+    fn sort_value(&self, name: &str) -> SortValue {
+        match name {
+            "username" => SortValue::Text(self.user.username.clone()),
+            "last_login" => SortValue::Timestamp(self.user.last_login.unwrap_or(DateTime::UNIX_EPOCH)),
+            _ => SortValue::Timestamp(self.user.created_at),
+        }
+    }
+
+    fn cursor_id(&self) -> Uuid {
+        self.user.id
+    }
+}
+
+/// The security change that is revoking an account's outstanding credentials.
+/// It decides whether the session making the change survives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevocationScope {
+    /// The owner changed their password; their current session stays
+    PasswordChanged,
+    /// The owner turned 2FA off; their current session stays
+    TwoFaDisabled,
+    /// An administrator reset the account's 2FA; every session ends
+    TwoFaReset,
+    /// An administrator locked the account; every session ends
+    AccountLocked,
+    /// A new password was set with a reset token; every session ends
+    PasswordReset,
+}
+
+impl RevocationScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RevocationScope::PasswordChanged => "password_changed",
+            RevocationScope::TwoFaDisabled => "two_fa_disabled",
+            RevocationScope::TwoFaReset => "two_fa_reset",
+            RevocationScope::AccountLocked => "account_locked",
+            RevocationScope::PasswordReset => "password_reset",
+        }
+    }
+
+    fn keeps_current_session(self) -> bool {
+        matches!(self, RevocationScope::PasswordChanged | RevocationScope::TwoFaDisabled)
+    }
+}
+
+/// What a login does when the account's password is temporary or has expired
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordRotation {
+    /// Sign in; the session can only change the password until it is (v1)
+    AfterSignIn,
+    /// Stop before signing in and ask for a new password with a challenge
+    /// token; the change completes the login (v2)
+    Inline,
+}
+
+/// Which sessions `rotate_session` replaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rotation<'a> {
+    /// Every session of the account; the replacement becomes its current one
+    Account,
+    /// Only this session, leaving the account's others signed in
+    Session(&'a str),
+}
+
+/// What `revoke_user_artifacts` took away
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RevokedArtifacts {
+    /// IDs of the sessions ended
+    pub sessions: Vec<String>,
+    /// A 2FA secret generated for setup but never confirmed was discarded
+    pub pending_two_fa_setup: bool,
+    /// Unused one-time admin action links for the account that were expired
+    pub action_links: u64,
 }
 
 impl AuthService {
@@ -26,72 +438,502 @@ impl AuthService {
         db_pool: SqlitePool,
         password_service: PasswordService,
         token_service: TokenService,
+        geoip: Arc<GeoIpService>,
+        clock: Arc<dyn Clock>,
     ) -> Self {
-        let audit_service = AuditService::new(db_pool.clone());
-        let two_fa_service = TwoFAService::new("Kenya FSFVI Platform".to_string());
+        let busy_retry = Arc::new(BusyRetry::default());
+        let audit_service =
+            AuditService::new(db_pool.clone(), geoip.clone(), clock.clone()).with_busy_retry(busy_retry.clone());
+        let notification_service = NotificationService::new(db_pool.clone());
+        let two_fa_service = TwoFAService::new(DEFAULT_ISSUER.to_string(), clock.clone())
+            .with_fingerprint_key(token_service.config().totp_fingerprint_key.as_bytes());
+        let break_glass = BreakGlassService::new(db_pool.clone());
+        let sessions = SessionService::new(db_pool.clone(), clock.clone());
+        let permissions = PermissionService::new(db_pool.clone());
+        let terms = TermsService::new(db_pool.clone(), clock.clone());
+        let admin_actions = AdminActionService::new(db_pool.clone(), clock.clone());
+        let data_exports = DataExportService::new(db_pool.clone(), clock.clone()).with_ip_privacy(geoip.privacy_mode());
+        let account_notes = AccountNotesService::new(db_pool.clone(), clock.clone());
+        let user_alerts = UserAlertService::new(db_pool.clone(), clock.clone());
+        let password_resets = PasswordResetService::new(db_pool.clone(), clock.clone());
+        let login_challenges = LoginChallengeService::new(db_pool.clone(), clock.clone());
+        let integration_events = IntegrationEventService::new(db_pool.clone(), clock.clone());
+        let device_authorizations = DeviceAuthorizationService::new(db_pool.clone(), clock.clone());
+        let features = Arc::new(FeatureFlags::new(db_pool.clone(), clock.clone(), FeatureDefaults::default()));
+        let lockdown = Arc::new(LockdownService::new(db_pool.clone(), clock.clone()));
+        let clients = Arc::new(ClientRegistry::new(db_pool.clone(), clock.clone()));
+        let policies = PolicyResolver::new(db_pool.clone(), EffectivePolicy::global(token_service.config()));
+        let bot_heuristics =
+            BotHeuristics::new(token_service.config().jwt_secret.as_bytes(), true, DEFAULT_MIN_FILL_MS, clock.clone());
+        let password_changes = PasswordChangeChallenges::new(token_service.config().jwt_secret.as_bytes(), clock.clone());
+        let download_tokens = DownloadTokens::new(token_service.config().jwt_secret.as_bytes(), clock.clone());
         Self {
+            read_pool: ReadPool::primary_only(db_pool.clone()),
             db_pool,
-            password_service,
+            password_service: Arc::new(password_service),
             token_service,
             audit_service,
+            notification_service,
+            failed_login_digests: FailedLoginDigests::default(),
             two_fa_service,
+            geoip,
+            verify_monitor: VerifyMonitor::default(),
+            login_queue: LoginQueue::default(),
+            throttle: Arc::new(ThrottleState::default()),
+            break_glass,
+            sessions,
+            permissions,
+            policies,
+            terms,
+            admin_actions,
+            data_exports,
+            account_notes,
+            user_alerts,
+            password_resets,
+            login_challenges,
+            integration_events,
+            device_authorizations,
+            password_changes,
+            download_tokens,
+            public_base_url: "http://localhost:8080".to_string(),
+            password_reset_url: "http://localhost:3000/reset-password".to_string(),
+            break_glass_enabled: false,
+            bot_heuristics,
+            features,
+            lockdown,
+            clients,
+            lockout_exemptions: Arc::default(),
+            decoy_hash: OnceLock::new(),
+            started_at: clock.now(),
+            busy_retry,
+            operation_timings: OperationTimings::default(),
+            clock,
+            health_details: Mutex::new(None),
+        }
+    }
+
+    /// Log at WARN when a phase of 2FA setup or a password change runs past its budget
+    pub fn with_phase_budgets(mut self, budgets: PhaseBudgets) -> Self {
+        self.operation_timings = OperationTimings::new(budgets);
+        self
+    }
+
+    /// Allow the break-glass account to sign in
+    pub fn with_break_glass_enabled(mut self, enabled: bool) -> Self {
+        self.break_glass_enabled = enabled;
+        self
+    }
+
+    /// Configure the login honeypot and the minimum form-fill time (0 turns it off)
+    pub fn with_bot_heuristics(mut self, honeypot_enabled: bool, min_fill_ms: i64) -> Self {
+        self.bot_heuristics = BotHeuristics::new(
+            self.token_service.config().jwt_secret.as_bytes(),
+            honeypot_enabled,
+            min_fill_ms,
+            self.clock.clone(),
+        );
+        self
+    }
+
+    /// Share runtime feature flags with the admin endpoints that change them
+    pub fn with_feature_flags(mut self, features: Arc<FeatureFlags>) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Share the lockdown state with the admin endpoints and the expiry task
+    pub fn with_lockdown(mut self, lockdown: Arc<LockdownService>) -> Self {
+        self.lockdown = lockdown;
+        self
+    }
+
+    /// Share the client app registry with the admin endpoints and the middleware
+    pub fn with_client_registry(mut self, clients: Arc<ClientRegistry>) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    /// Send buffered failed sign-ins once an account has had none for `quiet_minutes`
+    pub fn with_failed_login_digest(mut self, quiet_minutes: i64) -> Self {
+        self.failed_login_digests = FailedLoginDigests::new(quiet_minutes);
+        self
+    }
+
+    /// Address this API is reached at, for links sent by notification
+    pub fn with_public_base_url(mut self, public_base_url: &str) -> Self {
+        self.public_base_url = public_base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Frontend page that password reset links open, with `?token=...` added
+    pub fn with_password_reset_url(mut self, password_reset_url: &str) -> Self {
+        self.password_reset_url = password_reset_url.to_string();
+        self
+    }
+
+    /// Run account listings, dashboard counts and audit exports through
+    /// `read_pool`. Sign-in and every write stay on the primary.
+    pub fn with_read_pool(mut self, read_pool: ReadPool) -> Self {
+        self.audit_service = self.audit_service.with_read_pool(read_pool.clone());
+        self.read_pool = read_pool;
+        self
+    }
+
+    /// Keep full client addresses stored under privacy mode for `hours`
+    pub fn with_raw_ip_retention(mut self, hours: i64) -> Self {
+        self.audit_service = self.audit_service.with_raw_ip_retention(hours);
+        self
+    }
+
+    /// Cap the length of client-supplied text stored in audit rows, login
+    /// attempts and notifications
+    pub fn with_text_limits(mut self, text_limits: TextLimits) -> Self {
+        self.audit_service = self.audit_service.with_text_limits(text_limits);
+        self.notification_service = self.notification_service.with_text_limits(text_limits);
+        self
+    }
+
+    /// Don't lock accounts, or count failures toward the throttle, for these
+    /// monitoring accounts and addresses. Give the throttle state the same ones.
+    pub fn with_lockout_exemptions(mut self, exemptions: Arc<LockoutExemptions>) -> Self {
+        self.lockout_exemptions = exemptions;
+        self
+    }
+
+    /// Forward warning and critical security events to the SIEM webhook
+    pub fn with_webhooks(mut self, webhooks: Arc<dyn Notifier>) -> Self {
+        self.audit_service = self.audit_service.with_webhooks(webhooks);
+        self
+    }
+
+    /// Name authenticator apps show next to the 2FA entry
+    pub fn with_two_fa_issuer(mut self, issuer: &str) -> Self {
+        self.two_fa_service = TwoFAService::new(issuer.to_string(), self.clock.clone())
+            .with_fingerprint_key(self.token_service.config().totp_fingerprint_key.as_bytes());
+        self
+    }
+
+    /// Cap concurrent password verifications during login
+    pub fn with_login_concurrency(mut self, concurrency: usize) -> Self {
+        self.login_queue = LoginQueue::new(concurrency, DEFAULT_LOGIN_QUEUE_WAIT);
+        self
+    }
+
+    /// Share failure counters with the rate limiting middleware
+    pub fn with_throttle_state(mut self, throttle: Arc<ThrottleState>) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Track token verification failures per IP and open password change
+    /// challenges in `backend`, shared with other instances
+    pub fn with_shared_state(mut self, backend: Arc<dyn SharedStateBackend>) -> Self {
+        self.verify_monitor = VerifyMonitor::default().with_backend(backend.clone());
+        self.password_changes = self.password_changes.with_backend(backend.clone());
+        self.download_tokens = self.download_tokens.with_backend(backend);
+        self
+    }
+
+    /// Current occupancy of the login hashing queue
+    pub fn login_queue_depth(&self) -> LoginQueueDepth {
+        self.login_queue.depth()
+    }
+
+    /// Phase durations of 2FA setup and password changes since startup
+    pub fn operation_timings(&self) -> Vec<TimingHistogram> {
+        self.operation_timings.histograms()
+    }
+
+    /// Session event streams currently open
+    pub fn open_event_streams(&self) -> usize {
+        self.sessions.events().open_streams()
+    }
+
+    /// Occupancy of the session event streams' queues
+    pub fn event_queue_depth(&self) -> EventQueueDepth {
+        self.sessions.events().queue_depth()
+    }
+
+    /// Close the event streams whose client stopped reading; returns how many
+    pub fn reap_idle_event_streams(&self) -> usize {
+        self.sessions.events().reap(std::time::Instant::now())
+    }
+
+    /// The policy new passwords are checked against
+    pub fn password_policy(&self) -> &PasswordPolicy {
+        self.password_service.policy()
+    }
+
+    /// Run the startup crypto self-test against the services this instance signs in with
+    pub fn crypto_self_test(&self) -> Result<(), SelfTestError> {
+        run_crypto_self_test(&self.password_service, &self.token_service, &self.two_fa_service)
+    }
+
+    /// Lifetime of issued tokens, reported to clients as `expires_in`
+    pub fn token_lifetime_seconds(&self) -> i64 {
+        self.token_service.token_lifetime_seconds()
+    }
+
+    /// Verify a login password once a hashing slot is free. The hash runs on the
+    /// blocking pool so a login storm can't starve the async workers.
+    async fn verify_login_password(&self, password: &str, hash: &str) -> AuthResult<bool> {
+        let Some(_permit) = self.login_queue.admit().await else {
+            log::warn!("Login queue full ({:?}); turning login away", self.login_queue.depth());
+            return Err(AuthError::LoginQueueFull);
+        };
+
+        let password_service = self.password_service.clone();
+        let (password, hash) = (password.to_string(), hash.to_string());
+        tokio::task::spawn_blocking(move || password_service.verify_password(&password, &hash))
+            .await
+            .map_err(|e| AuthError::InternalError(format!("Password verification task failed: {}", e)))?
+    }
+
+    /// Signed `form_issued_at` value for a login form rendered now, and the
+    /// shortest fill time accepted without a flag
+    pub fn login_challenge(&self) -> (String, i64) {
+        (self.bot_heuristics.issue_challenge(), self.bot_heuristics.min_fill_ms())
+    }
+
+    fn decoy_hash(&self) -> AuthResult<&str> {
+        if let Some(hash) = self.decoy_hash.get() {
+            return Ok(hash);
         }
+        let decoy = self.password_service.generate_temporary_password();
+        let hash = self.password_service.hash_password(&decoy)?;
+        Ok(self.decoy_hash.get_or_init(|| hash))
+    }
+
+    /// The current time by the service clock, for handlers computing expiries
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Audit service shared with handlers that record their own security events
+    pub fn audit_service(&self) -> &AuditService {
+        &self.audit_service
+    }
+
+    /// Per-organization security policy overrides
+    pub fn policies(&self) -> &PolicyResolver {
+        &self.policies
+    }
+
+    /// Settings in force for `user`
+    async fn policy_for(&self, user: &User) -> AuthResult<EffectivePolicy> {
+        Ok(self.policies.resolve(user.organization.as_deref()).await?)
+    }
+
+    /// Whether `user`'s password is older than the max age `policy` allows
+    fn password_expired(&self, user: &User, policy: &EffectivePolicy) -> bool {
+        policy.password_max_age_days > 0
+            && user.password_changed_at.unwrap_or(user.created_at) + Duration::days(policy.password_max_age_days)
+                <= self.clock.now()
     }
 
     /// Authenticate user with credentials
-    pub async fn authenticate(&mut self, request: LoginRequest, ip_address: &str) -> AuthResult<LoginResponse> {
+    pub async fn authenticate(&self, ctx: &RequestContext, request: LoginRequest) -> AuthResult<LoginResponse> {
+        self.authenticate_with(ctx, request, PasswordRotation::AfterSignIn).await
+    }
+
+    /// Authenticate user with credentials, handling a password that must be
+    /// changed as `rotation` says
+    pub async fn authenticate_with(
+        &self,
+        ctx: &RequestContext,
+        request: LoginRequest,
+        rotation: PasswordRotation,
+    ) -> AuthResult<LoginResponse> {
+        let _span = tracer::span("AuthService::authenticate");
         // Check rate limiting first
-        self.check_rate_limit(&request.username, ip_address)?;
+        self.check_rate_limit(&request.username, &ctx.ip_address).await?;
+
+        let client = self.client_for_login(ctx, request.client_id.as_deref(), &request.username).await;
+
+        let bot_signal = if self.features.enabled(Feature::LoginBotChecks) {
+            self.bot_heuristics.inspect(&request)
+        } else {
+            None
+        };
+        if let Some(signal) = bot_signal {
+            self.audit_service.log_security_event(
+                ctx,
+                None,
+                AuditEventType::BotSuspected,
+                &format!("Login for {} looks scripted: {}", request.username, signal.as_str()),
+                false,
+                Severity::Warning,
+                Some(json!({
+                    "signal": signal.as_str(),
+                    "username": request.username,
+                    "elapsed_ms": match signal {
+                        BotSignal::TooFast { elapsed_ms } => Some(elapsed_ms),
+                        _ => None,
+                    },
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log suspected bot: {}", e));
+
+            // A filled honeypot is answered like a wrong password, after as
+            // long as checking one takes. Timing alone is only flagged.
+            if signal == BotSignal::Honeypot {
+                self.verify_login_password(&request.password, self.decoy_hash()?).await?;
+                self.throttle.record_failure(ThrottleScope::Ip, &ctx.ip_address).await;
+                return Err(AuthError::InvalidCredentials);
+            }
+        }
 
         // Get user from database
-        let mut user = self.get_user_by_username(&request.username).await?;
+        let mut user = match self.get_user_by_username(&request.username).await {
+            Ok(user) => user,
+            Err(AuthError::InvalidCredentials) => {
+                // Unknown usernames are recorded too, so credential stuffing is visible
+                self.record_login_attempt(&LoginAttempt::new(
+                    ctx,
+                    None,
+                    &request.username,
+                    false,
+                    Some("Unknown user"),
+                )).await?;
+
+                self.audit_service.log_login_attempt(
+                    ctx,
+                    None,
+                    &request.username,
+                    false,
+                    Some("Unknown user"),
+                ).await.unwrap_or_else(|e| log::error!("Failed to log failed login: {}", e));
+
+                self.count_login_failure(ctx, &request.username).await;
+                return Err(AuthError::InvalidCredentials);
+            }
+            Err(e) => return Err(e),
+        };
 
         // Check if account is locked
-        if user.is_locked && user.lockout_expiry.map(|exp| exp > Utc::now()).unwrap_or(false) {
+        if user.is_locked_at(self.clock.now()) {
             return Err(AuthError::AccountLocked);
         }
 
+        // The break-glass account signs in with its one-time passphrase only
+        if let Some(credential) = self.break_glass.credential_for(user.id).await? {
+            return self.authenticate_break_glass(ctx, user, credential, &request.password, &client).await;
+        }
+
         // Verify password
         log::debug!("Login: Verifying password for user: {}", user.username);
         log::debug!("Login: Password length: {}", request.password.len());
-        let password_valid = self.password_service.verify_password(&request.password, &user.password_hash)?;
+        let password_valid = self.verify_login_password(&request.password, &user.password_hash).await?;
 
         if !password_valid {
             // Record failed attempt
-            self.record_login_attempt(&user, ip_address, false, Some("Invalid password")).await?;
+            self.record_login_attempt(&LoginAttempt::new(
+                ctx,
+                Some(user.id),
+                &user.username,
+                false,
+                Some("Invalid password"),
+            )).await?;
 
             // Log to audit service
             self.audit_service.log_login_attempt(
+                ctx,
                 Some(user.id),
                 &user.username,
-                ip_address,
-                request.user_agent.as_deref(),
                 false,
                 Some("Invalid password"),
             ).await.unwrap_or_else(|e| log::error!("Failed to log failed login: {}", e));
 
-            // Increment failed attempts
-            user.login_attempts += 1;
+            let exempt = self.lockout_exempt(ctx, &user).await;
+            if !exempt {
+                self.count_login_failure(ctx, &user.username).await;
+            }
+            self.failed_login_digests.record(user.id, &user.username, &ctx.ip_address, self.clock.now());
+
+            // Counted in the database, since parallel failures all read the same stale row
+            let login_attempts = self.increment_failed_logins(user.id).await?;
+
+            // Lock account if too many attempts, ending any session it still has
+            let config = self.token_service.config();
+            let max_failed_attempts = self.policy_for(&user).await?.max_failed_attempts;
+            if login_attempts >= max_failed_attempts && exempt {
+                log::warn!(
+                    "{} failed sign-ins for {} from {}; not locking it, as it is exempt from lockout",
+                    login_attempts,
+                    user.username,
+                    ctx.ip_address
+                );
+            } else if login_attempts >= max_failed_attempts {
+                let locked_until = self.clock.now() + Duration::minutes(config.lockout_duration_minutes);
+
+                // Only the request that actually locks the account raises the alarm,
+                // so the owner hears about each lockout episode once
+                if !self.lock_after_failed_logins(user.id, locked_until).await? {
+                    return Err(AuthError::AccountLocked);
+                }
+
+                self.audit_service.log_account_lockout(
+                    ctx,
+                    user.id,
+                    &user.username,
+                    login_attempts,
+                ).await.unwrap_or_else(|e| log::error!("Failed to log account lockout: {}", e));
 
-            // Lock account if too many attempts
-            if user.login_attempts >= 5 {
-                user.is_locked = true;
-                user.lockout_expiry = Some(Utc::now() + Duration::minutes(5));
+                // The failures that led here go out now; the lockout notice itself is never held back
+                self.send_failed_login_digest(ctx, user.id, DigestTrigger::Locked).await;
+                self.notification_service
+                    .notify_account_locked(ctx, user.id, &user.username, locked_until)
+                    .await
+                    .unwrap_or_else(|e| log::error!("Failed to queue lockout notification: {}", e));
             }
 
-            self.update_user_security_info(&user).await?;
             return Err(AuthError::InvalidCredentials);
         }
 
+        // Deactivated accounts are refused only after the password checks out,
+        // so the response doesn't reveal account status to guessers
+        if !user.is_active {
+            self.record_login_attempt(&LoginAttempt::new(
+                ctx,
+                Some(user.id),
+                &user.username,
+                false,
+                Some("Account deactivated"),
+            )).await?;
+            return Err(AuthError::AccountDisabled);
+        }
+
+        // A bcrypt hash, from before Argon2 or imported from the legacy county
+        // portal, is replaced now that the password is known
+        if is_bcrypt_hash(&user.password_hash) {
+            self.upgrade_password_hash(&user, &request.password).await;
+        }
+
+        // The owner is back; tell them what happened while they were away
+        self.send_failed_login_digest(ctx, user.id, DigestTrigger::LoginSucceeded).await;
+
+        // A login the multiple-login policy refuses is turned away before the
+        // account's session columns change or a 2FA code is asked for
+        if let Err(refused) = self.sessions_to_sign_in_over(&user, request.sign_out_other_sessions).await {
+            self.record_login_attempt(&LoginAttempt::new(
+                ctx,
+                Some(user.id),
+                &user.username,
+                false,
+                Some("Signed in elsewhere"),
+            )).await?;
+            return Err(refused);
+        }
+
         // Reset login attempts on successful authentication
         user.login_attempts = 0;
         user.is_locked = false;
         user.lockout_expiry = None;
-        user.last_login = Some(Utc::now());
+        user.last_login = Some(self.clock.now());
 
         // Generate session ID and token
         let session_id = TokenService::generate_session_id();
-        let session_expires_at = Utc::now() + Duration::minutes(30); // 30-minute sessions
+        let policy = self.policy_for(&user).await?;
+        let session_expires_at = self.clock.now() + Duration::minutes(policy.session_timeout_minutes);
 
         user.session_token = Some(session_id.clone());
         user.session_expires_at = Some(session_expires_at);
@@ -103,60 +945,126 @@ impl AuthService {
         if user.two_fa_enabled {
             if let Some(two_fa_code) = &request.two_fa_code {
                 // Second step: Verify 2FA code
-                let is_valid = if two_fa_code.len() == 6 && two_fa_code.chars().all(|c| c.is_ascii_digit()) {
-                    // Verify TOTP code
-                    if let Some(ref secret) = user.two_fa_secret {
-                        self.two_fa_service.verify_totp(secret, two_fa_code)?
-                    } else {
-                        false
-                    }
-                } else if two_fa_code.len() == 8 && two_fa_code.chars().all(|c| c.is_ascii_alphanumeric()) {
-                    // Verify backup code
-                    if let Some(ref backup_codes) = user.two_fa_backup_codes {
-                        let (is_valid, updated_codes) = self.two_fa_service.verify_backup_code(backup_codes, two_fa_code)?;
-                        if is_valid {
-                            // Update backup codes in database (remove used code)
-                            self.update_user_backup_codes(user.id, &updated_codes).await?;
-                        }
-                        is_valid
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                };
+                let is_valid = self.verify_two_factor_code(&user, two_fa_code).await?;
+
+                self.audit_service
+                    .log_two_fa_attempt(ctx, user.id, &user.username, two_fa_code.kind(), is_valid)
+                    .await
+                    .unwrap_or_else(|e| log::error!("Failed to log 2FA attempt: {}", e));
 
                 if !is_valid {
                     // Record failed 2FA attempt
-                    self.record_login_attempt(&user, ip_address, false, Some("Invalid 2FA code")).await?;
+                    self.record_login_attempt(&LoginAttempt::new(
+                        ctx,
+                        Some(user.id),
+                        &user.username,
+                        false,
+                        Some("Invalid 2FA code"),
+                    )).await?;
                     return Err(AuthError::InvalidCredentials);
                 }
 
                 // 2FA verified, proceed with login
-                self.complete_login(user, session_id, ip_address, &request).await
+                self.finish_login(ctx, user, session_id, request.sign_out_other_sessions, &client, rotation).await
             } else {
-                // First step: Password verified, 2FA required
+                // First step: Password verified, 2FA required. The code can
+                // follow with the temp token instead of the password.
                 let temp_token = self.two_fa_service.generate_temp_token();
-                
-                // Store temp token temporarily (you might want to store this in Redis or database)
-                // For now, we'll return it and validate it on the next request
-                
-                Ok(LoginResponse {
-                    token: String::new(), // No full token yet
-                    user: UserResponse::from(user),
-                    expires_in: 0,
-                    requires_two_fa: true,
-                    two_fa_temp_token: Some(temp_token),
-                })
+                self.login_challenges
+                    .create(
+                        user.id,
+                        &self.two_fa_service.temp_token_digest(&temp_token)?,
+                        &session_id,
+                        request.sign_out_other_sessions,
+                        None,
+                        &client.id,
+                    )
+                    .await?;
+
+                self.second_factor_pending(user, &policy, temp_token, "authenticator").await
             }
+        } else if self.lockdown.active().is_some() {
+            // During a lockdown every sign-in takes a second factor. Accounts
+            // without 2FA of their own are sent a code by email.
+            if !self.has_verified_email(user.id).await? {
+                self.record_login_attempt(&LoginAttempt::new(
+                    ctx,
+                    Some(user.id),
+                    &user.username,
+                    false,
+                    Some("No second factor during lockdown"),
+                )).await?;
+                return Err(AuthError::LockdownSecondFactorUnavailable);
+            }
+
+            let temp_token = self.two_fa_service.generate_temp_token();
+            let code = self.two_fa_service.generate_email_code();
+            self.login_challenges
+                .create(
+                    user.id,
+                    &self.two_fa_service.temp_token_digest(&temp_token)?,
+                    &session_id,
+                    request.sign_out_other_sessions,
+                    Some(&self.two_fa_service.email_code_digest(&code)?),
+                    &client.id,
+                )
+                .await?;
+            let expires_at = self.clock.now() + Duration::minutes(LOGIN_CHALLENGE_TTL_MINUTES);
+            self.notification_service
+                .notify_lockdown_sign_in_code(ctx, user.id, &user.username, &code, expires_at)
+                .await?;
+
+            self.second_factor_pending(user, &policy, temp_token, "email").await
         } else {
             // No 2FA, complete login normally
-            self.complete_login(user, session_id, ip_address, &request).await
+            self.finish_login(ctx, user, session_id, request.sign_out_other_sessions, &client, rotation).await
         }
     }
 
-    /// Change user password
-    pub async fn change_password(&mut self, user_id: Uuid, request: ChangePasswordRequest) -> AuthResult<()> {
+    /// Answer to a password step that still needs a second factor, sent
+    /// with `temp_token`
+    async fn second_factor_pending(
+        &self,
+        user: User,
+        policy: &EffectivePolicy,
+        temp_token: String,
+        method: &'static str,
+    ) -> AuthResult<LoginResponse> {
+        let terms_accepted = self.terms_accepted(user.id).await?;
+        Ok(LoginResponse {
+            token: String::new(), // No full token yet
+            user: UserResponse::from(user)
+                .with_two_fa_required(policy.require_two_fa)
+                .with_terms_accepted(terms_accepted),
+            expires_in: 0,
+            requires_two_fa: true,
+            two_fa_temp_token: Some(temp_token),
+            two_fa_method: Some(method),
+            terms_accepted,
+            password_change_token: None,
+            undismissed_alerts: 0,
+        })
+    }
+
+    /// Change user password; the caller's session is replaced with a fresh one
+    pub async fn change_password(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        request: ChangePasswordRequest,
+    ) -> AuthResult<SessionRenewal> {
+        let _span = tracer::span("AuthService::change_password");
+        self.operation_timings
+            .measure(Operation::ChangePassword, self.change_password_phases(ctx, user_id, request))
+            .await
+    }
+
+    async fn change_password_phases(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        request: ChangePasswordRequest,
+    ) -> AuthResult<SessionRenewal> {
         log::debug!("Password change attempt for user ID: {}", user_id);
         log::debug!("Current password length: {}", request.current_password.len());
         log::debug!("New password length: {}", request.new_password.len());
@@ -168,14 +1076,17 @@ impl AuthService {
         }
 
         // Get current user
-        let user = self.get_user_by_id(user_id).await?;
+        let user = phase_timings::timed(Phase::Db, self.get_user_by_id(user_id)).await?;
         log::debug!("Retrieved user: {} (temporary: {})", user.username, user.is_temporary_password);
         log::debug!("Stored password hash prefix: {}", user.password_hash.chars().take(20).collect::<String>());
 
         // Verify current password
         log::debug!("Password change: Attempting to verify current password for user: {}", user.username);
         log::debug!("Password change: Current password length: {}", request.current_password.len());
-        let current_password_valid = self.password_service.verify_password(&request.current_password, &user.password_hash)?;
+        let current_password_valid = {
+            let _hashing = phase_timings::phase(Phase::Hashing);
+            self.password_service.verify_password(&request.current_password, &user.password_hash)?
+        };
         log::debug!("Password change: Password verification result: {}", current_password_valid);
 
         if !current_password_valid {
@@ -188,7 +1099,10 @@ impl AuthService {
 
         // Check that new password is different from current
         log::info!("Checking if new password is different from current password");
-        let same_password = self.password_service.passwords_are_same(&request.new_password, &user.password_hash);
+        let same_password = {
+            let _hashing = phase_timings::phase(Phase::Hashing);
+            self.password_service.passwords_are_same(&request.new_password, &user.password_hash)
+        };
         if same_password {
             log::warn!("User attempted to set the same password as current");
             return Err(AuthError::InternalError("New password must be different from current password".to_string()));
@@ -196,436 +1110,4866 @@ impl AuthService {
         log::info!("New password is different from current password - proceeding with change");
 
         // Hash new password
-        let new_password_hash = self.password_service.hash_password(&request.new_password)?;
+        let new_password_hash = {
+            let _hashing = phase_timings::phase(Phase::Hashing);
+            self.password_service.hash_password(&request.new_password)?
+        };
 
         // Update password in database
+        let _db = phase_timings::phase(Phase::Db);
         self.update_user_password(user_id, &new_password_hash).await?;
+        self.revoke_user_artifacts(ctx, user_id, RevocationScope::PasswordChanged).await?;
 
         // Log password change to audit service
         self.audit_service.log_password_change(
+            ctx,
             user_id,
             &user.username,
-            "localhost", // TODO: Get actual IP address
-            None,
             true,
             user.is_temporary_password,
         ).await.unwrap_or_else(|e| log::error!("Failed to log password change: {}", e));
 
         log::info!("Password changed successfully for user: {}", user.username);
 
-        Ok(())
+        let user = self.get_user_by_id(user_id).await?;
+        self.rotate_session(&user, Rotation::Account).await
     }
 
     /// Validate session token
     pub async fn validate_session(&self, token: &str) -> AuthResult<UserResponse> {
+        self.session_for(token, None).await
+    }
+
+    /// Validate a session for a request from the client app `client`. A
+    /// token issued to another app is refused, so it can't be replayed
+    /// against this one.
+    pub async fn validate_session_for_client(&self, token: &str, client: &ClientApp) -> AuthResult<UserResponse> {
+        self.session_for(token, Some(&client.token_audience)).await
+    }
+
+    async fn session_for(&self, token: &str, audience: Option<&str>) -> AuthResult<UserResponse> {
+        let _span = tracer::span("AuthService::validate_session");
         // Validate JWT token
         let token_validation = self.token_service.validate_token(token)?;
+        if audience.is_some_and(|audience| audience != token_validation.audience) {
+            return Err(AuthError::InvalidToken);
+        }
 
-        // Get user from database to check session
-        let user = self.get_user_by_id(token_validation.user_id).await?;
+        let (user, policy) = self
+            .live_session_user(
+                token_validation.user_id,
+                &token_validation.session_id,
+                &token_validation.jti,
+                token_validation.token_version,
+            )
+            .await?;
+        self.sessions.touch(&token_validation.session_id, &token_validation.kid).await?;
 
-        // Check if session is still valid
-        if let (Some(session_token), Some(session_expires_at)) = (&user.session_token, user.session_expires_at) {
-            if session_token == &token_validation.session_id && session_expires_at > Utc::now() {
-                Ok(UserResponse::from(user))
-            } else {
-                Err(AuthError::SessionExpired)
-            }
-        } else {
-            Err(AuthError::SessionExpired)
-        }
+        let terms_accepted = self.terms_accepted(user.id).await?;
+        Ok(UserResponse::from(user)
+            .with_permissions(token_validation.permissions)
+            .with_two_fa_required(policy.require_two_fa)
+            .with_terms_accepted(terms_accepted)
+            .with_kiosk(token_validation.kiosk))
     }
 
-    /// Logout user (invalidate session)
-    pub async fn logout(&mut self, user_id: Uuid) -> AuthResult<()> {
-        // Get user info for audit logging
+    /// The account behind a session and the policy it is under, while the
+    /// session is live and its current token is the one with ID `jti`
+    async fn live_session_user(
+        &self,
+        user_id: Uuid,
+        session_id: &str,
+        jti: &str,
+        token_version: i64,
+    ) -> AuthResult<(User, EffectivePolicy)> {
+        // Get user from database to check session
         let user = self.get_user_by_id(user_id).await?;
 
-        // Clear session information
-        sqlx::query!(
-            "UPDATE users SET session_token = NULL, session_expires_at = NULL WHERE id = ?",
-            user_id
-        )
-        .execute(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
+        // Locked or deactivated accounts lose access even if the session is still live
+        if !user.is_active || user.is_locked_at(self.clock.now()) {
+            return Err(AuthError::AccountDisabled);
+        }
 
-        // Log logout to audit service
-        self.audit_service.log_logout(
-            user_id,
-            &user.username,
-            "localhost", // TODO: Get actual IP address
-            None,
-        ).await.unwrap_or_else(|e| log::error!("Failed to log logout: {}", e));
+        // The session must still be live and hold this token, which must not
+        // have been revoked on its own
+        if self.sessions.is_token_revoked(jti).await? {
+            return Err(AuthError::SessionExpired);
+        }
+        let Some(session) = self
+            .sessions
+            .live_for_token(user.id, session_id, jti)
+            .await?
+        else {
+            return Err(AuthError::SessionExpired);
+        };
 
-        Ok(())
+        // A session timeout shortened since sign-in applies to live sessions too
+        let policy = self.policy_for(&user).await?;
+        if session.created_at + Duration::minutes(policy.session_timeout_minutes) <= self.clock.now() {
+            return Err(AuthError::SessionExpired);
+        }
+
+        // Tokens issued before a permission change carry stale permissions
+        if token_version != user.token_version {
+            return Err(AuthError::SessionExpired);
+        }
+        // Starting a lockdown ends every session created before it
+        if session.auth_epoch < self.lockdown.epoch() {
+            return Err(AuthError::SessionExpired);
+        }
+        Ok((user, policy))
+    }
+
+    /// Issue a download token for `resource` on the session of `token`.
+    /// Returns `None` when the session lacks the resource's permission.
+    pub async fn issue_download_token(
+        &self,
+        token: &str,
+        resource: DownloadResource,
+    ) -> AuthResult<Option<(String, DateTime<Utc>)>> {
+        let token_validation = self.token_service.validate_token(token)?;
+        if !token_validation.permissions.contains(resource.permission()) {
+            return Ok(None);
+        }
+        let grant = DownloadGrant {
+            id: Uuid::new_v4(),
+            user_id: token_validation.user_id,
+            session_id: token_validation.session_id,
+            jti: token_validation.jti,
+            token_version: token_validation.token_version,
+            resource,
+        };
+        let issued = self.download_tokens.issue(&grant).await.map_err(|e| AuthError::InternalError(e.to_string()))?;
+        Ok(Some(issued))
+    }
+
+    /// The caller a download token for `resource` stands in for, using the
+    /// token up. That is the account of the session it was issued on, holding
+    /// only the resource's permission, and only while the session is live and
+    /// still on the token the download token was asked for with.
+    pub async fn redeem_download_token(&self, token: &str, resource: DownloadResource) -> AuthResult<UserResponse> {
+        let grant = self
+            .download_tokens
+            .redeem(token, resource)
+            .await
+            .map_err(|e| AuthError::InternalError(e.to_string()))?
+            .ok_or(AuthError::InvalidToken)?;
+        let (user, policy) = self.live_session_user(grant.user_id, &grant.session_id, &grant.jti, grant.token_version).await?;
+
+        let terms_accepted = self.terms_accepted(user.id).await?;
+        Ok(UserResponse::from(user)
+            .with_permissions(PermissionSet::from_iter([resource.permission()]))
+            .with_two_fa_required(policy.require_two_fa)
+            .with_terms_accepted(terms_accepted))
     }
 
-    /// Initialize default government user (run once at startup)
-    pub async fn initialize_default_user(&self) -> AuthResult<()> {
-        // Check if any users exist
-        let user_count: i32 = sqlx::query_scalar!("SELECT COUNT(*) as count FROM users")
-            .fetch_one(&self.db_pool)
-            .await
-            .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
-
-        if user_count == 0 {
-            // Create default government user with temporary password
-            let temp_password = self.password_service.generate_temporary_password();
-            let password_hash = self.password_service.hash_password(&temp_password)?;
-            let user_id = Uuid::new_v4();
-            let now = Utc::now();
-
-            sqlx::query!(
-                r#"
-                INSERT INTO users (id, username, password_hash, role, is_temporary_password,
-                                 created_at, updated_at, login_attempts, is_locked,
-                                 two_fa_enabled, two_fa_secret, two_fa_backup_codes, two_fa_enabled_at)
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-                "#,
+    /// Validate a session for the token verification endpoint.
+    ///
+    /// Every failure and a sample of successes are audited, and an IP that keeps
+    /// presenting bad tokens raises a warning event.
+    pub async fn verify_session(&self, ctx: &RequestContext, token: &str) -> AuthResult<UserResponse> {
+        let _span = tracer::span("AuthService::verify_session");
+        let result = self.validate_session(token).await;
+
+        let outcome = match &result {
+            Ok(_) => VerifyOutcome::Valid,
+            Err(e) => VerifyOutcome::from_error(e),
+        };
+        if self.verify_monitor.record(outcome) {
+            let user_id = result.as_ref().ok().and_then(|user| Uuid::parse_str(&user.id).ok());
+            self.audit_service.log_token_validation(
+                ctx,
                 user_id,
-                "kenya_government",
-                password_hash,
-                "kenya_government",
-                true,
-                now,
-                now,
-                0,
-                false,
+                result.is_ok(),
+                (outcome != VerifyOutcome::Valid).then(|| outcome.as_str()),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log token validation: {}", e));
+        }
+
+        if outcome != VerifyOutcome::Valid && self.verify_monitor.record_failure_from(&ctx.ip_address).await {
+            self.audit_service.log_security_event(
+                ctx,
+                None,
+                AuditEventType::TokenGuessingSuspected,
+                &format!("Repeated failed token verifications from {}", ctx.ip_address),
                 false,
-                Option::<String>::None,
-                Option::<String>::None,
-                Option::<chrono::DateTime<chrono::Utc>>::None
-            )
-            .execute(&self.db_pool)
-            .await
-            .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
+                Severity::Warning,
+                Some(json!({ "last_outcome": outcome.as_str() })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log token guessing: {}", e));
+        }
+
+        result
+    }
 
-            log::warn!("Default user created with temporary password: {}", temp_password);
-            log::warn!("IMPORTANT: Change this password immediately after first login!");
+    /// Open an event stream on the token's session.
+    ///
+    /// Returns `None` when the account already holds as many streams as it may.
+    pub async fn subscribe_session_events(&self, token: &str) -> AuthResult<Option<Subscription>> {
+        let session_id = self.token_service.validate_token(token)?.session_id;
+        let user = self.validate_session(token).await?;
+        if !user.terms_accepted {
+            return Err(AuthError::TermsAcceptanceRequired);
         }
+        let user_id = Uuid::parse_str(&user.id).map_err(|_| AuthError::InvalidToken)?;
 
-        Ok(())
+        Ok(self.sessions.events().subscribe(user_id, &session_id))
     }
 
-    // Private helper methods
+    /// Warnings due on a session right now, or `None` once it is no longer live
+    pub async fn session_warnings(&self, user_id: Uuid, session_id: &str) -> AuthResult<Option<Vec<SessionEvent>>> {
+        let Some(session) = self.sessions.live(user_id, session_id).await? else {
+            return Ok(None);
+        };
+        let now = self.clock.now();
+        let mut warnings = Vec::new();
 
-    async fn get_user_by_username(&self, username: &str) -> AuthResult<User> {
-        sqlx::query_as::<_, User>(
-            r#"
-            SELECT id, username, password_hash,
-                   role,
-                   is_temporary_password,
-                   created_at,
-                   updated_at,
-                   last_login,
-                   login_attempts, is_locked,
-                   lockout_expiry,
-                   password_changed_at,
-                   session_token,
-                   session_expires_at,
-                   two_fa_enabled,
-                   two_fa_secret,
-                   two_fa_backup_codes,
-                   two_fa_enabled_at
-            FROM users WHERE username = ?
-            "#
-        )
-        .bind(username)
-        .fetch_optional(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?
-        .ok_or(AuthError::InvalidCredentials)
-    }
+        if session.expires_at - now <= Duration::minutes(SESSION_EXPIRY_WARNING_MINUTES) {
+            warnings.push(SessionEvent::SessionExpiring { expires_at: session.expires_at });
+        }
 
-    async fn get_user_by_id(&self, user_id: Uuid) -> AuthResult<User> {
-        sqlx::query_as::<_, User>(
-            r#"
-            SELECT id, username, password_hash,
-                   role,
-                   is_temporary_password,
-                   created_at,
-                   updated_at,
-                   last_login,
-                   login_attempts, is_locked,
-                   lockout_expiry,
-                   password_changed_at,
-                   session_token,
-                   session_expires_at,
-                   two_fa_enabled,
-                   two_fa_secret,
-                   two_fa_backup_codes,
-                   two_fa_enabled_at
-            FROM users WHERE id = ?
-            "#
-        )
-        .bind(user_id)
-        .fetch_optional(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?
-        .ok_or(AuthError::InvalidCredentials)
+        let user = self.get_user_by_id(user_id).await?;
+        let max_age_days = self.policy_for(&user).await?.password_max_age_days;
+        if max_age_days > 0 {
+            let expires_at = user.password_changed_at.unwrap_or(user.created_at) + Duration::days(max_age_days);
+            if expires_at - now <= Duration::days(PASSWORD_EXPIRY_WARNING_DAYS) {
+                warnings.push(SessionEvent::PasswordExpiring { expires_at });
+            }
+        }
+
+        Ok(Some(warnings))
     }
 
-    async fn update_user_security_info(&self, user: &User) -> AuthResult<()> {
-        let now = Utc::now();
-        sqlx::query!(
-            r#"
-            UPDATE users
-            SET login_attempts = ?, is_locked = ?, lockout_expiry = ?,
-                last_login = ?, session_token = ?, session_expires_at = ?,
-                updated_at = ?
-            WHERE id = ?
-            "#,
-            user.login_attempts,
-            user.is_locked,
-            user.lockout_expiry,
-            user.last_login,
-            user.session_token,
-            user.session_expires_at,
-            now,
-            user.id
-        )
-        .execute(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
+    /// Confirm the caller's identity again, replacing the session with one
+    /// that unlocks sensitive actions for `STEP_UP_VALIDITY_MINUTES`. The
+    /// presented token stops working; use the returned one.
+    pub async fn step_up(&self, ctx: &RequestContext, token: &str, request: StepUpRequest) -> AuthResult<SessionRenewal> {
+        let _span = tracer::span("AuthService::step_up");
+        let session_id = self.token_service.validate_token(token)?.session_id;
+        let user_response = self.validate_session(token).await?;
+        if !user_response.terms_accepted {
+            return Err(AuthError::TermsAcceptanceRequired);
+        }
+        let user = self.get_user_by_username(&user_response.username).await?;
 
-        Ok(())
-    }
+        let mut verified = self.verify_login_password(&request.password, &user.password_hash).await?;
+        if verified && user.two_fa_enabled {
+            verified = match &request.two_fa_code {
+                Some(code) => self.verify_two_factor_code(&user, code).await?,
+                None => false,
+            };
+        }
 
-    async fn update_user_password(&self, user_id: Uuid, password_hash: &str) -> AuthResult<()> {
-        let now = Utc::now();
-        sqlx::query!(
-            r#"
-            UPDATE users
-            SET password_hash = ?, is_temporary_password = ?,
-                password_changed_at = ?, updated_at = ?
-            WHERE id = ?
-            "#,
-            password_hash,
-            false,
-            now,
-            now,
-            user_id
-        )
-        .execute(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::StepUpAuth,
+            &format!("Step-up re-authentication for user: {}", user.username),
+            verified,
+            if verified { Severity::Info } else { Severity::Warning },
+            Some(json!({
+                "session_id": session_id,
+                "two_fa_code_type": request.two_fa_code.as_ref().map(TwoFactorCode::kind),
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log step-up: {}", e));
 
-        Ok(())
+        if !verified {
+            // Answered with 403, which the rate limiting middleware doesn't count
+            self.throttle.record_failure(ThrottleScope::Ip, &ctx.ip_address).await;
+            return Err(AuthError::InvalidCredentials);
+        }
+        // The stronger session gets a new token so one captured before the step-up can't use it
+        let renewal = self.rotate_session(&user, Rotation::Session(&session_id)).await?;
+        self.sessions.record_step_up(&renewal.session_id).await?;
+        Ok(renewal)
     }
 
-    async fn record_login_attempt(&self, user: &User, ip_address: &str, success: bool, failure_reason: Option<&str>) -> AuthResult<()> {
-        let attempt_id = Uuid::new_v4();
-        let now = Utc::now();
+    /// Start a kiosk sign-in from the client app `client`: a user code for
+    /// the kiosk to show and a polling token for it to keep
+    pub async fn start_device_authorization(&self, client: &ClientApp) -> AuthResult<DeviceAuthorizationStart> {
+        let (authorization, polling_token) = self.device_authorizations.start(&client.id).await?;
+        Ok(DeviceAuthorizationStart {
+            user_code: authorization.display_code(),
+            polling_token,
+            expires_in: DEVICE_CODE_TTL_MINUTES * 60,
+            interval: DEVICE_POLL_INTERVAL_SECONDS,
+        })
+    }
 
-        sqlx::query!(
-            r#"
-            INSERT INTO login_attempts (id, user_id, username, ip_address, success,
-                                      failure_reason, timestamp)
-            VALUES (?, ?, ?, ?, ?, ?, ?)
-            "#,
-            attempt_id,
-            user.id,
-            user.username,
-            ip_address,
-            success,
-            failure_reason,
-            now
-        )
-        .execute(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
+    /// Approve or deny the kiosk sign-in showing the request's user code, on
+    /// behalf of the signed-in `user_id`. Either answer is audited.
+    pub async fn decide_device_authorization(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        request: DeviceApprovalRequest,
+    ) -> AuthResult<()> {
+        let authorization = match self.device_authorizations.by_user_code(&request.user_code).await? {
+            DeviceLookup::Found(authorization) if authorization.status == DeviceAuthorizationStatus::Pending => authorization,
+            DeviceLookup::Found(_) => return Err(AuthError::DeviceCodeUsed),
+            DeviceLookup::Expired => return Err(AuthError::DeviceCodeExpired),
+            DeviceLookup::Unknown => return Err(AuthError::DeviceCodeInvalid),
+        };
+        // Another request decided it, or it expired, since the lookup
+        if !self.device_authorizations.decide(&authorization, user_id, request.approve).await? {
+            return Err(AuthError::DeviceCodeUsed);
+        }
 
+        let (event_type, severity, outcome) = if request.approve {
+            (AuditEventType::DeviceLoginApproved, Severity::Info, "approved")
+        } else {
+            (AuditEventType::DeviceLoginDenied, Severity::Warning, "denied")
+        };
+        log::info!("Kiosk sign-in {} {} by {} from IP: {}", authorization.display_code(), outcome, username, ctx.ip_address);
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user_id),
+            event_type,
+            &format!("Kiosk sign-in {} {} by {}", authorization.display_code(), outcome, username),
+            true,
+            severity,
+            Some(json!({
+                "user_code": authorization.display_code(),
+                "client_id": authorization.client_id,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log kiosk sign-in decision: {}", e));
         Ok(())
     }
 
-    fn check_rate_limit(&self, _username: &str, _ip_address: &str) -> AuthResult<()> {
-        // Simple in-memory rate limiting
-        // In production, use Redis or a proper rate limiting service
-
-        let now = Utc::now();
-        let _window_start = now - Duration::minutes(5);
-
-        // This is a simplified implementation
-        // TODO: Implement proper rate limiting with Redis or database
+    /// The session for a kiosk whose sign-in was approved, answered once.
+    /// Until then the error says whether it is pending, denied or expired.
+    pub async fn poll_device_authorization(
+        &self,
+        ctx: &RequestContext,
+        polling_token: &str,
+        client: &ClientApp,
+    ) -> AuthResult<LoginResponse> {
+        let authorization = match self.device_authorizations.by_polling_token(polling_token).await? {
+            // Only the client app the kiosk started from can redeem it
+            DeviceLookup::Found(authorization) if authorization.client_id == client.id => authorization,
+            DeviceLookup::Found(_) | DeviceLookup::Unknown => return Err(AuthError::DeviceCodeInvalid),
+            DeviceLookup::Expired => return Err(AuthError::DeviceCodeExpired),
+        };
+        let user_id = match (authorization.status, authorization.user_id) {
+            (DeviceAuthorizationStatus::Pending, _) => return Err(AuthError::DeviceAuthorizationPending),
+            (DeviceAuthorizationStatus::Denied, _) => return Err(AuthError::DeviceLoginDenied),
+            (DeviceAuthorizationStatus::Redeemed, _) => return Err(AuthError::DeviceCodeUsed),
+            (DeviceAuthorizationStatus::Approved, Some(user_id)) => user_id,
+            (DeviceAuthorizationStatus::Approved, None) => return Err(AuthError::DeviceCodeInvalid),
+        };
+        if !self.device_authorizations.redeem(&authorization).await? {
+            return Err(AuthError::DeviceCodeUsed);
+        }
 
-        Ok(())
+        // The account may have been locked or deactivated since it approved
+        let user = self.get_user_by_id(user_id).await?;
+        if !user.is_active || user.is_locked_at(self.clock.now()) {
+            return Err(AuthError::AccountDisabled);
+        }
+        self.complete_kiosk_login(ctx, user, client).await
     }
 
-    /// Complete the login process (generate token and log)
-    async fn complete_login(&mut self, user: User, session_id: String, ip_address: &str, request: &LoginRequest) -> AuthResult<LoginResponse> {
-        // Generate JWT token
-        let token = self.token_service.generate_token(&user, &session_id)?;
-
-        // Record successful login
-        self.record_login_attempt(&user, ip_address, true, None).await?;
+    /// Open a kiosk session for `user`, alongside their other sessions. It
+    /// lasts at most `KIOSK_SESSION_MINUTES`, however long it is used, and its
+    /// token carries the `kiosk` claim sensitive endpoints refuse.
+    async fn complete_kiosk_login(&self, ctx: &RequestContext, user: User, client: &ClientApp) -> AuthResult<LoginResponse> {
+        let policy = self.policy_for(&user).await?;
+        let lifetime = Duration::minutes(policy.session_timeout_minutes.min(KIOSK_SESSION_MINUTES)).min(self.default_token_lifetime());
+        let session_id = TokenService::generate_session_id();
+        let permissions = self.permissions.effective(&user).await?;
+        let issued =
+            self.token_service.issue_token_with(&user, &session_id, permissions, lifetime, &client.token_audience, true)?;
+        let expires_at = self.clock.now() + lifetime;
+        self.sessions
+            .create(ctx, user.id, &session_id, &issued.jti, &issued.kid, expires_at, self.lockdown.epoch(), &client.id)
+            .await?;
 
-        // Log to audit service
-        self.audit_service.log_login_attempt(
+        self.record_login_attempt(&LoginAttempt::new(ctx, Some(user.id), &user.username, true, None)).await?;
+        self.audit_service.log_security_event(
+            ctx,
             Some(user.id),
-            &user.username,
-            ip_address,
-            request.user_agent.as_deref(),
+            AuditEventType::KioskSessionStarted,
+            &format!("Kiosk session started for user {}", user.username),
             true,
-            None,
-        ).await.unwrap_or_else(|e| log::error!("Failed to log successful login: {}", e));
+            Severity::Info,
+            Some(json!({
+                "session_id": session_id,
+                "kiosk": true,
+                "client_id": client.id,
+                "expires_at": expires_at.to_rfc3339(),
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log kiosk session: {}", e));
 
+        let terms_accepted = self.terms_accepted(user.id).await?;
         Ok(LoginResponse {
-            token,
-            user: UserResponse::from(user),
-            expires_in: 28800, // 8 hours in seconds
+            token: issued.token,
+            user: UserResponse::from(user)
+                .with_permissions(permissions)
+                .with_two_fa_required(policy.require_two_fa)
+                .with_terms_accepted(terms_accepted)
+                .with_kiosk(true),
+            expires_in: lifetime.num_seconds(),
             requires_two_fa: false,
             two_fa_temp_token: None,
+            two_fa_method: None,
+            terms_accepted,
+            password_change_token: None,
+            // The owner's alerts aren't for a shared kiosk's screen
+            undismissed_alerts: 0,
         })
     }
 
-    /// Update user backup codes
-    async fn update_user_backup_codes(&self, user_id: Uuid, backup_codes: &str) -> AuthResult<()> {
-        let now = Utc::now();
-        sqlx::query!(
-            "UPDATE users SET two_fa_backup_codes = ?, updated_at = ? WHERE id = ?",
-            backup_codes,
-            now,
-            user_id
-        )
-        .execute(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
-
-        Ok(())
+    /// Whether the user has accepted the current terms of use; always true
+    /// when there are none
+    async fn terms_accepted(&self, user_id: Uuid) -> AuthResult<bool> {
+        match &self.token_service.config().terms_version {
+            Some(version) => Ok(self.terms.accepted_at(user_id, version).await?.is_some()),
+            None => Ok(true),
+        }
     }
 
-    /// Prepare 2FA setup - generates secret and QR code
-    pub async fn prepare_two_fa_setup(&mut self, user_id: Uuid) -> AuthResult<TwoFASetupResponse> {
+    /// Everything the dashboard's security banners depend on, for one user.
+    /// The independent lookups run concurrently.
+    pub async fn security_checkup(&self, user_id: Uuid) -> AuthResult<SecurityCheckup> {
         let user = self.get_user_by_id(user_id).await?;
-        
-        // Generate secret and backup codes
-        let secret = self.two_fa_service.generate_secret();
-        let backup_codes = self.two_fa_service.generate_backup_codes(10);
-        
-        // Generate QR code
-        let qr_code = self.two_fa_service.generate_qr_code(&user.username, &secret)?;
-        
-        Ok(TwoFASetupResponse {
-            secret,
-            qr_code,
-            backup_codes,
-            enabled: false, // Not enabled yet, just prepared
+        let (policy, terms_accepted, unacknowledged_sign_in_alerts, undismissed_alerts) = futures_util::try_join!(
+            self.policy_for(&user),
+            self.terms_accepted(user.id),
+            async {
+                Ok(self.audit_service.count_unacknowledged_for_user(user.id, SIGN_IN_ALERT_EVENTS).await?)
+            },
+            async { Ok(self.user_alerts.undismissed_count(user.id).await?) },
+        )?;
+
+        let password_expired = self.password_expired(&user, &policy);
+        let backup_codes_remaining = match (&user.two_fa_backup_codes, user.two_fa_enabled) {
+            (Some(codes), true) => self.two_fa_service.backup_codes_remaining(codes)?,
+            _ => 0,
+        };
+
+        Ok(SecurityCheckup {
+            temporary_password: user.is_temporary_password,
+            password_expired,
+            two_fa_enabled: user.two_fa_enabled,
+            two_fa_required: policy.require_two_fa,
+            backup_codes_remaining,
+            backup_codes_low: user.two_fa_enabled && backup_codes_remaining <= LOW_BACKUP_CODES,
+            unacknowledged_sign_in_alerts,
+            undismissed_alerts,
+            terms_accepted,
+        })
+    }
+
+    /// The user's own alerts from the last 30 days, newest first
+    pub async fn my_alerts(&self, user_id: Uuid) -> AuthResult<Vec<UserAlert>> {
+        Ok(self.user_alerts.feed(user_id).await?)
+    }
+
+    /// Dismiss one of the user's own alerts, by the ID of any event in it
+    pub async fn dismiss_my_alert(&self, user_id: Uuid, event_id: Uuid) -> AuthResult<UserAlert> {
+        self.user_alerts.dismiss(user_id, event_id).await?.ok_or(AuthError::AlertNotFound)
+    }
+
+    /// The current terms version and whether the user has accepted it
+    pub async fn terms_status(&self, user_id: Uuid) -> AuthResult<TermsStatus> {
+        let version = self.token_service.config().terms_version.clone();
+        let accepted_at = match &version {
+            Some(version) => self.terms.accepted_at(user_id, version).await?,
+            None => None,
+        };
+        Ok(TermsStatus {
+            accepted: version.is_none() || accepted_at.is_some(),
+            version,
+            accepted_at: accepted_at.map(|at| at.to_rfc3339()),
         })
     }
 
-    /// Set up 2FA for user - verifies TOTP and enables 2FA
-    pub async fn setup_two_fa(&mut self, user_id: Uuid, request: TwoFASetupRequest) -> AuthResult<TwoFASetupResponse> {
+    /// Record the user accepting the terms. `request.version` must be the
+    /// current one, so a client showing outdated terms can't accept newer ones.
+    pub async fn accept_terms(&self, ctx: &RequestContext, user_id: Uuid, request: AcceptTermsRequest) -> AuthResult<TermsStatus> {
+        let Some(version) = self.token_service.config().terms_version.as_deref() else {
+            return Err(AuthError::TermsVersionMismatch);
+        };
+        if request.version != version {
+            return Err(AuthError::TermsVersionMismatch);
+        }
+
         let user = self.get_user_by_id(user_id).await?;
-        
-        // Generate secret and backup codes (same as prepare, but we'll verify the code)
-        let secret = self.two_fa_service.generate_secret();
-        let backup_codes = self.two_fa_service.generate_backup_codes(10);
-        
-        // Verify the provided TOTP code against the generated secret
-        let is_valid = self.two_fa_service.verify_totp(&secret, &request.totp_code)?;
-        if !is_valid {
-            return Err(AuthError::InvalidCredentials);
+        let (accepted_at, recorded) = self.terms.record(ctx, user_id, version).await?;
+        if recorded {
+            self.audit_service.log_security_event(
+                ctx,
+                Some(user_id),
+                AuditEventType::TermsAccepted,
+                &format!("User {} accepted terms of use version {}", user.username, version),
+                true,
+                Severity::Info,
+                Some(json!({ "version": version, "accepted_at": accepted_at.to_rfc3339() })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log terms acceptance: {}", e));
         }
 
-        // Generate QR code
-        let qr_code = self.two_fa_service.generate_qr_code(&user.username, &secret)?;
-        
-        // Hash backup codes for storage
-        let backup_codes_json = self.two_fa_service.hash_backup_codes(&backup_codes)?;
-        
-        // Update user in database
-        let now = Utc::now();
-        sqlx::query!(
-            r#"
-            UPDATE users 
-            SET two_fa_enabled = ?, two_fa_secret = ?, two_fa_backup_codes = ?, 
-                two_fa_enabled_at = ?, updated_at = ?
-            WHERE id = ?
-            "#,
-            true,
-            secret,
-            backup_codes_json,
-            now,
-            now,
-            user_id
-        )
-        .execute(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
+        Ok(TermsStatus {
+            version: Some(version.to_string()),
+            accepted: true,
+            accepted_at: Some(accepted_at.to_rfc3339()),
+        })
+    }
 
-        Ok(TwoFASetupResponse {
-            secret,
-            qr_code,
-            backup_codes,
-            enabled: true,
+    /// Refuse unless the token's session stepped up recently
+    pub async fn require_step_up(&self, token: &str) -> AuthResult<()> {
+        let session_id = self.token_service.validate_token(token)?.session_id;
+        if self.sessions.has_recent_step_up(&session_id).await? {
+            Ok(())
+        } else {
+            Err(AuthError::StepUpRequired)
+        }
+    }
+
+    /// The accounts `filter` selects, for administrators, in the order `page`
+    /// asks for. Each account comes with its live session count, tags and
+    /// effective permissions, read in the same query: a page takes two
+    /// queries, its count and its rows, however many accounts it holds.
+    pub async fn list_users(&self, filter: &UserFilter, page: &PageRequest) -> AuthResult<Paginated<UserResponse>> {
+        let now = self.clock.now();
+        let (conditions, values) = where_clause(filter.conditions(now));
+        let count = format!("SELECT COUNT(*) FROM users WHERE {}", conditions);
+        let (total,) = self
+            .read_pool
+            .run(|pool| bind_filter(sqlx::query_as::<_, (i64,)>(&count), &values).fetch_one(pool))
+            .await?;
+
+        let after = page.after_condition().map(|condition| format!("AND {}", condition)).unwrap_or_default();
+        let query = format!(
+            "SELECT users.*, {} FROM users WHERE {} {} {}",
+            DIRECTORY_ANNOTATIONS,
+            conditions,
+            after,
+            page.order_and_limit()
+        );
+        let rows = self
+            .read_pool
+            .run(|pool| {
+                page.bind_limit(page.bind_after(bind_filter(sqlx::query_as::<_, DirectoryRow>(&query).bind(now), &values)))
+                    .fetch_all(pool)
+            })
+            .await?;
+
+        let rows = page.finish(rows, total);
+        Ok(Paginated {
+            items: rows.items.into_iter().map(DirectoryRow::into_response).collect(),
+            total: rows.total,
+            next_cursor: rows.next_cursor,
         })
     }
 
-    /// Verify 2FA code during login
-    pub async fn verify_two_fa(&mut self, request: TwoFAVerifyRequest) -> AuthResult<LoginResponse> {
-        // Validate temp token format
-        if !self.two_fa_service.validate_temp_token(&request.temp_token) {
-            return Err(AuthError::InvalidToken);
+    /// An account as administrators see it, with its tags and notes
+    pub async fn admin_user_detail(&self, user_id: Uuid) -> AuthResult<AdminUserDetail> {
+        let user = self.admin_target(user_id).await?;
+        let permissions = self.permissions.effective(&user).await?;
+        let tags = self.account_notes.tags(user_id).await?;
+        Ok(AdminUserDetail {
+            user: UserResponse::from(user).with_permissions(permissions).with_tags(tags),
+            notes: self.account_notes.list(user_id).await?,
+        })
+    }
+
+    /// Helpdesk notes on an account, pinned ones first, then newest first
+    pub async fn account_notes(&self, user_id: Uuid) -> AuthResult<Vec<AccountNote>> {
+        self.admin_target(user_id).await?;
+        Ok(self.account_notes.list(user_id).await?)
+    }
+
+    /// Add a helpdesk note by `admin_id` to an account
+    pub async fn add_account_note(&self, admin_id: Uuid, user_id: Uuid, note: &str, pinned: bool) -> AuthResult<AccountNote> {
+        self.admin_target(user_id).await?;
+        let note = sanitize::multiline_text(note, MAX_NOTE_CHARS);
+        Ok(self.account_notes.add(user_id, admin_id, &note, pinned).await?)
+    }
+
+    /// Strike through a note on an account. Returns the note and whether
+    /// this call struck it.
+    pub async fn strike_account_note(&self, admin_id: Uuid, user_id: Uuid, note_id: Uuid) -> AuthResult<(AccountNote, bool)> {
+        self.admin_target(user_id).await?;
+        self.account_notes.strike(user_id, note_id, admin_id).await?.ok_or(AuthError::AccountNoteNotFound)
+    }
+
+    /// Replace an account's tags with `tags`, already normalized. Returns the
+    /// tags it had before.
+    pub async fn set_user_tags(&self, user_id: Uuid, tags: &[String]) -> AuthResult<Vec<String>> {
+        self.admin_target(user_id).await?;
+        Ok(self.account_notes.set_tags(user_id, tags).await?)
+    }
+
+    /// Active accounts still on the temporary password they were provisioned
+    /// with more than `ONBOARDING_GRACE_DAYS` ago
+    /// Accounts imported from the legacy county portal that haven't signed in
+    /// since, so still have their bcrypt hash
+    pub async fn unmigrated_legacy_hashes(&self) -> AuthResult<i64> {
+        Ok(self.read_pool.run(legacy_import::unmigrated_count).await?)
+    }
+
+    pub async fn overdue_onboarding_count(&self) -> AuthResult<i64> {
+        let cutoff = self.clock.now() - Duration::days(ONBOARDING_GRACE_DAYS);
+        Ok(self
+            .read_pool
+            .run(|pool| {
+                sqlx::query_scalar(
+                    r#"
+                    SELECT COUNT(*) FROM users
+                    WHERE onboarded_at IS NULL AND is_temporary_password = TRUE
+                      AND is_active = TRUE AND created_at < ?
+                    "#
+                )
+                .bind(cutoff)
+                .fetch_one(pool)
+            })
+            .await?)
+    }
+
+    /// Live sessions of an account, for administrators
+    pub async fn user_sessions(&self, user_id: Uuid) -> AuthResult<Vec<SessionRecord>> {
+        self.admin_target(user_id).await?;
+        Ok(self.sessions.live_for_user(user_id).await?)
+    }
+
+    /// End every live session of an account on an administrator's behalf,
+    /// returning the IDs of the sessions ended
+    pub async fn terminate_user_sessions(&self, admin_id: Uuid, user_id: Uuid) -> AuthResult<Vec<String>> {
+        self.admin_target(user_id).await?;
+        let terminated = self.sessions.revoke_all_for_user(user_id, Some(admin_id), "terminated_by_admin").await?;
+        self.clear_current_session(user_id, &terminated).await?;
+        Ok(terminated)
+    }
+
+    /// End one live session on an administrator's behalf, returning its owner
+    pub async fn terminate_session(&self, admin_id: Uuid, session_id: &str) -> AuthResult<Uuid> {
+        let owner = self.sessions.owner_of(session_id).await?.ok_or(AuthError::SessionExpired)?;
+        let terminated = self.sessions.revoke(session_id, Some(admin_id), "terminated_by_admin").await?;
+        self.clear_current_session(owner, &terminated.into_iter().collect::<Vec<_>>()).await?;
+        Ok(owner)
+    }
+
+    /// An organization's stored overrides and the settings its accounts get
+    pub async fn org_policy(&self, organization: &str) -> AuthResult<OrgPolicyView> {
+        let overrides = self.policies.overrides(organization).await?;
+        let effective = self.policies.resolve(Some(organization)).await?;
+        Ok(OrgPolicyView {
+            organization: organization.to_string(),
+            overrides,
+            effective,
+        })
+    }
+
+    /// Move a user into `organization`, or out of any with `None`. Returns the
+    /// organization they were in; its policy stops applying on their next request.
+    pub async fn set_user_organization(&self, user_id: Uuid, organization: Option<&str>) -> AuthResult<Option<String>> {
+        let previous: Option<String> = sqlx::query_scalar("SELECT organization FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&self.db_pool)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        self.busy_retry
+            .run(|| {
+                sqlx::query("UPDATE users SET organization = ?, updated_at = ? WHERE id = ?")
+                    .bind(organization)
+                    .bind(self.clock.now())
+                    .bind(user_id)
+                    .execute(&self.db_pool)
+            })
+            .await?;
+        Ok(previous)
+    }
+
+    /// A user's role defaults, overrides and effective permissions
+    pub async fn user_permissions(&self, user_id: Uuid) -> AuthResult<UserPermissions> {
+        let (role, token_version): (UserRole, i64) =
+            sqlx::query_as("SELECT role, token_version FROM users WHERE id = ?")
+                .bind(user_id)
+                .fetch_optional(&self.db_pool)
+                .await?
+                .ok_or(AuthError::UserNotFound)?;
+        let overrides = self.permissions.overrides(user_id).await?;
+        let role_defaults = PermissionSet::for_role(&role);
+
+        Ok(UserPermissions {
+            effective: role_defaults.with_overrides(&overrides),
+            role,
+            role_defaults,
+            overrides,
+            token_version,
+        })
+    }
+
+    /// Replace a user's permission overrides on an administrator's behalf.
+    ///
+    /// Every token the user holds stops working at once; they sign in again
+    /// to pick up the new permissions.
+    pub async fn set_user_permissions(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        overrides: &[PermissionOverride],
+    ) -> AuthResult<UserPermissions> {
+        self.permissions
+            .replace_overrides(user_id, overrides, admin_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        self.user_permissions(user_id).await
+    }
+
+    /// Keep the account's current-session columns from pointing at a session
+    /// that was just ended
+    async fn clear_current_session(&self, user_id: Uuid, ended: &[String]) -> AuthResult<()> {
+        for session_id in ended {
+            self.busy_retry
+                .run(|| {
+                    sqlx::query(
+                        "UPDATE users SET session_token = NULL, session_expires_at = NULL WHERE id = ? AND session_token = ?",
+                    )
+                    .bind(user_id)
+                    .bind(session_id)
+                    .execute(&self.db_pool)
+                })
+                .await
+                .map_err(AuthError::Database)?;
         }
+        Ok(())
+    }
 
-        // In a real implementation, you would validate the temp token against a store (Redis/database)
-        // For this example, we'll assume it's valid if it has the right format
-        
-        // This is a simplified implementation - in production, you'd need to:
-        // 1. Store temp tokens with user association and expiry
-        // 2. Validate the temp token and get associated user
-        // 3. Complete the login process
-        
-        Err(AuthError::InternalError("2FA verification not fully implemented for temp tokens".to_string()))
+    /// Swap a valid token for a new one on the same session. The session's
+    /// expiry doesn't move, and the presented token stops working at once.
+    pub async fn reissue_token(&self, token: &str) -> AuthResult<SessionRenewal> {
+        let _span = tracer::span("AuthService::reissue_token");
+        let user_response = self.validate_session(token).await?;
+        let token_validation = self.token_service.validate_token(token)?;
+        let user = self.get_user_by_id(token_validation.user_id).await?;
+        let session = self
+            .sessions
+            .live(user.id, &token_validation.session_id)
+            .await?
+            .ok_or(AuthError::SessionExpired)?;
+
+        let token_lifetime = self.default_token_lifetime().min(session.expires_at - self.clock.now());
+        // The renewed token stays with the client app the presented one was
+        // issued to, and a kiosk's stays a kiosk's
+        let issued = self.token_service.issue_token_with(
+            &user,
+            &session.session_id,
+            user_response.permissions,
+            token_lifetime,
+            &token_validation.audience,
+            token_validation.kiosk,
+        )?;
+        if !self.sessions.reissue(&session.session_id, &issued.jti, &issued.kid).await? {
+            return Err(AuthError::SessionExpired);
+        }
+
+        Ok(SessionRenewal {
+            token: issued.token,
+            expires_in: token_lifetime.num_seconds(),
+            session_id: session.session_id,
+        })
     }
 
-    /// Disable 2FA for user
-    pub async fn disable_two_fa(&mut self, user_id: Uuid, request: TwoFADisableRequest) -> AuthResult<()> {
-        let user = self.get_user_by_id(user_id).await?;
-        
-        // Verify password
-        let password_valid = self.password_service.verify_password(&request.password, &user.password_hash)?;
-        if !password_valid {
-            return Err(AuthError::InvalidCredentials);
+    /// Revoke the token with ID `jti` on an administrator's behalf, e.g. one
+    /// reported leaked. Its session stays live for a token re-issued to it.
+    pub async fn revoke_token(
+        &self,
+        ctx: &RequestContext,
+        admin_id: Uuid,
+        admin_username: &str,
+        jti: &str,
+    ) -> AuthResult<RevokedToken> {
+        let revoked = self.sessions.revoke_token(jti, Some(admin_id)).await?.ok_or(AuthError::TokenNotFound)?;
+        if revoked.newly_revoked {
+            self.audit_service.log_security_event(
+                ctx,
+                Some(admin_id),
+                AuditEventType::TokenRevoked,
+                &format!("Token {} of user {} revoked by {}", jti, revoked.user_id, admin_username),
+                true,
+                Severity::Critical,
+                Some(json!({
+                    "jti": jti,
+                    "session_id": revoked.session_id,
+                    "target_user_id": revoked.user_id,
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log token revocation: {}", e));
         }
+        Ok(revoked)
+    }
 
-        // Verify either TOTP code or backup code if provided
-        if let Some(totp_code) = &request.totp_code {
-            if let Some(ref secret) = user.two_fa_secret {
-                let is_valid = self.two_fa_service.verify_totp(secret, totp_code)?;
-                if !is_valid {
-                    return Err(AuthError::InvalidCredentials);
+    /// Session, pool and memory gauges for capacity planning, counted at
+    /// most once every `HEALTH_DETAILS_TTL_SECONDS`
+    pub async fn health_details(&self) -> AuthResult<HealthDetails> {
+        let now = self.clock.now();
+        if let Ok(cached) = self.health_details.lock() {
+            if let Some(details) = cached.as_ref() {
+                if now - details.computed_at < Duration::seconds(HEALTH_DETAILS_TTL_SECONDS) {
+                    return Ok(details.clone());
                 }
             }
-        } else if let Some(backup_code) = &request.backup_code {
-            if let Some(ref backup_codes) = user.two_fa_backup_codes {
-                let (is_valid, _) = self.two_fa_service.verify_backup_code(backup_codes, backup_code)?;
-                if !is_valid {
-                    return Err(AuthError::InvalidCredentials);
-                }
+        }
+
+        let sessions = self.sessions.gauges().await?;
+        let size = self.db_pool.size();
+        let idle = u32::try_from(self.db_pool.num_idle()).unwrap_or(u32::MAX).min(size);
+        let throttle_counters = self.throttle.entry_count().await;
+        let verify_failure_ips = self.verify_monitor.tracked_ips().await;
+        let event_streams = self.open_event_streams();
+        let failed_login_digests = self.failed_login_digests.pending_users();
+        let password_change_challenges = self.password_changes.pending().await;
+        let download_tokens = self.download_tokens.pending().await;
+        let details = HealthDetails {
+            computed_at: now,
+            uptime_seconds: (now - self.started_at).num_seconds(),
+            sessions,
+            db_pool: PoolUsage { size, in_use: size - idle, idle },
+            ephemeral_store: EphemeralStoreUsage {
+                backend: self.throttle.backend_name(),
+                throttle_counters,
+                verify_failure_ips,
+                event_streams,
+                failed_login_digests,
+                password_change_challenges,
+                download_tokens,
+                total: throttle_counters
+                    + verify_failure_ips
+                    + event_streams
+                    + failed_login_digests
+                    + password_change_challenges
+                    + download_tokens,
+            },
+            event_queues: self.event_queue_depth(),
+            notification_queue: None,
+        };
+
+        if let Ok(mut cached) = self.health_details.lock() {
+            *cached = Some(details.clone());
+        }
+        Ok(details)
+    }
+
+    /// How far live sessions have moved from the previous JWT key to the
+    /// current one. Sessions under a key no longer configured can't be used
+    /// and aren't counted.
+    pub async fn jwt_migration_status(&self) -> AuthResult<JwtMigrationStatus> {
+        let mut status = JwtMigrationStatus {
+            current_kid: self.token_service.kid().to_string(),
+            previous_kid: self.token_service.previous_kid().map(str::to_string),
+            deadline: self.token_service.config().jwt_migration_deadline,
+            current_key_sessions: 0,
+            previous_key_sessions: 0,
+            unrecorded_sessions: 0,
+            previous_key_verifications: self.token_service.previous_key_verifications(),
+        };
+        for (kid, sessions) in self.sessions.live_by_token_kid().await? {
+            match kid {
+                None => status.unrecorded_sessions += sessions,
+                Some(kid) if kid == status.current_kid => status.current_key_sessions += sessions,
+                Some(kid) if status.previous_kid.as_ref() == Some(&kid) => status.previous_key_sessions += sessions,
+                Some(_) => {}
             }
-        } else {
-            return Err(AuthError::InternalError("Either TOTP code or backup code required".to_string()));
         }
+        Ok(status)
+    }
 
-        // Disable 2FA in database
-        let now = Utc::now();
-        sqlx::query!(
-            r#"
-            UPDATE users 
-            SET two_fa_enabled = ?, two_fa_secret = NULL, two_fa_backup_codes = NULL,
-                two_fa_enabled_at = NULL, updated_at = ?
-            WHERE id = ?
-            "#,
-            false,
-            now,
-            user_id
-        )
-        .execute(&self.db_pool)
-        .await
-        .map_err(|e| AuthError::InternalError(format!("Database error: {}", e)))?;
+    /// Token verification outcomes since startup
+    pub fn verify_counts(&self) -> VerifyCounts {
+        self.verify_monitor.counts()
+    }
+
+    /// Database writes retried, or given up on, as busy since startup
+    pub fn busy_retry_counts(&self) -> BusyRetryCounts {
+        self.busy_retry.counts()
+    }
+
+    /// Passwords Argon2 failed to hash since startup
+    pub fn hashing_failures(&self) -> u64 {
+        self.password_service.hashing_failures()
+    }
+
+    /// Tokens accepted since startup although issued ahead of this instance's clock
+    pub fn clock_skew_tolerated(&self) -> u64 {
+        self.token_service.clock_skew_tolerated()
+    }
 
+    /// Logout user (invalidate session)
+    pub async fn logout(&self, ctx: &RequestContext, user_id: Uuid) -> AuthResult<()> {
+        let _span = tracer::span("AuthService::logout");
+        // Get user info for audit logging
+        let user = self.get_user_by_id(user_id).await?;
+
+        // Clear session information
+        self.busy_retry
+            .run(|| {
+                sqlx::query!(
+                    "UPDATE users SET session_token = NULL, session_expires_at = NULL WHERE id = ?",
+                    user_id
+                )
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+        self.sessions.revoke_all_for_user(user_id, None, "logout").await?;
+
+        // Log logout to audit service
+        self.audit_service.log_logout(ctx, user_id, &user.username).await.unwrap_or_else(|e| log::error!("Failed to log logout: {}", e));
+
+        Ok(())
+    }
+
+    /// Lock an account until `until` (indefinitely when `None`), revoking its session
+    pub async fn lock_user(&self, ctx: &RequestContext, user_id: Uuid, until: Option<DateTime<Utc>>) -> AuthResult<()> {
+        let result = self
+            .busy_retry
+            .run(|| {
+                sqlx::query(
+                    r#"
+                    UPDATE users
+                    SET is_locked = TRUE, lockout_expiry = ?,
+                        updated_at = ?
+                    WHERE id = ?
+                    "#
+                )
+                .bind(until)
+                .bind(self.clock.now())
+                .bind(user_id)
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+        self.revoke_user_artifacts(ctx, user_id, RevocationScope::AccountLocked).await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Lift a lock and reset the failed-attempt counters
+    pub async fn unlock_user(&self, user_id: Uuid) -> AuthResult<()> {
+        let username: Option<String> = self
+            .busy_retry
+            .run(|| {
+                sqlx::query_scalar(
+                    r#"
+                    UPDATE users
+                    SET is_locked = FALSE, lockout_expiry = NULL, login_attempts = 0,
+                        updated_at = ?
+                    WHERE id = ?
+                    RETURNING username
+                    "#
+                )
+                .bind(self.clock.now())
+                .bind(user_id)
+                .fetch_optional(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        let username = username.ok_or(AuthError::UserNotFound)?;
+        self.throttle.reset(ThrottleScope::Username, &username).await;
+        Ok(())
+    }
+
+    /// Queue `action` on `target_id` for `approver_id` to carry out, and send
+    /// them the one-time link that does it. The link's token only goes out in
+    /// the notification.
+    pub async fn request_admin_action(
+        &self,
+        ctx: &RequestContext,
+        requester_id: Uuid,
+        requester_username: &str,
+        action: LinkedAction,
+        target_id: Uuid,
+        approver_id: Uuid,
+    ) -> AuthResult<PendingAction> {
+        let target = self.get_user_by_id(target_id).await?;
+        let (pending, token) = self.admin_actions.create(action, target_id, approver_id, requester_id).await?;
+        let link = format!("{}/api/admin/actions/confirm/{}", self.public_base_url, token);
+
+        self.notification_service
+            .notify_admin_action_link(approver_id, requester_username, &pending, &target.username, &link)
+            .await?;
+        self.audit_service.log_security_event(
+            ctx,
+            Some(requester_id),
+            AuditEventType::AdminActionLinkSent,
+            &format!(
+                "{} sent a one-time link to {} {} for approval",
+                requester_username,
+                action.as_str(),
+                target.username
+            ),
+            true,
+            Severity::Info,
+            Some(action_details(&pending)),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log admin action link: {}", e));
+
+        Ok(pending)
+    }
+
+    /// Carry out the action behind a one-time link, opened by `admin` in a
+    /// signed-in session. Unknown, misdirected, reused and expired links are
+    /// refused, each with its own audit event.
+    pub async fn confirm_admin_action(
+        &self,
+        ctx: &RequestContext,
+        admin_id: Uuid,
+        admin_username: &str,
+        token: &str,
+    ) -> AuthResult<PendingAction> {
+        let (event_type, problem, severity, error, pending) = match self.admin_actions.redeem(token, admin_id).await? {
+            Redemption::Confirmed(pending) => return self.carry_out_admin_action(ctx, admin_id, admin_username, pending).await,
+            Redemption::Unknown => {
+                (AuditEventType::AdminActionLinkInvalid, "doesn't exist", Severity::Warning, AuthError::ActionLinkInvalid, None)
+            }
+            Redemption::WrongAdmin(pending) => (
+                AuditEventType::AdminActionLinkWrongAdmin,
+                "was sent to another administrator",
+                Severity::Critical,
+                AuthError::ActionLinkWrongAdmin,
+                Some(pending),
+            ),
+            Redemption::AlreadyUsed(pending) => {
+                (AuditEventType::AdminActionLinkReused, "was already used", Severity::Warning, AuthError::ActionLinkUsed, Some(pending))
+            }
+            Redemption::Expired(pending) => {
+                (AuditEventType::AdminActionLinkExpired, "has expired", Severity::Info, AuthError::ActionLinkExpired, Some(pending))
+            }
+        };
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(admin_id),
+            event_type,
+            &format!("{} opened an action link that {}", admin_username, problem),
+            false,
+            severity,
+            pending.as_ref().map(action_details),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log refused action link: {}", e));
+        Err(error)
+    }
+
+    async fn carry_out_admin_action(
+        &self,
+        ctx: &RequestContext,
+        admin_id: Uuid,
+        admin_username: &str,
+        pending: PendingAction,
+    ) -> AuthResult<PendingAction> {
+        match pending.action {
+            LinkedAction::Unlock => self.unlock_user(pending.target_user_id).await?,
+            LinkedAction::ResetTwoFa => self.reset_two_fa(ctx, pending.target_user_id).await?,
+        }
+        self.audit_service.log_security_event(
+            ctx,
+            Some(admin_id),
+            AuditEventType::AdminActionConfirmed,
+            &format!(
+                "{} approved {} for user {} through a one-time link",
+                admin_username,
+                pending.action.as_str(),
+                pending.target_user_id
+            ),
+            true,
+            Severity::Warning,
+            Some(action_details(&pending)),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log admin action confirmation: {}", e));
+
+        Ok(pending)
+    }
+
+    /// Generate an export of `subject_id`'s personal data for `requester_id`,
+    /// the subject or an administrator. Returns it with its download link.
+    pub async fn request_data_export(
+        &self,
+        ctx: &RequestContext,
+        requester_id: Uuid,
+        subject_id: Uuid,
+    ) -> AuthResult<(DataExport, String)> {
+        let requester = self.get_user_by_id(requester_id).await?;
+        let (export, token) = self.data_exports.create(subject_id, requester_id).await?.ok_or(AuthError::UserNotFound)?;
+        let link = format!("{}/api/auth/data-exports/{}", self.public_base_url, token);
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(requester_id),
+            AuditEventType::DataExportRequested,
+            &format!("{} exported the personal data of user {}", requester.username, subject_id),
+            true,
+            if requester_id == subject_id { Severity::Info } else { Severity::Warning },
+            Some(data_export_details(&export)),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log data export: {}", e));
+
+        Ok((export, link))
+    }
+
+    /// Open the export behind a download link. Unknown and expired links are
+    /// refused, each with its own audit event.
+    pub async fn open_data_export(&self, ctx: &RequestContext, token: &str) -> AuthResult<DataExport> {
+        let (event_type, description, severity, result) = match self.data_exports.open(token).await? {
+            Download::Ready(export) => {
+                (AuditEventType::DataExportDownloaded, "Personal data export downloaded", Severity::Info, Ok(export))
+            }
+            Download::Unknown => (
+                AuditEventType::DataExportLinkInvalid,
+                "A data export link that doesn't exist was opened",
+                Severity::Warning,
+                Err(None),
+            ),
+            Download::Expired(export) => {
+                (AuditEventType::DataExportLinkExpired, "An expired data export link was opened", Severity::Info, Err(Some(export)))
+            }
+        };
+        let export = match &result {
+            Ok(export) | Err(Some(export)) => Some(export),
+            Err(None) => None,
+        };
+
+        self.audit_service.log_security_event(
+            ctx,
+            export.map(|export| export.user_id),
+            event_type,
+            description,
+            result.is_ok(),
+            severity,
+            export.map(data_export_details),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log data export download: {}", e));
+
+        result.map_err(|export| match export {
+            Some(_) => AuthError::DataExportExpired,
+            None => AuthError::DataExportNotFound,
+        })
+    }
+
+    /// Chunk `seq` of an opened export's document
+    pub async fn data_export_chunk(&self, export_id: Uuid, seq: i64) -> AuthResult<Option<String>> {
+        Ok(self.data_exports.chunk(export_id, seq).await?)
+    }
+
+    /// Handle a forgot-password request for `username`: when the account has a
+    /// verified email, send it a reset link. Returns the account's reset
+    /// channels, or `None` when there is no such account. The caller decides
+    /// how much of that to show; every request takes at least
+    /// `PASSWORD_RESET_REQUEST_MIN_DURATION` so the timing shows nothing.
+    pub async fn request_password_reset(&self, ctx: &RequestContext, username: &str) -> AuthResult<Option<Vec<ResetChannel>>> {
+        let started = std::time::Instant::now();
+        let result = self.send_password_reset(ctx, username).await;
+        if let Some(remaining) = PASSWORD_RESET_REQUEST_MIN_DURATION.checked_sub(started.elapsed()) {
+            tokio::time::sleep(remaining).await;
+        }
+        result
+    }
+
+    async fn send_password_reset(&self, ctx: &RequestContext, username: &str) -> AuthResult<Option<Vec<ResetChannel>>> {
+        let user = match self.get_user_by_username(username).await {
+            Ok(user) => user,
+            Err(AuthError::InvalidCredentials) => {
+                self.audit_service.log_security_event(
+                    ctx,
+                    None,
+                    AuditEventType::PasswordResetRequested,
+                    "Password reset requested for an unknown username",
+                    false,
+                    Severity::Info,
+                    Some(json!({ "username": username })),
+                ).await.unwrap_or_else(|e| log::error!("Failed to log password reset request: {}", e));
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let channels = self.password_reset_channels(user.id).await?;
+
+        let emailed = if channels.contains(&ResetChannel::Email) {
+            let (reset, token) = self.password_resets.create(user.id, ResetChannel::Email, None).await?;
+            let link = self.password_reset_link(&token);
+            self.notification_service.notify_password_reset(ctx, &reset, &user.username, &link).await?;
+            Some(reset)
+        } else {
+            None
+        };
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::PasswordResetRequested,
+            &format!(
+                "Password reset requested for {}; {}",
+                user.username,
+                if emailed.is_some() { "a link was emailed" } else { "no verified email to send a link to" }
+            ),
+            true,
+            Severity::Info,
+            Some(json!({
+                "username": user.username,
+                "channels": channels,
+                "reset_id": emailed.as_ref().map(|reset| reset.id),
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log password reset request: {}", e));
+
+        Ok(Some(channels))
+    }
+
+    /// How the account's owner can reset a forgotten password: by email once
+    /// the account has a verified address, and through an administrator
+    /// while it is active. A deactivated account has neither.
+    pub async fn password_reset_channels(&self, user_id: Uuid) -> AuthResult<Vec<ResetChannel>> {
+        let (is_active, verified_email): (bool, bool) = sqlx::query_as(
+            "SELECT is_active, email IS NOT NULL AND email_verified_at IS NOT NULL FROM users WHERE id = ?",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db_pool)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+        let mut channels = Vec::new();
+        if is_active && verified_email {
+            channels.push(ResetChannel::Email);
+        }
+        if is_active {
+            channels.push(ResetChannel::AdminAssisted);
+        }
+        Ok(channels)
+    }
+
+    /// Issue a password reset token for `user_id` to `admin_id`, who passes
+    /// the link on out of band. It works like an emailed one, and replaces it.
+    pub async fn issue_password_reset_link(
+        &self,
+        ctx: &RequestContext,
+        admin_id: Uuid,
+        admin_username: &str,
+        user_id: Uuid,
+    ) -> AuthResult<(PasswordReset, String, String)> {
+        let user = self.admin_target(user_id).await?;
+        if !user.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+        let (reset, token) = self.password_resets.create(user_id, ResetChannel::AdminAssisted, Some(admin_id)).await?;
+        let link = self.password_reset_link(&token);
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(admin_id),
+            AuditEventType::PasswordResetLinkIssued,
+            &format!("{} was given a password reset link for {}", admin_username, user.username),
+            true,
+            Severity::Warning,
+            Some(password_reset_details(&reset)),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log password reset link: {}", e));
+
+        Ok((reset, link, token))
+    }
+
+    /// Set a new password with a reset token from any channel, invitations included. Unknown,
+    /// reused and expired tokens are refused, each with its own audit event.
+    /// Every session of the account ends.
+    pub async fn complete_password_reset(&self, ctx: &RequestContext, request: ConfirmPasswordResetRequest) -> AuthResult<()> {
+        let _span = tracer::span("AuthService::complete_password_reset");
+        if request.new_password != request.confirm_password {
+            return Err(AuthError::PasswordMismatch);
+        }
+
+        let (event_type, problem, error, reset) = match self.password_resets.lookup(&request.token).await? {
+            ResetLookup::Valid(reset) => return self.reset_password(ctx, reset, &request.new_password).await,
+            ResetLookup::Unknown => (AuditEventType::PasswordResetLinkInvalid, "doesn't exist", AuthError::PasswordResetInvalid, None),
+            ResetLookup::AlreadyUsed(reset) => {
+                (AuditEventType::PasswordResetLinkReused, "was already used", AuthError::PasswordResetUsed, Some(reset))
+            }
+            ResetLookup::Expired(reset) => (AuditEventType::PasswordResetLinkExpired, "has expired", AuthError::PasswordResetExpired, Some(reset)),
+        };
+
+        self.audit_service.log_security_event(
+            ctx,
+            reset.as_ref().map(|reset| reset.user_id),
+            event_type,
+            &format!("A password reset link that {} was used", problem),
+            false,
+            if reset.is_some() { Severity::Info } else { Severity::Warning },
+            reset.as_ref().map(password_reset_details),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log refused password reset: {}", e));
+        Err(error)
+    }
+
+    async fn reset_password(&self, ctx: &RequestContext, reset: PasswordReset, new_password: &str) -> AuthResult<()> {
+        let user = self.get_user_by_id(reset.user_id).await?;
+        if !user.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+        self.password_service.validate_password_strength(new_password)?;
+        let new_password_hash = self.password_service.hash_password(new_password)?;
+
+        // Another request may have used the token while the password was hashed
+        if !self.password_resets.consume(&reset).await? {
+            return Err(AuthError::PasswordResetUsed);
+        }
+        self.update_user_password(user.id, &new_password_hash).await?;
+        self.revoke_user_artifacts(ctx, user.id, RevocationScope::PasswordReset).await?;
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::PasswordResetCompleted,
+            &format!("{} set a new password with a {} reset link", user.username, reset.channel.as_str()),
+            true,
+            Severity::Warning,
+            Some(password_reset_details(&reset)),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log password reset: {}", e));
+
+        Ok(())
+    }
+
+    fn password_reset_link(&self, token: &str) -> String {
+        format!("{}?token={}", self.password_reset_url, token)
+    }
+
+    /// Turn off 2FA for an account on an administrator's behalf, so its owner
+    /// can enroll again
+    async fn reset_two_fa(&self, ctx: &RequestContext, user_id: Uuid) -> AuthResult<()> {
+        let user = self.get_user_by_id(user_id).await?;
+        self.busy_retry
+            .run(|| {
+                sqlx::query(
+                    r#"
+                    UPDATE users
+                    SET two_fa_enabled = FALSE, two_fa_secret = NULL, two_fa_backup_codes = NULL,
+                        two_fa_enabled_at = NULL, two_fa_fingerprint = NULL, updated_at = ?
+                    WHERE id = ?
+                    "#
+                )
+                .bind(self.clock.now())
+                .bind(user_id)
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+        self.revoke_user_artifacts(ctx, user_id, RevocationScope::TwoFaReset).await?;
+
+        self.notification_service
+            .notify_two_fa_disabled(ctx, user_id, &user.username)
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to queue 2FA disabled notification: {}", e));
+        Ok(())
+    }
+
+    /// Revoke what an account holds that outlives a security change: its
+    /// sessions (all, or all but the current one, as `scope` says), a 2FA
+    /// setup still waiting for confirmation, and unused admin action links
+    /// for it. Done in one transaction and audited as one event.
+    pub async fn revoke_user_artifacts(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        scope: RevocationScope,
+    ) -> AuthResult<RevokedArtifacts> {
+        // The transaction rolls back whole when SQLite reports it busy, so it can run again
+        let (current_session, revoked) =
+            self.busy_retry.run(|| self.revoke_user_artifacts_in_transaction(user_id, scope)).await?;
+        self.sessions.announce_revoked(&revoked.sessions, scope.as_str());
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::CredentialsRevoked,
+            &format!(
+                "Revoked {} session(s), {} pending 2FA setup(s) and {} action link(s) after {}",
+                revoked.sessions.len(),
+                u8::from(revoked.pending_two_fa_setup),
+                revoked.action_links,
+                scope.as_str()
+            ),
+            true,
+            Severity::Info,
+            Some(json!({
+                "scope": scope.as_str(),
+                "kept_session": current_session,
+                "sessions": revoked.sessions,
+                "pending_two_fa_setup": revoked.pending_two_fa_setup,
+                "action_links": revoked.action_links,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log credential revocation: {}", e));
+
+        Ok(revoked)
+    }
+
+    /// The writes of `revoke_user_artifacts`, in one transaction. Returns the
+    /// session that was kept, if any, and what was revoked.
+    async fn revoke_user_artifacts_in_transaction(
+        &self,
+        user_id: Uuid,
+        scope: RevocationScope,
+    ) -> Result<(Option<String>, RevokedArtifacts), sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let current_session: Option<String> = if scope.keeps_current_session() {
+            sqlx::query_scalar("SELECT session_token FROM users WHERE id = ?")
+                .bind(user_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .flatten()
+        } else {
+            sqlx::query("UPDATE users SET session_token = NULL, session_expires_at = NULL WHERE id = ?")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            None
+        };
+        let sessions = self
+            .sessions
+            .revoke_for_user_in(&mut tx, user_id, current_session.as_deref(), None, scope.as_str())
+            .await?;
+        let pending_two_fa_setup = sqlx::query(
+            "UPDATE users SET two_fa_secret = NULL WHERE id = ? AND two_fa_enabled = FALSE AND two_fa_secret IS NOT NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        let action_links = self.admin_actions.cancel_for_target_in(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok((current_session, RevokedArtifacts { sessions, pending_two_fa_setup, action_links }))
+    }
+
+    /// Activate or deactivate an account; deactivation revokes its session
+    pub async fn set_user_active(&self, user_id: Uuid, active: bool) -> AuthResult<()> {
+        // Session columns are cleared in the same statement, so there is no
+        // window where a deactivated account still holds a live session
+        let result = self
+            .busy_retry
+            .run(|| {
+                sqlx::query(
+                    r#"
+                    UPDATE users
+                    SET is_active = ?,
+                        session_token = CASE WHEN ? THEN session_token ELSE NULL END,
+                        session_expires_at = CASE WHEN ? THEN session_expires_at ELSE NULL END,
+                        updated_at = ?
+                    WHERE id = ?
+                    "#
+                )
+                .bind(active)
+                .bind(active)
+                .bind(active)
+                .bind(self.clock.now())
+                .bind(user_id)
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+        if !active {
+            self.sessions.revoke_all_for_user(user_id, None, "account_deactivated").await?;
+        }
+        Ok(())
+    }
+
+    /// Apply one event the HR system `source` pushed. Each event ID from a
+    /// source is applied once; a redelivery is answered as a duplicate with
+    /// the account it applied to. Rejected events are audited but not
+    /// recorded, so the source can send them again once the cause is fixed.
+    pub async fn apply_hr_event(&self, ctx: &RequestContext, source: &str, event: &HrEvent) -> AuthResult<HrEventResult> {
+        let _span = tracer::span("AuthService::apply_hr_event");
+        let result = |status, user_id| HrEventResult {
+            event_id: event.event_id.clone(),
+            event_type: event.change.kind(),
+            status,
+            user_id,
+            error_type: None,
+            message: None,
+        };
+
+        if let Some(problem) = event.problem() {
+            return Ok(self.reject_hr_event(ctx, source, event, HrEventRejection::InvalidEvent(problem)).await);
+        }
+        if let Claim::Seen { user_id } = self.integration_events.claim(source, &event.event_id, event.change.kind()).await? {
+            return Ok(result(HrEventStatus::Duplicate, user_id));
+        }
+
+        let (applied, outcome) = match &event.change {
+            HrChange::Joined { username, email, organization } => {
+                (self.provision_hr_account(ctx, source, event, username, email, organization).await, "provisioned")
+            }
+            HrChange::Left { username } => (self.deprovision_hr_account(ctx, source, event, username).await, "deprovisioned"),
+            HrChange::Transferred { username, organization } => {
+                (self.transfer_hr_account(ctx, source, event, username, organization).await, "transferred")
+            }
+        };
+        match applied {
+            Ok(Ok(user_id)) => {
+                self.integration_events.complete(source, &event.event_id, user_id, outcome).await?;
+                Ok(result(HrEventStatus::Applied, Some(user_id)))
+            }
+            Ok(Err(rejection)) => {
+                self.integration_events.release(source, &event.event_id).await?;
+                Ok(self.reject_hr_event(ctx, source, event, rejection).await)
+            }
+            Err(e) => {
+                self.integration_events
+                    .release(source, &event.event_id)
+                    .await
+                    .unwrap_or_else(|release_error| log::error!("Failed to release HR event {}: {}", event.event_id, release_error));
+                Err(e)
+            }
+        }
+    }
+
+    /// Create a joiner's account with a password nobody knows, and send the
+    /// HR email, taken as verified, an invitation to choose one
+    async fn provision_hr_account(
+        &self,
+        ctx: &RequestContext,
+        source: &str,
+        event: &HrEvent,
+        username: &str,
+        email: &str,
+        organization: &str,
+    ) -> AuthResult<Result<Uuid, HrEventRejection>> {
+        if username == BREAK_GLASS_USERNAME {
+            return Ok(Err(HrEventRejection::UsernameTaken));
+        }
+        if !self.organization_exists(organization).await? {
+            return Ok(Err(HrEventRejection::UnknownOrganization));
+        }
+
+        let password_hash = self.password_service.hash_password(&self.password_service.generate_temporary_password())?;
+        let user_id = Uuid::new_v4();
+        let now = self.clock.now();
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO users (id, username, password_hash, role, is_temporary_password, created_at, updated_at,
+                               email, email_verified_at, organization)
+            VALUES (?, ?, ?, ?, TRUE, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(user_id)
+        .bind(username)
+        .bind(&password_hash)
+        .bind(UserRole::KenyaGovernment.as_str())
+        .bind(now)
+        .bind(now)
+        .bind(email)
+        .bind(now)
+        .bind(organization)
+        .execute(&self.db_pool)
+        .await;
+        match inserted {
+            Ok(_) => {}
+            Err(sqlx::Error::Database(db_error)) if db_error.is_unique_violation() => return Ok(Err(HrEventRejection::UsernameTaken)),
+            Err(e) => return Err(AuthError::Database(e)),
+        }
+
+        let (reset, token) = self.password_resets.create(user_id, ResetChannel::Invitation, None).await?;
+        let link = self.password_reset_link(&token);
+        self.notification_service.notify_invitation(&reset, username, &link).await?;
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::HrAccountProvisioned,
+            &format!("Account {} created for a joiner by {}", username, source),
+            true,
+            Severity::Info,
+            Some(json!({
+                "source": source,
+                "event_id": event.event_id,
+                "username": username,
+                "organization": organization,
+                "invitation_expires_at": reset.expires_at,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log HR account provisioning: {}", e));
+        Ok(Ok(user_id))
+    }
+
+    /// Deactivate a leaver's account, ending its sessions
+    async fn deprovision_hr_account(
+        &self,
+        ctx: &RequestContext,
+        source: &str,
+        event: &HrEvent,
+        username: &str,
+    ) -> AuthResult<Result<Uuid, HrEventRejection>> {
+        let Some(user) = self.hr_managed_user(username).await? else {
+            return Ok(Err(HrEventRejection::UnknownUser));
+        };
+        self.set_user_active(user.id, false).await?;
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::HrAccountDeprovisioned,
+            &format!("Account {} deactivated for a leaver by {}", username, source),
+            true,
+            Severity::Warning,
+            Some(json!({
+                "source": source,
+                "event_id": event.event_id,
+                "username": username,
+                "was_active": user.is_active,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log HR account deprovisioning: {}", e));
+        Ok(Ok(user.id))
+    }
+
+    /// Move a transferee's account to `organization`
+    async fn transfer_hr_account(
+        &self,
+        ctx: &RequestContext,
+        source: &str,
+        event: &HrEvent,
+        username: &str,
+        organization: &str,
+    ) -> AuthResult<Result<Uuid, HrEventRejection>> {
+        let Some(user) = self.hr_managed_user(username).await? else {
+            return Ok(Err(HrEventRejection::UnknownUser));
+        };
+        if !self.organization_exists(organization).await? {
+            return Ok(Err(HrEventRejection::UnknownOrganization));
+        }
+        let previous = self.set_user_organization(user.id, Some(organization)).await?;
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::HrAccountTransferred,
+            &format!("Account {} moved to {} by {}", username, organization, source),
+            true,
+            Severity::Info,
+            Some(json!({
+                "source": source,
+                "event_id": event.event_id,
+                "username": username,
+                "previous_organization": previous,
+                "organization": organization,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log HR account transfer: {}", e));
+        Ok(Ok(user.id))
+    }
+
+    /// Audit an HR event that wasn't applied and describe it for the source
+    async fn reject_hr_event(&self, ctx: &RequestContext, source: &str, event: &HrEvent, rejection: HrEventRejection) -> HrEventResult {
+        self.audit_service.log_security_event(
+            ctx,
+            None,
+            AuditEventType::HrEventRejected,
+            &format!("{} event {} from {} rejected: {}", event.change.kind(), event.event_id, source, rejection.message()),
+            false,
+            Severity::Warning,
+            Some(json!({
+                "source": source,
+                "event_id": event.event_id,
+                "type": event.change.kind(),
+                "username": event.change.username(),
+                "organization": event.change.organization(),
+                "error_type": rejection.error_type(),
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log rejected HR event: {}", e));
+
+        HrEventResult {
+            event_id: event.event_id.clone(),
+            event_type: event.change.kind(),
+            status: HrEventStatus::Rejected,
+            user_id: None,
+            error_type: Some(rejection.error_type()),
+            message: Some(rejection.message()),
+        }
+    }
+
+    /// The account HR events for `username` apply to. The break-glass
+    /// account is never one of them.
+    async fn hr_managed_user(&self, username: &str) -> AuthResult<Option<User>> {
+        if username == BREAK_GLASS_USERNAME {
+            return Ok(None);
+        }
+        match self.get_user_by_username(username).await {
+            Ok(user) => Ok(Some(user)),
+            Err(AuthError::InvalidCredentials) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether `organization` has a security policy or any members
+    async fn organization_exists(&self, organization: &str) -> AuthResult<bool> {
+        Ok(sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM org_security_policies WHERE organization = ?) \
+             OR EXISTS (SELECT 1 FROM users WHERE organization = ?)",
+        )
+        .bind(organization)
+        .bind(organization)
+        .fetch_one(&self.db_pool)
+        .await?)
+    }
+
+    /// When an account's temporary lockout ends, for the public lockout status check.
+    ///
+    /// Unknown, unlocked and administratively locked accounts all report the
+    /// current time, and every lookup takes at least `LOCKOUT_STATUS_MIN_DURATION`,
+    /// so the answer never confirms that a username exists.
+    pub async fn lockout_status(&self, username: &str) -> AuthResult<DateTime<Utc>> {
+        let started = std::time::Instant::now();
+        let now = self.clock.now();
+
+        let lockout_expiry: Option<Option<DateTime<Utc>>> = sqlx::query_scalar(
+            "SELECT lockout_expiry FROM users WHERE username = ? AND is_locked = TRUE",
+        )
+        .bind(username)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(AuthError::Database)?;
+
+        let unlocks_at = lockout_expiry.flatten().filter(|expiry| *expiry > now).unwrap_or(now);
+
+        if let Some(remaining) = LOCKOUT_STATUS_MIN_DURATION.checked_sub(started.elapsed()) {
+            tokio::time::sleep(remaining).await;
+        }
+
+        Ok(round_up_to_minute(unlocks_at))
+    }
+
+    /// Recent login attempts against a user's account, newest first
+    pub async fn get_login_history(&self, user_id: Uuid, limit: i64) -> AuthResult<Vec<LoginAttempt>> {
+        sqlx::query_as::<_, LoginAttempt>(
+            r#"
+            SELECT user_id, username,
+                   COALESCE(ip_address, 'unknown') as ip_address,
+                   user_agent, success, timestamp, failure_reason,
+                   country_code, region, city, asn, asn_org, latitude, longitude
+            FROM login_attempts
+            WHERE user_id = ?
+            ORDER BY timestamp DESC
+            LIMIT ?
+            "#
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(AuthError::Database)
+    }
+
+    /// Create the first administrator, `kenya_government` with a temporary
+    /// password, unless an active administrator exists. Instances starting
+    /// together against one database take turns through the `bootstrap_lock`
+    /// row, and the insert never replaces an existing account. Returns the
+    /// temporary password when this call created the account.
+    pub async fn initialize_default_user(&self) -> AuthResult<Option<String>> {
+        if self.active_admin_exists().await? {
+            return Ok(None);
+        }
+
+        let holder = Uuid::new_v4().to_string();
+        let now = self.clock.now();
+        let acquired = sqlx::query(
+            "UPDATE bootstrap_lock SET holder = ?, expires_at = ? WHERE name = ? AND (holder IS NULL OR expires_at < ?)",
+        )
+        .bind(&holder)
+        .bind(now + Duration::seconds(BOOTSTRAP_LOCK_SECONDS))
+        .bind(DEFAULT_USER_LOCK)
+        .bind(now)
+        .execute(&self.db_pool)
+        .await?;
+        if acquired.rows_affected() == 0 {
+            log::info!("Another instance is creating the default user");
+            return Ok(None);
+        }
+
+        let created = self.create_default_user().await;
+
+        // Released whatever happened, so a failed attempt can be retried at once
+        sqlx::query("UPDATE bootstrap_lock SET holder = NULL, expires_at = NULL WHERE name = ? AND holder = ?")
+            .bind(DEFAULT_USER_LOCK)
+            .bind(&holder)
+            .execute(&self.db_pool)
+            .await
+            .map(|_| ())
+            .unwrap_or_else(|e| log::error!("Failed to release the default user lock: {}", e));
+        created
+    }
+
+    /// Whether an administrator other than the break-glass account can sign in
+    async fn active_admin_exists(&self) -> AuthResult<bool> {
+        Ok(sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE role = ? AND is_active = TRUE AND username != ?)",
+        )
+        .bind(UserRole::Admin.as_str())
+        .bind(BREAK_GLASS_USERNAME)
+        .fetch_one(&self.db_pool)
+        .await?)
+    }
+
+    async fn create_default_user(&self) -> AuthResult<Option<String>> {
+        // Checked again under the lock: the instance that held it before may have just made one
+        if self.active_admin_exists().await? {
+            return Ok(None);
+        }
+
+        let temp_password = self.password_service.generate_temporary_password();
+        let password_hash = self.password_service.hash_password(&temp_password)?;
+        let now = self.clock.now();
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO users (id, username, password_hash, role, is_temporary_password, created_at, updated_at)
+            VALUES (?, ?, ?, ?, TRUE, ?, ?)
+            ON CONFLICT(username) DO NOTHING
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(DEFAULT_USERNAME)
+        .bind(&password_hash)
+        .bind(UserRole::Admin.as_str())
+        .bind(now)
+        .bind(now)
+        .execute(&self.db_pool)
+        .await?;
+
+        if inserted.rows_affected() == 0 {
+            log::warn!(
+                "No active administrator, but an account named {} exists; reactivate it or create an administrator",
+                DEFAULT_USERNAME
+            );
+            return Ok(None);
+        }
+        Ok(Some(temp_password))
+    }
+
+    /// Fingerprint 2FA secrets enrolled before fingerprints were stored (run
+    /// once at startup). Accounts sharing a secret keep no fingerprint and are
+    /// logged for support to re-enroll.
+    pub async fn backfill_two_fa_fingerprints(&self) -> AuthResult<usize> {
+        let pending: Vec<(Uuid, String)> = sqlx::query_as(
+            "SELECT id, two_fa_secret FROM users \
+             WHERE two_fa_enabled = TRUE AND two_fa_secret IS NOT NULL AND two_fa_fingerprint IS NULL",
+        )
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(AuthError::Database)?;
+
+        let mut filled = 0;
+        for (user_id, secret) in pending {
+            let fingerprint = self.two_fa_service.fingerprint_secret(&secret)?;
+            let result = sqlx::query("UPDATE users SET two_fa_fingerprint = ? WHERE id = ?")
+                .bind(&fingerprint)
+                .bind(user_id)
+                .execute(&self.db_pool)
+                .await;
+            match result {
+                Ok(_) => filled += 1,
+                Err(sqlx::Error::Database(db_error)) if db_error.is_unique_violation() => {
+                    log::warn!("User {} shares a 2FA secret with another account; it should re-enroll", user_id);
+                }
+                Err(e) => return Err(AuthError::Database(e)),
+            }
+        }
+        Ok(filled)
+    }
+
+    // Private helper methods
+
+    async fn get_user_by_username(&self, username: &str) -> AuthResult<User> {
+        sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, password_hash,
+                   role,
+                   is_temporary_password,
+                   created_at,
+                   updated_at,
+                   last_login,
+                   login_attempts, is_locked,
+                   lockout_expiry,
+                   is_active,
+                   password_changed_at,
+                   session_token,
+                   session_expires_at,
+                   two_fa_enabled,
+                   two_fa_secret,
+                   two_fa_backup_codes,
+                   two_fa_enabled_at,
+                   token_version,
+                   onboarded_at,
+                   organization
+            FROM users WHERE username = ?
+            "#
+        )
+        .bind(username)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(AuthError::Database)?
+        .ok_or(AuthError::InvalidCredentials)
+    }
+
+    async fn get_user_by_id(&self, user_id: Uuid) -> AuthResult<User> {
+        sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, password_hash,
+                   role,
+                   is_temporary_password,
+                   created_at,
+                   updated_at,
+                   last_login,
+                   login_attempts, is_locked,
+                   lockout_expiry,
+                   is_active,
+                   password_changed_at,
+                   session_token,
+                   session_expires_at,
+                   two_fa_enabled,
+                   two_fa_secret,
+                   two_fa_backup_codes,
+                   two_fa_enabled_at,
+                   token_version,
+                   onboarded_at,
+                   organization
+            FROM users WHERE id = ?
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(AuthError::Database)?
+        .ok_or(AuthError::InvalidCredentials)
+    }
+
+    /// The account an administrator named. Unlike `get_user_by_id` a missing
+    /// one is `UserNotFound`, since the caller's own credentials are fine.
+    async fn admin_target(&self, user_id: Uuid) -> AuthResult<User> {
+        match self.get_user_by_id(user_id).await {
+            Err(AuthError::InvalidCredentials) => Err(AuthError::UserNotFound),
+            result => result,
+        }
+    }
+
+    /// Replace a user's session with a new session ID and token.
+    ///
+    /// Used whenever a session's authentication strength changes, so a token
+    /// captured before the change can't ride along with the stronger session.
+    async fn rotate_session(&self, user: &User, rotation: Rotation<'_>) -> AuthResult<SessionRenewal> {
+        let session_id = TokenService::generate_session_id();
+        let now = self.clock.now();
+
+        // A break-glass session keeps its original expiry rather than gaining a full timeout
+        let (session_expires_at, token_lifetime) = if self.break_glass.credential_for(user.id).await?.is_some() {
+            let expires_at = user.session_expires_at.filter(|at| *at > now).ok_or(AuthError::SessionExpired)?;
+            (expires_at, expires_at - now)
+        } else {
+            (
+                now + Duration::minutes(self.policy_for(user).await?.session_timeout_minutes),
+                self.default_token_lifetime(),
+            )
+        };
+        // The new session keeps the client details and app of the one it replaces
+        let previous_id = match rotation {
+            Rotation::Account => user.session_token.as_deref(),
+            Rotation::Session(session_id) => Some(session_id),
+        };
+        let previous = match previous_id {
+            Some(previous_id) => self.sessions.live(user.id, previous_id).await?,
+            None => None,
+        };
+        let client = self.clients.resolve(previous.as_ref().map(|session| session.client_id.as_str())).0;
+        let ctx = previous
+            .map(|session| RequestContext::new(&session.ip_address, session.user_agent.as_deref()))
+            .unwrap_or_else(|| RequestContext::new("unknown", None));
+
+        let permissions = self.permissions.effective(user).await?;
+        let issued = self.token_service.issue_token(user, &session_id, permissions, token_lifetime, &client.token_audience)?;
+        // Rotating a single session only makes the new one current if the old one was
+        let replaces_current = match rotation {
+            Rotation::Account => {
+                self.sessions.revoke_all_for_user(user.id, None, "rotated").await?;
+                None
+            }
+            Rotation::Session(previous_id) => {
+                self.sessions.revoke(previous_id, None, "rotated").await?;
+                Some(previous_id)
+            }
+        };
+        self.sessions
+            .create(&ctx, user.id, &session_id, &issued.jti, &issued.kid, session_expires_at, self.lockdown.epoch(), &client.id)
+            .await?;
+
+        self.busy_retry
+            .run(|| {
+                sqlx::query(
+                    "UPDATE users SET session_token = ?, session_expires_at = ?, updated_at = ? \
+                     WHERE id = ? AND (? IS NULL OR session_token = ?)",
+                )
+                .bind(&session_id)
+                .bind(session_expires_at)
+                .bind(now)
+                .bind(user.id)
+                .bind(replaces_current)
+                .bind(replaces_current)
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        Ok(SessionRenewal {
+            token: issued.token,
+            expires_in: token_lifetime.num_seconds(),
+            session_id,
+        })
+    }
+
+    /// Send the failed sign-ins buffered for a user, if any, as one notification
+    async fn send_failed_login_digest(&self, ctx: &RequestContext, user_id: Uuid, trigger: DigestTrigger) {
+        if let Some(digest) = self.failed_login_digests.take(user_id) {
+            self.deliver_failed_login_digest(ctx, user_id, &digest, trigger).await;
+        }
+    }
+
+    /// Send the digests of accounts that have had no failed sign-ins for the
+    /// quiet period, returning how many went out. Run periodically.
+    pub async fn flush_quiet_failed_login_digests(&self) -> usize {
+        let quiet = self.failed_login_digests.take_quiet(self.clock.now());
+        let ctx = RequestContext::new("system", None);
+        for (user_id, digest) in &quiet {
+            self.deliver_failed_login_digest(&ctx, *user_id, digest, DigestTrigger::QuietPeriod).await;
+        }
+        quiet.len()
+    }
+
+    async fn deliver_failed_login_digest(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        digest: &FailedLoginDigest,
+        trigger: DigestTrigger,
+    ) {
+        if let Err(e) = self.notification_service.notify_failed_login_digest(user_id, digest, trigger).await {
+            log::error!("Failed to queue failed sign-in digest: {}", e);
+            return;
+        }
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::NotificationDigestSent,
+            &format!(
+                "Sent {} a digest of {} failed sign-in attempts from {} IP addresses",
+                digest.username,
+                digest.attempts,
+                digest.ip_addresses.len()
+            ),
+            true,
+            Severity::Info,
+            Some(json!({
+                "kind": "FAILED_LOGIN_DIGEST",
+                "attempts": digest.attempts,
+                "ip_addresses": digest.ip_addresses.iter().map(|ip| self.geoip.stored_ip(ip)).collect::<BTreeSet<_>>(),
+                "first_at": digest.first_at.to_rfc3339(),
+                "last_at": digest.last_at.to_rfc3339(),
+                "trigger": trigger.as_str(),
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log digest notification: {}", e));
+    }
+
+    /// Count a failed password and return the attempts now on record, in one
+    /// statement so concurrent failures can't overwrite each other's count
+    async fn increment_failed_logins(&self, user_id: Uuid) -> AuthResult<i32> {
+        Ok(self
+            .busy_retry
+            .run(|| {
+                sqlx::query_scalar(
+                    "UPDATE users SET login_attempts = login_attempts + 1, updated_at = ? WHERE id = ? RETURNING login_attempts",
+                )
+                .bind(self.clock.now())
+                .bind(user_id)
+                .fetch_one(&self.db_pool)
+            })
+            .await?)
+    }
+
+    /// Lock an account after repeated failed logins.
+    ///
+    /// Returns `true` only for the call that moved the account into the locked
+    /// state; accounts already under a live lock are left untouched.
+    async fn lock_after_failed_logins(&self, user_id: Uuid, locked_until: DateTime<Utc>) -> AuthResult<bool> {
+        let now = self.clock.now();
+        let result = self
+            .busy_retry
+            .run(|| {
+                sqlx::query(
+                    r#"
+                    UPDATE users
+                    SET is_locked = TRUE, lockout_expiry = ?,
+                        session_token = NULL, session_expires_at = NULL, updated_at = ?
+                    WHERE id = ?
+                      AND NOT (is_locked = TRUE AND (lockout_expiry IS NULL OR lockout_expiry > ?))
+                    "#
+                )
+                .bind(locked_until)
+                .bind(now)
+                .bind(user_id)
+                .bind(now)
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        self.sessions.revoke_all_for_user(user_id, None, "account_locked").await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn update_user_security_info(&self, user: &User) -> AuthResult<()> {
+        let now = self.clock.now();
+        self.busy_retry
+            .run(|| {
+                sqlx::query!(
+                    r#"
+                    UPDATE users
+                    SET login_attempts = ?, is_locked = ?, lockout_expiry = ?,
+                        last_login = ?, session_token = ?, session_expires_at = ?,
+                        updated_at = ?
+                    WHERE id = ?
+                    "#,
+                    user.login_attempts,
+                    user.is_locked,
+                    user.lockout_expiry,
+                    user.last_login,
+                    user.session_token,
+                    user.session_expires_at,
+                    now,
+                    user.id
+                )
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        Ok(())
+    }
+
+    /// Re-hash a verified password with Argon2 in place of its bcrypt hash.
+    /// A failure is logged and the sign-in goes on; the next one tries again.
+    async fn upgrade_password_hash(&self, user: &User, password: &str) {
+        let password_service = self.password_service.clone();
+        let password = password.to_string();
+        let password_hash = match tokio::task::spawn_blocking(move || password_service.rehash_password(&password)).await {
+            Ok(Ok(hash)) => hash,
+            Ok(Err(e)) => {
+                log::error!("Failed to re-hash the password of {}: {}", user.username, e);
+                return;
+            }
+            Err(e) => {
+                log::error!("Password re-hash task for {} failed: {}", user.username, e);
+                return;
+            }
+        };
+
+        // Only over the hash just verified, so a password changed meanwhile stays
+        let upgraded = sqlx::query(
+            "UPDATE users SET password_hash = ?, hash_scheme = ?, updated_at = ? WHERE id = ? AND password_hash = ?",
+        )
+        .bind(&password_hash)
+        .bind(HASH_SCHEME_ARGON2)
+        .bind(self.clock.now())
+        .bind(user.id)
+        .bind(&user.password_hash)
+        .execute(&self.db_pool)
+        .await;
+        match upgraded {
+            Ok(result) if result.rows_affected() > 0 => log::info!("Password hash of {} upgraded to Argon2", user.username),
+            Ok(_) => {}
+            Err(e) => log::error!("Failed to store the upgraded password hash of {}: {}", user.username, e),
+        }
+    }
+
+    /// Store a new password chosen by the user. Replacing a temporary password
+    /// for the first time marks the account onboarded.
+    async fn update_user_password(&self, user_id: Uuid, password_hash: &str) -> AuthResult<()> {
+        let now = self.clock.now();
+        self.busy_retry
+            .run(|| {
+                sqlx::query(
+                    r#"
+                    UPDATE users
+                    SET password_hash = ?, hash_scheme = ?, is_temporary_password = FALSE,
+                        onboarded_at = CASE WHEN is_temporary_password THEN COALESCE(onboarded_at, ?) ELSE onboarded_at END,
+                        password_changed_at = ?, updated_at = ?
+                    WHERE id = ?
+                    "#
+                )
+                .bind(password_hash)
+                .bind(HASH_SCHEME_ARGON2)
+                .bind(now)
+                .bind(now)
+                .bind(now)
+                .bind(user_id)
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        Ok(())
+    }
+
+    async fn record_login_attempt(&self, attempt: &LoginAttempt) -> AuthResult<()> {
+        let attempt_id = Uuid::new_v4();
+        // Throttling has already seen the full address; storage gets what privacy mode allows
+        let location = self.geoip.stored_location(&attempt.ip_address).unwrap_or_default();
+        let ip_address = self.geoip.stored_ip(&attempt.ip_address);
+        let raw_ip = self.geoip.raw_ip(&attempt.ip_address);
+        // Failed logins store whatever username the client typed
+        let limits = self.audit_service.text_limits();
+        let username = sanitize::text(&attempt.username, limits.username);
+        let user_agent = attempt.user_agent.as_deref().map(|ua| sanitize::text(ua, limits.user_agent));
+        let failure_reason = attempt.failure_reason.as_deref().map(|reason| sanitize::text(reason, limits.text));
+        // Stamped by the service clock, like everything the lockout and alert checks compare against
+        let timestamp = self.clock.now();
+
+        self.busy_retry
+            .run(|| {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO login_attempts (id, user_id, username, ip_address, raw_ip, user_agent,
+                                              success, failure_reason, timestamp,
+                                              country_code, region, city, asn, asn_org, latitude, longitude)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    "#,
+                    attempt_id,
+                    attempt.user_id,
+                    username,
+                    ip_address,
+                    raw_ip,
+                    user_agent,
+                    attempt.success,
+                    failure_reason,
+                    timestamp,
+                    location.country_code,
+                    location.region,
+                    location.city,
+                    location.asn,
+                    location.asn_org,
+                    location.latitude,
+                    location.longitude
+                )
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        Ok(())
+    }
+
+    /// The client app a login names. An unknown or disabled one is replaced by
+    /// the default app and recorded as a warning.
+    async fn client_for_login(&self, ctx: &RequestContext, client_id: Option<&str>, username: &str) -> ClientApp {
+        let (client, fallback) = self.clients.resolve(client_id);
+        if let (Some(fallback), Some(requested)) = (fallback, client_id) {
+            log::warn!("Login for {} named {} client app {:?}; using the default app", username, fallback.as_str(), requested);
+            self.audit_service.log_security_event(
+                ctx,
+                None,
+                AuditEventType::UnknownClientApp,
+                &format!(
+                    "Login for {} named {} client app {}; signed in as the default app",
+                    username,
+                    fallback.as_str(),
+                    sanitize::text(requested, self.audit_service.text_limits().text)
+                ),
+                true,
+                Severity::Warning,
+                Some(json!({
+                    "username": username,
+                    "client_id": requested,
+                    "reason": fallback.as_str(),
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log unknown client app: {}", e));
+        }
+        client
+    }
+
+    /// Whether a failed sign-in for `user` is left out of lockout and the
+    /// throttle: a listed username on an account tagged `monitoring`, coming
+    /// from an exempted network when any are configured. An address alone
+    /// never exempts anyone. Only consulted once a check has failed, so the
+    /// password and second factor are verified all the same.
+    async fn lockout_exempt(&self, ctx: &RequestContext, user: &User) -> bool {
+        let exemptions = &self.lockout_exemptions;
+        if !exemptions.lists_username(&user.username) || !exemptions.allows_source(&ctx.ip_address) {
+            return false;
+        }
+        match self.account_notes.tags(user.id).await {
+            Ok(tags) => tags.iter().any(|tag| tag == MONITORING_TAG),
+            Err(e) => {
+                log::error!("Failed to read the tags of {}, not exempting it from lockout: {}", user.username, e);
+                false
+            }
+        }
+    }
+
+    /// Count a failed sign-in against the throttle, then hold the answer back
+    /// as long as the failure streak calls for. The sleep doesn't hold a
+    /// hashing slot, so slowed guessers don't crowd out other logins.
+    async fn count_login_failure(&self, ctx: &RequestContext, username: &str) {
+        self.throttle.record_login_failure(&ctx.ip_address, username).await;
+        let delay = self.throttle.record_failure_streak(&ctx.ip_address, username).await;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Refuse a login the shared throttle state has already blocked, with the
+    /// same 429/423 the middleware would give
+    async fn check_rate_limit(&self, username: &str, ip_address: &str) -> AuthResult<()> {
+        // A lockdown cuts each address's failure budget
+        if self.lockdown.active().is_some()
+            && self.throttle.failures(ThrottleScope::Ip, ip_address).await >= LOCKDOWN_IP_FAILURE_LIMIT
+        {
+            return Err(AuthError::TooManyAttempts);
+        }
+        match self.throttle.check_login(ip_address, username).await {
+            Decision::Allow => Ok(()),
+            Decision::Throttle { .. } => Err(AuthError::TooManyAttempts),
+            Decision::Lock { .. } => Err(AuthError::AccountLocked),
+        }
+    }
+
+    /// Complete a login whose credentials all checked out, unless `rotation`
+    /// asks for a temporary or expired password to be changed first
+    async fn finish_login(
+        &self,
+        ctx: &RequestContext,
+        user: User,
+        session_id: String,
+        sign_out_others: bool,
+        client: &ClientApp,
+        rotation: PasswordRotation,
+    ) -> AuthResult<LoginResponse> {
+        let policy = self.policy_for(&user).await?;
+        let reason = if user.is_temporary_password {
+            Some("temporary")
+        } else if self.password_expired(&user, &policy) {
+            Some("expired")
+        } else {
+            None
+        };
+        let Some(reason) = reason.filter(|_| rotation == PasswordRotation::Inline) else {
+            return self.complete_login(ctx, user, session_id, self.default_token_lifetime(), sign_out_others, client).await;
+        };
+
+        let challenge = PasswordChangeChallenge {
+            user_id: user.id,
+            session_id,
+            client_id: client.id.clone(),
+            sign_out_other_sessions: sign_out_others,
+        };
+        let token = self.password_changes.issue(&challenge).await.map_err(|e| AuthError::InternalError(e.to_string()))?;
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::LoginPasswordChangeRequired,
+            &format!("Login for {} is waiting for a new password ({})", user.username, reason),
+            true,
+            Severity::Info,
+            Some(json!({ "username": user.username, "reason": reason, "client_id": client.id })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log password change challenge: {}", e));
+
+        let terms_accepted = self.terms_accepted(user.id).await?;
+        Ok(LoginResponse {
+            token: String::new(), // No token until the password is changed
+            user: UserResponse::from(user)
+                .with_two_fa_required(policy.require_two_fa)
+                .with_terms_accepted(terms_accepted),
+            expires_in: 0,
+            requires_two_fa: false,
+            two_fa_temp_token: None,
+            two_fa_method: None,
+            terms_accepted,
+            password_change_token: Some(token),
+            undismissed_alerts: 0,
+        })
+    }
+
+    /// Complete the login process (generate token and log)
+    async fn complete_login(
+        &self,
+        ctx: &RequestContext,
+        user: User,
+        session_id: String,
+        token_lifetime: Duration,
+        sign_out_others: bool,
+        client: &ClientApp,
+    ) -> AuthResult<LoginResponse> {
+        let others = self.sessions_to_sign_in_over(&user, sign_out_others).await?;
+
+        // Generate JWT token for the client app the login came from
+        let permissions = self.permissions.effective(&user).await?;
+        let issued = self.token_service.issue_token(&user, &session_id, permissions, token_lifetime, &client.token_audience)?;
+        let policy = self.policy_for(&user).await?;
+
+        // Other live sessions are ended unless the policy lets them run alongside this one
+        let session_expires_at = user
+            .session_expires_at
+            .unwrap_or_else(|| self.clock.now() + Duration::minutes(policy.session_timeout_minutes));
+        let keep_others = self.token_service.config().multiple_login_policy == MultipleLoginPolicy::Additional
+            && !sign_out_others;
+        if !keep_others {
+            self.sessions.revoke_all_for_user(user.id, None, "replaced").await?;
+        }
+        self.sessions
+            .create(ctx, user.id, &session_id, &issued.jti, &issued.kid, session_expires_at, self.lockdown.epoch(), &client.id)
+            .await?;
+        if !keep_others && !others.is_empty() {
+            self.report_sessions_replaced(ctx, &user, &others).await;
+        }
+
+        self.throttle.record_login_success(&ctx.ip_address, &user.username).await;
+
+        // Look up where the previous login came from before this one is recorded
+        let previous_fix = self.last_login_fix(user.id).await?;
+
+        // Record successful login
+        self.record_login_attempt(&LoginAttempt::new(
+            ctx,
+            Some(user.id),
+            &user.username,
+            true,
+            None,
+        )).await?;
+
+        // Log to audit service
+        self.audit_service.log_login_attempt(
+            ctx,
+            Some(user.id),
+            &user.username,
+            true,
+            None,
+        ).await.unwrap_or_else(|e| log::error!("Failed to log successful login: {}", e));
+
+        self.flag_unexpected_country(ctx, &user).await;
+        if let Some(previous_fix) = previous_fix.filter(|_| self.features.enabled(Feature::ImpossibleTravel)) {
+            self.flag_impossible_travel(ctx, &user, previous_fix).await;
+        }
+
+        let terms_accepted = self.terms_accepted(user.id).await?;
+        let undismissed_alerts = self.user_alerts.undismissed_count(user.id).await?;
+        Ok(LoginResponse {
+            token: issued.token,
+            user: UserResponse::from(user)
+                .with_permissions(permissions)
+                .with_two_fa_required(policy.require_two_fa)
+                .with_terms_accepted(terms_accepted),
+            expires_in: token_lifetime.num_seconds(),
+            requires_two_fa: false,
+            two_fa_temp_token: None,
+            two_fa_method: None,
+            terms_accepted,
+            password_change_token: None,
+            undismissed_alerts,
+        })
+    }
+
+    /// The account's live sessions a new login would sign in over. Under
+    /// `MultipleLoginPolicy::Deny` a live session refuses the login with
+    /// `SessionExists`, unless the client asked to sign the others out.
+    async fn sessions_to_sign_in_over(&self, user: &User, sign_out_others: bool) -> AuthResult<Vec<SessionRecord>> {
+        let mut live = self.sessions.live_for_user(user.id).await?;
+        if self.token_service.config().multiple_login_policy == MultipleLoginPolicy::Deny && !sign_out_others && !live.is_empty() {
+            // Newest first
+            return Err(AuthError::SessionExists(Box::new(live.swap_remove(0))));
+        }
+        Ok(live)
+    }
+
+    /// Record and tell the owner that a login ended their other sessions
+    async fn report_sessions_replaced(&self, ctx: &RequestContext, user: &User, replaced: &[SessionRecord]) {
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::SessionReplaced,
+            &format!("Login for user {} ended {} other session(s)", user.username, replaced.len()),
+            true,
+            Severity::Info,
+            Some(json!({
+                "replaced_sessions": replaced
+                    .iter()
+                    .map(|session| json!({
+                        "ip_address": session.ip_address,
+                        "user_agent": session.user_agent,
+                        "created_at": session.created_at.to_rfc3339(),
+                    }))
+                    .collect::<Vec<_>>(),
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log session replacement: {}", e));
+
+        self.notification_service
+            .notify_session_replaced(ctx, user.id, &user.username, &replaced[0])
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to queue session replaced notification: {}", e));
+    }
+
+    /// Lifetime of ordinary login tokens
+    fn default_token_lifetime(&self) -> Duration {
+        Duration::hours(self.token_service.config().jwt_expiration_hours)
+    }
+
+    /// Sign in to the break-glass account.
+    ///
+    /// Requires the server's activation flag and an unused credential. A
+    /// successful use burns the credential, raises a critical event, notifies
+    /// every administrator and grants a session of at most
+    /// `BREAK_GLASS_MAX_SESSION_MINUTES`.
+    async fn authenticate_break_glass(
+        &self,
+        ctx: &RequestContext,
+        mut user: User,
+        credential: BreakGlassCredential,
+        passphrase: &str,
+        client: &ClientApp,
+    ) -> AuthResult<LoginResponse> {
+        let refusal = match credential.passphrase_hash.as_deref() {
+            _ if !self.break_glass_enabled => Some("Break-glass access not activated"),
+            _ if !user.is_active => Some("Account deactivated"),
+            None => Some("Break-glass credential already used"),
+            Some(hash) => {
+                if !self.verify_login_password(passphrase, hash).await? {
+                    Some("Invalid passphrase")
+                } else if !self.break_glass.consume(user.id, hash).await? {
+                    // A concurrent login burned it between our read and now
+                    Some("Break-glass credential already used")
+                } else {
+                    None
+                }
+            }
+        };
+
+        if let Some(reason) = refusal {
+            self.record_login_attempt(&LoginAttempt::new(ctx, Some(user.id), &user.username, false, Some(reason)))
+                .await?;
+            self.audit_service.log_security_event(
+                ctx,
+                Some(user.id),
+                AuditEventType::BreakGlassRefused,
+                &format!("Break-glass sign-in refused for {}: {}", user.username, reason),
+                false,
+                Severity::Warning,
+                Some(json!({
+                    "username": user.username,
+                    "failure_reason": reason,
+                    "credential_used_at": credential.used_at.map(|at| at.to_rfc3339()),
+                })),
+            ).await.unwrap_or_else(|e| log::error!("Failed to log refused break-glass sign-in: {}", e));
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        // Capped regardless of configuration, for both the server session and the token
+        let cap = Duration::minutes(BREAK_GLASS_MAX_SESSION_MINUTES);
+        let config = self.token_service.config();
+        let session_lifetime = Duration::minutes(config.session_timeout_minutes).min(cap);
+        let token_lifetime = self.default_token_lifetime().min(cap);
+
+        let now = self.clock.now();
+        let session_id = TokenService::generate_session_id();
+        let session_expires_at = now + session_lifetime;
+        user.login_attempts = 0;
+        user.last_login = Some(now);
+        user.session_token = Some(session_id.clone());
+        user.session_expires_at = Some(session_expires_at);
+        self.update_user_security_info(&user).await?;
+
+        log::warn!(
+            "Break-glass credential provisioned at {} used from IP: {}; it is now disabled",
+            credential.provisioned_at.to_rfc3339(),
+            ctx.ip_address
+        );
+        self.audit_service
+            .log_break_glass_used(ctx, user.id, &user.username, session_expires_at)
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to log break-glass use: {}", e));
+
+        match self.admin_ids_except(user.id).await {
+            Ok(admin_ids) => self
+                .notification_service
+                .notify_break_glass_used(ctx, &admin_ids, &user.username, session_expires_at)
+                .await
+                .unwrap_or_else(|e| log::error!("Failed to queue break-glass notifications: {}", e)),
+            Err(e) => log::error!("Failed to look up administrators to notify of break-glass use: {}", e),
+        }
+
+        // Emergency access always takes over from any other session
+        self.complete_login(ctx, user, session_id, token_lifetime, true, client).await
+    }
+
+    /// Whether the account has a verified email address to send codes to
+    async fn has_verified_email(&self, user_id: Uuid) -> AuthResult<bool> {
+        let verified: Option<bool> =
+            sqlx::query_scalar("SELECT email IS NOT NULL AND email_verified_at IS NOT NULL FROM users WHERE id = ?")
+                .bind(user_id)
+                .fetch_optional(&self.db_pool)
+                .await?;
+        Ok(verified.unwrap_or(false))
+    }
+
+    /// Every active administrator account
+    async fn admin_ids(&self) -> AuthResult<Vec<Uuid>> {
+        sqlx::query_scalar("SELECT id FROM users WHERE role = ? AND is_active = TRUE")
+            .bind(UserRole::Admin.as_str())
+            .fetch_all(&self.db_pool)
+            .await
+            .map_err(AuthError::Database)
+    }
+
+    /// Request an emergency lockdown of `duration_minutes`. The first request
+    /// waits for a different administrator's; that second request starts it,
+    /// with the first one's reason and duration. From then on every session
+    /// created before it is refused and every sign-in takes a second factor,
+    /// until it runs out or is lifted. Each step is audited and every
+    /// administrator is told.
+    pub async fn request_lockdown(
+        &self,
+        ctx: &RequestContext,
+        admin_id: Uuid,
+        admin_username: &str,
+        reason: &str,
+        duration_minutes: i64,
+    ) -> AuthResult<LockdownRequest> {
+        let reason = sanitize::text(reason, self.audit_service.text_limits().text);
+        let outcome = self.lockdown.request(admin_id, &reason, duration_minutes).await?;
+        let reported = match &outcome {
+            LockdownRequest::Pending(lockdown) => Some((
+                lockdown,
+                AuditEventType::LockdownRequested,
+                format!("{} requested a {}-minute lockdown: {}", admin_username, lockdown.duration_minutes, lockdown.reason),
+            )),
+            LockdownRequest::Started(lockdown) => Some((
+                lockdown,
+                AuditEventType::LockdownStarted,
+                format!("{} approved the lockdown; every session was ended: {}", admin_username, lockdown.reason),
+            )),
+            LockdownRequest::AwaitingApproval(_) | LockdownRequest::AlreadyActive(_) => None,
+        };
+
+        if let Some((lockdown, event_type, description)) = reported {
+            log::warn!("{} from IP: {}", description, ctx.ip_address);
+            self.report_lockdown(ctx, Some(admin_id), admin_username, lockdown, event_type, &description).await;
+        }
+        Ok(outcome)
+    }
+
+    /// End the lockdown in force, or withdraw the request waiting for a
+    /// second administrator. Sessions the lockdown ended stay ended.
+    pub async fn lift_lockdown(&self, ctx: &RequestContext, admin_id: Uuid, admin_username: &str) -> AuthResult<Lockdown> {
+        let lockdown = self.lockdown.lift(admin_id).await?.ok_or(AuthError::LockdownNotFound)?;
+        let description = if lockdown.started_at.is_some() {
+            format!("{} ended the lockdown early: {}", admin_username, lockdown.reason)
+        } else {
+            format!("{} withdrew the lockdown request: {}", admin_username, lockdown.reason)
+        };
+
+        log::warn!("{} from IP: {}", description, ctx.ip_address);
+        self.report_lockdown(ctx, Some(admin_id), admin_username, &lockdown, AuditEventType::LockdownEnded, &description)
+            .await;
+        Ok(lockdown)
+    }
+
+    /// Record the end of lockdowns whose time is up. Run by every instance;
+    /// each lockdown is reported once.
+    pub async fn end_expired_lockdowns(&self, ctx: &RequestContext) -> AuthResult<usize> {
+        let finished = self.lockdown.finish_expired().await?;
+        for lockdown in &finished {
+            let description = format!("The lockdown ran out after {} minutes: {}", lockdown.duration_minutes, lockdown.reason);
+            log::warn!("{}", description);
+            self.report_lockdown(ctx, None, "system", lockdown, AuditEventType::LockdownEnded, &description).await;
+        }
+        Ok(finished.len())
+    }
+
+    /// Audit a lockdown step and tell every administrator about it. Failures
+    /// are logged; the step has already happened.
+    async fn report_lockdown(
+        &self,
+        ctx: &RequestContext,
+        admin_id: Option<Uuid>,
+        admin_username: &str,
+        lockdown: &Lockdown,
+        event_type: AuditEventType,
+        description: &str,
+    ) {
+        // An unapproved request withdrawn is worth a look, not an alarm
+        let severity = if lockdown.started_at.is_none() { Severity::Warning } else { event_type.default_severity() };
+        self.audit_service.log_security_event(
+            ctx,
+            admin_id,
+            event_type,
+            description,
+            true,
+            severity,
+            Some(json!({
+                "lockdown_id": lockdown.id,
+                "reason": lockdown.reason,
+                "duration_minutes": lockdown.duration_minutes,
+                "requested_by": lockdown.requested_by,
+                "approved_by": lockdown.approved_by,
+                "auth_epoch": lockdown.auth_epoch,
+                "started_at": lockdown.started_at.map(|at| at.to_rfc3339()),
+                "ends_at": lockdown.ends_at.map(|at| at.to_rfc3339()),
+                "ended_at": lockdown.ended_at.map(|at| at.to_rfc3339()),
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log lockdown change: {}", e));
+
+        match self.admin_ids().await {
+            Ok(admin_ids) => self
+                .notification_service
+                .notify_lockdown(&admin_ids, lockdown, admin_username)
+                .await
+                .unwrap_or_else(|e| log::error!("Failed to queue lockdown notifications: {}", e)),
+            Err(e) => log::error!("Failed to look up administrators to notify of the lockdown: {}", e),
+        }
+    }
+
+    /// Active administrator accounts other than `excluded`
+    async fn admin_ids_except(&self, excluded: Uuid) -> AuthResult<Vec<Uuid>> {
+        sqlx::query_scalar("SELECT id FROM users WHERE role = ? AND is_active = TRUE AND id != ?")
+            .bind(UserRole::Admin.as_str())
+            .bind(excluded)
+            .fetch_all(&self.db_pool)
+            .await
+            .map_err(AuthError::Database)
+    }
+
+    /// Create the break-glass account, or re-arm it after use, and return its
+    /// new one-time passphrase. Reachable only from the `--provision-break-glass`
+    /// command line flag; the passphrase is never stored in the clear.
+    pub async fn provision_break_glass(&self, ctx: &RequestContext) -> AuthResult<String> {
+        let (passphrase, passphrase_hash) = self.generate_break_glass_passphrase()?;
+
+        let user_id = match self.get_user_by_username(BREAK_GLASS_USERNAME).await {
+            Ok(user) => user.id,
+            Err(AuthError::InvalidCredentials) => {
+                // The account's own password is random and never shown; only the
+                // passphrase in the credential table can sign in
+                let (_, unusable_hash) = self.generate_break_glass_passphrase()?;
+                let user_id = Uuid::new_v4();
+                let now = self.clock.now();
+                sqlx::query(
+                    r#"
+                    INSERT INTO users (id, username, password_hash, role, is_temporary_password,
+                                     created_at, updated_at, login_attempts, is_locked, two_fa_enabled)
+                    VALUES (?, ?, ?, ?, FALSE, ?, ?, 0, FALSE, FALSE)
+                    "#
+                )
+                .bind(user_id)
+                .bind(BREAK_GLASS_USERNAME)
+                .bind(unusable_hash)
+                .bind(UserRole::Admin.as_str())
+                .bind(now)
+                .bind(now)
+                .execute(&self.db_pool)
+                .await
+                .map_err(AuthError::Database)?;
+                user_id
+            }
+            Err(e) => return Err(e),
+        };
+
+        self.break_glass.provision(user_id, &passphrase_hash).await?;
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user_id),
+            AuditEventType::BreakGlassProvisioned,
+            &format!("Break-glass credential provisioned for {}", BREAK_GLASS_USERNAME),
+            true,
+            Severity::Warning,
+            Some(json!({ "username": BREAK_GLASS_USERNAME })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log break-glass provisioning: {}", e));
+
+        Ok(passphrase)
+    }
+
+    /// A random passphrase and its hash, redrawing the rare draw that trips the
+    /// strength policy
+    fn generate_break_glass_passphrase(&self) -> AuthResult<(String, String)> {
+        loop {
+            let passphrase = format!(
+                "{}-{}",
+                self.password_service.generate_temporary_password(),
+                self.password_service.generate_temporary_password()
+            );
+            match self.password_service.hash_password(&passphrase) {
+                Ok(hash) => return Ok((passphrase, hash)),
+                Err(AuthError::PasswordTooWeak) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Raise a warning when a login resolves to a country outside the allowed list
+    async fn flag_unexpected_country(&self, ctx: &RequestContext, user: &User) {
+        let Some(country_code) = ctx.ip.and_then(|ip| self.geoip.lookup_ip(ip)).and_then(|location| location.country_code) else {
+            return;
+        };
+        if self.geoip.is_country_allowed(&country_code) {
+            return;
+        }
+
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::LoginFromUnexpectedCountry,
+            &format!("Login for user {} from outside the allowed countries ({})", user.username, country_code),
+            true,
+            Severity::Warning,
+            Some(json!({
+                "username": user.username,
+                "country_code": country_code,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log unexpected login country: {}", e));
+    }
+
+    /// Position and time of the user's most recent successful login with known coordinates
+    async fn last_login_fix(&self, user_id: Uuid) -> AuthResult<Option<GeoFix>> {
+        let row: Option<(f64, f64, DateTime<Utc>)> = sqlx::query_as(
+            r#"
+            SELECT latitude, longitude, timestamp
+            FROM login_attempts
+            WHERE user_id = ? AND success = TRUE
+              AND latitude IS NOT NULL AND longitude IS NOT NULL
+            ORDER BY timestamp DESC
+            LIMIT 1
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(AuthError::Database)?;
+
+        Ok(row.map(|(latitude, longitude, timestamp)| GeoFix { latitude, longitude, timestamp }))
+    }
+
+    /// Raise a critical alert when this login is implausibly far from the previous one.
+    /// Logins without coordinates are skipped.
+    async fn flag_impossible_travel(&self, ctx: &RequestContext, user: &User, previous: GeoFix) {
+        let Some(location) = ctx.ip.and_then(|ip| self.geoip.lookup_ip(ip)) else {
+            return;
+        };
+        let (Some(latitude), Some(longitude)) = (location.latitude, location.longitude) else {
+            return;
+        };
+        let current = GeoFix { latitude, longitude, timestamp: ctx.received_at };
+
+        if !self.geoip.is_impossible_travel(&previous, &current) {
+            return;
+        }
+
+        let distance_km = previous.distance_km(&current);
+        let speed_kmh = previous.implied_speed_kmh(&current);
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::ImpossibleTravel,
+            &format!(
+                "Login for user {} is {:.0} km from the previous login, implying {:.0} km/h",
+                user.username, distance_km, speed_kmh
+            ),
+            true,
+            Severity::Critical,
+            Some(json!({
+                "username": user.username,
+                "distance_km": distance_km.round(),
+                // Infinity isn't valid JSON; back-to-back logins report null
+                "speed_kmh": speed_kmh.is_finite().then(|| speed_kmh.round()),
+                "previous_login_at": previous.timestamp.to_rfc3339(),
+                "previous_latitude": previous.latitude,
+                "previous_longitude": previous.longitude,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log impossible travel: {}", e));
+    }
+
+    /// Use up `backup_code` if the account holds it; false if not. `stored`
+    /// is the code list as the caller read it. The list is only replaced
+    /// while it is still the one the code was checked against; if another
+    /// sign-in used a code first, the code is checked again against what
+    /// that left. Parallel sign-ins with different codes both succeed, and a
+    /// used code never verifies again.
+    async fn consume_backup_code(&self, user_id: Uuid, stored: &str, backup_code: &str) -> AuthResult<bool> {
+        let mut stored = stored.to_string();
+        for _ in 0..BACKUP_CODE_CONSUME_ATTEMPTS {
+            let (is_valid, remaining) = self.two_fa_service.verify_backup_code(&stored, backup_code)?;
+            if !is_valid {
+                return Ok(false);
+            }
+
+            let now = self.clock.now();
+            let replaced = self
+                .busy_retry
+                .run(|| {
+                    sqlx::query(
+                        r#"
+                        UPDATE users SET two_fa_backup_codes = ?, updated_at = ?
+                        WHERE id = ? AND two_fa_backup_codes = ?
+                        "#,
+                    )
+                    .bind(&remaining)
+                    .bind(now)
+                    .bind(user_id)
+                    .bind(&stored)
+                    .execute(&self.db_pool)
+                })
+                .await
+                .map_err(AuthError::Database)?
+                .rows_affected();
+            if replaced == 1 {
+                return Ok(true);
+            }
+
+            // The re-read can meet the same busy database as the write it follows
+            let current = self
+                .busy_retry
+                .run(|| {
+                    sqlx::query_scalar::<_, Option<String>>(
+                        "SELECT two_fa_backup_codes FROM users WHERE id = ? AND two_fa_enabled = TRUE",
+                    )
+                    .bind(user_id)
+                    .fetch_optional(&self.db_pool)
+                })
+                .await
+                .map_err(AuthError::Database)?
+                .flatten();
+            match current {
+                Some(current) => stored = current,
+                // 2FA was turned off meanwhile
+                None => return Ok(false),
+            }
+        }
+
+        // Every attempt lost to another sign-in; refusing is safe, using the code twice is not
+        log::warn!(
+            "Gave up using a backup code for user {} after {} conflicting writes",
+            user_id,
+            BACKUP_CODE_CONSUME_ATTEMPTS
+        );
+        Ok(false)
+    }
+
+    /// Prepare 2FA setup - generates secret and QR code
+    pub async fn prepare_two_fa_setup(&self, user_id: Uuid) -> AuthResult<TwoFASetupResponse> {
+        self.operation_timings.measure(Operation::PrepareTwoFaSetup, self.prepare_two_fa_setup_phases(user_id)).await
+    }
+
+    async fn prepare_two_fa_setup_phases(&self, user_id: Uuid) -> AuthResult<TwoFASetupResponse> {
+        let user = phase_timings::timed(Phase::Db, self.get_user_by_id(user_id)).await?;
+        
+        // Generate secret and backup codes
+        let secret = self.two_fa_service.generate_secret();
+        let backup_codes = self.two_fa_service.generate_backup_codes(10);
+        
+        // Generate QR code
+        let qr_code = self.two_fa_service.generate_qr_code(&user.username, &secret)?;
+
+        // Keep the secret pending until setup confirms a code generated from it;
+        // an account that already has 2FA keeps its active secret
+        let _db = phase_timings::phase(Phase::Db);
+        self.busy_retry
+            .run(|| {
+                sqlx::query("UPDATE users SET two_fa_secret = ?, updated_at = ? WHERE id = ? AND two_fa_enabled = FALSE")
+                    .bind(&secret)
+                    .bind(self.clock.now())
+                    .bind(user_id)
+                    .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+
+        Ok(TwoFASetupResponse {
+            secret,
+            qr_code,
+            backup_codes,
+            enabled: false, // Not enabled yet, just prepared
+            session: None,
+        })
+    }
+
+    /// Set up 2FA for user - verifies a TOTP code against the prepared secret,
+    /// enables 2FA and replaces the caller's session with a fresh one
+    pub async fn setup_two_fa(&self, user_id: Uuid, request: TwoFASetupRequest) -> AuthResult<TwoFASetupResponse> {
+        self.operation_timings.measure(Operation::SetupTwoFa, self.setup_two_fa_phases(user_id, request)).await
+    }
+
+    async fn setup_two_fa_phases(&self, user_id: Uuid, request: TwoFASetupRequest) -> AuthResult<TwoFASetupResponse> {
+        let user = phase_timings::timed(Phase::Db, self.get_user_by_id(user_id)).await?;
+
+        // The secret comes from prepare_two_fa_setup
+        let secret = match (&user.two_fa_secret, user.two_fa_enabled) {
+            (Some(secret), false) => secret.clone(),
+            _ => return Err(AuthError::InvalidCredentials),
+        };
+        self.two_fa_service.check_secret_strength(&secret)?;
+        let backup_codes = self.two_fa_service.generate_backup_codes(10);
+
+        // Verify the provided TOTP code against the prepared secret
+        let is_valid = {
+            let _hashing = phase_timings::phase(Phase::Hashing);
+            self.two_fa_service.verify_totp(&secret, &request.totp_code)?
+        };
+        if !is_valid {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        // One authenticator may back only one account
+        let fingerprint = {
+            let _hashing = phase_timings::phase(Phase::Hashing);
+            self.two_fa_service.fingerprint_secret(&secret)?
+        };
+        let enrolled_elsewhere: Option<i64> = phase_timings::timed(
+            Phase::Db,
+            sqlx::query_scalar("SELECT 1 FROM users WHERE two_fa_fingerprint = ? AND id != ?")
+                .bind(&fingerprint)
+                .bind(user_id)
+                .fetch_optional(&self.db_pool),
+        )
+        .await
+        .map_err(AuthError::Database)?;
+        if enrolled_elsewhere.is_some() {
+            return Err(AuthError::TwoFactorSecretInUse);
+        }
+
+        // Generate QR code
+        let qr_code = self.two_fa_service.generate_qr_code(&user.username, &secret)?;
+        
+        // Hash backup codes for storage
+        let backup_codes_json = {
+            let _hashing = phase_timings::phase(Phase::Hashing);
+            self.two_fa_service.hash_backup_codes(&backup_codes)?
+        };
+        
+        // Update user in database
+        let _db = phase_timings::phase(Phase::Db);
+        let now = self.clock.now();
+        self.busy_retry
+            .run(|| {
+                sqlx::query(
+                    r#"
+                    UPDATE users 
+                    SET two_fa_enabled = ?, two_fa_secret = ?, two_fa_backup_codes = ?, 
+                        two_fa_enabled_at = ?, two_fa_fingerprint = ?, updated_at = ?
+                    WHERE id = ?
+                    "#,
+                )
+                .bind(true)
+                .bind(&secret)
+                .bind(&backup_codes_json)
+                .bind(now)
+                .bind(&fingerprint)
+                .bind(now)
+                .bind(user_id)
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(|e| match &e {
+                // Lost a race with another enrollment of the same secret
+                sqlx::Error::Database(db_error) if db_error.is_unique_violation() => AuthError::TwoFactorSecretInUse,
+                _ => AuthError::Database(e),
+            })?;
+
+        let session = self.rotate_session(&user, Rotation::Account).await?;
+
+        Ok(TwoFASetupResponse {
+            secret,
+            qr_code,
+            backup_codes,
+            enabled: true,
+            session: Some(session),
+        })
+    }
+
+    /// Verify 2FA code during login
+    pub async fn verify_two_fa(&self, ctx: &RequestContext, request: TwoFAVerifyRequest) -> AuthResult<LoginResponse> {
+        let code = request.totp_code.parse::<TwoFactorCode>().map_err(|_| AuthError::InvalidCredentials)?;
+        self.complete_two_factor_login(ctx, &request.temp_token, code, PasswordRotation::AfterSignIn).await
+    }
+
+    /// Second step of a 2FA login: answer the challenge `authenticate`
+    /// handed out with an authenticator or backup code, or with the emailed
+    /// code of a sign-in during a lockdown. The password isn't
+    /// asked for again. Unknown, expired, used or superseded challenges are
+    /// `InvalidToken`; a wrong code is `InvalidCredentials`.
+    pub async fn complete_two_factor_login(
+        &self,
+        ctx: &RequestContext,
+        challenge_token: &str,
+        code: TwoFactorCode,
+        rotation: PasswordRotation,
+    ) -> AuthResult<LoginResponse> {
+        let _span = tracer::span("AuthService::complete_two_factor_login");
+        if !self.two_fa_service.validate_temp_token(challenge_token) {
+            return Err(AuthError::InvalidToken);
+        }
+        let digest = self.two_fa_service.temp_token_digest(challenge_token)?;
+        let Some(challenge) = self.login_challenges.find_open(&digest).await? else {
+            return Err(AuthError::InvalidToken);
+        };
+
+        let user = self.get_user_by_id(challenge.user_id).await?;
+        self.check_rate_limit(&user.username, &ctx.ip_address).await?;
+        if user.is_locked_at(self.clock.now()) {
+            return Err(AuthError::AccountLocked);
+        }
+        if !user.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+        // A later password login on the account set up a session of its own.
+        // Without 2FA only a lockdown sign-in's emailed code can answer.
+        let can_answer = user.two_fa_enabled || challenge.email_code_digest.is_some();
+        if !can_answer || user.session_token.as_deref() != Some(challenge.session_id.as_str()) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        let (is_valid, code_type) = match (&challenge.email_code_digest, &code) {
+            (Some(expected), TwoFactorCode::Totp(emailed)) => {
+                (ct_eq_str(&self.two_fa_service.email_code_digest(emailed)?, expected), "email")
+            }
+            (Some(_), TwoFactorCode::Backup(_)) => (false, "email"),
+            (None, _) => (self.verify_two_factor_code(&user, &code).await?, code.kind()),
+        };
+        self.audit_service
+            .log_two_fa_attempt(ctx, user.id, &user.username, code_type, is_valid)
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to log 2FA attempt: {}", e));
+
+        if !is_valid {
+            self.login_challenges.record_failed_code(&challenge).await?;
+            self.record_login_attempt(&LoginAttempt::new(
+                ctx,
+                Some(user.id),
+                &user.username,
+                false,
+                Some("Invalid 2FA code"),
+            )).await?;
+            if !self.lockout_exempt(ctx, &user).await {
+                self.count_login_failure(ctx, &user.username).await;
+            }
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        // Two requests racing with the same code: only one signs in
+        if !self.login_challenges.consume(&challenge).await? {
+            return Err(AuthError::InvalidToken);
+        }
+
+        // An app disabled since the password step signs in as the default app
+        let client = self.clients.resolve(Some(&challenge.client_id)).0;
+        self.finish_login(ctx, user, challenge.session_id, challenge.sign_out_other_sessions, &client, rotation).await
+    }
+
+    /// Last step of a v2 login stopped for its password: re-check the current
+    /// password, set the new one and sign in. A new password that is refused
+    /// leaves the challenge open for another try; unknown, expired, used or
+    /// superseded challenges are `InvalidToken`.
+    pub async fn complete_password_change_login(
+        &self,
+        ctx: &RequestContext,
+        request: LoginPasswordChangeRequest,
+    ) -> AuthResult<LoginResponse> {
+        let Some(challenge) = self
+            .password_changes
+            .open(&request.challenge_token)
+            .await
+            .map_err(|e| AuthError::InternalError(e.to_string()))?
+        else {
+            return Err(AuthError::InvalidToken);
+        };
+
+        let user = self.get_user_by_id(challenge.user_id).await?;
+        self.check_rate_limit(&user.username, &ctx.ip_address).await?;
+        if user.is_locked_at(self.clock.now()) {
+            return Err(AuthError::AccountLocked);
+        }
+        if !user.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+        // A later login on the account set up a session of its own
+        if user.session_token.as_deref() != Some(challenge.session_id.as_str()) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        if !self.verify_login_password(&request.current_password, &user.password_hash).await? {
+            self.record_login_attempt(&LoginAttempt::new(
+                ctx,
+                Some(user.id),
+                &user.username,
+                false,
+                Some("Invalid password"),
+            )).await?;
+            if !self.lockout_exempt(ctx, &user).await {
+                self.count_login_failure(ctx, &user.username).await;
+            }
+            return Err(AuthError::InvalidCredentials);
+        }
+        if request.new_password != request.confirm_password {
+            return Err(AuthError::PasswordMismatch);
+        }
+        self.password_service.validate_password_strength(&request.new_password)?;
+        if self.password_service.passwords_are_same(&request.new_password, &user.password_hash) {
+            return Err(AuthError::PasswordReused);
+        }
+        let new_password_hash = self.password_service.hash_password(&request.new_password)?;
+
+        // Two requests racing with the same challenge: only one changes the password
+        if !self.password_changes.consume(&challenge).await.map_err(|e| AuthError::InternalError(e.to_string()))? {
+            return Err(AuthError::InvalidToken);
+        }
+        self.update_user_password(user.id, &new_password_hash).await?;
+        self.revoke_user_artifacts(ctx, user.id, RevocationScope::PasswordChanged).await?;
+
+        self.audit_service
+            .log_password_change(ctx, user.id, &user.username, true, user.is_temporary_password)
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to log password change: {}", e));
+        self.audit_service.log_security_event(
+            ctx,
+            Some(user.id),
+            AuditEventType::LoginPasswordChanged,
+            &format!("{} changed their password while signing in", user.username),
+            true,
+            Severity::Info,
+            Some(json!({
+                "username": user.username,
+                "was_temporary_password": user.is_temporary_password,
+                "client_id": challenge.client_id,
+            })),
+        ).await.unwrap_or_else(|e| log::error!("Failed to log password change at login: {}", e));
+
+        // An app disabled since the password step signs in as the default app
+        let client = self.clients.resolve(Some(&challenge.client_id)).0;
+        let user = self.get_user_by_id(user.id).await?;
+        self.complete_login(
+            ctx,
+            user,
+            challenge.session_id,
+            self.default_token_lifetime(),
+            challenge.sign_out_other_sessions,
+            &client,
+        ).await
+    }
+
+    /// Check a second-factor code against the account. A matching backup code
+    /// is used up.
+    async fn verify_two_factor_code(&self, user: &User, code: &TwoFactorCode) -> AuthResult<bool> {
+        match code {
+            TwoFactorCode::Totp(totp_code) => match &user.two_fa_secret {
+                Some(secret) => self.two_fa_service.verify_totp(secret, totp_code),
+                None => Ok(false),
+            },
+            TwoFactorCode::Backup(backup_code) => {
+                let Some(backup_codes) = &user.two_fa_backup_codes else {
+                    return Ok(false);
+                };
+                self.consume_backup_code(user.id, backup_codes, backup_code).await
+            }
+        }
+    }
+
+    /// Show the QR code of the account's active 2FA secret again, for adding
+    /// another device. Needs the password and a current TOTP code, changes
+    /// nothing, and is allowed `TWO_FA_QR_REDISPLAYS_PER_HOUR` times an hour.
+    pub async fn redisplay_two_fa_qr(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        request: TwoFAQrRequest,
+    ) -> AuthResult<TwoFAQrResponse> {
+        let user = self.get_user_by_id(user_id).await?;
+
+        let shown = self
+            .audit_service
+            .count_user_successes_since(user_id, AuditEventType::TwoFaQrRedisplayed, self.clock.now() - Duration::hours(1))
+            .await?;
+        if shown >= TWO_FA_QR_REDISPLAYS_PER_HOUR {
+            return Err(AuthError::TwoFactorQrLimitReached);
+        }
+
+        let mut verified = self.verify_login_password(&request.password, &user.password_hash).await?;
+        if verified {
+            let secret = match &user.two_fa_secret {
+                Some(secret) if user.two_fa_enabled && self.two_fa_service.is_usable_secret(secret) => secret,
+                _ => return Err(AuthError::TwoFactorReenrollmentRequired),
+            };
+            verified = self.two_fa_service.verify_totp(secret, &request.totp_code)?;
+        }
+
+        self.audit_service
+            .log_two_fa_qr_redisplayed(ctx, user_id, &user.username, verified)
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to log 2FA QR redisplay: {}", e));
+
+        let secret = match (verified, &user.two_fa_secret) {
+            (true, Some(secret)) => secret,
+            _ => {
+                // Answered with 403, which the rate limiting middleware doesn't count
+                self.throttle.record_failure(ThrottleScope::Ip, &ctx.ip_address).await;
+                return Err(AuthError::InvalidCredentials);
+            }
+        };
+        Ok(TwoFAQrResponse {
+            otpauth_url: self.two_fa_service.otpauth_url(&user.username, secret),
+            qr_code: self.two_fa_service.generate_qr_code(&user.username, secret)?,
+        })
+    }
+
+    /// Disable 2FA for user
+    pub async fn disable_two_fa(&self, ctx: &RequestContext, user_id: Uuid, request: TwoFADisableRequest) -> AuthResult<()> {
+        let user = self.get_user_by_id(user_id).await?;
+        
+        // Verify password
+        let password_valid = self.password_service.verify_password(&request.password, &user.password_hash)?;
+        if !password_valid {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        if !self.verify_two_factor_code(&user, &request.two_fa_code).await? {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        // Disable 2FA in database
+        let now = self.clock.now();
+        self.busy_retry
+            .run(|| {
+                sqlx::query!(
+                    r#"
+                    UPDATE users 
+                    SET two_fa_enabled = ?, two_fa_secret = NULL, two_fa_backup_codes = NULL,
+                        two_fa_enabled_at = NULL, two_fa_fingerprint = NULL, updated_at = ?
+                    WHERE id = ?
+                    "#,
+                    false,
+                    now,
+                    user_id
+                )
+                .execute(&self.db_pool)
+            })
+            .await
+            .map_err(AuthError::Database)?;
+        self.revoke_user_artifacts(ctx, user_id, RevocationScope::TwoFaDisabled).await?;
+
+        self.audit_service.log_two_fa_disabled(ctx, user_id, &user.username, request.two_fa_code.kind())
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to log 2FA disable: {}", e));
+        self.notification_service
+            .notify_two_fa_disabled(ctx, user_id, &user.username)
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to queue 2FA disabled notification: {}", e));
+
+        Ok(())
+    }
+}
+
+/// Audit details identifying a personal data export
+fn data_export_details(export: &DataExport) -> serde_json::Value {
+    json!({
+        "export_id": export.id,
+        "subject_user_id": export.user_id,
+        "requested_by": export.requested_by,
+        "size_bytes": export.size_bytes,
+        "expires_at": export.expires_at.to_rfc3339(),
+    })
+}
+
+/// Audit details identifying a password reset token, never the token itself
+fn password_reset_details(reset: &PasswordReset) -> serde_json::Value {
+    json!({
+        "reset_id": reset.id,
+        "user_id": reset.user_id,
+        "channel": reset.channel,
+        "issued_by": reset.issued_by,
+        "expires_at": reset.expires_at.to_rfc3339(),
+    })
+}
+
+/// Audit details identifying a pending admin action
+fn action_details(pending: &PendingAction) -> serde_json::Value {
+    json!({
+        "action_id": pending.id,
+        "action": pending.action,
+        "target_user_id": pending.target_user_id,
+        "approver_id": pending.admin_id,
+        "requested_by": pending.requested_by,
+        "expires_at": pending.expires_at.to_rfc3339(),
+    })
+}
+
+/// Round a timestamp up to the next whole minute, for "unlocks by HH:MM" messages
+fn round_up_to_minute(timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    let seconds = timestamp.timestamp();
+    let rounded = if seconds % 60 == 0 && timestamp.timestamp_subsec_nanos() == 0 {
+        seconds
+    } else {
+        seconds - seconds.rem_euclid(60) + 60
+    };
+    DateTime::from_timestamp(rounded, 0).unwrap_or(timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth::{SecurityConfig, MAX_USER_AGENT_LENGTH};
+    use crate::models::pagination::ListParams;
+    use crate::models::permission::Permission;
+    use crate::services::audit_service::AuditFilter;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::clock::SystemClock;
+    use crate::utils::database::test_pool;
+
+    async fn test_service() -> AuthService {
+        service_with_geoip(GeoIpService::disabled()).await
+    }
+
+    async fn service_with_geoip(geoip: GeoIpService) -> AuthService {
+        AuthService::new(
+            test_pool().await,
+            PasswordService::new(),
+            TokenService::new(SecurityConfig::default(), Arc::new(SystemClock)),
+            Arc::new(geoip),
+            Arc::new(SystemClock),
+        )
+    }
+
+    async fn service_with_config(config: SecurityConfig) -> AuthService {
+        AuthService::new(
+            test_pool().await,
+            PasswordService::new(),
+            TokenService::new(config, Arc::new(SystemClock)),
+            Arc::new(GeoIpService::disabled()),
+            Arc::new(SystemClock),
+        )
+    }
+
+    async fn create_user(service: &AuthService, username: &str) -> Uuid {
+        insert_user(&service.db_pool, service.clock.clone(), username, UserRole::KenyaGovernment, TEST_PASSWORD, false)
+            .await
+            .id
+    }
+
+    fn login_request(username: &str, password: &str) -> LoginRequest {
+        LoginRequest {
+            username: username.to_string(),
+            password: password.to_string(),
+            two_fa_code: None,
+            website: None,
+            form_issued_at: None,
+            sign_out_other_sessions: false,
+            client_id: None,
+        }
+    }
+
+    fn client(ip_address: &str, user_agent: Option<&str>) -> RequestContext {
+        RequestContext::new(ip_address, user_agent)
+    }
+
+    async fn stored_attempts(service: &AuthService, username: &str) -> Vec<LoginAttempt> {
+        sqlx::query_as::<_, LoginAttempt>(
+            "SELECT user_id, username, ip_address, user_agent, success, timestamp, failure_reason,
+                    country_code, region, city, asn, asn_org, latitude, longitude
+             FROM login_attempts WHERE username = ? ORDER BY timestamp",
+        )
+        .bind(username)
+        .fetch_all(&service.db_pool)
+        .await
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_failed_login_records_user_agent() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "ua_user").await;
+
+        let result = service
+            .authenticate(&client("10.0.0.1", Some("curl/8.4.0")), login_request("ua_user", "WrongPassw0rd!!"))
+            .await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        let attempts = stored_attempts(&service, "ua_user").await;
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].user_id, Some(user_id));
+        assert_eq!(attempts[0].user_agent.as_deref(), Some("curl/8.4.0"));
+        assert_eq!(attempts[0].failure_reason.as_deref(), Some("Invalid password"));
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_user_attempt_records_user_agent() {
+        let service = test_service().await;
+
+        let result = service
+            .authenticate(&client("10.0.0.1", Some("python-requests/2.31")), login_request("ghost_user", TEST_PASSWORD))
+            .await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        let attempts = stored_attempts(&service, "ghost_user").await;
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].user_id, None);
+        assert_eq!(attempts[0].user_agent.as_deref(), Some("python-requests/2.31"));
+        assert_eq!(attempts[0].failure_reason.as_deref(), Some("Unknown user"));
+    }
+
+    #[actix_web::test]
+    async fn test_long_user_agent_is_truncated() {
+        let service = test_service().await;
+        create_user(&service, "long_ua_user").await;
+        let huge_user_agent = "A".repeat(10_000);
+
+        let _ = service
+            .authenticate(&client("10.0.0.1", Some(&huge_user_agent)), login_request("long_ua_user", "WrongPassw0rd!!"))
+            .await;
+
+        let attempts = stored_attempts(&service, "long_ua_user").await;
+        assert_eq!(attempts[0].user_agent.as_ref().map(|ua| ua.len()), Some(MAX_USER_AGENT_LENGTH));
+    }
+
+    #[actix_web::test]
+    async fn test_login_history_includes_user_agent() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "history_user").await;
+
+        service
+            .authenticate(&client("10.0.0.1", Some("Mozilla/5.0 (X11; Linux)")), login_request("history_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        let history = service.get_login_history(user_id, 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].success);
+        assert_eq!(history[0].ip_address, "10.0.0.1");
+        assert_eq!(history[0].user_agent.as_deref(), Some("Mozilla/5.0 (X11; Linux)"));
+    }
+
+    #[actix_web::test]
+    async fn test_locking_user_revokes_live_session() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "locked_mid_session").await;
+
+        let login = service
+            .authenticate(&client("10.0.0.1", None), login_request("locked_mid_session", TEST_PASSWORD))
+            .await
+            .unwrap();
+        assert!(service.validate_session(&login.token).await.is_ok());
+
+        service.lock_user(&client("10.0.0.1", None), user_id, None).await.unwrap();
+
+        let result = service.validate_session(&login.token).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+
+        // Unlocking does not resurrect the revoked session
+        service.unlock_user(user_id).await.unwrap();
+        let result = service.validate_session(&login.token).await;
+        assert!(matches!(result, Err(AuthError::SessionExpired)));
+    }
+
+    #[actix_web::test]
+    async fn test_deactivated_user_is_rejected() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "deactivated_user").await;
+
+        let login = service
+            .authenticate(&client("10.0.0.1", None), login_request("deactivated_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        service.set_user_active(user_id, false).await.unwrap();
+
+        let result = service.validate_session(&login.token).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+
+        let result = service
+            .authenticate(&client("10.0.0.1", None), login_request("deactivated_user", TEST_PASSWORD))
+            .await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+    }
+
+    #[actix_web::test]
+    async fn test_failed_login_lockout_clears_session() {
+        let service = test_service().await;
+        create_user(&service, "brute_forced_user").await;
+
+        let login = service
+            .authenticate(&client("10.0.0.1", None), login_request("brute_forced_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        for _ in 0..5 {
+            let _ = service
+                .authenticate(&client("10.0.0.9", None), login_request("brute_forced_user", "WrongPassw0rd!!"))
+                .await;
+        }
+
+        let result = service.validate_session(&login.token).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+
+        let user = service.get_user_by_username("brute_forced_user").await.unwrap();
+        assert!(user.session_token.is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_lockout_notifies_owner_once_per_episode() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "notified_user").await;
+
+        // Five failures lock the account; the rest arrive while it's locked
+        for _ in 0..8 {
+            let _ = service
+                .authenticate(&client("197.232.61.4", None), login_request("notified_user", "WrongPassw0rd!!"))
+                .await;
+        }
+
+        // The failures so far go out as a digest alongside the lockout notice
+        let pending = service.notification_service.pending_for_user(user_id).await.unwrap();
+        assert_eq!(pending.iter().map(|n| n.kind.as_str()).collect::<Vec<_>>(), vec!["FAILED_LOGIN_DIGEST", "ACCOUNT_LOCKED"]);
+        let digest: serde_json::Value = serde_json::from_str(pending[0].metadata.as_deref().unwrap()).unwrap();
+        assert_eq!(digest["attempts"], 5);
+        assert_eq!(digest["trigger"], "locked");
+        assert!(pending[1].message.contains("197.232.61.4"));
+        let metadata: serde_json::Value = serde_json::from_str(pending[1].metadata.as_deref().unwrap()).unwrap();
+        assert!(metadata["locked_until"].is_string());
+
+        let lockout_events: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM security_events WHERE event_type = 'ACCOUNT_LOCKOUT' AND user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_one(&service.db_pool)
+        .await
+        .unwrap();
+        assert_eq!(lockout_events, 1);
+
+        // Racing requests can't both claim the transition into a live lock
+        let until = Utc::now() + Duration::minutes(5);
+        assert!(!service.lock_after_failed_logins(user_id, until).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_parallel_failures_are_all_counted() {
+        // Room for every attempt in the hashing queue, so all of them reach the password check
+        let service = service_with_config(SecurityConfig { max_failed_attempts: 10, ..SecurityConfig::default() })
+            .await
+            .with_login_concurrency(10);
+        let user_id = create_user(&service, "raced_user").await;
+        let ctx = client("10.0.0.9", None);
+
+        let attempts = (0..10).map(|_| service.authenticate(&ctx, login_request("raced_user", "WrongPassw0rd!!")));
+        for result in futures_util::future::join_all(attempts).await {
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        }
+
+        let user = service.get_user_by_id(user_id).await.unwrap();
+        assert_eq!(user.login_attempts, 10);
+        assert!(user.is_locked_at(Utc::now()));
+        let pending = service.notification_service.pending_for_user(user_id).await.unwrap();
+        assert_eq!(pending.iter().filter(|n| n.kind == "ACCOUNT_LOCKED").count(), 1);
+    }
+
+    async fn clocked_service(config: SecurityConfig) -> (AuthService, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new());
+        let service = AuthService::new(
+            test_pool().await,
+            PasswordService::new(),
+            TokenService::new(config, clock.clone()),
+            Arc::new(GeoIpService::disabled()),
+            clock.clone(),
+        );
+        (service, clock)
+    }
+
+    async fn digest_events(service: &AuthService) -> Vec<serde_json::Value> {
+        let events = service.audit_service.get_recent_events(50, false, None).await.unwrap();
+        events
+            .into_iter()
+            .filter(|e| e.event_type == "NOTIFICATION_DIGEST_SENT")
+            .map(|e| e.details.unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_failed_login_burst_is_sent_as_one_digest_once_quiet() {
+        let (service, clock) = clocked_service(SecurityConfig { max_failed_attempts: 50, ..SecurityConfig::default() }).await;
+        let service = service.with_failed_login_digest(15);
+        let user_id = create_user(&service, "burst_user").await;
+
+        for i in 0..17 {
+            let ip = ["197.232.61.4", "197.232.61.5", "41.90.12.7"][i % 3];
+            let result = service.authenticate(&client(ip, None), login_request("burst_user", "WrongPassw0rd!!")).await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+            clock.advance(Duration::seconds(50));
+        }
+        assert!(service.notification_service.pending_for_user(user_id).await.unwrap().is_empty());
+
+        // Not quiet yet: the last failure was under 15 minutes ago
+        clock.advance(Duration::minutes(10));
+        assert_eq!(service.flush_quiet_failed_login_digests().await, 0);
+        clock.advance(Duration::minutes(5));
+        assert_eq!(service.flush_quiet_failed_login_digests().await, 1);
+        assert_eq!(service.flush_quiet_failed_login_digests().await, 0);
+
+        let pending = service.notification_service.pending_for_user(user_id).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].kind, "FAILED_LOGIN_DIGEST");
+        assert!(pending[0].message.starts_with("17 failed sign-in attempts to your account burst_user from 3 IP addresses between"));
+        let metadata: serde_json::Value = serde_json::from_str(pending[0].metadata.as_deref().unwrap()).unwrap();
+        assert_eq!(metadata["attempts"], 17);
+        assert_eq!(metadata["ip_addresses"], json!(["197.232.61.4", "197.232.61.5", "41.90.12.7"]));
+
+        let events = digest_events(&service).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["attempts"], 17);
+        assert_eq!(events[0]["trigger"], "quiet_period");
+    }
+
+    #[actix_web::test]
+    async fn test_replace_policy_ends_the_other_session_and_tells_the_owner() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "replaced_user").await;
+
+        let first = service.authenticate(&client("10.0.0.1", Some("Firefox")), login_request("replaced_user", TEST_PASSWORD)).await.unwrap();
+        assert!(service.notification_service.pending_for_user(user_id).await.unwrap().is_empty());
+        let second = service.authenticate(&client("10.0.0.2", None), login_request("replaced_user", TEST_PASSWORD)).await.unwrap();
+
+        assert!(matches!(service.validate_session(&first.token).await, Err(AuthError::SessionExpired)));
+        assert!(service.validate_session(&second.token).await.is_ok());
+
+        let pending = service.notification_service.pending_for_user(user_id).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].kind, "SESSION_REPLACED");
+        let events = service.audit_service.get_recent_events(50, false, None).await.unwrap();
+        let replaced = events.iter().find(|e| e.event_type == "SESSION_REPLACED").unwrap();
+        assert_eq!(replaced.user_id, Some(user_id));
+        let details = replaced.details.as_ref().unwrap();
+        assert_eq!(details["replaced_sessions"][0]["ip_address"], "10.0.0.1");
+        assert_eq!(details["replaced_sessions"][0]["user_agent"], "Firefox");
+    }
+
+    #[actix_web::test]
+    async fn test_additional_policy_keeps_both_sessions_unless_asked() {
+        let service = service_with_config(SecurityConfig {
+            multiple_login_policy: MultipleLoginPolicy::Additional,
+            ..SecurityConfig::default()
+        })
+        .await;
+        let user_id = create_user(&service, "additional_user").await;
+        let ctx = client("10.0.0.1", None);
+
+        let first = service.authenticate(&ctx, login_request("additional_user", TEST_PASSWORD)).await.unwrap();
+        let second = service.authenticate(&ctx, login_request("additional_user", TEST_PASSWORD)).await.unwrap();
+        assert!(service.validate_session(&first.token).await.is_ok());
+        assert!(service.validate_session(&second.token).await.is_ok());
+        assert_eq!(service.sessions.live_for_user(user_id).await.unwrap().len(), 2);
+        assert!(service.notification_service.pending_for_user(user_id).await.unwrap().is_empty());
+
+        let mut request = login_request("additional_user", TEST_PASSWORD);
+        request.sign_out_other_sessions = true;
+        let third = service.authenticate(&ctx, request).await.unwrap();
+        assert!(service.validate_session(&first.token).await.is_err());
+        assert!(service.validate_session(&second.token).await.is_err());
+        assert!(service.validate_session(&third.token).await.is_ok());
+        assert_eq!(service.notification_service.pending_for_user(user_id).await.unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_credential_revocation_depends_on_scope() {
+        use crate::services::admin_action_service::{LinkedAction, Redemption};
+
+        let service = service_with_config(SecurityConfig {
+            multiple_login_policy: MultipleLoginPolicy::Additional,
+            ..SecurityConfig::default()
+        })
+        .await;
+        let admin_id = create_user(&service, "revoking_admin").await;
+        let ctx = client("10.0.0.1", None);
+
+        let scopes = [
+            (RevocationScope::PasswordChanged, true),
+            (RevocationScope::TwoFaDisabled, true),
+            (RevocationScope::TwoFaReset, false),
+            (RevocationScope::AccountLocked, false),
+        ];
+        for (scope, keeps_current) in scopes {
+            let username = format!("revoked_{}", scope.as_str());
+            let user_id = create_user(&service, &username).await;
+            let other = service.authenticate(&ctx, login_request(&username, TEST_PASSWORD)).await.unwrap();
+            let current = service.authenticate(&ctx, login_request(&username, TEST_PASSWORD)).await.unwrap();
+            let prepared = service.prepare_two_fa_setup(user_id).await.unwrap();
+            let (_, link) = service.admin_actions.create(LinkedAction::Unlock, user_id, admin_id, admin_id).await.unwrap();
+
+            let revoked = service.revoke_user_artifacts(&ctx, user_id, scope).await.unwrap();
+            assert_eq!(revoked.sessions.len(), if keeps_current { 1 } else { 2 }, "{:?}", scope);
+            assert!(revoked.pending_two_fa_setup, "{:?}", scope);
+            assert_eq!(revoked.action_links, 1, "{:?}", scope);
+
+            assert!(service.validate_session(&other.token).await.is_err(), "{:?}", scope);
+            assert_eq!(service.validate_session(&current.token).await.is_ok(), keeps_current, "{:?}", scope);
+            let user = service.get_user_by_id(user_id).await.unwrap();
+            assert!(user.two_fa_secret.is_none(), "{:?}", scope);
+            assert_eq!(user.session_token.is_some(), keeps_current, "{:?}", scope);
+            assert!(enroll(&service, user_id, &prepared.secret).await.is_err(), "{:?}", scope);
+            let redeemed = service.admin_actions.redeem(&link, admin_id).await.unwrap();
+            assert!(matches!(redeemed, Redemption::Expired(_)), "{:?}", scope);
+
+            // Nothing is left to revoke a second time
+            let again = service.revoke_user_artifacts(&ctx, user_id, scope).await.unwrap();
+            assert_eq!(again, RevokedArtifacts::default(), "{:?}", scope);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_successful_login_sends_pending_digest() {
+        let (service, clock) = clocked_service(SecurityConfig::default()).await;
+        let user_id = create_user(&service, "returning_user").await;
+        let ctx = client("197.232.61.4", None);
+
+        for _ in 0..3 {
+            let _ = service.authenticate(&ctx, login_request("returning_user", "WrongPassw0rd!!")).await;
+        }
+        service.authenticate(&ctx, login_request("returning_user", TEST_PASSWORD)).await.unwrap();
+        // A clean login with nothing buffered sends nothing more
+        service.authenticate(&ctx, login_request("returning_user", TEST_PASSWORD)).await.unwrap();
+
+        let pending: Vec<_> = service
+            .notification_service
+            .pending_for_user(user_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|n| n.kind == "FAILED_LOGIN_DIGEST")
+            .collect();
+        assert_eq!(pending.len(), 1);
+        assert!(pending[0].message.starts_with("3 failed sign-in attempts to your account returning_user from 1 IP address at"));
+        let events = digest_events(&service).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["trigger"], "login_succeeded");
+
+        clock.advance(Duration::hours(1));
+        assert_eq!(service.flush_quiet_failed_login_digests().await, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_lockout_status_does_not_reveal_accounts() {
+        let service = test_service().await;
+        create_user(&service, "status_user").await;
+        let locked_id = create_user(&service, "status_locked").await;
+        service.lock_user(&client("10.0.0.1", None), locked_id, Some(Utc::now() + Duration::minutes(5))).await.unwrap();
+
+        let before = Utc::now();
+        let unknown = service.lockout_status("no_such_user").await.unwrap();
+        let unlocked = service.lockout_status("status_user").await.unwrap();
+        let locked = service.lockout_status("status_locked").await.unwrap();
+
+        // Unknown and unlocked accounts give the same answer: "by now"
+        assert_eq!(unknown, unlocked);
+        assert!(unknown >= before && unknown <= before + Duration::minutes(1));
+        assert!(locked >= before + Duration::minutes(5));
+        assert_eq!(locked.timestamp() % 60, 0);
+    }
+
+    #[test]
+    fn test_round_up_to_minute() {
+        let exact = DateTime::parse_from_rfc3339("2024-03-01T14:05:00Z").unwrap().with_timezone(&Utc);
+        let later = DateTime::parse_from_rfc3339("2024-03-01T14:05:00.250Z").unwrap().with_timezone(&Utc);
+
+        assert_eq!(round_up_to_minute(exact), exact);
+        assert_eq!(round_up_to_minute(later), exact + Duration::minutes(1));
+    }
+
+    #[actix_web::test]
+    async fn test_session_length_follows_config() {
+        let service = service_with_config(SecurityConfig {
+            session_timeout_minutes: 2,
+            jwt_expiration_hours: 1,
+            ..SecurityConfig::default()
+        })
+        .await;
+        create_user(&service, "short_session_user").await;
+
+        let before = Utc::now();
+        let login = service
+            .authenticate(&client("10.0.0.1", None), login_request("short_session_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+        assert_eq!(login.expires_in, 3600);
+
+        let user = service.get_user_by_username("short_session_user").await.unwrap();
+        let expires_at = user.session_expires_at.unwrap();
+        assert!(expires_at >= before + Duration::minutes(2));
+        assert!(expires_at <= Utc::now() + Duration::minutes(2));
+    }
+
+    #[actix_web::test]
+    async fn test_lockout_threshold_and_duration_follow_config() {
+        let service = service_with_config(SecurityConfig {
+            max_failed_attempts: 2,
+            lockout_duration_minutes: 45,
+            ..SecurityConfig::default()
+        })
+        .await;
+        create_user(&service, "strict_user").await;
+
+        let _ = service
+            .authenticate(&client("10.0.0.9", None), login_request("strict_user", "WrongPassw0rd!!"))
+            .await;
+        assert!(!service.get_user_by_username("strict_user").await.unwrap().is_locked_at(Utc::now()));
+
+        let before = Utc::now();
+        let _ = service
+            .authenticate(&client("10.0.0.9", None), login_request("strict_user", "WrongPassw0rd!!"))
+            .await;
+
+        let user = service.get_user_by_username("strict_user").await.unwrap();
+        assert!(user.is_locked_at(Utc::now()));
+        assert!(user.lockout_expiry.unwrap() >= before + Duration::minutes(45));
+
+        let result = service
+            .authenticate(&client("10.0.0.9", None), login_request("strict_user", TEST_PASSWORD))
+            .await;
+        assert!(matches!(result, Err(AuthError::AccountLocked)));
+    }
+
+    #[actix_web::test]
+    async fn test_password_change_rotates_session() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "rotating_user").await;
+        let ctx = client("10.0.0.1", None);
+
+        let login = service.authenticate(&ctx, login_request("rotating_user", TEST_PASSWORD)).await.unwrap();
+
+        let renewal = service
+            .change_password(&ctx, user_id, ChangePasswordRequest {
+                current_password: TEST_PASSWORD.to_string(),
+                new_password: "FreshPassw0rd654!".to_string(),
+                confirm_password: "FreshPassw0rd654!".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert!(matches!(service.validate_session(&login.token).await, Err(AuthError::SessionExpired)));
+        assert_eq!(service.validate_session(&renewal.token).await.unwrap().username, "rotating_user");
+    }
+
+    #[actix_web::test]
+    async fn test_onboarding_is_recorded_once() {
+        let clock = Arc::new(MockClock::new());
+        let service = AuthService::new(
+            test_pool().await,
+            PasswordService::new(),
+            TokenService::new(SecurityConfig::default(), clock.clone()),
+            Arc::new(GeoIpService::disabled()),
+            clock.clone(),
+        );
+        let user_id = create_user(&service, "provisioned_user").await;
+        sqlx::query("UPDATE users SET is_temporary_password = TRUE, onboarded_at = NULL WHERE id = ?")
+            .bind(user_id)
+            .execute(&service.db_pool)
+            .await
+            .unwrap();
+        let ctx = client("10.0.0.1", None);
+        let change = |current: &str, new: &str| ChangePasswordRequest {
+            current_password: current.to_string(),
+            new_password: new.to_string(),
+            confirm_password: new.to_string(),
+        };
+
+        service.change_password(&ctx, user_id, change(TEST_PASSWORD, "FreshPassw0rd654!")).await.unwrap();
+        let onboarded_at = service.get_user_by_id(user_id).await.unwrap().onboarded_at;
+        assert_eq!(onboarded_at, Some(clock.now()));
+
+        // Later changes replace a password of the user's own and leave it alone
+        clock.advance(Duration::days(1));
+        service.change_password(&ctx, user_id, change("FreshPassw0rd654!", "LaterPassw0rd321!")).await.unwrap();
+        assert_eq!(service.get_user_by_id(user_id).await.unwrap().onboarded_at, onboarded_at);
+    }
+
+    #[actix_web::test]
+    async fn test_two_fa_setup_rotates_session() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "enrolling_user").await;
+
+        let login = service
+            .authenticate(&client("10.0.0.1", None), login_request("enrolling_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        let prepared = service.prepare_two_fa_setup(user_id).await.unwrap();
+        let totp_code = service.two_fa_service.generate_totp(&prepared.secret, None).unwrap();
+        let enrolled = service.setup_two_fa(user_id, TwoFASetupRequest { totp_code }).await.unwrap();
+        assert!(enrolled.enabled);
+
+        let renewal = enrolled.session.unwrap();
+        assert!(matches!(service.validate_session(&login.token).await, Err(AuthError::SessionExpired)));
+        assert!(service.validate_session(&renewal.token).await.unwrap().two_fa_enabled);
+    }
+
+    async fn set_pending_secret(service: &AuthService, user_id: Uuid, secret: &str) {
+        sqlx::query("UPDATE users SET two_fa_secret = ? WHERE id = ?")
+            .bind(secret)
+            .bind(user_id)
+            .execute(&service.db_pool)
+            .await
+            .unwrap();
+    }
+
+    async fn enroll(service: &AuthService, user_id: Uuid, secret: &str) -> AuthResult<TwoFASetupResponse> {
+        let totp_code = service.two_fa_service.generate_totp(secret, None).unwrap();
+        service.setup_two_fa(user_id, TwoFASetupRequest { totp_code }).await
+    }
+
+    #[actix_web::test]
+    async fn test_two_fa_setup_refuses_weak_and_shared_secrets() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let service = test_service().await;
+        let first = create_user(&service, "first_enrollee").await;
+        let second = create_user(&service, "second_enrollee").await;
+
+        // 80 bits is too short even though the code matches
+        let short = general_purpose::STANDARD.encode([7u8, 42, 99, 1, 200, 13, 54, 88, 3, 17]);
+        set_pending_secret(&service, first, &short).await;
+        assert!(matches!(enroll(&service, first, &short).await, Err(AuthError::WeakTwoFactorSecret)));
+        assert!(!service.get_user_by_id(first).await.unwrap().two_fa_enabled);
+
+        let prepared = service.prepare_two_fa_setup(first).await.unwrap();
+        enroll(&service, first, &prepared.secret).await.unwrap();
+
+        // The same authenticator can't be enrolled on a second account
+        set_pending_secret(&service, second, &prepared.secret).await;
+        assert!(matches!(enroll(&service, second, &prepared.secret).await, Err(AuthError::TwoFactorSecretInUse)));
+        assert!(!service.get_user_by_id(second).await.unwrap().two_fa_enabled);
+
+        let fingerprint: Option<String> = sqlx::query_scalar("SELECT two_fa_fingerprint FROM users WHERE id = ?")
+            .bind(first)
+            .fetch_one(&service.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(fingerprint, Some(service.two_fa_service.fingerprint_secret(&prepared.secret).unwrap()));
+
+        let prepared = service.prepare_two_fa_setup(second).await.unwrap();
+        enroll(&service, second, &prepared.secret).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_two_fa_code_types_are_audited_and_backup_codes_work() {
+        let service = test_service().await;
+        let user_id = create_user(&service, "two_factor_user").await;
+        let prepared = service.prepare_two_fa_setup(user_id).await.unwrap();
+        let totp_code = service.two_fa_service.generate_totp(&prepared.secret, None).unwrap();
+        let enrolled = service.setup_two_fa(user_id, TwoFASetupRequest { totp_code }).await.unwrap();
+        let ctx = client("10.0.0.4", None);
+
+        let mut request = login_request("two_factor_user", TEST_PASSWORD);
+        request.two_fa_code = Some("zzzz9999".parse().unwrap());
+        let result = service.authenticate(&ctx, request).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        // A backup code pasted in lower case with a space still signs in
+        let pasted = format!("{} {}", &enrolled.backup_codes[0][..4], &enrolled.backup_codes[0][4..]).to_lowercase();
+        let mut request = login_request("two_factor_user", TEST_PASSWORD);
+        request.two_fa_code = Some(pasted.parse().unwrap());
+        service.authenticate(&ctx, request).await.unwrap();
+
+        let attempts: Vec<_> = service
+            .audit_service
+            .get_recent_events(50, false, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.event_type == "TWO_FA_ATTEMPT")
+            .collect();
+        assert_eq!(attempts.len(), 2);
+        assert!(attempts.iter().all(|e| e.details.as_ref().unwrap()["code_type"] == "backup"));
+        assert_eq!(attempts.iter().filter(|e| e.success).count(), 1);
+
+        // The used code is gone; the next one disables 2FA
+        let disable = |code: &str| TwoFADisableRequest {
+            password: TEST_PASSWORD.to_string(),
+            two_fa_code: code.parse().unwrap(),
+        };
+        let reused = service.disable_two_fa(&ctx, user_id, disable(&enrolled.backup_codes[0])).await;
+        assert!(matches!(reused, Err(AuthError::InvalidCredentials)));
+        service.disable_two_fa(&ctx, user_id, disable(&enrolled.backup_codes[1])).await.unwrap();
+        assert!(!service.get_user_by_id(user_id).await.unwrap().two_fa_enabled);
+
+        // The owner hears about it straight away, not in a digest
+        let pending = service.notification_service.pending_for_user(user_id).await.unwrap();
+        let kinds: Vec<_> = pending.iter().map(|n| n.kind.as_str()).filter(|kind| *kind != "SESSION_REPLACED").collect();
+        assert_eq!(kinds, vec!["TWO_FA_DISABLED"]);
+    }
+
+    #[actix_web::test]
+    async fn test_parallel_sign_ins_use_each_backup_code_once() {
+        let mut service = test_service().await;
+        // Room for all three sign-ins, so each reaches the backup code check
+        service.login_queue = LoginQueue::new(3, std::time::Duration::from_secs(60));
+        let user_id = create_user(&service, "parallel_backup_user").await;
+        let prepared = service.prepare_two_fa_setup(user_id).await.unwrap();
+        let totp_code = service.two_fa_service.generate_totp(&prepared.secret, None).unwrap();
+        let codes = service.setup_two_fa(user_id, TwoFASetupRequest { totp_code }).await.unwrap().backup_codes;
+        let ctx = client("10.0.0.4", None);
+        let (service, ctx) = (&service, &ctx);
+        let sign_in = move |code: &str| {
+            let mut request = login_request("parallel_backup_user", TEST_PASSWORD);
+            request.two_fa_code = Some(code.parse().unwrap());
+            service.authenticate(ctx, request)
+        };
+
+        // Two different codes and the first again, all checked against the same list
+        let (first, second, repeated) =
+            tokio::join!(sign_in(codes[0].as_str()), sign_in(codes[1].as_str()), sign_in(codes[0].as_str()));
+        assert!(second.is_ok(), "{:?}", second.err());
+        assert!(first.is_ok() != repeated.is_ok(), "{:?} {:?}", first.err(), repeated.err());
+        assert!(matches!(first.err().or(repeated.err()), Some(AuthError::InvalidCredentials)));
+
+        let stored = service.get_user_by_id(user_id).await.unwrap().two_fa_backup_codes.unwrap();
+        assert_eq!(service.two_fa_service.backup_codes_remaining(&stored).unwrap(), 8);
+        assert!(!stored.contains(&codes[0]) && !stored.contains(&codes[1]));
+
+        // A list read before either was used brings neither back, and a
+        // fresh code checked against it is still used up against the current one
+        let stale = serde_json::to_string(&codes).unwrap();
+        assert!(!service.consume_backup_code(user_id, &stale, &codes[1]).await.unwrap());
+        assert!(service.consume_backup_code(user_id, &stale, &codes[2]).await.unwrap());
+        assert!(!service.consume_backup_code(user_id, &stale, &codes[2]).await.unwrap());
+        let stored = service.get_user_by_id(user_id).await.unwrap().two_fa_backup_codes.unwrap();
+        assert_eq!(service.two_fa_service.backup_codes_remaining(&stored).unwrap(), 7);
+        assert!(!stored.contains(&codes[2]));
+    }
+
+    #[actix_web::test]
+    async fn test_token_verification_is_audited() {
+        let mut service = test_service().await;
+        service.verify_monitor = VerifyMonitor::new(1, 2, std::time::Duration::from_secs(300));
+        create_user(&service, "polling_user").await;
+        let ctx = client("10.0.0.3", None);
+
+        let login = service.authenticate(&ctx, login_request("polling_user", TEST_PASSWORD)).await.unwrap();
+        service.verify_session(&ctx, &login.token).await.unwrap();
+        for _ in 0..2 {
+            assert!(service.verify_session(&ctx, "not-a-token").await.is_err());
+        }
+
+        let events = service.audit_service.get_recent_events(50, false, None).await.unwrap();
+        let validations: Vec<_> = events.iter().filter(|e| e.event_type == "TOKEN_VALIDATION").collect();
+        assert_eq!(validations.len(), 3);
+        assert_eq!(validations.iter().filter(|e| e.success).count(), 1);
+        assert_eq!(events.iter().filter(|e| e.event_type == "TOKEN_GUESSING_SUSPECTED").count(), 1);
+
+        let counts = service.verify_counts();
+        assert_eq!(counts.valid, 1);
+        assert_eq!(counts.invalid, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_saturated_login_queue_turns_logins_away() {
+        let mut service = test_service().await;
+        service.login_queue = LoginQueue::new(1, std::time::Duration::from_millis(20));
+        create_user(&service, "storm_user").await;
+        let ctx = client("10.0.0.4", None);
+
+        let login = service.authenticate(&ctx, login_request("storm_user", TEST_PASSWORD)).await.unwrap();
+
+        // Every hashing slot is busy: logins are refused, token checks are not
+        let held = service.login_queue.admit().await.unwrap();
+        let result = service.authenticate(&ctx, login_request("storm_user", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::LoginQueueFull)));
+        assert!(service.verify_session(&ctx, &login.token).await.is_ok());
+        assert_eq!(service.login_queue_depth().in_flight, 1);
+
+        // A refused login is not a failed attempt
+        let user = service.get_user_by_username("storm_user").await.unwrap();
+        assert_eq!(user.login_attempts, 0);
+
+        drop(held);
+        assert!(service.authenticate(&ctx, login_request("storm_user", TEST_PASSWORD)).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_listings_read_from_the_replica_and_sign_in_from_the_primary() {
+        let service = test_service().await;
+        let replica = test_pool().await;
+        let primary = service.db_pool.clone();
+        let service = service.with_read_pool(ReadPool::with_replica(primary, replica.clone()));
+        create_user(&service, "new_user").await;
+        insert_user(&replica, service.clock.clone(), "replicated_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let page = ListParams::default().resolve(&USER_SORT).unwrap();
+        let usernames = |users: Paginated<UserResponse>| users.items.into_iter().map(|u| u.username).collect::<Vec<_>>();
+
+        // The replica hasn't caught up with the new account, but sign-in doesn't wait for it
+        assert_eq!(usernames(service.list_users(&UserFilter::default(), &page).await.unwrap()), ["replicated_user"]);
+        service.authenticate(&client("10.0.0.1", None), login_request("new_user", TEST_PASSWORD)).await.unwrap();
+        assert!(service.audit_service().list_events(10, &AuditFilter::default()).await.unwrap().is_empty());
+
+        // With the replica gone the same reads come from the primary
+        replica.close().await;
+        assert_eq!(usernames(service.list_users(&UserFilter::default(), &page).await.unwrap()), ["new_user"]);
+        assert!(!service.audit_service().list_events(10, &AuditFilter::default()).await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_user_listing_takes_two_queries_whatever_the_page_size() {
+        let service = test_service().await;
+        let admin_id = create_user(&service, "directory_admin").await;
+        let ctx = client("10.0.0.1", None);
+        for i in 0..6 {
+            let username = format!("county_officer_{}", i);
+            let user_id = create_user(&service, &username).await;
+            service.authenticate(&ctx, login_request(&username, TEST_PASSWORD)).await.unwrap();
+            service.set_user_tags(user_id, &["county".to_string(), format!("batch-{}", i % 2)]).await.unwrap();
+            let grant = [PermissionOverride { permission: Permission::DataUpload, granted: i % 2 == 0 }];
+            service.permissions.replace_overrides(user_id, &grant, admin_id).await.unwrap();
+        }
+        let filter = UserFilter { search: Some("officer".to_string()), ..UserFilter::default() };
+
+        for per_page in [1, 3, 10] {
+            let page = ListParams { per_page: Some(per_page), sort: Some("username".to_string()), ..ListParams::default() }
+                .resolve(&USER_SORT)
+                .unwrap();
+            let before = service.read_pool.queries_run();
+            let users = service.list_users(&filter, &page).await.unwrap();
+            assert_eq!(service.read_pool.queries_run() - before, 2, "{} per page", per_page);
+            assert_eq!(users.total, 6);
+            assert_eq!(users.items.len(), (per_page as usize).min(6));
+        }
+
+        // The annotations read in the same query match what the per-account lookups say
+        let page = ListParams::default().resolve(&USER_SORT).unwrap();
+        let users = service.list_users(&filter, &page).await.unwrap();
+        for listed in &users.items {
+            let user = service.get_user_by_username(&listed.username).await.unwrap();
+            assert_eq!(listed.active_sessions, Some(1));
+            assert_eq!(listed.tags, Some(service.account_notes.tags(user.id).await.unwrap()));
+            assert_eq!(listed.permissions, service.permissions.effective(&user).await.unwrap());
+        }
+        let granted = users.items.iter().filter(|u| u.permissions.contains(Permission::DataUpload)).count();
+        assert!(granted > 0 && granted < 6);
+    }
+
+    async fn break_glass_service(config: SecurityConfig) -> (AuthService, String) {
+        let service = service_with_config(config).await.with_break_glass_enabled(true);
+        let passphrase = service.provision_break_glass(&client("cli", None)).await.unwrap();
+        (service, passphrase)
+    }
+
+    #[actix_web::test]
+    async fn test_break_glass_requires_activation_flag() {
+        let (service, passphrase) = break_glass_service(SecurityConfig::default()).await;
+        let service = service.with_break_glass_enabled(false);
+        let ctx = client("10.0.0.7", None);
+
+        let result = service.authenticate(&ctx, login_request(BREAK_GLASS_USERNAME, &passphrase)).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        let attempts = stored_attempts(&service, BREAK_GLASS_USERNAME).await;
+        assert_eq!(attempts[0].failure_reason.as_deref(), Some("Break-glass access not activated"));
+
+        // A refused attempt leaves the credential armed
+        let service = service.with_break_glass_enabled(true);
+        assert!(service.authenticate(&ctx, login_request(BREAK_GLASS_USERNAME, &passphrase)).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_break_glass_credential_works_once() {
+        let (service, passphrase) = break_glass_service(SecurityConfig::default()).await;
+        let admin_id = create_user(&service, "regular_admin").await;
+        sqlx::query("UPDATE users SET role = 'admin' WHERE id = ?").bind(admin_id).execute(&service.db_pool).await.unwrap();
+        let ctx = client("10.0.0.7", None);
+
+        let login = service.authenticate(&ctx, login_request(BREAK_GLASS_USERNAME, &passphrase)).await.unwrap();
+        assert_eq!(login.user.role, UserRole::Admin);
+        assert!(login.user.permissions.contains(Permission::UserManage));
+
+        let result = service.authenticate(&ctx, login_request(BREAK_GLASS_USERNAME, &passphrase)).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        // Every use is a critical event that stays on the dashboard until acknowledged
+        let unreviewed = service.audit_service.unreviewed_break_glass_events().await.unwrap();
+        assert_eq!(unreviewed.len(), 1);
+        assert_eq!(unreviewed[0].severity, Severity::Critical);
+        service.audit_service.acknowledge_event(&ctx, unreviewed[0].id, admin_id, Some("Drill")).await.unwrap();
+        assert!(service.audit_service.unreviewed_break_glass_events().await.unwrap().is_empty());
+
+        let pending = service.notification_service.pending_for_user(admin_id).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].kind, "BREAK_GLASS_USED");
+
+        // Re-provisioning arms a new passphrase; the old one stays dead
+        let fresh = service.provision_break_glass(&client("cli", None)).await.unwrap();
+        assert_ne!(fresh, passphrase);
+        assert!(service.authenticate(&ctx, login_request(BREAK_GLASS_USERNAME, &passphrase)).await.is_err());
+        assert!(service.authenticate(&ctx, login_request(BREAK_GLASS_USERNAME, &fresh)).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_break_glass_session_is_capped() {
+        let (service, passphrase) = break_glass_service(SecurityConfig {
+            session_timeout_minutes: 600,
+            jwt_expiration_hours: 24,
+            ..SecurityConfig::default()
+        })
+        .await;
+        let ctx = client("10.0.0.7", None);
+
+        let before = Utc::now();
+        let login = service.authenticate(&ctx, login_request(BREAK_GLASS_USERNAME, &passphrase)).await.unwrap();
+        assert_eq!(login.expires_in, BREAK_GLASS_MAX_SESSION_MINUTES * 60);
+
+        let cap = Duration::minutes(BREAK_GLASS_MAX_SESSION_MINUTES);
+        let claims = service.token_service.claims(&login.token).unwrap();
+        assert!(claims.exp as i64 <= (Utc::now() + cap).timestamp());
+
+        let user = service.get_user_by_username(BREAK_GLASS_USERNAME).await.unwrap();
+        let session_expires_at = user.session_expires_at.unwrap();
+        assert!(session_expires_at >= before + cap - Duration::seconds(1));
+        assert!(session_expires_at <= Utc::now() + cap);
+
+        // Rotating the session can't stretch it past the original cap
+        let renewal = service.rotate_session(&user, Rotation::Account).await.unwrap();
+        assert!(renewal.expires_in <= BREAK_GLASS_MAX_SESSION_MINUTES * 60);
+        let rotated = service.get_user_by_username(BREAK_GLASS_USERNAME).await.unwrap();
+        assert_eq!(rotated.session_expires_at, Some(session_expires_at));
+    }
+
+    fn tight_throttle() -> Arc<ThrottleState> {
+        use crate::services::throttle_state::{ScopeLimits, ThrottleConfig};
+
+        let limits = |max_failures| ScopeLimits {
+            max_failures,
+            window: std::time::Duration::from_secs(60),
+            block_for: std::time::Duration::from_secs(60),
+        };
+        Arc::new(ThrottleState::new(ThrottleConfig { ip: limits(3), username: limits(2), ip_username: limits(10) }))
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_and_service_share_throttle_counters() {
+        use crate::middleware::security::{RateLimiting, RateLimits};
+        use actix_web::{test, web, App, HttpResponse};
+
+        let throttle = tight_throttle();
+        let service = test_service().await.with_throttle_state(throttle.clone());
+        let app = test::init_service(
+            App::new()
+                .wrap(RateLimiting::new(Arc::new(RateLimits::new(100)), throttle.clone()))
+                .route("/api/auth/verify", web::get().to(HttpResponse::Unauthorized))
+                .route("/api/auth/me", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = |path: &str| test::TestRequest::get().uri(path).peer_addr("10.0.0.1:4000".parse().unwrap()).to_request();
+
+        // Two failures seen by the middleware, one by the service: the same address budget
+        for _ in 0..2 {
+            assert_eq!(test::call_service(&app, get("/api/auth/verify")).await.status(), 401);
+        }
+        let ctx = client("10.0.0.1", None);
+        let result = service.authenticate(&ctx, login_request("nobody", "Wr0ngPassword!")).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        assert_eq!(throttle.failures(ThrottleScope::Ip, "10.0.0.1").await, 3);
+
+        // Both layers now refuse the address with a 429
+        assert_eq!(test::call_service(&app, get("/api/auth/me")).await.status(), 429);
+        let result = service.authenticate(&ctx, login_request("someone", "Wr0ngPassword!")).await;
+        assert!(matches!(result, Err(AuthError::TooManyAttempts)));
+    }
+
+    #[actix_web::test]
+    async fn test_username_throttle_locks_from_any_address_until_unlocked() {
+        let throttle = tight_throttle();
+        let service = test_service().await.with_throttle_state(throttle.clone());
+        let user_id = create_user(&service, "sprayed").await;
+
+        for ip in ["10.0.0.1", "10.0.0.2"] {
+            let result = service.authenticate(&client(ip, None), login_request("sprayed", "Wr0ngPassword!")).await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        }
+
+        // Even the right password from a fresh address is refused with a 423
+        let result = service.authenticate(&client("10.0.0.3", None), login_request("sprayed", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::AccountLocked)));
+
+        service.unlock_user(user_id).await.unwrap();
+        let result = service.authenticate(&client("10.0.0.3", None), login_request("sprayed", TEST_PASSWORD)).await;
+        assert!(result.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_address_spellings_lock_throttle_and_audit_as_one_client() {
+        // (as the proxy sends it, as an admin looks it up, as stored, another address in its network)
+        let cases = [
+            ("41.90.12.7:52311", "::ffff:41.90.12.7", "41.90.12.7", "41.90.12.7"),
+            ("[2C0F:FE38:2001:5A1::7]:443", "2c0f:fe38:2001:5a1:0:0:0:7", "2c0f:fe38:2001:5a1::7", "2c0f:fe38:2001:5a1::8"),
+            ("::ffff:197.232.61.4", "[::ffff:197.232.61.4]:443", "197.232.61.4", "197.232.61.4:80"),
+            ("fe80::1%eth0", "[fe80::1]:443", "fe80::1", "fe80::2%wlan0"),
+        ];
+        for (sent, looked_up, stored, neighbour) in cases {
+            let service = test_service().await.with_throttle_state(tight_throttle());
+            create_user(&service, "roaming").await;
+
+            // The username budget of two locks the account
+            for _ in 0..2 {
+                let result = service.authenticate(&client(sent, None), login_request("roaming", "Wr0ngPassword!")).await;
+                assert!(matches!(result, Err(AuthError::InvalidCredentials)), "{}", sent);
+            }
+            let result = service.authenticate(&client(sent, None), login_request("roaming", TEST_PASSWORD)).await;
+            assert!(matches!(result, Err(AuthError::AccountLocked)), "{}", sent);
+
+            // The third failure uses up the address budget for its whole network
+            let result = service.authenticate(&client(sent, None), login_request("nobody", "Wr0ngPassword!")).await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)), "{}", sent);
+            let other: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE ip_address != ?")
+                .bind(stored)
+                .fetch_one(&service.db_pool)
+                .await
+                .unwrap();
+            assert_eq!(other, 0, "{}", sent);
+            let result = service.authenticate(&client(neighbour, None), login_request("someone", "Wr0ngPassword!")).await;
+            assert!(matches!(result, Err(AuthError::TooManyAttempts)), "{} then {}", sent, neighbour);
+
+            let attempts = stored_attempts(&service, "roaming").await;
+            assert!(attempts.len() >= 2);
+            assert!(attempts.iter().all(|attempt| attempt.ip_address == stored), "{:?}", attempts);
+
+            let activity = service.audit_service().ip_activity(looked_up, 50, 0).await.unwrap();
+            assert_eq!(activity.ip_address, stored);
+            assert!(activity.summary.login_failures >= 3, "{:?}", activity.summary.login_failures);
+            assert!(activity.summary.usernames_targeted.contains(&"roaming".to_string()));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_exempt_accounts_never_lock_from_their_network_but_are_audited() {
+        let exemptions = LockoutExemptions::new(
+            &["uptime_probe".to_string(), "untagged_probe".to_string(), "roaming_probe".to_string()],
+            &["196.201.214.0/24".to_string()],
+        )
+        .unwrap();
+        let service = test_service().await.with_lockout_exemptions(Arc::new(exemptions));
+        let probe_id = create_user(&service, "uptime_probe").await;
+        service.set_user_tags(probe_id, &[MONITORING_TAG.to_string()]).await.unwrap();
+        let roaming_id = create_user(&service, "roaming_probe").await;
+        service.set_user_tags(roaming_id, &[MONITORING_TAG.to_string()]).await.unwrap();
+        create_user(&service, "untagged_probe").await;
+        create_user(&service, "field_officer").await;
+
+        // Twice the per-address-and-account throttle, four times the lockout
+        let probe = client("196.201.214.7", None);
+        for _ in 0..20 {
+            let result = service.authenticate(&probe, login_request("uptime_probe", "WrongPassw0rd!!")).await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)), "{:?}", result.err());
+        }
+        assert!(service.authenticate(&probe, login_request("uptime_probe", TEST_PASSWORD)).await.is_ok());
+
+        // Every failure is still recorded
+        let attempts = stored_attempts(&service, "uptime_probe").await;
+        assert_eq!(attempts.iter().filter(|attempt| !attempt.success).count(), 20);
+        let audited: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE event_type = 'LOGIN_ATTEMPT' AND user_id = ?")
+                .bind(probe_id)
+                .fetch_one(&service.db_pool)
+                .await
+                .unwrap();
+        assert_eq!(audited, 21);
+
+        // Listing a username without the tag exempts nothing, even from the network
+        let workstation = client("196.201.214.40", None);
+        for _ in 0..5 {
+            let _ = service.authenticate(&workstation, login_request("untagged_probe", "WrongPassw0rd!!")).await;
+        }
+        let result = service.authenticate(&workstation, login_request("untagged_probe", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::AccountLocked)));
+
+        // A monitoring account is only exempt from the listed networks
+        for _ in 0..5 {
+            let _ = service.authenticate(&client("10.0.0.2", None), login_request("roaming_probe", "WrongPassw0rd!!")).await;
+        }
+        let result = service.authenticate(&client("10.0.0.2", None), login_request("roaming_probe", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::AccountLocked)));
+
+        // The network alone spares no one: an ordinary account signing in from it still locks
+        let neighbour = client("196.201.214.41", None);
+        for _ in 0..5 {
+            let _ = service.authenticate(&neighbour, login_request("field_officer", "WrongPassw0rd!!")).await;
+        }
+        let result = service.authenticate(&neighbour, login_request("field_officer", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::AccountLocked)));
+    }
+
+    #[actix_web::test]
+    async fn test_failures_in_a_row_are_answered_ever_more_slowly() {
+        use crate::services::throttle_state::FailureDelay;
+        use std::time::{Duration as StdDuration, Instant};
+
+        let delay = FailureDelay { free_failures: 1, base: StdDuration::from_millis(200), max: StdDuration::from_millis(500) };
+        let exemptions = Arc::new(LockoutExemptions::new(&["uptime_probe".to_string()], &[]).unwrap());
+        let service = test_service()
+            .await
+            .with_throttle_state(Arc::new(ThrottleState::default().with_failure_delay(delay)))
+            .with_lockout_exemptions(exemptions);
+        create_user(&service, "returning_officer").await;
+        let probe_id = create_user(&service, "uptime_probe").await;
+        service.set_user_tags(probe_id, &[MONITORING_TAG.to_string()]).await.unwrap();
+        let service = &service;
+        let timed = move |ip: &'static str, username: &'static str, password: &'static str| async move {
+            let started = Instant::now();
+            let result = service.authenticate(&client(ip, None), login_request(username, password)).await;
+            (result, started.elapsed())
+        };
+
+        // Unknown usernames are held back like wrong passwords, 0, 200, 400,
+        // then capped at 500 where doubling would give 800 and 1600
+        let ghosts = ["ghost_1", "ghost_2", "ghost_3", "ghost_4", "ghost_5"];
+        for (username, expected_ms) in ghosts.into_iter().zip([0, 200, 400, 500, 500]) {
+            let (result, elapsed) = timed("10.0.0.1", username, "WrongPassw0rd!!").await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+            let expected = StdDuration::from_millis(expected_ms);
+            assert!(elapsed >= expected && elapsed < expected + StdDuration::from_millis(150), "{}: {:?}", username, elapsed);
+        }
+
+        // A success ends the address's streak, so its next failure is answered at once
+        let (result, _) = timed("10.0.0.1", "returning_officer", TEST_PASSWORD).await;
+        assert!(result.is_ok());
+        let (_, elapsed) = timed("10.0.0.1", "ghost_6", "WrongPassw0rd!!").await;
+        assert!(elapsed < StdDuration::from_millis(150), "{:?}", elapsed);
+
+        // An exempt monitoring account's failures neither wait nor lengthen the streak
+        for _ in 0..4 {
+            let (result, _) = timed("10.0.0.2", "uptime_probe", "WrongPassw0rd!!").await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        }
+        let (_, elapsed) = timed("10.0.0.2", "ghost_7", "WrongPassw0rd!!").await;
+        assert!(elapsed < StdDuration::from_millis(150), "{:?}", elapsed);
+    }
+
+    #[actix_web::test]
+    async fn test_locking_unknown_user_fails() {
+        let service = test_service().await;
+        let result = service.lock_user(&client("10.0.0.1", None), Uuid::new_v4(), None).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+    }
+
+    #[actix_web::test]
+    async fn test_login_history_is_geo_enriched_and_flags_unexpected_country() {
+        use crate::services::geoip_service::TEST_DATABASE;
+
+        let geoip = GeoIpService::open(Some(TEST_DATABASE), Some(TEST_DATABASE), vec!["KE".to_string()]).unwrap();
+        let service = service_with_geoip(geoip).await;
+        let user_id = create_user(&service, "geo_user").await;
+
+        service
+            .authenticate(&client("197.232.61.4", None), login_request("geo_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+        service
+            .authenticate(&client("81.2.69.160", None), login_request("geo_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+        service
+            .authenticate(&client("10.0.0.1", None), login_request("geo_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        let history = service.get_login_history(user_id, 10).await.unwrap();
+        let from = |ip: &str| history.iter().find(|attempt| attempt.ip_address == ip).unwrap();
+
+        let nairobi = from("197.232.61.4");
+        assert_eq!(nairobi.country_code.as_deref(), Some("KE"));
+        assert_eq!(nairobi.city.as_deref(), Some("Nairobi"));
+        assert_eq!(nairobi.asn, Some(33771));
+        assert_eq!(nairobi.asn_org.as_deref(), Some("Safaricom Limited"));
+
+        let private = from("10.0.0.1");
+        assert_eq!(private.country_code, None);
+        assert_eq!(private.asn, None);
+
+        let flagged = service
+            .audit_service()
+            .get_recent_events(50, false, Some(Severity::Warning))
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.event_type == "LOGIN_FROM_UNEXPECTED_COUNTRY")
+            .collect::<Vec<_>>();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].ip_address.as_deref(), Some("81.2.69.160"));
+    }
+
+    #[actix_web::test]
+    async fn test_privacy_mode_truncates_stored_addresses_but_throttles_full_ones() {
+        use crate::services::geoip_service::TEST_DATABASE;
+
+        let geoip = GeoIpService::open(Some(TEST_DATABASE), Some(TEST_DATABASE), Vec::new()).unwrap().with_privacy_mode(true);
+        let throttle = tight_throttle();
+        let service = service_with_geoip(geoip).await.with_throttle_state(throttle.clone());
+        let user_id = create_user(&service, "private_user").await;
+
+        for username in ["nobody_1", "nobody_2", "nobody_3"] {
+            let result = service.authenticate(&client("197.232.61.4", None), login_request(username, "Wr0ngPassword!")).await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        }
+
+        // The address that failed is refused as before, its neighbours in the same /24 are not
+        assert_eq!(throttle.failures(ThrottleScope::Ip, "197.232.61.4").await, 3);
+        let result = service.authenticate(&client("197.232.61.4", None), login_request("private_user", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::TooManyAttempts)));
+        service
+            .authenticate(&client("197.232.61.5", None), login_request("private_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        let history = service.get_login_history(user_id, 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].ip_address, "197.232.61.0");
+        assert_eq!(history[0].country_code.as_deref(), Some("KE"));
+        assert_eq!(history[0].region.as_deref(), Some("Nairobi County"));
+        assert_eq!((history[0].city.as_deref(), history[0].asn), (None, None));
+        assert_eq!((history[0].latitude, history[0].longitude), (Some(-1.0), Some(37.0)));
+
+        let stored: Vec<(String, Option<String>)> =
+            sqlx::query_as("SELECT ip_address, raw_ip FROM login_attempts WHERE username = 'nobody_1'")
+                .fetch_all(&service.db_pool)
+                .await
+                .unwrap();
+        assert_eq!(stored, vec![("197.232.61.0".to_string(), Some("197.232.61.4".to_string()))]);
+        let unmasked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE ip_address NOT IN ('197.232.61.0')")
+            .fetch_one(&service.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(unmasked, 0);
+
+        // Looking an address up finds its network's activity
+        let activity = service.audit_service().ip_activity("197.232.61.5", 10, 0).await.unwrap();
+        assert_eq!(activity.ip_address, "197.232.61.0");
+        assert_eq!((activity.summary.login_successes, activity.summary.login_failures), (1, 3));
+    }
+
+    async fn seed_successful_login(service: &AuthService, user_id: Uuid, latitude: f64, longitude: f64, minutes_ago: i64) {
+        sqlx::query(
+            "INSERT INTO login_attempts (id, user_id, username, ip_address, success, timestamp, latitude, longitude)
+             VALUES (?, ?, 'travel_user', '198.51.100.1', TRUE, ?, ?, ?)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(Utc::now() - Duration::minutes(minutes_ago))
+        .bind(latitude)
+        .bind(longitude)
+        .execute(&service.db_pool)
+        .await
+        .unwrap();
+    }
+
+    async fn impossible_travel_events(service: &AuthService) -> usize {
+        service
+            .audit_service()
+            .get_recent_events(50, false, Some(Severity::Critical))
+            .await
+            .unwrap()
+            .iter()
+            .filter(|e| e.event_type == "IMPOSSIBLE_TRAVEL")
+            .count()
+    }
+
+    #[actix_web::test]
+    async fn test_impossible_travel_is_flagged_above_threshold() {
+        use crate::services::geoip_service::TEST_DATABASE;
+
+        let geoip = GeoIpService::open(Some(TEST_DATABASE), None, Vec::new()).unwrap();
+        let service = service_with_geoip(geoip).await;
+        let user_id = create_user(&service, "travel_user").await;
+
+        // Nairobi twenty minutes ago, London now
+        seed_successful_login(&service, user_id, -1.2833, 36.8167, 20).await;
+        service
+            .authenticate(&client("81.2.69.160", None), login_request("travel_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+        assert_eq!(impossible_travel_events(&service).await, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_plausible_travel_and_missing_geo_are_not_flagged() {
+        use crate::services::geoip_service::TEST_DATABASE;
+
+        let geoip = GeoIpService::open(Some(TEST_DATABASE), None, Vec::new()).unwrap();
+        let service = service_with_geoip(geoip).await;
+        let user_id = create_user(&service, "travel_user").await;
+
+        // Nairobi two days ago, London now
+        seed_successful_login(&service, user_id, -1.2833, 36.8167, 2 * 24 * 60).await;
+        service
+            .authenticate(&client("81.2.69.160", None), login_request("travel_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        // A login from an unresolvable address is skipped silently
+        service
+            .authenticate(&client("10.0.0.1", None), login_request("travel_user", TEST_PASSWORD))
+            .await
+            .unwrap();
+
+        assert_eq!(impossible_travel_events(&service).await, 0);
+    }
+
+    async fn start_lockdown(service: &AuthService, duration_minutes: i64) -> Lockdown {
+        let ctx = client("10.0.0.9", None);
+        let clock = service.clock.clone();
+        let first = insert_user(&service.db_pool, clock.clone(), "first_officer", UserRole::Admin, TEST_PASSWORD, false).await;
+        let second = insert_user(&service.db_pool, clock, "second_officer", UserRole::Admin, TEST_PASSWORD, false).await;
+
+        let requested = service.request_lockdown(&ctx, first.id, &first.username, "Leaked credentials", duration_minutes);
+        assert!(matches!(requested.await.unwrap(), LockdownRequest::Pending(_)));
+        match service.request_lockdown(&ctx, second.id, &second.username, "Approved", duration_minutes).await.unwrap() {
+            LockdownRequest::Started(lockdown) => lockdown,
+            other => panic!("expected the lockdown to start, got {:?}", other),
+        }
+    }
+
+    async fn lockdown_events(service: &AuthService, event_type: AuditEventType) -> usize {
+        service
+            .audit_service()
+            .get_recent_events(50, false, Some(Severity::Critical))
+            .await
+            .unwrap()
+            .iter()
+            .filter(|e| e.event_type == event_type)
+            .count()
+    }
+
+    #[actix_web::test]
+    async fn test_lockdown_ends_sessions_created_before_it() {
+        let (service, _clock) = clocked_service(SecurityConfig::default()).await;
+        create_user(&service, "field_officer").await;
+        let ctx = client("10.0.0.1", None);
+        let before = service.authenticate(&ctx, login_request("field_officer", TEST_PASSWORD)).await.unwrap();
+        assert!(service.validate_session(&before.token).await.is_ok());
+
+        let lockdown = start_lockdown(&service, 60).await;
+        assert!(matches!(service.validate_session(&before.token).await, Err(AuthError::SessionExpired)));
+        assert_eq!(lockdown_events(&service, AuditEventType::LockdownStarted).await, 1);
+        let approver = lockdown.approved_by.unwrap();
+        let notified = service.notification_service.pending_for_user(lockdown.requested_by).await.unwrap();
+        assert!(notified.iter().any(|n| n.kind == "LOCKDOWN_STARTED"));
+
+        // Lifting it early lets sign-ins through as before, but ended sessions stay ended
+        let lifted = service.lift_lockdown(&ctx, approver, "second_officer").await.unwrap();
+        assert_eq!(lifted.ended_by, Some(approver));
+        assert_eq!(lockdown_events(&service, AuditEventType::LockdownEnded).await, 1);
+        assert!(matches!(service.validate_session(&before.token).await, Err(AuthError::SessionExpired)));
+        let after = service.authenticate(&ctx, login_request("field_officer", TEST_PASSWORD)).await.unwrap();
+        assert!(service.validate_session(&after.token).await.is_ok());
+        assert!(matches!(service.lift_lockdown(&ctx, approver, "second_officer").await, Err(AuthError::LockdownNotFound)));
+    }
+
+    #[actix_web::test]
+    async fn test_lockdown_sign_in_without_two_fa_takes_an_emailed_code() {
+        let (service, _clock) = clocked_service(SecurityConfig::default()).await;
+        let user_id = create_user(&service, "emailed_user").await;
+        create_user(&service, "stranded_user").await;
+        sqlx::query("UPDATE users SET email = 'emailed@agriculture.go.ke', email_verified_at = ? WHERE id = ?")
+            .bind(service.clock.now())
+            .bind(user_id)
+            .execute(&service.db_pool)
+            .await
+            .unwrap();
+        start_lockdown(&service, 60).await;
+        let ctx = client("10.0.0.2", None);
+
+        let result = service.authenticate(&ctx, login_request("stranded_user", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::LockdownSecondFactorUnavailable)));
+
+        let pending = service.authenticate(&ctx, login_request("emailed_user", TEST_PASSWORD)).await.unwrap();
+        assert!(pending.requires_two_fa);
+        assert!(pending.token.is_empty());
+        assert_eq!(pending.two_fa_method, Some("email"));
+        let challenge = pending.two_fa_temp_token.unwrap();
+
+        let notifications = service.notification_service.pending_for_user(user_id).await.unwrap();
+        let message = &notifications.iter().find(|n| n.kind == "LOCKDOWN_SIGN_IN_CODE").unwrap().message;
+        let code = message.split("Enter ").nth(1).unwrap()[..6].to_string();
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+
+        let result = service.complete_two_factor_login(&ctx, &challenge, TwoFactorCode::Totp(wrong.to_string()), PasswordRotation::AfterSignIn).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        let login = service.complete_two_factor_login(&ctx, &challenge, TwoFactorCode::Totp(code), PasswordRotation::AfterSignIn).await.unwrap();
+        assert!(!login.requires_two_fa);
+        assert!(service.validate_session(&login.token).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_lockdown_runs_out_on_its_own() {
+        let (service, clock) = clocked_service(SecurityConfig::default()).await;
+        create_user(&service, "returning_user").await;
+        start_lockdown(&service, 30).await;
+        let ctx = client("10.0.0.3", None);
+
+        let result = service.authenticate(&ctx, login_request("returning_user", TEST_PASSWORD)).await;
+        assert!(matches!(result, Err(AuthError::LockdownSecondFactorUnavailable)));
+
+        // Past its end sign-ins work again at once; the end is recorded, and reported, once
+        clock.advance(Duration::minutes(30));
+        let login = service.authenticate(&ctx, login_request("returning_user", TEST_PASSWORD)).await.unwrap();
+        assert!(!login.requires_two_fa);
+        assert_eq!(service.end_expired_lockdowns(&ctx).await.unwrap(), 1);
+        assert_eq!(service.end_expired_lockdowns(&ctx).await.unwrap(), 0);
+        assert_eq!(lockdown_events(&service, AuditEventType::LockdownEnded).await, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_instances_starting_together_create_one_default_user() {
+        use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+        // Two instances sharing one file database
+        let dir = tempfile::tempdir().unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("shared.db"))
+            .create_if_missing(true)
+            .busy_timeout(std::time::Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new().max_connections(4).connect_with(options).await.unwrap();
+        crate::utils::database::run_migrations(&pool).await.unwrap();
+        let instance = || {
+            AuthService::new(
+                pool.clone(),
+                PasswordService::new(),
+                TokenService::new(SecurityConfig::default(), Arc::new(SystemClock)),
+                Arc::new(GeoIpService::disabled()),
+                Arc::new(SystemClock),
+            )
+        };
+        let (first, second) = (instance(), instance());
+
+        let (a, b) = tokio::join!(first.initialize_default_user(), second.initialize_default_user());
+        let passwords: Vec<String> = [a.unwrap(), b.unwrap()].into_iter().flatten().collect();
+        assert_eq!(passwords.len(), 1);
+
+        let hashes: Vec<String> = sqlx::query_scalar("SELECT password_hash FROM users").fetch_all(&pool).await.unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert!(first.password_service.verify_password(&passwords[0], &hashes[0]).unwrap());
+        let holder: Option<String> = sqlx::query_scalar("SELECT holder FROM bootstrap_lock WHERE name = 'default_user'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(holder, None);
+
+        // A later startup finds the administrator and does nothing
+        assert_eq!(second.initialize_default_user().await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn test_default_user_waits_for_a_held_lock_and_ignores_inactive_admins() {
+        let service = test_service().await;
+        // Neither an officer nor a deactivated administrator counts
+        create_user(&service, "county_officer").await;
+        let former = insert_user(&service.db_pool, service.clock.clone(), "former_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        sqlx::query("UPDATE users SET is_active = FALSE WHERE id = ?").bind(former.id).execute(&service.db_pool).await.unwrap();
+
+        // Another instance is part way through
+        sqlx::query("UPDATE bootstrap_lock SET holder = 'other-instance', expires_at = ?")
+            .bind(Utc::now() + Duration::minutes(1))
+            .execute(&service.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(service.initialize_default_user().await.unwrap(), None);
+
+        // It died holding the lock, which has since lapsed
+        sqlx::query("UPDATE bootstrap_lock SET expires_at = ?")
+            .bind(Utc::now() - Duration::minutes(1))
+            .execute(&service.db_pool)
+            .await
+            .unwrap();
+        assert!(service.initialize_default_user().await.unwrap().is_some());
+        assert_eq!(service.initialize_default_user().await.unwrap(), None);
+    }
+}
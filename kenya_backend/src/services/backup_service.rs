@@ -0,0 +1,346 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, SqlitePool};
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::utils::clock::Clock;
+
+/// Scheduled and on-demand backups are named `<prefix><timestamp>.db`
+const BACKUP_FILE_PREFIX: &str = "kenya_fsfvi-";
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0} already exists")]
+    AlreadyExists(PathBuf),
+    #[error("A server is running against this database; stop it before restoring")]
+    ServerRunning,
+    #[error("{0} is not a usable backup: {1}")]
+    InvalidSnapshot(PathBuf, String),
+}
+
+/// A snapshot written to disk
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupFile {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    /// Hex SHA-256 of the file, for checking copies taken off the host
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Consistent snapshots of the live database into `dir`, keeping the newest
+/// `retention` of them
+#[derive(Clone)]
+pub struct BackupService {
+    db_pool: SqlitePool,
+    dir: PathBuf,
+    retention: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl BackupService {
+    pub fn new(db_pool: SqlitePool, dir: PathBuf, retention: usize, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, dir, retention: retention.max(1), clock }
+    }
+
+    /// Snapshot into a timestamped file in the backup directory, then drop
+    /// the oldest backups beyond the retention count
+    pub async fn create_backup(&self) -> Result<BackupFile, BackupError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let now = self.clock.now();
+        let path = self.dir.join(format!("{}{}.db", BACKUP_FILE_PREFIX, now.format("%Y%m%dT%H%M%S%3fZ")));
+
+        let backup = snapshot(&self.db_pool, &path, now).await?;
+        self.prune().await?;
+        Ok(backup)
+    }
+
+    /// Take a backup every `interval` for as long as the server runs
+    pub fn spawn_schedule(self, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately; startup isn't a backup point
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match self.create_backup().await {
+                    Ok(backup) => log::info!("Scheduled backup written to {} (sha256 {})", backup.path.display(), backup.sha256),
+                    Err(e) => log::error!("Scheduled backup failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Remove all but the newest `retention` backups; the timestamped names sort by age
+    async fn prune(&self) -> Result<(), BackupError> {
+        let mut backups = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(BACKUP_FILE_PREFIX) && name.ends_with(".db") {
+                backups.push(entry.path());
+            }
+        }
+        backups.sort();
+
+        let excess = backups.len().saturating_sub(self.retention);
+        for old in &backups[..excess] {
+            log::info!("Removing old backup {}", old.display());
+            tokio::fs::remove_file(old).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a transactionally consistent copy of the database to `out` with
+/// `VACUUM INTO`. Safe while the server is taking writes. An in-memory
+/// database snapshots into memory, so only file databases produce a file.
+pub async fn snapshot(pool: &SqlitePool, out: &Path, now: DateTime<Utc>) -> Result<BackupFile, BackupError> {
+    if out.exists() {
+        return Err(BackupError::AlreadyExists(out.to_path_buf()));
+    }
+
+    sqlx::query("VACUUM INTO ?")
+        .bind(out.to_string_lossy().into_owned())
+        .execute(pool)
+        .await?;
+
+    let path = out.to_path_buf();
+    let (size_bytes, sha256) = tokio::task::spawn_blocking(move || file_sha256(&path))
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))??;
+
+    Ok(BackupFile { path: out.to_path_buf(), size_bytes, sha256, created_at: now })
+}
+
+/// Replace the database file at `db_path` with the backup at `backup`.
+///
+/// Refuses while any server holds the database lock, and checks the backup
+/// before touching anything. The database being replaced is kept alongside
+/// as `<name>.pre-restore-<timestamp>`, whose path is returned.
+pub async fn restore(db_path: &Path, backup: &Path, now: DateTime<Utc>) -> Result<Option<PathBuf>, BackupError> {
+    let _lock = ServerLock::exclusive(db_path)?;
+
+    let mut conn = SqliteConnectOptions::new()
+        .filename(backup)
+        .read_only(true)
+        .connect()
+        .await
+        .map_err(|e| BackupError::InvalidSnapshot(backup.to_path_buf(), e.to_string()))?;
+    let integrity: String = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_one(&mut conn)
+        .await
+        .map_err(|e| BackupError::InvalidSnapshot(backup.to_path_buf(), e.to_string()))?;
+    if integrity != "ok" {
+        return Err(BackupError::InvalidSnapshot(backup.to_path_buf(), integrity));
+    }
+    let has_users: Option<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'users'")
+        .fetch_optional(&mut conn)
+        .await?;
+    if has_users.is_none() {
+        return Err(BackupError::InvalidSnapshot(backup.to_path_buf(), "no users table".to_string()));
+    }
+    drop(conn);
+
+    let previous = if db_path.exists() {
+        let previous = sibling(db_path, &format!(".pre-restore-{}", now.format("%Y%m%dT%H%M%SZ")));
+        tokio::fs::copy(db_path, &previous).await?;
+        Some(previous)
+    } else {
+        None
+    };
+
+    // Copy beside the database first so the swap itself is a rename
+    let staging = sibling(db_path, ".restoring");
+    tokio::fs::copy(backup, &staging).await?;
+    tokio::fs::rename(&staging, db_path).await?;
+    for suffix in ["-wal", "-shm", "-journal"] {
+        let leftover = sibling(db_path, suffix);
+        if leftover.exists() {
+            tokio::fs::remove_file(leftover).await?;
+        }
+    }
+
+    Ok(previous)
+}
+
+/// Advisory lock on `<database>.lock`. Running servers share it; a restore
+/// needs it exclusively. Released when dropped or when the process exits.
+pub struct ServerLock {
+    _file: File,
+}
+
+impl ServerLock {
+    /// Taken by every server process for its whole lifetime
+    pub fn shared(db_path: &Path) -> Result<Self, BackupError> {
+        let file = Self::open(db_path)?;
+        file.try_lock_shared().map_err(|e| match e {
+            TryLockError::WouldBlock => BackupError::Io(std::io::Error::other("database is being restored")),
+            TryLockError::Error(e) => BackupError::Io(e),
+        })?;
+        Ok(Self { _file: file })
+    }
+
+    fn exclusive(db_path: &Path) -> Result<Self, BackupError> {
+        let file = Self::open(db_path)?;
+        file.try_lock().map_err(|e| match e {
+            TryLockError::WouldBlock => BackupError::ServerRunning,
+            TryLockError::Error(e) => BackupError::Io(e),
+        })?;
+        Ok(Self { _file: file })
+    }
+
+    fn open(db_path: &Path) -> std::io::Result<File> {
+        File::options().create(true).truncate(false).write(true).open(sibling(db_path, ".lock"))
+    }
+}
+
+/// Filesystem path of a SQLite `DATABASE_URL`, or `None` for in-memory databases
+pub fn sqlite_path(database_url: &str) -> Option<PathBuf> {
+    let rest = database_url
+        .strip_prefix("sqlite://")
+        .or_else(|| database_url.strip_prefix("sqlite:"))?;
+    let path = rest.split('?').next().unwrap_or(rest);
+    if path.is_empty() || path == ":memory:" {
+        return None;
+    }
+    Some(PathBuf::from(path))
+}
+
+/// `path` with `suffix` appended to its file name
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Size and hex SHA-256 of the file at `path`
+pub fn file_sha256(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)?;
+    Ok((size, hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClock;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    async fn file_pool(path: &Path) -> SqlitePool {
+        let options = SqliteConnectOptions::new().filename(path).create_if_missing(true);
+        let pool = SqlitePoolOptions::new().max_connections(4).connect_with(options).await.unwrap();
+        sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY, username TEXT NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    async fn insert_users(pool: &SqlitePool, from: i64, to: i64) {
+        for i in from..to {
+            sqlx::query("INSERT INTO users (id, username) VALUES (?, ?)")
+                .bind(i)
+                .bind(format!("user_{}", i))
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn count_users(path: &Path) -> i64 {
+        let mut conn = SqliteConnectOptions::new().filename(path).read_only(true).connect().await.unwrap();
+        let integrity: String = sqlx::query_scalar("PRAGMA integrity_check").fetch_one(&mut conn).await.unwrap();
+        assert_eq!(integrity, "ok");
+        sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(&mut conn).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_backup_under_concurrent_writes_is_consistent() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = file_pool(&dir.path().join("live.db")).await;
+        insert_users(&pool, 0, 200).await;
+
+        let writing = Arc::new(AtomicBool::new(true));
+        let writer = {
+            let (pool, writing) = (pool.clone(), writing.clone());
+            tokio::spawn(async move {
+                let mut next = 200;
+                while writing.load(Ordering::Relaxed) {
+                    insert_users(&pool, next, next + 1).await;
+                    next += 1;
+                    tokio::task::yield_now().await;
+                }
+                next
+            })
+        };
+
+        let service = BackupService::new(pool.clone(), dir.path().join("backups"), 3, Arc::new(MockClock::new()));
+        let backup = service.create_backup().await.unwrap();
+        writing.store(false, Ordering::Relaxed);
+        let written = writer.await.unwrap();
+
+        let snapshot_rows = count_users(&backup.path).await;
+        assert!((200..=written).contains(&snapshot_rows));
+        assert_eq!(backup.size_bytes, std::fs::metadata(&backup.path).unwrap().len());
+        assert_eq!(backup.sha256, file_sha256(&backup.path).unwrap().1);
+    }
+
+    #[actix_web::test]
+    async fn test_only_the_newest_backups_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = file_pool(&dir.path().join("live.db")).await;
+        let clock = Arc::new(MockClock::new());
+        let service = BackupService::new(pool, dir.path().join("backups"), 2, clock.clone());
+
+        let mut paths = Vec::new();
+        for _ in 0..4 {
+            paths.push(service.create_backup().await.unwrap().path);
+            clock.advance(chrono::Duration::minutes(1));
+        }
+
+        let kept: Vec<bool> = paths.iter().map(|p| p.exists()).collect();
+        assert_eq!(kept, vec![false, false, true, true]);
+    }
+
+    #[actix_web::test]
+    async fn test_restore_replaces_database_and_refuses_while_server_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("live.db");
+        let pool = file_pool(&db_path).await;
+        insert_users(&pool, 0, 10).await;
+        let backup = snapshot(&pool, &dir.path().join("backup.db"), Utc::now()).await.unwrap();
+        insert_users(&pool, 10, 15).await;
+        pool.close().await;
+
+        let server = ServerLock::shared(&db_path).unwrap();
+        assert!(matches!(restore(&db_path, &backup.path, Utc::now()).await, Err(BackupError::ServerRunning)));
+        drop(server);
+
+        let previous = restore(&db_path, &backup.path, Utc::now()).await.unwrap().unwrap();
+        assert_eq!(count_users(&db_path).await, 10);
+        assert_eq!(count_users(&previous).await, 15);
+
+        std::fs::write(dir.path().join("garbage.db"), b"not a database").unwrap();
+        let result = restore(&db_path, &dir.path().join("garbage.db"), Utc::now()).await;
+        assert!(matches!(result, Err(BackupError::InvalidSnapshot(..))));
+    }
+
+    #[test]
+    fn test_sqlite_path_from_url() {
+        assert_eq!(sqlite_path("sqlite:./kenya_fsfvi.db"), Some(PathBuf::from("./kenya_fsfvi.db")));
+        assert_eq!(sqlite_path("sqlite:///var/lib/kfs.db?mode=rwc"), Some(PathBuf::from("/var/lib/kfs.db")));
+        assert_eq!(sqlite_path("sqlite::memory:"), None);
+        assert_eq!(sqlite_path("postgresql://localhost/db"), None);
+    }
+}
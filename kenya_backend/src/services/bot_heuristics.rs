@@ -0,0 +1,161 @@
+use chrono::Duration;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+
+use crate::models::user::LoginRequest;
+use crate::utils::clock::Clock;
+
+/// Fastest a person can plausibly fill in the login form after loading it
+pub const DEFAULT_MIN_FILL_MS: i64 = 1000;
+
+/// Keeps challenge signatures apart from anything else signed with the same secret
+const CHALLENGE_CONTEXT: &[u8] = b"login-challenge";
+
+/// Why a login submission looks scripted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotSignal {
+    /// The hidden `website` field, which people never see, was filled in
+    Honeypot,
+    /// Submitted sooner after the form was issued than a person could type
+    TooFast { elapsed_ms: i64 },
+    /// `form_issued_at` wasn't issued by this server
+    ForgedChallenge,
+}
+
+impl BotSignal {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BotSignal::Honeypot => "honeypot",
+            BotSignal::TooFast { .. } => "too_fast",
+            BotSignal::ForgedChallenge => "forged_challenge",
+        }
+    }
+}
+
+/// Cheap checks for scripted logins. Both rely on optional fields, so a
+/// request that leaves them out is never flagged.
+pub struct BotHeuristics {
+    honeypot_enabled: bool,
+    /// Zero turns the form-fill check off
+    min_fill: Duration,
+    key: Vec<u8>,
+    clock: Arc<dyn Clock>,
+}
+
+impl BotHeuristics {
+    pub fn new(key: &[u8], honeypot_enabled: bool, min_fill_ms: i64, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            honeypot_enabled,
+            min_fill: Duration::milliseconds(min_fill_ms.max(0)),
+            key: key.to_vec(),
+            clock,
+        }
+    }
+
+    /// Shortest fill time accepted without a flag, in milliseconds
+    pub fn min_fill_ms(&self) -> i64 {
+        self.min_fill.num_milliseconds()
+    }
+
+    /// Signed `form_issued_at` value for a login form rendered now
+    pub fn issue_challenge(&self) -> String {
+        let issued_at = self.clock.now().timestamp_millis().to_string();
+        format!("{}.{}", issued_at, self.sign(&issued_at))
+    }
+
+    /// The first bot signal `request` raises, if any
+    pub fn inspect(&self, request: &LoginRequest) -> Option<BotSignal> {
+        if self.honeypot_enabled && request.website.as_deref().is_some_and(|value| !value.is_empty()) {
+            return Some(BotSignal::Honeypot);
+        }
+        if self.min_fill.is_zero() {
+            return None;
+        }
+
+        let challenge = request.form_issued_at.as_deref()?;
+        let Some(issued_at) = self.verify(challenge) else {
+            return Some(BotSignal::ForgedChallenge);
+        };
+        let elapsed_ms = self.clock.now().timestamp_millis() - issued_at;
+        (elapsed_ms < self.min_fill.num_milliseconds()).then_some(BotSignal::TooFast { elapsed_ms })
+    }
+
+    /// Issue time of a challenge this server signed
+    fn verify(&self, challenge: &str) -> Option<i64> {
+        let (issued_at, signature) = challenge.split_once('.')?;
+        let issued_at_ms = issued_at.parse().ok()?;
+        let expected = (0..signature.len())
+            .step_by(2)
+            .map(|i| signature.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()?;
+        // Constant-time comparison
+        self.mac(issued_at).verify_slice(&expected).ok()?;
+        Some(issued_at_ms)
+    }
+
+    fn sign(&self, issued_at: &str) -> String {
+        self.mac(issued_at).finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn mac(&self, issued_at: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC key of any length");
+        mac.update(CHALLENGE_CONTEXT);
+        mac.update(b".");
+        mac.update(issued_at.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClock;
+
+    fn request(website: Option<&str>, form_issued_at: Option<String>) -> LoginRequest {
+        LoginRequest {
+            username: "field_officer".to_string(),
+            password: "Passw0rd!".to_string(),
+            two_fa_code: None,
+            website: website.map(str::to_string),
+            form_issued_at,
+            sign_out_other_sessions: false,
+            client_id: None,
+        }
+    }
+
+    #[test]
+    fn test_requests_without_the_optional_fields_pass() {
+        let heuristics = BotHeuristics::new(b"key", true, DEFAULT_MIN_FILL_MS, Arc::new(MockClock::new()));
+        assert_eq!(heuristics.inspect(&request(None, None)), None);
+        assert_eq!(heuristics.inspect(&request(Some(""), None)), None);
+        assert_eq!(heuristics.inspect(&request(Some("https://spam.example"), None)), Some(BotSignal::Honeypot));
+    }
+
+    #[test]
+    fn test_fill_time_is_measured_from_a_signed_challenge() {
+        let clock = Arc::new(MockClock::new());
+        let heuristics = BotHeuristics::new(b"key", true, DEFAULT_MIN_FILL_MS, clock.clone());
+        let challenge = heuristics.issue_challenge();
+
+        clock.advance(Duration::milliseconds(300));
+        assert_eq!(
+            heuristics.inspect(&request(None, Some(challenge.clone()))),
+            Some(BotSignal::TooFast { elapsed_ms: 300 })
+        );
+        clock.advance(Duration::seconds(4));
+        assert_eq!(heuristics.inspect(&request(None, Some(challenge.clone()))), None);
+
+        // Back-dating the issue time breaks the signature
+        let (_, signature) = challenge.split_once('.').unwrap();
+        let backdated = format!("{}.{}", clock.now().timestamp_millis() - 60_000, signature);
+        assert_eq!(heuristics.inspect(&request(None, Some(backdated))), Some(BotSignal::ForgedChallenge));
+        let other_server = BotHeuristics::new(b"other", true, DEFAULT_MIN_FILL_MS, clock.clone()).issue_challenge();
+        assert_eq!(heuristics.inspect(&request(None, Some(other_server))), Some(BotSignal::ForgedChallenge));
+        assert_eq!(heuristics.inspect(&request(None, Some("soon".to_string()))), Some(BotSignal::ForgedChallenge));
+
+        // Both checks can be turned off
+        let off = BotHeuristics::new(b"key", false, 0, clock.clone());
+        assert_eq!(off.inspect(&request(Some("https://spam.example"), Some("soon".to_string()))), None);
+    }
+}
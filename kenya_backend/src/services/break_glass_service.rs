@@ -0,0 +1,83 @@
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+/// Username of the emergency access account created by `--provision-break-glass`
+pub const BREAK_GLASS_USERNAME: &str = "break_glass";
+
+/// Longest session a break-glass login gets, whatever the configured timeouts
+pub const BREAK_GLASS_MAX_SESSION_MINUTES: i64 = 60;
+
+/// One-time passphrase for the break-glass account
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct BreakGlassCredential {
+    /// Cleared once the credential has been used
+    pub passphrase_hash: Option<String>,
+    pub provisioned_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+/// Break-glass credential store.
+///
+/// Credentials live apart from `users.password_hash` so the ordinary login,
+/// password change and reset paths can never set or reuse them.
+pub struct BreakGlassService {
+    db_pool: SqlitePool,
+}
+
+impl BreakGlassService {
+    pub fn new(db_pool: SqlitePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// The break-glass credential for an account, if it is a break-glass account
+    pub async fn credential_for(&self, user_id: Uuid) -> Result<Option<BreakGlassCredential>, sqlx::Error> {
+        sqlx::query_as::<_, BreakGlassCredential>(
+            "SELECT passphrase_hash, provisioned_at, used_at FROM break_glass_credentials WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Store a fresh passphrase hash, re-arming a used credential
+    pub async fn provision(&self, user_id: Uuid, passphrase_hash: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO break_glass_credentials (user_id, passphrase_hash, provisioned_at, used_at)
+            VALUES (?, ?, ?, NULL)
+            ON CONFLICT (user_id) DO UPDATE
+            SET passphrase_hash = excluded.passphrase_hash,
+                provisioned_at = excluded.provisioned_at,
+                used_at = NULL
+            "#
+        )
+        .bind(user_id)
+        .bind(passphrase_hash)
+        .bind(Utc::now())
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Mark the credential used, disabling it until it is re-provisioned.
+    ///
+    /// Returns `false` when another login consumed it first.
+    pub async fn consume(&self, user_id: Uuid, passphrase_hash: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE break_glass_credentials
+            SET passphrase_hash = NULL, used_at = ?
+            WHERE user_id = ? AND passphrase_hash = ?
+            "#
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .bind(passphrase_hash)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -0,0 +1,397 @@
+use actix_web::http::header::HeaderMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::services::token_service::DEFAULT_TOKEN_AUDIENCE;
+use crate::utils::clock::Clock;
+
+/// How often each instance reloads the registry, so an app changed through
+/// another instance is picked up here too
+pub const CLIENT_APP_REFRESH_SECONDS: u64 = 30;
+
+/// ID of the built-in app, used by clients that name none
+pub const DEFAULT_CLIENT_ID: &str = "default";
+
+/// Header a client names its app with on every request
+pub const CLIENT_ID_HEADER: &str = "X-Client-Id";
+
+/// Request quota of an app's clients, relative to the configured default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitTier {
+    /// A quarter of the default quota
+    Restricted,
+    #[default]
+    Standard,
+    /// Four times the default quota
+    Elevated,
+}
+
+impl RateLimitTier {
+    pub const ALL: [RateLimitTier; 3] = [RateLimitTier::Restricted, RateLimitTier::Standard, RateLimitTier::Elevated];
+
+    /// Tier name as stored in the database and shown to clients
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RateLimitTier::Restricted => "restricted",
+            RateLimitTier::Standard => "standard",
+            RateLimitTier::Elevated => "elevated",
+        }
+    }
+
+    /// Requests per minute for this tier, given the default quota
+    pub fn per_minute(self, standard: u32) -> u32 {
+        match self {
+            RateLimitTier::Restricted => (standard / 4).max(1),
+            RateLimitTier::Standard => standard,
+            RateLimitTier::Elevated => standard.saturating_mul(4),
+        }
+    }
+}
+
+impl FromStr for RateLimitTier {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        RateLimitTier::ALL
+            .into_iter()
+            .find(|tier| tier.as_str() == name)
+            .ok_or_else(|| format!("unknown rate limit tier: {}", name))
+    }
+}
+
+/// An application that signs users in against this backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientApp {
+    /// What the client sends as `client_id` at login and `X-Client-Id` after
+    pub id: String,
+    pub name: String,
+    /// Browser origins allowed besides the configured CORS origins
+    pub allowed_origins: Vec<String>,
+    /// Stamped into the app's tokens as `aud`
+    pub token_audience: String,
+    pub rate_limit_tier: RateLimitTier,
+    pub enabled: bool,
+    /// `None` for the built-in default app
+    pub created_by: Option<Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl ClientApp {
+    /// The built-in app: the configured CORS origins, the original audience
+    /// and the default quota
+    pub fn default_app() -> Self {
+        Self {
+            id: DEFAULT_CLIENT_ID.to_string(),
+            name: "Default".to_string(),
+            allowed_origins: Vec::new(),
+            token_audience: DEFAULT_TOKEN_AUDIENCE.to_string(),
+            rate_limit_tier: RateLimitTier::Standard,
+            enabled: true,
+            created_by: None,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+}
+
+/// An app's settings, as an administrator sets them
+#[derive(Debug, Clone)]
+pub struct ClientAppDraft {
+    pub name: String,
+    pub allowed_origins: Vec<String>,
+    pub token_audience: String,
+    pub rate_limit_tier: RateLimitTier,
+    pub enabled: bool,
+}
+
+/// Why a named app was passed over for the default one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientFallback {
+    Unknown,
+    Disabled,
+}
+
+impl ClientFallback {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClientFallback::Unknown => "unknown",
+            ClientFallback::Disabled => "disabled",
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ClientAppRow {
+    id: String,
+    name: String,
+    allowed_origins: String,
+    token_audience: String,
+    rate_limit_tier: String,
+    enabled: bool,
+    created_by: Uuid,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl ClientAppRow {
+    /// Unreadable origins or an unknown tier are logged and read as none and
+    /// the default tier, so one bad row can't take the registry down
+    fn into_app(self) -> ClientApp {
+        let allowed_origins = serde_json::from_str(&self.allowed_origins).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable allowed_origins of client app {}: {}", self.id, e);
+            Vec::new()
+        });
+        let rate_limit_tier = self.rate_limit_tier.parse().unwrap_or_else(|e| {
+            log::warn!("Client app {}: {}", self.id, e);
+            RateLimitTier::Standard
+        });
+        ClientApp {
+            id: self.id,
+            name: self.name,
+            allowed_origins,
+            token_audience: self.token_audience,
+            rate_limit_tier,
+            enabled: self.enabled,
+            created_by: Some(self.created_by),
+            created_at: Some(self.created_at),
+            updated_at: Some(self.updated_at),
+        }
+    }
+}
+
+const CLIENT_APP_COLUMNS: &str =
+    "id, name, allowed_origins, token_audience, rate_limit_tier, enabled, created_by, created_at, updated_at";
+
+/// Registered client applications, each with its own CORS origins, token
+/// audience and rate limit tier. The CORS, rate limiting and session checks
+/// read an in-process copy, reloaded every `CLIENT_APP_REFRESH_SECONDS` and
+/// after each change made here.
+pub struct ClientRegistry {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    /// Registered apps by ID; the default app is not among them
+    apps: RwLock<BTreeMap<String, ClientApp>>,
+}
+
+impl fmt::Debug for ClientRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientRegistry").field("apps", &self.apps).finish()
+    }
+}
+
+impl ClientRegistry {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock, apps: RwLock::new(BTreeMap::new()) }
+    }
+
+    /// The app registered as `id`, enabled or not
+    pub fn get(&self, id: &str) -> Option<ClientApp> {
+        if id == DEFAULT_CLIENT_ID {
+            return Some(ClientApp::default_app());
+        }
+        self.apps.read().ok().and_then(|apps| apps.get(id).cloned())
+    }
+
+    /// The app a client named, or the default app when it named none. An
+    /// unknown or disabled app is replaced by the default app, with the reason.
+    pub fn resolve(&self, client_id: Option<&str>) -> (ClientApp, Option<ClientFallback>) {
+        match client_id.map(|id| self.get(id)) {
+            None => (ClientApp::default_app(), None),
+            Some(Some(app)) if app.enabled => (app, None),
+            Some(Some(_)) => (ClientApp::default_app(), Some(ClientFallback::Disabled)),
+            Some(None) => (ClientApp::default_app(), Some(ClientFallback::Unknown)),
+        }
+    }
+
+    /// The app a request names in `X-Client-Id`, resolved as by `resolve`
+    pub fn for_request(&self, headers: &HeaderMap) -> ClientApp {
+        self.resolve(headers.get(CLIENT_ID_HEADER).and_then(|value| value.to_str().ok())).0
+    }
+
+    /// Whether an enabled app lists `origin`
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.apps
+            .read()
+            .map(|apps| apps.values().any(|app| app.enabled && app.allowed_origins.iter().any(|allowed| allowed == origin)))
+            .unwrap_or(false)
+    }
+
+    /// Whether an app other than `except` already stamps `audience`
+    pub fn audience_taken(&self, audience: &str, except: Option<&str>) -> bool {
+        audience == DEFAULT_TOKEN_AUDIENCE
+            || self
+                .apps
+                .read()
+                .map(|apps| apps.values().any(|app| app.token_audience == audience && Some(app.id.as_str()) != except))
+                .unwrap_or(false)
+    }
+
+    /// The default app, then every registered one by ID
+    pub fn list(&self) -> Vec<ClientApp> {
+        let registered = self.apps.read().map(|apps| apps.values().cloned().collect::<Vec<_>>()).unwrap_or_default();
+        std::iter::once(ClientApp::default_app()).chain(registered).collect()
+    }
+
+    /// Reload the registered apps from the database
+    pub async fn refresh(&self) -> Result<(), sqlx::Error> {
+        let rows: Vec<ClientAppRow> = sqlx::query_as(&format!("SELECT {} FROM client_apps", CLIENT_APP_COLUMNS))
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        let apps = rows.into_iter().map(|row| (row.id.clone(), row.into_app())).collect();
+        if let Ok(mut current) = self.apps.write() {
+            *current = apps;
+        }
+        Ok(())
+    }
+
+    /// Register an app as `id` on `admin_id`'s behalf
+    pub async fn create(&self, id: &str, draft: &ClientAppDraft, admin_id: Uuid) -> Result<ClientApp, sqlx::Error> {
+        let now = self.clock.now();
+        sqlx::query(
+            r#"
+            INSERT INTO client_apps (id, name, allowed_origins, token_audience, rate_limit_tier, enabled, created_by, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(id)
+        .bind(&draft.name)
+        .bind(origins_json(&draft.allowed_origins))
+        .bind(&draft.token_audience)
+        .bind(draft.rate_limit_tier.as_str())
+        .bind(draft.enabled)
+        .bind(admin_id)
+        .bind(now)
+        .bind(now)
+        .execute(&self.db_pool)
+        .await?;
+
+        self.refresh().await?;
+        self.get(id).ok_or(sqlx::Error::RowNotFound)
+    }
+
+    /// Replace a registered app's settings. Returns the app before and after,
+    /// or `None` when no app is registered as `id`.
+    pub async fn update(&self, id: &str, draft: &ClientAppDraft) -> Result<Option<(ClientApp, ClientApp)>, sqlx::Error> {
+        let Some(previous) = self.load(id).await? else {
+            return Ok(None);
+        };
+        sqlx::query(
+            r#"
+            UPDATE client_apps
+            SET name = ?, allowed_origins = ?, token_audience = ?, rate_limit_tier = ?, enabled = ?, updated_at = ?
+            WHERE id = ?
+            "#
+        )
+        .bind(&draft.name)
+        .bind(origins_json(&draft.allowed_origins))
+        .bind(&draft.token_audience)
+        .bind(draft.rate_limit_tier.as_str())
+        .bind(draft.enabled)
+        .bind(self.clock.now())
+        .bind(id)
+        .execute(&self.db_pool)
+        .await?;
+
+        self.refresh().await?;
+        Ok(self.get(id).map(|app| (previous, app)))
+    }
+
+    /// Remove a registered app. Returns it, or `None` when no app is registered as `id`.
+    pub async fn delete(&self, id: &str) -> Result<Option<ClientApp>, sqlx::Error> {
+        let Some(app) = self.load(id).await? else {
+            return Ok(None);
+        };
+        sqlx::query("DELETE FROM client_apps WHERE id = ?").bind(id).execute(&self.db_pool).await?;
+
+        self.refresh().await?;
+        Ok(Some(app))
+    }
+
+    /// A registered app as stored now, whatever this instance has cached
+    async fn load(&self, id: &str) -> Result<Option<ClientApp>, sqlx::Error> {
+        let row: Option<ClientAppRow> = sqlx::query_as(&format!("SELECT {} FROM client_apps WHERE id = ?", CLIENT_APP_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.db_pool)
+            .await?;
+        Ok(row.map(ClientAppRow::into_app))
+    }
+
+    /// Reload the registry every `CLIENT_APP_REFRESH_SECONDS` in the background
+    pub fn spawn_refresh(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(CLIENT_APP_REFRESH_SECONDS));
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.refresh().await {
+                    log::error!("Failed to refresh client apps: {}", e);
+                }
+            }
+        });
+    }
+}
+
+fn origins_json(origins: &[String]) -> String {
+    serde_json::to_string(origins).unwrap_or_else(|_| "[]".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    fn draft(audience: &str, origin: &str) -> ClientAppDraft {
+        ClientAppDraft {
+            name: "Field app".to_string(),
+            allowed_origins: vec![origin.to_string()],
+            token_audience: audience.to_string(),
+            rate_limit_tier: RateLimitTier::Restricted,
+            enabled: true,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_and_disabled_apps_resolve_to_the_default_app() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let registry = ClientRegistry::new(pool.clone(), clock.clone());
+        let admin = insert_user(&pool, clock.clone(), "clients_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+
+        registry.create("field", &draft("fsfvi-field", "https://field.fsfvi.ai"), admin.id).await.unwrap();
+        let (app, fallback) = registry.resolve(Some("field"));
+        assert_eq!((app.token_audience.as_str(), fallback), ("fsfvi-field", None));
+        assert!(registry.allows_origin("https://field.fsfvi.ai"));
+        assert!(registry.audience_taken("fsfvi-field", None));
+        assert!(!registry.audience_taken("fsfvi-field", Some("field")));
+
+        let (app, fallback) = registry.resolve(Some("kiosk"));
+        assert_eq!((app.id.as_str(), fallback), (DEFAULT_CLIENT_ID, Some(ClientFallback::Unknown)));
+
+        let disabled = ClientAppDraft { enabled: false, ..draft("fsfvi-field", "https://field.fsfvi.ai") };
+        let (before, after) = registry.update("field", &disabled).await.unwrap().unwrap();
+        assert!(before.enabled && !after.enabled);
+        let (app, fallback) = registry.resolve(Some("field"));
+        assert_eq!((app.id.as_str(), fallback), (DEFAULT_CLIENT_ID, Some(ClientFallback::Disabled)));
+        assert!(!registry.allows_origin("https://field.fsfvi.ai"));
+
+        // Another instance sees the app on its next refresh
+        let other = ClientRegistry::new(pool, clock);
+        assert_eq!(other.list().len(), 1);
+        other.refresh().await.unwrap();
+        assert_eq!(other.list().len(), 2);
+
+        assert!(registry.delete("field").await.unwrap().is_some());
+        assert!(registry.delete("field").await.unwrap().is_none());
+        assert!(registry.get("field").is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// A stored configuration snapshot and what changed since the one before it
+#[derive(Debug, Serialize)]
+pub struct ConfigSnapshot {
+    pub id: Uuid,
+    pub config_hash: String,
+    pub recorded_at: DateTime<Utc>,
+    pub config: Value,
+    /// Settings that differ from the previous snapshot; empty for the first one
+    pub changes: Vec<ConfigChange>,
+}
+
+/// One setting that differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigChange {
+    /// Dotted path, e.g. `security.session_timeout_minutes`
+    pub setting: String,
+    /// `null` when the setting is new
+    pub from: Value,
+    /// `null` when the setting was removed
+    pub to: Value,
+}
+
+/// History of the running configuration. Each startup records the effective
+/// non-secret configuration when it differs from the newest stored snapshot.
+pub struct ConfigSnapshotService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    /// This process's configuration
+    current: Value,
+}
+
+impl ConfigSnapshotService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>, current: Value) -> Self {
+        Self { db_pool, clock, current }
+    }
+
+    /// This process's configuration and its hash
+    pub fn current(&self) -> (&Value, String) {
+        (&self.current, config_hash(&self.current))
+    }
+
+    /// Store this process's configuration unless it matches the newest
+    /// snapshot. Returns whether a snapshot was added.
+    pub async fn record_current(&self) -> Result<bool, sqlx::Error> {
+        let hash = config_hash(&self.current);
+        let latest: Option<String> =
+            sqlx::query_scalar("SELECT config_hash FROM config_snapshots ORDER BY recorded_at DESC LIMIT 1")
+                .fetch_optional(&self.db_pool)
+                .await?;
+        if latest.as_deref() == Some(hash.as_str()) {
+            return Ok(false);
+        }
+
+        sqlx::query("INSERT INTO config_snapshots (id, config_hash, config, recorded_at) VALUES (?, ?, ?, ?)")
+            .bind(Uuid::new_v4())
+            .bind(&hash)
+            .bind(self.current.to_string())
+            .bind(self.clock.now())
+            .execute(&self.db_pool)
+            .await?;
+        Ok(true)
+    }
+
+    /// Stored snapshots, newest first, each with its changes from the one before
+    pub async fn history(&self, limit: i64) -> Result<Vec<ConfigSnapshot>, sqlx::Error> {
+        // One more than asked for, so the oldest returned snapshot has something to diff against
+        let rows: Vec<(Uuid, String, String, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT id, config_hash, config, recorded_at FROM config_snapshots ORDER BY recorded_at DESC LIMIT ?",
+        )
+        .bind(limit + 1)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let configs: Vec<Value> = rows
+            .iter()
+            .map(|(_, _, config, _)| serde_json::from_str(config).unwrap_or(Value::Null))
+            .collect();
+        Ok(rows
+            .into_iter()
+            .zip(configs.iter())
+            .enumerate()
+            .take(limit.max(0) as usize)
+            .map(|(i, ((id, config_hash, _, recorded_at), config))| ConfigSnapshot {
+                id,
+                config_hash,
+                recorded_at,
+                config: config.clone(),
+                changes: configs.get(i + 1).map(|previous| diff(previous, config)).unwrap_or_default(),
+            })
+            .collect())
+    }
+}
+
+/// SHA-256 of the configuration's JSON, whose object keys serialize sorted
+pub fn config_hash(config: &Value) -> String {
+    Sha256::digest(config.to_string().as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Settings whose values differ between `before` and `after`, by dotted path
+pub fn diff(before: &Value, after: &Value) -> Vec<ConfigChange> {
+    let (mut before_settings, mut after_settings) = (BTreeMap::new(), BTreeMap::new());
+    flatten("", before, &mut before_settings);
+    flatten("", after, &mut after_settings);
+
+    let mut settings: Vec<&String> = before_settings.keys().chain(after_settings.keys()).collect();
+    settings.sort();
+    settings.dedup();
+    settings
+        .into_iter()
+        .filter_map(|setting| {
+            let from = before_settings.get(setting).cloned().unwrap_or(Value::Null);
+            let to = after_settings.get(setting).cloned().unwrap_or(Value::Null);
+            (from != to).then(|| ConfigChange { setting: setting.clone(), from, to })
+        })
+        .collect()
+}
+
+/// Objects are walked into; anything else, lists included, is one setting
+fn flatten(prefix: &str, value: &Value, settings: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&path, child, settings);
+            }
+        }
+        other => {
+            settings.insert(prefix.to_string(), other.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::test_support::MockClock;
+    use crate::utils::database::test_pool;
+    use chrono::Duration;
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn test_startups_record_only_changed_configuration() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let startup = |config: &AppConfig| ConfigSnapshotService::new(pool.clone(), clock.clone(), config.snapshot());
+
+        let mut config = AppConfig::test_config();
+        assert!(startup(&config).record_current().await.unwrap());
+        clock.advance(Duration::hours(1));
+        assert!(!startup(&config).record_current().await.unwrap());
+
+        clock.advance(Duration::hours(1));
+        config.session_timeout_minutes = 30;
+        config.cors_origins.push("https://kenya.fsfvi.ai".to_string());
+        let restarted = startup(&config);
+        assert!(restarted.record_current().await.unwrap());
+
+        let history = restarted.history(10).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].config_hash, restarted.current().1);
+        assert_eq!(
+            history[0].changes,
+            vec![
+                ConfigChange {
+                    setting: "cors.origins".to_string(),
+                    from: json!(["http://localhost:3000"]),
+                    to: json!(["http://localhost:3000", "https://kenya.fsfvi.ai"]),
+                },
+                ConfigChange {
+                    setting: "security.session_timeout_minutes".to_string(),
+                    from: json!(15),
+                    to: json!(30),
+                },
+            ]
+        );
+        assert!(history[1].changes.is_empty());
+
+        // A page that stops short of the first snapshot still diffs its oldest entry
+        let newest = restarted.history(1).await.unwrap();
+        assert_eq!(newest.len(), 1);
+        assert_eq!(newest[0].changes.len(), 2);
+    }
+
+    #[test]
+    fn test_diff_reports_added_and_removed_settings() {
+        let changes = diff(&json!({ "a": { "b": 1, "c": 2 } }), &json!({ "a": { "b": 1, "d": 3 } }));
+        assert_eq!(
+            changes,
+            vec![
+                ConfigChange { setting: "a.c".to_string(), from: json!(2), to: Value::Null },
+                ConfigChange { setting: "a.d".to_string(), from: Value::Null, to: json!(3) },
+            ]
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::models::context::RequestContext;
+
+/// Largest report body accepted; anything bigger is dropped unread
+pub const MAX_CSP_REPORT_BYTES: usize = 8 * 1024;
+
+/// Identical reports within this window bump one row instead of adding another
+pub const CSP_REPORT_DEDUP_WINDOW_MINUTES: i64 = 60;
+
+/// Longest value kept for any one report field
+const MAX_FIELD_CHARS: usize = 512;
+
+/// Most violations taken from one Reporting API batch
+const MAX_REPORTS_PER_BATCH: usize = 10;
+
+/// One policy violation, normalised from either browser report format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspViolation {
+    pub document_uri: String,
+    pub violated_directive: String,
+    pub effective_directive: Option<String>,
+    pub blocked_uri: Option<String>,
+    pub source_file: Option<String>,
+    pub line_number: Option<i64>,
+    pub column_number: Option<i64>,
+    pub disposition: Option<String>,
+}
+
+impl CspViolation {
+    /// Legacy `report-uri` body: `{"csp-report": {"document-uri": ..., ...}}`
+    fn from_legacy(report: &Value) -> Option<Self> {
+        Self::build(report, [
+            "document-uri",
+            "violated-directive",
+            "effective-directive",
+            "blocked-uri",
+            "source-file",
+            "line-number",
+            "column-number",
+            "disposition",
+        ])
+    }
+
+    /// Reporting API body: `{"type": "csp-violation", "body": {"documentURL": ..., ...}}`.
+    /// It has no separate violated directive, so the effective one stands in.
+    fn from_reporting_api(report: &Value) -> Option<Self> {
+        if report.get("type")?.as_str()? != "csp-violation" {
+            return None;
+        }
+        Self::build(report.get("body")?, [
+            "documentURL",
+            "effectiveDirective",
+            "effectiveDirective",
+            "blockedURL",
+            "sourceFile",
+            "lineNumber",
+            "columnNumber",
+            "disposition",
+        ])
+    }
+
+    fn build(body: &Value, keys: [&str; 8]) -> Option<Self> {
+        let text = |key: &str| {
+            body.get(key)
+                .and_then(Value::as_str)
+                .map(|value| value.trim().chars().take(MAX_FIELD_CHARS).collect::<String>())
+                .filter(|value| !value.is_empty())
+        };
+        let number = |key: &str| body.get(key).and_then(Value::as_i64).filter(|n| *n >= 0);
+
+        Some(Self {
+            document_uri: text(keys[0])?,
+            violated_directive: text(keys[1])?,
+            effective_directive: text(keys[2]),
+            blocked_uri: text(keys[3]),
+            source_file: text(keys[4]),
+            line_number: number(keys[5]),
+            column_number: number(keys[6]),
+            disposition: text(keys[7]),
+        })
+    }
+
+    /// Stable identity of the violation, independent of who reported it
+    fn fingerprint(&self) -> String {
+        let parts = [
+            self.document_uri.as_str(),
+            &self.violated_directive,
+            self.effective_directive.as_deref().unwrap_or(""),
+            self.blocked_uri.as_deref().unwrap_or(""),
+            self.source_file.as_deref().unwrap_or(""),
+            &self.line_number.map(|n| n.to_string()).unwrap_or_default(),
+            &self.column_number.map(|n| n.to_string()).unwrap_or_default(),
+            self.disposition.as_deref().unwrap_or(""),
+        ];
+        Sha256::digest(parts.join("\n").as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Parse a report body in either browser format.
+///
+/// Returns no violations for anything oversized, malformed or not a CSP report.
+pub fn parse_csp_reports(body: &[u8]) -> Vec<CspViolation> {
+    if body.len() > MAX_CSP_REPORT_BYTES {
+        return Vec::new();
+    }
+    let Ok(value) = serde_json::from_slice::<Value>(body) else {
+        return Vec::new();
+    };
+
+    match value {
+        Value::Object(_) => value
+            .get("csp-report")
+            .and_then(CspViolation::from_legacy)
+            .into_iter()
+            .collect(),
+        Value::Array(reports) => reports
+            .iter()
+            .take(MAX_REPORTS_PER_BATCH)
+            .filter_map(CspViolation::from_reporting_api)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// A stored violation as shown to administrators
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct CspReport {
+    pub id: String,
+    pub document_uri: String,
+    pub violated_directive: String,
+    pub effective_directive: Option<String>,
+    pub blocked_uri: Option<String>,
+    pub source_file: Option<String>,
+    pub line_number: Option<i64>,
+    pub column_number: Option<i64>,
+    pub disposition: Option<String>,
+    /// Reporter of the first occurrence
+    pub user_agent: Option<String>,
+    pub ip_address: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// Occurrences folded into this row
+    pub count: i64,
+}
+
+/// Store for browser-submitted Content-Security-Policy violation reports
+pub struct CspReportService {
+    db_pool: SqlitePool,
+}
+
+impl CspReportService {
+    pub fn new(db_pool: SqlitePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Store a violation, folding it into an identical one seen within
+    /// `CSP_REPORT_DEDUP_WINDOW_MINUTES`. Returns `true` when a new row was added.
+    pub async fn record(&self, ctx: &RequestContext, violation: &CspViolation) -> Result<bool, sqlx::Error> {
+        let now = Utc::now();
+        let fingerprint = violation.fingerprint();
+
+        let bumped = sqlx::query(
+            r#"
+            UPDATE csp_reports SET count = count + 1, last_seen_at = ?
+            WHERE id = (
+                SELECT id FROM csp_reports
+                WHERE fingerprint = ? AND first_seen_at > ?
+                ORDER BY first_seen_at DESC LIMIT 1
+            )
+            "#
+        )
+        .bind(now)
+        .bind(&fingerprint)
+        .bind(now - Duration::minutes(CSP_REPORT_DEDUP_WINDOW_MINUTES))
+        .execute(&self.db_pool)
+        .await?;
+        if bumped.rows_affected() > 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO csp_reports (id, fingerprint, document_uri, violated_directive, effective_directive,
+                                     blocked_uri, source_file, line_number, column_number, disposition,
+                                     user_agent, ip_address, first_seen_at, last_seen_at, count)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 1)
+            "#
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(&fingerprint)
+        .bind(&violation.document_uri)
+        .bind(&violation.violated_directive)
+        .bind(&violation.effective_directive)
+        .bind(&violation.blocked_uri)
+        .bind(&violation.source_file)
+        .bind(violation.line_number)
+        .bind(violation.column_number)
+        .bind(&violation.disposition)
+        .bind(&ctx.user_agent)
+        .bind(&ctx.ip_address)
+        .bind(now)
+        .bind(now)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Most recently seen violations first
+    pub async fn list(&self, limit: i64) -> Result<Vec<CspReport>, sqlx::Error> {
+        sqlx::query_as::<_, CspReport>(
+            r#"
+            SELECT id, document_uri, violated_directive, effective_directive, blocked_uri, source_file,
+                   line_number, column_number, disposition, user_agent, ip_address,
+                   first_seen_at, last_seen_at, count
+            FROM csp_reports
+            ORDER BY last_seen_at DESC
+            LIMIT ?
+            "#
+        )
+        .bind(limit)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_legacy_report() {
+        let body = br#"{"csp-report": {
+            "document-uri": "https://fsfvi.go.ke/dashboard",
+            "violated-directive": "script-src-elem",
+            "effective-directive": "script-src-elem",
+            "blocked-uri": "https://cdn.example.com/widget.js",
+            "line-number": 12,
+            "disposition": "enforce"
+        }}"#;
+
+        let reports = parse_csp_reports(body);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].document_uri, "https://fsfvi.go.ke/dashboard");
+        assert_eq!(reports[0].blocked_uri.as_deref(), Some("https://cdn.example.com/widget.js"));
+        assert_eq!(reports[0].line_number, Some(12));
+    }
+
+    #[test]
+    fn test_parses_reporting_api_batch() {
+        let body = br#"[
+            {"type": "csp-violation", "body": {"documentURL": "https://fsfvi.go.ke/", "effectiveDirective": "img-src", "blockedURL": "https://tracker.example.com/p.gif"}},
+            {"type": "deprecation", "body": {"id": "x"}},
+            {"type": "csp-violation", "body": {"effectiveDirective": "img-src"}}
+        ]"#;
+
+        let reports = parse_csp_reports(body);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].violated_directive, "img-src");
+        assert_eq!(reports[0].effective_directive.as_deref(), Some("img-src"));
+    }
+
+    #[test]
+    fn test_malformed_and_oversized_bodies_yield_nothing() {
+        assert!(parse_csp_reports(b"not json").is_empty());
+        assert!(parse_csp_reports(br#"{"csp-report": "nope"}"#).is_empty());
+        assert!(parse_csp_reports(br#"{"csp-report": {"document-uri": "https://fsfvi.go.ke/"}}"#).is_empty());
+        assert!(parse_csp_reports(b"42").is_empty());
+
+        let padding = "a".repeat(MAX_CSP_REPORT_BYTES);
+        let oversized = format!(
+            r#"{{"csp-report": {{"document-uri": "https://fsfvi.go.ke/", "violated-directive": "img-src", "x": "{}"}}}}"#,
+            padding
+        );
+        assert!(parse_csp_reports(oversized.as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_long_fields_are_truncated() {
+        let body = format!(
+            r#"{{"csp-report": {{"document-uri": "https://fsfvi.go.ke/{}", "violated-directive": "img-src"}}}}"#,
+            "a".repeat(2000)
+        );
+        let reports = parse_csp_reports(body.as_bytes());
+        assert_eq!(reports[0].document_uri.chars().count(), MAX_FIELD_CHARS);
+    }
+}
@@ -0,0 +1,509 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{FromRow, SqlitePool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::auth::Severity;
+use crate::services::geoip_service::truncate_ip;
+use crate::utils::clock::Clock;
+
+/// How long an export's download link works after it is generated
+pub const DATA_EXPORT_TTL_MINUTES: i64 = 30;
+
+/// Identifies the layout of the export document
+pub const DATA_EXPORT_FORMAT: &str = "kenya-fsfvi-personal-data/1";
+
+/// Rows read, and stored as one chunk, at a time
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+/// A generated export of one user's personal data
+#[derive(Debug, Clone, Serialize)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub requested_by: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub chunks: i64,
+    pub size_bytes: i64,
+}
+
+/// What a download token came to
+#[derive(Debug)]
+pub enum Download {
+    Ready(DataExport),
+    /// No finished export has this token
+    Unknown,
+    Expired(DataExport),
+}
+
+type DataExportRow = (Uuid, Uuid, Uuid, DateTime<Utc>, DateTime<Utc>, i64, i64);
+
+/// The account itself, without password hashes, 2FA secrets or session tokens
+#[derive(Serialize, FromRow)]
+struct ExportedUser {
+    id: Uuid,
+    username: String,
+    role: String,
+    organization: Option<String>,
+    is_active: bool,
+    is_temporary_password: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    onboarded_at: Option<DateTime<Utc>>,
+    last_login: Option<DateTime<Utc>>,
+    password_changed_at: Option<DateTime<Utc>>,
+    login_attempts: i64,
+    is_locked: bool,
+    lockout_expiry: Option<DateTime<Utc>>,
+    two_fa_enabled: bool,
+    two_fa_enabled_at: Option<DateTime<Utc>>,
+}
+
+/// Rows of one list in the document, read in `rowid` order a batch at a time
+trait Section: for<'r> FromRow<'r, SqliteRow> + Serialize + Send + Unpin {
+    /// Key of the list in the document
+    const NAME: &'static str;
+    /// Takes the user ID, the `rowid` to continue after and the batch size
+    const QUERY: &'static str;
+
+    fn rowid(&self) -> i64;
+
+    /// Cut addresses and locations down to what privacy mode stores, for rows
+    /// recorded before it was turned on
+    fn truncate_ips(&mut self) {}
+}
+
+#[derive(Serialize, FromRow)]
+struct ExportedLoginAttempt {
+    #[serde(skip)]
+    rowid: i64,
+    id: Uuid,
+    username: String,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    success: bool,
+    failure_reason: Option<String>,
+    timestamp: DateTime<Utc>,
+    country_code: Option<String>,
+    region: Option<String>,
+    city: Option<String>,
+    asn: Option<i64>,
+    asn_org: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+impl Section for ExportedLoginAttempt {
+    const NAME: &'static str = "login_attempts";
+    const QUERY: &'static str = r#"
+        SELECT rowid, id, username, ip_address, user_agent, success, failure_reason, timestamp,
+               country_code, region, city, asn, asn_org, latitude, longitude
+        FROM login_attempts WHERE user_id = ? AND rowid > ? ORDER BY rowid LIMIT ?
+    "#;
+
+    fn rowid(&self) -> i64 {
+        self.rowid
+    }
+
+    fn truncate_ips(&mut self) {
+        self.ip_address = self.ip_address.as_deref().map(truncate_ip);
+        self.city = None;
+        self.asn = None;
+        self.asn_org = None;
+        self.latitude = self.latitude.map(f64::round);
+        self.longitude = self.longitude.map(f64::round);
+    }
+}
+
+#[derive(Serialize, FromRow)]
+struct ExportedSecurityEvent {
+    #[serde(skip)]
+    rowid: i64,
+    id: Uuid,
+    event_type: String,
+    description: String,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    success: bool,
+    severity: Severity,
+    timestamp: DateTime<Utc>,
+    #[serde(serialize_with = "embedded_json")]
+    metadata: Option<String>,
+}
+
+impl Section for ExportedSecurityEvent {
+    const NAME: &'static str = "security_events";
+    const QUERY: &'static str = r#"
+        SELECT rowid, id, event_type, description, ip_address, user_agent, success, severity, timestamp, metadata
+        FROM security_events WHERE user_id = ? AND rowid > ? ORDER BY rowid LIMIT ?
+    "#;
+
+    fn rowid(&self) -> i64 {
+        self.rowid
+    }
+
+    fn truncate_ips(&mut self) {
+        self.ip_address = self.ip_address.as_deref().map(truncate_ip);
+    }
+}
+
+/// Sessions, without token IDs or who revoked them
+#[derive(Serialize, FromRow)]
+struct ExportedSession {
+    #[serde(skip)]
+    rowid: i64,
+    id: String,
+    ip_address: String,
+    user_agent: Option<String>,
+    created_at: DateTime<Utc>,
+    last_activity_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+    revoke_reason: Option<String>,
+}
+
+impl Section for ExportedSession {
+    const NAME: &'static str = "sessions";
+    const QUERY: &'static str = r#"
+        SELECT rowid, id, ip_address, user_agent, created_at, last_activity_at, expires_at, revoked_at, revoke_reason
+        FROM sessions WHERE user_id = ? AND rowid > ? ORDER BY rowid LIMIT ?
+    "#;
+
+    fn rowid(&self) -> i64 {
+        self.rowid
+    }
+}
+
+#[derive(Serialize, FromRow)]
+struct ExportedTermsAcceptance {
+    #[serde(skip)]
+    rowid: i64,
+    version: String,
+    accepted_at: DateTime<Utc>,
+    ip_address: String,
+    user_agent: Option<String>,
+}
+
+impl Section for ExportedTermsAcceptance {
+    const NAME: &'static str = "terms_acceptances";
+    const QUERY: &'static str = r#"
+        SELECT rowid, version, accepted_at, ip_address, user_agent
+        FROM terms_acceptances WHERE user_id = ? AND rowid > ? ORDER BY rowid LIMIT ?
+    "#;
+
+    fn rowid(&self) -> i64 {
+        self.rowid
+    }
+}
+
+/// Stored event metadata is JSON text; embed it as JSON where it parses
+fn embedded_json<S: Serializer>(metadata: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match metadata.as_deref().map(|text| serde_json::from_str::<serde_json::Value>(text).map_err(|_| text)) {
+        Some(Ok(value)) => value.serialize(serializer),
+        Some(Err(text)) => serializer.serialize_str(text),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Collects document text and stores it as numbered chunks
+struct ChunkWriter<'a> {
+    db_pool: &'a SqlitePool,
+    export_id: Uuid,
+    buffer: String,
+    chunks: i64,
+    size_bytes: i64,
+}
+
+impl ChunkWriter<'_> {
+    async fn flush(&mut self) -> Result<(), sqlx::Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        sqlx::query("INSERT INTO data_export_chunks (export_id, seq, content) VALUES (?, ?, ?)")
+            .bind(self.export_id)
+            .bind(self.chunks)
+            .bind(&self.buffer)
+            .execute(self.db_pool)
+            .await?;
+        self.chunks += 1;
+        self.size_bytes += self.buffer.len() as i64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Exports of a user's personal data for data subject access requests.
+///
+/// The document is built a batch of rows at a time, each batch stored as one
+/// chunk, and is downloaded chunk by chunk through a random token of which
+/// only the hash is stored. Exports are deleted once their link expires.
+pub struct DataExportService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    /// Truncate login and audit addresses the way privacy mode stores them
+    ip_privacy: bool,
+}
+
+impl DataExportService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock, ip_privacy: false }
+    }
+
+    /// Export login attempts and security events with truncated addresses and
+    /// coarse locations, even those recorded before privacy mode was on
+    pub fn with_ip_privacy(mut self, ip_privacy: bool) -> Self {
+        self.ip_privacy = ip_privacy;
+        self
+    }
+
+    /// Generate an export of `user_id`'s data for `requested_by`. Returns it
+    /// with the download token, or `None` when there is no such user.
+    pub async fn create(&self, user_id: Uuid, requested_by: Uuid) -> Result<Option<(DataExport, String)>, sqlx::Error> {
+        let user: Option<ExportedUser> = sqlx::query_as(
+            r#"
+            SELECT id, username, role, organization, is_active, is_temporary_password, created_at, updated_at,
+                   onboarded_at, last_login, password_changed_at, login_attempts, is_locked, lockout_expiry,
+                   two_fa_enabled, two_fa_enabled_at
+            FROM users WHERE id = ?
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db_pool)
+        .await?;
+        let Some(user) = user else {
+            return Ok(None);
+        };
+        self.purge_expired().await?;
+
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let now = self.clock.now();
+        let mut export = DataExport {
+            id: Uuid::new_v4(),
+            user_id,
+            requested_by,
+            created_at: now,
+            expires_at: now + Duration::minutes(DATA_EXPORT_TTL_MINUTES),
+            chunks: 0,
+            size_bytes: 0,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO data_exports (id, user_id, requested_by, token_hash, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(export.id)
+        .bind(user_id)
+        .bind(requested_by)
+        .bind(token_hash(&token))
+        .bind(export.created_at)
+        .bind(export.expires_at)
+        .execute(&self.db_pool)
+        .await?;
+
+        let mut writer = ChunkWriter {
+            db_pool: &self.db_pool,
+            export_id: export.id,
+            buffer: String::new(),
+            chunks: 0,
+            size_bytes: 0,
+        };
+        writer.buffer = format!(
+            r#"{{"format":{},"export_id":{},"generated_at":{},"user":{}"#,
+            json_text(&DATA_EXPORT_FORMAT),
+            json_text(&export.id),
+            json_text(&now),
+            json_text(&user),
+        );
+        self.write_section::<ExportedLoginAttempt>(&mut writer, user_id).await?;
+        self.write_section::<ExportedSecurityEvent>(&mut writer, user_id).await?;
+        self.write_section::<ExportedSession>(&mut writer, user_id).await?;
+        self.write_section::<ExportedTermsAcceptance>(&mut writer, user_id).await?;
+        writer.buffer.push('}');
+        writer.flush().await?;
+
+        // Only a finished export can be downloaded
+        sqlx::query("UPDATE data_exports SET completed_at = ?, chunks = ?, size_bytes = ? WHERE id = ?")
+            .bind(self.clock.now())
+            .bind(writer.chunks)
+            .bind(writer.size_bytes)
+            .bind(export.id)
+            .execute(&self.db_pool)
+            .await?;
+        export.chunks = writer.chunks;
+        export.size_bytes = writer.size_bytes;
+
+        Ok(Some((export, token)))
+    }
+
+    /// Append `T`'s rows for the user as a list, one stored chunk per batch
+    async fn write_section<T: Section>(&self, writer: &mut ChunkWriter<'_>, user_id: Uuid) -> Result<(), sqlx::Error> {
+        writer.buffer.push_str(&format!(r#","{}":["#, T::NAME));
+        let mut after = 0i64;
+        let mut first = true;
+        loop {
+            let mut rows: Vec<T> = sqlx::query_as(T::QUERY)
+                .bind(user_id)
+                .bind(after)
+                .bind(EXPORT_BATCH_SIZE)
+                .fetch_all(&self.db_pool)
+                .await?;
+            if self.ip_privacy {
+                rows.iter_mut().for_each(T::truncate_ips);
+            }
+            for row in &rows {
+                if !first {
+                    writer.buffer.push(',');
+                }
+                first = false;
+                writer.buffer.push_str(&json_text(row));
+            }
+            match rows.last() {
+                Some(last) if rows.len() as i64 == EXPORT_BATCH_SIZE => {
+                    after = last.rowid();
+                    writer.flush().await?;
+                }
+                _ => break,
+            }
+        }
+        writer.buffer.push(']');
+        Ok(())
+    }
+
+    /// Look up the export behind a download token. A `Ready` export has the
+    /// download counted.
+    pub async fn open(&self, token: &str) -> Result<Download, sqlx::Error> {
+        let row: Option<DataExportRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, requested_by, created_at, expires_at, chunks, size_bytes
+            FROM data_exports WHERE token_hash = ? AND completed_at IS NOT NULL
+            "#
+        )
+        .bind(token_hash(token))
+        .fetch_optional(&self.db_pool)
+        .await?;
+        let Some((id, user_id, requested_by, created_at, expires_at, chunks, size_bytes)) = row else {
+            return Ok(Download::Unknown);
+        };
+        let export = DataExport { id, user_id, requested_by, created_at, expires_at, chunks, size_bytes };
+
+        let now = self.clock.now();
+        if now >= export.expires_at {
+            return Ok(Download::Expired(export));
+        }
+        sqlx::query("UPDATE data_exports SET downloads = downloads + 1, last_downloaded_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(export.id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(Download::Ready(export))
+    }
+
+    /// Chunk `seq` of an export's document
+    pub async fn chunk(&self, export_id: Uuid, seq: i64) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT content FROM data_export_chunks WHERE export_id = ? AND seq = ?")
+            .bind(export_id)
+            .bind(seq)
+            .fetch_optional(&self.db_pool)
+            .await
+    }
+
+    /// Delete exports whose link has expired, so copies of personal data
+    /// don't outlive them
+    async fn purge_expired(&self) -> Result<(), sqlx::Error> {
+        let now = self.clock.now();
+        let mut tx = self.db_pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM data_export_chunks WHERE export_id IN (SELECT id FROM data_exports WHERE expires_at <= ?)",
+        )
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM data_exports WHERE expires_at <= ?").bind(now).execute(&mut *tx).await?;
+        tx.commit().await
+
+    }
+}
+
+fn json_text<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "null".to_string())
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    async fn seed_login_attempts(pool: &SqlitePool, user_id: Uuid, username: &str, count: usize) {
+        let mut tx = pool.begin().await.unwrap();
+        for _ in 0..count {
+            sqlx::query(
+                "INSERT INTO login_attempts (id, user_id, username, ip_address, success, timestamp) VALUES (?, ?, ?, '10.0.0.1', TRUE, ?)",
+            )
+            .bind(Uuid::new_v4())
+            .bind(user_id)
+            .bind(username)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+
+    async fn document(service: &DataExportService, export: &DataExport) -> serde_json::Value {
+        let mut text = String::new();
+        for seq in 0..export.chunks {
+            text.push_str(&service.chunk(export.id, seq).await.unwrap().unwrap());
+        }
+        assert_eq!(text.len() as i64, export.size_bytes);
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_long_histories_are_written_in_batches() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = DataExportService::new(pool.clone(), clock.clone());
+        let busy = insert_user(&pool, clock.clone(), "busy_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let quiet = insert_user(&pool, clock.clone(), "quiet_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let batches = 2 * EXPORT_BATCH_SIZE as usize + 1;
+        seed_login_attempts(&pool, busy.id, "busy_user", batches).await;
+        seed_login_attempts(&pool, quiet.id, "quiet_user", 3).await;
+
+        let (export, token) = service.create(busy.id, busy.id).await.unwrap().unwrap();
+        assert_eq!(export.chunks, 3);
+        let busy_document = document(&service, &export).await;
+        let attempts = busy_document["login_attempts"].as_array().unwrap();
+        assert_eq!(attempts.len(), batches);
+        assert!(attempts.iter().all(|attempt| attempt["username"] == "busy_user"));
+        assert!(matches!(service.open(&token).await.unwrap(), Download::Ready(_)));
+
+        let (quiet_export, _) = service.create(quiet.id, busy.id).await.unwrap().unwrap();
+        assert_eq!(quiet_export.chunks, 1);
+        assert_eq!(document(&service, &quiet_export).await["login_attempts"].as_array().unwrap().len(), 3);
+        assert!(service.create(Uuid::new_v4(), busy.id).await.unwrap().is_none());
+
+        // The next export after the link expires deletes the stored copy
+        clock.advance(Duration::minutes(DATA_EXPORT_TTL_MINUTES));
+        assert!(matches!(service.open(&token).await.unwrap(), Download::Expired(_)));
+        service.create(quiet.id, quiet.id).await.unwrap().unwrap();
+        assert!(matches!(service.open(&token).await.unwrap(), Download::Unknown));
+        assert_eq!(service.chunk(export.id, 0).await.unwrap(), None);
+    }
+}
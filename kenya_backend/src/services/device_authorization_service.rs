@@ -0,0 +1,281 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// How long a kiosk's user code can be approved, and its polling token redeemed
+pub const DEVICE_CODE_TTL_MINUTES: i64 = 2;
+
+/// Longest a kiosk session lasts, whatever the session timeout
+pub const KIOSK_SESSION_MINUTES: i64 = 15;
+
+/// How often a kiosk is asked to poll
+pub const DEVICE_POLL_INTERVAL_SECONDS: i64 = 5;
+
+/// Letters user codes are made of: no vowels, so codes never spell words,
+/// and nothing easily misread off a kiosk screen
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+/// Characters in a user code, shown as two groups of four
+const USER_CODE_LENGTH: usize = 8;
+
+/// Where a kiosk sign-in stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceAuthorizationStatus {
+    /// Waiting for the account owner to approve or deny the user code
+    Pending,
+    /// Approved; the kiosk's next poll gets the session
+    Approved,
+    Denied,
+    /// The kiosk has its session
+    Redeemed,
+}
+
+impl DeviceAuthorizationStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceAuthorizationStatus::Pending => "pending",
+            DeviceAuthorizationStatus::Approved => "approved",
+            DeviceAuthorizationStatus::Denied => "denied",
+            DeviceAuthorizationStatus::Redeemed => "redeemed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pending" => Some(DeviceAuthorizationStatus::Pending),
+            "approved" => Some(DeviceAuthorizationStatus::Approved),
+            "denied" => Some(DeviceAuthorizationStatus::Denied),
+            "redeemed" => Some(DeviceAuthorizationStatus::Redeemed),
+            _ => None,
+        }
+    }
+}
+
+/// A kiosk sign-in, from the kiosk asking for a code to it getting its session
+#[derive(Debug, Clone)]
+pub struct DeviceAuthorization {
+    pub id: Uuid,
+    /// Without the separator, as `normalize_user_code` leaves it
+    pub user_code: String,
+    /// Client app the kiosk started from; only it can redeem the approval
+    pub client_id: String,
+    pub status: DeviceAuthorizationStatus,
+    /// The account that approved or denied it
+    pub user_id: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl DeviceAuthorization {
+    /// The user code as the kiosk shows it, e.g. `BDFG-HJKL`
+    pub fn display_code(&self) -> String {
+        let (first, second) = self.user_code.split_at(self.user_code.len() / 2);
+        format!("{}-{}", first, second)
+    }
+}
+
+/// What a presented user code or polling token came to
+#[derive(Debug)]
+pub enum DeviceLookup {
+    Found(DeviceAuthorization),
+    /// Not yet redeemed, but past `DEVICE_CODE_TTL_MINUTES`
+    Expired,
+    Unknown,
+}
+
+type DeviceAuthorizationRow = (Uuid, String, String, String, Option<Uuid>, DateTime<Utc>);
+
+/// Kiosk sign-ins approved from another device, in the manner of the OAuth
+/// device authorization grant. The kiosk gets a short user code to show and
+/// a polling token to keep; the account owner approves the code from a
+/// signed-in session, and the kiosk's next poll redeems the approval once.
+pub struct DeviceAuthorizationService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl DeviceAuthorizationService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// Start a sign-in for a kiosk of the client app `client_id`. Returns it
+    /// with the polling token, which isn't kept and can't be recovered later.
+    pub async fn start(&self, client_id: &str) -> Result<(DeviceAuthorization, String), sqlx::Error> {
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let polling_token = URL_SAFE_NO_PAD.encode(bytes);
+        let mut rng = rand::rngs::OsRng;
+        let user_code = (0..USER_CODE_LENGTH)
+            .map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+            .collect();
+        let now = self.clock.now();
+        let authorization = DeviceAuthorization {
+            id: Uuid::new_v4(),
+            user_code,
+            client_id: client_id.to_string(),
+            status: DeviceAuthorizationStatus::Pending,
+            user_id: None,
+            expires_at: now + Duration::minutes(DEVICE_CODE_TTL_MINUTES),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO device_authorizations (id, user_code, polling_token_hash, client_id, status, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(authorization.id)
+        .bind(&authorization.user_code)
+        .bind(token_hash(&polling_token))
+        .bind(client_id)
+        .bind(authorization.status.as_str())
+        .bind(now)
+        .bind(authorization.expires_at)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok((authorization, polling_token))
+    }
+
+    /// The sign-in showing `user_code`, typed or scanned. The newest one
+    /// wins should an expired code come round again.
+    pub async fn by_user_code(&self, user_code: &str) -> Result<DeviceLookup, sqlx::Error> {
+        let row = sqlx::query_as(
+            r#"
+            SELECT id, user_code, client_id, status, user_id, expires_at
+            FROM device_authorizations WHERE user_code = ?
+            ORDER BY created_at DESC LIMIT 1
+            "#
+        )
+        .bind(normalize_user_code(user_code))
+        .fetch_optional(&self.db_pool)
+        .await?;
+        Ok(self.lookup(row))
+    }
+
+    /// The sign-in a kiosk polls with `polling_token`
+    pub async fn by_polling_token(&self, polling_token: &str) -> Result<DeviceLookup, sqlx::Error> {
+        let row = sqlx::query_as(
+            r#"
+            SELECT id, user_code, client_id, status, user_id, expires_at
+            FROM device_authorizations WHERE polling_token_hash = ?
+            "#
+        )
+        .bind(token_hash(polling_token))
+        .fetch_optional(&self.db_pool)
+        .await?;
+        Ok(self.lookup(row))
+    }
+
+    fn lookup(&self, row: Option<DeviceAuthorizationRow>) -> DeviceLookup {
+        let Some(authorization) = row.and_then(|(id, user_code, client_id, status, user_id, expires_at)| {
+            Some(DeviceAuthorization {
+                id,
+                user_code,
+                client_id,
+                status: DeviceAuthorizationStatus::from_name(&status)?,
+                user_id,
+                expires_at,
+            })
+        }) else {
+            return DeviceLookup::Unknown;
+        };
+        let open = matches!(authorization.status, DeviceAuthorizationStatus::Pending | DeviceAuthorizationStatus::Approved);
+        if open && self.clock.now() >= authorization.expires_at {
+            return DeviceLookup::Expired;
+        }
+        DeviceLookup::Found(authorization)
+    }
+
+    /// Approve or deny a pending sign-in on behalf of `user_id`. Returns
+    /// `false` when it was decided, or expired, in the meantime.
+    pub async fn decide(&self, authorization: &DeviceAuthorization, user_id: Uuid, approve: bool) -> Result<bool, sqlx::Error> {
+        let status = if approve { DeviceAuthorizationStatus::Approved } else { DeviceAuthorizationStatus::Denied };
+        let now = self.clock.now();
+        Ok(sqlx::query(
+            "UPDATE device_authorizations SET status = ?, user_id = ?, decided_at = ? WHERE id = ? AND status = 'pending' AND expires_at > ?",
+        )
+        .bind(status.as_str())
+        .bind(user_id)
+        .bind(now)
+        .bind(authorization.id)
+        .bind(now)
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Hand an approved sign-in to the kiosk. Returns `false` when another
+    /// poll redeemed it, or it expired, in the meantime.
+    pub async fn redeem(&self, authorization: &DeviceAuthorization) -> Result<bool, sqlx::Error> {
+        Ok(sqlx::query(
+            "UPDATE device_authorizations SET status = 'redeemed' WHERE id = ? AND status = 'approved' AND expires_at > ?",
+        )
+        .bind(authorization.id)
+        .bind(self.clock.now())
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+}
+
+/// A user code as stored: upper case, without the separator or spaces
+fn normalize_user_code(user_code: &str) -> String {
+    user_code.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_uppercase()).collect()
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_sign_ins_are_decided_once_and_redeemed_once() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let user_id = insert_user(&pool, clock.clone(), "kiosk_user", UserRole::KenyaGovernment, TEST_PASSWORD, false).await.id;
+        let service = DeviceAuthorizationService::new(pool, clock.clone());
+
+        let (started, polling_token) = service.start("kiosk").await.unwrap();
+        assert_eq!(started.user_code.len(), USER_CODE_LENGTH);
+        assert!(started.user_code.bytes().all(|c| USER_CODE_ALPHABET.contains(&c)));
+        // Codes are found however they are typed
+        let typed = started.display_code().to_lowercase().replace('-', " - ");
+        let DeviceLookup::Found(found) = service.by_user_code(&typed).await.unwrap() else { panic!("code not found") };
+        assert_eq!(found.id, started.id);
+        assert!(matches!(service.by_user_code("BBBB-BBBB").await.unwrap(), DeviceLookup::Unknown));
+
+        assert!(!service.redeem(&found).await.unwrap());
+        assert!(service.decide(&found, user_id, true).await.unwrap());
+        assert!(!service.decide(&found, user_id, false).await.unwrap());
+        let DeviceLookup::Found(approved) = service.by_polling_token(&polling_token).await.unwrap() else { panic!("token not found") };
+        assert_eq!(approved.status, DeviceAuthorizationStatus::Approved);
+        assert_eq!(approved.user_id, Some(user_id));
+        assert_eq!(approved.client_id, "kiosk");
+        assert!(service.redeem(&approved).await.unwrap());
+        assert!(!service.redeem(&approved).await.unwrap());
+
+        // Past its lifetime an undecided sign-in can't be approved
+        let (late, late_token) = service.start("kiosk").await.unwrap();
+        clock.advance(Duration::minutes(DEVICE_CODE_TTL_MINUTES));
+        assert!(matches!(service.by_polling_token(&late_token).await.unwrap(), DeviceLookup::Expired));
+        assert!(!service.decide(&late, user_id, true).await.unwrap());
+        // A redeemed one stays redeemed
+        let DeviceLookup::Found(redeemed) = service.by_polling_token(&polling_token).await.unwrap() else { panic!("token not found") };
+        assert_eq!(redeemed.status, DeviceAuthorizationStatus::Redeemed);
+    }
+}
@@ -0,0 +1,196 @@
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::permission::Permission;
+use crate::services::shared_state::{InProcessBackend, SharedStateBackend, SharedStateError};
+use crate::utils::clock::Clock;
+
+/// How long a download token can be redeemed for
+pub const DOWNLOAD_TOKEN_TTL_SECONDS: i64 = 60;
+
+/// Query parameter a download token is sent in
+pub const DOWNLOAD_TOKEN_PARAM: &str = "dt";
+
+/// Keeps download token signatures apart from anything else signed with the same secret
+const DOWNLOAD_CONTEXT: &[u8] = b"download-token";
+
+const OPEN_KEY_PREFIX: &str = "download:";
+const USED_KEY_PREFIX: &str = "download_used:";
+
+/// What a download token may fetch. Each is a `GET` a browser opens
+/// directly, so it can't send an `Authorization` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadResource {
+    /// `GET /api/admin/audit/export`
+    AuditExport,
+}
+
+impl DownloadResource {
+    pub const ALL: [DownloadResource; 1] = [DownloadResource::AuditExport];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DownloadResource::AuditExport => "audit_export",
+        }
+    }
+
+    /// Path the token is redeemed at, sent in its `DOWNLOAD_TOKEN_PARAM` query parameter
+    pub fn path(self) -> &'static str {
+        match self {
+            DownloadResource::AuditExport => "/api/admin/audit/export",
+        }
+    }
+
+    /// Required of the session asking for a token, and the only permission the token grants
+    pub fn permission(self) -> Permission {
+        match self {
+            DownloadResource::AuditExport => Permission::AuditExport,
+        }
+    }
+}
+
+impl fmt::Display for DownloadResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DownloadResource {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|resource| resource.as_str() == value)
+            .ok_or_else(|| format!("{:?} is not a downloadable resource", value))
+    }
+}
+
+/// The session a download token was issued on, and what it fetches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadGrant {
+    /// Names the token's markers in the shared state backend
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub session_id: String,
+    /// ID of the session token it was asked for with; reissuing that token ends the grant
+    pub jti: String,
+    /// Token version of the account when issued; a permission change ends the grant
+    pub token_version: i64,
+    pub resource: DownloadResource,
+}
+
+/// Short-lived tokens that stand in for the session token on one download.
+///
+/// A token names its grant and is signed, so nothing but its open marker is
+/// stored. The marker lives in the shared state backend for
+/// `DOWNLOAD_TOKEN_TTL_SECONDS`, so a token issued by one instance can be
+/// redeemed on another, and a token works once. One that turns up in an
+/// access log is no use to anyone once it is used or expired.
+pub struct DownloadTokens {
+    key: Vec<u8>,
+    clock: Arc<dyn Clock>,
+    backend: Arc<dyn SharedStateBackend>,
+}
+
+impl DownloadTokens {
+    pub fn new(key: &[u8], clock: Arc<dyn Clock>) -> Self {
+        Self { key: key.to_vec(), clock, backend: Arc::new(InProcessBackend::default()) }
+    }
+
+    /// Keep the open tokens in `backend` instead of this process's memory
+    pub fn with_backend(mut self, backend: Arc<dyn SharedStateBackend>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Open a grant and return its token and when it expires
+    pub async fn issue(&self, grant: &DownloadGrant) -> Result<(String, DateTime<Utc>), SharedStateError> {
+        let expires_at = self.clock.now() + Duration::seconds(DOWNLOAD_TOKEN_TTL_SECONDS);
+        let payload = format!(
+            "{}.{}.{}.{}.{}.{}.{}",
+            grant.id,
+            grant.user_id,
+            grant.session_id,
+            grant.jti,
+            grant.token_version,
+            grant.resource,
+            expires_at.timestamp(),
+        );
+        self.backend.hold(&open_key(grant.id), ttl()).await?;
+        Ok((format!("{}.{}", payload, self.sign(&payload)), expires_at))
+    }
+
+    /// Use up the token for `resource`. Returns its grant when the token was
+    /// signed here for `resource`, hasn't expired and this is its first use;
+    /// only the first of several racing requests gets it.
+    pub async fn redeem(&self, token: &str, resource: DownloadResource) -> Result<Option<DownloadGrant>, SharedStateError> {
+        let Some((grant, expires_at)) = self.verify(token) else {
+            return Ok(None);
+        };
+        if grant.resource != resource || expires_at <= self.clock.now().timestamp() {
+            return Ok(None);
+        }
+        if self.backend.remaining(&open_key(grant.id)).await?.is_none() {
+            return Ok(None);
+        }
+        let claims = self.backend.increment(&format!("{}{}", USED_KEY_PREFIX, grant.id), ttl()).await?;
+        self.backend.remove(&open_key(grant.id)).await?;
+        Ok((claims == 1).then_some(grant))
+    }
+
+    /// Tokens issued and not yet used or expired
+    pub async fn pending(&self) -> usize {
+        self.backend.count_keys(OPEN_KEY_PREFIX).await.unwrap_or(0)
+    }
+
+    /// The grant a token carries and when it expires, if this server signed it
+    fn verify(&self, token: &str) -> Option<(DownloadGrant, i64)> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let expected = (0..signature.len())
+            .step_by(2)
+            .map(|i| signature.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()?;
+        // Constant-time comparison
+        self.mac(payload).verify_slice(&expected).ok()?;
+
+        let mut parts = payload.split('.');
+        let grant = DownloadGrant {
+            id: Uuid::parse_str(parts.next()?).ok()?,
+            user_id: Uuid::parse_str(parts.next()?).ok()?,
+            session_id: parts.next()?.to_string(),
+            jti: parts.next()?.to_string(),
+            token_version: parts.next()?.parse().ok()?,
+            resource: parts.next()?.parse().ok()?,
+        };
+        let expires_at = parts.next()?.parse().ok()?;
+        parts.next().is_none().then_some((grant, expires_at))
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        self.mac(payload).finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC key of any length");
+        mac.update(DOWNLOAD_CONTEXT);
+        mac.update(b".");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+fn open_key(id: Uuid) -> String {
+    format!("{}{}", OPEN_KEY_PREFIX, id)
+}
+
+fn ttl() -> std::time::Duration {
+    std::time::Duration::from_secs(DOWNLOAD_TOKEN_TTL_SECONDS as u64)
+}
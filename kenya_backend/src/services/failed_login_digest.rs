@@ -0,0 +1,131 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Minutes without a further failure before a user's buffered failures are sent
+pub const DEFAULT_DIGEST_QUIET_MINUTES: i64 = 15;
+
+/// Why a digest was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestTrigger {
+    /// The failures locked the account
+    Locked,
+    /// The owner signed in after the failures
+    LoginSucceeded,
+    /// No further failures arrived for the quiet period
+    QuietPeriod,
+}
+
+impl DigestTrigger {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DigestTrigger::Locked => "locked",
+            DigestTrigger::LoginSucceeded => "login_succeeded",
+            DigestTrigger::QuietPeriod => "quiet_period",
+        }
+    }
+}
+
+/// Failed sign-ins to one account, summarised for a single notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedLoginDigest {
+    pub username: String,
+    pub attempts: u32,
+    /// Distinct client addresses, sorted
+    pub ip_addresses: BTreeSet<String>,
+    pub first_at: DateTime<Utc>,
+    pub last_at: DateTime<Utc>,
+}
+
+/// Per-user buffers of failed sign-ins waiting to be sent as one digest, so a
+/// brute-force run produces one notification rather than one per guess.
+/// Held in process memory; buffers are dropped when they are taken.
+pub struct FailedLoginDigests {
+    quiet_period: Duration,
+    buffers: Mutex<HashMap<Uuid, FailedLoginDigest>>,
+}
+
+impl Default for FailedLoginDigests {
+    fn default() -> Self {
+        Self::new(DEFAULT_DIGEST_QUIET_MINUTES)
+    }
+}
+
+impl FailedLoginDigests {
+    pub fn new(quiet_minutes: i64) -> Self {
+        Self {
+            quiet_period: Duration::minutes(quiet_minutes.max(1)),
+            buffers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add a failed sign-in to the user's buffer
+    pub fn record(&self, user_id: Uuid, username: &str, ip_address: &str, at: DateTime<Utc>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        let digest = buffers.entry(user_id).or_insert_with(|| FailedLoginDigest {
+            username: username.to_string(),
+            attempts: 0,
+            ip_addresses: BTreeSet::new(),
+            first_at: at,
+            last_at: at,
+        });
+        digest.attempts += 1;
+        digest.ip_addresses.insert(ip_address.to_string());
+        digest.last_at = digest.last_at.max(at);
+    }
+
+    /// Remove and return the user's buffered failures, if any
+    pub fn take(&self, user_id: Uuid) -> Option<FailedLoginDigest> {
+        self.buffers.lock().unwrap().remove(&user_id)
+    }
+
+    /// Remove and return every buffer that has had no failures for the quiet period
+    pub fn take_quiet(&self, now: DateTime<Utc>) -> Vec<(Uuid, FailedLoginDigest)> {
+        let mut buffers = self.buffers.lock().unwrap();
+        let quiet: Vec<Uuid> = buffers
+            .iter()
+            .filter(|(_, digest)| now - digest.last_at >= self.quiet_period)
+            .map(|(user_id, _)| *user_id)
+            .collect();
+        quiet
+            .into_iter()
+            .filter_map(|user_id| buffers.remove(&user_id).map(|digest| (user_id, digest)))
+            .collect()
+    }
+
+    /// Users with failures waiting to be sent
+    pub fn pending_users(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_collapses_into_one_buffer_until_quiet() {
+        let digests = FailedLoginDigests::new(15);
+        let user_id = Uuid::new_v4();
+        let start = Utc::now();
+        for (i, ip) in ["197.232.61.4", "197.232.61.5", "197.232.61.4", "41.90.12.7"].iter().enumerate() {
+            digests.record(user_id, "brute_target", ip, start + Duration::minutes(i as i64));
+        }
+        assert_eq!(digests.pending_users(), 1);
+
+        // Fourteen minutes after the last failure isn't quiet yet
+        assert!(digests.take_quiet(start + Duration::minutes(17)).is_empty());
+        let quiet = digests.take_quiet(start + Duration::minutes(18));
+        assert_eq!(quiet.len(), 1);
+        let (id, digest) = &quiet[0];
+        assert_eq!(*id, user_id);
+        assert_eq!(digest.attempts, 4);
+        assert_eq!(digest.ip_addresses.len(), 3);
+        assert_eq!(digest.first_at, start);
+        assert_eq!(digest.last_at, start + Duration::minutes(3));
+
+        assert_eq!(digests.pending_users(), 0);
+        assert_eq!(digests.take(user_id), None);
+    }
+}
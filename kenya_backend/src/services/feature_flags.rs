@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// How often each instance reloads the flags, so a change made through
+/// another instance takes effect here too
+pub const FEATURE_FLAG_REFRESH_SECONDS: u64 = 30;
+
+/// A code path that can be switched off at runtime without a redeploy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// Audit sign-ins from somewhere the account couldn't have travelled to in time
+    ImpossibleTravel,
+    /// Honeypot and form-fill time checks on login submissions
+    LoginBotChecks,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 2] = [Feature::ImpossibleTravel, Feature::LoginBotChecks];
+
+    /// Flag name as stored in the database and shown to clients
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::ImpossibleTravel => "impossible_travel",
+            Feature::LoginBotChecks => "login_bot_checks",
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.as_str() == name)
+            .ok_or_else(|| format!("unknown feature flag: {}", name))
+    }
+}
+
+/// Values the flags take until an administrator overrides them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureDefaults {
+    pub impossible_travel: bool,
+    pub login_bot_checks: bool,
+}
+
+impl FeatureDefaults {
+    pub fn get(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::ImpossibleTravel => self.impossible_travel,
+            Feature::LoginBotChecks => self.login_bot_checks,
+        }
+    }
+}
+
+impl Default for FeatureDefaults {
+    fn default() -> Self {
+        Self { impossible_travel: true, login_bot_checks: true }
+    }
+}
+
+/// An administrator's override of one flag
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Override {
+    enabled: bool,
+    updated_at: DateTime<Utc>,
+    updated_by: Option<Uuid>,
+}
+
+/// A flag's current state, for the admin view
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureFlag {
+    pub name: Feature,
+    pub enabled: bool,
+    /// Value from the configuration, in force while there is no override
+    pub default: bool,
+    pub overridden: bool,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub updated_by: Option<Uuid>,
+}
+
+/// One flag an update changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlagChange {
+    pub flag: Feature,
+    pub from: bool,
+    pub to: bool,
+}
+
+/// Runtime feature flags: configuration defaults, overridden by rows in
+/// `feature_flags`. Reads come from an in-process copy, reloaded every
+/// `FEATURE_FLAG_REFRESH_SECONDS` and after each update.
+pub struct FeatureFlags {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    defaults: FeatureDefaults,
+    overrides: RwLock<BTreeMap<Feature, Override>>,
+}
+
+impl FeatureFlags {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>, defaults: FeatureDefaults) -> Self {
+        Self { db_pool, clock, defaults, overrides: RwLock::new(BTreeMap::new()) }
+    }
+
+    /// Whether `feature`'s code path should run
+    pub fn enabled(&self, feature: Feature) -> bool {
+        self.overrides
+            .read()
+            .ok()
+            .and_then(|overrides| overrides.get(&feature).map(|o| o.enabled))
+            .unwrap_or(self.defaults.get(feature))
+    }
+
+    /// Every flag with its default and override
+    pub fn list(&self) -> Vec<FeatureFlag> {
+        let overrides = self.overrides.read().map(|o| o.clone()).unwrap_or_default();
+        Feature::ALL
+            .into_iter()
+            .map(|feature| {
+                let default = self.defaults.get(feature);
+                let set = overrides.get(&feature);
+                FeatureFlag {
+                    name: feature,
+                    enabled: set.map(|o| o.enabled).unwrap_or(default),
+                    default,
+                    overridden: set.is_some(),
+                    updated_at: set.map(|o| o.updated_at),
+                    updated_by: set.and_then(|o| o.updated_by),
+                }
+            })
+            .collect()
+    }
+
+    /// Reload the overrides from the database. Rows for flags this binary
+    /// doesn't know are skipped with a warning.
+    pub async fn refresh(&self) -> Result<(), sqlx::Error> {
+        let rows: Vec<(String, bool, DateTime<Utc>, Option<Uuid>)> =
+            sqlx::query_as("SELECT name, enabled, updated_at, updated_by FROM feature_flags")
+                .fetch_all(&self.db_pool)
+                .await?;
+
+        let mut overrides = BTreeMap::new();
+        for (name, enabled, updated_at, updated_by) in rows {
+            match name.parse::<Feature>() {
+                Ok(feature) => {
+                    overrides.insert(feature, Override { enabled, updated_at, updated_by });
+                }
+                Err(e) => log::warn!("Ignoring feature_flags row: {}", e),
+            }
+        }
+        if let Ok(mut current) = self.overrides.write() {
+            *current = overrides;
+        }
+        Ok(())
+    }
+
+    /// Override the given flags on `admin_id`'s behalf. Returns the flags
+    /// whose value changed, from their state just before the update.
+    pub async fn set(&self, values: &BTreeMap<Feature, bool>, admin_id: Uuid) -> Result<Vec<FlagChange>, sqlx::Error> {
+        self.refresh().await?;
+        let changes: Vec<FlagChange> = values
+            .iter()
+            .map(|(&flag, &to)| FlagChange { flag, from: self.enabled(flag), to })
+            .filter(|change| change.from != change.to)
+            .collect();
+
+        let now = self.clock.now();
+        let mut tx = self.db_pool.begin().await?;
+        for (feature, enabled) in values {
+            sqlx::query(
+                r#"
+                INSERT INTO feature_flags (name, enabled, updated_at, updated_by) VALUES (?, ?, ?, ?)
+                ON CONFLICT(name) DO UPDATE SET enabled = excluded.enabled, updated_at = excluded.updated_at,
+                                                updated_by = excluded.updated_by
+                "#
+            )
+            .bind(feature.as_str())
+            .bind(enabled)
+            .bind(now)
+            .bind(admin_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.refresh().await?;
+        Ok(changes)
+    }
+
+    /// Reload the flags every `FEATURE_FLAG_REFRESH_SECONDS` in the background
+    pub fn spawn_refresh(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(FEATURE_FLAG_REFRESH_SECONDS));
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.refresh().await {
+                    log::error!("Failed to refresh feature flags: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_overrides_beat_defaults_and_unknown_rows_are_ignored() {
+        let pool = test_pool().await;
+        let defaults = FeatureDefaults { impossible_travel: true, login_bot_checks: false };
+        let clock = Arc::new(MockClock::new());
+        let flags = FeatureFlags::new(pool.clone(), clock.clone(), defaults);
+        assert!(flags.enabled(Feature::ImpossibleTravel));
+        assert!(!flags.enabled(Feature::LoginBotChecks));
+
+        // Written by a newer binary, or by hand
+        sqlx::query("INSERT INTO feature_flags (name, enabled, updated_at) VALUES ('cookie_sessions', 1, ?)")
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let admin = insert_user(&pool, clock.clone(), "flags_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let admin_id = admin.id;
+
+        let values = BTreeMap::from([(Feature::ImpossibleTravel, false), (Feature::LoginBotChecks, false)]);
+        let changes = flags.set(&values, admin_id).await.unwrap();
+        assert_eq!(changes, vec![FlagChange { flag: Feature::ImpossibleTravel, from: true, to: false }]);
+        assert!(!flags.enabled(Feature::ImpossibleTravel));
+
+        // Another instance sees the change on its next refresh
+        let other = FeatureFlags::new(pool, clock, defaults);
+        assert!(other.enabled(Feature::ImpossibleTravel));
+        other.refresh().await.unwrap();
+        let listed = other.list();
+        assert_eq!(listed.len(), 2);
+        assert!(!listed[0].enabled && listed[0].default && listed[0].overridden);
+        assert_eq!(listed[0].updated_by, Some(admin_id));
+    }
+}
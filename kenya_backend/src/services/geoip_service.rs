@@ -0,0 +1,389 @@
+use chrono::{DateTime, Utc};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::models::context::{normalize_ip, parse_ip};
+
+/// Default speed above which consecutive logins count as impossible travel (airliner cruise)
+pub const DEFAULT_MAX_TRAVEL_SPEED_KMH: f64 = 900.0;
+
+/// Distances below this are GeoIP noise, not travel
+const MIN_TRAVEL_DISTANCE_KM: f64 = 200.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Location details resolved from a client IP address
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GeoLocation {
+    pub country_code: Option<String>,
+    /// First-level subdivision: county, state or province
+    pub region: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub asn_org: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+impl GeoLocation {
+    /// Only what privacy mode stores: the country, the region and coordinates
+    /// rounded to whole degrees (about 100 km), which is still enough for
+    /// impossible-travel checks since they ignore anything under 200 km
+    pub fn coarse(self) -> GeoLocation {
+        GeoLocation {
+            country_code: self.country_code,
+            region: self.region,
+            latitude: self.latitude.map(f64::round),
+            longitude: self.longitude.map(f64::round),
+            ..GeoLocation::default()
+        }
+    }
+}
+
+/// Where and when a login happened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoFix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl GeoFix {
+    /// Great-circle (haversine) distance to another fix
+    pub fn distance_km(&self, other: &GeoFix) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let delta_lat = lat2 - lat1;
+        let delta_lon = (other.longitude - self.longitude).to_radians();
+
+        let a = (delta_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (delta_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
+    /// Speed needed to get from this fix to a later one; infinite if no time passed
+    pub fn implied_speed_kmh(&self, later: &GeoFix) -> f64 {
+        let hours = (later.timestamp - self.timestamp).num_milliseconds() as f64 / 3_600_000.0;
+        let distance = self.distance_km(later);
+        if hours <= 0.0 {
+            f64::INFINITY
+        } else {
+            distance / hours
+        }
+    }
+}
+
+/// GeoIP lookups against MaxMind GeoLite2 City and ASN databases.
+///
+/// Both databases are optional and held in memory, so lookups are synchronous
+/// and cheap enough to run inline while logging. Without a database every
+/// lookup simply returns `None`.
+///
+/// In privacy mode the service also decides what of a client's address and
+/// location is persisted: see `stored_ip` and `stored_location`. Lookups and
+/// checks made in memory still see the full address.
+pub struct GeoIpService {
+    city_reader: Option<Reader<Vec<u8>>>,
+    asn_reader: Option<Reader<Vec<u8>>>,
+    allowed_countries: Vec<String>,
+    max_travel_speed_kmh: f64,
+    privacy_mode: bool,
+}
+
+impl GeoIpService {
+    /// Service with no databases loaded; lookups never resolve
+    pub fn disabled() -> Self {
+        Self {
+            city_reader: None,
+            asn_reader: None,
+            allowed_countries: Vec::new(),
+            max_travel_speed_kmh: DEFAULT_MAX_TRAVEL_SPEED_KMH,
+            privacy_mode: false,
+        }
+    }
+
+    /// Load the configured databases. `allowed_countries` are ISO codes; empty allows everywhere
+    pub fn open(
+        city_path: Option<&str>,
+        asn_path: Option<&str>,
+        allowed_countries: Vec<String>,
+    ) -> Result<Self, MaxMindDBError> {
+        Ok(Self {
+            city_reader: city_path.map(Reader::open_readfile).transpose()?,
+            asn_reader: asn_path.map(Reader::open_readfile).transpose()?,
+            allowed_countries: allowed_countries.into_iter().map(|c| c.to_uppercase()).collect(),
+            max_travel_speed_kmh: DEFAULT_MAX_TRAVEL_SPEED_KMH,
+            privacy_mode: false,
+        })
+    }
+
+    /// Override the impossible-travel speed threshold
+    pub fn with_max_travel_speed(mut self, max_travel_speed_kmh: f64) -> Self {
+        self.max_travel_speed_kmh = max_travel_speed_kmh;
+        self
+    }
+
+    /// Persist truncated addresses and coarse locations only
+    pub fn with_privacy_mode(mut self, privacy_mode: bool) -> Self {
+        self.privacy_mode = privacy_mode;
+        self
+    }
+
+    pub fn privacy_mode(&self) -> bool {
+        self.privacy_mode
+    }
+
+    /// The form of a client address that goes into audit rows, login history and
+    /// exports: canonical, and truncated in privacy mode
+    pub fn stored_ip(&self, ip_address: &str) -> String {
+        if self.privacy_mode {
+            truncate_ip(ip_address)
+        } else {
+            normalize_ip(ip_address).unwrap_or_else(|| ip_address.to_string())
+        }
+    }
+
+    /// The full address, kept alongside a truncated one for incident response.
+    /// `None` outside privacy mode, where the stored address is already full.
+    pub fn raw_ip(&self, ip_address: &str) -> Option<String> {
+        self.privacy_mode.then(|| normalize_ip(ip_address).unwrap_or_else(|| ip_address.to_string()))
+    }
+
+    /// The location to persist for a client address; coarse in privacy mode
+    pub fn stored_location(&self, ip_address: &str) -> Option<GeoLocation> {
+        let location = self.lookup(ip_address)?;
+        Some(if self.privacy_mode { location.coarse() } else { location })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.city_reader.is_some() || self.asn_reader.is_some()
+    }
+
+    /// Resolve an IP address; `None` for unparseable, private or unknown addresses
+    pub fn lookup(&self, ip_address: &str) -> Option<GeoLocation> {
+        self.lookup_ip(parse_ip(ip_address)?)
+    }
+
+    /// Resolve a parsed address. IPv4-mapped IPv6 is looked up as the IPv4
+    /// address, which is where the databases keep it.
+    pub fn lookup_ip(&self, ip: IpAddr) -> Option<GeoLocation> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let ip = ip.to_canonical();
+        if !is_publicly_routable(&ip) {
+            return None;
+        }
+
+        let mut location = GeoLocation::default();
+
+        if let Some(reader) = &self.city_reader {
+            if let Ok(city) = reader.lookup::<geoip2::City>(ip) {
+                location.country_code = city.country.and_then(|c| c.iso_code).map(str::to_string);
+                location.region = city
+                    .subdivisions
+                    .and_then(|subdivisions| subdivisions.into_iter().next())
+                    .and_then(|subdivision| subdivision.names)
+                    .and_then(|names| names.get("en").map(|name| name.to_string()));
+                location.latitude = city.location.as_ref().and_then(|l| l.latitude);
+                location.longitude = city.location.as_ref().and_then(|l| l.longitude);
+                location.city = city
+                    .city
+                    .and_then(|c| c.names)
+                    .and_then(|names| names.get("en").map(|name| name.to_string()));
+            }
+        }
+
+        if let Some(reader) = &self.asn_reader {
+            if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) {
+                location.asn = asn.autonomous_system_number;
+                location.asn_org = asn.autonomous_system_organization.map(str::to_string);
+            }
+        }
+
+        if location == GeoLocation::default() {
+            None
+        } else {
+            Some(location)
+        }
+    }
+
+    /// Whether logins from this country need no extra scrutiny
+    pub fn is_country_allowed(&self, country_code: &str) -> bool {
+        self.allowed_countries.is_empty()
+            || self.allowed_countries.iter().any(|c| c.eq_ignore_ascii_case(country_code))
+    }
+
+    /// Whether getting from one login to the next would take an implausible speed
+    pub fn is_impossible_travel(&self, previous: &GeoFix, current: &GeoFix) -> bool {
+        previous.distance_km(current) >= MIN_TRAVEL_DISTANCE_KM
+            && previous.implied_speed_kmh(current) > self.max_travel_speed_kmh
+    }
+}
+
+/// The network an address belongs to: IPv4 cut to its /24 and IPv6 to its
+/// /48, written with the host bits zeroed. IPv4-mapped IPv6 is cut as the
+/// IPv4 address it carries. Anything that isn't an address (`unknown`,
+/// `system`) is returned as it is.
+pub fn truncate_ip(ip_address: &str) -> String {
+    match parse_ip(ip_address) {
+        Some(IpAddr::V4(v4)) => {
+            let [a, b, c, _] = v4.octets();
+            Ipv4Addr::new(a, b, c, 0).to_string()
+        }
+        Some(IpAddr::V6(v6)) => {
+            let [a, b, c, ..] = v6.segments();
+            Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0).to_string()
+        }
+        None => ip_address.to_string(),
+    }
+}
+
+/// Private, loopback, link-local and other non-internet addresses have no location
+fn is_publicly_routable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let shared_address_space = v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64;
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || shared_address_space)
+        }
+        IpAddr::V6(v6) => {
+            let unique_local = (v6.segments()[0] & 0xfe00) == 0xfc00;
+            let link_local = (v6.segments()[0] & 0xffc0) == 0xfe80;
+            !(v6.is_loopback() || v6.is_unspecified() || unique_local || link_local)
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) const TEST_DATABASE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/geoip-test.mmdb");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_service(allowed_countries: Vec<String>) -> GeoIpService {
+        GeoIpService::open(Some(TEST_DATABASE), Some(TEST_DATABASE), allowed_countries).unwrap()
+    }
+
+    #[test]
+    fn test_lookup_resolves_country_city_and_asn() {
+        let service = test_service(Vec::new());
+
+        let location = service.lookup("197.232.61.4").unwrap();
+        assert_eq!(location.country_code.as_deref(), Some("KE"));
+        assert_eq!(location.region.as_deref(), Some("Nairobi County"));
+        assert_eq!(location.city.as_deref(), Some("Nairobi"));
+        assert_eq!(location.asn, Some(33771));
+        assert_eq!(location.asn_org.as_deref(), Some("Safaricom Limited"));
+        assert!(location.latitude.is_some() && location.longitude.is_some());
+
+        // However the address reached us
+        for written in ["::ffff:197.232.61.4", "[::ffff:197.232.61.4]:443", "197.232.61.4:52311"] {
+            assert_eq!(service.lookup(written), Some(location.clone()), "{}", written);
+        }
+    }
+
+    #[test]
+    fn test_private_unknown_and_invalid_addresses_are_unresolved() {
+        let service = test_service(Vec::new());
+
+        assert_eq!(service.lookup("10.0.0.1"), None);
+        assert_eq!(service.lookup("127.0.0.1"), None);
+        assert_eq!(service.lookup("::1"), None);
+        assert_eq!(service.lookup("::ffff:10.0.0.1"), None);
+        assert_eq!(service.lookup("fe80::1%eth0"), None);
+        assert_eq!(service.lookup("8.8.8.8"), None);
+        assert_eq!(service.lookup("unknown"), None);
+    }
+
+    #[test]
+    fn test_disabled_service_resolves_nothing() {
+        let service = GeoIpService::disabled();
+        assert!(!service.is_enabled());
+        assert_eq!(service.lookup("197.232.61.4"), None);
+    }
+
+    #[test]
+    fn test_allowed_countries() {
+        assert!(test_service(Vec::new()).is_country_allowed("GB"));
+
+        let service = test_service(vec!["ke".to_string()]);
+        assert!(service.is_country_allowed("KE"));
+        assert!(!service.is_country_allowed("GB"));
+    }
+
+    #[test]
+    fn test_truncate_ip_keeps_the_network_only() {
+        assert_eq!(truncate_ip("197.232.61.4"), "197.232.61.0");
+        assert_eq!(truncate_ip("2001:db8:85a3:8d3:1319:8a2e:370:7348"), "2001:db8:85a3::");
+        assert_eq!(truncate_ip("fe80::1c2:3ff:fe44:5566%eth0"), "fe80::");
+        // Not `::`, the /48 a mapped address would otherwise fall in
+        assert_eq!(truncate_ip("::ffff:197.232.61.4"), "197.232.61.0");
+        assert_eq!(truncate_ip("unknown"), "unknown");
+    }
+
+    #[test]
+    fn test_privacy_mode_stores_coarse_locations_only() {
+        let service = test_service(Vec::new());
+        assert_eq!(service.stored_ip("197.232.61.4"), "197.232.61.4");
+        assert_eq!(service.raw_ip("197.232.61.4"), None);
+
+        let private = test_service(Vec::new()).with_privacy_mode(true);
+        assert_eq!(private.stored_ip("197.232.61.4"), "197.232.61.0");
+        assert_eq!(private.raw_ip("197.232.61.4").as_deref(), Some("197.232.61.4"));
+        assert_eq!(
+            private.stored_location("197.232.61.4"),
+            Some(GeoLocation {
+                country_code: Some("KE".to_string()),
+                region: Some("Nairobi County".to_string()),
+                latitude: Some(-1.0),
+                longitude: Some(37.0),
+                ..GeoLocation::default()
+            })
+        );
+        // Lookups made in memory are unaffected
+        assert_eq!(private.lookup("197.232.61.4").unwrap().city.as_deref(), Some("Nairobi"));
+    }
+
+    fn fix(latitude: f64, longitude: f64, minutes_ago: i64) -> GeoFix {
+        GeoFix {
+            latitude,
+            longitude,
+            timestamp: Utc::now() - chrono::Duration::minutes(minutes_ago),
+        }
+    }
+
+    #[test]
+    fn test_great_circle_distance() {
+        let nairobi = fix(-1.2833, 36.8167, 0);
+        let london = fix(51.5142, -0.0931, 0);
+
+        let distance = nairobi.distance_km(&london);
+        assert!((distance - 6800.0).abs() < 100.0, "Nairobi-London was {} km", distance);
+        assert!(nairobi.distance_km(&nairobi) < 0.001);
+    }
+
+    #[test]
+    fn test_impossible_travel_threshold() {
+        let service = GeoIpService::disabled();
+        let london = fix(51.5142, -0.0931, 0);
+
+        // ~6800 km in 20 minutes, then in 12 hours (~570 km/h)
+        assert!(service.is_impossible_travel(&fix(-1.2833, 36.8167, 20), &london));
+        assert!(!service.is_impossible_travel(&fix(-1.2833, 36.8167, 12 * 60), &london));
+
+        // A lower threshold catches the slower trip too
+        let strict = GeoIpService::disabled().with_max_travel_speed(500.0);
+        assert!(strict.is_impossible_travel(&fix(-1.2833, 36.8167, 12 * 60), &london));
+
+        // Nearby fixes are GeoIP jitter, however quick
+        let nairobi_suburb = fix(-1.30, 36.90, 0);
+        assert!(!service.is_impossible_travel(&fix(-1.2833, 36.8167, 1), &nairobi_suburb));
+    }
+}
@@ -0,0 +1,320 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::models::audit_event::AuditEventType;
+use crate::models::auth::Severity;
+use crate::models::context::RequestContext;
+use crate::services::audit_service::AuditService;
+use crate::services::webhook_service::{Notifier, Urgency, SIEM_DESTINATION};
+use crate::utils::clock::Clock;
+
+/// How often the background monitor probes the database
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a probe may take before it counts as failed
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Probes slower than this leave the service degraded rather than healthy
+const SLOW_PROBE: Duration = Duration::from_millis(500);
+
+/// Failed probes in a row before the service counts as down
+pub const DOWN_AFTER_FAILURES: u32 = 3;
+
+/// Webhook event type of a health transition
+const WEBHOOK_EVENT: &str = "health_state_changed";
+
+/// Whether the database answers, and how well
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    Healthy,
+    /// Slow, or failing but not yet for long enough to be down
+    Degraded,
+    /// Auth and admin requests are answered with 503 at once
+    Down,
+}
+
+impl HealthState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HealthState::Healthy => "healthy",
+            HealthState::Degraded => "degraded",
+            HealthState::Down => "down",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => HealthState::Healthy,
+            1 => HealthState::Degraded,
+            _ => HealthState::Down,
+        }
+    }
+}
+
+/// A change of health state and the probe that caused it
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthTransition {
+    pub from: HealthState,
+    pub to: HealthState,
+    pub at: DateTime<Utc>,
+    pub reason: String,
+}
+
+/// The service's health as the background probes last saw it, shared by the
+/// `HealthGate` middleware and the health endpoints
+pub struct HealthMonitor {
+    state: AtomicU8,
+    since: RwLock<DateTime<Utc>>,
+    consecutive_failures: AtomicU32,
+    /// Transitions announced but not yet in the audit log, usually because
+    /// the database was what went down
+    unrecorded: Mutex<Vec<HealthTransition>>,
+    /// Notified of every transition when a SIEM destination is configured
+    webhooks: Option<Arc<dyn Notifier>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl HealthMonitor {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            state: AtomicU8::new(HealthState::Healthy as u8),
+            since: RwLock::new(clock.now()),
+            consecutive_failures: AtomicU32::new(0),
+            unrecorded: Mutex::new(Vec::new()),
+            webhooks: None,
+            clock,
+        }
+    }
+
+    /// Send each transition to the `siem` webhook destination, if there is one
+    pub fn with_webhooks(mut self, webhooks: Arc<dyn Notifier>) -> Self {
+        self.webhooks = Some(webhooks).filter(|w| w.has_destination(SIEM_DESTINATION));
+        self
+    }
+
+    pub fn state(&self) -> HealthState {
+        HealthState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    /// When the current state began
+    pub fn since(&self) -> DateTime<Utc> {
+        self.since.read().map(|since| *since).unwrap_or_else(|_| self.clock.now())
+    }
+
+    /// Probe `pool` once, announce a change of state, and write transitions
+    /// to the audit log as soon as it takes them
+    pub async fn check(&self, pool: &SqlitePool, audit: &AuditService) -> HealthState {
+        if let Some(transition) = self.observe(probe(pool).await) {
+            self.announce(&transition);
+            if let Ok(mut unrecorded) = self.unrecorded.lock() {
+                unrecorded.push(transition);
+            }
+        }
+        self.record_transitions(audit).await;
+        self.state()
+    }
+
+    /// Move to the state a probe outcome calls for; the transition, if the state changed
+    fn observe(&self, outcome: Result<Duration, String>) -> Option<HealthTransition> {
+        let (next, reason) = match outcome {
+            Ok(latency) => {
+                self.consecutive_failures.store(0, Ordering::SeqCst);
+                let next = if latency > SLOW_PROBE { HealthState::Degraded } else { HealthState::Healthy };
+                (next, format!("Database answered in {} ms", latency.as_millis()))
+            }
+            Err(e) => {
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+                let next = if failures >= DOWN_AFTER_FAILURES { HealthState::Down } else { HealthState::Degraded };
+                (next, format!("Database probe failed ({} in a row): {}", failures, e))
+            }
+        };
+
+        let from = HealthState::from_u8(self.state.swap(next as u8, Ordering::SeqCst));
+        if from == next {
+            return None;
+        }
+        let at = self.clock.now();
+        if let Ok(mut since) = self.since.write() {
+            *since = at;
+        }
+        Some(HealthTransition { from, to: next, at, reason })
+    }
+
+    /// Log a transition and send it to the SIEM; neither needs the database
+    fn announce(&self, transition: &HealthTransition) {
+        match transition.to {
+            HealthState::Down => log::error!(
+                "HEALTH CRITICAL: service is down, answering auth and admin requests with 503 ({})",
+                transition.reason
+            ),
+            HealthState::Degraded => log::warn!("HEALTH: service is degraded ({})", transition.reason),
+            HealthState::Healthy => log::info!("HEALTH: service recovered from {} ({})", transition.from.as_str(), transition.reason),
+        }
+        if let Some(webhooks) = &self.webhooks {
+            let urgency = if transition.to == HealthState::Down { Urgency::Critical } else { Urgency::Informational };
+            webhooks.clone().notify(SIEM_DESTINATION, WEBHOOK_EVENT, json!(transition), urgency);
+        }
+    }
+
+    /// Write announced transitions to the audit log, oldest first, keeping
+    /// the rest for the next check once a write fails
+    async fn record_transitions(&self, audit: &AuditService) {
+        let pending = match self.unrecorded.lock() {
+            Ok(mut unrecorded) => std::mem::take(&mut *unrecorded),
+            Err(_) => return,
+        };
+        let ctx = RequestContext::new("system", None);
+
+        let mut pending = pending.into_iter();
+        while let Some(transition) = pending.next() {
+            let severity = match transition.to {
+                HealthState::Down => Severity::Critical,
+                _ => AuditEventType::HealthStateChanged.default_severity(),
+            };
+            let written = audit
+                .log_security_event(
+                    &ctx,
+                    None,
+                    AuditEventType::HealthStateChanged,
+                    &format!("Service health changed from {} to {}", transition.from.as_str(), transition.to.as_str()),
+                    transition.to != HealthState::Down,
+                    severity,
+                    Some(json!({
+                        "from": transition.from,
+                        "to": transition.to,
+                        "at": transition.at,
+                        "reason": transition.reason,
+                    })),
+                )
+                .await;
+            if written.is_err() {
+                if let Ok(mut unrecorded) = self.unrecorded.lock() {
+                    let later = std::mem::take(&mut *unrecorded);
+                    unrecorded.push(transition);
+                    unrecorded.extend(pending);
+                    unrecorded.extend(later);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Run a trivial query, timing how long the database takes to answer
+async fn probe(pool: &SqlitePool) -> Result<Duration, String> {
+    let started = Instant::now();
+    match tokio::time::timeout(PROBE_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(Ok(_)) => Ok(started.elapsed()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no answer within {} ms", PROBE_TIMEOUT.as_millis())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header;
+    use actix_web::test::{read_body_json, TestRequest};
+
+    use crate::models::user::UserRole;
+    use crate::services::geoip_service::GeoIpService;
+    use crate::test_support::{bearer, MockClock, TestApp, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    /// `HEALTH_STATE_CHANGED` events oldest first, as (from, to, severity)
+    async fn transitions(audit: &AuditService) -> Vec<(String, String, String)> {
+        let mut events = audit.get_recent_events(100, false, None).await.unwrap();
+        events.retain(|event| event.event_type == AuditEventType::HealthStateChanged);
+        events.reverse();
+        events
+            .into_iter()
+            .map(|event| {
+                let details = event.details.as_ref().unwrap();
+                (details["from"].as_str().unwrap().to_string(), details["to"].as_str().unwrap().to_string(), event.severity.as_str().to_string())
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_a_closed_pool_takes_the_api_down_until_it_answers_again() {
+        let app = TestApp::spawn().await;
+        let admin = app.create_user("health_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let token = app.login_as(&admin, "10.0.0.1").await;
+        let credentials = json!({ "username": admin.username, "password": admin.password });
+        let health = &app.data.health;
+        let audit = app.auth_service().audit_service();
+
+        let probed = test_pool().await;
+        assert_eq!(health.check(&probed, audit).await, HealthState::Healthy);
+        probed.close().await;
+        for expected in [HealthState::Degraded, HealthState::Degraded, HealthState::Down] {
+            app.clock.advance(chrono::Duration::seconds(5));
+            assert_eq!(health.check(&probed, audit).await, expected);
+        }
+
+        // Auth and admin requests are refused at once rather than waiting on the pool
+        let login = TestRequest::post().uri("/api/auth/login").set_json(&credentials);
+        let started = Instant::now();
+        for request in [login, bearer(TestRequest::get().uri("/api/admin/audit"), &token)] {
+            let response = app.call(request).await;
+            assert_eq!(response.status(), 503);
+            assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "5");
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body["error_code"], "service_down");
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // Health and readiness still answer, and say why
+        let body = app.call_json(TestRequest::get().uri("/api/health")).await;
+        assert_eq!((&body["status"], &body["database"]), (&json!("down"), &json!("down")));
+        let response = app.call(TestRequest::get().uri("/api/health/ready")).await;
+        assert_eq!(response.status(), 503);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["ready"], false);
+
+        // The next probe that gets an answer brings everything back
+        app.clock.advance(chrono::Duration::seconds(5));
+        assert_eq!(health.check(&test_pool().await, audit).await, HealthState::Healthy);
+        let login = TestRequest::post().uri("/api/auth/login").set_json(&credentials);
+        assert_eq!(app.call(login).await.status(), 200);
+        assert_eq!(app.call(TestRequest::get().uri("/api/health/ready")).await.status(), 200);
+
+        let expected = [("healthy", "degraded", "warning"), ("degraded", "down", "critical"), ("down", "healthy", "warning")];
+        let expected = expected.map(|(from, to, severity)| (from.to_string(), to.to_string(), severity.to_string()));
+        assert_eq!(transitions(audit).await, expected);
+    }
+
+    #[actix_web::test]
+    async fn test_transitions_the_audit_log_missed_are_written_once_it_answers() {
+        let clock = Arc::new(MockClock::new());
+        let monitor = HealthMonitor::new(clock.clone());
+        let broken = test_pool().await;
+        broken.close().await;
+        let unwritable = AuditService::new(broken.clone(), Arc::new(GeoIpService::disabled()), clock.clone());
+
+        for _ in 0..DOWN_AFTER_FAILURES {
+            clock.advance(chrono::Duration::seconds(5));
+            monitor.check(&broken, &unwritable).await;
+        }
+        assert_eq!(monitor.state(), HealthState::Down);
+        assert_eq!(monitor.unrecorded.lock().unwrap().len(), 2);
+
+        let pool = test_pool().await;
+        let audit = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), clock.clone());
+        clock.advance(chrono::Duration::seconds(5));
+        assert_eq!(monitor.check(&pool, &audit).await, HealthState::Healthy);
+        assert!(monitor.unrecorded.lock().unwrap().is_empty());
+        let recorded = transitions(&audit).await.into_iter().map(|(from, to, _)| format!("{}->{}", from, to)).collect::<Vec<_>>();
+        assert_eq!(recorded, ["healthy->degraded", "degraded->down", "down->healthy"]);
+
+        // A slow answer is degraded without counting as a failure
+        assert_eq!(monitor.observe(Ok(Duration::from_millis(800))).map(|t| t.to), Some(HealthState::Degraded));
+        assert_eq!(monitor.consecutive_failures.load(Ordering::SeqCst), 0);
+    }
+}
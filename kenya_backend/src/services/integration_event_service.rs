@@ -0,0 +1,111 @@
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// Whether an event is new to this server
+#[derive(Debug, PartialEq, Eq)]
+pub enum Claim {
+    /// Claimed for this request, to `complete` or `release`
+    New,
+    /// Already delivered; `user_id` is the account it applied to, `None`
+    /// while another request is still applying it
+    Seen { user_id: Option<Uuid> },
+}
+
+/// The events integrations pushed, by source and the ID the source gave
+/// them, so a redelivery is recognised instead of applied again.
+pub struct IntegrationEventService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl IntegrationEventService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// Claim `event_id` from `source`. Of several requests racing with the
+    /// same event, only one gets `Claim::New`.
+    pub async fn claim(&self, source: &str, event_id: &str, event_type: &str) -> Result<Claim, sqlx::Error> {
+        let claimed = sqlx::query(
+            r#"
+            INSERT INTO processed_integration_events (source, event_id, event_type, processed_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (source, event_id) DO NOTHING
+            "#
+        )
+        .bind(source)
+        .bind(event_id)
+        .bind(event_type)
+        .bind(self.clock.now())
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if claimed {
+            return Ok(Claim::New);
+        }
+
+        let user_id = sqlx::query_scalar("SELECT user_id FROM processed_integration_events WHERE source = ? AND event_id = ?")
+            .bind(source)
+            .bind(event_id)
+            .fetch_one(&self.db_pool)
+            .await?;
+        Ok(Claim::Seen { user_id })
+    }
+
+    /// Record what applying a claimed event did
+    pub async fn complete(&self, source: &str, event_id: &str, user_id: Uuid, outcome: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE processed_integration_events SET user_id = ?, outcome = ?, processed_at = ? WHERE source = ? AND event_id = ?",
+        )
+        .bind(user_id)
+        .bind(outcome)
+        .bind(self.clock.now())
+        .bind(source)
+        .bind(event_id)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Give up a claimed event that wasn't applied, so the source can send it again
+    pub async fn release(&self, source: &str, event_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM processed_integration_events WHERE source = ? AND event_id = ? AND outcome IS NULL")
+            .bind(source)
+            .bind(event_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClock;
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_events_are_claimed_once_per_source() {
+        let pool = test_pool().await;
+        let events = IntegrationEventService::new(pool, Arc::new(MockClock::new()));
+        let user_id = Uuid::new_v4();
+
+        assert_eq!(events.claim("hr", "evt-1", "employee_left").await.unwrap(), Claim::New);
+        assert_eq!(events.claim("hr", "evt-1", "employee_left").await.unwrap(), Claim::Seen { user_id: None });
+        // Event IDs are the source's own
+        assert_eq!(events.claim("payroll", "evt-1", "employee_left").await.unwrap(), Claim::New);
+
+        events.complete("hr", "evt-1", user_id, "deactivated").await.unwrap();
+        events.release("hr", "evt-1").await.unwrap();
+        // Completed, it stays recorded
+        assert_eq!(events.claim("hr", "evt-1", "employee_left").await.unwrap(), Claim::Seen { user_id: Some(user_id) });
+
+        // A released event can be delivered again
+        events.release("payroll", "evt-1").await.unwrap();
+        assert_eq!(events.claim("payroll", "evt-1", "employee_left").await.unwrap(), Claim::New);
+    }
+}
@@ -0,0 +1,273 @@
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::user::UserRole;
+use crate::services::break_glass_service::BREAK_GLASS_USERNAME;
+use crate::services::password_service::{is_bcrypt_hash, HASH_SCHEME_BCRYPT_LEGACY};
+use crate::utils::clock::Clock;
+
+/// Why a line of an import file was skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportProblem {
+    /// Not three fields: username, bcrypt hash and organization
+    Malformed,
+    /// Usernames are 3 to 50 characters, as at sign-in
+    InvalidUsername,
+    /// The hash isn't a bcrypt hash
+    NotBcrypt,
+    /// An account with that username already exists; it is left as it is
+    UsernameTaken,
+}
+
+impl ImportProblem {
+    pub fn message(self) -> &'static str {
+        match self {
+            ImportProblem::Malformed => "expected username,bcrypt_hash,org",
+            ImportProblem::InvalidUsername => "username must be 3 to 50 characters",
+            ImportProblem::NotBcrypt => "hash is not a bcrypt hash",
+            ImportProblem::UsernameTaken => "an account with that username already exists",
+        }
+    }
+}
+
+/// A line of the import file that didn't become an account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedLine {
+    /// 1-based, as an editor shows it
+    pub line: usize,
+    pub username: String,
+    pub problem: ImportProblem,
+}
+
+/// What an import did
+#[derive(Debug, Default)]
+pub struct LegacyImportReport {
+    pub imported: usize,
+    pub skipped: Vec<SkippedLine>,
+    /// Imported accounts, from this import and earlier ones, still on their bcrypt hash
+    pub unmigrated: i64,
+}
+
+/// Accounts brought over from the legacy county portal with the bcrypt
+/// hashes it exported. They sign in with their existing passwords; the
+/// first sign-in re-hashes the password with Argon2.
+pub struct LegacyImportService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl LegacyImportService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// Create an account for each `username,bcrypt_hash,org` line of `csv`.
+    /// A `username,...` header line, blank lines and `#` comments are
+    /// ignored, and an empty org leaves the account without one. Existing
+    /// usernames are reported, never overwritten. All accounts are created
+    /// or none are.
+    pub async fn import(&self, csv: &str) -> Result<LegacyImportReport, sqlx::Error> {
+        let now = self.clock.now();
+        let mut report = LegacyImportReport::default();
+        let mut tx = self.db_pool.begin().await?;
+
+        for (index, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim().trim_matches('"')).collect();
+            if report.imported == 0 && report.skipped.is_empty() && fields.first() == Some(&"username") {
+                continue;
+            }
+            let skip = |problem| SkippedLine {
+                line: index + 1,
+                username: fields.first().copied().unwrap_or_default().to_string(),
+                problem,
+            };
+
+            let [username, hash, organization] = fields[..] else {
+                report.skipped.push(skip(ImportProblem::Malformed));
+                continue;
+            };
+            if !(3..=50).contains(&username.chars().count()) {
+                report.skipped.push(skip(ImportProblem::InvalidUsername));
+                continue;
+            }
+            if !is_bcrypt_hash(hash) {
+                report.skipped.push(skip(ImportProblem::NotBcrypt));
+                continue;
+            }
+            if username == BREAK_GLASS_USERNAME {
+                report.skipped.push(skip(ImportProblem::UsernameTaken));
+                continue;
+            }
+
+            // The legacy password was chosen by its owner, so it isn't temporary
+            // and the account needs no onboarding
+            let inserted = sqlx::query(
+                r#"
+                INSERT INTO users (id, username, password_hash, role, is_temporary_password, created_at, updated_at,
+                                   onboarded_at, organization, hash_scheme)
+                VALUES (?, ?, ?, ?, FALSE, ?, ?, ?, ?, ?)
+                "#
+            )
+            .bind(Uuid::new_v4())
+            .bind(username)
+            .bind(hash)
+            .bind(UserRole::KenyaGovernment.as_str())
+            .bind(now)
+            .bind(now)
+            .bind(now)
+            .bind(Some(organization).filter(|org| !org.is_empty()))
+            .bind(HASH_SCHEME_BCRYPT_LEGACY)
+            .execute(&mut *tx)
+            .await;
+            match inserted {
+                Ok(_) => report.imported += 1,
+                Err(sqlx::Error::Database(db_error)) if db_error.is_unique_violation() => {
+                    report.skipped.push(skip(ImportProblem::UsernameTaken));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        tx.commit().await?;
+        report.unmigrated = unmigrated_count(&self.db_pool).await?;
+        Ok(report)
+    }
+}
+
+/// Imported accounts that haven't signed in since, so still have a bcrypt hash
+pub async fn unmigrated_count(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE hash_scheme = ?")
+        .bind(HASH_SCHEME_BCRYPT_LEGACY)
+        .fetch_one(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::context::RequestContext;
+    use crate::models::user::LoginRequest;
+    use crate::services::password_service::PasswordService;
+    use crate::test_support::{bearer, TestApp, TEST_PASSWORD};
+    use actix_web::test;
+
+    const LEGACY_PASSWORD: &str = "CountyPortal#2019";
+
+    fn login_request(username: &str, password: &str) -> LoginRequest {
+        LoginRequest {
+            username: username.to_string(),
+            password: password.to_string(),
+            two_fa_code: None,
+            website: None,
+            form_issued_at: None,
+            sign_out_other_sessions: false,
+            client_id: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_import_reports_lines_it_skips() {
+        let app = TestApp::spawn().await;
+        app.create_user("existing_officer", UserRole::KenyaGovernment, TEST_PASSWORD, false).await;
+        let hash = bcrypt::hash(LEGACY_PASSWORD, 4).unwrap();
+        let csv = format!(
+            "username,bcrypt_hash,org\n\
+             nakuru_officer,{hash},nakuru\n\
+             \n\
+             # carried over from the 2019 export\n\
+             kisumu_officer,\"{hash}\",\n\
+             existing_officer,{hash},nakuru\n\
+             nakuru_officer,{hash},nakuru\n\
+             md5_officer,5f4dcc3b5aa765d61d8327deb882cf99,nakuru\n\
+             ab,{hash},nakuru\n\
+             only_a_username\n",
+        );
+
+        let report = LegacyImportService::new(app.pool.clone(), app.clock.clone()).import(&csv).await.unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.unmigrated, 2);
+        let skipped: Vec<_> = report.skipped.iter().map(|s| (s.line, s.username.as_str(), s.problem)).collect();
+        assert_eq!(
+            skipped,
+            [
+                (6, "existing_officer", ImportProblem::UsernameTaken),
+                (7, "nakuru_officer", ImportProblem::UsernameTaken),
+                (8, "md5_officer", ImportProblem::NotBcrypt),
+                (9, "ab", ImportProblem::InvalidUsername),
+                (10, "only_a_username", ImportProblem::Malformed),
+            ]
+        );
+
+        // The existing account keeps its own password
+        let ctx = RequestContext::new("197.232.61.4", Some("test-agent"));
+        let auth_service = app.auth_service();
+        assert!(auth_service.authenticate(&ctx, login_request("existing_officer", TEST_PASSWORD)).await.is_ok());
+        let organization: Option<String> = sqlx::query_scalar("SELECT organization FROM users WHERE username = 'kisumu_officer'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(organization, None);
+    }
+
+    #[actix_web::test]
+    async fn test_legacy_password_signs_in_and_is_rehashed_with_argon2() {
+        let app = TestApp::spawn().await;
+        let csv = format!("nakuru_officer,{},nakuru\n", bcrypt::hash(LEGACY_PASSWORD, 4).unwrap());
+        LegacyImportService::new(app.pool.clone(), app.clock.clone()).import(&csv).await.unwrap();
+        let auth_service = app.auth_service();
+        let admin = app.create_user("import_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let admin_token = app.login_as(&admin, "10.0.0.1").await;
+        let summary = || bearer(test::TestRequest::get().uri("/api/admin/audit/summary"), &admin_token);
+        assert_eq!(app.call_json(summary()).await["data"]["unmigrated_legacy_hashes"], 1);
+
+        let ctx = RequestContext::new("197.232.61.4", Some("test-agent"));
+        let wrong = auth_service.authenticate(&ctx, login_request("nakuru_officer", "NotThePassw0rd!")).await;
+        assert!(wrong.is_err());
+        assert_eq!(auth_service.unmigrated_legacy_hashes().await.unwrap(), 1);
+
+        let response = auth_service.authenticate(&ctx, login_request("nakuru_officer", LEGACY_PASSWORD)).await.unwrap();
+        assert!(!response.token.is_empty() && !response.user.is_temporary_password);
+
+        let (hash, scheme): (String, String) =
+            sqlx::query_as("SELECT password_hash, hash_scheme FROM users WHERE username = 'nakuru_officer'")
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        assert!(hash.starts_with("$argon2"), "still {}", hash);
+        assert_eq!(scheme, "argon2");
+        assert_eq!(app.call_json(summary()).await["data"]["unmigrated_legacy_hashes"], 0);
+
+        // The same password goes on working against the new hash
+        auth_service.logout(&ctx, response.user.id.parse().unwrap()).await.unwrap();
+        assert!(auth_service.authenticate(&ctx, login_request("nakuru_officer", LEGACY_PASSWORD)).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_legacy_password_below_the_current_policy_is_still_rehashed() {
+        // Too short, no special character, and a forbidden pattern
+        const WEAK_PASSWORD: &str = "kenya123";
+        assert!(PasswordService::new().validate_password_strength(WEAK_PASSWORD).is_err());
+
+        let app = TestApp::spawn().await;
+        let csv = format!("eldoret_officer,{},eldoret\n", bcrypt::hash(WEAK_PASSWORD, 4).unwrap());
+        LegacyImportService::new(app.pool.clone(), app.clock.clone()).import(&csv).await.unwrap();
+        let auth_service = app.auth_service();
+
+        let ctx = RequestContext::new("197.232.61.4", Some("test-agent"));
+        assert!(auth_service.authenticate(&ctx, login_request("eldoret_officer", WEAK_PASSWORD)).await.is_ok());
+        let (hash, scheme): (String, String) =
+            sqlx::query_as("SELECT password_hash, hash_scheme FROM users WHERE username = 'eldoret_officer'")
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        assert!(hash.starts_with("$argon2"), "still {}", hash);
+        assert_eq!(scheme, "argon2");
+        assert_eq!(auth_service.unmigrated_legacy_hashes().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// How often each instance reloads the lockdown state and ends a lockdown
+/// whose time is up
+pub const LOCKDOWN_REFRESH_SECONDS: u64 = 10;
+
+/// How long a lockdown request waits for a second administrator
+pub const LOCKDOWN_APPROVAL_MINUTES: i64 = 15;
+
+/// How long a lockdown lasts unless configured or requested otherwise
+pub const DEFAULT_LOCKDOWN_MINUTES: i64 = 60;
+
+/// Longest lockdown that can be requested
+pub const MAX_LOCKDOWN_MINUTES: i64 = 24 * 60;
+
+/// A lockdown, from its request to its end
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Lockdown {
+    pub id: Uuid,
+    pub reason: String,
+    pub duration_minutes: i64,
+    pub requested_by: Uuid,
+    pub requested_at: DateTime<Utc>,
+    /// Until when a second administrator can approve the request
+    pub request_expires_at: DateTime<Utc>,
+    pub approved_by: Option<Uuid>,
+    /// Epoch the lockdown started; sessions from earlier epochs stopped working
+    pub auth_epoch: Option<i64>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub ended_at: Option<DateTime<Utc>>,
+    /// `None` for a lockdown that ran its full duration
+    pub ended_by: Option<Uuid>,
+}
+
+/// What a lockdown request came to
+#[derive(Debug)]
+pub enum LockdownRequest {
+    /// Recorded; it starts once another administrator requests it too
+    Pending(Lockdown),
+    /// Another administrator's pending request was approved and is now in force
+    Started(Lockdown),
+    /// The administrator's own request is still waiting for a second one
+    AwaitingApproval(Lockdown),
+    /// A lockdown is in force already
+    AlreadyActive(Lockdown),
+}
+
+/// The state every request checks, kept in memory
+#[derive(Debug, Clone, Default)]
+struct LockdownState {
+    epoch: i64,
+    active: Option<Lockdown>,
+}
+
+/// Emergency lockdowns, which end every session and make each sign-in take
+/// a second factor. Starting one takes two administrators; it ends on its
+/// own after its duration. The auth epoch and the lockdown in force are read
+/// from an in-process copy, reloaded every `LOCKDOWN_REFRESH_SECONDS` and
+/// after each change made here.
+pub struct LockdownService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    state: RwLock<LockdownState>,
+    /// Duration of a lockdown whose request doesn't give one
+    default_minutes: i64,
+}
+
+impl LockdownService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            db_pool,
+            clock,
+            state: RwLock::new(LockdownState::default()),
+            default_minutes: DEFAULT_LOCKDOWN_MINUTES,
+        }
+    }
+
+    /// Last `minutes` when a request doesn't say how long
+    pub fn with_default_minutes(mut self, minutes: i64) -> Self {
+        self.default_minutes = minutes.clamp(1, MAX_LOCKDOWN_MINUTES);
+        self
+    }
+
+    /// Duration of a lockdown whose request doesn't give one
+    pub fn default_minutes(&self) -> i64 {
+        self.default_minutes
+    }
+
+    /// Epoch new sessions are created in. Sessions from an earlier one are refused.
+    pub fn epoch(&self) -> i64 {
+        self.state.read().map(|state| state.epoch).unwrap_or(0)
+    }
+
+    /// The lockdown in force. One past its end time no longer counts, even
+    /// before `finish_expired` records that it ended.
+    pub fn active(&self) -> Option<Lockdown> {
+        let now = self.clock.now();
+        self.state
+            .read()
+            .ok()
+            .and_then(|state| state.active.clone())
+            .filter(|lockdown| lockdown.ends_at.is_some_and(|ends_at| ends_at > now))
+    }
+
+    /// Reload the epoch and the lockdown in force from the database
+    pub async fn refresh(&self) -> Result<(), sqlx::Error> {
+        let epoch: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(auth_epoch), 0) FROM lockdowns")
+            .fetch_one(&self.db_pool)
+            .await?;
+        let active = sqlx::query_as::<_, Lockdown>(
+            "SELECT * FROM lockdowns WHERE started_at IS NOT NULL AND ended_at IS NULL ORDER BY started_at DESC LIMIT 1",
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        if let Ok(mut state) = self.state.write() {
+            *state = LockdownState { epoch, active };
+        }
+        Ok(())
+    }
+
+    /// The request waiting for a second administrator, if any
+    pub async fn pending(&self) -> Result<Option<Lockdown>, sqlx::Error> {
+        sqlx::query_as::<_, Lockdown>(
+            r#"
+            SELECT * FROM lockdowns
+            WHERE started_at IS NULL AND ended_at IS NULL AND request_expires_at > ?
+            ORDER BY requested_at DESC LIMIT 1
+            "#
+        )
+        .bind(self.clock.now())
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Request a lockdown on `admin_id`'s behalf. When another administrator
+    /// already asked for one, this approves and starts theirs, with their
+    /// reason and duration; otherwise it is recorded for a second one to approve.
+    pub async fn request(&self, admin_id: Uuid, reason: &str, duration_minutes: i64) -> Result<LockdownRequest, sqlx::Error> {
+        self.refresh().await?;
+        if let Some(active) = self.active() {
+            return Ok(LockdownRequest::AlreadyActive(active));
+        }
+
+        let now = self.clock.now();
+        let mut tx = self.db_pool.begin().await?;
+        let pending = sqlx::query_as::<_, Lockdown>(
+            r#"
+            SELECT * FROM lockdowns
+            WHERE started_at IS NULL AND ended_at IS NULL AND request_expires_at > ?
+            ORDER BY requested_at DESC LIMIT 1
+            "#
+        )
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let outcome = match pending {
+            Some(pending) if pending.requested_by == admin_id => LockdownRequest::AwaitingApproval(pending),
+            Some(pending) => {
+                let started = sqlx::query_as::<_, Lockdown>(
+                    r#"
+                    UPDATE lockdowns
+                    SET approved_by = ?, started_at = ?, ends_at = ?,
+                        auth_epoch = (SELECT COALESCE(MAX(auth_epoch), 0) + 1 FROM lockdowns)
+                    WHERE id = ? AND started_at IS NULL
+                    RETURNING *
+                    "#
+                )
+                .bind(admin_id)
+                .bind(now)
+                .bind(now + Duration::minutes(pending.duration_minutes))
+                .bind(pending.id)
+                .fetch_optional(&mut *tx)
+                .await?;
+                match started {
+                    Some(started) => LockdownRequest::Started(started),
+                    // Approved by someone else in the meantime
+                    None => LockdownRequest::AlreadyActive(pending),
+                }
+            }
+            None => {
+                let requested = sqlx::query_as::<_, Lockdown>(
+                    r#"
+                    INSERT INTO lockdowns (id, reason, duration_minutes, requested_by, requested_at, request_expires_at)
+                    VALUES (?, ?, ?, ?, ?, ?)
+                    RETURNING *
+                    "#
+                )
+                .bind(Uuid::new_v4())
+                .bind(reason)
+                .bind(duration_minutes)
+                .bind(admin_id)
+                .bind(now)
+                .bind(now + Duration::minutes(LOCKDOWN_APPROVAL_MINUTES))
+                .fetch_one(&mut *tx)
+                .await?;
+                LockdownRequest::Pending(requested)
+            }
+        };
+        tx.commit().await?;
+
+        self.refresh().await?;
+        Ok(outcome)
+    }
+
+    /// End the lockdown in force, or withdraw the request waiting for
+    /// approval, on `admin_id`'s behalf. Returns the one it ended, or `None`
+    /// when there was neither.
+    pub async fn lift(&self, admin_id: Uuid) -> Result<Option<Lockdown>, sqlx::Error> {
+        let now = self.clock.now();
+        let lifted = sqlx::query_as::<_, Lockdown>(
+            r#"
+            UPDATE lockdowns SET ended_at = ?, ended_by = ?
+            WHERE ended_at IS NULL AND (ends_at > ? OR (started_at IS NULL AND request_expires_at > ?))
+            RETURNING *
+            "#
+        )
+        .bind(now)
+        .bind(admin_id)
+        .bind(now)
+        .bind(now)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        self.refresh().await?;
+        // The lockdown in force matters more than a request nobody approved
+        Ok(lifted.iter().find(|lockdown| lockdown.started_at.is_some()).or(lifted.first()).cloned())
+    }
+
+    /// Record the end of every lockdown whose time is up. Returns them, so
+    /// the caller can report each once however many instances run this.
+    pub async fn finish_expired(&self) -> Result<Vec<Lockdown>, sqlx::Error> {
+        let finished = sqlx::query_as::<_, Lockdown>(
+            r#"
+            UPDATE lockdowns SET ended_at = ends_at
+            WHERE started_at IS NOT NULL AND ended_at IS NULL AND ends_at <= ?
+            RETURNING *
+            "#
+        )
+        .bind(self.clock.now())
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        self.refresh().await?;
+        Ok(finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    async fn admins(pool: &SqlitePool, clock: Arc<dyn Clock>) -> (Uuid, Uuid) {
+        let first = insert_user(pool, clock.clone(), "first_admin", UserRole::Admin, TEST_PASSWORD, false).await.id;
+        let second = insert_user(pool, clock, "second_admin", UserRole::Admin, TEST_PASSWORD, false).await.id;
+        (first, second)
+    }
+
+    #[actix_web::test]
+    async fn test_lockdown_starts_once_a_second_admin_approves_and_bumps_the_epoch() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = LockdownService::new(pool.clone(), clock.clone());
+        let (first, second) = admins(&pool, clock.clone()).await;
+
+        let LockdownRequest::Pending(requested) = service.request(first, "credential leak", 60).await.unwrap() else {
+            panic!("expected a pending request");
+        };
+        assert!(service.active().is_none());
+        assert_eq!(service.epoch(), 0);
+        assert!(matches!(service.request(first, "again", 30).await.unwrap(), LockdownRequest::AwaitingApproval(_)));
+
+        let LockdownRequest::Started(started) = service.request(second, "ignored", 5).await.unwrap() else {
+            panic!("expected the lockdown to start");
+        };
+        assert_eq!(started.id, requested.id);
+        assert_eq!(started.reason, "credential leak");
+        assert_eq!(started.approved_by, Some(second));
+        assert_eq!(started.auth_epoch, Some(1));
+        assert_eq!(started.ends_at, Some(clock.now() + Duration::minutes(60)));
+        assert_eq!(service.epoch(), 1);
+        assert_eq!(service.active().map(|active| active.id), Some(requested.id));
+        assert!(matches!(service.request(first, "more", 60).await.unwrap(), LockdownRequest::AlreadyActive(_)));
+
+        // Lifting it keeps the epoch, so the sessions it ended stay ended
+        let lifted = service.lift(second).await.unwrap().unwrap();
+        assert_eq!(lifted.ended_by, Some(second));
+        assert!(service.active().is_none());
+        assert_eq!(service.epoch(), 1);
+        assert!(service.lift(second).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_lockdown_and_unapproved_requests_expire() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = LockdownService::new(pool.clone(), clock.clone());
+        let (first, second) = admins(&pool, clock.clone()).await;
+
+        // Nobody approved in time, so the next request starts over
+        service.request(first, "suspicious logins", 30).await.unwrap();
+        clock.advance(Duration::minutes(LOCKDOWN_APPROVAL_MINUTES));
+        assert!(service.pending().await.unwrap().is_none());
+        assert!(matches!(service.request(second, "suspicious logins", 30).await.unwrap(), LockdownRequest::Pending(_)));
+        assert!(matches!(service.request(first, "", 0).await.unwrap(), LockdownRequest::Started(_)));
+
+        clock.advance(Duration::minutes(29));
+        assert!(service.finish_expired().await.unwrap().is_empty());
+        assert!(service.active().is_some());
+
+        // Past its end it stops counting at once, and is recorded as ended once
+        clock.advance(Duration::minutes(1));
+        assert!(service.active().is_none());
+        let finished = service.finish_expired().await.unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].ended_at, finished[0].ends_at);
+        assert_eq!(finished[0].ended_by, None);
+        assert!(service.finish_expired().await.unwrap().is_empty());
+        assert_eq!(service.epoch(), 1);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::models::context::{parse_ip, IpNetwork};
+
+/// Account tag that, on an account listed in `LOCKOUT_EXEMPT_USERNAMES`,
+/// exempts it from lockout
+pub const MONITORING_TAG: &str = "monitoring";
+
+/// Most usernames `LOCKOUT_EXEMPT_USERNAMES` may list
+pub const MAX_EXEMPT_USERNAMES: usize = 5;
+
+/// Most networks `LOCKOUT_EXEMPT_CIDRS` may list
+pub const MAX_EXEMPT_NETWORKS: usize = 10;
+
+/// Widest networks that may be exempted: a /24 of IPv4 or a /64 of IPv6
+const MIN_IPV4_PREFIX: u8 = 24;
+const MIN_IPV6_PREFIX: u8 = 64;
+
+/// Monitoring accounts whose failed sign-ins neither lock the account nor
+/// count toward the throttle, optionally only from the networks their
+/// probes run in. They are audited like any other sign-in, and the password
+/// is always checked: an exemption only decides what a failure counts toward.
+#[derive(Debug, Clone, Default)]
+pub struct LockoutExemptions {
+    /// Exempt only while the account also carries `MONITORING_TAG`
+    usernames: Vec<String>,
+    /// Where the listed accounts must sign in from to be exempt; anywhere when empty
+    networks: Vec<IpNetwork>,
+}
+
+impl LockoutExemptions {
+    pub fn new(usernames: &[String], networks: &[String]) -> Result<Self, String> {
+        if usernames.len() > MAX_EXEMPT_USERNAMES {
+            return Err(format!(
+                "LOCKOUT_EXEMPT_USERNAMES lists {} accounts; at most {} may be exempted",
+                usernames.len(),
+                MAX_EXEMPT_USERNAMES
+            ));
+        }
+        if networks.len() > MAX_EXEMPT_NETWORKS {
+            return Err(format!(
+                "LOCKOUT_EXEMPT_CIDRS lists {} networks; at most {} may be exempted",
+                networks.len(),
+                MAX_EXEMPT_NETWORKS
+            ));
+        }
+        let networks: Vec<IpNetwork> = networks
+            .iter()
+            .map(|network| network.parse())
+            .collect::<Result<_, String>>()
+            .map_err(|e| format!("LOCKOUT_EXEMPT_CIDRS: {}", e))?;
+        for network in &networks {
+            let narrowest_allowed = if network.is_ipv4() { MIN_IPV4_PREFIX } else { MIN_IPV6_PREFIX };
+            if network.prefix() < narrowest_allowed {
+                return Err(format!(
+                    "LOCKOUT_EXEMPT_CIDRS: {} is wider than the /{} allowed for an exemption",
+                    network, narrowest_allowed
+                ));
+            }
+        }
+        Ok(Self { usernames: usernames.to_vec(), networks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.usernames.is_empty() && self.networks.is_empty()
+    }
+
+    /// Whether `ip_address` is in an exempted network
+    pub fn covers_ip(&self, ip_address: &str) -> bool {
+        match parse_ip(ip_address) {
+            Some(ip) => self.networks.iter().any(|network| network.contains(ip)),
+            None => false,
+        }
+    }
+
+    /// Whether a listed account may be exempted when signing in from
+    /// `ip_address`: from anywhere without exempted networks, otherwise only
+    /// from one of them
+    pub fn allows_source(&self, ip_address: &str) -> bool {
+        self.networks.is_empty() || self.covers_ip(ip_address)
+    }
+
+    /// Whether `username` is listed; the account must carry `MONITORING_TAG` too
+    pub fn lists_username(&self, username: &str) -> bool {
+        self.usernames.iter().any(|listed| listed.eq_ignore_ascii_case(username))
+    }
+
+    pub fn usernames(&self) -> &[String] {
+        &self.usernames
+    }
+
+    pub fn networks(&self) -> &[IpNetwork] {
+        &self.networks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_networks_match_by_prefix_and_wide_ones_are_refused() {
+        let exemptions = LockoutExemptions::new(
+            &["uptime_probe".to_string()],
+            &["196.201.214.0/24".to_string(), "41.90.64.17".to_string(), "2c0f:fe38:2001::/64".to_string()],
+        )
+        .unwrap();
+        assert!(exemptions.covers_ip("196.201.214.200"));
+        assert!(!exemptions.covers_ip("196.201.215.1"));
+        assert!(exemptions.covers_ip("41.90.64.17"));
+        assert!(!exemptions.covers_ip("41.90.64.18"));
+        assert!(exemptions.covers_ip("::ffff:196.201.214.9"));
+        assert!(exemptions.covers_ip("2c0f:fe38:2001::42"));
+        assert!(!exemptions.covers_ip("2c0f:fe38:2002::42"));
+        assert!(exemptions.covers_ip("[2c0f:fe38:2001::42]:443"));
+        assert!(exemptions.covers_ip("2c0f:fe38:2001::42%wwan0"));
+        assert!(exemptions.covers_ip("196.201.214.200:52311"));
+        assert!(!exemptions.covers_ip("unknown"));
+        assert!(exemptions.lists_username("Uptime_Probe"));
+        assert_eq!(
+            exemptions.networks().iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["196.201.214.0/24", "41.90.64.17", "2c0f:fe38:2001::/64"]
+        );
+
+        // A /23 or anything wider would quietly exempt a whole provider
+        assert!(LockoutExemptions::new(&[], &["196.201.214.0/23".to_string()]).is_err());
+        assert!(LockoutExemptions::new(&[], &["0.0.0.0/0".to_string()]).is_err());
+        assert!(LockoutExemptions::new(&[], &["2c0f:fe38::/32".to_string()]).is_err());
+        assert!(LockoutExemptions::new(&[], &["10.0.0.0/33".to_string()]).is_err());
+        assert!(LockoutExemptions::new(&[], &["monitoring-host".to_string()]).is_err());
+        let too_many: Vec<String> = (0..=MAX_EXEMPT_USERNAMES).map(|i| format!("probe_{}", i)).collect();
+        assert!(LockoutExemptions::new(&too_many, &[]).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use chrono::Duration;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// How long a second-factor challenge can be answered after the password checked out
+pub const LOGIN_CHALLENGE_TTL_MINUTES: i64 = 5;
+
+/// Wrong codes a challenge takes before it stops working and the password must be entered again
+pub const MAX_CHALLENGE_CODE_ATTEMPTS: i64 = 5;
+
+/// A login waiting for its second factor
+#[derive(Debug, Clone)]
+pub struct LoginChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Session the password step set up on the account; the login completes into it
+    pub session_id: String,
+    pub sign_out_other_sessions: bool,
+    /// Digest of the code emailed for this login, for an account without 2FA
+    /// that has to give a second factor during a lockdown
+    pub email_code_digest: Option<String>,
+    /// Client app the login started from
+    pub client_id: String,
+}
+
+type LoginChallengeRow = (Uuid, Uuid, String, bool, Option<String>, String);
+
+/// Challenges issued between the password and the second-factor steps of a
+/// login. Challenges are stored under the digest of their token, so the
+/// caller hashes before calling in; issuing one expires the account's
+/// earlier ones.
+pub struct LoginChallengeService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl LoginChallengeService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// Store a challenge for `user_id` under `token_digest`, answered with
+    /// the emailed code behind `email_code_digest` when there is one, for a
+    /// login from the client app `client_id`
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        token_digest: &str,
+        session_id: &str,
+        sign_out_other_sessions: bool,
+        email_code_digest: Option<&str>,
+        client_id: &str,
+    ) -> Result<LoginChallenge, sqlx::Error> {
+        let now = self.clock.now();
+        let challenge = LoginChallenge {
+            id: Uuid::new_v4(),
+            user_id,
+            session_id: session_id.to_string(),
+            sign_out_other_sessions,
+            email_code_digest: email_code_digest.map(str::to_string),
+            client_id: client_id.to_string(),
+        };
+
+        let mut tx = self.db_pool.begin().await?;
+        sqlx::query("UPDATE login_challenges SET expires_at = ? WHERE user_id = ? AND used_at IS NULL AND expires_at > ?")
+            .bind(now)
+            .bind(user_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO login_challenges (id, user_id, token_digest, session_id, sign_out_other_sessions, email_code_digest, client_id, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(challenge.id)
+        .bind(user_id)
+        .bind(token_digest)
+        .bind(session_id)
+        .bind(sign_out_other_sessions)
+        .bind(email_code_digest)
+        .bind(client_id)
+        .bind(now)
+        .bind(now + Duration::minutes(LOGIN_CHALLENGE_TTL_MINUTES))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(challenge)
+    }
+
+    /// The challenge stored under `token_digest`, if it can still be answered
+    pub async fn find_open(&self, token_digest: &str) -> Result<Option<LoginChallenge>, sqlx::Error> {
+        let row: Option<LoginChallengeRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, session_id, sign_out_other_sessions, email_code_digest, client_id
+            FROM login_challenges
+            WHERE token_digest = ? AND used_at IS NULL AND expires_at > ? AND failed_codes < ?
+            "#
+        )
+        .bind(token_digest)
+        .bind(self.clock.now())
+        .bind(MAX_CHALLENGE_CODE_ATTEMPTS)
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(row.map(|(id, user_id, session_id, sign_out_other_sessions, email_code_digest, client_id)| LoginChallenge {
+            id,
+            user_id,
+            session_id,
+            sign_out_other_sessions,
+            email_code_digest,
+            client_id,
+        }))
+    }
+
+    /// Count a wrong code against the challenge
+    pub async fn record_failed_code(&self, challenge: &LoginChallenge) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE login_challenges SET failed_codes = failed_codes + 1 WHERE id = ?")
+            .bind(challenge.id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Use a challenge `find_open` returned. Returns `false` when another
+    /// request answered it, or it expired, in the meantime.
+    pub async fn consume(&self, challenge: &LoginChallenge) -> Result<bool, sqlx::Error> {
+        let now = self.clock.now();
+        Ok(sqlx::query(
+            "UPDATE login_challenges SET used_at = ? WHERE id = ? AND used_at IS NULL AND expires_at > ? AND failed_codes < ?",
+        )
+        .bind(now)
+        .bind(challenge.id)
+        .bind(now)
+        .bind(MAX_CHALLENGE_CODE_ATTEMPTS)
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_challenges_work_once_and_supersede_each_other() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = LoginChallengeService::new(pool.clone(), clock.clone());
+        let user_id = insert_user(&pool, clock, "two-step", UserRole::KenyaGovernment, TEST_PASSWORD, false).await.id;
+
+        service.create(user_id, "first", "session-1", false, None, "default").await.unwrap();
+        let issued = service.create(user_id, "second", "session-2", true, Some("emailed"), "field").await.unwrap();
+        assert!(service.find_open("first").await.unwrap().is_none());
+        assert!(service.find_open("unknown").await.unwrap().is_none());
+
+        let challenge = service.find_open("second").await.unwrap().unwrap();
+        assert_eq!(challenge.id, issued.id);
+        assert_eq!(challenge.session_id, "session-2");
+        assert!(challenge.sign_out_other_sessions);
+        assert_eq!(challenge.email_code_digest.as_deref(), Some("emailed"));
+        assert_eq!(challenge.client_id, "field");
+        assert!(service.consume(&challenge).await.unwrap());
+        assert!(!service.consume(&challenge).await.unwrap());
+        assert!(service.find_open("second").await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_challenges_expire_and_stop_after_too_many_wrong_codes() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = LoginChallengeService::new(pool.clone(), clock.clone());
+        let user_id = insert_user(&pool, clock.clone(), "two-step", UserRole::KenyaGovernment, TEST_PASSWORD, false)
+            .await
+            .id;
+
+        let challenge = service.create(user_id, "guessed", "session", false, None, "default").await.unwrap();
+        for _ in 0..MAX_CHALLENGE_CODE_ATTEMPTS - 1 {
+            service.record_failed_code(&challenge).await.unwrap();
+        }
+        assert!(service.find_open("guessed").await.unwrap().is_some());
+        service.record_failed_code(&challenge).await.unwrap();
+        assert!(service.find_open("guessed").await.unwrap().is_none());
+        assert!(!service.consume(&challenge).await.unwrap());
+
+        let challenge = service.create(user_id, "slow", "session", false, None, "default").await.unwrap();
+        clock.advance(Duration::minutes(LOGIN_CHALLENGE_TTL_MINUTES));
+        assert!(service.find_open("slow").await.unwrap().is_none());
+        assert!(!service.consume(&challenge).await.unwrap());
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How long a login waits for a hashing slot before being turned away
+pub const DEFAULT_LOGIN_QUEUE_WAIT: Duration = Duration::from_millis(500);
+
+/// Seconds a turned-away client is told to wait before retrying
+pub const LOGIN_QUEUE_RETRY_AFTER_SECONDS: u64 = 2;
+
+/// Default number of concurrent password verifications: twice the CPU count
+pub fn default_login_concurrency() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1) * 2
+}
+
+/// Snapshot of the login queue for health and metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LoginQueueDepth {
+    pub capacity: usize,
+    pub in_flight: usize,
+    pub waiting: usize,
+}
+
+/// Bounded admission for password hashing, so a burst of logins waits
+/// briefly for a slot and is then refused rather than piling up
+pub struct LoginQueue {
+    permits: Arc<Semaphore>,
+    capacity: usize,
+    max_wait: Duration,
+    waiting: AtomicUsize,
+}
+
+impl Default for LoginQueue {
+    fn default() -> Self {
+        Self::new(default_login_concurrency(), DEFAULT_LOGIN_QUEUE_WAIT)
+    }
+}
+
+impl LoginQueue {
+    pub fn new(capacity: usize, max_wait: Duration) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(capacity)),
+            capacity,
+            max_wait,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait up to the deadline for a hashing slot; `None` means the queue is saturated
+    pub async fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        let _waiting = Waiting::enter(&self.waiting);
+        let permit = tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned()).await;
+
+        // The semaphore is never closed, so only the timeout can fail
+        permit.ok().and_then(Result::ok)
+    }
+
+    pub fn depth(&self) -> LoginQueueDepth {
+        LoginQueueDepth {
+            capacity: self.capacity,
+            in_flight: self.capacity - self.permits.available_permits(),
+            waiting: self.waiting.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts a login as waiting until it is dropped, so a request abandoned
+/// mid-wait (e.g. its client disconnected) doesn't stay counted
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn enter(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_saturated_queue_refuses_after_deadline() {
+        let queue = LoginQueue::new(2, Duration::from_millis(20));
+
+        let first = queue.admit().await.expect("first slot");
+        let _second = queue.admit().await.expect("second slot");
+        assert_eq!(queue.depth(), LoginQueueDepth { capacity: 2, in_flight: 2, waiting: 0 });
+
+        assert!(queue.admit().await.is_none());
+
+        drop(first);
+        assert!(queue.admit().await.is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_waiters_are_counted() {
+        let queue = Arc::new(LoginQueue::new(1, Duration::from_secs(5)));
+        let held = queue.admit().await.unwrap();
+
+        let waiter = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.admit().await.is_some() })
+        };
+        while queue.depth().waiting == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(queue.depth().waiting, 1);
+
+        drop(held);
+        assert!(waiter.await.unwrap());
+        assert_eq!(queue.depth(), LoginQueueDepth { capacity: 1, in_flight: 0, waiting: 0 });
+    }
+
+    #[actix_web::test]
+    async fn test_abandoned_waiters_stop_being_counted() {
+        let queue = Arc::new(LoginQueue::new(1, Duration::from_secs(5)));
+        let _held = queue.admit().await.unwrap();
+
+        let waiter = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.admit().await.is_some() })
+        };
+        while queue.depth().waiting == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        waiter.abort();
+        assert!(waiter.await.unwrap_err().is_cancelled());
+        assert_eq!(queue.depth().waiting, 0);
+    }
+}
@@ -1,5 +1,49 @@
 pub mod auth_service;
 pub mod password_service;
+pub mod password_dictionary;
+pub mod password_rules;
 pub mod token_service;
 pub mod audit_service;
-pub mod two_fa_service;
\ No newline at end of file
+pub mod two_fa_service;pub mod geoip_service;
+pub mod notification_service;
+pub mod verify_monitor;
+pub mod login_queue;
+pub mod break_glass_service;
+pub mod throttle_state;
+pub mod session_service;
+pub mod session_events;
+pub mod csp_report_service;
+pub mod permission_service;
+pub mod backup_service;
+pub mod audit_archive_service;
+pub mod webhook_signer;
+pub mod webhook_service;
+pub mod policy_resolver;
+pub mod bot_heuristics;
+pub mod terms_service;
+pub mod failed_login_digest;
+pub mod config_snapshot_service;
+pub mod admin_action_service;
+pub mod feature_flags;
+pub mod data_export_service;
+pub mod account_notes_service;
+pub mod audit_bundle;
+pub mod password_reset_service;
+pub mod login_challenge_service;
+pub mod system_message_service;
+pub mod shared_state;
+pub mod health_monitor;
+pub mod security_posture;
+pub mod lockdown_service;
+pub mod client_app_service;
+pub mod password_change_challenge;
+pub mod api_key_service;
+pub mod integration_event_service;
+pub mod device_authorization_service;
+pub mod tracer;
+pub mod legacy_import;
+pub mod lockout_exemptions;
+pub mod secret_rotation;
+pub mod download_tokens;
+pub mod user_alerts;
+pub mod phase_timings;
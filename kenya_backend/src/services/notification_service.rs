@@ -0,0 +1,429 @@
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::models::context::RequestContext;
+use crate::services::admin_action_service::PendingAction;
+use crate::services::failed_login_digest::{DigestTrigger, FailedLoginDigest};
+use crate::services::lockdown_service::Lockdown;
+use crate::services::password_reset_service::PasswordReset;
+use crate::services::session_service::SessionRecord;
+use crate::utils::sanitize::{self, TextLimits};
+
+/// A notification queued for a user, waiting for a delivery channel to pick it up
+#[cfg(test)]
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct Notification {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub kind: String,
+    pub message: String,
+    pub metadata: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// Notification service - queues security notices for account owners.
+///
+/// Notifications are written to an outbox table; delivery (email once
+/// addresses are verified) reads from there.
+pub struct NotificationService {
+    db_pool: SqlitePool,
+    text_limits: TextLimits,
+}
+
+impl NotificationService {
+    pub fn new(db_pool: SqlitePool) -> Self {
+        Self { db_pool, text_limits: TextLimits::default() }
+    }
+
+    /// Cap the length of stored notification text
+    pub fn with_text_limits(mut self, text_limits: TextLimits) -> Self {
+        self.text_limits = text_limits;
+        self
+    }
+
+    /// Tell the owner their account was locked, when, from where, and until when
+    pub async fn notify_account_locked(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        locked_until: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "Your account {} was locked at {} after repeated failed sign-in attempts from {}. \
+             It unlocks at {}. If this wasn't you, contact an administrator.",
+            username,
+            ctx.received_at.format("%Y-%m-%d %H:%M UTC"),
+            ctx.ip_address,
+            locked_until.format("%Y-%m-%d %H:%M UTC"),
+        );
+        let metadata = json!({
+            "locked_at": ctx.received_at.to_rfc3339(),
+            "ip_address": ctx.ip_address,
+            "locked_until": locked_until.to_rfc3339(),
+            "request_id": ctx.request_id,
+        });
+
+        self.enqueue(user_id, "ACCOUNT_LOCKED", &message, Some(metadata)).await?;
+        log::info!("Queued lockout notification for user: {}", username);
+
+        Ok(())
+    }
+
+    /// Tell each administrator that the break-glass account has just been used
+    pub async fn notify_break_glass_used(
+        &self,
+        ctx: &RequestContext,
+        admin_ids: &[Uuid],
+        username: &str,
+        session_expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "The break-glass account {} was used to sign in at {} from {}. Its session ends at {}. \
+             Review and acknowledge the BREAK_GLASS_USED event in the security dashboard.",
+            username,
+            ctx.received_at.format("%Y-%m-%d %H:%M UTC"),
+            ctx.ip_address,
+            session_expires_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+        let metadata = json!({
+            "used_at": ctx.received_at.to_rfc3339(),
+            "ip_address": ctx.ip_address,
+            "session_expires_at": session_expires_at.to_rfc3339(),
+            "request_id": ctx.request_id,
+        });
+
+        for admin_id in admin_ids {
+            self.enqueue(*admin_id, "BREAK_GLASS_USED", &message, Some(metadata.clone())).await?;
+        }
+        log::info!("Queued break-glass notifications for {} administrators", admin_ids.len());
+
+        Ok(())
+    }
+
+    /// Tell the owner about a run of failed sign-ins in one summary, e.g.
+    /// "17 failed sign-in attempts from 3 IP addresses between 02:10 and 02:25 UTC"
+    pub async fn notify_failed_login_digest(
+        &self,
+        user_id: Uuid,
+        digest: &FailedLoginDigest,
+        trigger: DigestTrigger,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "{} failed sign-in {} to your account {} from {} IP {} {} on {}. {}",
+            digest.attempts,
+            if digest.attempts == 1 { "attempt" } else { "attempts" },
+            digest.username,
+            digest.ip_addresses.len(),
+            if digest.ip_addresses.len() == 1 { "address" } else { "addresses" },
+            if digest.first_at == digest.last_at {
+                format!("at {}", digest.first_at.format("%H:%M UTC"))
+            } else {
+                format!("between {} and {}", digest.first_at.format("%H:%M"), digest.last_at.format("%H:%M UTC"))
+            },
+            digest.first_at.format("%Y-%m-%d"),
+            match trigger {
+                DigestTrigger::LoginSucceeded => "You have since signed in; if the attempts weren't yours, change your password.",
+                _ => "If this wasn't you, change your password and contact an administrator.",
+            },
+        );
+        let metadata = json!({
+            "attempts": digest.attempts,
+            "ip_addresses": digest.ip_addresses,
+            "first_at": digest.first_at.to_rfc3339(),
+            "last_at": digest.last_at.to_rfc3339(),
+            "trigger": trigger.as_str(),
+        });
+
+        self.enqueue(user_id, "FAILED_LOGIN_DIGEST", &message, Some(metadata)).await?;
+        log::info!("Queued failed sign-in digest for user: {}", digest.username);
+
+        Ok(())
+    }
+
+    /// Tell the owner a new sign-in ended the session they had open elsewhere
+    pub async fn notify_session_replaced(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        replaced: &SessionRecord,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "Your account {} signed in at {} from {}, ending the session started at {} from {}. \
+             If this wasn't you, change your password and contact an administrator.",
+            username,
+            ctx.received_at.format("%Y-%m-%d %H:%M UTC"),
+            ctx.ip_address,
+            replaced.created_at.format("%Y-%m-%d %H:%M UTC"),
+            replaced.ip_address,
+        );
+        let metadata = json!({
+            "signed_in_at": ctx.received_at.to_rfc3339(),
+            "ip_address": ctx.ip_address,
+            "replaced_ip_address": replaced.ip_address,
+            "replaced_user_agent": replaced.user_agent,
+            "replaced_created_at": replaced.created_at.to_rfc3339(),
+            "request_id": ctx.request_id,
+        });
+
+        self.enqueue(user_id, "SESSION_REPLACED", &message, Some(metadata)).await?;
+        log::info!("Queued session replaced notification for user: {}", username);
+
+        Ok(())
+    }
+
+    /// Send an administrator the one-time link approving an action another
+    /// administrator asked them to carry out
+    pub async fn notify_admin_action_link(
+        &self,
+        admin_id: Uuid,
+        requested_by: &str,
+        pending: &PendingAction,
+        target_username: &str,
+        link: &str,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "{} asked you to approve {} for account {}. Sign in and open {} before {} to carry it out. \
+             The link works once.",
+            requested_by,
+            pending.action.as_str(),
+            target_username,
+            link,
+            pending.expires_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+        let metadata = json!({
+            "action_id": pending.id,
+            "action": pending.action,
+            "target_user_id": pending.target_user_id,
+            "link": link,
+            "expires_at": pending.expires_at.to_rfc3339(),
+        });
+
+        self.enqueue(admin_id, "ADMIN_ACTION_LINK", &message, Some(metadata)).await?;
+        log::info!("Queued {} action link for admin: {}", pending.action.as_str(), admin_id);
+
+        Ok(())
+    }
+
+    /// Tell the owner two-factor authentication was turned off on their account
+    pub async fn notify_two_fa_disabled(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "Two-factor authentication was turned off for your account {} at {} from {}. \
+             If this wasn't you, contact an administrator.",
+            username,
+            ctx.received_at.format("%Y-%m-%d %H:%M UTC"),
+            ctx.ip_address,
+        );
+        let metadata = json!({
+            "disabled_at": ctx.received_at.to_rfc3339(),
+            "ip_address": ctx.ip_address,
+            "request_id": ctx.request_id,
+        });
+
+        self.enqueue(user_id, "TWO_FA_DISABLED", &message, Some(metadata)).await?;
+        log::info!("Queued 2FA disabled notification for user: {}", username);
+
+        Ok(())
+    }
+
+    /// Send the owner the link that sets a new password, to their verified email
+    pub async fn notify_password_reset(
+        &self,
+        ctx: &RequestContext,
+        reset: &PasswordReset,
+        username: &str,
+        link: &str,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "A password reset was requested for your account {} from {}. Open {} before {} to choose a new password. \
+             The link works once. If this wasn't you, ignore this message.",
+            username,
+            ctx.ip_address,
+            link,
+            reset.expires_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+        let metadata = json!({
+            "reset_id": reset.id,
+            "channel": reset.channel,
+            "link": link,
+            "expires_at": reset.expires_at.to_rfc3339(),
+            "ip_address": ctx.ip_address,
+            "request_id": ctx.request_id,
+        });
+
+        self.enqueue(reset.user_id, "PASSWORD_RESET", &message, Some(metadata)).await?;
+        log::info!("Queued password reset link for user: {}", username);
+
+        Ok(())
+    }
+
+    /// Welcome a newly provisioned account with the link that sets its first password
+    pub async fn notify_invitation(
+        &self,
+        reset: &PasswordReset,
+        username: &str,
+        link: &str,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "An account {} was created for you on the Kenya FSFVI platform. Open {} before {} to choose your password. \
+             The link works once.",
+            username,
+            link,
+            reset.expires_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+        let metadata = json!({
+            "reset_id": reset.id,
+            "channel": reset.channel,
+            "link": link,
+            "expires_at": reset.expires_at.to_rfc3339(),
+        });
+
+        self.enqueue(reset.user_id, "INVITATION", &message, Some(metadata)).await?;
+        log::info!("Queued invitation for user: {}", username);
+
+        Ok(())
+    }
+
+    /// Send the code that completes a sign-in during a lockdown, for an
+    /// account without 2FA of its own
+    pub async fn notify_lockdown_sign_in_code(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        username: &str,
+        code: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let message = format!(
+            "Sign-ins need a second factor during the current security lockdown. Enter {} to finish signing in to \
+             your account {} from {}. The code works once, until {}. If this wasn't you, change your password.",
+            code,
+            username,
+            ctx.ip_address,
+            expires_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+        let metadata = json!({
+            "ip_address": ctx.ip_address,
+            "expires_at": expires_at.to_rfc3339(),
+            "request_id": ctx.request_id,
+        });
+
+        self.enqueue(user_id, "LOCKDOWN_SIGN_IN_CODE", &message, Some(metadata)).await?;
+        log::info!("Queued lockdown sign-in code for user: {}", username);
+
+        Ok(())
+    }
+
+    /// Tell each administrator that a lockdown was requested, started or
+    /// ended, whichever `lockdown` shows, by `admin_username` ("system" when
+    /// it ran out)
+    pub async fn notify_lockdown(
+        &self,
+        admin_ids: &[Uuid],
+        lockdown: &Lockdown,
+        admin_username: &str,
+    ) -> Result<(), sqlx::Error> {
+        let (kind, message) = match (lockdown.started_at, lockdown.ended_at, lockdown.ends_at) {
+            (None, None, _) => (
+                "LOCKDOWN_REQUESTED",
+                format!(
+                    "{} requested a {}-minute security lockdown: {}. It starts once another administrator requests \
+                     it too, before {}.",
+                    admin_username,
+                    lockdown.duration_minutes,
+                    lockdown.reason,
+                    lockdown.request_expires_at.format("%Y-%m-%d %H:%M UTC"),
+                ),
+            ),
+            (None, Some(_), _) => (
+                "LOCKDOWN_WITHDRAWN",
+                format!("{} withdrew the request for a security lockdown: {}.", admin_username, lockdown.reason),
+            ),
+            (Some(_), None, Some(ends_at)) => (
+                "LOCKDOWN_STARTED",
+                format!(
+                    "{} approved a security lockdown: {}. Every session was ended and sign-ins need a second factor \
+                     until {}.",
+                    admin_username,
+                    lockdown.reason,
+                    ends_at.format("%Y-%m-%d %H:%M UTC"),
+                ),
+            ),
+            _ => (
+                "LOCKDOWN_ENDED",
+                format!(
+                    "The security lockdown ({}) was ended by {}. Sign-ins work as before; sessions ended by the \
+                     lockdown stay ended.",
+                    lockdown.reason,
+                    admin_username,
+                ),
+            ),
+        };
+        let metadata = json!({
+            "lockdown_id": lockdown.id,
+            "reason": lockdown.reason,
+            "requested_by": lockdown.requested_by,
+            "approved_by": lockdown.approved_by,
+            "ends_at": lockdown.ends_at.map(|ends_at| ends_at.to_rfc3339()),
+            "ended_at": lockdown.ended_at.map(|ended_at| ended_at.to_rfc3339()),
+        });
+
+        for admin_id in admin_ids {
+            self.enqueue(*admin_id, kind, &message, Some(metadata.clone())).await?;
+        }
+        log::info!("Queued {} notifications for {} administrators", kind, admin_ids.len());
+
+        Ok(())
+    }
+
+    async fn enqueue(
+        &self,
+        user_id: Uuid,
+        kind: &str,
+        message: &str,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO notifications (id, user_id, kind, message, metadata, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(kind)
+        // Messages quote usernames and user agents the client chose
+        .bind(sanitize::text(message, self.text_limits.text))
+        .bind(metadata.map(|m| sanitize::json(m, self.text_limits.text).to_string()))
+        .bind(Utc::now())
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Notifications for a user that haven't been delivered yet, oldest first
+    #[cfg(test)]
+    pub async fn pending_for_user(&self, user_id: Uuid) -> Result<Vec<Notification>, sqlx::Error> {
+        sqlx::query_as::<_, Notification>(
+            r#"
+            SELECT id, user_id, kind, message, metadata, created_at, delivered_at
+            FROM notifications
+            WHERE user_id = ? AND delivered_at IS NULL
+            ORDER BY created_at
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+}
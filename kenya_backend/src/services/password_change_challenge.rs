@@ -0,0 +1,176 @@
+use chrono::Duration;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::services::shared_state::{InProcessBackend, SharedStateBackend, SharedStateError};
+use crate::utils::clock::Clock;
+
+/// How long a v2 login waits for the new password once it has asked for one
+pub const PASSWORD_CHANGE_CHALLENGE_TTL_MINUTES: i64 = 10;
+
+/// Keeps challenge signatures apart from anything else signed with the same secret
+const CHALLENGE_CONTEXT: &[u8] = b"password-change-challenge";
+
+const OPEN_KEY_PREFIX: &str = "password_change:";
+const USED_KEY_PREFIX: &str = "password_change_used:";
+
+/// A login stopped until its password is changed, as carried by its challenge token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordChangeChallenge {
+    pub user_id: Uuid,
+    /// Session the login set up on the account; a later login replaces it
+    pub session_id: String,
+    /// Client app the login came from
+    pub client_id: String,
+    pub sign_out_other_sessions: bool,
+}
+
+/// Challenge tokens for v2 logins whose password must be changed first.
+///
+/// A token names the login it continues and is signed, so nothing but its
+/// open marker is stored. The marker lives in the shared state backend for
+/// `PASSWORD_CHANGE_CHALLENGE_TTL_MINUTES`, so a challenge issued by one
+/// instance can be answered on another, and is removed when the password is
+/// changed so the token works once.
+pub struct PasswordChangeChallenges {
+    key: Vec<u8>,
+    clock: Arc<dyn Clock>,
+    backend: Arc<dyn SharedStateBackend>,
+}
+
+impl PasswordChangeChallenges {
+    pub fn new(key: &[u8], clock: Arc<dyn Clock>) -> Self {
+        Self { key: key.to_vec(), clock, backend: Arc::new(InProcessBackend::default()) }
+    }
+
+    /// Keep the open challenges in `backend` instead of this process's memory
+    pub fn with_backend(mut self, backend: Arc<dyn SharedStateBackend>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Open a challenge for `challenge` and return its token
+    pub async fn issue(&self, challenge: &PasswordChangeChallenge) -> Result<String, SharedStateError> {
+        let expires_at = self.clock.now() + Duration::minutes(PASSWORD_CHANGE_CHALLENGE_TTL_MINUTES);
+        let payload = format!(
+            "{}.{}.{}.{}.{}",
+            challenge.user_id,
+            challenge.session_id,
+            challenge.client_id,
+            u8::from(challenge.sign_out_other_sessions),
+            expires_at.timestamp(),
+        );
+        self.backend.hold(&open_key(&challenge.session_id), ttl()).await?;
+        Ok(format!("{}.{}", payload, self.sign(&payload)))
+    }
+
+    /// The challenge behind `token` while it is open: signed here, not
+    /// expired and not used yet
+    pub async fn open(&self, token: &str) -> Result<Option<PasswordChangeChallenge>, SharedStateError> {
+        let Some((challenge, expires_at)) = self.verify(token) else {
+            return Ok(None);
+        };
+        if expires_at <= self.clock.now().timestamp() {
+            return Ok(None);
+        }
+        let open = self.backend.remaining(&open_key(&challenge.session_id)).await?.is_some();
+        Ok(open.then_some(challenge))
+    }
+
+    /// Use the challenge up. Only the first of several racing requests gets `true`.
+    pub async fn consume(&self, challenge: &PasswordChangeChallenge) -> Result<bool, SharedStateError> {
+        let claims = self.backend.increment(&format!("{}{}", USED_KEY_PREFIX, challenge.session_id), ttl()).await?;
+        self.backend.remove(&open_key(&challenge.session_id)).await?;
+        Ok(claims == 1)
+    }
+
+    /// Challenges issued and not yet used or expired
+    pub async fn pending(&self) -> usize {
+        self.backend.count_keys(OPEN_KEY_PREFIX).await.unwrap_or(0)
+    }
+
+    /// The challenge a token carries and when it expires, if this server signed it
+    fn verify(&self, token: &str) -> Option<(PasswordChangeChallenge, i64)> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let expected = (0..signature.len())
+            .step_by(2)
+            .map(|i| signature.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()?;
+        // Constant-time comparison
+        self.mac(payload).verify_slice(&expected).ok()?;
+
+        let mut parts = payload.split('.');
+        let challenge = PasswordChangeChallenge {
+            user_id: Uuid::parse_str(parts.next()?).ok()?,
+            session_id: parts.next()?.to_string(),
+            client_id: parts.next()?.to_string(),
+            sign_out_other_sessions: parts.next()? == "1",
+        };
+        let expires_at = parts.next()?.parse().ok()?;
+        parts.next().is_none().then_some((challenge, expires_at))
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        self.mac(payload).finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC key of any length");
+        mac.update(CHALLENGE_CONTEXT);
+        mac.update(b".");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+fn open_key(session_id: &str) -> String {
+    format!("{}{}", OPEN_KEY_PREFIX, session_id)
+}
+
+fn ttl() -> std::time::Duration {
+    std::time::Duration::from_secs(PASSWORD_CHANGE_CHALLENGE_TTL_MINUTES as u64 * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClock;
+
+    fn challenge() -> PasswordChangeChallenge {
+        PasswordChangeChallenge {
+            user_id: Uuid::new_v4(),
+            session_id: Uuid::new_v4().to_string(),
+            client_id: "field".to_string(),
+            sign_out_other_sessions: true,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_challenge_opens_until_used_or_expired() {
+        let clock = Arc::new(MockClock::new());
+        let challenges = PasswordChangeChallenges::new(b"key", clock.clone());
+
+        let issued = challenge();
+        let token = challenges.issue(&issued).await.unwrap();
+        assert_eq!(challenges.open(&token).await.unwrap(), Some(issued.clone()));
+        assert_eq!(challenges.pending().await, 1);
+
+        // Tampered with, or signed with another key
+        let forged = token.replacen("field", "admin", 1);
+        assert_eq!(challenges.open(&forged).await.unwrap(), None);
+        let stranger = PasswordChangeChallenges::new(b"other key", clock.clone());
+        assert_eq!(stranger.open(&token).await.unwrap(), None);
+
+        assert!(challenges.consume(&issued).await.unwrap());
+        assert!(!challenges.consume(&issued).await.unwrap());
+        assert_eq!(challenges.open(&token).await.unwrap(), None);
+        assert_eq!(challenges.pending().await, 0);
+
+        let later = challenge();
+        let token = challenges.issue(&later).await.unwrap();
+        clock.advance(Duration::minutes(PASSWORD_CHANGE_CHALLENGE_TTL_MINUTES));
+        assert_eq!(challenges.open(&token).await.unwrap(), None);
+    }
+}
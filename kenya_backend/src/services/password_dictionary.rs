@@ -0,0 +1,141 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Denylist used when no dictionary file is configured or it can't be read
+const EMBEDDED_COMMON_PASSWORDS: &[&str] = &[
+    "password", "passw0rd", "123456", "12345678", "123456789", "password123", "admin", "qwerty",
+    "letmein", "welcome", "monkey", "dragon", "master", "iloveyou", "abc123", "sunshine",
+    "princess", "football", "trustno1", "changeme", "kenya", "government", "nairobi", "fsfvi",
+];
+
+/// Common passwords that are refused whatever else the policy says.
+///
+/// Entries are lowercased, sorted and deduplicated once when loaded, so a
+/// lookup is a binary search even for a 100k-entry list.
+#[derive(Clone)]
+pub struct PasswordDictionary {
+    entries: Vec<Box<str>>,
+}
+
+impl PasswordDictionary {
+    /// The small built-in list
+    pub fn embedded() -> Self {
+        Self::from_entries(EMBEDDED_COMMON_PASSWORDS.iter().copied())
+    }
+
+    /// Load a list with one password per line; blank lines and `#` comments are skipped
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Self::from_entries(
+            contents.lines().map(str::trim).filter(|line| !line.starts_with('#')),
+        ))
+    }
+
+    /// Load the configured list, falling back to the embedded one with a warning
+    pub fn load_or_embedded(path: Option<&str>) -> Self {
+        let Some(path) = path else {
+            return Self::embedded();
+        };
+        match Self::load(path) {
+            Ok(dictionary) => {
+                log::info!("Loaded {} common passwords from {}", dictionary.entry_count(), path);
+                dictionary
+            }
+            Err(e) => {
+                log::warn!("Password dictionary {} could not be read ({}), using the embedded list", path, e);
+                Self::embedded()
+            }
+        }
+    }
+
+    fn from_entries<'a>(entries: impl Iterator<Item = &'a str>) -> Self {
+        let mut entries: Vec<Box<str>> = entries
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| entry.to_lowercase().into_boxed_str())
+            .collect();
+        entries.sort_unstable();
+        entries.dedup();
+        Self { entries }
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the password is on the list, ignoring case, either as typed or
+    /// with the digits and symbols people tack on the end removed
+    /// (`Password2024!` matches `password`)
+    pub fn contains(&self, password: &str) -> bool {
+        let lowercase = password.to_lowercase();
+        let stripped = lowercase.trim_end_matches(|c: char| !c.is_alphabetic());
+
+        self.has(&lowercase) || (!stripped.is_empty() && self.has(stripped))
+    }
+
+    fn has(&self, candidate: &str) -> bool {
+        self.entries.binary_search_by(|entry| (**entry).cmp(candidate)).is_ok()
+    }
+}
+
+impl fmt::Debug for PasswordDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasswordDictionary").field("entries", &self.entries.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn temp_file(contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("password-dictionary-{}.txt", uuid::Uuid::new_v4()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_trailing_digits_and_symbols_are_stripped() {
+        let dictionary = PasswordDictionary::embedded();
+
+        assert!(dictionary.contains("password"));
+        assert!(dictionary.contains("Password2024!"));
+        assert!(dictionary.contains("DRAGON!!"));
+        assert!(dictionary.contains("123456"));
+        assert!(!dictionary.contains("MyPassword2024!"));
+        assert!(!dictionary.contains("2024!"));
+        assert!(!dictionary.contains("TestPassw0rd987!"));
+    }
+
+    #[test]
+    fn test_loads_file_and_falls_back_when_missing() {
+        let path = temp_file("# leaked list\nHunter2\n\n  correcthorse  \nhunter2\n");
+        let dictionary = PasswordDictionary::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(dictionary.entry_count(), 2);
+        assert!(dictionary.contains("Hunter2"));
+        assert!(dictionary.contains("CorrectHorse99#"));
+        assert!(!dictionary.contains("# leaked list"));
+
+        let fallback = PasswordDictionary::load_or_embedded(Some("/nonexistent/passwords.txt"));
+        assert_eq!(fallback.entry_count(), PasswordDictionary::embedded().entry_count());
+    }
+
+    #[test]
+    fn test_loading_100k_entries_fits_startup_budget() {
+        let contents: String = (0..100_000).map(|i| format!("common{:06}word\n", i)).collect();
+        let path = temp_file(&contents);
+
+        let started = Instant::now();
+        let dictionary = PasswordDictionary::load(&path).unwrap();
+        let elapsed = started.elapsed();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(dictionary.entry_count(), 100_000);
+        assert!(dictionary.contains("Common054321Word1!"));
+        assert!(elapsed < Duration::from_secs(2), "loading took {:?}", elapsed);
+    }
+}
@@ -0,0 +1,256 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::utils::clock::Clock;
+
+/// How long a password reset token works after it is issued
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+
+/// How long the link a new account gets to choose its first password works
+pub const INVITATION_TTL_HOURS: i64 = 72;
+
+/// How a password reset token reaches the account owner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetChannel {
+    /// Emailed to the account's verified address
+    Email,
+    /// Issued to an administrator, who passes it on out of band
+    AdminAssisted,
+    /// Sent to a newly provisioned account to choose its first password
+    Invitation,
+}
+
+impl ResetChannel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResetChannel::Email => "email",
+            ResetChannel::AdminAssisted => "admin_assisted",
+            ResetChannel::Invitation => "invitation",
+        }
+    }
+
+    /// How long a token issued through the channel works
+    pub fn ttl(self) -> Duration {
+        match self {
+            ResetChannel::Email | ResetChannel::AdminAssisted => Duration::minutes(PASSWORD_RESET_TTL_MINUTES),
+            ResetChannel::Invitation => Duration::hours(INVITATION_TTL_HOURS),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "email" => Some(ResetChannel::Email),
+            "admin_assisted" => Some(ResetChannel::AdminAssisted),
+            "invitation" => Some(ResetChannel::Invitation),
+            _ => None,
+        }
+    }
+}
+
+/// Channels named to anyone who asks, so the answer doesn't reveal whether
+/// the account exists or has a verified email
+pub const GENERIC_RESET_CHANNELS: [ResetChannel; 2] = [ResetChannel::Email, ResetChannel::AdminAssisted];
+
+/// An issued password reset token
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub channel: ResetChannel,
+    /// The administrator it was issued to, for `AdminAssisted`
+    pub issued_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+/// What a presented token came to
+#[derive(Debug)]
+pub enum ResetLookup {
+    /// Unused and unexpired; `consume` it once the new password is ready
+    Valid(PasswordReset),
+    Unknown,
+    AlreadyUsed(PasswordReset),
+    Expired(PasswordReset),
+}
+
+type PasswordResetRow = (Uuid, Uuid, String, Option<Uuid>, DateTime<Utc>, DateTime<Utc>, Option<DateTime<Utc>>);
+
+/// One-time password reset tokens. Tokens from every channel are alike:
+/// only the hash is stored, each works once within its channel's `ttl`,
+/// and issuing one expires the account's earlier ones.
+pub struct PasswordResetService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl PasswordResetService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// Issue a token for `user_id` through `channel`. Returns it with the
+    /// token, which isn't kept and can't be recovered later.
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        channel: ResetChannel,
+        issued_by: Option<Uuid>,
+    ) -> Result<(PasswordReset, String), sqlx::Error> {
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let now = self.clock.now();
+        let reset = PasswordReset {
+            id: Uuid::new_v4(),
+            user_id,
+            channel,
+            issued_by,
+            created_at: now,
+            expires_at: now + channel.ttl(),
+            used_at: None,
+        };
+
+        let mut tx = self.db_pool.begin().await?;
+        sqlx::query("UPDATE password_resets SET expires_at = ? WHERE user_id = ? AND used_at IS NULL AND expires_at > ?")
+            .bind(now)
+            .bind(user_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO password_resets (id, user_id, channel, issued_by, token_hash, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(reset.id)
+        .bind(user_id)
+        .bind(channel.as_str())
+        .bind(issued_by)
+        .bind(token_hash(&token))
+        .bind(reset.created_at)
+        .bind(reset.expires_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok((reset, token))
+    }
+
+    /// Look `token` up without using it
+    pub async fn lookup(&self, token: &str) -> Result<ResetLookup, sqlx::Error> {
+        let row: Option<PasswordResetRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, channel, issued_by, created_at, expires_at, used_at
+            FROM password_resets WHERE token_hash = ?
+            "#
+        )
+        .bind(token_hash(token))
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        let Some(reset) = row.and_then(|(id, user_id, channel, issued_by, created_at, expires_at, used_at)| {
+            Some(PasswordReset {
+                id,
+                user_id,
+                channel: ResetChannel::from_name(&channel)?,
+                issued_by,
+                created_at,
+                expires_at,
+                used_at,
+            })
+        }) else {
+            return Ok(ResetLookup::Unknown);
+        };
+        if reset.used_at.is_some() {
+            return Ok(ResetLookup::AlreadyUsed(reset));
+        }
+        if self.clock.now() >= reset.expires_at {
+            return Ok(ResetLookup::Expired(reset));
+        }
+        Ok(ResetLookup::Valid(reset))
+    }
+
+    /// Use a token `lookup` found valid. Returns `false` when another request
+    /// used it, or it expired, in the meantime.
+    pub async fn consume(&self, reset: &PasswordReset) -> Result<bool, sqlx::Error> {
+        let now = self.clock.now();
+        Ok(sqlx::query("UPDATE password_resets SET used_at = ? WHERE id = ? AND used_at IS NULL AND expires_at > ?")
+            .bind(now)
+            .bind(reset.id)
+            .bind(now)
+            .execute(&self.db_pool)
+            .await?
+            .rows_affected()
+            > 0)
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_tokens_work_once_and_supersede_each_other() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = PasswordResetService::new(pool.clone(), clock.clone());
+        let user_id = insert_user(&pool, clock, "forgetful", UserRole::KenyaGovernment, TEST_PASSWORD, false)
+            .await
+            .id;
+
+        let (_, emailed) = service.create(user_id, ResetChannel::Email, None).await.unwrap();
+        let (issued, handed_over) = service.create(user_id, ResetChannel::AdminAssisted, Some(user_id)).await.unwrap();
+        assert!(matches!(service.lookup(&emailed).await.unwrap(), ResetLookup::Expired(_)));
+        assert!(matches!(service.lookup("not-a-token").await.unwrap(), ResetLookup::Unknown));
+
+        let ResetLookup::Valid(reset) = service.lookup(&handed_over).await.unwrap() else {
+            panic!("the newest token should be valid");
+        };
+        assert_eq!(reset.id, issued.id);
+        assert_eq!(reset.channel, ResetChannel::AdminAssisted);
+        assert!(service.consume(&reset).await.unwrap());
+        assert!(!service.consume(&reset).await.unwrap());
+        assert!(matches!(service.lookup(&handed_over).await.unwrap(), ResetLookup::AlreadyUsed(_)));
+    }
+
+    #[actix_web::test]
+    async fn test_tokens_expire() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = PasswordResetService::new(pool.clone(), clock.clone());
+        let user_id = insert_user(&pool, clock.clone(), "forgetful", UserRole::KenyaGovernment, TEST_PASSWORD, false)
+            .await
+            .id;
+
+        let (_, token) = service.create(user_id, ResetChannel::Email, None).await.unwrap();
+        let ResetLookup::Valid(reset) = service.lookup(&token).await.unwrap() else {
+            panic!("a fresh token should be valid");
+        };
+        clock.advance(Duration::minutes(PASSWORD_RESET_TTL_MINUTES));
+        assert!(matches!(service.lookup(&token).await.unwrap(), ResetLookup::Expired(_)));
+        assert!(!service.consume(&reset).await.unwrap());
+
+        // Invitations outlast reset links
+        let (_, invitation) = service.create(user_id, ResetChannel::Invitation, None).await.unwrap();
+        clock.advance(Duration::hours(INVITATION_TTL_HOURS) - Duration::minutes(1));
+        assert!(matches!(service.lookup(&invitation).await.unwrap(), ResetLookup::Valid(_)));
+        clock.advance(Duration::minutes(1));
+        assert!(matches!(service.lookup(&invitation).await.unwrap(), ResetLookup::Expired(_)));
+    }
+}
@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+use std::sync::{Arc, OnceLock};
+
+use crate::services::password_dictionary::PasswordDictionary;
+
+/// Characters that count as special for `require_special_chars`
+pub const SPECIAL_CHARACTERS: &str = "!@#$%^&*()_+-=[]{}|;:,.<>?";
+
+/// Password policy configuration
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_numbers: bool,
+    pub require_special_chars: bool,
+    pub max_repeating_chars: usize,
+    pub forbidden_patterns: Vec<String>,
+    /// Passwords refused outright, shared because a full list is large
+    pub common_passwords: Arc<PasswordDictionary>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 12,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_numbers: true,
+            require_special_chars: true,
+            max_repeating_chars: 3,
+            forbidden_patterns: vec![
+                "123".to_string(),
+                "abc".to_string(),
+                "password".to_string(),
+                "qwerty".to_string(),
+                "kenya".to_string(),
+                "government".to_string(),
+            ],
+            common_passwords: Arc::new(PasswordDictionary::embedded()),
+        }
+    }
+}
+
+/// Policy request bodies are validated against, set once at startup
+static POLICY: OnceLock<PasswordPolicy> = OnceLock::new();
+
+/// Validate request bodies against `policy`, the one the password service
+/// was built with. Only the first call takes effect; false for later ones.
+pub fn install(policy: PasswordPolicy) -> bool {
+    POLICY.set(policy).is_ok()
+}
+
+/// The installed policy, or the default one before `install` is called
+pub fn policy() -> &'static PasswordPolicy {
+    POLICY.get_or_init(PasswordPolicy::default)
+}
+
+/// Every rule of `policy` the password breaks, as messages for the user;
+/// empty when it is strong enough
+pub fn violations(policy: &PasswordPolicy, password: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if password.len() < policy.min_length {
+        errors.push(format!("Password must be at least {} characters long", policy.min_length));
+    }
+    if policy.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+        errors.push("Password must contain at least one uppercase letter".to_string());
+    }
+    if policy.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+        errors.push("Password must contain at least one lowercase letter".to_string());
+    }
+    if policy.require_numbers && !password.chars().any(|c| c.is_numeric()) {
+        errors.push("Password must contain at least one number".to_string());
+    }
+    if policy.require_special_chars && !password.chars().any(|c| SPECIAL_CHARACTERS.contains(c)) {
+        errors.push("Password must contain at least one special character".to_string());
+    }
+    if has_excessive_repeating_chars(policy, password) {
+        errors.push(format!("Password cannot have more than {} repeating characters", policy.max_repeating_chars));
+    }
+
+    let lowercase_password = password.to_lowercase();
+    for pattern in &policy.forbidden_patterns {
+        if lowercase_password.contains(&pattern.to_lowercase()) {
+            errors.push(format!("Password cannot contain the pattern: {}", pattern));
+        }
+    }
+
+    if policy.common_passwords.contains(password) {
+        errors.push("Password is too common".to_string());
+    }
+
+    errors
+}
+
+/// Whether the same character appears more than `max_repeating_chars` times in a row
+pub fn has_excessive_repeating_chars(policy: &PasswordPolicy, password: &str) -> bool {
+    let chars: Vec<char> = password.chars().collect();
+    let mut count = 1;
+    let mut max_count = 1;
+
+    for i in 1..chars.len() {
+        if chars[i] == chars[i - 1] {
+            count += 1;
+            max_count = max_count.max(count);
+        } else {
+            count = 1;
+        }
+    }
+
+    max_count > policy.max_repeating_chars
+}
+
+/// `violations` as a `validator` error, with every message joined
+pub fn check(policy: &PasswordPolicy, password: &str) -> Result<(), validator::ValidationError> {
+    let errors = violations(policy, password);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(validator::ValidationError::new("password_strength").with_message(Cow::Owned(errors.join("; "))))
+    }
+}
+
+/// For `#[validate(custom(function = ...))]` on new password fields, against the installed policy
+pub fn validate_password_strength(password: &str) -> Result<(), validator::ValidationError> {
+    check(policy(), password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::ChangePasswordRequest;
+    use crate::services::password_service::PasswordService;
+    use validator::Validate;
+
+    const PASSWORDS: [&str; 12] = [
+        "ComplexP@ssw0rd789",
+        "Short1!",
+        "NoSpecialChars987",
+        "nouppercase#9876",
+        "NOLOWERCASE#9876",
+        "NoDigits#Anywhere",
+        "Aaaa#bcd98765x",
+        "Myp@ss123word!x",
+        "Changeme2024!",
+        "Nairobi-County-Farms-7",
+        "Kenya#Harvest2031",
+        "Ab1!",
+    ];
+
+    fn policies() -> Vec<PasswordPolicy> {
+        let lenient = PasswordPolicy {
+            min_length: 4,
+            require_uppercase: false,
+            require_lowercase: false,
+            require_numbers: false,
+            require_special_chars: false,
+            max_repeating_chars: 5,
+            forbidden_patterns: Vec::new(),
+            ..PasswordPolicy::default()
+        };
+        let strict = PasswordPolicy {
+            min_length: 16,
+            max_repeating_chars: 2,
+            forbidden_patterns: vec!["harvest".to_string(), "county".to_string()],
+            ..PasswordPolicy::default()
+        };
+        vec![PasswordPolicy::default(), lenient, strict]
+    }
+
+    #[test]
+    fn test_service_and_request_validation_agree_for_every_policy() {
+        for (index, policy) in policies().into_iter().enumerate() {
+            let service = PasswordService::with_policy(policy.clone());
+            for password in PASSWORDS {
+                assert_eq!(
+                    service.validate_password_strength(password).is_ok(),
+                    check(&policy, password).is_ok(),
+                    "policy {} disagrees on {:?}",
+                    index,
+                    password
+                );
+            }
+        }
+
+        // The matrix tells the policies apart
+        let accepted = |policy: &PasswordPolicy| PASSWORDS.iter().filter(|p| check(policy, p).is_ok()).count();
+        let counts: Vec<usize> = policies().iter().map(accepted).collect();
+        assert!(counts[1] > counts[0] && counts[0] > counts[2], "{:?}", counts);
+    }
+
+    #[test]
+    fn test_request_bodies_follow_the_service_policy() {
+        // Nothing installs a policy under test, so both use the default
+        let service = PasswordService::new();
+        for password in PASSWORDS {
+            let request = ChangePasswordRequest {
+                current_password: "OldPassw0rd-2024".to_string(),
+                new_password: password.to_string(),
+                confirm_password: password.to_string(),
+            };
+            assert_eq!(
+                request.validate().is_ok(),
+                service.validate_password_strength(password).is_ok(),
+                "{:?}",
+                password
+            );
+        }
+
+        let message = check(policy(), "short").unwrap_err().message.unwrap();
+        assert!(message.starts_with("Password must be at least 12 characters long; "));
+    }
+}
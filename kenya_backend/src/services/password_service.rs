@@ -4,49 +4,126 @@ use argon2::{
 };
 use bcrypt;
 use rand::Rng;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::models::auth::{AuthError, AuthResult};
+use crate::services::password_dictionary::PasswordDictionary;
+use crate::services::password_rules::{self, PasswordPolicy};
+use crate::services::webhook_service::{Notifier, Urgency, SIEM_DESTINATION};
+
+/// Webhook event type of a password hashing failure
+const HASHING_FAILURE_EVENT: &str = "password_hashing_unavailable";
+
+/// `users.hash_scheme` of hashes this service wrote
+pub const HASH_SCHEME_ARGON2: &str = "argon2";
+
+/// `users.hash_scheme` of bcrypt hashes imported from the legacy county portal
+pub const HASH_SCHEME_BCRYPT_LEGACY: &str = "bcrypt_legacy";
+
+/// Whether `hash` is a bcrypt hash in modular crypt format, `$2b$12$` and
+/// 53 characters of salt and digest. Signing in with one re-hashes the
+/// password with Argon2.
+pub fn is_bcrypt_hash(hash: &str) -> bool {
+    let Some(rest) = hash.strip_prefix("$2") else { return false };
+    let mut parts = rest.splitn(3, '$');
+    let (Some(variant), Some(cost), Some(digest)) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    matches!(variant, "a" | "b" | "x" | "y")
+        && cost.len() == 2
+        && cost.bytes().all(|b| b.is_ascii_digit())
+        && cost.parse::<u32>().is_ok_and(|cost| (4..=31).contains(&cost))
+        && digest.len() == 53
+        && digest.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'/')
+}
 
-use crate::models::auth::{AuthError, AuthResult, PasswordPolicy};
-
-/// Password service for secure password hashing and validation
+/// Password service for secure password hashing and validation.
+///
+/// New hashes are always Argon2. bcrypt is only used to verify hashes from
+/// before the switch; if Argon2 can't hash, the request fails instead.
 pub struct PasswordService {
     policy: PasswordPolicy,
     argon2: Argon2<'static>,
+    /// Argon2 hashing failures since startup
+    hashing_failures: AtomicU64,
+    /// Told of every hashing failure when a SIEM destination is configured
+    webhooks: Option<Arc<dyn Notifier>>,
 }
 
 impl PasswordService {
     pub fn new() -> Self {
-        Self {
-            policy: PasswordPolicy::default(),
-            argon2: Argon2::default(),
-        }
+        Self::with_policy(PasswordPolicy::default())
     }
 
     pub fn with_policy(policy: PasswordPolicy) -> Self {
         Self {
             policy,
             argon2: Argon2::default(),
+            hashing_failures: AtomicU64::new(0),
+            webhooks: None,
         }
     }
 
-    /// Hash a password using Argon2 (primary) with bcrypt fallback
+    /// Refuse the passwords on `dictionary` instead of the embedded list
+    pub fn with_common_passwords(mut self, dictionary: Arc<PasswordDictionary>) -> Self {
+        self.policy.common_passwords = dictionary;
+        self
+    }
+
+    /// Alert the `siem` webhook destination, if there is one, when hashing fails
+    pub fn with_webhooks(mut self, webhooks: Arc<dyn Notifier>) -> Self {
+        self.webhooks = Some(webhooks).filter(|w| w.has_destination(SIEM_DESTINATION));
+        self
+    }
+
+    /// Hash with Argon2 `params` instead of the defaults
+    #[cfg(test)]
+    pub fn with_argon2_params(mut self, params: argon2::Params) -> Self {
+        self.argon2 = Argon2::from(params);
+        self
+    }
+
+    /// Hash a password using Argon2. There is no fallback: a failure means the
+    /// hasher is misconfigured, and is counted and alerted on.
     pub fn hash_password(&self, password: &str) -> AuthResult<String> {
         // Validate password first
         self.validate_password_strength(password)?;
+        self.rehash_password(password)
+    }
 
+    /// Hash a password the user already signed in with, to move it off an
+    /// older scheme. It isn't held to the current policy: it is their
+    /// password either way, and refusing would leave the weaker hash in place.
+    pub fn rehash_password(&self, password: &str) -> AuthResult<String> {
         // Generate salt
         let salt = SaltString::generate(&mut OsRng);
 
         // Hash with Argon2
         match self.argon2.hash_password(password.as_bytes(), &salt) {
             Ok(hash) => Ok(hash.to_string()),
-            Err(_) => {
-                // Fallback to bcrypt if Argon2 fails
-                bcrypt::hash(password, 12)
-                    .map_err(|_| AuthError::InternalError("Failed to hash password".to_string()))
+            Err(e) => {
+                let failures = self.hashing_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                log::error!("CRITICAL: Argon2 password hashing failed ({} since startup): {}", failures, e);
+                if let Some(webhooks) = &self.webhooks {
+                    webhooks.clone().notify(
+                        SIEM_DESTINATION,
+                        HASHING_FAILURE_EVENT,
+                        json!({ "error": e.to_string(), "failures_since_startup": failures }),
+                        Urgency::Critical,
+                    );
+                }
+                Err(AuthError::HashingUnavailable)
             }
         }
     }
 
+    /// Argon2 hashing failures since startup
+    pub fn hashing_failures(&self) -> u64 {
+        self.hashing_failures.load(Ordering::Relaxed)
+    }
+
     /// Verify password against hash  
     pub fn verify_password(&self, password: &str, hash: &str) -> AuthResult<bool> {
         self.verify_password_with_context(password, hash, "Authentication")
@@ -67,6 +144,12 @@ impl PasswordService {
                     log::debug!("{}: Argon2 verification successful", context);
                     return Ok(true);
                 }
+                Err(argon2::password_hash::Error::Password) => {
+                    // A well-formed Argon2 hash that doesn't match is a plain mismatch;
+                    // it must not fall through to bcrypt, which would turn it into an error
+                    log::debug!("{}: Argon2 verification failed: password does not match", context);
+                    return Ok(false);
+                }
                 Err(argon2_err) => {
                     if context == "Password similarity check" {
                         log::debug!("{}: Argon2 verification failed (expected for different passwords): {:?}", context, argon2_err);
@@ -79,7 +162,7 @@ impl PasswordService {
             log::debug!("Hash parsing failed, trying bcrypt directly");
         }
 
-        // Fallback to bcrypt
+        // Hashes from before the switch to Argon2
         match bcrypt::verify(password, hash) {
             Ok(result) => {
                 log::debug!("{}: bcrypt verification result: {}", context, result);
@@ -98,80 +181,24 @@ impl PasswordService {
 
     /// Check if two passwords are the same (used for password change validation)
     pub fn passwords_are_same(&self, new_password: &str, current_hash: &str) -> bool {
-        match self.verify_password_with_context(new_password, current_hash, "Password similarity check") {
-            Ok(is_same) => is_same,
-            Err(_) => false, // If verification fails, passwords are different
-        }
+        // If verification fails, passwords are different
+        self.verify_password_with_context(new_password, current_hash, "Password similarity check")
+            .unwrap_or_default()
     }
 
-    /// Validate password strength according to policy
+    /// Validate password strength according to policy. The rules live in
+    /// `password_rules`, which request validation shares.
     pub fn validate_password_strength(&self, password: &str) -> AuthResult<()> {
-        let mut errors = Vec::new();
-
-        // Check minimum length
-        if password.len() < self.policy.min_length {
-            errors.push(format!("Password must be at least {} characters long", self.policy.min_length));
-        }
-
-        // Check character requirements
-        if self.policy.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
-            errors.push("Password must contain at least one uppercase letter".to_string());
-        }
-
-        if self.policy.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
-            errors.push("Password must contain at least one lowercase letter".to_string());
-        }
-
-        if self.policy.require_numbers && !password.chars().any(|c| c.is_numeric()) {
-            errors.push("Password must contain at least one number".to_string());
-        }
-
-        if self.policy.require_special_chars {
-            let special_chars = "!@#$%^&*()_+-=[]{}|;:,.<>?";
-            if !password.chars().any(|c| special_chars.contains(c)) {
-                errors.push("Password must contain at least one special character".to_string());
-            }
-        }
-
-        // Check for excessive repeating characters
-        if self.has_excessive_repeating_chars(password) {
-            errors.push(format!("Password cannot have more than {} repeating characters", self.policy.max_repeating_chars));
-        }
-
-        // Check for forbidden patterns
-        let lowercase_password = password.to_lowercase();
-        for pattern in &self.policy.forbidden_patterns {
-            if lowercase_password.contains(&pattern.to_lowercase()) {
-                errors.push(format!("Password cannot contain the pattern: {}", pattern));
-            }
-        }
-
-        // Check for username inclusion (this would be done with user context)
-        // For now, we'll check if it's just common weak patterns
-
-        if errors.is_empty() {
+        if password_rules::violations(&self.policy, password).is_empty() {
             Ok(())
         } else {
             Err(AuthError::PasswordTooWeak)
         }
     }
 
-    /// Check if password contains excessive repeating characters
-    fn has_excessive_repeating_chars(&self, password: &str) -> bool {
-        let chars: Vec<char> = password.chars().collect();
-        let mut count = 1;
-        let mut max_count = 1;
-
-        for i in 1..chars.len() {
-            if chars[i] == chars[i - 1] {
-                count += 1;
-                max_count = max_count.max(count);
-            } else {
-                count = 1;
-            }
-        }
-
-        max_count > self.policy.max_repeating_chars
+    /// The policy passwords are checked against
+    pub fn policy(&self) -> &PasswordPolicy {
+        &self.policy
     }
 
     /// Generate a temporary password
@@ -209,110 +236,6 @@ impl PasswordService {
 
         chars.into_iter().collect()
     }
-
-    /// Check if password is commonly used (basic check)
-    pub fn is_common_password(&self, password: &str) -> bool {
-        let common_passwords = [
-            "password", "123456", "password123", "admin", "qwerty",
-            "letmein", "welcome", "monkey", "dragon", "master",
-            "kenya", "government", "nairobi", "fsfvi"
-        ];
-
-        let lowercase_password = password.to_lowercase();
-        common_passwords.iter().any(|&common| lowercase_password.contains(common))
-    }
-
-    /// Calculate password entropy (rough estimation)
-    pub fn calculate_entropy(&self, password: &str) -> f64 {
-        if password.is_empty() {
-            return 0.0;
-        }
-
-        let mut charset_size = 0;
-
-        if password.chars().any(|c| c.is_lowercase()) {
-            charset_size += 26;
-        }
-        if password.chars().any(|c| c.is_uppercase()) {
-            charset_size += 26;
-        }
-        if password.chars().any(|c| c.is_numeric()) {
-            charset_size += 10;
-        }
-        if password.chars().any(|c| "!@#$%^&*()_+-=[]{}|;:,.<>?".contains(c)) {
-            charset_size += 32;
-        }
-
-        if charset_size == 0 {
-            return 0.0;
-        }
-
-        let entropy = (password.len() as f64) * (charset_size as f64).log2();
-        entropy
-    }
-
-    /// Rate password strength
-    pub fn rate_password_strength(&self, password: &str) -> PasswordStrength {
-        let entropy = self.calculate_entropy(password);
-        let length = password.len();
-
-        // Check for common patterns
-        let has_common_patterns = self.is_common_password(password);
-        let has_repeating = self.has_excessive_repeating_chars(password);
-
-        // Scoring algorithm
-        let mut score = 0;
-
-        // Length scoring
-        if length >= 12 { score += 20; }
-        if length >= 16 { score += 10; }
-        if length >= 20 { score += 10; }
-
-        // Character variety scoring
-        if password.chars().any(|c| c.is_lowercase()) { score += 5; }
-        if password.chars().any(|c| c.is_uppercase()) { score += 5; }
-        if password.chars().any(|c| c.is_numeric()) { score += 5; }
-        if password.chars().any(|c| "!@#$%^&*()_+-=[]{}|;:,.<>?".contains(c)) { score += 10; }
-
-        // Entropy scoring
-        if entropy >= 60.0 { score += 20; }
-        else if entropy >= 40.0 { score += 15; }
-        else if entropy >= 25.0 { score += 10; }
-
-        // Penalties
-        if has_common_patterns { score -= 30; }
-        if has_repeating { score -= 20; }
-
-        match score {
-            0..=30 => PasswordStrength::VeryWeak,
-            31..=50 => PasswordStrength::Weak,
-            51..=70 => PasswordStrength::Moderate,
-            71..=85 => PasswordStrength::Strong,
-            _ => PasswordStrength::VeryStrong,
-        }
-    }
-}
-
-/// Password strength levels
-#[derive(Debug, Clone, PartialEq)]
-pub enum PasswordStrength {
-    VeryWeak,
-    Weak,
-    Moderate,
-    Strong,
-    VeryStrong,
-}
-
-impl std::fmt::Display for PasswordStrength {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            PasswordStrength::VeryWeak => write!(f, "Very Weak"),
-            PasswordStrength::Weak => write!(f, "Weak"),
-            PasswordStrength::Moderate => write!(f, "Moderate"),
-            PasswordStrength::Strong => write!(f, "Strong"),
-            PasswordStrength::VeryStrong => write!(f, "Very Strong"),
-        }
-    }
 }
 
 #[cfg(test)]
@@ -322,19 +245,39 @@ mod tests {
     #[test]
     fn test_password_hashing() {
         let service = PasswordService::new();
-        let password = "TestPassword123!";
+        let password = "TestPassw0rd987!";
 
         let hash = service.hash_password(password).unwrap();
         assert!(service.verify_password(password, &hash).unwrap());
         assert!(!service.verify_password("wrong", &hash).unwrap());
     }
 
+    #[test]
+    fn test_argon2_failure_is_surfaced_without_falling_back_to_bcrypt() {
+        // Argon2 accepts the params, but can't produce an output this long
+        let params = argon2::Params::new(argon2::Params::DEFAULT_M_COST, 2, 1, Some(128)).unwrap();
+        let service = PasswordService::new().with_argon2_params(params);
+
+        assert!(matches!(service.hash_password("TestPassw0rd987!"), Err(AuthError::HashingUnavailable)));
+        assert!(matches!(service.hash_password("ComplexP@ssw0rd789"), Err(AuthError::HashingUnavailable)));
+        assert_eq!(service.hashing_failures(), 2);
+        // A weak password is still refused as such, and isn't a hashing failure
+        assert!(matches!(service.hash_password("weak"), Err(AuthError::PasswordTooWeak)));
+        assert_eq!(service.hashing_failures(), 2);
+
+        // Existing bcrypt hashes still verify
+        let legacy = bcrypt::hash("TestPassw0rd987!", 4).unwrap();
+        assert!(service.verify_password("TestPassw0rd987!", &legacy).unwrap());
+        assert!(!service.verify_password("wrong", &legacy).unwrap());
+        assert!(PasswordService::new().hash_password("TestPassw0rd987!").unwrap().starts_with("$argon2"));
+    }
+
     #[test]
     fn test_password_strength_validation() {
         let service = PasswordService::new();
 
         // Should pass
-        assert!(service.validate_password_strength("ComplexP@ssw0rd123").is_ok());
+        assert!(service.validate_password_strength("ComplexP@ssw0rd789").is_ok());
 
         // Should fail - too short
         assert!(service.validate_password_strength("Short1!").is_err());
@@ -343,6 +286,15 @@ mod tests {
         assert!(service.validate_password_strength("NoSpecialChars123").is_err());
     }
 
+    #[test]
+    fn test_common_passwords_are_refused() {
+        let service = PasswordService::new();
+
+        // Meets every character rule, but is a listed password with a year tacked on
+        assert!(service.validate_password_strength("Changeme2024!").is_err());
+        assert!(service.validate_password_strength("Chanqeme2024!").is_ok());
+    }
+
     #[test]
     fn test_temporary_password_generation() {
         let service = PasswordService::new();
@@ -0,0 +1,92 @@
+use chrono::Utc;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::models::permission::{Permission, PermissionOverride, PermissionSet};
+use crate::models::user::User;
+
+/// Store for per-user permission overrides
+pub struct PermissionService {
+    db_pool: SqlitePool,
+}
+
+impl PermissionService {
+    pub fn new(db_pool: SqlitePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// A user's overrides. Names this binary doesn't know are skipped.
+    pub async fn overrides(&self, user_id: Uuid) -> Result<Vec<PermissionOverride>, sqlx::Error> {
+        let rows: Vec<(String, bool)> = sqlx::query_as(
+            "SELECT permission, granted FROM user_permissions WHERE user_id = ? ORDER BY permission",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(name, granted)| match name.parse::<Permission>() {
+                Ok(permission) => Some(PermissionOverride { permission, granted }),
+                Err(e) => {
+                    log::warn!("Ignoring override for user {}: {}", user_id, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// The permissions a user holds right now: role defaults, then overrides
+    pub async fn effective(&self, user: &User) -> Result<PermissionSet, sqlx::Error> {
+        let overrides = self.overrides(user.id).await?;
+        Ok(PermissionSet::for_role(&user.role).with_overrides(&overrides))
+    }
+
+    /// Replace every override of a user and bump their token version so
+    /// tokens issued under the old permissions stop working.
+    ///
+    /// Returns the new token version, or `None` when there is no such user.
+    pub async fn replace_overrides(
+        &self,
+        user_id: Uuid,
+        overrides: &[PermissionOverride],
+        updated_by: Uuid,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.db_pool.begin().await?;
+
+        let token_version: Option<i64> = sqlx::query_scalar(
+            "UPDATE users SET token_version = token_version + 1, updated_at = ? WHERE id = ? RETURNING token_version",
+        )
+        .bind(now)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if token_version.is_none() {
+            return Ok(None);
+        }
+
+        sqlx::query("DELETE FROM user_permissions WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        for o in overrides {
+            sqlx::query(
+                r#"
+                INSERT INTO user_permissions (user_id, permission, granted, updated_by, updated_at)
+                VALUES (?, ?, ?, ?, ?)
+                "#
+            )
+            .bind(user_id)
+            .bind(o.permission.as_str())
+            .bind(o.granted)
+            .bind(updated_by)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(token_version)
+    }
+}
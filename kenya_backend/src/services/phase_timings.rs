@@ -0,0 +1,344 @@
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::services::tracer::{self, SpanGuard};
+
+/// Budget for password and backup code hashing, in milliseconds
+pub const DEFAULT_HASHING_BUDGET_MS: u64 = 1500;
+
+/// Budget for drawing the 2FA QR code, in milliseconds
+pub const DEFAULT_RENDERING_BUDGET_MS: u64 = 250;
+
+/// Budget for encoding the QR code as a PNG data URL, in milliseconds
+pub const DEFAULT_ENCODING_BUDGET_MS: u64 = 250;
+
+/// Budget for the database work of one operation, in milliseconds
+pub const DEFAULT_DB_BUDGET_MS: u64 = 500;
+
+/// Upper bounds of the histogram buckets, in milliseconds
+const BUCKET_BOUNDS_MS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+tokio::task_local! {
+    /// Time spent per phase by the operation being measured, set by `OperationTimings::measure`
+    static PHASES: RefCell<Vec<(Phase, Duration)>>;
+    /// Breakdowns of the operations a request ran, set by the `ServerTiming` middleware
+    static REPORT: RefCell<Vec<Breakdown>>;
+}
+
+/// What a measured operation spends its time on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    /// Argon2 and HMAC work on passwords, TOTP secrets and backup codes
+    Hashing,
+    /// Drawing the QR code and scaling it up
+    Rendering,
+    /// PNG and base64 encoding of the QR code
+    Encoding,
+    /// Queries and writes, including the audit record and the new session
+    Db,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Hashing => "hashing",
+            Phase::Rendering => "rendering",
+            Phase::Encoding => "encoding",
+            Phase::Db => "db",
+        }
+    }
+
+    fn span_name(self) -> &'static str {
+        match self {
+            Phase::Hashing => "phase::hashing",
+            Phase::Rendering => "phase::rendering",
+            Phase::Encoding => "phase::encoding",
+            Phase::Db => "phase::db",
+        }
+    }
+}
+
+/// The operations measured phase by phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    PrepareTwoFaSetup,
+    SetupTwoFa,
+    ChangePassword,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::PrepareTwoFaSetup => "prepare_two_fa_setup",
+            Operation::SetupTwoFa => "setup_two_fa",
+            Operation::ChangePassword => "change_password",
+        }
+    }
+}
+
+/// Milliseconds each phase may take before it is logged at WARN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseBudgets {
+    pub hashing_ms: u64,
+    pub rendering_ms: u64,
+    pub encoding_ms: u64,
+    pub db_ms: u64,
+}
+
+impl Default for PhaseBudgets {
+    fn default() -> Self {
+        Self {
+            hashing_ms: DEFAULT_HASHING_BUDGET_MS,
+            rendering_ms: DEFAULT_RENDERING_BUDGET_MS,
+            encoding_ms: DEFAULT_ENCODING_BUDGET_MS,
+            db_ms: DEFAULT_DB_BUDGET_MS,
+        }
+    }
+}
+
+impl PhaseBudgets {
+    pub fn of(&self, phase: Phase) -> Duration {
+        Duration::from_millis(match phase {
+            Phase::Hashing => self.hashing_ms,
+            Phase::Rendering => self.rendering_ms,
+            Phase::Encoding => self.encoding_ms,
+            Phase::Db => self.db_ms,
+        })
+    }
+}
+
+/// Where one run of an operation spent its time
+#[derive(Debug, Clone)]
+pub struct Breakdown {
+    pub operation: Operation,
+    /// Time in each phase that ran, in the order they first ran
+    pub phases: Vec<(Phase, Duration)>,
+    pub total: Duration,
+}
+
+impl Breakdown {
+    /// Time counted in any phase; the rest of `total` is unattributed
+    #[cfg(test)]
+    pub fn accounted(&self) -> Duration {
+        self.phases.iter().map(|(_, spent)| *spent).sum()
+    }
+
+    /// The breakdown as `Server-Timing` entries: each phase, then the total
+    /// described by the operation's name, in milliseconds
+    pub fn server_timing(&self) -> String {
+        let mut entries: Vec<String> =
+            self.phases.iter().map(|(phase, spent)| format!("{};dur={}", phase.as_str(), millis(*spent))).collect();
+        entries.push(format!("total;dur={};desc=\"{}\"", millis(self.total), self.operation.as_str()));
+        entries.join(", ")
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2}", duration.as_secs_f64() * 1000.0)
+}
+
+/// A phase in progress, added to the operation being measured when dropped
+pub struct PhaseGuard {
+    phase: Phase,
+    start: Instant,
+    _span: Option<SpanGuard>,
+}
+
+/// Count the time until the guard is dropped as `phase` of the operation
+/// being measured. Outside `OperationTimings::measure` only its trace span
+/// is recorded, so shared code can mark its phases unconditionally.
+pub fn phase(phase: Phase) -> PhaseGuard {
+    PhaseGuard { phase, start: Instant::now(), _span: tracer::span(phase.span_name()) }
+}
+
+/// Run `work`, counting its time as `phase`
+pub async fn timed<F: Future>(phase: Phase, work: F) -> F::Output {
+    let _guard = self::phase(phase);
+    work.await
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        let spent = self.start.elapsed();
+        let _ = PHASES.try_with(|phases| {
+            let mut phases = phases.borrow_mut();
+            match phases.iter_mut().find(|(phase, _)| *phase == self.phase) {
+                Some((_, total)) => *total += spent,
+                None => phases.push((self.phase, spent)),
+            }
+        });
+    }
+}
+
+/// Run `work`, collecting the breakdown of every operation it measures, for
+/// the `Server-Timing` header
+pub async fn collect<F: Future>(work: F) -> (F::Output, Vec<Breakdown>) {
+    REPORT
+        .scope(RefCell::default(), async {
+            let output = work.await;
+            (output, REPORT.with(|report| report.take()))
+        })
+        .await
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations at or under each of `BUCKET_BOUNDS_MS`
+    buckets: [u64; BUCKET_BOUNDS_MS.len()],
+    count: u64,
+    sum: Duration,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+        for (bound, bucket) in BUCKET_BOUNDS_MS.iter().zip(self.buckets.iter_mut()) {
+            if ms <= *bound as f64 {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += duration;
+    }
+}
+
+/// One bucket of a timing histogram
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramBucket {
+    pub le_ms: u64,
+    pub count: u64,
+}
+
+/// Durations of one phase of an operation since startup
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingHistogram {
+    pub operation: &'static str,
+    /// A phase, or `total` for the whole operation
+    pub phase: &'static str,
+    pub count: u64,
+    pub sum_ms: f64,
+    /// Cumulative, as in Prometheus: each counts the observations at or
+    /// under its bound. Longer ones are only in `count`.
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// Phase durations of the crypto- and image-heavy operations, as histograms
+/// labelled by operation and phase, and the budgets phases are held to
+#[derive(Debug, Default)]
+pub struct OperationTimings {
+    budgets: PhaseBudgets,
+    /// Keyed by operation and phase; no phase for the whole operation
+    histograms: Mutex<BTreeMap<(Operation, Option<Phase>), Histogram>>,
+}
+
+impl OperationTimings {
+    pub fn new(budgets: PhaseBudgets) -> Self {
+        Self { budgets, histograms: Mutex::default() }
+    }
+
+    /// Run `work` as one run of `operation`, timing the phases it marks.
+    /// The breakdown goes into the histograms and, when the request is
+    /// collecting them, into its `Server-Timing` header.
+    pub async fn measure<F: Future>(&self, operation: Operation, work: F) -> F::Output {
+        let started = Instant::now();
+        let (output, phases) = PHASES
+            .scope(RefCell::default(), async {
+                let output = work.await;
+                (output, PHASES.with(|phases| phases.take()))
+            })
+            .await;
+        let breakdown = Breakdown { operation, phases, total: started.elapsed() };
+        self.record(&breakdown);
+        let _ = REPORT.try_with(|report| report.borrow_mut().push(breakdown));
+        output
+    }
+
+    fn record(&self, breakdown: &Breakdown) {
+        for (phase, spent) in &breakdown.phases {
+            let budget = self.budgets.of(*phase);
+            if *spent > budget {
+                log::warn!(
+                    "{} spent {} ms in {}, over its {} ms budget",
+                    breakdown.operation.as_str(),
+                    spent.as_millis(),
+                    phase.as_str(),
+                    budget.as_millis()
+                );
+            }
+        }
+
+        let mut histograms = self.histograms.lock().unwrap();
+        histograms.entry((breakdown.operation, None)).or_default().observe(breakdown.total);
+        for (phase, spent) in &breakdown.phases {
+            histograms.entry((breakdown.operation, Some(*phase))).or_default().observe(*spent);
+        }
+    }
+
+    /// Every histogram with an observation, by operation, the total first
+    pub fn histograms(&self) -> Vec<TimingHistogram> {
+        let histograms = self.histograms.lock().unwrap();
+        histograms
+            .iter()
+            .map(|((operation, phase), histogram)| TimingHistogram {
+                operation: operation.as_str(),
+                phase: phase.map(Phase::as_str).unwrap_or("total"),
+                count: histogram.count,
+                sum_ms: histogram.sum.as_secs_f64() * 1000.0,
+                buckets: BUCKET_BOUNDS_MS
+                    .iter()
+                    .zip(histogram.buckets)
+                    .map(|(bound, count)| HistogramBucket { le_ms: *bound, count })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_phases_add_up_to_the_total_and_land_in_the_histograms() {
+        let timings = OperationTimings::new(PhaseBudgets { db_ms: 10, ..PhaseBudgets::default() });
+        let (output, report) = collect(timings.measure(Operation::ChangePassword, async {
+            timed(Phase::Db, tokio::time::sleep(Duration::from_millis(30))).await;
+            {
+                let _hashing = phase(Phase::Hashing);
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            timed(Phase::Db, tokio::time::sleep(Duration::from_millis(10))).await;
+            "changed"
+        }))
+        .await;
+        assert_eq!(output, "changed");
+
+        // Both database phases are one entry, and little is left unattributed
+        let [breakdown] = report.as_slice() else { panic!("expected one breakdown, got {:?}", report) };
+        let phases: Vec<Phase> = breakdown.phases.iter().map(|(phase, _)| *phase).collect();
+        assert_eq!(phases, [Phase::Db, Phase::Hashing]);
+        assert!(breakdown.phases[0].1 >= Duration::from_millis(40));
+        assert!(breakdown.accounted() <= breakdown.total);
+        assert!(breakdown.total - breakdown.accounted() < Duration::from_millis(20), "{:?}", breakdown);
+        let header = breakdown.server_timing();
+        assert!(header.starts_with("db;dur="), "{}", header);
+        assert!(header.ends_with(";desc=\"change_password\""), "{}", header);
+
+        let histograms = timings.histograms();
+        let labels: Vec<(&str, &str)> = histograms.iter().map(|h| (h.operation, h.phase)).collect();
+        assert_eq!(labels, [("change_password", "total"), ("change_password", "hashing"), ("change_password", "db")]);
+        let db = &histograms[2];
+        assert_eq!(db.count, 1);
+        let bucket = |le_ms: u64| db.buckets.iter().find(|b| b.le_ms == le_ms).unwrap().count;
+        assert_eq!((bucket(25), bucket(10000)), (0, 1));
+
+        // Outside a measured operation phases are not counted anywhere
+        let (_, report) = collect(timed(Phase::Db, async {})).await;
+        assert!(report.is_empty());
+        assert_eq!(timings.histograms()[0].count, 1);
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::Utc;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::models::policy::{EffectivePolicy, OrgSecurityPolicy, SetOrgPolicyRequest};
+
+const POLICY_COLUMNS: &str = "organization, session_timeout_minutes, max_failed_attempts, require_two_fa, \
+                              password_max_age_days, updated_by, updated_at";
+
+/// Resolves the security settings in force for an account from its
+/// organization's overrides and the global configuration.
+///
+/// Lookups are cached per organization, including organizations without a
+/// policy, since every login and session check resolves one. `set` refreshes
+/// the cache, so a change applies to the next request.
+pub struct PolicyResolver {
+    db_pool: SqlitePool,
+    global: EffectivePolicy,
+    cache: Mutex<HashMap<String, Option<OrgSecurityPolicy>>>,
+}
+
+impl PolicyResolver {
+    pub fn new(db_pool: SqlitePool, global: EffectivePolicy) -> Self {
+        Self {
+            db_pool,
+            global,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Settings for an account in `organization`, or the global settings without one
+    pub async fn resolve(&self, organization: Option<&str>) -> Result<EffectivePolicy, sqlx::Error> {
+        let Some(organization) = organization else {
+            return Ok(self.global);
+        };
+        Ok(match self.overrides(organization).await? {
+            Some(policy) => self.global.with_overrides(&policy),
+            None => self.global,
+        })
+    }
+
+    /// An organization's stored overrides, if it has any
+    pub async fn overrides(&self, organization: &str) -> Result<Option<OrgSecurityPolicy>, sqlx::Error> {
+        if let Some(cached) = self.cache.lock().unwrap().get(organization) {
+            return Ok(cached.clone());
+        }
+
+        let policy = sqlx::query_as::<_, OrgSecurityPolicy>(&format!(
+            "SELECT {} FROM org_security_policies WHERE organization = ?",
+            POLICY_COLUMNS
+        ))
+        .bind(organization)
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        self.cache.lock().unwrap().insert(organization.to_string(), policy.clone());
+        Ok(policy)
+    }
+
+    /// Every stored policy, by organization
+    pub async fn list(&self) -> Result<Vec<OrgSecurityPolicy>, sqlx::Error> {
+        sqlx::query_as::<_, OrgSecurityPolicy>(&format!(
+            "SELECT {} FROM org_security_policies ORDER BY organization",
+            POLICY_COLUMNS
+        ))
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// Replace an organization's overrides and drop its cached resolution
+    pub async fn set(
+        &self,
+        organization: &str,
+        request: &SetOrgPolicyRequest,
+        updated_by: Uuid,
+    ) -> Result<OrgSecurityPolicy, sqlx::Error> {
+        let policy = sqlx::query_as::<_, OrgSecurityPolicy>(&format!(
+            r#"
+            INSERT INTO org_security_policies ({})
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(organization) DO UPDATE SET
+                session_timeout_minutes = excluded.session_timeout_minutes,
+                max_failed_attempts = excluded.max_failed_attempts,
+                require_two_fa = excluded.require_two_fa,
+                password_max_age_days = excluded.password_max_age_days,
+                updated_by = excluded.updated_by,
+                updated_at = excluded.updated_at
+            RETURNING {}
+            "#,
+            POLICY_COLUMNS, POLICY_COLUMNS
+        ))
+        .bind(organization)
+        .bind(request.session_timeout_minutes)
+        .bind(request.max_failed_attempts)
+        .bind(request.require_two_fa)
+        .bind(request.password_max_age_days)
+        .bind(updated_by.to_string())
+        .bind(Utc::now())
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        self.cache.lock().unwrap().remove(organization);
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth::SecurityConfig;
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_updates_replace_cached_resolution() {
+        let global = EffectivePolicy::global(&SecurityConfig::default());
+        let resolver = PolicyResolver::new(test_pool().await, global);
+
+        assert_eq!(resolver.resolve(None).await.unwrap(), global);
+        // Organizations without a policy resolve to the global settings, and that is cached too
+        assert_eq!(resolver.resolve(Some("Nakuru County")).await.unwrap(), global);
+
+        let request = SetOrgPolicyRequest { session_timeout_minutes: Some(10), ..SetOrgPolicyRequest::default() };
+        resolver.set("Nakuru County", &request, Uuid::new_v4()).await.unwrap();
+        assert_eq!(resolver.resolve(Some("Nakuru County")).await.unwrap().session_timeout_minutes, 10);
+
+        let request = SetOrgPolicyRequest { session_timeout_minutes: Some(15), require_two_fa: Some(true), ..SetOrgPolicyRequest::default() };
+        let stored = resolver.set("Nakuru County", &request, Uuid::new_v4()).await.unwrap();
+        assert_eq!(stored.max_failed_attempts, None);
+
+        let effective = resolver.resolve(Some("Nakuru County")).await.unwrap();
+        assert_eq!(effective.session_timeout_minutes, 15);
+        assert!(effective.require_two_fa);
+        assert_eq!(effective.max_failed_attempts, global.max_failed_attempts);
+        assert_eq!(resolver.list().await.unwrap(), vec![stored]);
+    }
+}
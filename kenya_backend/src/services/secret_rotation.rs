@@ -0,0 +1,562 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::audit_event::AuditEventType;
+use crate::models::auth::Severity;
+use crate::models::context::RequestContext;
+use crate::services::audit_service::AuditService;
+use crate::services::geoip_service::GeoIpService;
+use crate::utils::clock::Clock;
+
+/// How long a second administrator has to confirm a staged secret
+pub const STAGE_CONFIRM_TTL_MINUTES: i64 = 60;
+
+/// Shortest secret that may be staged, as the security posture check asks of `JWT_SECRET`
+const MIN_SECRET_BYTES: usize = 32;
+
+/// A secret whose changes need two administrators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretKind {
+    /// `JWT_SECRET`
+    Jwt,
+}
+
+impl SecretKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecretKind::Jwt => "jwt",
+        }
+    }
+
+    /// The setting the secret is read from
+    pub fn env_var(self) -> &'static str {
+        match self {
+            SecretKind::Jwt => "JWT_SECRET",
+        }
+    }
+}
+
+impl FromStr for SecretKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "jwt" => Ok(SecretKind::Jwt),
+            "pepper" => Err("passwords are hashed without a pepper, so there is none to rotate".to_string()),
+            _ => Err(format!("unknown secret kind {:?}; expected jwt", name)),
+        }
+    }
+}
+
+impl fmt::Display for SecretKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A new secret one administrator has staged, identified only by its fingerprint
+#[derive(Debug, Clone)]
+pub struct StagedSecret {
+    pub id: Uuid,
+    pub kind: SecretKind,
+    /// Hex SHA-256 of the secret
+    pub fingerprint: String,
+    pub staged_by: Uuid,
+    pub staged_at: DateTime<Utc>,
+    /// Unconfirmed after this, the stage can no longer be confirmed
+    pub expires_at: DateTime<Utc>,
+    pub confirmed_by: Option<Uuid>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    /// When a server first started with the secret
+    pub activated_at: Option<DateTime<Utc>>,
+}
+
+impl StagedSecret {
+    /// The short form logs and the startup banner show
+    pub fn short_fingerprint(&self) -> &str {
+        &self.fingerprint[..8]
+    }
+}
+
+/// What the startup check found
+#[derive(Debug)]
+pub enum SecretCheck {
+    /// The configured secret is the one already in use
+    Unchanged,
+    /// Nothing was recorded yet, so the configured secret is taken as the one in use
+    Adopted,
+    /// The configured secret is a confirmed stage, now in use
+    Activated(StagedSecret),
+    /// The configured secret changed without a confirmed stage, and this
+    /// isn't production, so it was taken anyway
+    Unguarded,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RotationError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("The new secret is {0} bytes; use at least 32")]
+    TooShort(usize),
+    #[error("The new secret is the one already in use")]
+    AlreadyActive,
+    #[error("No staged secret has this token")]
+    UnknownToken,
+    #[error("The administrator who staged a secret can't also confirm it")]
+    SameAdministrator,
+    #[error("The staged secret was already confirmed")]
+    AlreadyConfirmed,
+    #[error("The staged secret expired unconfirmed at {0}; stage it again")]
+    Expired(DateTime<Utc>),
+    #[error("{env_var} (fingerprint {fingerprint}) is staged but not confirmed; a second administrator must run `admin confirm-secret`")]
+    AwaitingConfirmation { env_var: &'static str, fingerprint: String },
+    #[error("{env_var} (fingerprint {fingerprint}) doesn't match the secret in use or any confirmed stage; stage and confirm it with `admin stage-secret` and `admin confirm-secret`")]
+    Unapproved { env_var: &'static str, fingerprint: String },
+}
+
+type StageRow = (
+    Uuid,
+    String,
+    String,
+    Uuid,
+    DateTime<Utc>,
+    DateTime<Utc>,
+    Option<Uuid>,
+    Option<DateTime<Utc>>,
+    Option<DateTime<Utc>>,
+);
+
+/// Two-person changes to the secrets the server signs with.
+///
+/// One administrator stages the new secret from the command line; only its
+/// fingerprint is stored, with the hash of an activation token. A second
+/// administrator confirms it with that token within
+/// `STAGE_CONFIRM_TTL_MINUTES`. At startup the configured secret must be the
+/// one in use or a confirmed stage, or a production server refuses to start.
+/// Every step is a critical security event.
+pub struct SecretRotationService {
+    db_pool: SqlitePool,
+    audit_service: AuditService,
+    clock: Arc<dyn Clock>,
+}
+
+impl SecretRotationService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        let audit_service = AuditService::new(db_pool.clone(), Arc::new(GeoIpService::disabled()), clock.clone());
+        Self { db_pool, audit_service, clock }
+    }
+
+    /// Stage `material` as the next `kind` secret on behalf of `staged_by`.
+    /// Returns the stage with its activation token, which isn't kept and
+    /// can't be recovered later.
+    pub async fn stage(
+        &self,
+        ctx: &RequestContext,
+        kind: SecretKind,
+        material: &str,
+        staged_by: Uuid,
+    ) -> Result<(StagedSecret, String), RotationError> {
+        if material.len() < MIN_SECRET_BYTES {
+            return Err(RotationError::TooShort(material.len()));
+        }
+        let fingerprint = fingerprint(material);
+        if self.active_fingerprint(kind).await?.as_deref() == Some(fingerprint.as_str()) {
+            return Err(RotationError::AlreadyActive);
+        }
+
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let now = self.clock.now();
+        let stage = StagedSecret {
+            id: Uuid::new_v4(),
+            kind,
+            fingerprint,
+            staged_by,
+            staged_at: now,
+            expires_at: now + Duration::minutes(STAGE_CONFIRM_TTL_MINUTES),
+            confirmed_by: None,
+            confirmed_at: None,
+            activated_at: None,
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO secret_stages (id, kind, fingerprint, token_hash, staged_by, staged_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(stage.id)
+        .bind(kind.as_str())
+        .bind(&stage.fingerprint)
+        .bind(token_hash(&token))
+        .bind(staged_by)
+        .bind(stage.staged_at)
+        .bind(stage.expires_at)
+        .execute(&self.db_pool)
+        .await?;
+
+        self.audit(
+            ctx,
+            Some(staged_by),
+            AuditEventType::SecretStaged,
+            &format!("New {} staged, awaiting a second administrator", kind.env_var()),
+            true,
+            json!({ "kind": kind.as_str(), "fingerprint": stage.short_fingerprint(), "expires_at": stage.expires_at }),
+        )
+        .await;
+        Ok((stage, token))
+    }
+
+    /// Confirm the stage behind `token` on behalf of `confirmed_by`, who
+    /// must not be the administrator who staged it
+    pub async fn confirm(&self, ctx: &RequestContext, token: &str, confirmed_by: Uuid) -> Result<StagedSecret, RotationError> {
+        let hash = token_hash(token);
+        let Some(stage) = self.find(&hash).await? else {
+            return Err(self.refuse_confirmation(ctx, confirmed_by, None, RotationError::UnknownToken).await);
+        };
+        if stage.staged_by == confirmed_by {
+            return Err(self.refuse_confirmation(ctx, confirmed_by, Some(&stage), RotationError::SameAdministrator).await);
+        }
+        if stage.confirmed_at.is_some() {
+            return Err(self.refuse_confirmation(ctx, confirmed_by, Some(&stage), RotationError::AlreadyConfirmed).await);
+        }
+        let now = self.clock.now();
+        if now >= stage.expires_at {
+            let expired = RotationError::Expired(stage.expires_at);
+            return Err(self.refuse_confirmation(ctx, confirmed_by, Some(&stage), expired).await);
+        }
+
+        // Two confirmations racing: only one of them counts
+        let confirmed = sqlx::query(
+            "UPDATE secret_stages SET confirmed_by = ?, confirmed_at = ? WHERE token_hash = ? AND confirmed_at IS NULL",
+        )
+        .bind(confirmed_by)
+        .bind(now)
+        .bind(&hash)
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if !confirmed {
+            return Err(self.refuse_confirmation(ctx, confirmed_by, Some(&stage), RotationError::AlreadyConfirmed).await);
+        }
+
+        let stage = StagedSecret { confirmed_by: Some(confirmed_by), confirmed_at: Some(now), ..stage };
+        self.audit(
+            ctx,
+            Some(confirmed_by),
+            AuditEventType::SecretConfirmed,
+            &format!("New {} confirmed; servers take it on their next restart", stage.kind.env_var()),
+            true,
+            json!({ "kind": stage.kind.as_str(), "fingerprint": stage.short_fingerprint(), "staged_by": stage.staged_by }),
+        )
+        .await;
+        Ok(stage)
+    }
+
+    /// Record a refused confirmation, handing back why it was refused
+    async fn refuse_confirmation(
+        &self,
+        ctx: &RequestContext,
+        confirmed_by: Uuid,
+        stage: Option<&StagedSecret>,
+        error: RotationError,
+    ) -> RotationError {
+        self.audit(
+            ctx,
+            Some(confirmed_by),
+            AuditEventType::SecretRejected,
+            &format!("Staged secret not confirmed: {}", error),
+            false,
+            json!({
+                "kind": stage.map(|stage| stage.kind.as_str()),
+                "fingerprint": stage.map(StagedSecret::short_fingerprint),
+            }),
+        )
+        .await;
+        error
+    }
+
+    /// Check the configured `material` at startup. The secret in use, or a
+    /// confirmed stage, is accepted; the first check on a database adopts
+    /// whatever is configured. Anything else is refused when `enforce` is
+    /// set, and otherwise taken with a warning.
+    pub async fn check_configured(&self, kind: SecretKind, material: &str, enforce: bool) -> Result<SecretCheck, RotationError> {
+        let ctx = RequestContext::new("startup", None);
+        let fingerprint = fingerprint(material);
+        let short = fingerprint[..8].to_string();
+        let active = self.active_fingerprint(kind).await?;
+        if active.as_deref() == Some(fingerprint.as_str()) {
+            return Ok(SecretCheck::Unchanged);
+        }
+        if active.is_none() {
+            self.set_active(kind, &fingerprint).await?;
+            return Ok(SecretCheck::Adopted);
+        }
+
+        let stages = self.stages_with(kind, &fingerprint).await?;
+        if let Some(stage) = stages.iter().find(|stage| stage.confirmed_at.is_some()) {
+            let now = self.clock.now();
+            sqlx::query("UPDATE secret_stages SET activated_at = ? WHERE id = ? AND activated_at IS NULL")
+                .bind(now)
+                .bind(stage.id)
+                .execute(&self.db_pool)
+                .await?;
+            self.set_active(kind, &fingerprint).await?;
+            self.audit(
+                &ctx,
+                stage.confirmed_by,
+                AuditEventType::SecretActivated,
+                &format!("Server started with the confirmed new {}", kind.env_var()),
+                true,
+                json!({ "kind": kind.as_str(), "fingerprint": short, "staged_by": stage.staged_by, "confirmed_by": stage.confirmed_by }),
+            )
+            .await;
+            return Ok(SecretCheck::Activated(StagedSecret { activated_at: stage.activated_at.or(Some(now)), ..stage.clone() }));
+        }
+
+        let error = if stages.is_empty() {
+            RotationError::Unapproved { env_var: kind.env_var(), fingerprint: short.clone() }
+        } else {
+            RotationError::AwaitingConfirmation { env_var: kind.env_var(), fingerprint: short.clone() }
+        };
+        if !enforce {
+            self.set_active(kind, &fingerprint).await?;
+            self.audit(
+                &ctx,
+                None,
+                AuditEventType::SecretActivated,
+                &format!("Server started with an unconfirmed {} outside production", kind.env_var()),
+                false,
+                json!({ "kind": kind.as_str(), "fingerprint": short, "unguarded": true }),
+            )
+            .await;
+            return Ok(SecretCheck::Unguarded);
+        }
+        self.audit(
+            &ctx,
+            None,
+            AuditEventType::SecretRejected,
+            &format!("Refused to start: {}", error),
+            false,
+            json!({ "kind": kind.as_str(), "fingerprint": short }),
+        )
+        .await;
+        Err(error)
+    }
+
+    async fn active_fingerprint(&self, kind: SecretKind) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT fingerprint FROM active_secrets WHERE kind = ?")
+            .bind(kind.as_str())
+            .fetch_optional(&self.db_pool)
+            .await
+    }
+
+    async fn set_active(&self, kind: SecretKind, fingerprint: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO active_secrets (kind, fingerprint, activated_at) VALUES (?, ?, ?)
+            ON CONFLICT(kind) DO UPDATE SET fingerprint = excluded.fingerprint, activated_at = excluded.activated_at
+            "#
+        )
+        .bind(kind.as_str())
+        .bind(fingerprint)
+        .bind(self.clock.now())
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn find(&self, hash: &str) -> Result<Option<StagedSecret>, sqlx::Error> {
+        let row: Option<StageRow> = sqlx::query_as(
+            r#"
+            SELECT id, kind, fingerprint, staged_by, staged_at, expires_at, confirmed_by, confirmed_at, activated_at
+            FROM secret_stages WHERE token_hash = ?
+            "#
+        )
+        .bind(hash)
+        .fetch_optional(&self.db_pool)
+        .await?;
+        Ok(row.and_then(stage_from_row))
+    }
+
+    /// Stages of `fingerprint`, most recently staged first
+    async fn stages_with(&self, kind: SecretKind, fingerprint: &str) -> Result<Vec<StagedSecret>, sqlx::Error> {
+        let rows: Vec<StageRow> = sqlx::query_as(
+            r#"
+            SELECT id, kind, fingerprint, staged_by, staged_at, expires_at, confirmed_by, confirmed_at, activated_at
+            FROM secret_stages WHERE kind = ? AND fingerprint = ? ORDER BY staged_at DESC
+            "#
+        )
+        .bind(kind.as_str())
+        .bind(fingerprint)
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows.into_iter().filter_map(stage_from_row).collect())
+    }
+
+    async fn audit(
+        &self,
+        ctx: &RequestContext,
+        user_id: Option<Uuid>,
+        event_type: AuditEventType,
+        description: &str,
+        success: bool,
+        details: serde_json::Value,
+    ) {
+        self.audit_service
+            .log_security_event(ctx, user_id, event_type, description, success, Severity::Critical, Some(details))
+            .await
+            .unwrap_or_else(|e| log::error!("Failed to log {}: {}", event_type, e));
+    }
+}
+
+/// A stored stage, unless it names a kind this binary doesn't know
+fn stage_from_row(
+    (id, kind, fingerprint, staged_by, staged_at, expires_at, confirmed_by, confirmed_at, activated_at): StageRow,
+) -> Option<StagedSecret> {
+    Some(StagedSecret {
+        id,
+        kind: kind.parse().ok()?,
+        fingerprint,
+        staged_by,
+        staged_at,
+        expires_at,
+        confirmed_by,
+        confirmed_at,
+        activated_at,
+    })
+}
+
+/// Hex SHA-256 of a secret. Its first 8 characters are the fingerprint the
+/// startup banner shows for the JWT secret.
+fn fingerprint(material: &str) -> String {
+    Sha256::digest(material.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    const CURRENT: &str = "current-jwt-secret-of-enough-length-0001";
+    const NEXT: &str = "next-jwt-secret-of-enough-length-00000002";
+
+    async fn critical_events(pool: &SqlitePool, event_type: AuditEventType) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM security_events WHERE event_type = ? AND severity = 'critical'")
+            .bind(event_type)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_second_administrator_confirms_and_a_restart_activates() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let rotation = SecretRotationService::new(pool.clone(), clock.clone());
+        let first = insert_user(&pool, clock.clone(), "first_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let second = insert_user(&pool, clock.clone(), "second_admin", UserRole::Admin, TEST_PASSWORD, false).await;
+        let ctx = RequestContext::new("cli", None);
+        assert!(matches!(rotation.check_configured(SecretKind::Jwt, CURRENT, true).await, Ok(SecretCheck::Adopted)));
+
+        let (stage, token) = rotation.stage(&ctx, SecretKind::Jwt, NEXT, first.id).await.unwrap();
+        // Only the fingerprint and the token's hash are kept
+        let stored: (String, String) = sqlx::query_as("SELECT fingerprint, token_hash FROM secret_stages")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored.0, stage.fingerprint);
+        assert_eq!(&stored.0[..8], crate::utils::self_test::secret_fingerprint(NEXT));
+        assert_ne!(stored.1, token);
+
+        // Staged, the new secret still can't start a server
+        let refused = rotation.check_configured(SecretKind::Jwt, NEXT, true).await;
+        assert!(matches!(refused, Err(RotationError::AwaitingConfirmation { .. })), "{:?}", refused);
+
+        // The administrator who staged it can't approve it too
+        let same = rotation.confirm(&ctx, &token, first.id).await;
+        assert!(matches!(same, Err(RotationError::SameAdministrator)));
+        assert!(matches!(rotation.confirm(&ctx, "not-a-token", second.id).await, Err(RotationError::UnknownToken)));
+
+        let confirmed = rotation.confirm(&ctx, &token, second.id).await.unwrap();
+        assert_eq!(confirmed.confirmed_by, Some(second.id));
+        assert!(matches!(rotation.confirm(&ctx, &token, second.id).await, Err(RotationError::AlreadyConfirmed)));
+
+        let Ok(SecretCheck::Activated(activated)) = rotation.check_configured(SecretKind::Jwt, NEXT, true).await else {
+            panic!("the confirmed secret should activate");
+        };
+        assert_eq!(activated.id, stage.id);
+        assert!(activated.activated_at.is_some());
+        assert!(matches!(rotation.check_configured(SecretKind::Jwt, NEXT, true).await, Ok(SecretCheck::Unchanged)));
+
+        // Going back is a change like any other
+        let back = rotation.check_configured(SecretKind::Jwt, CURRENT, true).await;
+        assert!(matches!(back, Err(RotationError::Unapproved { .. })), "{:?}", back);
+
+        for event_type in [AuditEventType::SecretStaged, AuditEventType::SecretConfirmed, AuditEventType::SecretActivated] {
+            assert_eq!(critical_events(&pool, event_type).await, 1, "{}", event_type);
+        }
+        assert_eq!(critical_events(&pool, AuditEventType::SecretRejected).await, 5);
+    }
+
+    #[actix_web::test]
+    async fn test_unstaged_secret_refuses_production_startup_only() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let rotation = SecretRotationService::new(pool.clone(), clock.clone());
+        rotation.check_configured(SecretKind::Jwt, CURRENT, true).await.unwrap();
+
+        let refused = rotation.check_configured(SecretKind::Jwt, NEXT, true).await;
+        let Err(RotationError::Unapproved { env_var, fingerprint }) = refused else {
+            panic!("expected a refusal, got {:?}", refused);
+        };
+        assert_eq!((env_var, fingerprint.as_str()), ("JWT_SECRET", crate::utils::self_test::secret_fingerprint(NEXT).as_str()));
+        assert_eq!(critical_events(&pool, AuditEventType::SecretRejected).await, 1);
+
+        // Outside production the change is taken, and recorded
+        assert!(matches!(rotation.check_configured(SecretKind::Jwt, NEXT, false).await, Ok(SecretCheck::Unguarded)));
+        assert!(matches!(rotation.check_configured(SecretKind::Jwt, NEXT, true).await, Ok(SecretCheck::Unchanged)));
+
+        let ctx = RequestContext::new("cli", None);
+        let staged_by = Uuid::new_v4();
+        assert!(matches!(rotation.stage(&ctx, SecretKind::Jwt, "short", staged_by).await, Err(RotationError::TooShort(5))));
+        assert!(matches!(rotation.stage(&ctx, SecretKind::Jwt, NEXT, staged_by).await, Err(RotationError::AlreadyActive)));
+        assert!("pepper".parse::<SecretKind>().is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_unconfirmed_stage_expires() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let rotation = SecretRotationService::new(pool.clone(), clock.clone());
+        let ctx = RequestContext::new("cli", None);
+        rotation.check_configured(SecretKind::Jwt, CURRENT, true).await.unwrap();
+
+        let (stage, token) = rotation.stage(&ctx, SecretKind::Jwt, NEXT, Uuid::new_v4()).await.unwrap();
+        clock.advance(Duration::minutes(STAGE_CONFIRM_TTL_MINUTES));
+        let expired = rotation.confirm(&ctx, &token, Uuid::new_v4()).await;
+        assert!(matches!(expired, Err(RotationError::Expired(at)) if at == stage.expires_at), "{:?}", expired);
+        assert!(matches!(rotation.check_configured(SecretKind::Jwt, NEXT, true).await, Err(RotationError::AwaitingConfirmation { .. })));
+
+        // Staged again, it can be confirmed afresh
+        let (_, token) = rotation.stage(&ctx, SecretKind::Jwt, NEXT, Uuid::new_v4()).await.unwrap();
+        rotation.confirm(&ctx, &token, Uuid::new_v4()).await.unwrap();
+        assert!(matches!(rotation.check_configured(SecretKind::Jwt, NEXT, true).await, Ok(SecretCheck::Activated(_))));
+    }
+}
@@ -0,0 +1,411 @@
+use serde::Serialize;
+use std::sync::RwLock;
+
+use crate::config::{AppConfig, DEFAULT_JWT_SECRET};
+use crate::models::auth::Severity;
+use crate::services::feature_flags::{Feature, FeatureFlags};
+
+/// JWT secrets shorter than this are brute-forceable offline from one token
+const MIN_JWT_SECRET_BYTES: usize = 32;
+
+/// Webhook signing secrets shorter than this are guessable from signed payloads
+const MIN_WEBHOOK_SECRET_BYTES: usize = 16;
+
+/// Failed logins beyond this before a lockout leave room for password guessing
+const MAX_LOCKOUT_ATTEMPTS: i32 = 10;
+
+/// Lockouts shorter than this barely slow guessing down
+const MIN_LOCKOUT_MINUTES: i64 = 5;
+
+/// One combination of settings that weakens the deployment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PostureFinding {
+    /// Stable name of the rule that raised it
+    pub id: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// What the rules look at: the configuration and the feature flags in force
+pub struct PostureInputs<'a> {
+    pub config: &'a AppConfig,
+    pub enabled: &'a dyn Fn(Feature) -> bool,
+}
+
+struct Rule {
+    id: &'static str,
+    severity: Severity,
+    /// Why the inputs trip the rule, or `None` when they don't
+    check: fn(&PostureInputs) -> Option<String>,
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        id: "default_jwt_secret",
+        severity: Severity::Critical,
+        check: |inputs| {
+            (inputs.config.jwt_secret == DEFAULT_JWT_SECRET)
+                .then(|| "JWT_SECRET is unset, so tokens are signed with the published default key".to_string())
+        },
+    },
+    Rule {
+        id: "short_jwt_secret",
+        severity: Severity::Warning,
+        check: |inputs| {
+            let secret = &inputs.config.jwt_secret;
+            (secret != DEFAULT_JWT_SECRET && secret.len() < MIN_JWT_SECRET_BYTES).then(|| {
+                format!("JWT_SECRET is {} bytes; use at least {}", secret.len(), MIN_JWT_SECRET_BYTES)
+            })
+        },
+    },
+    Rule {
+        id: "plaintext_public_url",
+        severity: Severity::Critical,
+        check: |inputs| {
+            let url = &inputs.config.public_base_url;
+            is_remote_http(url).then(|| {
+                format!("PUBLIC_BASE_URL {} is not HTTPS, so emailed links and their tokens travel in clear", url)
+            })
+        },
+    },
+    Rule {
+        id: "http_cors_origin",
+        severity: Severity::Warning,
+        check: |inputs| {
+            let origins: Vec<&str> = inputs
+                .config
+                .cors_origins
+                .iter()
+                .filter(|origin| origin.starts_with("http://"))
+                .map(String::as_str)
+                .collect();
+            (inputs.config.production && !origins.is_empty())
+                .then(|| format!("CORS_ORIGINS allows plain HTTP origins in production: {}", origins.join(", ")))
+        },
+    },
+    Rule {
+        id: "plaintext_webhook",
+        severity: Severity::Warning,
+        check: |inputs| {
+            let names: Vec<&str> = inputs
+                .config
+                .webhook_destinations
+                .iter()
+                .filter(|destination| is_remote_http(&destination.url))
+                .map(|destination| destination.name.as_str())
+                .collect();
+            (!names.is_empty())
+                .then(|| format!("Webhook destinations deliver security events over plain HTTP: {}", names.join(", ")))
+        },
+    },
+    Rule {
+        id: "short_webhook_secret",
+        severity: Severity::Warning,
+        check: |inputs| {
+            let names: Vec<&str> = inputs
+                .config
+                .webhook_destinations
+                .iter()
+                .filter(|destination| destination.secret.len() < MIN_WEBHOOK_SECRET_BYTES)
+                .map(|destination| destination.name.as_str())
+                .collect();
+            (!names.is_empty()).then(|| {
+                format!(
+                    "Webhook signing secrets shorter than {} bytes: {}",
+                    MIN_WEBHOOK_SECRET_BYTES,
+                    names.join(", ")
+                )
+            })
+        },
+    },
+    Rule {
+        id: "login_bot_checks_off",
+        severity: Severity::Warning,
+        check: |inputs| {
+            if !(inputs.enabled)(Feature::LoginBotChecks) {
+                Some("The login_bot_checks feature flag is off, so scripted logins go unnoticed".to_string())
+            } else if !inputs.config.login_honeypot_enabled {
+                Some("LOGIN_HONEYPOT_ENABLED is off, so filled-in honeypots don't refuse logins".to_string())
+            } else {
+                None
+            }
+        },
+    },
+    Rule {
+        id: "impossible_travel_without_geoip",
+        severity: Severity::Warning,
+        check: |inputs| {
+            ((inputs.enabled)(Feature::ImpossibleTravel) && inputs.config.geoip_city_db_path.is_none()).then(|| {
+                "The impossible_travel feature flag is on without GEOIP_CITY_DB_PATH, so it never fires".to_string()
+            })
+        },
+    },
+    Rule {
+        id: "break_glass_enabled",
+        severity: Severity::Warning,
+        check: |inputs| {
+            inputs
+                .config
+                .break_glass_enabled
+                .then(|| "BREAK_GLASS_ENABLED is on; turn it off once the emergency is over".to_string())
+        },
+    },
+    Rule {
+        id: "per_instance_counters",
+        severity: Severity::Warning,
+        check: |inputs| {
+            (inputs.config.instance_count > 1 && inputs.config.redis_url.is_none()).then(|| {
+                format!(
+                    "INSTANCE_COUNT={} without REDIS_URL, so each instance allows the full login failure budget",
+                    inputs.config.instance_count
+                )
+            })
+        },
+    },
+    Rule {
+        id: "lax_lockout",
+        severity: Severity::Warning,
+        check: |inputs| {
+            let config = inputs.config;
+            (config.max_failed_login_attempts > MAX_LOCKOUT_ATTEMPTS || config.lockout_duration_minutes < MIN_LOCKOUT_MINUTES)
+                .then(|| {
+                    format!(
+                        "Accounts lock after {} failed logins for {} minutes; use at most {} attempts and at least {} minutes",
+                        config.max_failed_login_attempts,
+                        config.lockout_duration_minutes,
+                        MAX_LOCKOUT_ATTEMPTS,
+                        MIN_LOCKOUT_MINUTES
+                    )
+                })
+        },
+    },
+    Rule {
+        id: "unsigned_audit_exports",
+        severity: Severity::Info,
+        check: |inputs| {
+            (inputs.config.production && inputs.config.audit_signing_key_path.is_none())
+                .then(|| "AUDIT_SIGNING_KEY_PATH is unset, so audit exports can't be verified".to_string())
+        },
+    },
+    Rule {
+        id: "lockout_exemptions",
+        severity: Severity::Warning,
+        check: |inputs| {
+            // Networks only narrow where the listed accounts are exempt
+            let config = inputs.config;
+            (!config.lockout_exempt_usernames.is_empty()).then(|| {
+                let from = if config.lockout_exempt_cidrs.is_empty() {
+                    "anywhere".to_string()
+                } else {
+                    format!("networks {}", config.lockout_exempt_cidrs.join(", "))
+                };
+                format!(
+                    "Failed logins by {} (while tagged `monitoring`) from {} never lock them or count toward throttling",
+                    config.lockout_exempt_usernames.join(", "),
+                    from
+                )
+            })
+        },
+    },
+];
+
+/// Whether `url` is plain HTTP to somewhere other than this host
+fn is_remote_http(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("http://") else {
+        return false;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = authority.rsplit('@').next().unwrap_or("");
+    let host = if host_port.starts_with('[') {
+        host_port.split(']').next().map(|h| &h[1..]).unwrap_or("")
+    } else {
+        host_port.split(':').next().unwrap_or("")
+    };
+    !(host == "localhost" || host == "::1" || host.starts_with("127."))
+}
+
+/// Every finding the rules raise for `inputs`, most severe first
+pub fn evaluate(inputs: &PostureInputs) -> Vec<PostureFinding> {
+    let mut findings: Vec<PostureFinding> = RULES
+        .iter()
+        .filter_map(|rule| {
+            (rule.check)(inputs).map(|message| PostureFinding { id: rule.id, severity: rule.severity, message })
+        })
+        .collect();
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    findings
+}
+
+/// Settings combinations that silently weaken the deployment. Checked at
+/// startup and whenever the feature flags or the findings are asked for;
+/// each finding is logged when it first appears and when it clears.
+pub struct SecurityPostureCheck {
+    config: AppConfig,
+    last: RwLock<Vec<PostureFinding>>,
+}
+
+impl SecurityPostureCheck {
+    pub fn new(config: AppConfig) -> Self {
+        Self { config, last: RwLock::new(Vec::new()) }
+    }
+
+    /// Evaluate the rules against the configuration and the flags in force now
+    pub fn run(&self, features: &FeatureFlags) -> Vec<PostureFinding> {
+        let enabled = |feature| features.enabled(feature);
+        let findings = evaluate(&PostureInputs { config: &self.config, enabled: &enabled });
+
+        if let Ok(mut last) = self.last.write() {
+            for finding in findings.iter().filter(|f| !last.iter().any(|seen| seen.id == f.id)) {
+                match finding.severity {
+                    Severity::Critical => log::error!("Security posture [{}]: {}", finding.id, finding.message),
+                    Severity::Warning => log::warn!("Security posture [{}]: {}", finding.id, finding.message),
+                    Severity::Info => log::info!("Security posture [{}]: {}", finding.id, finding.message),
+                }
+            }
+            for cleared in last.iter().filter(|seen| !findings.iter().any(|f| f.id == seen.id)) {
+                log::info!("Security posture [{}] cleared", cleared.id);
+            }
+            *last = findings.clone();
+        }
+        findings
+    }
+
+    /// Why startup should stop: production with `SECURITY_POSTURE_ENFORCE`
+    /// and at least one critical finding
+    pub fn blocking_reason(&self, findings: &[PostureFinding]) -> Option<String> {
+        if !(self.config.production && self.config.security_posture_enforce) {
+            return None;
+        }
+        let critical: Vec<&str> = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Critical)
+            .map(|f| f.id)
+            .collect();
+        (!critical.is_empty()).then(|| {
+            format!(
+                "Refusing to start: critical security posture findings ({}); fix them or unset SECURITY_POSTURE_ENFORCE",
+                critical.join(", ")
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::webhook_service::WebhookDestination;
+
+    /// A production configuration that raises nothing
+    fn sound_config() -> AppConfig {
+        let mut config = AppConfig::test_config();
+        config.production = true;
+        config.jwt_secret = "a-production-jwt-secret-of-enough-length".to_string();
+        config.cors_origins = vec!["https://kenya.fsfvi.ai".to_string()];
+        config.geoip_city_db_path = Some("/var/lib/geoip/GeoLite2-City.mmdb".to_string());
+        config.audit_signing_key_path = Some("/etc/fsfvi/audit-signing.key".to_string());
+        config
+    }
+
+    fn findings(config: &AppConfig, flags_on: bool) -> Vec<&'static str> {
+        let enabled = |_: Feature| flags_on;
+        evaluate(&PostureInputs { config, enabled: &enabled }).into_iter().map(|f| f.id).collect()
+    }
+
+    type Tweak = fn(&mut AppConfig);
+
+    #[test]
+    fn test_each_rule_fires_on_its_trigger_only() {
+        assert_eq!(findings(&sound_config(), true), Vec::<&str>::new());
+
+        // Rule, a configuration that trips it, and a close one that doesn't
+        let cases: &[(&str, Tweak, Tweak)] = &[
+            ("default_jwt_secret", |c| c.jwt_secret = DEFAULT_JWT_SECRET.to_string(), |c| c.jwt_secret = "x".repeat(32)),
+            ("short_jwt_secret", |c| c.jwt_secret = "x".repeat(31), |c| c.jwt_secret = "x".repeat(32)),
+            (
+                "plaintext_public_url",
+                |c| c.public_base_url = "http://api.kenya.fsfvi.ai".to_string(),
+                |c| c.public_base_url = "http://localhost:8080".to_string(),
+            ),
+            (
+                "http_cors_origin",
+                |c| c.cors_origins.push("http://localhost:3000".to_string()),
+                |c| {
+                    c.cors_origins.push("http://localhost:3000".to_string());
+                    c.production = false;
+                },
+            ),
+            (
+                "plaintext_webhook",
+                |c| c.webhook_destinations[0].url = "http://siem.example.go.ke/hooks".to_string(),
+                |c| c.webhook_destinations[0].url = "http://127.0.0.1:9000/hooks".to_string(),
+            ),
+            (
+                "short_webhook_secret",
+                |c| {
+                    c.webhook_destinations.push(WebhookDestination {
+                        name: "slack".to_string(),
+                        url: "https://hooks.example.com/x".to_string(),
+                        secret: "short".to_string(),
+                    })
+                },
+                |c| c.webhook_destinations[0].secret = "x".repeat(16),
+            ),
+            ("login_bot_checks_off", |c| c.login_honeypot_enabled = false, |c| c.login_min_fill_ms = 0),
+            ("impossible_travel_without_geoip", |c| c.geoip_city_db_path = None, |c| c.geoip_asn_db_path = None),
+            ("break_glass_enabled", |c| c.break_glass_enabled = true, |c| c.maintenance_mode = true),
+            ("per_instance_counters", |c| c.redis_url = None, |c| {
+                c.redis_url = None;
+                c.instance_count = 1;
+            }),
+            ("lax_lockout", |c| c.lockout_duration_minutes = 4, |c| c.max_failed_login_attempts = 10),
+            ("unsigned_audit_exports", |c| c.audit_signing_key_path = None, |c| {
+                c.audit_signing_key_path = None;
+                c.production = false;
+            }),
+            ("lockout_exemptions", |c| c.lockout_exempt_usernames.push("uptime_probe".to_string()), |c| {
+                c.lockout_exempt_usernames.clear();
+                c.lockout_exempt_cidrs.push("196.201.214.0/24".to_string());
+            }),
+        ];
+        assert_eq!(cases.len(), RULES.len(), "every rule needs a case");
+
+        for (id, trigger, non_trigger) in cases {
+            let mut config = sound_config();
+            trigger(&mut config);
+            assert_eq!(findings(&config, true), vec![*id], "{} should fire", id);
+
+            let mut config = sound_config();
+            non_trigger(&mut config);
+            assert_eq!(findings(&config, true), Vec::<&str>::new(), "{} should stay quiet", id);
+        }
+    }
+
+    #[test]
+    fn test_flags_switched_off_are_findings() {
+        let mut config = sound_config();
+        assert_eq!(findings(&config, false), vec!["login_bot_checks_off"]);
+
+        // Impossible travel can't fire without GeoIP, but switched off it isn't claimed either
+        config.geoip_city_db_path = None;
+        assert_eq!(findings(&config, false), vec!["login_bot_checks_off"]);
+    }
+
+    #[test]
+    fn test_critical_findings_block_startup_only_when_enforced_in_production() {
+        let mut config = sound_config();
+        config.jwt_secret = DEFAULT_JWT_SECRET.to_string();
+        config.break_glass_enabled = true;
+        let enabled = |_: Feature| true;
+        let found = evaluate(&PostureInputs { config: &config, enabled: &enabled });
+        assert_eq!(found[0].severity, Severity::Critical);
+
+        assert_eq!(SecurityPostureCheck::new(config.clone()).blocking_reason(&found), None);
+        config.security_posture_enforce = true;
+        let reason = SecurityPostureCheck::new(config.clone()).blocking_reason(&found).unwrap();
+        assert!(reason.contains("default_jwt_secret"));
+        assert!(!reason.contains("break_glass_enabled"));
+
+        config.production = false;
+        assert_eq!(SecurityPostureCheck::new(config).blocking_reason(&found), None);
+    }
+}
@@ -0,0 +1,439 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// Event streams one account may hold open at once
+pub const MAX_EVENT_STREAMS_PER_USER: usize = 5;
+
+/// Events one stream holds while its client isn't reading. Past this the
+/// oldest informational event is dropped; a revocation never is.
+pub const MAX_QUEUED_EVENTS: usize = 16;
+
+/// How often an open stream sends something, a keep-alive when there is nothing else
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A stream whose client hasn't taken anything for this long is treated as
+/// dead. With a reaper running every `HEARTBEAT_INTERVAL`, a dead client is
+/// gone within 30 seconds.
+pub const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How long before a session ends its stream warns about it
+pub const SESSION_EXPIRY_WARNING_MINUTES: i64 = 2;
+
+/// How long before a password ages out its owner is warned
+pub const PASSWORD_EXPIRY_WARNING_DAYS: i64 = 7;
+
+/// Something the dashboard should hear about its session without polling
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum SessionEvent {
+    SessionExpiring { expires_at: DateTime<Utc> },
+    /// The session was ended elsewhere; `reason` is the revoke reason stored with it
+    SessionRevoked { reason: String },
+    PasswordExpiring { expires_at: DateTime<Utc> },
+}
+
+impl SessionEvent {
+    /// Event name on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionEvent::SessionExpiring { .. } => "session_expiring",
+            SessionEvent::SessionRevoked { .. } => "session_revoked",
+            SessionEvent::PasswordExpiring { .. } => "password_expiring",
+        }
+    }
+
+    /// Whether the event ends the session; the others only warn
+    pub fn is_critical(&self) -> bool {
+        matches!(self, SessionEvent::SessionRevoked { .. })
+    }
+
+    /// The event as one Server-Sent Events message
+    pub fn to_sse(&self) -> String {
+        format!(
+            "event: {}\ndata: {}\n\n",
+            self.name(),
+            serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+        )
+    }
+}
+
+/// Occupancy of the per-stream event queues, for health and metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct EventQueueDepth {
+    /// Most events any one stream may hold
+    pub capacity: usize,
+    pub streams: usize,
+    /// Events waiting across all streams
+    pub queued: usize,
+    /// Events waiting on the fullest stream
+    pub deepest: usize,
+    /// Informational events dropped from full queues since startup
+    pub dropped: u64,
+    /// Streams closed since startup because their client stopped reading
+    pub reaped: u64,
+}
+
+#[derive(Debug)]
+struct QueueState {
+    events: VecDeque<SessionEvent>,
+    /// Set once the stream is reaped; nothing more is queued and `recv` ends
+    closed: bool,
+    last_read: Instant,
+}
+
+/// Events waiting for one stream's client to read them
+#[derive(Debug)]
+struct StreamQueue {
+    id: u64,
+    user_id: Uuid,
+    state: Mutex<QueueState>,
+    ready: Notify,
+}
+
+impl StreamQueue {
+    /// Queue an event; true when another had to be dropped to make room.
+    /// A stream ends at its first revocation, so later ones and repeats of
+    /// a waiting event aren't queued at all.
+    fn push(&self, event: SessionEvent) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        if state.closed
+            || state.events.contains(&event)
+            || (event.is_critical() && state.events.iter().any(SessionEvent::is_critical))
+        {
+            return false;
+        }
+        let mut dropped = false;
+        if state.events.len() >= MAX_QUEUED_EVENTS {
+            // At most one revocation is ever waiting, so there is always an
+            // informational event to give way
+            if let Some(oldest) = state.events.iter().position(|queued| !queued.is_critical()) {
+                state.events.remove(oldest);
+                dropped = true;
+            }
+        }
+        state.events.push_back(event);
+        drop(state);
+        self.ready.notify_one();
+        dropped
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().map(|state| state.events.len()).unwrap_or(0)
+    }
+
+    fn idle_since(&self, now: Instant) -> Duration {
+        self.state
+            .lock()
+            .map(|state| now.saturating_duration_since(state.last_read))
+            .unwrap_or_default()
+    }
+
+    fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            state.events.clear();
+        }
+        self.ready.notify_one();
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: u64,
+    /// Open streams by session ID
+    streams: HashMap<String, Vec<Arc<StreamQueue>>>,
+    /// Open streams by account, for the per-user cap
+    per_user: HashMap<Uuid, usize>,
+}
+
+impl Registry {
+    fn release(&mut self, user_id: Uuid) {
+        if let Some(open) = self.per_user.get_mut(&user_id) {
+            *open = open.saturating_sub(1);
+            if *open == 0 {
+                self.per_user.remove(&user_id);
+            }
+        }
+    }
+}
+
+/// In-process registry of open session event streams.
+///
+/// Revocation paths publish into it by session ID; each open stream holds a
+/// `Subscription` that unregisters itself when the client goes away. Each
+/// stream's queue is bounded by `MAX_QUEUED_EVENTS`, and streams whose
+/// client stops reading are reaped rather than left to hold a slot.
+#[derive(Debug, Default)]
+pub struct SessionEvents {
+    registry: Mutex<Registry>,
+    dropped: AtomicU64,
+    reaped: AtomicU64,
+}
+
+impl SessionEvents {
+    /// Open a stream for a session, or `None` when the account already has
+    /// `MAX_EVENT_STREAMS_PER_USER` open
+    pub fn subscribe(self: &Arc<Self>, user_id: Uuid, session_id: &str) -> Option<Subscription> {
+        let mut registry = self.registry.lock().ok()?;
+        let open = registry.per_user.entry(user_id).or_default();
+        if *open >= MAX_EVENT_STREAMS_PER_USER {
+            return None;
+        }
+        *open += 1;
+
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let queue = Arc::new(StreamQueue {
+            id,
+            user_id,
+            state: Mutex::new(QueueState { events: VecDeque::new(), closed: false, last_read: Instant::now() }),
+            ready: Notify::new(),
+        });
+        registry.streams.entry(session_id.to_string()).or_default().push(queue.clone());
+
+        Some(Subscription {
+            events: self.clone(),
+            user_id,
+            session_id: session_id.to_string(),
+            queue,
+        })
+    }
+
+    /// Deliver an event to every stream open on a session
+    pub fn publish(&self, session_id: &str, event: SessionEvent) {
+        let Ok(registry) = self.registry.lock() else {
+            return;
+        };
+        for queue in registry.streams.get(session_id).into_iter().flatten() {
+            if queue.push(event.clone()) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Tell the streams of just-revoked sessions why they ended
+    pub fn publish_revoked(&self, session_ids: &[String], reason: &str) {
+        for session_id in session_ids {
+            self.publish(session_id, SessionEvent::SessionRevoked { reason: reason.to_string() });
+        }
+    }
+
+    /// Streams currently open across all accounts
+    pub fn open_streams(&self) -> usize {
+        self.registry
+            .lock()
+            .map(|registry| registry.streams.values().map(Vec::len).sum())
+            .unwrap_or(0)
+    }
+
+    /// Current queue occupancy, and what the caps have cost since startup
+    pub fn queue_depth(&self) -> EventQueueDepth {
+        let depths: Vec<usize> = self
+            .registry
+            .lock()
+            .map(|registry| registry.streams.values().flatten().map(|queue| queue.len()).collect())
+            .unwrap_or_default();
+        EventQueueDepth {
+            capacity: MAX_QUEUED_EVENTS,
+            streams: depths.len(),
+            queued: depths.iter().sum(),
+            deepest: depths.iter().copied().max().unwrap_or(0),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            reaped: self.reaped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Close the streams not read from for `STREAM_IDLE_TIMEOUT`, freeing
+    /// their queues and per-user slots. A client that is gone, or connected
+    /// but no longer reading, stops taking even the heartbeats, so this is
+    /// what ends its stream. Returns how many were closed.
+    pub fn reap(&self, now: Instant) -> usize {
+        let Ok(mut registry) = self.registry.lock() else {
+            return 0;
+        };
+        let mut idle = Vec::new();
+        registry.streams.retain(|_, queues| {
+            queues.retain(|queue| {
+                let alive = queue.idle_since(now) < STREAM_IDLE_TIMEOUT;
+                if !alive {
+                    idle.push(queue.clone());
+                }
+                alive
+            });
+            !queues.is_empty()
+        });
+        for queue in &idle {
+            queue.close();
+            registry.release(queue.user_id);
+        }
+        self.reaped.fetch_add(idle.len() as u64, Ordering::Relaxed);
+        idle.len()
+    }
+
+    fn unsubscribe(&self, subscription: &Subscription) {
+        let Ok(mut registry) = self.registry.lock() else {
+            return;
+        };
+        // A reaped stream has already given its slot back
+        let Some(queues) = registry.streams.get_mut(&subscription.session_id) else {
+            return;
+        };
+        let before = queues.len();
+        queues.retain(|queue| queue.id != subscription.queue.id);
+        let removed = queues.len() < before;
+        if queues.is_empty() {
+            registry.streams.remove(&subscription.session_id);
+        }
+        if removed {
+            registry.release(subscription.user_id);
+        }
+    }
+}
+
+/// One open event stream. Dropping it unregisters the stream.
+pub struct Subscription {
+    events: Arc<SessionEvents>,
+    pub user_id: Uuid,
+    pub session_id: String,
+    queue: Arc<StreamQueue>,
+}
+
+impl Subscription {
+    /// The next event published to this session; `None` once the stream was reaped
+    pub async fn recv(&mut self) -> Option<SessionEvent> {
+        loop {
+            {
+                let mut state = self.queue.state.lock().ok()?;
+                if let Some(event) = state.events.pop_front() {
+                    return Some(event);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.queue.ready.notified().await;
+        }
+    }
+
+    /// Record that the client took what was sent, so the stream isn't reaped
+    pub fn mark_read(&self) {
+        if let Ok(mut state) = self.queue.state.lock() {
+            state.last_read = Instant::now();
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.events.unsubscribe(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_publish_reaches_only_the_session() {
+        let events = Arc::new(SessionEvents::default());
+        let user_id = Uuid::new_v4();
+        let mut first = events.subscribe(user_id, "session-a").unwrap();
+        let second = events.subscribe(user_id, "session-b").unwrap();
+
+        events.publish_revoked(&["session-a".to_string()], "logout");
+
+        assert_eq!(first.recv().await, Some(SessionEvent::SessionRevoked { reason: "logout".to_string() }));
+        assert_eq!(second.queue.len(), 0);
+    }
+
+    #[test]
+    fn test_streams_are_capped_per_user_and_freed_on_drop() {
+        let events = Arc::new(SessionEvents::default());
+        let user_id = Uuid::new_v4();
+        let open: Vec<_> = (0..MAX_EVENT_STREAMS_PER_USER)
+            .map(|_| events.subscribe(user_id, "session-a").unwrap())
+            .collect();
+
+        assert!(events.subscribe(user_id, "session-b").is_none());
+        assert!(events.subscribe(Uuid::new_v4(), "session-c").is_some());
+
+        drop(open);
+        assert_eq!(events.open_streams(), 0);
+        assert!(events.subscribe(user_id, "session-b").is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_queue_of_a_client_not_reading_is_bounded_and_keeps_the_revocation() {
+        let events = Arc::new(SessionEvents::default());
+        let mut stuck = events.subscribe(Uuid::new_v4(), "session-a").unwrap();
+        let start = Utc::now();
+        let warning = |n: i64| SessionEvent::PasswordExpiring { expires_at: start + chrono::Duration::minutes(n) };
+
+        for n in 0..10 {
+            events.publish("session-a", warning(n));
+        }
+        events.publish_revoked(&["session-a".to_string()], "logout");
+        events.publish_revoked(&["session-a".to_string()], "logout_all");
+        for n in 10..100 {
+            events.publish("session-a", warning(n));
+        }
+
+        let depth = events.queue_depth();
+        assert_eq!(depth.queued, MAX_QUEUED_EVENTS);
+        assert_eq!(depth.deepest, MAX_QUEUED_EVENTS);
+        assert_eq!(depth.dropped, 100 + 1 - MAX_QUEUED_EVENTS as u64);
+
+        // The oldest warnings made room; the revocation stayed, once
+        let mut received = Vec::new();
+        for _ in 0..MAX_QUEUED_EVENTS {
+            received.push(stuck.recv().await.unwrap());
+        }
+        assert_eq!(received[0], SessionEvent::SessionRevoked { reason: "logout".to_string() });
+        assert_eq!(received[1], warning(100 - MAX_QUEUED_EVENTS as i64 + 1));
+        assert_eq!(received.last(), Some(&warning(99)));
+        assert_eq!(received.iter().filter(|event| event.is_critical()).count(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_streams_not_read_from_are_reaped() {
+        let events = Arc::new(SessionEvents::default());
+        let user_id = Uuid::new_v4();
+        let mut idle = events.subscribe(user_id, "session-a").unwrap();
+        let active = events.subscribe(user_id, "session-b").unwrap();
+        events.publish_revoked(&["session-a".to_string()], "logout");
+
+        assert_eq!(events.reap(Instant::now()), 0);
+        let later = Instant::now() + STREAM_IDLE_TIMEOUT;
+        active.queue.state.lock().unwrap().last_read = later;
+        assert_eq!(events.reap(later), 1);
+
+        // The queue is freed and the stream ends
+        assert_eq!(events.open_streams(), 1);
+        assert_eq!(events.queue_depth().reaped, 1);
+        assert_eq!(idle.recv().await, None);
+
+        // Dropping the reaped stream gives back nothing more than it held
+        let others: Vec<_> = (1..MAX_EVENT_STREAMS_PER_USER)
+            .map(|_| events.subscribe(user_id, "session-c").unwrap())
+            .collect();
+        drop(idle);
+        assert!(events.subscribe(user_id, "session-d").is_none());
+        drop(others);
+        drop(active);
+        assert_eq!(events.open_streams(), 0);
+    }
+
+    #[test]
+    fn test_sse_framing() {
+        let event = SessionEvent::SessionRevoked { reason: "terminated_by_admin".to_string() };
+        assert_eq!(event.to_sse(), "event: session_revoked\ndata: {\"reason\":\"terminated_by_admin\"}\n\n");
+    }
+}
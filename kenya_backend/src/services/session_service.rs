@@ -0,0 +1,386 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::context::RequestContext;
+use crate::services::session_events::SessionEvents;
+use crate::utils::clock::Clock;
+
+/// How long a step-up re-authentication unlocks sensitive admin actions
+pub const STEP_UP_VALIDITY_MINUTES: i64 = 5;
+
+/// A live session as shown to administrators
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SessionRecord {
+    pub session_id: String,
+    pub ip_address: String,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_activity_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Lockdown epoch the session was created in
+    pub auth_epoch: i64,
+    /// Client app the session signed in through
+    pub client_id: String,
+    /// The account had signed in successfully from this user agent before
+    pub device_known: bool,
+}
+
+/// Session and token counts for capacity planning
+#[derive(Debug, Clone, Copy, Serialize, sqlx::FromRow)]
+pub struct SessionGauges {
+    /// Sessions neither revoked nor expired
+    pub active_sessions: i64,
+    /// One token is issued per session, so this is sessions created in the last hour
+    pub tokens_issued_last_hour: i64,
+    /// Revoked tokens that would otherwise still be accepted
+    pub blacklist_size: i64,
+}
+
+/// Session ID and owner of a session that was just revoked
+type RevokedSession = (String, Uuid);
+
+/// A token revoked out of band by its ID
+#[derive(Debug, Clone, Serialize)]
+pub struct RevokedToken {
+    pub jti: String,
+    pub session_id: String,
+    pub user_id: Uuid,
+    /// False when the token had already been revoked
+    pub newly_revoked: bool,
+}
+
+const SESSION_COLUMNS: &str = r#"
+    s.id AS session_id, s.ip_address, s.user_agent, s.created_at, s.last_activity_at, s.expires_at, s.auth_epoch, s.client_id,
+    EXISTS (
+        SELECT 1 FROM login_attempts a
+        WHERE a.user_id = s.user_id AND a.success = TRUE
+          AND a.user_agent IS s.user_agent AND a.timestamp < s.created_at
+    ) AS device_known
+"#;
+
+/// Session store. A token is only honoured while the session it names is
+/// live here, not revoked and not past its expiry, and still holds that
+/// token's ID. Revoking a session needs nothing else; `revoked_tokens` only
+/// lists tokens revoked one by one.
+pub struct SessionService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    /// Open event streams, told when their session is revoked
+    events: Arc<SessionEvents>,
+}
+
+impl SessionService {
+    /// Judge liveness and stamp activity by the time `clock` reports
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            db_pool,
+            clock,
+            events: Arc::new(SessionEvents::default()),
+        }
+    }
+
+    /// Registry of open session event streams
+    pub fn events(&self) -> &Arc<SessionEvents> {
+        &self.events
+    }
+
+    /// Record a newly issued session and the ID of its token, which starts a
+    /// new token family, and of the key that signed it, in lockdown epoch
+    /// `auth_epoch` for the client app `client_id`
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        session_id: &str,
+        jti: &str,
+        token_kid: &str,
+        expires_at: DateTime<Utc>,
+        auth_epoch: i64,
+        client_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        let now = self.clock.now();
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, jti, token_family, token_kid, ip_address, user_agent, created_at, last_activity_at, expires_at, auth_epoch, client_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .bind(jti)
+        .bind(Uuid::new_v4().to_string())
+        .bind(token_kid)
+        .bind(&ctx.ip_address)
+        .bind(&ctx.user_agent)
+        .bind(now)
+        .bind(now)
+        .bind(expires_at)
+        .bind(auth_epoch)
+        .bind(client_id)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The session, if it belongs to `user_id` and is still live
+    pub async fn live(&self, user_id: Uuid, session_id: &str) -> Result<Option<SessionRecord>, sqlx::Error> {
+        sqlx::query_as::<_, SessionRecord>(&format!(
+            "SELECT {} FROM sessions s
+             WHERE s.id = ? AND s.user_id = ? AND s.revoked_at IS NULL AND s.expires_at > ?",
+            SESSION_COLUMNS
+        ))
+        .bind(session_id)
+        .bind(user_id)
+        .bind(self.clock.now())
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// The session, if it belongs to `user_id`, is still live and its current
+    /// token is the one with ID `jti`
+    pub async fn live_for_token(&self, user_id: Uuid, session_id: &str, jti: &str) -> Result<Option<SessionRecord>, sqlx::Error> {
+        sqlx::query_as::<_, SessionRecord>(&format!(
+            "SELECT {} FROM sessions s
+             WHERE s.id = ? AND s.user_id = ? AND s.jti = ? AND s.revoked_at IS NULL AND s.expires_at > ?",
+            SESSION_COLUMNS
+        ))
+        .bind(session_id)
+        .bind(user_id)
+        .bind(jti)
+        .bind(self.clock.now())
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Make `jti`, signed with key `token_kid`, the live session's current
+    /// token, retiring the one it held. Returns false when the session is no
+    /// longer live.
+    pub async fn reissue(&self, session_id: &str, jti: &str, token_kid: &str) -> Result<bool, sqlx::Error> {
+        Ok(sqlx::query("UPDATE sessions SET jti = ?, token_kid = ? WHERE id = ? AND revoked_at IS NULL AND expires_at > ?")
+            .bind(jti)
+            .bind(token_kid)
+            .bind(session_id)
+            .bind(self.clock.now())
+            .execute(&self.db_pool)
+            .await?
+            .rows_affected()
+            > 0)
+    }
+
+    /// Every live session of an account, newest first
+    pub async fn live_for_user(&self, user_id: Uuid) -> Result<Vec<SessionRecord>, sqlx::Error> {
+        sqlx::query_as::<_, SessionRecord>(&format!(
+            "SELECT {} FROM sessions s
+             WHERE s.user_id = ? AND s.revoked_at IS NULL AND s.expires_at > ?
+             ORDER BY s.created_at DESC",
+            SESSION_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(self.clock.now())
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// Owner of a live session
+    pub async fn owner_of(&self, session_id: &str) -> Result<Option<Uuid>, sqlx::Error> {
+        sqlx::query_scalar("SELECT user_id FROM sessions WHERE id = ? AND revoked_at IS NULL AND expires_at > ?")
+            .bind(session_id)
+            .bind(self.clock.now())
+            .fetch_optional(&self.db_pool)
+            .await
+    }
+
+    /// Note activity on a session with a token signed by key `token_kid`,
+    /// which fills in the key of sessions from before key IDs were recorded
+    pub async fn touch(&self, session_id: &str, token_kid: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE sessions SET last_activity_at = ?, token_kid = ? WHERE id = ?")
+            .bind(self.clock.now())
+            .bind(token_kid)
+            .bind(session_id)
+            .execute(&self.db_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Current session gauges. Both counts are range scans over an indexed
+    /// timestamp, bounded by the sessions still live or issued within the hour.
+    pub async fn gauges(&self) -> Result<SessionGauges, sqlx::Error> {
+        let now = self.clock.now();
+        sqlx::query_as::<_, SessionGauges>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM sessions WHERE expires_at > ?1 AND revoked_at IS NULL) AS active_sessions,
+                (SELECT COUNT(*) FROM sessions WHERE created_at > ?2) AS tokens_issued_last_hour,
+                (SELECT COUNT(*) FROM sessions WHERE expires_at > ?1 AND revoked_at IS NOT NULL) AS blacklist_size
+            "#
+        )
+        .bind(now)
+        .bind(now - chrono::Duration::hours(1))
+        .fetch_one(&self.db_pool)
+        .await
+    }
+
+    /// Live sessions by the ID of the key that signed their current token;
+    /// `None` for sessions not used since key IDs were first recorded
+    pub async fn live_by_token_kid(&self) -> Result<Vec<(Option<String>, i64)>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT token_kid, COUNT(*) FROM sessions
+             WHERE revoked_at IS NULL AND expires_at > ?
+             GROUP BY token_kid",
+        )
+        .bind(self.clock.now())
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// Whether the token with this ID was revoked on its own
+    pub async fn is_token_revoked(&self, jti: &str) -> Result<bool, sqlx::Error> {
+        let revoked: Option<String> = sqlx::query_scalar("SELECT jti FROM revoked_tokens WHERE jti = ?")
+            .bind(jti)
+            .fetch_optional(&self.db_pool)
+            .await?;
+
+        Ok(revoked.is_some())
+    }
+
+    /// Revoke one live session.
+    ///
+    /// `revoked_by` is the administrator responsible, if any. Returns the
+    /// session ID when it was live.
+    pub async fn revoke(
+        &self,
+        session_id: &str,
+        revoked_by: Option<Uuid>,
+        reason: &str,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let now = self.clock.now();
+        let revoked = sqlx::query_as::<_, RevokedSession>(
+            r#"
+            UPDATE sessions SET revoked_at = ?, revoked_by = ?, revoke_reason = ?
+            WHERE id = ? AND revoked_at IS NULL AND expires_at > ?
+            RETURNING id, user_id
+            "#
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(reason)
+        .bind(session_id)
+        .bind(now)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let revoked: Vec<String> = revoked.into_iter().map(|(session_id, _)| session_id).collect();
+        self.events.publish_revoked(&revoked, reason);
+        Ok(revoked.into_iter().next())
+    }
+
+    /// Revoke every live session of an account, returning their IDs
+    pub async fn revoke_all_for_user(
+        &self,
+        user_id: Uuid,
+        revoked_by: Option<Uuid>,
+        reason: &str,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let revoked = self.revoke_for_user_in(&mut tx, user_id, None, revoked_by, reason).await?;
+        tx.commit().await?;
+
+        self.announce_revoked(&revoked, reason);
+        Ok(revoked)
+    }
+
+    /// Revoke an account's live sessions other than `keep` as part of `tx`.
+    /// Open event streams aren't told; call `announce_revoked` once `tx` commits.
+    pub async fn revoke_for_user_in(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: Uuid,
+        keep: Option<&str>,
+        revoked_by: Option<Uuid>,
+        reason: &str,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let now = self.clock.now();
+        let revoked = sqlx::query_as::<_, RevokedSession>(
+            r#"
+            UPDATE sessions SET revoked_at = ?, revoked_by = ?, revoke_reason = ?
+            WHERE user_id = ? AND revoked_at IS NULL AND expires_at > ? AND id IS NOT ?
+            RETURNING id, user_id
+            "#
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(reason)
+        .bind(user_id)
+        .bind(now)
+        .bind(keep)
+        .fetch_all(&mut **tx)
+        .await?;
+
+        Ok(revoked.into_iter().map(|(session_id, _)| session_id).collect())
+    }
+
+    /// Tell open event streams their sessions were revoked
+    pub fn announce_revoked(&self, session_ids: &[String], reason: &str) {
+        self.events.publish_revoked(session_ids, reason);
+    }
+
+    /// Refuse the token with ID `jti` while leaving its session live, for a
+    /// token reported leaked. Only the current token of a session can be
+    /// revoked; earlier ones already stopped working when it was re-issued.
+    pub async fn revoke_token(&self, jti: &str, revoked_by: Option<Uuid>) -> Result<Option<RevokedToken>, sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let session: Option<(String, Uuid)> = sqlx::query_as("SELECT id, user_id FROM sessions WHERE jti = ?")
+            .bind(jti)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some((session_id, user_id)) = session else {
+            return Ok(None);
+        };
+        let newly_revoked = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO revoked_tokens (jti, session_id, user_id, revoked_at, revoked_by)
+            VALUES (?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(jti)
+        .bind(&session_id)
+        .bind(user_id)
+        .bind(self.clock.now())
+        .bind(revoked_by)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        tx.commit().await?;
+
+        Ok(Some(RevokedToken { jti: jti.to_string(), session_id, user_id, newly_revoked }))
+    }
+
+    /// Record that the session's owner just re-entered their credentials
+    pub async fn record_step_up(&self, session_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE sessions SET step_up_at = ? WHERE id = ?")
+            .bind(self.clock.now())
+            .bind(session_id)
+            .execute(&self.db_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Whether the session re-authenticated within `STEP_UP_VALIDITY_MINUTES`
+    pub async fn has_recent_step_up(&self, session_id: &str) -> Result<bool, sqlx::Error> {
+        let since = self.clock.now() - chrono::Duration::minutes(STEP_UP_VALIDITY_MINUTES);
+        let step_up_at: Option<Option<DateTime<Utc>>> = sqlx::query_scalar("SELECT step_up_at FROM sessions WHERE id = ?")
+            .bind(session_id)
+            .fetch_optional(&self.db_pool)
+            .await?;
+
+        Ok(step_up_at.flatten().is_some_and(|at| at > since))
+    }
+}
@@ -0,0 +1,468 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A shared state operation that couldn't be carried out
+#[derive(Debug, thiserror::Error)]
+pub enum SharedStateError {
+    #[error("shared state backend unavailable: {0}")]
+    Unavailable(String),
+    #[cfg_attr(feature = "redis", allow(dead_code))]
+    #[error("REDIS_URL is set, but this build has no Redis support; rebuild with `--features redis`")]
+    RedisNotBuilt,
+}
+
+/// Short-lived counters and markers that every instance behind the load
+/// balancer must agree on: login failure counters, blocks and per-IP token
+/// verification failures. Every entry expires on its own, so a backend never
+/// needs cleaning up.
+///
+/// The in-process backend is the default and is enough for one instance;
+/// with the `redis` cargo feature, `RedisBackend` shares the entries across
+/// instances.
+#[async_trait]
+pub trait SharedStateBackend: Send + Sync {
+    /// Backend name for logs and health details
+    fn name(&self) -> &'static str;
+
+    /// Add one to the counter at `key`, creating it to expire `ttl` from now
+    /// when it doesn't exist. Returns the new count.
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, SharedStateError>;
+
+    /// The counter at `key`; 0 when it doesn't exist
+    async fn count(&self, key: &str) -> Result<u64, SharedStateError>;
+
+    /// Set `key` for `ttl` unless it is already set. Returns how long it has left.
+    async fn hold(&self, key: &str, ttl: Duration) -> Result<Duration, SharedStateError>;
+
+    /// How long `key` has left, or `None` when it isn't set
+    async fn remaining(&self, key: &str) -> Result<Option<Duration>, SharedStateError>;
+
+    async fn remove(&self, key: &str) -> Result<(), SharedStateError>;
+
+    /// Live entries whose key starts with `prefix`
+    async fn count_keys(&self, prefix: &str) -> Result<usize, SharedStateError>;
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: u64,
+    expires_at: Instant,
+}
+
+/// Entries in this process's memory; each instance has its own
+#[derive(Debug, Default)]
+pub struct InProcessBackend {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl InProcessBackend {
+    fn entries(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Entry>>, SharedStateError> {
+        self.entries.lock().map_err(|_| SharedStateError::Unavailable("in-process store lock poisoned".to_string()))
+    }
+}
+
+#[async_trait]
+impl SharedStateBackend for InProcessBackend {
+    fn name(&self) -> &'static str {
+        "in_process"
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, SharedStateError> {
+        let mut entries = self.entries()?;
+        let now = Instant::now();
+
+        // Forget expired entries so the map doesn't grow without bound
+        entries.retain(|_, entry| entry.expires_at > now);
+
+        let entry = entries.entry(key.to_string()).or_insert(Entry { value: 0, expires_at: now + ttl });
+        entry.value += 1;
+        let value = entry.value;
+        if ttl.is_zero() {
+            entries.remove(key);
+        }
+        Ok(value)
+    }
+
+    async fn count(&self, key: &str) -> Result<u64, SharedStateError> {
+        let now = Instant::now();
+        Ok(self.entries()?.get(key).filter(|entry| entry.expires_at > now).map(|entry| entry.value).unwrap_or(0))
+    }
+
+    async fn hold(&self, key: &str, ttl: Duration) -> Result<Duration, SharedStateError> {
+        let mut entries = self.entries()?;
+        let now = Instant::now();
+
+        entries.retain(|_, entry| entry.expires_at > now);
+
+        let entry = entries.entry(key.to_string()).or_insert(Entry { value: 1, expires_at: now + ttl });
+        Ok(entry.expires_at - now)
+    }
+
+    async fn remaining(&self, key: &str) -> Result<Option<Duration>, SharedStateError> {
+        let now = Instant::now();
+        Ok(self.entries()?.get(key).filter(|entry| entry.expires_at > now).map(|entry| entry.expires_at - now))
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), SharedStateError> {
+        self.entries()?.remove(key);
+        Ok(())
+    }
+
+    async fn count_keys(&self, prefix: &str) -> Result<usize, SharedStateError> {
+        let now = Instant::now();
+        Ok(self.entries()?.iter().filter(|(key, entry)| key.starts_with(prefix) && entry.expires_at > now).count())
+    }
+}
+
+/// A shared backend that falls back to this instance's memory while it is
+/// unreachable, so an outage loosens limits to per-instance ones instead of
+/// lifting them. Entries counted during the outage stay local.
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+pub struct FailoverBackend {
+    primary: Arc<dyn SharedStateBackend>,
+    fallback: InProcessBackend,
+    failed_over: AtomicBool,
+}
+
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+impl FailoverBackend {
+    pub fn new(primary: Arc<dyn SharedStateBackend>) -> Self {
+        Self { primary, fallback: InProcessBackend::default(), failed_over: AtomicBool::new(false) }
+    }
+
+    /// Note the primary's answer, logging only when it goes away or comes back
+    fn observe<T>(&self, result: &Result<T, SharedStateError>) {
+        match result {
+            Ok(_) => {
+                if self.failed_over.swap(false, Ordering::Relaxed) {
+                    log::warn!("Shared state backend {} is reachable again", self.primary.name());
+                }
+            }
+            Err(e) => {
+                if !self.failed_over.swap(true, Ordering::Relaxed) {
+                    log::error!("{}; counting in this instance's memory until it is back", e);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SharedStateBackend for FailoverBackend {
+    fn name(&self) -> &'static str {
+        self.primary.name()
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, SharedStateError> {
+        let result = self.primary.increment(key, ttl).await;
+        self.observe(&result);
+        match result {
+            Ok(value) => Ok(value),
+            Err(_) => self.fallback.increment(key, ttl).await,
+        }
+    }
+
+    async fn count(&self, key: &str) -> Result<u64, SharedStateError> {
+        let result = self.primary.count(key).await;
+        self.observe(&result);
+        match result {
+            Ok(value) => Ok(value),
+            Err(_) => self.fallback.count(key).await,
+        }
+    }
+
+    async fn hold(&self, key: &str, ttl: Duration) -> Result<Duration, SharedStateError> {
+        let result = self.primary.hold(key, ttl).await;
+        self.observe(&result);
+        match result {
+            Ok(left) => Ok(left),
+            Err(_) => self.fallback.hold(key, ttl).await,
+        }
+    }
+
+    async fn remaining(&self, key: &str) -> Result<Option<Duration>, SharedStateError> {
+        let result = self.primary.remaining(key).await;
+        self.observe(&result);
+        match result {
+            Ok(left) => Ok(left),
+            Err(_) => self.fallback.remaining(key).await,
+        }
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), SharedStateError> {
+        // Cleared in both, so failures counted during one outage don't resurface in the next
+        let result = self.primary.remove(key).await;
+        self.observe(&result);
+        self.fallback.remove(key).await
+    }
+
+    async fn count_keys(&self, prefix: &str) -> Result<usize, SharedStateError> {
+        let result = self.primary.count_keys(prefix).await;
+        self.observe(&result);
+        match result {
+            Ok(count) => Ok(count),
+            Err(_) => self.fallback.count_keys(prefix).await,
+        }
+    }
+}
+
+/// The backend for `redis_url`: Redis behind a failover to this process's
+/// memory when set, this process's memory alone when not
+pub async fn connect(redis_url: Option<&str>) -> Result<Arc<dyn SharedStateBackend>, SharedStateError> {
+    match redis_url {
+        None => Ok(Arc::new(InProcessBackend::default())),
+        #[cfg(feature = "redis")]
+        Some(url) => Ok(Arc::new(FailoverBackend::new(Arc::new(RedisBackend::connect(url).await?)))),
+        #[cfg(not(feature = "redis"))]
+        Some(_) => Err(SharedStateError::RedisNotBuilt),
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_backend::RedisBackend;
+
+#[cfg(feature = "redis")]
+mod redis_backend {
+    use super::*;
+    use redis::aio::ConnectionManager;
+
+    /// Every key this backend writes starts with this
+    const KEY_NAMESPACE: &str = "kenya_fsfvi:";
+
+    const INCREMENT_SCRIPT: &str = r#"
+        local value = redis.call('INCR', KEYS[1])
+        if value == 1 then redis.call('PEXPIRE', KEYS[1], ARGV[1]) end
+        return value
+    "#;
+
+    const HOLD_SCRIPT: &str = r#"
+        redis.call('SET', KEYS[1], 1, 'NX', 'PX', ARGV[1])
+        return redis.call('PTTL', KEYS[1])
+    "#;
+
+    /// Entries in Redis, shared by every instance pointed at it. The
+    /// connection reconnects by itself after Redis restarts or fails over.
+    pub struct RedisBackend {
+        connection: ConnectionManager,
+        increment: redis::Script,
+        hold: redis::Script,
+    }
+
+    impl RedisBackend {
+        pub async fn connect(url: &str) -> Result<Self, SharedStateError> {
+            let client = redis::Client::open(url).map_err(unavailable)?;
+            let connection = ConnectionManager::new(client).await.map_err(unavailable)?;
+            Ok(Self { connection, increment: redis::Script::new(INCREMENT_SCRIPT), hold: redis::Script::new(HOLD_SCRIPT) })
+        }
+    }
+
+    fn unavailable(e: redis::RedisError) -> SharedStateError {
+        SharedStateError::Unavailable(format!("redis: {}", e))
+    }
+
+    fn namespaced(key: &str) -> String {
+        format!("{}{}", KEY_NAMESPACE, key)
+    }
+
+    fn millis(ttl: Duration) -> u64 {
+        u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    #[async_trait]
+    impl SharedStateBackend for RedisBackend {
+        fn name(&self) -> &'static str {
+            "redis"
+        }
+
+        async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, SharedStateError> {
+            // PEXPIRE 0 deletes the key, matching an entry that is already over
+            self.increment
+                .key(namespaced(key))
+                .arg(millis(ttl))
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)
+        }
+
+        async fn count(&self, key: &str) -> Result<u64, SharedStateError> {
+            let value: Option<u64> = redis::cmd("GET")
+                .arg(namespaced(key))
+                .query_async(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)?;
+            Ok(value.unwrap_or(0))
+        }
+
+        async fn hold(&self, key: &str, ttl: Duration) -> Result<Duration, SharedStateError> {
+            // SET refuses a zero expiry, so the shortest hold is a millisecond
+            let left: i64 = self
+                .hold
+                .key(namespaced(key))
+                .arg(millis(ttl).max(1))
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)?;
+            Ok(Duration::from_millis(left.max(0) as u64))
+        }
+
+        async fn remaining(&self, key: &str) -> Result<Option<Duration>, SharedStateError> {
+            // -2 when the key doesn't exist, -1 when it never expires (which this backend never writes)
+            let left: i64 = redis::cmd("PTTL")
+                .arg(namespaced(key))
+                .query_async(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)?;
+            Ok((left > 0).then(|| Duration::from_millis(left as u64)))
+        }
+
+        async fn remove(&self, key: &str) -> Result<(), SharedStateError> {
+            redis::cmd("DEL")
+                .arg(namespaced(key))
+                .query_async(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)
+        }
+
+        async fn count_keys(&self, prefix: &str) -> Result<usize, SharedStateError> {
+            let pattern = format!("{}*", namespaced(prefix));
+            let mut connection = self.connection.clone();
+            let mut cursor = 0u64;
+            let mut count = 0;
+            loop {
+                let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                    .cursor_arg(cursor)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .arg("COUNT")
+                    .arg(1000)
+                    .query_async(&mut connection)
+                    .await
+                    .map_err(unavailable)?;
+                count += keys.len();
+                if next == 0 {
+                    return Ok(count);
+                }
+                cursor = next;
+            }
+        }
+    }
+}
+
+/// One conformance suite for every backend, so they can be swapped without
+/// changing what the throttle and verify monitor decide
+#[cfg(test)]
+pub mod conformance {
+    use super::*;
+    use uuid::Uuid;
+
+    pub async fn run(backend: &dyn SharedStateBackend) {
+        // Keys are unique per run, so a shared Redis can be reused between runs
+        let run = Uuid::new_v4().simple().to_string();
+        let key = |name: &str| format!("conformance:{}:{}", run, name);
+        let minute = Duration::from_secs(60);
+
+        assert_eq!(backend.count(&key("counter")).await.unwrap(), 0);
+        assert_eq!(backend.increment(&key("counter"), minute).await.unwrap(), 1);
+        assert_eq!(backend.increment(&key("counter"), minute).await.unwrap(), 2);
+        assert_eq!(backend.count(&key("counter")).await.unwrap(), 2);
+        let left = backend.remaining(&key("counter")).await.unwrap().unwrap();
+        assert!(left > Duration::from_secs(55) && left <= minute);
+
+        // A zero window is over at once, so the count never builds up
+        assert_eq!(backend.increment(&key("instant"), Duration::ZERO).await.unwrap(), 1);
+        assert_eq!(backend.increment(&key("instant"), Duration::ZERO).await.unwrap(), 1);
+        assert_eq!(backend.count(&key("instant")).await.unwrap(), 0);
+
+        // A hold keeps its first expiry however often it is asked for again
+        let left = backend.hold(&key("hold"), Duration::from_secs(120)).await.unwrap();
+        assert!(left > Duration::from_secs(115) && left <= Duration::from_secs(120));
+        let again = backend.hold(&key("hold"), Duration::from_secs(600)).await.unwrap();
+        assert!(again <= left);
+        assert!(backend.remaining(&key("hold")).await.unwrap().is_some());
+        assert_eq!(backend.remaining(&key("missing")).await.unwrap(), None);
+
+        // Entries expire on their own
+        backend.hold(&key("brief"), Duration::from_millis(50)).await.unwrap();
+        backend.increment(&key("brief_counter"), Duration::from_millis(50)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(backend.remaining(&key("brief")).await.unwrap(), None);
+        assert_eq!(backend.count(&key("brief_counter")).await.unwrap(), 0);
+
+        assert_eq!(backend.count_keys(&format!("conformance:{}:", run)).await.unwrap(), 2);
+        assert_eq!(backend.count_keys(&key("hol")).await.unwrap(), 1);
+
+        backend.remove(&key("counter")).await.unwrap();
+        backend.remove(&key("hold")).await.unwrap();
+        backend.remove(&key("missing")).await.unwrap();
+        assert_eq!(backend.count(&key("counter")).await.unwrap(), 0);
+        assert_eq!(backend.remaining(&key("hold")).await.unwrap(), None);
+        assert_eq!(backend.count_keys(&format!("conformance:{}:", run)).await.unwrap(), 0);
+    }
+
+    /// The Redis backend under test, from `TEST_REDIS_URL` (e.g. a
+    /// `docker run -p 6379:6379 redis:7` container), or `None` to skip
+    #[cfg(feature = "redis")]
+    pub async fn test_redis() -> Option<RedisBackend> {
+        let Ok(url) = std::env::var("TEST_REDIS_URL") else {
+            eprintln!("TEST_REDIS_URL not set; skipping the Redis backend");
+            return None;
+        };
+        Some(RedisBackend::connect(&url).await.expect("TEST_REDIS_URL is set but Redis is unreachable"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backend that is down
+    struct Unreachable;
+
+    #[async_trait]
+    impl SharedStateBackend for Unreachable {
+        fn name(&self) -> &'static str {
+            "unreachable"
+        }
+        async fn increment(&self, _: &str, _: Duration) -> Result<u64, SharedStateError> {
+            Err(SharedStateError::Unavailable("connection refused".to_string()))
+        }
+        async fn count(&self, _: &str) -> Result<u64, SharedStateError> {
+            Err(SharedStateError::Unavailable("connection refused".to_string()))
+        }
+        async fn hold(&self, _: &str, _: Duration) -> Result<Duration, SharedStateError> {
+            Err(SharedStateError::Unavailable("connection refused".to_string()))
+        }
+        async fn remaining(&self, _: &str) -> Result<Option<Duration>, SharedStateError> {
+            Err(SharedStateError::Unavailable("connection refused".to_string()))
+        }
+        async fn remove(&self, _: &str) -> Result<(), SharedStateError> {
+            Err(SharedStateError::Unavailable("connection refused".to_string()))
+        }
+        async fn count_keys(&self, _: &str) -> Result<usize, SharedStateError> {
+            Err(SharedStateError::Unavailable("connection refused".to_string()))
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_in_process_conformance() {
+        conformance::run(&InProcessBackend::default()).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[actix_web::test]
+    async fn test_redis_conformance() {
+        if let Some(redis) = conformance::test_redis().await {
+            conformance::run(&redis).await;
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_failover_counts_locally_while_the_primary_is_down() {
+        let backend = FailoverBackend::new(Arc::new(Unreachable));
+        assert_eq!(backend.name(), "unreachable");
+        conformance::run(&backend).await;
+    }
+}
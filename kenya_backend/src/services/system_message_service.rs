@@ -0,0 +1,286 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::models::auth::Severity;
+use crate::utils::clock::Clock;
+
+/// Most messages the login screen is sent at once, most severe first
+pub const MAX_ACTIVE_MESSAGES: usize = 5;
+
+/// How long the active messages are served from memory, and may be cached by clients
+pub const SYSTEM_MESSAGES_CACHE_SECONDS: i64 = 60;
+
+/// A notice for the login screen
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SystemMessage {
+    pub id: Uuid,
+    /// Plain text, or the markdown subset `markup_problem` allows
+    pub body: String,
+    pub severity: Severity,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub created_by: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SystemMessage {
+    fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.starts_at <= now && now < self.ends_at
+    }
+}
+
+/// A message's content and window, as an administrator sets them
+#[derive(Debug, Clone)]
+pub struct MessageDraft {
+    pub body: String,
+    pub severity: Severity,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+/// Why `body` can't be shown, if it can't. Bodies are plain text with a
+/// little markdown: `**bold**`, `*italic*`, `` `code` `` and
+/// `[text](https://...)` links. HTML, images, control characters other than
+/// newlines and links anywhere but `https://` are refused, so every frontend
+/// can render a message without sanitizing it again.
+pub fn markup_problem(body: &str) -> Option<&'static str> {
+    if body.contains(['<', '>']) {
+        return Some("HTML is not allowed");
+    }
+    if body.chars().any(|c| c.is_control() && c != '\n') {
+        return Some("Control characters are not allowed");
+    }
+    if body.contains("![") {
+        return Some("Images are not allowed");
+    }
+    let mut rest = body;
+    while let Some(start) = rest.find("](") {
+        rest = &rest[start + 2..];
+        if !rest.starts_with("https://") {
+            return Some("Links must start with https://");
+        }
+    }
+    None
+}
+
+/// Administrator-managed notices for the login screen. The active ones are
+/// read on every login page load, so they are kept in memory for
+/// `SYSTEM_MESSAGES_CACHE_SECONDS` and reloaded at once after any change.
+pub struct SystemMessageService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    /// Messages active or starting within the cache period, and when they were loaded
+    active_cache: Mutex<Option<(DateTime<Utc>, Vec<SystemMessage>)>>,
+}
+
+impl SystemMessageService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock, active_cache: Mutex::new(None) }
+    }
+
+    /// Messages to show now, most severe first, at most `MAX_ACTIVE_MESSAGES`
+    pub async fn active(&self) -> Result<Vec<SystemMessage>, sqlx::Error> {
+        let now = self.clock.now();
+        let cached = self
+            .active_cache
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(loaded_at, _)| now - *loaded_at < Duration::seconds(SYSTEM_MESSAGES_CACHE_SECONDS))
+            .map(|(_, messages)| messages.clone());
+        let messages = match cached {
+            Some(messages) => messages,
+            None => {
+                // Messages starting before the cache goes stale come along, so they show on time
+                let messages = sqlx::query_as::<_, SystemMessage>(
+                    r#"
+                    SELECT * FROM system_messages
+                    WHERE starts_at <= ? AND ends_at > ?
+                    ORDER BY CASE severity WHEN 'critical' THEN 0 WHEN 'warning' THEN 1 ELSE 2 END, starts_at
+                    "#
+                )
+                .bind(now + Duration::seconds(SYSTEM_MESSAGES_CACHE_SECONDS))
+                .bind(now)
+                .fetch_all(&self.db_pool)
+                .await?;
+                *self.active_cache.lock().unwrap() = Some((now, messages.clone()));
+                messages
+            }
+        };
+
+        Ok(messages.into_iter().filter(|message| message.is_active_at(now)).take(MAX_ACTIVE_MESSAGES).collect())
+    }
+
+    /// Every message, past, current and scheduled, newest window first
+    pub async fn list(&self) -> Result<Vec<SystemMessage>, sqlx::Error> {
+        sqlx::query_as::<_, SystemMessage>("SELECT * FROM system_messages ORDER BY starts_at DESC, created_at DESC")
+            .fetch_all(&self.db_pool)
+            .await
+    }
+
+    pub async fn create(&self, draft: &MessageDraft, created_by: Uuid) -> Result<SystemMessage, sqlx::Error> {
+        let now = self.clock.now();
+        let message = SystemMessage {
+            id: Uuid::new_v4(),
+            body: draft.body.clone(),
+            severity: draft.severity,
+            starts_at: draft.starts_at,
+            ends_at: draft.ends_at,
+            created_by,
+            created_at: now,
+            updated_at: now,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO system_messages (id, body, severity, starts_at, ends_at, created_by, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(message.id)
+        .bind(&message.body)
+        .bind(message.severity)
+        .bind(message.starts_at)
+        .bind(message.ends_at)
+        .bind(message.created_by)
+        .bind(message.created_at)
+        .bind(message.updated_at)
+        .execute(&self.db_pool)
+        .await?;
+
+        self.forget_active();
+        Ok(message)
+    }
+
+    /// Replace a message's content and window. Returns it as it was and as
+    /// it is now, or `None` when there is no such message.
+    pub async fn update(&self, id: Uuid, draft: &MessageDraft) -> Result<Option<(SystemMessage, SystemMessage)>, sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let Some(previous) = sqlx::query_as::<_, SystemMessage>("SELECT * FROM system_messages WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let updated = sqlx::query_as::<_, SystemMessage>(
+            r#"
+            UPDATE system_messages SET body = ?, severity = ?, starts_at = ?, ends_at = ?, updated_at = ?
+            WHERE id = ?
+            RETURNING *
+            "#
+        )
+        .bind(&draft.body)
+        .bind(draft.severity)
+        .bind(draft.starts_at)
+        .bind(draft.ends_at)
+        .bind(self.clock.now())
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.forget_active();
+        Ok(Some((previous, updated)))
+    }
+
+    /// Delete a message, returning it, or `None` when there is no such message
+    pub async fn delete(&self, id: Uuid) -> Result<Option<SystemMessage>, sqlx::Error> {
+        let deleted = sqlx::query_as::<_, SystemMessage>("DELETE FROM system_messages WHERE id = ? RETURNING *")
+            .bind(id)
+            .fetch_optional(&self.db_pool)
+            .await?;
+
+        self.forget_active();
+        Ok(deleted)
+    }
+
+    fn forget_active(&self) {
+        *self.active_cache.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    fn draft(body: &str, severity: Severity, starts_at: DateTime<Utc>, ends_at: DateTime<Utc>) -> MessageDraft {
+        MessageDraft { body: body.to_string(), severity, starts_at, ends_at }
+    }
+
+    #[actix_web::test]
+    async fn test_messages_show_only_within_their_window() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = SystemMessageService::new(pool.clone(), clock.clone());
+        let admin_id = insert_user(&pool, clock.clone(), "notice_admin", UserRole::Admin, TEST_PASSWORD, false).await.id;
+        let now = clock.now();
+
+        let current = service.create(&draft("Welcome", Severity::Info, now, now + Duration::hours(1)), admin_id).await.unwrap();
+        let outage = service
+            .create(&draft("Degraded service", Severity::Critical, now - Duration::hours(1), now + Duration::hours(3)), admin_id)
+            .await
+            .unwrap();
+        let scheduled = service
+            .create(&draft("Maintenance Saturday", Severity::Warning, now + Duration::hours(2), now + Duration::hours(4)), admin_id)
+            .await
+            .unwrap();
+        service.create(&draft("Yesterday", Severity::Info, now - Duration::days(1), now - Duration::hours(20)), admin_id).await.unwrap();
+
+        let ids = |messages: Vec<SystemMessage>| messages.into_iter().map(|message| message.id).collect::<Vec<_>>();
+        assert_eq!(ids(service.active().await.unwrap()), vec![outage.id, current.id]);
+
+        // The cached list still drops a message the moment its window closes
+        clock.advance(Duration::minutes(60));
+        assert_eq!(ids(service.active().await.unwrap()), vec![outage.id]);
+        clock.advance(Duration::minutes(60));
+        assert_eq!(ids(service.active().await.unwrap()), vec![outage.id, scheduled.id]);
+        clock.advance(Duration::minutes(60));
+        assert_eq!(ids(service.active().await.unwrap()), vec![scheduled.id]);
+        assert_eq!(service.list().await.unwrap().len(), 4);
+    }
+
+    #[actix_web::test]
+    async fn test_changes_show_without_waiting_for_the_cache() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let service = SystemMessageService::new(pool.clone(), clock.clone());
+        let admin_id = insert_user(&pool, clock.clone(), "notice_admin", UserRole::Admin, TEST_PASSWORD, false).await.id;
+        let now = clock.now();
+
+        let message = service.create(&draft("Old text", Severity::Info, now, now + Duration::hours(1)), admin_id).await.unwrap();
+        assert_eq!(service.active().await.unwrap()[0].body, "Old text");
+
+        let (previous, updated) = service
+            .update(message.id, &draft("New text", Severity::Warning, now, now + Duration::hours(1)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(previous.body, "Old text");
+        assert_eq!(updated.severity, Severity::Warning);
+        assert_eq!(service.active().await.unwrap()[0].body, "New text");
+
+        assert!(service.delete(message.id).await.unwrap().is_some());
+        assert!(service.active().await.unwrap().is_empty());
+        assert!(service.delete(message.id).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_markup_problem() {
+        assert_eq!(markup_problem("Maintenance **Saturday 20:00-22:00**"), None);
+        assert_eq!(markup_problem("See [the status page](https://status.kenya.fsfvi.ai)\nfor *details*"), None);
+        assert_eq!(markup_problem("Use `Ctrl+F5` after the upgrade"), None);
+        assert_eq!(markup_problem("<script>alert(1)</script>"), Some("HTML is not allowed"));
+        assert_eq!(markup_problem("a < b"), Some("HTML is not allowed"));
+        assert_eq!(markup_problem("![logo](https://kenya.fsfvi.ai/logo.png)"), Some("Images are not allowed"));
+        assert_eq!(markup_problem("[click](javascript:alert(1))"), Some("Links must start with https://"));
+        assert_eq!(markup_problem("[ok](https://a.ke) then [bad](http://b.ke)"), Some("Links must start with https://"));
+        assert_eq!(markup_problem("bell\u{7}"), Some("Control characters are not allowed"));
+    }
+}
@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::context::RequestContext;
+use crate::utils::clock::Clock;
+
+/// Record of users accepting versions of the acceptable-use terms. Rows are
+/// only ever added; the database refuses updates and deletes.
+pub struct TermsService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl TermsService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// When the user accepted `version`, if they have
+    pub async fn accepted_at(&self, user_id: Uuid, version: &str) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        sqlx::query_scalar("SELECT accepted_at FROM terms_acceptances WHERE user_id = ? AND version = ?")
+            .bind(user_id)
+            .bind(version)
+            .fetch_optional(&self.db_pool)
+            .await
+    }
+
+    /// Record the user accepting `version` from the request's client.
+    ///
+    /// Returns the acceptance time and whether it is new; accepting a version
+    /// again keeps the original record.
+    pub async fn record(
+        &self,
+        ctx: &RequestContext,
+        user_id: Uuid,
+        version: &str,
+    ) -> Result<(DateTime<Utc>, bool), sqlx::Error> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO terms_acceptances (user_id, version, accepted_at, ip_address, user_agent)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(user_id, version) DO NOTHING
+            "#
+        )
+        .bind(user_id)
+        .bind(version)
+        .bind(self.clock.now())
+        .bind(&ctx.ip_address)
+        .bind(&ctx.user_agent)
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected()
+            > 0;
+
+        let accepted_at = self.accepted_at(user_id, version).await?.ok_or(sqlx::Error::RowNotFound)?;
+        Ok((accepted_at, inserted))
+    }
+}
@@ -0,0 +1,472 @@
+use serde::Serialize;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::models::context::client_network;
+use crate::services::shared_state::{InProcessBackend, SharedStateBackend};
+
+/// What a failure counter is keyed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThrottleScope {
+    /// One client address, whatever it targets; IPv6 clients by their /64
+    Ip,
+    /// One account, from any address
+    Username,
+    /// One account from one address
+    IpUsername,
+}
+
+impl ThrottleScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            ThrottleScope::Ip => "ip",
+            ThrottleScope::Username => "username",
+            ThrottleScope::IpUsername => "ip_username",
+        }
+    }
+}
+
+/// Failure budget for one scope
+#[derive(Debug, Clone, Copy)]
+pub struct ScopeLimits {
+    /// Failures within `window` that trip the block
+    pub max_failures: u32,
+    pub window: Duration,
+    /// How long the key stays blocked once tripped
+    pub block_for: Duration,
+}
+
+/// Per-scope budgets
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottleConfig {
+    pub ip: ScopeLimits,
+    pub username: ScopeLimits,
+    pub ip_username: ScopeLimits,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        let fifteen_minutes = Duration::from_secs(15 * 60);
+        Self {
+            ip: ScopeLimits { max_failures: 30, window: fifteen_minutes, block_for: fifteen_minutes },
+            username: ScopeLimits { max_failures: 50, window: fifteen_minutes, block_for: fifteen_minutes },
+            ip_username: ScopeLimits { max_failures: 10, window: fifteen_minutes, block_for: fifteen_minutes },
+        }
+    }
+}
+
+impl ThrottleConfig {
+    fn limits(&self, scope: ThrottleScope) -> ScopeLimits {
+        match scope {
+            ThrottleScope::Ip => self.ip,
+            ThrottleScope::Username => self.username,
+            ThrottleScope::IpUsername => self.ip_username,
+        }
+    }
+}
+
+/// First pause before answering a failed login, once past the free failures
+pub const DEFAULT_FAILURE_DELAY_MS: u64 = 500;
+
+/// Longest pause before answering a failed login
+pub const DEFAULT_FAILURE_DELAY_MAX_MS: u64 = 5000;
+
+/// Failures in a row answered at once, before the pause starts
+pub const DEFAULT_FAILURE_DELAY_AFTER: u32 = 1;
+
+/// How long to hold back the answer to a failed login, growing with the
+/// failures in a row from the address or against the account: nothing for the
+/// first `free_failures`, then `base`, doubling with each further failure up
+/// to `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailureDelay {
+    pub free_failures: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl FailureDelay {
+    /// Every failure answered at once
+    pub const OFF: FailureDelay = FailureDelay { free_failures: 0, base: Duration::ZERO, max: Duration::ZERO };
+
+    pub fn is_off(&self) -> bool {
+        self.base.is_zero() || self.max.is_zero()
+    }
+
+    /// Pause for the `failures`th failure in a row
+    pub fn after(&self, failures: u32) -> Duration {
+        if self.is_off() || failures <= self.free_failures {
+            return Duration::ZERO;
+        }
+        let doublings = (failures - self.free_failures - 1).min(16);
+        self.base.saturating_mul(1 << doublings).min(self.max)
+    }
+}
+
+/// Whether a request may proceed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// The client is sending too much: answer 429
+    Throttle { retry_after_secs: u64 },
+    /// The account is under attack from many places: answer 423
+    Lock { retry_after_secs: u64 },
+}
+
+impl Decision {
+    /// The stricter of two decisions; a lock outranks a throttle
+    fn max(self, other: Decision) -> Decision {
+        match (self, other) {
+            (Decision::Lock { .. }, _) | (Decision::Throttle { .. }, Decision::Allow) | (Decision::Allow, Decision::Allow) => self,
+            _ => other,
+        }
+    }
+}
+
+/// Keys currently blocked in each scope
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ThrottleCounts {
+    pub ip: usize,
+    pub username: usize,
+    pub ip_username: usize,
+}
+
+/// Failure counters shared by the `RateLimiting` middleware and `AuthService`,
+/// so both layers make the same 429/423 decision from the same numbers.
+///
+/// Each key has a failure count that expires with its window and, once
+/// tripped, a block that expires on its own. Both live in the shared state
+/// backend, so every instance sees the same counts.
+pub struct ThrottleState {
+    config: ThrottleConfig,
+    backend: Arc<dyn SharedStateBackend>,
+    /// Pause before answering failed logins, off unless configured
+    failure_delay: FailureDelay,
+}
+
+impl Default for ThrottleState {
+    fn default() -> Self {
+        Self::new(ThrottleConfig::default())
+    }
+}
+
+impl ThrottleState {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            backend: Arc::new(InProcessBackend::default()),
+            failure_delay: FailureDelay::OFF,
+        }
+    }
+
+    /// Keep the counters in `backend` instead of this process's memory
+    pub fn with_backend(mut self, backend: Arc<dyn SharedStateBackend>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Hold back the answer to each failed login as `delay` says
+    pub fn with_failure_delay(mut self, delay: FailureDelay) -> Self {
+        self.failure_delay = delay;
+        self
+    }
+
+    /// Where the counters are kept, for health details
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    /// Decide whether `key` may proceed in `scope`
+    pub async fn check(&self, scope: ThrottleScope, key: &str) -> Decision {
+        match self.backend.remaining(&block_key(scope, key)).await {
+            Ok(Some(remaining)) => Self::blocked(scope, remaining),
+            Ok(None) => Decision::Allow,
+            Err(e) => {
+                log::error!("Failed to read throttle state: {}", e);
+                Decision::Allow
+            }
+        }
+    }
+
+    /// Count a failure against `key`, returning the decision that now applies
+    pub async fn record_failure(&self, scope: ThrottleScope, key: &str) -> Decision {
+        let limits = self.config.limits(scope);
+        let failures = match self.backend.increment(&count_key(scope, key), limits.window).await {
+            Ok(failures) => failures,
+            Err(e) => {
+                log::error!("Failed to count a throttle failure: {}", e);
+                return Decision::Allow;
+            }
+        };
+
+        if failures >= u64::from(limits.max_failures.max(1)) {
+            // An existing block keeps its expiry rather than being extended
+            return match self.backend.hold(&block_key(scope, key), limits.block_for).await {
+                Ok(remaining) => Self::blocked(scope, remaining),
+                Err(e) => {
+                    log::error!("Failed to block a throttled key: {}", e);
+                    Decision::Allow
+                }
+            };
+        }
+        Decision::Allow
+    }
+
+    /// Clear the counter for `key`, e.g. after a successful login or an admin unlock
+    pub async fn reset(&self, scope: ThrottleScope, key: &str) {
+        for entry in [count_key(scope, key), block_key(scope, key)] {
+            if let Err(e) = self.backend.remove(&entry).await {
+                log::error!("Failed to reset throttle state: {}", e);
+            }
+        }
+    }
+
+    /// Current failure count for `key` within its window
+    pub async fn failures(&self, scope: ThrottleScope, key: &str) -> u32 {
+        self.backend
+            .count(&count_key(scope, key))
+            .await
+            .map(|failures| u32::try_from(failures).unwrap_or(u32::MAX))
+            .unwrap_or(0)
+    }
+
+    /// Counts and blocks held in the backend
+    pub async fn entry_count(&self) -> usize {
+        self.backend.count_keys("throttle:").await.unwrap_or(0)
+    }
+
+    /// How many keys each scope is blocking right now
+    pub async fn blocked_counts(&self) -> ThrottleCounts {
+        let blocked = |scope: ThrottleScope| async move {
+            self.backend.count_keys(&block_key(scope, "")).await.unwrap_or(0)
+        };
+        ThrottleCounts {
+            ip: blocked(ThrottleScope::Ip).await,
+            username: blocked(ThrottleScope::Username).await,
+            ip_username: blocked(ThrottleScope::IpUsername).await,
+        }
+    }
+
+    /// Decision for a login from `ip_address` to `username`, across every scope
+    pub async fn check_login(&self, ip_address: &str, username: &str) -> Decision {
+        self.check(ThrottleScope::Ip, ip_address)
+            .await
+            .max(self.check(ThrottleScope::Username, username).await)
+            .max(self.check(ThrottleScope::IpUsername, &login_key(ip_address, username)).await)
+    }
+
+    /// Count a failed login in every scope
+    pub async fn record_login_failure(&self, ip_address: &str, username: &str) -> Decision {
+        self.record_failure(ThrottleScope::Ip, ip_address)
+            .await
+            .max(self.record_failure(ThrottleScope::Username, username).await)
+            .max(self.record_failure(ThrottleScope::IpUsername, &login_key(ip_address, username)).await)
+    }
+
+    /// Extend the failure streaks of the address and the account, returning
+    /// how long to pause before answering: as long as the longer streak calls for
+    pub async fn record_failure_streak(&self, ip_address: &str, username: &str) -> Duration {
+        if self.failure_delay.is_off() {
+            return Duration::ZERO;
+        }
+        let mut longest = 0;
+        for (scope, key) in [(ThrottleScope::Ip, ip_address), (ThrottleScope::Username, username)] {
+            match self.backend.increment(&streak_key(scope, key), self.config.limits(scope).window).await {
+                Ok(failures) => longest = longest.max(failures),
+                Err(e) => log::error!("Failed to count a failure streak: {}", e),
+            }
+        }
+        self.failure_delay.after(u32::try_from(longest).unwrap_or(u32::MAX))
+    }
+
+    /// Forget an account's failures after it signs in. The address keeps its
+    /// count, so one good login can't launder a spray across other accounts,
+    /// but its streak ends: the next failure from it is answered at once.
+    pub async fn record_login_success(&self, ip_address: &str, username: &str) {
+        self.reset(ThrottleScope::Username, username).await;
+        self.reset(ThrottleScope::IpUsername, &login_key(ip_address, username)).await;
+        for entry in [streak_key(ThrottleScope::Ip, ip_address), streak_key(ThrottleScope::Username, username)] {
+            if let Err(e) = self.backend.remove(&entry).await {
+                log::error!("Failed to reset a failure streak: {}", e);
+            }
+        }
+    }
+
+    fn blocked(scope: ThrottleScope, remaining: Duration) -> Decision {
+        let retry_after_secs = remaining.as_secs().max(1);
+        match scope {
+            ThrottleScope::Username => Decision::Lock { retry_after_secs },
+            ThrottleScope::Ip | ThrottleScope::IpUsername => Decision::Throttle { retry_after_secs },
+        }
+    }
+}
+
+fn count_key(scope: ThrottleScope, key: &str) -> String {
+    format!("throttle:count:{}:{}", scope.as_str(), subject(scope, key))
+}
+
+/// Scope names are followed by `:`, so `ip` never matches `ip_username` keys
+fn block_key(scope: ThrottleScope, key: &str) -> String {
+    format!("throttle:block:{}:{}", scope.as_str(), subject(scope, key))
+}
+
+/// Failures in a row, ended by a successful login rather than only by the window
+fn streak_key(scope: ThrottleScope, key: &str) -> String {
+    format!("throttle:streak:{}:{}", scope.as_str(), subject(scope, key))
+}
+
+/// What a key is counted under: an address by its `client_network`, so every
+/// way of writing it, and every address in an IPv6 /64, shares one counter
+fn subject(scope: ThrottleScope, key: &str) -> Cow<'_, str> {
+    match scope {
+        ThrottleScope::Ip => Cow::Owned(client_network(key)),
+        ThrottleScope::Username | ThrottleScope::IpUsername => Cow::Borrowed(key),
+    }
+}
+
+fn login_key(ip_address: &str, username: &str) -> String {
+    format!("{}|{}", client_network(ip_address), username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_failures: u32) -> ScopeLimits {
+        ScopeLimits {
+            max_failures,
+            window: Duration::from_secs(60),
+            block_for: Duration::from_secs(120),
+        }
+    }
+
+    fn state() -> ThrottleState {
+        ThrottleState::new(ThrottleConfig { ip: limits(3), username: limits(3), ip_username: limits(2) })
+    }
+
+    #[actix_web::test]
+    async fn test_decision_table() {
+        let throttle = state();
+
+        // Under budget in every scope
+        assert_eq!(throttle.record_login_failure("10.0.0.1", "alice").await, Decision::Allow);
+        assert_eq!(throttle.check_login("10.0.0.1", "alice").await, Decision::Allow);
+
+        // ip+username trips first: that pair is throttled, the address isn't yet
+        assert!(matches!(throttle.record_login_failure("10.0.0.1", "alice").await, Decision::Throttle { .. }));
+        assert!(matches!(throttle.check_login("10.0.0.1", "alice").await, Decision::Throttle { .. }));
+        assert_eq!(throttle.check_login("10.0.0.1", "bob").await, Decision::Allow);
+        assert_eq!(throttle.check_login("10.0.0.2", "alice").await, Decision::Allow);
+
+        // The account's third failure, from elsewhere, locks it for everyone
+        assert!(matches!(throttle.record_login_failure("10.0.0.2", "alice").await, Decision::Lock { .. }));
+        assert!(matches!(throttle.check_login("10.0.0.3", "alice").await, Decision::Lock { .. }));
+
+        // A lock outranks a throttle
+        assert!(matches!(throttle.check_login("10.0.0.1", "alice").await, Decision::Lock { .. }));
+
+        // The address's third failure throttles it for every account
+        assert!(matches!(throttle.record_login_failure("10.0.0.1", "carol").await, Decision::Throttle { .. }));
+        assert!(matches!(throttle.check(ThrottleScope::Ip, "10.0.0.1").await, Decision::Throttle { .. }));
+        assert!(matches!(throttle.check_login("10.0.0.1", "dave").await, Decision::Throttle { .. }));
+
+        let counts = throttle.blocked_counts().await;
+        assert_eq!((counts.ip, counts.username, counts.ip_username), (1, 1, 1));
+    }
+
+    #[actix_web::test]
+    async fn test_block_reports_time_remaining() {
+        let throttle = state();
+        throttle.record_failure(ThrottleScope::IpUsername, "10.0.0.1|alice").await;
+        let Decision::Throttle { retry_after_secs } = throttle.record_failure(ThrottleScope::IpUsername,"10.0.0.1|alice").await else {
+            panic!("expected a throttle");
+        };
+        assert!(retry_after_secs > 60 && retry_after_secs <= 120);
+    }
+
+    #[actix_web::test]
+    async fn test_success_clears_account_counters_only() {
+        let throttle = state();
+        throttle.record_login_failure("10.0.0.1", "alice").await;
+        throttle.record_login_success("10.0.0.1", "alice").await;
+
+        assert_eq!(throttle.failures(ThrottleScope::Username, "alice").await, 0);
+        assert_eq!(throttle.failures(ThrottleScope::IpUsername, "10.0.0.1|alice").await, 0);
+        assert_eq!(throttle.failures(ThrottleScope::Ip, "10.0.0.1").await, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_failure_delay_grows_to_its_cap_and_ends_with_a_success() {
+        let delay = FailureDelay {
+            free_failures: DEFAULT_FAILURE_DELAY_AFTER,
+            base: Duration::from_millis(DEFAULT_FAILURE_DELAY_MS),
+            max: Duration::from_millis(DEFAULT_FAILURE_DELAY_MAX_MS),
+        };
+        let schedule: Vec<u64> = (1..=7).map(|failures| delay.after(failures).as_millis() as u64).collect();
+        assert_eq!(schedule, [0, 500, 1000, 2000, 4000, 5000, 5000]);
+        assert_eq!(delay.after(u32::MAX), Duration::from_millis(5000));
+        assert_eq!(FailureDelay::OFF.after(40), Duration::ZERO);
+
+        // The longer of the address's and the account's streaks sets the pause
+        let throttle = state().with_failure_delay(delay);
+        assert_eq!(throttle.record_failure_streak("10.0.0.1", "alice").await, Duration::ZERO);
+        assert_eq!(throttle.record_failure_streak("10.0.0.1", "bob").await, Duration::from_millis(500));
+        assert_eq!(throttle.record_failure_streak("10.0.0.2", "alice").await, Duration::from_millis(500));
+        assert_eq!(throttle.record_failure_streak("10.0.0.1", "carol").await, Duration::from_millis(1000));
+
+        throttle.record_login_success("10.0.0.1", "alice").await;
+        assert_eq!(throttle.record_failure_streak("10.0.0.1", "dave").await, Duration::ZERO);
+        assert_eq!(throttle.record_failure_streak("10.0.0.3", "alice").await, Duration::ZERO);
+    }
+
+    #[actix_web::test]
+    async fn test_addresses_are_counted_by_their_network() {
+        let throttle = state();
+
+        // Every spelling of an address is one client, and an IPv6 client is its /64
+        throttle.record_failure(ThrottleScope::Ip, "2c0f:fe38:2001:5a1::10").await;
+        throttle.record_failure(ThrottleScope::Ip, "[2C0F:FE38:2001:5A1:0:0:0:77]:443").await;
+        throttle.record_failure(ThrottleScope::Ip, "2c0f:fe38:2001:5a1:9e1f:2ab:fe00:1%wlan0").await;
+        assert!(matches!(throttle.check(ThrottleScope::Ip, "2c0f:fe38:2001:5a1:ffff::1").await, Decision::Throttle { .. }));
+        assert_eq!(throttle.check(ThrottleScope::Ip, "2c0f:fe38:2001:5a2::10").await, Decision::Allow);
+        assert_eq!(throttle.failures(ThrottleScope::Ip, "2c0f:fe38:2001:5a1::").await, 3);
+
+        // An IPv4-mapped address is the IPv4 client, not a network of its own
+        throttle.record_failure(ThrottleScope::Ip, "::ffff:197.232.61.4").await;
+        throttle.record_failure(ThrottleScope::Ip, "197.232.61.4:52311").await;
+        assert_eq!(throttle.failures(ThrottleScope::Ip, "197.232.61.4").await, 2);
+        assert_eq!(throttle.failures(ThrottleScope::Ip, "197.232.61.5").await, 0);
+
+        // Pairs with an account follow the same networks
+        throttle.record_login_failure("2c0f:fe38:2001:5a1::10", "alice").await;
+        assert!(matches!(throttle.record_login_failure("2c0f:fe38:2001:5a1::20", "alice").await, Decision::Throttle { .. }));
+        assert_eq!(throttle.failures(ThrottleScope::IpUsername, "2c0f:fe38:2001:5a1::/64|alice").await, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_instances_sharing_a_backend_agree() {
+        let backend: Arc<dyn SharedStateBackend> = Arc::new(InProcessBackend::default());
+        let config = ThrottleConfig { ip: limits(3), username: limits(3), ip_username: limits(2) };
+        let first = ThrottleState::new(config).with_backend(backend.clone());
+        let second = ThrottleState::new(config).with_backend(backend);
+
+        first.record_login_failure("10.0.0.1", "alice").await;
+        assert!(matches!(second.record_login_failure("10.0.0.1", "alice").await, Decision::Throttle { .. }));
+        assert!(matches!(first.check_login("10.0.0.1", "alice").await, Decision::Throttle { .. }));
+
+        second.reset(ThrottleScope::IpUsername, "10.0.0.1|alice").await;
+        assert_eq!(first.check_login("10.0.0.1", "alice").await, Decision::Allow);
+    }
+
+    #[actix_web::test]
+    async fn test_window_expiry_forgets_failures() {
+        let throttle = ThrottleState::new(ThrottleConfig {
+            ip: ScopeLimits { max_failures: 2, window: Duration::ZERO, block_for: Duration::from_secs(60) },
+            ..ThrottleConfig::default()
+        });
+
+        assert_eq!(throttle.record_failure(ThrottleScope::Ip, "10.0.0.1").await, Decision::Allow);
+        assert_eq!(throttle.record_failure(ThrottleScope::Ip, "10.0.0.1").await, Decision::Allow);
+    }
+}
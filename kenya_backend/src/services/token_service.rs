@@ -1,74 +1,218 @@
-use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use serde_json::json;
-use std::collections::HashSet;
+use chrono::Duration;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::models::auth::{AuthError, AuthResult, Claims, SecurityConfig, TokenValidation};
+use crate::models::auth::{AuthError, AuthResult, Claims, SecurityConfig, TokenValidation, CLAIMS_VERSION};
+use crate::models::permission::PermissionSet;
 use crate::models::user::{User, UserRole};
+use crate::utils::clock::Clock;
+use crate::utils::self_test::secret_fingerprint;
+
+/// Audience of tokens issued to clients that name no app, and of every
+/// token from before client apps existed
+pub const DEFAULT_TOKEN_AUDIENCE: &str = "kenya-government";
+
+/// A freshly signed token and its ID
+#[derive(Debug, Clone)]
+pub struct IssuedToken {
+    pub token: String,
+    pub jti: String,
+    /// ID of the key that signed it
+    pub kid: String,
+}
+
+/// The secret being migrated away from
+struct PreviousKey {
+    kid: String,
+    decoding_key: DecodingKey,
+}
+
+/// Claims of tokens issued before `claims_version` existed
+#[derive(Debug, Deserialize)]
+struct LegacyClaims {
+    sub: String,
+    username: String,
+    role: String,
+    exp: usize,
+    iat: usize,
+    iss: String,
+    aud: String,
+    jti: String,
+    session_id: String,
+    is_temp_password: bool,
+    perms: u32,
+    token_version: i64,
+}
+
+impl LegacyClaims {
+    /// The same claims in the current schema; legacy tokens carry no organization
+    fn upgrade(self) -> AuthResult<Claims> {
+        Ok(Claims {
+            claims_version: CLAIMS_VERSION,
+            role: UserRole::from_name(&self.role).ok_or(AuthError::Unauthorized)?,
+            sub: self.sub,
+            username: self.username,
+            exp: self.exp,
+            iat: self.iat,
+            iss: self.iss,
+            aud: self.aud,
+            jti: self.jti,
+            session_id: self.session_id,
+            is_temp_password: self.is_temp_password,
+            perms: self.perms,
+            org: None,
+            token_version: self.token_version,
+            kiosk: false,
+        })
+    }
+}
 
 /// JWT Token service for secure token management
 pub struct TokenService {
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
+    /// Sent as the `kid` header of every token this service signs
+    kid: String,
+    previous_key: Option<PreviousKey>,
+    /// Tokens verified with the previous key since startup
+    previous_key_verifications: AtomicU64,
+    /// Tokens accepted since startup although issued ahead of the clock
+    clock_skew_tolerated: AtomicU64,
     config: SecurityConfig,
     validation: Validation,
+    clock: Arc<dyn Clock>,
 }
 
 impl TokenService {
-    pub fn new(config: SecurityConfig) -> Self {
+    /// Issue and expire tokens by the time `clock` reports
+    pub fn new(config: SecurityConfig, clock: Arc<dyn Clock>) -> Self {
         let encoding_key = EncodingKey::from_secret(config.jwt_secret.as_ref());
         let decoding_key = DecodingKey::from_secret(config.jwt_secret.as_ref());
+        let previous_key = config.jwt_previous_secret.as_deref().map(|secret| PreviousKey {
+            kid: key_id(secret),
+            decoding_key: DecodingKey::from_secret(secret.as_ref()),
+        });
 
         let mut validation = Validation::new(Algorithm::HS256);
-        validation.set_audience(&["kenya-government"]);
+        // Each client app has an audience of its own; `AuthService` matches
+        // it against the app a request comes from
+        validation.validate_aud = false;
         validation.set_issuer(&["fsfvi-kenya-backend"]);
-        validation.leeway = 60; // 1 minute leeway for clock skew
+        // Expiry and issue time are checked against the injected clock, with
+        // the configured leeways, in `validate_claims`
+        validation.validate_exp = false;
 
         Self {
             encoding_key,
             decoding_key,
+            kid: key_id(&config.jwt_secret),
+            previous_key,
+            previous_key_verifications: AtomicU64::new(0),
+            clock_skew_tolerated: AtomicU64::new(0),
             config,
             validation,
+            clock,
         }
     }
 
-    /// Generate JWT token for authenticated user
+    /// ID of the key new tokens are signed with
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    /// ID of the key being migrated away from, if any
+    pub fn previous_kid(&self) -> Option<&str> {
+        self.previous_key.as_ref().map(|key| key.kid.as_str())
+    }
+
+    /// Tokens this instance verified with the previous key since it started
+    pub fn previous_key_verifications(&self) -> u64 {
+        self.previous_key_verifications.load(Ordering::Relaxed)
+    }
+
+    /// Tokens this instance accepted since it started although they were
+    /// issued ahead of its clock, within `jwt_iat_leeway_seconds`
+    pub fn clock_skew_tolerated(&self) -> u64 {
+        self.clock_skew_tolerated.load(Ordering::Relaxed)
+    }
+
+    /// Security settings this service was built with
+    pub fn config(&self) -> &SecurityConfig {
+        &self.config
+    }
+
+    /// How long an issued token stays valid
+    pub fn token_lifetime_seconds(&self) -> i64 {
+        self.config.jwt_expiration_hours * 3600
+    }
+
+    /// Generate JWT token for authenticated user, carrying their role's default permissions
     pub fn generate_token(&self, user: &User, session_id: &str) -> AuthResult<String> {
-        let now = Utc::now();
-        let expires_at = now + Duration::hours(self.config.jwt_expiration_hours);
+        let permissions = PermissionSet::for_role(&user.role);
+        let lifetime = Duration::hours(self.config.jwt_expiration_hours);
+        self.issue_token(user, session_id, permissions, lifetime, DEFAULT_TOKEN_AUDIENCE)
+            .map(|issued| issued.token)
+    }
+
+    /// Issue a JWT token for the client app with `audience`, carrying
+    /// `permissions` and expiring after `lifetime` instead of the configured period
+    pub fn issue_token(
+        &self,
+        user: &User,
+        session_id: &str,
+        permissions: PermissionSet,
+        lifetime: Duration,
+        audience: &str,
+    ) -> AuthResult<IssuedToken> {
+        self.issue_token_with(user, session_id, permissions, lifetime, audience, false)
+    }
+
+    /// Issue a token as `issue_token` does, with the `kiosk` claim set as given
+    pub fn issue_token_with(
+        &self,
+        user: &User,
+        session_id: &str,
+        permissions: PermissionSet,
+        lifetime: Duration,
+        audience: &str,
+        kiosk: bool,
+    ) -> AuthResult<IssuedToken> {
+        let now = self.clock.now();
+        let expires_at = now + lifetime;
+        let jti = Uuid::new_v4().to_string();
 
         let claims = Claims {
+            claims_version: CLAIMS_VERSION,
             sub: user.id.to_string(),
             username: user.username.clone(),
-            role: match user.role {
-                UserRole::KenyaGovernment => "kenya_government".to_string(),
-            },
+            role: user.role.clone(),
             exp: expires_at.timestamp() as usize,
             iat: now.timestamp() as usize,
             iss: "fsfvi-kenya-backend".to_string(),
-            aud: "kenya-government".to_string(),
-            jti: Uuid::new_v4().to_string(),
+            aud: audience.to_string(),
+            jti: jti.clone(),
             session_id: session_id.to_string(),
             is_temp_password: user.is_temporary_password,
+            perms: permissions.bits(),
+            org: user.organization.clone(),
+            token_version: user.token_version,
+            kiosk,
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|_| AuthError::InternalError("Failed to generate token".to_string()))
+        let header = Header { kid: Some(self.kid.clone()), ..Header::default() };
+        let token = encode(&header, &claims, &self.encoding_key)
+            .map_err(AuthError::TokenEncoding)?;
+        Ok(IssuedToken { token, jti, kid: self.kid.clone() })
     }
 
     /// Validate and decode JWT token
     pub fn validate_token(&self, token: &str) -> AuthResult<TokenValidation> {
-        let token_data = decode::<Claims>(token, &self.decoding_key, &self.validation)
-            .map_err(|e| match e.kind() {
-                jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::TokenExpired,
-                jsonwebtoken::errors::ErrorKind::InvalidToken => AuthError::InvalidToken,
-                jsonwebtoken::errors::ErrorKind::InvalidIssuer => AuthError::InvalidToken,
-                jsonwebtoken::errors::ErrorKind::InvalidAudience => AuthError::InvalidToken,
-                _ => AuthError::InvalidToken,
-            })?;
+        let (token_data, kid) = self.verify_signature(token)?;
 
-        let claims = token_data.claims;
+        let claims = self.typed_claims(token_data.claims)?;
 
         // Additional validation
         self.validate_claims(&claims)?;
@@ -76,141 +220,174 @@ impl TokenService {
         let user_id = Uuid::parse_str(&claims.sub)
             .map_err(|_| AuthError::InvalidToken)?;
 
-        let expires_at = chrono::DateTime::from_timestamp(claims.exp as i64, 0)
-            .ok_or(AuthError::InvalidToken)?
-            .with_timezone(&Utc);
-
         Ok(TokenValidation {
             user_id,
-            username: claims.username,
-            role: claims.role,
             session_id: claims.session_id,
-            is_temp_password: claims.is_temp_password,
-            expires_at,
+            jti: claims.jti,
+            permissions: PermissionSet::from_bits(claims.perms),
+            token_version: claims.token_version,
+            kid: kid.to_string(),
+            audience: claims.aud,
+            kiosk: claims.kiosk,
         })
     }
 
-    /// Validate token claims
-    fn validate_claims(&self, claims: &Claims) -> AuthResult<()> {
-        // Check if token is expired (with some leeway)
-        let now = Utc::now().timestamp() as usize;
-        if claims.exp < now {
-            return Err(AuthError::TokenExpired);
-        }
+    /// Every claim of a token whose signature checks out
+    #[cfg(test)]
+    pub fn claims(&self, token: &str) -> AuthResult<Claims> {
+        let (token_data, _) = self.verify_signature(token)?;
+        self.typed_claims(token_data.claims)
+    }
 
-        // Validate role
-        match claims.role.as_str() {
-            "kenya_government" => Ok(()),
-            _ => Err(AuthError::Unauthorized),
-        }
+    /// Check the signature with the key the `kid` header names. Tokens from
+    /// before key IDs carry none and may be signed with either key.
+    fn verify_signature(&self, token: &str) -> AuthResult<(TokenData<serde_json::Value>, &str)> {
+        let header = decode_header(token).map_err(|_| AuthError::InvalidToken)?;
+        let previous_key = self.previous_key.as_ref();
+        let verified = match header.kid.as_deref() {
+            Some(kid) if kid == self.kid => (self.decode_with(token, &self.decoding_key)?, self.kid.as_str()),
+            Some(kid) => match previous_key.filter(|key| key.kid == kid) {
+                Some(key) => (self.decode_with_previous(token, key)?, key.kid.as_str()),
+                None => return Err(AuthError::InvalidToken),
+            },
+            None => match (self.decode_with(token, &self.decoding_key), previous_key) {
+                (Ok(token_data), _) => (token_data, self.kid.as_str()),
+                (Err(AuthError::InvalidToken), Some(key)) => (self.decode_with_previous(token, key)?, key.kid.as_str()),
+                (Err(e), _) => return Err(e),
+            },
+        };
+        Ok(verified)
     }
 
-    /// Extract user ID from token without full validation (for logging purposes)
-    pub fn extract_user_id(&self, token: &str) -> Option<Uuid> {
-        // Create a more lenient validation for extraction
-        let mut lenient_validation = Validation::new(Algorithm::HS256);
-        lenient_validation.validate_exp = false;
-        lenient_validation.validate_aud = false;
-
-        if let Ok(token_data) = decode::<Claims>(token, &self.decoding_key, &lenient_validation) {
-            Uuid::parse_str(&token_data.claims.sub).ok()
-        } else {
-            None
+    /// Verify with the previous key, which is refused from the migration
+    /// deadline on. Every use is counted and logged to follow the migration.
+    fn decode_with_previous(&self, token: &str, key: &PreviousKey) -> AuthResult<TokenData<serde_json::Value>> {
+        if self.config.jwt_migration_deadline.is_some_and(|deadline| self.clock.now() >= deadline) {
+            return Err(AuthError::InvalidToken);
         }
+        let token_data = self.decode_with(token, &key.decoding_key)?;
+        self.previous_key_verifications.fetch_add(1, Ordering::Relaxed);
+        log::info!(
+            "Token {} of {} verified with the previous JWT key {}",
+            token_data.claims.get("jti").and_then(|jti| jti.as_str()).unwrap_or("-"),
+            token_data.claims.get("username").and_then(|username| username.as_str()).unwrap_or("-"),
+            key.kid
+        );
+        Ok(token_data)
     }
 
-    /// Generate a refresh token (for future use)
-    pub fn generate_refresh_token(&self, user_id: &Uuid) -> AuthResult<String> {
-        let now = Utc::now();
-        let expires_at = now + Duration::days(30); // Refresh tokens last 30 days
-
-        let claims = json!({
-            "sub": user_id.to_string(),
-            "type": "refresh",
-            "exp": expires_at.timestamp(),
-            "iat": now.timestamp(),
-            "iss": "fsfvi-kenya-backend",
-            "jti": Uuid::new_v4().to_string(),
-        });
-
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|_| AuthError::InternalError("Failed to generate refresh token".to_string()))
+    fn decode_with(&self, token: &str, key: &DecodingKey) -> AuthResult<TokenData<serde_json::Value>> {
+        decode::<serde_json::Value>(token, key, &self.validation).map_err(|e| match e.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::TokenExpired,
+            jsonwebtoken::errors::ErrorKind::InvalidToken => AuthError::InvalidToken,
+            jsonwebtoken::errors::ErrorKind::InvalidIssuer => AuthError::InvalidToken,
+            jsonwebtoken::errors::ErrorKind::InvalidAudience => AuthError::InvalidToken,
+            _ => AuthError::InvalidToken,
+        })
     }
 
-    /// Blacklist a token (would need persistent storage in production)
-    pub fn blacklist_token(&self, token: &str) -> AuthResult<()> {
-        // In a production environment, you would store blacklisted tokens
-        // in Redis or a database with their expiration times
-
-        // For now, we'll just validate that the token is valid before blacklisting
-        self.validate_token(token)?;
+    /// Read a verified payload by its `claims_version`. Version-less tokens go
+    /// through the legacy decoder while the transition window is open; any
+    /// other version is refused.
+    fn typed_claims(&self, payload: serde_json::Value) -> AuthResult<Claims> {
+        match payload.get("claims_version") {
+            None => {
+                let window_open = self
+                    .config
+                    .legacy_claims_accepted_until
+                    .is_some_and(|until| self.clock.now() < until);
+                if !window_open {
+                    return Err(AuthError::InvalidToken);
+                }
+                serde_json::from_value::<LegacyClaims>(payload)
+                    .map_err(|_| AuthError::InvalidToken)?
+                    .upgrade()
+            }
+            Some(version) if version.as_u64() == Some(u64::from(CLAIMS_VERSION)) => {
+                serde_json::from_value(payload).map_err(|_| AuthError::InvalidToken)
+            }
+            Some(_) => Err(AuthError::InvalidToken),
+        }
+    }
 
-        // TODO: Implement token blacklisting storage
-        log::info!("Token blacklisted: {}", &token[..20]);
+    /// Check expiry and issue time against the injected clock. A token issued
+    /// ahead of it, as when the clock steps back after signing or another
+    /// instance runs fast, is accepted within `jwt_iat_leeway_seconds`.
+    fn validate_claims(&self, claims: &Claims) -> AuthResult<()> {
+        let now = self.clock.now().timestamp();
+        if (claims.exp as i64).saturating_add(self.config.jwt_exp_leeway_seconds) < now {
+            return Err(AuthError::TokenExpired);
+        }
 
+        let ahead = claims.iat as i64 - now;
+        if ahead > self.config.jwt_iat_leeway_seconds {
+            log::warn!(
+                "Token {} of {} refused: issued {}s ahead of this instance's clock, beyond the {}s leeway",
+                claims.jti,
+                claims.username,
+                ahead,
+                self.config.jwt_iat_leeway_seconds
+            );
+            return Err(AuthError::InvalidToken);
+        }
+        if ahead > 0 {
+            self.clock_skew_tolerated.fetch_add(1, Ordering::Relaxed);
+            log::warn!(
+                "Clock skew: token {} of {} issued {}s ahead of this instance's clock, accepted within the {}s leeway",
+                claims.jti,
+                claims.username,
+                ahead,
+                self.config.jwt_iat_leeway_seconds
+            );
+        }
         Ok(())
     }
 
-    /// Check if token is blacklisted (would need persistent storage in production)
-    pub fn is_token_blacklisted(&self, _token: &str) -> bool {
-        // TODO: Implement blacklist checking
-        false
-    }
-
     /// Generate session ID
     pub fn generate_session_id() -> String {
         Uuid::new_v4().to_string()
     }
-
-    /// Create secure token response
-    pub fn create_token_response(&self, token: String, expires_in_seconds: i64) -> serde_json::Value {
-        json!({
-            "access_token": token,
-            "token_type": "Bearer",
-            "expires_in": expires_in_seconds,
-            "issued_at": Utc::now().timestamp(),
-            "scope": "kenya_government_access"
-        })
-    }
-}
-
-/// Token blacklist service (in-memory implementation)
-/// In production, this should be backed by Redis or a database
-pub struct TokenBlacklist {
-    blacklisted_tokens: HashSet<String>,
 }
 
-impl TokenBlacklist {
-    pub fn new() -> Self {
-        Self {
-            blacklisted_tokens: HashSet::new(),
-        }
-    }
-
-    pub fn blacklist_token(&mut self, token: String) {
-        self.blacklisted_tokens.insert(token);
-    }
-
-    pub fn is_blacklisted(&self, token: &str) -> bool {
-        self.blacklisted_tokens.contains(token)
-    }
-
-    pub fn cleanup_expired(&mut self, token_service: &TokenService) {
-        // Remove expired tokens from blacklist
-        self.blacklisted_tokens.retain(|token| {
-            // If we can't validate the token, it's probably expired, so remove it
-            token_service.validate_token(token).is_ok()
-        });
-    }
+/// ID a secret's tokens are signed under: its fingerprint, as shown in the startup log
+pub fn key_id(secret: &str) -> String {
+    secret_fingerprint(secret)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::models::user::{User, UserRole};
+    use crate::test_support::MockClock;
+    use crate::utils::clock::SystemClock;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
     use chrono::Utc;
     use uuid::Uuid;
 
+    // Tokens signed with the default secret, as issued by each claims schema.
+    // They expire in 2100.
+
+    /// Before `claims_version`: role as a plain string, no organization
+    const LEGACY_TOKEN: &str = concat!(
+        "eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzI1NiJ9.",
+        "eyJzdWIiOiI2ZjFjMmIxZS0zZDRhLTRiNWMtOGQ5ZS0wZjFhMmIzYzRkNWUiLCJ1c2VybmFtZSI6ImZpeHR1cmVfdXNlciIsImV4cCI6NDEwMjQ0NDgwMCwiaWF0IjoxNzkwMDAwMDAwLCJpc3MiOiJmc2Z2aS1rZW55YS1iYWNrZW5kIiwiYXVkIjoia2VueWEtZ292ZXJubWVudCIsImp0aSI6IjdhOGI5YzBkLTFlMmYtNGEzYi05YzRkLTVlNmY3YThiOWMwZCIsInNlc3Npb25faWQiOiJmaXh0dXJlX3Nlc3Npb24iLCJpc190ZW1wX3Bhc3N3b3JkIjpmYWxzZSwicGVybXMiOjMsInRva2VuX3ZlcnNpb24iOjQsInJvbGUiOiJrZW55YV9nb3Zlcm5tZW50In0.",
+        "ZJCngdatE_wyK2-R71y9zSsOLG_iZ6cAZieamuRfjzA"
+    );
+
+    /// Version 2: admin in "Kisumu County" with permission bits 3
+    const V2_TOKEN: &str = concat!(
+        "eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzI1NiJ9.",
+        "eyJjbGFpbXNfdmVyc2lvbiI6Miwic3ViIjoiNmYxYzJiMWUtM2Q0YS00YjVjLThkOWUtMGYxYTJiM2M0ZDVlIiwidXNlcm5hbWUiOiJmaXh0dXJlX3VzZXIiLCJleHAiOjQxMDI0NDQ4MDAsImlhdCI6MTc5MDAwMDAwMCwiaXNzIjoiZnNmdmkta2VueWEtYmFja2VuZCIsImF1ZCI6ImtlbnlhLWdvdmVybm1lbnQiLCJqdGkiOiI3YThiOWMwZC0xZTJmLTRhM2ItOWM0ZC01ZTZmN2E4YjljMGQiLCJzZXNzaW9uX2lkIjoiZml4dHVyZV9zZXNzaW9uIiwiaXNfdGVtcF9wYXNzd29yZCI6ZmFsc2UsInBlcm1zIjozLCJ0b2tlbl92ZXJzaW9uIjo0LCJyb2xlIjoiYWRtaW4iLCJvcmciOiJLaXN1bXUgQ291bnR5In0.",
+        "0NIZ4bbnmwp-1a-xFvnnasV8-i2iyUfO4NS-Y_yXsnI"
+    );
+
+    /// The version 2 payload labelled version 99
+    const V99_TOKEN: &str = concat!(
+        "eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzI1NiJ9.",
+        "eyJjbGFpbXNfdmVyc2lvbiI6OTksInN1YiI6IjZmMWMyYjFlLTNkNGEtNGI1Yy04ZDllLTBmMWEyYjNjNGQ1ZSIsInVzZXJuYW1lIjoiZml4dHVyZV91c2VyIiwiZXhwIjo0MTAyNDQ0ODAwLCJpYXQiOjE3OTAwMDAwMDAsImlzcyI6ImZzZnZpLWtlbnlhLWJhY2tlbmQiLCJhdWQiOiJrZW55YS1nb3Zlcm5tZW50IiwianRpIjoiN2E4YjljMGQtMWUyZi00YTNiLTljNGQtNWU2ZjdhOGI5YzBkIiwic2Vzc2lvbl9pZCI6ImZpeHR1cmVfc2Vzc2lvbiIsImlzX3RlbXBfcGFzc3dvcmQiOmZhbHNlLCJwZXJtcyI6MywidG9rZW5fdmVyc2lvbiI6NCwicm9sZSI6ImFkbWluIiwib3JnIjoiS2lzdW11IENvdW50eSJ9.",
+        "K7Acs8hlmaqifhxDH4f7wCO5FJqEcvdR3vcKSFBmauc"
+    );
+
     fn create_test_user() -> User {
         User {
             id: Uuid::new_v4(),
@@ -224,6 +401,7 @@ mod tests {
             login_attempts: 0,
             is_locked: false,
             lockout_expiry: None,
+            is_active: true,
             password_changed_at: None,
             session_token: None,
             session_expires_at: None,
@@ -231,13 +409,16 @@ mod tests {
             two_fa_secret: None,
             two_fa_backup_codes: None,
             two_fa_enabled_at: None,
+            token_version: 0,
+            onboarded_at: None,
+            organization: None,
         }
     }
 
     #[test]
     fn test_token_generation_and_validation() {
         let config = SecurityConfig::default();
-        let service = TokenService::new(config);
+        let service = TokenService::new(config, Arc::new(SystemClock));
         let user = create_test_user();
         let session_id = "test_session";
 
@@ -248,14 +429,16 @@ mod tests {
         // Validate token
         let validation = service.validate_token(&token).unwrap();
         assert_eq!(validation.user_id, user.id);
-        assert_eq!(validation.username, user.username);
+        assert_eq!(service.claims(&token).unwrap().username, user.username);
         assert_eq!(validation.session_id, session_id);
+        assert_eq!(validation.permissions, PermissionSet::for_role(&user.role));
+        assert_eq!(validation.audience, DEFAULT_TOKEN_AUDIENCE);
     }
 
     #[test]
     fn test_invalid_token_rejection() {
         let config = SecurityConfig::default();
-        let service = TokenService::new(config);
+        let service = TokenService::new(config, Arc::new(SystemClock));
 
         // Test invalid token
         assert!(service.validate_token("invalid_token").is_err());
@@ -263,4 +446,178 @@ mod tests {
         // Test malformed token
         assert!(service.validate_token("header.payload.signature").is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_token_expires_by_injected_clock() {
+        let clock = Arc::new(MockClock::new());
+        let service = TokenService::new(SecurityConfig::default(), clock.clone());
+        let user = create_test_user();
+        let issued = service
+            .issue_token(&user, "test_session", PermissionSet::default(), Duration::minutes(10), DEFAULT_TOKEN_AUDIENCE)
+            .unwrap();
+
+        clock.advance(Duration::minutes(9));
+        assert!(service.validate_token(&issued.token).is_ok());
+
+        clock.advance(Duration::minutes(2));
+        assert!(matches!(service.validate_token(&issued.token), Err(AuthError::TokenExpired)));
+    }
+
+    #[test]
+    fn test_expiry_leeway_comes_from_config() {
+        let clock = Arc::new(MockClock::new());
+        let config = SecurityConfig { jwt_exp_leeway_seconds: 30, ..SecurityConfig::default() };
+        let service = TokenService::new(config, clock.clone());
+        let user = create_test_user();
+        let issued = service
+            .issue_token(&user, "test_session", PermissionSet::default(), Duration::minutes(10), DEFAULT_TOKEN_AUDIENCE)
+            .unwrap();
+
+        clock.advance(Duration::minutes(10) + Duration::seconds(30));
+        assert!(service.validate_token(&issued.token).is_ok());
+        clock.advance(Duration::seconds(1));
+        assert!(matches!(service.validate_token(&issued.token), Err(AuthError::TokenExpired)));
+    }
+
+    #[test]
+    fn test_tokens_survive_the_clock_stepping_back_within_the_iat_leeway() {
+        let clock = Arc::new(MockClock::new());
+        let service = TokenService::new(SecurityConfig::default(), clock.clone());
+        let user = create_test_user();
+        let issued = service
+            .issue_token(&user, "test_session", PermissionSet::default(), Duration::hours(8), DEFAULT_TOKEN_AUDIENCE)
+            .unwrap();
+        assert!(service.validate_token(&issued.token).is_ok());
+        assert_eq!(service.clock_skew_tolerated(), 0);
+
+        // NTP steps the clock back: the token now looks issued in the future
+        clock.advance(Duration::seconds(-45));
+        assert!(service.validate_token(&issued.token).is_ok());
+        clock.advance(Duration::seconds(-15));
+        assert!(service.validate_token(&issued.token).is_ok());
+        assert_eq!(service.clock_skew_tolerated(), 2);
+
+        // Beyond the leeway it is refused, and not counted as tolerated
+        clock.advance(Duration::seconds(-1));
+        assert!(matches!(service.validate_token(&issued.token), Err(AuthError::InvalidToken)));
+        assert_eq!(service.clock_skew_tolerated(), 2);
+
+        // Once the clock catches up the token is ordinary again
+        clock.advance(Duration::seconds(61));
+        assert!(service.validate_token(&issued.token).is_ok());
+        assert_eq!(service.clock_skew_tolerated(), 2);
+
+        let no_leeway = SecurityConfig { jwt_iat_leeway_seconds: 0, ..SecurityConfig::default() };
+        let strict = TokenService::new(no_leeway, clock.clone());
+        clock.advance(Duration::seconds(-1));
+        assert!(matches!(strict.validate_token(&issued.token), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_issued_claims_keep_snake_case_names() {
+        let service = TokenService::new(SecurityConfig::default(), Arc::new(SystemClock));
+        let user = User { organization: Some("Kisumu County".to_string()), ..create_test_user() };
+        let token = service.generate_token(&user, "test_session").unwrap();
+
+        let payload = token.split('.').nth(1).unwrap();
+        let payload: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        assert_eq!(payload["claims_version"], CLAIMS_VERSION);
+        assert_eq!(payload["role"], "kenya_government");
+        assert_eq!(payload["org"], "Kisumu County");
+        assert_eq!(payload["perms"], PermissionSet::for_role(&user.role).bits());
+        assert_eq!(payload["kiosk"], false);
+
+        let claims = service.claims(&token).unwrap();
+        assert_eq!(claims.role, UserRole::KenyaGovernment);
+        assert_eq!(claims.org.as_deref(), Some("Kisumu County"));
+    }
+
+    #[test]
+    fn test_fixture_tokens_decode_by_claims_version() {
+        let clock = Arc::new(MockClock::new());
+        let config = SecurityConfig {
+            legacy_claims_accepted_until: Some(clock.now() + Duration::hours(8)),
+            ..SecurityConfig::default()
+        };
+        let service = TokenService::new(config, clock.clone());
+
+        let current = service.validate_token(V2_TOKEN).unwrap();
+        assert_eq!(current.permissions, PermissionSet::from_bits(3));
+        assert_eq!(current.token_version, 4);
+        let claims = service.claims(V2_TOKEN).unwrap();
+        assert_eq!(claims.role, UserRole::Admin);
+        assert_eq!(claims.org.as_deref(), Some("Kisumu County"));
+
+        let legacy = service.validate_token(LEGACY_TOKEN).unwrap();
+        assert_eq!(legacy.user_id, current.user_id);
+        assert_eq!(legacy.permissions, PermissionSet::from_bits(3));
+        let claims = service.claims(LEGACY_TOKEN).unwrap();
+        assert_eq!(claims.role, UserRole::KenyaGovernment);
+        assert_eq!(claims.org, None);
+
+        assert!(matches!(service.validate_token(V99_TOKEN), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_legacy_tokens_are_refused_once_the_transition_window_closes() {
+        let clock = Arc::new(MockClock::new());
+        let cutoff = clock.now() + Duration::hours(8);
+        let service = TokenService::new(
+            SecurityConfig { legacy_claims_accepted_until: Some(cutoff), ..SecurityConfig::default() },
+            clock.clone(),
+        );
+
+        clock.advance(Duration::hours(8) - Duration::seconds(1));
+        assert!(service.validate_token(LEGACY_TOKEN).is_ok());
+        clock.advance(Duration::seconds(1));
+        assert!(matches!(service.validate_token(LEGACY_TOKEN), Err(AuthError::InvalidToken)));
+        // Current tokens are unaffected by the cutoff
+        assert!(service.validate_token(V2_TOKEN).is_ok());
+
+        // Without a window, legacy tokens are never accepted
+        let strict = TokenService::new(SecurityConfig::default(), clock);
+        assert!(matches!(strict.validate_token(LEGACY_TOKEN), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_previous_key_tokens_are_accepted_until_the_migration_deadline() {
+        let clock = Arc::new(MockClock::new());
+        let previous = SecurityConfig::default();
+        let old_service = TokenService::new(previous.clone(), clock.clone());
+        let service = TokenService::new(
+            SecurityConfig {
+                jwt_secret: "freshly-rotated-jwt-secret".to_string(),
+                jwt_previous_secret: Some(previous.jwt_secret.clone()),
+                jwt_migration_deadline: Some(clock.now() + Duration::hours(1)),
+                ..SecurityConfig::default()
+            },
+            clock.clone(),
+        );
+        let user = create_test_user();
+        let old = old_service.issue_token(&user, "old_session", PermissionSet::default(), Duration::hours(8), DEFAULT_TOKEN_AUDIENCE).unwrap();
+        let new = service.issue_token(&user, "new_session", PermissionSet::default(), Duration::hours(8), DEFAULT_TOKEN_AUDIENCE).unwrap();
+        assert_eq!(old.kid, key_id(&previous.jwt_secret));
+        assert_eq!(new.kid, service.kid());
+
+        // Tokens from either key, and from before key IDs, verify under the key that signed them
+        assert_eq!(service.validate_token(&new.token).unwrap().kid, service.kid());
+        assert_eq!(service.validate_token(&old.token).unwrap().kid, old.kid);
+        assert_eq!(service.validate_token(V2_TOKEN).unwrap().kid, old.kid);
+        assert_eq!(service.previous_key_verifications(), 2);
+
+        clock.advance(Duration::hours(1) - Duration::seconds(1));
+        assert!(service.validate_token(&old.token).is_ok());
+        clock.advance(Duration::seconds(1));
+        assert!(matches!(service.validate_token(&old.token), Err(AuthError::InvalidToken)));
+        assert!(matches!(service.validate_token(V2_TOKEN), Err(AuthError::InvalidToken)));
+        assert!(service.validate_token(&new.token).is_ok());
+        assert_eq!(service.previous_key_verifications(), 3);
+
+        // A key that isn't configured verifies nothing, whatever the header says
+        let stranger = TokenService::new(SecurityConfig { jwt_secret: "unrelated-secret".to_string(), ..SecurityConfig::default() }, clock);
+        let foreign = stranger.issue_token(&user, "foreign_session", PermissionSet::default(), Duration::hours(1), DEFAULT_TOKEN_AUDIENCE).unwrap();
+        assert!(matches!(service.validate_token(&foreign.token), Err(AuthError::InvalidToken)));
+        assert!(matches!(old_service.validate_token(&new.token), Err(AuthError::InvalidToken)));
+    }
+}
@@ -0,0 +1,407 @@
+use rand::{Rng, RngCore};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header carrying W3C trace context between services
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Name the spans are reported under
+const SERVICE_NAME: &str = "kenya-fsfvi-auth";
+
+/// How often finished spans are sent to the collector
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long one export may take
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Finished spans held for the next export; past this, new ones are dropped
+/// rather than letting a collector outage grow memory
+const MAX_PENDING_SPANS: usize = 4096;
+
+tokio::task_local! {
+    /// The trace of the request being handled, set by `RequestTracing`
+    static CURRENT: ActiveTrace;
+}
+
+/// W3C trace context: the trace a span belongs to and the span itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// The start of a new trace
+    fn root(sampled: bool) -> Self {
+        let mut trace_id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut trace_id);
+        Self { trace_id, span_id: new_span_id(), sampled }
+    }
+
+    /// A span under this one, in the same trace
+    fn child(&self) -> Self {
+        Self { span_id: new_span_id(), ..*self }
+    }
+
+    /// Read a `traceparent` header, `00-<trace id>-<span id>-<flags>` in
+    /// lowercase hex. Later versions are read as version 00, as the
+    /// specification asks; all-zero IDs are refused.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let [version]: [u8; 1] = decode_hex(parts.next()?)?.try_into().ok()?;
+        let trace_id: [u8; 16] = decode_hex(parts.next()?)?.try_into().ok()?;
+        let span_id: [u8; 8] = decode_hex(parts.next()?)?.try_into().ok()?;
+        let [flags]: [u8; 1] = decode_hex(parts.next()?)?.try_into().ok()?;
+        if version == 0xff || (version == 0 && parts.next().is_some()) {
+            return None;
+        }
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(Self { trace_id, span_id, sampled: flags & 1 == 1 })
+    }
+
+    /// The `traceparent` header naming this span as the caller
+    pub fn header_value(&self) -> String {
+        format!("00-{}-{}-{:02x}", encode_hex(&self.trace_id), encode_hex(&self.span_id), u8::from(self.sampled))
+    }
+}
+
+fn new_span_id() -> [u8; 8] {
+    let mut span_id = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut span_id);
+    span_id
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+/// What a span did, in OTLP's numbering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal = 1,
+    /// Handling an incoming request
+    Server = 2,
+    /// Calling another service
+    Client = 3,
+}
+
+/// A span attribute. Only route patterns, methods, statuses and IDs are
+/// ever recorded: never paths, query strings, headers or bodies, which can
+/// carry tokens and passwords.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attribute {
+    Str(String),
+    Int(i64),
+}
+
+/// A span that has ended, waiting for the next export
+#[derive(Debug, Clone)]
+pub struct FinishedSpan {
+    pub context: TraceContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, Attribute)>,
+    pub error: bool,
+}
+
+impl FinishedSpan {
+    fn to_otlp(&self) -> Value {
+        let nanos = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string();
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| match value {
+                Attribute::Str(value) => json!({ "key": key, "value": { "stringValue": value } }),
+                // OTLP JSON carries 64-bit integers as strings
+                Attribute::Int(value) => json!({ "key": key, "value": { "intValue": value.to_string() } }),
+            })
+            .collect();
+        json!({
+            "traceId": encode_hex(&self.context.trace_id),
+            "spanId": encode_hex(&self.context.span_id),
+            "parentSpanId": self.parent_span_id.map(|id| encode_hex(&id)).unwrap_or_default(),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(self.end),
+            "attributes": attributes,
+            "status": { "code": if self.error { 2 } else { 0 } },
+        })
+    }
+}
+
+/// Where sampled spans go
+struct Exporter {
+    /// `{OTEL_EXPORTER_OTLP_ENDPOINT}/v1/traces`
+    url: String,
+    client: reqwest::Client,
+    pending: Mutex<Vec<FinishedSpan>>,
+}
+
+/// Request tracing exported to an OpenTelemetry collector over OTLP/HTTP.
+/// Without an endpoint it is disabled: `RequestTracing` isn't installed,
+/// no request carries a trace, and `span` returns `None` at once.
+pub struct Tracer {
+    exporter: Option<Exporter>,
+    /// Share of new traces recorded; traces started by a caller follow its decision
+    sample_rate: f64,
+}
+
+impl Tracer {
+    pub fn disabled() -> Self {
+        Self { exporter: None, sample_rate: 0.0 }
+    }
+
+    /// Export to the collector at `endpoint`, e.g. `http://otel-collector:4318`,
+    /// keeping `sample_rate` of the traces started here
+    pub fn new(endpoint: Option<&str>, sample_rate: f64) -> Self {
+        let Some(endpoint) = endpoint.map(|e| e.trim().trim_end_matches('/')).filter(|e| !e.is_empty()) else {
+            return Self::disabled();
+        };
+        let client = reqwest::Client::builder().timeout(EXPORT_TIMEOUT).build().unwrap_or_default();
+        Self {
+            exporter: Some(Exporter {
+                url: format!("{}/v1/traces", endpoint),
+                client,
+                pending: Mutex::new(Vec::new()),
+            }),
+            sample_rate: if sample_rate.is_finite() { sample_rate.clamp(0.0, 1.0) } else { 1.0 },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.exporter.is_some()
+    }
+
+    /// The span for a request arriving with `traceparent`: under the
+    /// caller's when there is one, otherwise the root of a new trace.
+    /// Returns the caller's span ID alongside.
+    pub fn start_request(&self, traceparent: Option<&str>) -> (TraceContext, Option<[u8; 8]>) {
+        match traceparent.and_then(TraceContext::parse) {
+            Some(parent) => (parent.child(), Some(parent.span_id)),
+            None => (TraceContext::root(rand::thread_rng().gen_bool(self.sample_rate)), None),
+        }
+    }
+
+    /// Keep a finished span for the next export, if its trace is sampled
+    pub fn record(&self, span: FinishedSpan) {
+        let Some(exporter) = &self.exporter else { return };
+        if !span.context.sampled {
+            return;
+        }
+        if let Ok(mut pending) = exporter.pending.lock() {
+            if pending.len() < MAX_PENDING_SPANS {
+                pending.push(span);
+            }
+        }
+    }
+
+    /// Spans waiting for the next export
+    #[cfg(test)]
+    pub fn pending(&self) -> usize {
+        self.exporter.as_ref().and_then(|e| e.pending.lock().ok().map(|pending| pending.len())).unwrap_or(0)
+    }
+
+    /// Send the waiting spans to the collector. Returns how many were sent;
+    /// on failure they are dropped, as a collector that is down shouldn't
+    /// hold up the server.
+    pub async fn flush(&self) -> Result<usize, reqwest::Error> {
+        let Some(exporter) = &self.exporter else { return Ok(0) };
+        let spans = match exporter.pending.lock() {
+            Ok(mut pending) => std::mem::take(&mut *pending),
+            Err(_) => return Ok(0),
+        };
+        if spans.is_empty() {
+            return Ok(0);
+        }
+
+        let body = json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [{ "key": "service.name", "value": { "stringValue": SERVICE_NAME } }] },
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans.iter().map(FinishedSpan::to_otlp).collect::<Vec<_>>(),
+                }],
+            }],
+        });
+        exporter.client.post(&exporter.url).json(&body).send().await?.error_for_status()?;
+        Ok(spans.len())
+    }
+
+    /// Export the finished spans every few seconds
+    pub fn spawn_export(self: Arc<Self>) {
+        if !self.is_enabled() {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(EXPORT_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.flush().await {
+                    log::warn!("Failed to export trace spans: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// The trace a piece of work belongs to, and where its spans go
+#[derive(Clone)]
+pub struct ActiveTrace {
+    tracer: Arc<Tracer>,
+    context: TraceContext,
+}
+
+impl ActiveTrace {
+    pub fn new(tracer: Arc<Tracer>, context: TraceContext) -> Self {
+        Self { tracer, context }
+    }
+
+    /// Start a span under this one. It is recorded when dropped.
+    pub fn child(&self, name: &'static str, kind: SpanKind) -> SpanGuard {
+        SpanGuard {
+            tracer: self.tracer.clone(),
+            context: self.context.child(),
+            parent_span_id: self.context.span_id,
+            name,
+            kind,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    /// Run `work` as part of this trace, so `span` and `current` find it
+    pub async fn scope<F: Future>(self, work: F) -> F::Output {
+        CURRENT.scope(self, work).await
+    }
+}
+
+/// The trace of the request being handled, for work that outlives it
+pub fn current() -> Option<ActiveTrace> {
+    CURRENT.try_with(ActiveTrace::clone).ok()
+}
+
+/// Start an internal span under the current request's, ended when the
+/// guard is dropped. `None`, without further work, outside a sampled trace.
+pub fn span(name: &'static str) -> Option<SpanGuard> {
+    CURRENT
+        .try_with(|active| active.context.sampled.then(|| active.child(name, SpanKind::Internal)))
+        .ok()
+        .flatten()
+}
+
+/// A span in progress
+pub struct SpanGuard {
+    tracer: Arc<Tracer>,
+    context: TraceContext,
+    parent_span_id: [u8; 8],
+    name: &'static str,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(&'static str, Attribute)>,
+    error: bool,
+}
+
+impl SpanGuard {
+    /// This span's context, to send on as the caller of an outbound request
+    pub fn context(&self) -> TraceContext {
+        self.context
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: Attribute) {
+        self.attributes.push((key, value));
+    }
+
+    pub fn set_error(&mut self) {
+        self.error = true;
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        self.tracer.record(FinishedSpan {
+            context: self.context,
+            parent_span_id: Some(self.parent_span_id),
+            name: self.name.to_string(),
+            kind: self.kind,
+            start: self.start,
+            end: SystemTime::now(),
+            attributes: std::mem::take(&mut self.attributes),
+            error: self.error,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_traceparent_is_read_and_written_as_the_specification_says() {
+        let parent = TraceContext::parse(PARENT).unwrap();
+        assert!(parent.sampled);
+        assert_eq!(parent.header_value(), PARENT);
+        let child = parent.child();
+        assert_eq!(child.trace_id, parent.trace_id);
+        assert_ne!(child.span_id, parent.span_id);
+
+        // A later version may add fields; version 00 may not
+        assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra").is_some());
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+            "",
+        ] {
+            assert!(TraceContext::parse(invalid).is_none(), "{:?} should be refused", invalid);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_spans_cost_nothing_outside_a_trace() {
+        assert!(!Tracer::new(None, 1.0).is_enabled());
+        assert!(!Tracer::new(Some("  "), 1.0).is_enabled());
+
+        // What the login path pays per span while tracing is off: one
+        // task-local lookup. Coarse on purpose, so a loaded machine passes.
+        let started = Instant::now();
+        for _ in 0..100_000 {
+            assert!(span("AuthService::authenticate").is_none());
+        }
+        assert!(started.elapsed() < Duration::from_millis(500), "took {:?}", started.elapsed());
+
+        // Unsampled traces propagate but record nothing
+        let tracer = Arc::new(Tracer::new(Some("http://127.0.0.1:9"), 0.0));
+        let (context, parent) = tracer.start_request(None);
+        assert!(!context.sampled && parent.is_none());
+        ActiveTrace::new(tracer.clone(), context)
+            .scope(async {
+                assert!(span("AuthService::authenticate").is_none());
+                assert_eq!(current().unwrap().context.trace_id, context.trace_id);
+            })
+            .await;
+        assert_eq!(tracer.pending(), 0);
+    }
+}
@@ -1,26 +1,54 @@
 use base64::{engine::general_purpose, Engine as _};
-use chrono::Utc;
+use hmac::{Hmac, Mac};
 use qrcode::QrCode;
 use rand::{distributions::Alphanumeric, Rng};
-use totp_lite::{totp, Sha1};
+use totp_lite::{totp_custom, Sha1, DEFAULT_STEP};
 use uuid::Uuid;
 use image::{ImageBuffer, Luma};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::sync::Arc;
 
 use crate::models::auth::{AuthError, AuthResult};
+use crate::services::phase_timings::{self, Phase};
+use crate::utils::clock::Clock;
+use crate::utils::constant_time::ct_eq_str;
+
+/// Number of digits in a TOTP code (what authenticator apps display)
+const TOTP_DIGITS: u32 = 6;
+
+/// Shortest secret accepted at enrollment, in bytes (160 bits, per RFC 4226)
+pub const MIN_SECRET_BYTES: usize = 20;
+
+/// Public prefix of 2FA temp tokens; the UUID after it is the secret part
+const TEMP_TOKEN_PREFIX: &str = "2fa_temp_";
+
+/// Issuer authenticator apps show unless `TWO_FA_ISSUER` is set
+pub const DEFAULT_ISSUER: &str = "Kenya FSFVI Platform";
 
 /// Two-Factor Authentication service
 pub struct TwoFAService {
     issuer: String,
+    /// Time source for TOTP windows
+    clock: Arc<dyn Clock>,
+    /// HMAC key for secret fingerprints and temp token digests
+    fingerprint_key: Vec<u8>,
 }
 
 impl TwoFAService {
-    pub fn new(issuer: String) -> Self {
-        Self { issuer }
+    pub fn new(issuer: String, clock: Arc<dyn Clock>) -> Self {
+        Self { issuer, clock, fingerprint_key: Vec::new() }
+    }
+
+    /// Key the secret fingerprints with a server-side secret
+    pub fn with_fingerprint_key(mut self, key: &[u8]) -> Self {
+        self.fingerprint_key = key.to_vec();
+        self
     }
 
     /// Generate a new TOTP secret
     pub fn generate_secret(&self) -> String {
-        let secret: Vec<u8> = (0..20).map(|_| rand::random::<u8>()).collect();
+        let secret: Vec<u8> = (0..MIN_SECRET_BYTES).map(|_| rand::random::<u8>()).collect();
         general_purpose::STANDARD.encode(&secret)
     }
 
@@ -31,13 +59,13 @@ impl TwoFAService {
             .map_err(|_| AuthError::InvalidToken)?;
 
         let time = if let Some(offset) = time_offset {
-            (Utc::now().timestamp() + offset) as u64
+            (self.clock.now().timestamp() + offset) as u64
         } else {
-            Utc::now().timestamp() as u64
+            self.clock.now().timestamp() as u64
         };
 
-        let code = totp::<Sha1>(&decoded_secret, time);
-        Ok(format!("{:06}", code))
+        let code = totp_custom::<Sha1>(DEFAULT_STEP, TOTP_DIGITS, &decoded_secret, time);
+        Ok(code)
     }
 
     /// Verify TOTP code against secret
@@ -47,7 +75,7 @@ impl TwoFAService {
             .map_err(|_| AuthError::InvalidToken)?;
 
         // Check current time window and one window before/after to account for clock drift
-        let current_time = Utc::now().timestamp() as u64;
+        let current_time = self.clock.now().timestamp() as u64;
         
         for time_offset in [-30i64, 0i64, 30i64] {
             let check_time = if time_offset < 0 {
@@ -56,10 +84,9 @@ impl TwoFAService {
                 current_time + time_offset as u64
             };
 
-            let expected_code = totp::<Sha1>(&decoded_secret, check_time);
-            let expected_str = format!("{:06}", expected_code);
+            let expected_code = totp_custom::<Sha1>(DEFAULT_STEP, TOTP_DIGITS, &decoded_secret, check_time);
 
-            if expected_str == code {
+            if ct_eq_str(&expected_code, code) {
                 return Ok(true);
             }
         }
@@ -67,18 +94,69 @@ impl TwoFAService {
         Ok(false)
     }
 
-    /// Generate QR code for TOTP setup
-    pub fn generate_qr_code(&self, username: &str, secret: &str) -> AuthResult<String> {
-        let totp_url = format!(
+    /// Refuse secrets that are short, undecodable, or made of only a few
+    /// distinct bytes (all-zero, repeated patterns)
+    pub fn check_secret_strength(&self, secret: &str) -> AuthResult<()> {
+        let decoded = general_purpose::STANDARD
+            .decode(secret)
+            .map_err(|_| AuthError::WeakTwoFactorSecret)?;
+
+        if decoded.len() < MIN_SECRET_BYTES {
+            return Err(AuthError::WeakTwoFactorSecret);
+        }
+
+        // Twenty random bytes almost always hold 17 or more distinct values
+        let distinct = decoded.iter().collect::<HashSet<_>>().len();
+        if distinct * 2 < decoded.len() {
+            return Err(AuthError::WeakTwoFactorSecret);
+        }
+        Ok(())
+    }
+
+    /// Keyed hash of a secret, comparable across accounts without exposing it
+    pub fn fingerprint_secret(&self, secret: &str) -> AuthResult<String> {
+        let decoded = general_purpose::STANDARD
+            .decode(secret)
+            .map_err(|_| AuthError::InvalidToken)?;
+        self.keyed_digest(&decoded)
+    }
+
+    /// Hex HMAC-SHA256 of `data` under the fingerprint key
+    fn keyed_digest(&self, data: &[u8]) -> AuthResult<String> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.fingerprint_key)
+            .map_err(|e| AuthError::InternalError(e.to_string()))?;
+        mac.update(data);
+        Ok(mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+
+    /// Whether a stored secret can still generate codes
+    pub fn is_usable_secret(&self, secret: &str) -> bool {
+        general_purpose::STANDARD.decode(secret).is_ok_and(|decoded| !decoded.is_empty())
+    }
+
+    /// Key URI an authenticator app imports, as encoded in the enrollment QR code
+    pub fn otpauth_url(&self, username: &str, secret: &str) -> String {
+        format!(
             "otpauth://totp/{}:{}?secret={}&issuer={}",
             self.issuer,
             username,
             secret,
             self.issuer
-        );
+        )
+    }
 
+    /// Generate QR code for TOTP setup
+    pub fn generate_qr_code(&self, username: &str, secret: &str) -> AuthResult<String> {
+        let totp_url = self.otpauth_url(username, secret);
+
+        let rendering = phase_timings::phase(Phase::Rendering);
         let qr_code = QrCode::new(&totp_url)
-            .map_err(|_| AuthError::InternalError("Failed to generate QR code".to_string()))?;
+            .map_err(AuthError::QrCode)?;
 
         // Create a larger QR code image (300x300 pixels)
         let image = qr_code.render::<Luma<u8>>().build();
@@ -102,10 +180,13 @@ impl TwoFAService {
             }
         }
 
+        drop(rendering);
+
         // Convert to PNG bytes
+        let _encoding = phase_timings::phase(Phase::Encoding);
         let mut png_bytes = Vec::new();
         scaled_image.write_to(&mut std::io::Cursor::new(&mut png_bytes), image::ImageOutputFormat::Png)
-            .map_err(|_| AuthError::InternalError("Failed to encode QR code as PNG".to_string()))?;
+            .map_err(AuthError::ImageEncoding)?;
 
         // Convert PNG to base64
         let png_base64 = general_purpose::STANDARD.encode(&png_bytes);
@@ -126,55 +207,89 @@ impl TwoFAService {
             .collect()
     }
 
-    /// Verify backup code
+    /// Verify backup code. Every stored code is compared, in constant time, so
+    /// the time taken says nothing about which code or how much of it matched.
     pub fn verify_backup_code(&self, backup_codes_json: &str, provided_code: &str) -> AuthResult<(bool, String)> {
         let mut backup_codes: Vec<String> = serde_json::from_str(backup_codes_json)
-            .map_err(|_| AuthError::InternalError("Invalid backup codes format".to_string()))?;
+            .map_err(AuthError::Serialization)?;
 
-        if let Some(index) = backup_codes.iter().position(|code| code == provided_code) {
+        let mut matched = None;
+        for (index, code) in backup_codes.iter().enumerate() {
+            if ct_eq_str(code, provided_code) && matched.is_none() {
+                matched = Some(index);
+            }
+        }
+
+        if let Some(index) = matched {
             // Remove the used backup code
             backup_codes.remove(index);
             let updated_json = serde_json::to_string(&backup_codes)
-                .map_err(|_| AuthError::InternalError("Failed to serialize backup codes".to_string()))?;
+                .map_err(AuthError::Serialization)?;
             Ok((true, updated_json))
         } else {
             Ok((false, backup_codes_json.to_string()))
         }
     }
 
+    /// Backup codes not yet used
+    pub fn backup_codes_remaining(&self, backup_codes_json: &str) -> AuthResult<usize> {
+        let backup_codes: Vec<String> = serde_json::from_str(backup_codes_json)
+            .map_err(AuthError::Serialization)?;
+        Ok(backup_codes.len())
+    }
+
     /// Generate temporary token for 2FA completion
     pub fn generate_temp_token(&self) -> String {
-        format!("2fa_temp_{}", Uuid::new_v4())
+        format!("{}{}", TEMP_TOKEN_PREFIX, Uuid::new_v4())
     }
 
-    /// Validate temporary token format
+    /// Validate temporary token format. Only the public prefix and the shape
+    /// of the UUID are checked; matching a token against issued ones goes
+    /// through `temp_token_digest`.
     pub fn validate_temp_token(&self, token: &str) -> bool {
-        token.starts_with("2fa_temp_") && token.len() == 45 // "2fa_temp_" + 36 chars UUID
+        token
+            .strip_prefix(TEMP_TOKEN_PREFIX)
+            .is_some_and(|id| id.len() == 36 && Uuid::parse_str(id).is_ok())
+    }
+
+    /// Key a temp token is stored and looked up under. Lookups by this HMAC
+    /// rather than the raw token mean the store's index comparisons can't
+    /// reveal how much of a guessed token matches an issued one.
+    pub fn temp_token_digest(&self, token: &str) -> AuthResult<String> {
+        self.keyed_digest(token.as_bytes())
+    }
+
+    /// Six-digit code emailed as the second factor of an account without 2FA
+    pub fn generate_email_code(&self) -> String {
+        format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+    }
+
+    /// Digest an emailed code is stored and compared under
+    pub fn email_code_digest(&self, code: &str) -> AuthResult<String> {
+        self.keyed_digest(format!("email-code:{}", code).as_bytes())
     }
 
     /// Hash backup codes for secure storage
     pub fn hash_backup_codes(&self, codes: &[String]) -> AuthResult<String> {
         let codes_json = serde_json::to_string(codes)
-            .map_err(|_| AuthError::InternalError("Failed to serialize backup codes".to_string()))?;
+            .map_err(AuthError::Serialization)?;
         
         // In a real implementation, you might want to hash individual codes
         // For simplicity, we'll store them as JSON (they should be treated as one-time use)
         Ok(codes_json)
     }
-    /// Get the issuer name
-    #[allow(dead_code)]
-    pub fn get_issuer(&self) -> &str {
-        &self.issuer
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::MockClock;
+    use crate::utils::clock::SystemClock;
+    use chrono::Duration;
 
     #[test]
     fn test_generate_secret() {
-        let service = TwoFAService::new("TestApp".to_string());
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
         let secret = service.generate_secret();
         
         assert!(!secret.is_empty());
@@ -183,7 +298,7 @@ mod tests {
 
     #[test]
     fn test_totp_generation_and_verification() {
-        let service = TwoFAService::new("TestApp".to_string());
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
         let secret = service.generate_secret();
         
         let code = service.generate_totp(&secret, None).unwrap();
@@ -193,9 +308,54 @@ mod tests {
         assert!(is_valid);
     }
 
+    #[test]
+    fn test_totp_accepts_one_window_of_drift() {
+        let clock = Arc::new(MockClock::new());
+        let service = TwoFAService::new("TestApp".to_string(), clock.clone());
+        let secret = service.generate_secret();
+        let code = service.generate_totp(&secret, None).unwrap();
+
+        clock.advance(Duration::seconds(30));
+        assert!(service.verify_totp(&secret, &code).unwrap());
+
+        clock.advance(Duration::seconds(60));
+        assert!(!service.verify_totp(&secret, &code).unwrap());
+    }
+
+    #[test]
+    fn test_secret_strength() {
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
+
+        assert!(service.check_secret_strength(&service.generate_secret()).is_ok());
+
+        let short = general_purpose::STANDARD.encode([7u8, 42, 99, 1, 200, 13, 54, 88, 3, 17]);
+        assert!(matches!(service.check_secret_strength(&short), Err(AuthError::WeakTwoFactorSecret)));
+
+        let zeros = general_purpose::STANDARD.encode([0u8; 20]);
+        assert!(matches!(service.check_secret_strength(&zeros), Err(AuthError::WeakTwoFactorSecret)));
+
+        let pattern = general_purpose::STANDARD.encode([1u8, 2, 3, 4].repeat(8));
+        assert!(matches!(service.check_secret_strength(&pattern), Err(AuthError::WeakTwoFactorSecret)));
+
+        assert!(matches!(service.check_secret_strength("not base64!"), Err(AuthError::WeakTwoFactorSecret)));
+    }
+
+    #[test]
+    fn test_fingerprint_depends_on_secret_and_key() {
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock)).with_fingerprint_key(b"key-one");
+        let other_key = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock)).with_fingerprint_key(b"key-two");
+        let secret = service.generate_secret();
+
+        let fingerprint = service.fingerprint_secret(&secret).unwrap();
+        assert_eq!(fingerprint.len(), 64);
+        assert_eq!(fingerprint, service.fingerprint_secret(&secret).unwrap());
+        assert_ne!(fingerprint, service.fingerprint_secret(&service.generate_secret()).unwrap());
+        assert_ne!(fingerprint, other_key.fingerprint_secret(&secret).unwrap());
+    }
+
     #[test]
     fn test_backup_codes() {
-        let service = TwoFAService::new("TestApp".to_string());
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
         let codes = service.generate_backup_codes(10);
         
         assert_eq!(codes.len(), 10);
@@ -206,12 +366,46 @@ mod tests {
         assert!(is_valid);
     }
 
+    #[test]
+    fn test_backup_code_is_used_up_and_near_misses_fail() {
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
+        let codes = vec!["AAAA1111".to_string(), "BBBB2222".to_string(), "CCCC3333".to_string()];
+        let codes_json = service.hash_backup_codes(&codes).unwrap();
+
+        for near_miss in ["BBBB222", "BBBB22222", "BBBB2223", "bbbb2222", ""] {
+            let (is_valid, unchanged) = service.verify_backup_code(&codes_json, near_miss).unwrap();
+            assert!(!is_valid, "{near_miss:?} matched");
+            assert_eq!(unchanged, codes_json);
+        }
+
+        let (is_valid, remaining) = service.verify_backup_code(&codes_json, "BBBB2222").unwrap();
+        assert!(is_valid);
+        assert_eq!(remaining, r#"["AAAA1111","CCCC3333"]"#);
+        assert!(!service.verify_backup_code(&remaining, "BBBB2222").unwrap().0);
+    }
+
     #[test]
     fn test_temp_token() {
-        let service = TwoFAService::new("TestApp".to_string());
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
         let token = service.generate_temp_token();
         
         assert!(service.validate_temp_token(&token));
         assert!(!service.validate_temp_token("invalid_token"));
+        assert!(!service.validate_temp_token(&format!("2fa_temp_{}", "x".repeat(36))));
+        assert!(!service.validate_temp_token(&token[..token.len() - 1]));
+    }
+
+    #[test]
+    fn test_temp_token_digest_is_keyed() {
+        let service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock)).with_fingerprint_key(b"key-one");
+        let other_key = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock)).with_fingerprint_key(b"key-two");
+        let token = service.generate_temp_token();
+
+        let digest = service.temp_token_digest(&token).unwrap();
+        assert_eq!(digest.len(), 64);
+        assert!(!digest.contains(token.trim_start_matches(TEMP_TOKEN_PREFIX)));
+        assert_eq!(digest, service.temp_token_digest(&token).unwrap());
+        assert_ne!(digest, service.temp_token_digest(&service.generate_temp_token()).unwrap());
+        assert_ne!(digest, other_key.temp_token_digest(&token).unwrap());
     }
 }
@@ -0,0 +1,298 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::audit_event::AuditEventType;
+use crate::models::auth::Severity;
+use crate::utils::clock::Clock;
+
+/// How far back a user's alert feed looks
+pub const ALERT_WINDOW_DAYS: i64 = 30;
+
+/// Most events one feed is built from, newest first
+pub const MAX_ALERT_EVENTS: i64 = 200;
+
+/// What a user is told about, by the warning and critical events it comes from
+const ALERT_EVENTS: &[(AuditEventType, AlertKind)] = &[
+    (AuditEventType::LoginAttempt, AlertKind::FailedSignIn),
+    (AuditEventType::TwoFaAttempt, AlertKind::FailedSignIn),
+    (AuditEventType::LoginFromUnexpectedCountry, AlertKind::UnusualSignIn),
+    (AuditEventType::ImpossibleTravel, AlertKind::UnusualSignIn),
+    (AuditEventType::PasswordResetCompleted, AlertKind::PasswordReset),
+    (AuditEventType::TwoFaDisabled, AlertKind::TwoFactorChanged),
+    (AuditEventType::TwoFaQrRedisplayed, AlertKind::TwoFactorChanged),
+    (AuditEventType::AccountLockout, AlertKind::AccountLocked),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    FailedSignIn,
+    /// From a country the account doesn't sign in from, or too far from the last sign-in
+    UnusualSignIn,
+    PasswordReset,
+    /// 2FA turned off, or its QR code shown again
+    TwoFactorChanged,
+    AccountLocked,
+}
+
+impl AlertKind {
+    fn of(event_type: AuditEventType) -> Option<Self> {
+        ALERT_EVENTS.iter().find(|(alerting, _)| *alerting == event_type).map(|(_, kind)| *kind)
+    }
+
+    /// What the banner says about `count` events on `day`
+    fn message(self, count: usize, day: NaiveDate) -> String {
+        let plural = if count == 1 { "" } else { "s" };
+        let times = if count == 1 { String::new() } else { format!(" {} times", count) };
+        match self {
+            AlertKind::FailedSignIn => format!("{} failed sign-in attempt{} on your account on {}", count, plural, day),
+            AlertKind::UnusualSignIn => format!("{} sign-in{} from an unusual location on {}", count, plural, day),
+            AlertKind::PasswordReset => format!("Your password was reset{} on {}", times, day),
+            AlertKind::TwoFactorChanged => format!("Your two-factor settings changed{} on {}", times, day),
+            AlertKind::AccountLocked => format!("Your account was locked{} on {}", times, day),
+        }
+    }
+}
+
+/// Events of one kind on one day, shown to the account owner as one banner
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserAlert {
+    /// The newest event; `POST /api/auth/my-alerts/{id}/dismiss` takes it, or any other in the alert
+    pub id: Uuid,
+    pub kind: AlertKind,
+    /// The most severe of the events
+    pub severity: Severity,
+    pub count: usize,
+    pub first_at: DateTime<Utc>,
+    pub last_at: DateTime<Utc>,
+    pub message: String,
+    pub dismissed: bool,
+}
+
+#[derive(Debug, FromRow)]
+struct AlertEvent {
+    id: Uuid,
+    event_type: AuditEventType,
+    severity: Severity,
+    timestamp: DateTime<Utc>,
+    dismissed: bool,
+}
+
+/// Each user's own view of the warning and critical events about their
+/// account, and which of them they have dismissed
+pub struct UserAlertService {
+    db_pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+}
+
+impl UserAlertService {
+    pub fn new(db_pool: SqlitePool, clock: Arc<dyn Clock>) -> Self {
+        Self { db_pool, clock }
+    }
+
+    /// The user's alerts from the last `ALERT_WINDOW_DAYS`, newest first
+    pub async fn feed(&self, user_id: Uuid) -> Result<Vec<UserAlert>, sqlx::Error> {
+        Ok(group(self.events(user_id).await?))
+    }
+
+    /// Alerts the user hasn't dismissed, as the login response and security checkup count them
+    pub async fn undismissed_count(&self, user_id: Uuid) -> Result<usize, sqlx::Error> {
+        Ok(self.feed(user_id).await?.iter().filter(|alert| !alert.dismissed).count())
+    }
+
+    /// Dismiss the alert holding event `event_id`, with every other event in
+    /// it. `None` when no alert in the user's feed holds the event, which is
+    /// also the answer for another user's events.
+    pub async fn dismiss(&self, user_id: Uuid, event_id: Uuid) -> Result<Option<UserAlert>, sqlx::Error> {
+        let events = self.events(user_id).await?;
+        let Some(target) = events.iter().find(|event| event.id == event_id) else {
+            return Ok(None);
+        };
+        let (kind, day) = (AlertKind::of(target.event_type), target.timestamp.date_naive());
+
+        let now = self.clock.now();
+        let mut tx = self.db_pool.begin().await?;
+        for event in events.iter().filter(|e| AlertKind::of(e.event_type) == kind && e.timestamp.date_naive() == day) {
+            sqlx::query(
+                "INSERT INTO user_alert_dismissals (user_id, event_id, dismissed_at) VALUES (?, ?, ?)
+                 ON CONFLICT(user_id, event_id) DO NOTHING",
+            )
+            .bind(user_id)
+            .bind(event.id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(self
+            .feed(user_id)
+            .await?
+            .into_iter()
+            .find(|alert| alert.dismissed && Some(alert.kind) == kind && alert.last_at.date_naive() == day))
+    }
+
+    /// The user's own alerting events in the window, newest first and at most `MAX_ALERT_EVENTS`
+    async fn events(&self, user_id: Uuid) -> Result<Vec<AlertEvent>, sqlx::Error> {
+        let placeholders = vec!["?"; ALERT_EVENTS.len()].join(", ");
+        let sql = format!(
+            "SELECT e.id, e.event_type, e.severity, e.timestamp, d.event_id IS NOT NULL AS dismissed
+             FROM security_events e
+             LEFT JOIN user_alert_dismissals d ON d.user_id = e.user_id AND d.event_id = e.id
+             WHERE e.user_id = ? AND e.timestamp > ? AND e.severity != 'info' AND e.event_type IN ({})
+             ORDER BY e.timestamp DESC
+             LIMIT ?",
+            placeholders
+        );
+        let mut query = sqlx::query_as::<_, AlertEvent>(&sql)
+            .bind(user_id)
+            .bind(self.clock.now() - Duration::days(ALERT_WINDOW_DAYS));
+        for (event_type, _) in ALERT_EVENTS {
+            query = query.bind(*event_type);
+        }
+        query.bind(MAX_ALERT_EVENTS).fetch_all(&self.db_pool).await
+    }
+}
+
+/// Fold events, newest first, into one alert per kind, day and dismissal state
+fn group(events: Vec<AlertEvent>) -> Vec<UserAlert> {
+    let mut alerts: Vec<UserAlert> = Vec::new();
+    for event in events {
+        let Some(kind) = AlertKind::of(event.event_type) else {
+            continue;
+        };
+        let day = event.timestamp.date_naive();
+        match alerts
+            .iter_mut()
+            .find(|alert| alert.kind == kind && alert.dismissed == event.dismissed && alert.last_at.date_naive() == day)
+        {
+            Some(alert) => {
+                alert.count += 1;
+                alert.first_at = event.timestamp;
+                alert.severity = alert.severity.max(event.severity);
+                alert.message = kind.message(alert.count, day);
+            }
+            None => alerts.push(UserAlert {
+                id: event.id,
+                kind,
+                severity: event.severity,
+                count: 1,
+                first_at: event.timestamp,
+                last_at: event.timestamp,
+                message: kind.message(1, day),
+                dismissed: event.dismissed,
+            }),
+        }
+    }
+    alerts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::context::RequestContext;
+    use crate::services::audit_service::AuditService;
+    use crate::services::geoip_service::GeoIpService;
+    use crate::models::user::UserRole;
+    use crate::test_support::{insert_user, MockClock, TEST_PASSWORD};
+    use crate::utils::database::test_pool;
+
+    #[actix_web::test]
+    async fn test_alerts_are_derived_from_the_users_own_recent_events() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let audit = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), clock.clone());
+        let alerts = UserAlertService::new(pool.clone(), clock.clone());
+        let owner = insert_user(&pool, clock.clone(), "alert_owner", UserRole::KenyaGovernment, TEST_PASSWORD, false)
+            .await
+            .id;
+        let neighbour = insert_user(
+            &pool,
+            clock.clone(),
+            "alert_neighbour",
+            UserRole::KenyaGovernment,
+            TEST_PASSWORD,
+            false,
+        )
+        .await
+        .id;
+        let ctx = RequestContext::new("41.90.12.7", None);
+
+        // Outside the window by the time the feed is read
+        audit.log_login_attempt(&ctx, Some(owner), "alert_owner", false, Some("Invalid password")).await.unwrap();
+        clock.advance(Duration::days(ALERT_WINDOW_DAYS + 1));
+        for _ in 0..3 {
+            audit.log_login_attempt(&ctx, Some(owner), "alert_owner", false, Some("Invalid password")).await.unwrap();
+        }
+        clock.advance(Duration::seconds(1));
+        audit.log_account_lockout(&ctx, owner, "alert_owner", 3).await.unwrap();
+        // Successful sign-ins and other accounts' failures are no alerts of the owner's
+        audit.log_login_attempt(&ctx, Some(owner), "alert_owner", true, None).await.unwrap();
+        audit.log_login_attempt(&ctx, Some(neighbour), "alert_neighbour", false, None).await.unwrap();
+        clock.advance(Duration::days(1));
+        audit.log_two_fa_disabled(&ctx, owner, "alert_owner", "totp").await.unwrap();
+
+        let feed = alerts.feed(owner).await.unwrap();
+        let summary: Vec<_> =
+            feed.iter().map(|alert| (alert.kind, alert.count, alert.severity, alert.dismissed)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (AlertKind::TwoFactorChanged, 1, Severity::Critical, false),
+                (AlertKind::AccountLocked, 1, Severity::Critical, false),
+                (AlertKind::FailedSignIn, 3, Severity::Warning, false),
+            ]
+        );
+        assert!(feed[2].message.starts_with("3 failed sign-in attempts on your account on "), "{}", feed[2].message);
+        assert_eq!(alerts.undismissed_count(owner).await.unwrap(), 3);
+        assert_eq!(alerts.undismissed_count(neighbour).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_dismissals_persist_and_stay_with_their_owner() {
+        let pool = test_pool().await;
+        let clock = Arc::new(MockClock::new());
+        let audit = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), clock.clone());
+        let owner = insert_user(&pool, clock.clone(), "dismiss_owner", UserRole::KenyaGovernment, TEST_PASSWORD, false)
+            .await
+            .id;
+        let neighbour = insert_user(
+            &pool,
+            clock.clone(),
+            "dismiss_neighbour",
+            UserRole::KenyaGovernment,
+            TEST_PASSWORD,
+            false,
+        )
+        .await
+        .id;
+        let ctx = RequestContext::new("41.90.12.7", None);
+        for _ in 0..2 {
+            audit.log_login_attempt(&ctx, Some(owner), "dismiss_owner", false, Some("Invalid password")).await.unwrap();
+        }
+        let alerts = UserAlertService::new(pool.clone(), clock.clone());
+        let alert = alerts.feed(owner).await.unwrap().remove(0);
+
+        // Nobody else can dismiss the owner's alert
+        assert_eq!(alerts.dismiss(neighbour, alert.id).await.unwrap(), None);
+        assert_eq!(alerts.dismiss(owner, Uuid::new_v4()).await.unwrap(), None);
+
+        let dismissed = alerts.dismiss(owner, alert.id).await.unwrap().unwrap();
+        assert_eq!((dismissed.count, dismissed.dismissed), (2, true));
+        // Again is no different
+        assert_eq!(alerts.dismiss(owner, alert.id).await.unwrap(), Some(dismissed));
+
+        // A failure after the dismissal is a fresh alert, and the dismissal
+        // is kept for a service started later
+        clock.advance(Duration::seconds(1));
+        audit.log_login_attempt(&ctx, Some(owner), "dismiss_owner", false, Some("Invalid password")).await.unwrap();
+        let restarted = UserAlertService::new(pool.clone(), clock.clone());
+        let feed = restarted.feed(owner).await.unwrap();
+        let states: Vec<_> = feed.iter().map(|alert| (alert.count, alert.dismissed)).collect();
+        assert_eq!(states, vec![(1, false), (2, true)]);
+        assert_eq!(restarted.undismissed_count(owner).await.unwrap(), 1);
+    }
+}
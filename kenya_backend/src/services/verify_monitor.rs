@@ -0,0 +1,179 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::models::auth::AuthError;
+use crate::models::context::client_network;
+use crate::services::shared_state::{InProcessBackend, SharedStateBackend};
+
+/// Audit one in this many successful token verifications
+pub const DEFAULT_SUCCESS_SAMPLE_RATE: u64 = 100;
+
+/// Failed verifications from one IP within the window that count as token guessing
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 20;
+
+const DEFAULT_FAILURE_WINDOW: Duration = Duration::from_secs(300);
+
+const FAILURES_KEY_PREFIX: &str = "verify_failures:";
+
+/// Result of one token verification, as counted in the metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    Valid,
+    Expired,
+    Invalid,
+    SessionExpired,
+    AccountDisabled,
+    Error,
+}
+
+impl VerifyOutcome {
+    pub fn from_error(error: &AuthError) -> Self {
+        match error {
+            AuthError::TokenExpired => VerifyOutcome::Expired,
+            AuthError::InvalidToken | AuthError::Unauthorized => VerifyOutcome::Invalid,
+            AuthError::SessionExpired => VerifyOutcome::SessionExpired,
+            AuthError::AccountDisabled => VerifyOutcome::AccountDisabled,
+            _ => VerifyOutcome::Error,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VerifyOutcome::Valid => "valid",
+            VerifyOutcome::Expired => "expired",
+            VerifyOutcome::Invalid => "invalid",
+            VerifyOutcome::SessionExpired => "session_expired",
+            VerifyOutcome::AccountDisabled => "account_disabled",
+            VerifyOutcome::Error => "error",
+        }
+    }
+}
+
+/// Token verification counts per outcome since the process started
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct VerifyCounts {
+    pub valid: u64,
+    pub expired: u64,
+    pub invalid: u64,
+    pub session_expired: u64,
+    pub account_disabled: u64,
+    pub error: u64,
+}
+
+/// Bookkeeping for the token verification endpoint: outcome counters,
+/// success sampling for the audit log, and per-IP failure tracking.
+///
+/// The outcome counters are this instance's own; the per-IP failures live in
+/// the shared state backend, so guessing spread across instances still adds up.
+pub struct VerifyMonitor {
+    success_sample_rate: u64,
+    failure_threshold: u32,
+    failure_window: Duration,
+    counters: [AtomicU64; 6],
+    failures_by_ip: Arc<dyn SharedStateBackend>,
+}
+
+impl Default for VerifyMonitor {
+    fn default() -> Self {
+        Self::new(DEFAULT_SUCCESS_SAMPLE_RATE, DEFAULT_FAILURE_THRESHOLD, DEFAULT_FAILURE_WINDOW)
+    }
+}
+
+impl VerifyMonitor {
+    pub fn new(success_sample_rate: u64, failure_threshold: u32, failure_window: Duration) -> Self {
+        Self {
+            success_sample_rate: success_sample_rate.max(1),
+            failure_threshold: failure_threshold.max(1),
+            failure_window,
+            counters: Default::default(),
+            failures_by_ip: Arc::new(InProcessBackend::default()),
+        }
+    }
+
+    /// Track per-IP failures in `backend` instead of this process's memory
+    pub fn with_backend(mut self, backend: Arc<dyn SharedStateBackend>) -> Self {
+        self.failures_by_ip = backend;
+        self
+    }
+
+    /// Count an outcome. Returns whether it should be written to the audit log:
+    /// every failure, and one in `success_sample_rate` successes.
+    pub fn record(&self, outcome: VerifyOutcome) -> bool {
+        let previous = self.counters[outcome as usize].fetch_add(1, Ordering::Relaxed);
+        outcome != VerifyOutcome::Valid || previous.is_multiple_of(self.success_sample_rate)
+    }
+
+    /// Note a failed verification from `ip_address`, an IPv6 client counted by
+    /// its /64. Returns `true` exactly once per window, when that client's
+    /// failures reach the threshold.
+    pub async fn record_failure_from(&self, ip_address: &str) -> bool {
+        let key = format!("{}{}", FAILURES_KEY_PREFIX, client_network(ip_address));
+        match self.failures_by_ip.increment(&key, self.failure_window).await {
+            Ok(count) => count == u64::from(self.failure_threshold),
+            Err(e) => {
+                log::error!("Failed to count a token verification failure: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Addresses with failures being tracked
+    pub async fn tracked_ips(&self) -> usize {
+        self.failures_by_ip.count_keys(FAILURES_KEY_PREFIX).await.unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> VerifyCounts {
+        let count = |outcome: VerifyOutcome| self.counters[outcome as usize].load(Ordering::Relaxed);
+        VerifyCounts {
+            valid: count(VerifyOutcome::Valid),
+            expired: count(VerifyOutcome::Expired),
+            invalid: count(VerifyOutcome::Invalid),
+            session_expired: count(VerifyOutcome::SessionExpired),
+            account_disabled: count(VerifyOutcome::AccountDisabled),
+            error: count(VerifyOutcome::Error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_successes_are_sampled_and_failures_always_logged() {
+        let monitor = VerifyMonitor::new(10, DEFAULT_FAILURE_THRESHOLD, DEFAULT_FAILURE_WINDOW);
+
+        let logged = (0..25).filter(|_| monitor.record(VerifyOutcome::Valid)).count();
+        assert_eq!(logged, 3); // the 1st, 11th and 21st
+
+        assert!((0..5).all(|_| monitor.record(VerifyOutcome::Invalid)));
+        assert!(monitor.record(VerifyOutcome::Expired));
+
+        let counts = monitor.counts();
+        assert_eq!(counts.valid, 25);
+        assert_eq!(counts.invalid, 5);
+        assert_eq!(counts.expired, 1);
+        assert_eq!(counts.session_expired, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_failure_threshold_fires_once_per_ip() {
+        let monitor = VerifyMonitor::new(1, 3, DEFAULT_FAILURE_WINDOW);
+
+        assert!(!monitor.record_failure_from("10.0.0.7").await);
+        assert!(!monitor.record_failure_from("10.0.0.7").await);
+        assert!(!monitor.record_failure_from("10.0.0.8").await);
+        assert!(monitor.record_failure_from("10.0.0.7").await);
+        assert!(!monitor.record_failure_from("10.0.0.7").await);
+    }
+
+    #[actix_web::test]
+    async fn test_failure_window_expires() {
+        let monitor = VerifyMonitor::new(1, 2, Duration::ZERO);
+
+        assert!(!monitor.record_failure_from("10.0.0.7").await);
+        assert!(!monitor.record_failure_from("10.0.0.7").await);
+    }
+}
@@ -0,0 +1,803 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::services::tracer::{self, ActiveTrace, Attribute, SpanKind, TRACEPARENT_HEADER};
+use crate::services::webhook_signer::{WebhookSigner, DELIVERY_ID_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::utils::clock::Clock;
+
+/// Destination that receives every warning and critical security event
+pub const SIEM_DESTINATION: &str = "siem";
+
+/// Attempts per delivery, including the first, unless configured otherwise
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry unless configured otherwise; doubles per attempt
+pub const DEFAULT_RETRY_BASE_MS: u64 = 1_000;
+
+/// Longest wait between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// How long one attempt may take before it counts as failed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Names the kind of notification, alongside the signature headers
+const EVENT_HEADER: &str = "X-FSFVI-Event";
+
+/// Notifications one destination holds in memory while its receiver is slow
+/// or down. Past this the oldest informational one is dropped.
+pub const MAX_QUEUED_NOTIFICATIONS: usize = 256;
+
+/// How much a notification matters, which decides what a full queue gives up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    /// Dropped, oldest first, to make room in a full queue
+    Informational,
+    /// Never dropped. When the queue is full of other critical notifications
+    /// it is written to `pending_notifications` instead, and queued again
+    /// once there is room.
+    Critical,
+}
+
+/// A receiver of outbound notifications and the secret its requests are signed with
+#[derive(Debug, Clone)]
+pub struct WebhookDestination {
+    pub name: String,
+    pub url: String,
+    pub secret: String,
+}
+
+/// How often, and how patiently, a delivery is retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_RETRY_BASE_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before attempt `attempt + 1`: exponential, with the upper half
+    /// jittered so receivers recovering from an outage aren't hit in lockstep
+    fn delay_after(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(MAX_RETRY_DELAY);
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// How a delivery ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    Delivered { delivery_id: Uuid, attempts: u32 },
+    /// Given up on and recorded in the dead-letter table
+    DeadLettered { delivery_id: Uuid, attempts: u32 },
+}
+
+/// A delivery that failed permanently, kept for administrators to inspect
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct DeadLetter {
+    pub delivery_id: String,
+    pub destination: String,
+    pub event_type: String,
+    pub url: String,
+    /// The signed body, as sent
+    pub payload: String,
+    pub attempts: i64,
+    /// HTTP status of the last attempt, absent when no response arrived
+    pub last_status: Option<i64>,
+    pub last_error: String,
+    pub first_attempt_at: DateTime<Utc>,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("No webhook destination named {0}")]
+    UnknownDestination(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Occupancy of the outbound notification queues, for health and metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct NotificationQueueDepth {
+    /// Most notifications any one destination may hold
+    pub capacity: usize,
+    /// Notifications waiting across all destinations
+    pub queued: usize,
+    /// Notifications waiting for the most backed-up destination
+    pub deepest: usize,
+    /// Informational notifications dropped from full queues since startup
+    pub dropped: u64,
+    /// Critical notifications written to `pending_notifications` since startup
+    pub persisted: u64,
+    /// Critical notifications waiting in `pending_notifications` now
+    pub pending: i64,
+}
+
+/// A notification waiting for its destination's worker
+struct Queued {
+    event_type: String,
+    data: serde_json::Value,
+    urgency: Urgency,
+    /// Trace of the request that sent it, which its delivery stays in
+    trace: Option<ActiveTrace>,
+}
+
+/// What a full queue did with a notification
+enum Admission {
+    Queued,
+    /// Queued in place of an older informational one, or itself dropped
+    Dropped,
+    /// Critical, with no room even after dropping; it has to be persisted
+    Refused(Queued),
+}
+
+/// Notifications waiting for one destination, delivered one at a time by
+/// a worker started with the first of them
+#[derive(Default)]
+struct DeliveryQueue {
+    items: Mutex<VecDeque<Queued>>,
+    ready: Notify,
+    worker_started: AtomicBool,
+}
+
+impl DeliveryQueue {
+    fn push(&self, item: Queued) -> Admission {
+        let Ok(mut items) = self.items.lock() else {
+            return Admission::Refused(item);
+        };
+        let mut admission = Admission::Queued;
+        if items.len() >= MAX_QUEUED_NOTIFICATIONS {
+            match items.iter().position(|queued| queued.urgency == Urgency::Informational) {
+                Some(oldest) => {
+                    items.remove(oldest);
+                    admission = Admission::Dropped;
+                }
+                None if item.urgency == Urgency::Informational => return Admission::Dropped,
+                None => return Admission::Refused(item),
+            }
+        }
+        items.push_back(item);
+        drop(items);
+        self.ready.notify_one();
+        admission
+    }
+
+    fn pop(&self) -> Option<Queued> {
+        self.items.lock().ok()?.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.items.lock().map(|items| items.len()).unwrap_or(0)
+    }
+}
+
+/// Why one attempt failed, and whether another might succeed
+struct AttemptFailure {
+    status: Option<u16>,
+    error: String,
+    retryable: bool,
+}
+
+/// Signed delivery of outbound HTTP notifications, with retries and a
+/// dead-letter table for those that never get through. Each destination has
+/// a bounded queue in front of it, so a receiver that is down holds at most
+/// `MAX_QUEUED_NOTIFICATIONS` in memory.
+pub struct WebhookService {
+    client: reqwest::Client,
+    db_pool: SqlitePool,
+    destinations: HashMap<String, WebhookDestination>,
+    queues: HashMap<String, DeliveryQueue>,
+    retry: RetryPolicy,
+    clock: Arc<dyn Clock>,
+    dropped: AtomicU64,
+    persisted: AtomicU64,
+}
+
+/// Where services send security notifications. `WebhookService` delivers
+/// them over HTTP; tests put a recorder in its place so nothing leaves the
+/// process.
+pub trait Notifier: Send + Sync {
+    fn has_destination(&self, name: &str) -> bool;
+
+    /// Send in the background; the outcome is only logged
+    fn notify(self: Arc<Self>, destination: &str, event_type: &str, data: serde_json::Value, urgency: Urgency);
+}
+
+impl Notifier for WebhookService {
+    fn has_destination(&self, name: &str) -> bool {
+        WebhookService::has_destination(self, name)
+    }
+
+    fn notify(self: Arc<Self>, destination: &str, event_type: &str, data: serde_json::Value, urgency: Urgency) {
+        self.send(destination, event_type, data, urgency);
+    }
+}
+
+impl WebhookService {
+    pub fn new(
+        db_pool: SqlitePool,
+        destinations: Vec<WebhookDestination>,
+        retry: RetryPolicy,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            client,
+            db_pool,
+            queues: destinations.iter().map(|d| (d.name.clone(), DeliveryQueue::default())).collect(),
+            destinations: destinations.into_iter().map(|d| (d.name.clone(), d)).collect(),
+            retry: RetryPolicy { max_attempts: retry.max_attempts.max(1), ..retry },
+            clock,
+            dropped: AtomicU64::new(0),
+            persisted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn has_destination(&self, name: &str) -> bool {
+        self.destinations.contains_key(name)
+    }
+
+    /// Queue for delivery in the background; the outcome is only logged.
+    /// The delivery stays in the trace of the request that sent it.
+    pub fn send(self: &Arc<Self>, destination: &str, event_type: &str, data: serde_json::Value, urgency: Urgency) {
+        let Some(queue) = self.queues.get(destination) else {
+            let error = WebhookError::UnknownDestination(destination.to_string());
+            log::error!("Webhook delivery to {} failed: {}", destination, error);
+            return;
+        };
+        let item = Queued { event_type: event_type.to_string(), data, urgency, trace: tracer::current() };
+        match queue.push(item) {
+            Admission::Queued => {}
+            Admission::Dropped => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                log::warn!(
+                    "Webhook queue for {} is full; dropped an informational notification ({} since startup)",
+                    destination,
+                    dropped
+                );
+            }
+            Admission::Refused(item) => {
+                // Written straight away rather than held in memory
+                let service = self.clone();
+                let destination = destination.to_string();
+                tokio::spawn(async move {
+                    if let Err(e) = service.persist_pending(&destination, &item).await {
+                        log::error!(
+                            "CRITICAL: {} notification to {} could be neither queued nor persisted: {}",
+                            item.event_type,
+                            destination,
+                            e
+                        );
+                    }
+                });
+            }
+        }
+        self.start_worker(destination);
+    }
+
+    fn start_worker(self: &Arc<Self>, destination: &str) {
+        let Some(queue) = self.queues.get(destination) else {
+            return;
+        };
+        if !queue.worker_started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(self.clone().drain(destination.to_string()));
+        }
+    }
+
+    /// Deliver one destination's queue in order, for as long as the process runs
+    async fn drain(self: Arc<Self>, destination: String) {
+        let queue = &self.queues[&destination];
+        loop {
+            let Some(Queued { event_type, data, trace, .. }) = queue.pop() else {
+                queue.ready.notified().await;
+                continue;
+            };
+            let delivery = async {
+                match self.deliver(&destination, &event_type, data).await {
+                    Ok(DeliveryOutcome::Delivered { .. }) => {}
+                    Ok(DeliveryOutcome::DeadLettered { delivery_id, attempts }) => log::error!(
+                        "Webhook delivery {} to {} failed after {} attempts; moved to the dead-letter table",
+                        delivery_id,
+                        destination,
+                        attempts
+                    ),
+                    Err(e) => log::error!("Webhook delivery to {} failed: {}", destination, e),
+                }
+            };
+            match trace {
+                Some(trace) => trace.scope(delivery).await,
+                None => delivery.await,
+            }
+        }
+    }
+
+    async fn persist_pending(&self, destination: &str, item: &Queued) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO pending_notifications (id, destination, event_type, data, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(destination)
+        .bind(&item.event_type)
+        .bind(item.data.to_string())
+        .bind(self.clock.now())
+        .execute(&self.db_pool)
+        .await?;
+        let persisted = self.persisted.fetch_add(1, Ordering::Relaxed) + 1;
+        log::warn!(
+            "Webhook queue for {} is full of critical notifications; kept {} for later ({} since startup)",
+            destination,
+            item.event_type,
+            persisted
+        );
+        Ok(())
+    }
+
+    /// Move critical notifications waiting in `pending_notifications` back
+    /// into their queues, oldest first and as far as there is room. Each row
+    /// is deleted once queued, so one is sent twice rather than lost if the
+    /// process stops in between. Returns how many were moved.
+    pub async fn retry_pending(self: &Arc<Self>) -> Result<usize, sqlx::Error> {
+        let mut total = 0;
+        for (destination, queue) in &self.queues {
+            let room = MAX_QUEUED_NOTIFICATIONS.saturating_sub(queue.len());
+            if room == 0 {
+                continue;
+            }
+            let rows: Vec<(String, String, String)> = sqlx::query_as(
+                r#"
+                SELECT id, event_type, data FROM pending_notifications
+                WHERE destination = ?
+                ORDER BY created_at
+                LIMIT ?
+                "#
+            )
+            .bind(destination)
+            .bind(i64::try_from(room).unwrap_or(i64::MAX))
+            .fetch_all(&self.db_pool)
+            .await?;
+            let mut moved = 0;
+            for (id, event_type, data) in rows {
+                let item = Queued {
+                    event_type,
+                    data: serde_json::from_str(&data).unwrap_or(serde_json::Value::Null),
+                    urgency: Urgency::Critical,
+                    trace: None,
+                };
+                match queue.push(item) {
+                    Admission::Queued => {}
+                    Admission::Dropped => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Admission::Refused(_) => break,
+                }
+                sqlx::query("DELETE FROM pending_notifications WHERE id = ?")
+                    .bind(&id)
+                    .execute(&self.db_pool)
+                    .await?;
+                moved += 1;
+            }
+            if moved > 0 {
+                self.start_worker(destination);
+            }
+            total += moved;
+        }
+        Ok(total)
+    }
+
+    /// Current queue occupancy, and what the caps have cost since startup
+    pub async fn queue_depth(&self) -> Result<NotificationQueueDepth, sqlx::Error> {
+        let pending = sqlx::query_scalar("SELECT COUNT(*) FROM pending_notifications")
+            .fetch_one(&self.db_pool)
+            .await?;
+        let depths: Vec<usize> = self.queues.values().map(DeliveryQueue::len).collect();
+        Ok(NotificationQueueDepth {
+            capacity: MAX_QUEUED_NOTIFICATIONS,
+            queued: depths.iter().sum(),
+            deepest: depths.iter().copied().max().unwrap_or(0),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            persisted: self.persisted.load(Ordering::Relaxed),
+            pending,
+        })
+    }
+
+    /// Deliver `data` to a destination, retrying failures that might be
+    /// temporary. The body and delivery ID stay the same across attempts;
+    /// each attempt is signed afresh, so its timestamp stays current.
+    pub async fn deliver(
+        &self,
+        destination: &str,
+        event_type: &str,
+        data: serde_json::Value,
+    ) -> Result<DeliveryOutcome, WebhookError> {
+        let target = self
+            .destinations
+            .get(destination)
+            .ok_or_else(|| WebhookError::UnknownDestination(destination.to_string()))?;
+        let signer = WebhookSigner::new(target.secret.as_bytes());
+        let delivery_id = Uuid::new_v4();
+        let first_attempt_at = self.clock.now();
+        let body = json!({
+            "delivery_id": delivery_id,
+            "event_type": event_type,
+            "created_at": first_attempt_at,
+            "data": data,
+        })
+        .to_string();
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            // Each attempt is a span of its own, named as the receiver's caller
+            let span = tracer::current().map(|trace| {
+                let mut span = trace.child("webhook.deliver", SpanKind::Client);
+                span.set_attribute("webhook.destination", Attribute::Str(target.name.clone()));
+                span.set_attribute("webhook.attempt", Attribute::Int(i64::from(attempts)));
+                span
+            });
+            let traceparent = span.as_ref().map(|span| span.context().header_value());
+            let failure = match self.attempt(target, &signer, delivery_id, event_type, &body, traceparent.as_deref()).await {
+                Ok(()) => return Ok(DeliveryOutcome::Delivered { delivery_id, attempts }),
+                Err(failure) => failure,
+            };
+            if let Some(mut span) = span {
+                span.set_error();
+            }
+            log::warn!(
+                "Webhook delivery {} to {} failed (attempt {} of {}): {}",
+                delivery_id,
+                target.name,
+                attempts,
+                self.retry.max_attempts,
+                failure.error
+            );
+
+            if !failure.retryable || attempts >= self.retry.max_attempts {
+                sqlx::query(
+                    r#"
+                    INSERT INTO webhook_dead_letters (delivery_id, destination, event_type, url, payload, attempts,
+                                                      last_status, last_error, first_attempt_at, failed_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    "#
+                )
+                .bind(delivery_id.to_string())
+                .bind(&target.name)
+                .bind(event_type)
+                .bind(&target.url)
+                .bind(&body)
+                .bind(attempts)
+                .bind(failure.status)
+                .bind(&failure.error)
+                .bind(first_attempt_at)
+                .bind(self.clock.now())
+                .execute(&self.db_pool)
+                .await?;
+                return Ok(DeliveryOutcome::DeadLettered { delivery_id, attempts });
+            }
+            tokio::time::sleep(self.retry.delay_after(attempts)).await;
+        }
+    }
+
+    async fn attempt(
+        &self,
+        target: &WebhookDestination,
+        signer: &WebhookSigner,
+        delivery_id: Uuid,
+        event_type: &str,
+        body: &str,
+        traceparent: Option<&str>,
+    ) -> Result<(), AttemptFailure> {
+        let timestamp = self.clock.now().timestamp();
+        let mut request = self.client.post(&target.url);
+        if let Some(traceparent) = traceparent {
+            request = request.header(TRACEPARENT_HEADER, traceparent);
+        }
+        let response = request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, signer.sign(timestamp, body.as_bytes()))
+            .header(DELIVERY_ID_HEADER, delivery_id.to_string())
+            .header(EVENT_HEADER, event_type)
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| AttemptFailure { status: None, error: e.to_string(), retryable: true })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        Err(AttemptFailure {
+            status: Some(status.as_u16()),
+            error: format!("Receiver answered {}", status),
+            // Other client errors mean the request itself is wrong; sending it again won't help
+            retryable: status.is_server_error()
+                || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                || status == reqwest::StatusCode::REQUEST_TIMEOUT,
+        })
+    }
+
+    /// Permanently failed deliveries, newest first
+    pub async fn dead_letters(&self, limit: i64, offset: i64) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        sqlx::query_as::<_, DeadLetter>(
+            r#"
+            SELECT delivery_id, destination, event_type, url, payload, attempts, last_status, last_error,
+                   first_attempt_at, failed_at
+            FROM webhook_dead_letters
+            ORDER BY failed_at DESC
+            LIMIT ? OFFSET ?
+            "#
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use crate::models::audit_event::AuditEventType;
+    use crate::models::auth::Severity;
+    use crate::models::context::RequestContext;
+    use crate::services::audit_service::AuditService;
+    use crate::services::geoip_service::GeoIpService;
+    use crate::services::webhook_signer::{verify_signature, DEFAULT_TOLERANCE_SECONDS};
+    use crate::utils::clock::SystemClock;
+    use crate::utils::database::test_pool;
+
+    const SECRET: &str = "siem-shared-secret";
+
+    /// What the mock receiver saw: delivery ID, event type and whether the signature checked out
+    type Received = Arc<Mutex<Vec<(String, String, bool)>>>;
+
+    /// Receiver that checks each request's signature, records it and answers
+    /// with the next queued status (200 once the queue is empty)
+    async fn mock_receiver(statuses: Vec<u16>) -> (String, Received) {
+        let received: Received = Arc::default();
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+        let state = (received.clone(), statuses);
+
+        let server = HttpServer::new(move || {
+            let (received, statuses) = state.clone();
+            App::new().default_service(web::to(move |req: HttpRequest, body: web::Bytes| {
+                let (received, statuses) = (received.clone(), statuses.clone());
+                async move {
+                    let header = |name: &str| {
+                        req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string()
+                    };
+                    let verified = verify_signature(
+                        SECRET.as_bytes(),
+                        &header(TIMESTAMP_HEADER),
+                        &header(SIGNATURE_HEADER),
+                        &body,
+                        Utc::now().timestamp(),
+                        DEFAULT_TOLERANCE_SECONDS,
+                    )
+                    .is_ok();
+                    received.lock().unwrap().push((header(DELIVERY_ID_HEADER), header(EVENT_HEADER), verified));
+
+                    let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+                    HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap()).finish()
+                }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/hook", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (url, received)
+    }
+
+    fn service(pool: SqlitePool, url: &str) -> WebhookService {
+        let destination = WebhookDestination {
+            name: SIEM_DESTINATION.to_string(),
+            url: url.to_string(),
+            secret: SECRET.to_string(),
+        };
+        let retry = RetryPolicy { max_attempts: 3, base_delay: Duration::from_millis(10) };
+        WebhookService::new(pool, vec![destination], retry, Arc::new(SystemClock))
+    }
+
+    #[actix_web::test]
+    async fn test_delivery_is_signed_for_the_receiver() {
+        let (url, received) = mock_receiver(vec![]).await;
+        let service = service(test_pool().await, &url);
+
+        let outcome = service.deliver(SIEM_DESTINATION, "security_event", json!({ "severity": "critical" })).await.unwrap();
+        let DeliveryOutcome::Delivered { delivery_id, attempts } = outcome else {
+            panic!("expected delivery, got {:?}", outcome);
+        };
+        assert_eq!(attempts, 1);
+        assert_eq!(*received.lock().unwrap(), vec![(delivery_id.to_string(), "security_event".to_string(), true)]);
+    }
+
+    #[actix_web::test]
+    async fn test_temporary_failures_are_retried_under_one_delivery_id() {
+        let (url, received) = mock_receiver(vec![503, 500]).await;
+        let pool = test_pool().await;
+        let service = service(pool.clone(), &url);
+
+        let outcome = service.deliver(SIEM_DESTINATION, "security_event", json!({})).await.unwrap();
+        assert!(matches!(outcome, DeliveryOutcome::Delivered { attempts: 3, .. }));
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 3);
+        assert!(received.iter().all(|(id, _, verified)| *id == received[0].0 && *verified));
+        assert!(service.dead_letters(10, 0).await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_permanent_failures_are_dead_lettered() {
+        let (url, received) = mock_receiver(vec![503, 503, 503, 400]).await;
+        let pool = test_pool().await;
+        let service = service(pool.clone(), &url);
+
+        // Retries run out
+        let outcome = service.deliver(SIEM_DESTINATION, "security_event", json!({ "n": 1 })).await.unwrap();
+        let DeliveryOutcome::DeadLettered { delivery_id, attempts: 3 } = outcome else {
+            panic!("expected dead letter after 3 attempts, got {:?}", outcome);
+        };
+
+        // A client error isn't retried at all
+        let outcome = service.deliver(SIEM_DESTINATION, "data_notification", json!({ "n": 2 })).await.unwrap();
+        assert!(matches!(outcome, DeliveryOutcome::DeadLettered { attempts: 1, .. }));
+        assert_eq!(received.lock().unwrap().len(), 4);
+
+        let dead = service.dead_letters(10, 0).await.unwrap();
+        assert_eq!(dead.len(), 2);
+        let exhausted = dead.iter().find(|d| d.delivery_id == delivery_id.to_string()).unwrap();
+        assert_eq!(exhausted.attempts, 3);
+        assert_eq!(exhausted.last_status, Some(503));
+        assert_eq!(exhausted.destination, SIEM_DESTINATION);
+        let payload: serde_json::Value = serde_json::from_str(&exhausted.payload).unwrap();
+        assert_eq!(payload["data"]["n"], 1);
+
+        assert!(matches!(
+            service.deliver("nowhere", "security_event", json!({})).await,
+            Err(WebhookError::UnknownDestination(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_warning_and_critical_events_are_forwarded_to_siem() {
+        let (url, received) = mock_receiver(vec![]).await;
+        let pool = test_pool().await;
+        let audit = AuditService::new(pool.clone(), Arc::new(GeoIpService::disabled()), Arc::new(SystemClock))
+            .with_webhooks(Arc::new(service(pool, &url)));
+        let ctx = RequestContext::new("10.0.0.1", None);
+
+        audit.log_security_event(&ctx, None, AuditEventType::Logout, "Routine event", true, Severity::Info, None).await.unwrap();
+        audit.log_security_event(&ctx, None, AuditEventType::ImpossibleTravel, "Suspicious event", false, Severity::Critical, None).await.unwrap();
+
+        // Delivery runs in the background
+        for _ in 0..100 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].1, "security_event");
+        assert!(received[0].2);
+    }
+
+    #[actix_web::test]
+    async fn test_queue_for_a_failing_receiver_is_bounded_and_keeps_critical_notifications() {
+        // The receiver fails, and the retry waits minutes, so the worker stays on the first delivery
+        let (url, received) = mock_receiver(vec![503; 3]).await;
+        let pool = test_pool().await;
+        let destination = WebhookDestination {
+            name: SIEM_DESTINATION.to_string(),
+            url,
+            secret: SECRET.to_string(),
+        };
+        let retry = RetryPolicy { max_attempts: 3, base_delay: Duration::from_secs(600) };
+        let service = Arc::new(WebhookService::new(pool.clone(), vec![destination], retry, Arc::new(SystemClock)));
+        let queue = &service.queues[SIEM_DESTINATION];
+        let queued = || -> Vec<i64> {
+            queue.items.lock().unwrap().iter().map(|item| item.data["n"].as_i64().unwrap()).collect()
+        };
+        let send = |n: usize, urgency: Urgency| {
+            service.send(SIEM_DESTINATION, "security_event", json!({ "n": n }), urgency);
+        };
+
+        send(0, Urgency::Informational);
+        for _ in 0..100 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(queue.len(), 0);
+
+        // Critical notifications push out the oldest informational ones
+        for n in 1..=10 {
+            send(n, Urgency::Informational);
+        }
+        let critical = 1000..1000 + MAX_QUEUED_NOTIFICATIONS;
+        for n in critical.clone().take(MAX_QUEUED_NOTIFICATIONS - 9) {
+            send(n, Urgency::Critical);
+        }
+        assert_eq!(queue.len(), MAX_QUEUED_NOTIFICATIONS);
+        assert_eq!(queued()[..9], [2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        for n in critical.clone().skip(MAX_QUEUED_NOTIFICATIONS - 9) {
+            send(n, Urgency::Critical);
+        }
+
+        // Once only critical ones are left, an informational one is dropped and a critical one persisted
+        send(11, Urgency::Informational);
+        send(2000, Urgency::Critical);
+        assert_eq!(queued(), critical.clone().map(|n| n as i64).collect::<Vec<_>>());
+        let mut depth = service.queue_depth().await.unwrap();
+        for _ in 0..100 {
+            if depth.pending > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            depth = service.queue_depth().await.unwrap();
+        }
+        assert_eq!(
+            depth,
+            NotificationQueueDepth {
+                capacity: MAX_QUEUED_NOTIFICATIONS,
+                queued: MAX_QUEUED_NOTIFICATIONS,
+                deepest: MAX_QUEUED_NOTIFICATIONS,
+                dropped: 11,
+                persisted: 1,
+                pending: 1,
+            }
+        );
+        let (event_type, data): (String, String) =
+            sqlx::query_as("SELECT event_type, data FROM pending_notifications").fetch_one(&pool).await.unwrap();
+        assert_eq!(event_type, "security_event");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&data).unwrap()["n"], 2000);
+
+        // It stays there until the queue has room again
+        assert_eq!(service.retry_pending().await.unwrap(), 0);
+        queue.pop();
+        assert_eq!(service.retry_pending().await.unwrap(), 1);
+        assert_eq!(queued().last(), Some(&2000));
+        assert_eq!(service.queue_depth().await.unwrap().pending, 0);
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_with_jitter_and_is_capped() {
+        let policy = RetryPolicy { max_attempts: 10, base_delay: Duration::from_secs(1) };
+        for (attempt, ceiling) in [(1, 1), (2, 2), (3, 4), (4, 8)] {
+            let delay = policy.delay_after(attempt);
+            assert!(delay >= Duration::from_millis(ceiling * 500) && delay <= Duration::from_secs(ceiling));
+        }
+        assert!(policy.delay_after(30) <= MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,151 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Unix time, in seconds, at which the request was signed
+pub const TIMESTAMP_HEADER: &str = "X-FSFVI-Timestamp";
+
+/// `sha256=<hex>` HMAC of `timestamp.body`
+pub const SIGNATURE_HEADER: &str = "X-FSFVI-Signature";
+
+/// Unique per delivery and unchanged across its retries, so receivers can drop duplicates
+pub const DELIVERY_ID_HEADER: &str = "X-FSFVI-Delivery";
+
+/// Signatures older or newer than this are refused as replays
+#[cfg_attr(not(test), allow(dead_code))] // Only `verify_signature` and its tests use it
+pub const DEFAULT_TOLERANCE_SECONDS: i64 = 300;
+
+/// Why a received webhook was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// Timestamp or signature header missing its expected format
+    Malformed,
+    /// Signed too long ago, or too far in the future, to be a fresh delivery
+    OutsideTolerance,
+    /// Signed with another secret, or the body or timestamp was altered
+    Mismatch,
+}
+
+/// Signs outbound HTTP notifications with one destination's shared secret
+pub struct WebhookSigner {
+    secret: Vec<u8>,
+}
+
+impl WebhookSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self { secret: secret.to_vec() }
+    }
+
+    /// `X-FSFVI-Signature` value for `body` sent at `timestamp`
+    pub fn sign(&self, timestamp: i64, body: &[u8]) -> String {
+        let mac = signature_mac(&self.secret, &timestamp.to_string(), body);
+        let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256={}", hex)
+    }
+}
+
+fn signature_mac(secret: &[u8], timestamp: &str, body: &[u8]) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC key of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Check a received webhook: the header values as sent, the raw body, and
+/// the receiver's clock as Unix seconds. Refuses timestamps more than
+/// `tolerance_seconds` from `now`, so a captured request can't be replayed
+/// later. Receivers may copy this function as it stands.
+///
+/// The server only signs, so nothing but the unit tests calls this, and
+/// the example isn't compiled: doctests don't run in a binary crate.
+///
+/// ```rust,ignore
+/// let timestamp = headers["X-FSFVI-Timestamp"].to_str()?;
+/// let signature = headers["X-FSFVI-Signature"].to_str()?;
+/// verify_signature(secret, timestamp, signature, &body, Utc::now().timestamp(), DEFAULT_TOLERANCE_SECONDS)?;
+/// // Then drop the request if its X-FSFVI-Delivery id was seen before
+/// ```
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn verify_signature(
+    secret: &[u8],
+    timestamp: &str,
+    signature: &str,
+    body: &[u8],
+    now: i64,
+    tolerance_seconds: i64,
+) -> Result<(), SignatureError> {
+    let signed_at: i64 = timestamp.parse().map_err(|_| SignatureError::Malformed)?;
+    let hex = signature.strip_prefix("sha256=").ok_or(SignatureError::Malformed)?;
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(SignatureError::Malformed);
+    }
+    let expected = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| SignatureError::Malformed)?;
+
+    if (now - signed_at).abs() > tolerance_seconds {
+        return Err(SignatureError::OutsideTolerance);
+    }
+    // Constant-time comparison
+    signature_mac(secret, timestamp, body)
+        .verify_slice(&expected)
+        .map_err(|_| SignatureError::Mismatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"destination-secret";
+    const SIGNED_AT: i64 = 1_790_000_000;
+
+    #[test]
+    fn test_signature_round_trip() {
+        let body = br#"{"event":"security_event"}"#;
+        let signature = WebhookSigner::new(SECRET).sign(SIGNED_AT, body);
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), 7 + 64);
+
+        let timestamp = SIGNED_AT.to_string();
+        let verify = |secret: &[u8], body: &[u8], now: i64| {
+            verify_signature(secret, &timestamp, &signature, body, now, DEFAULT_TOLERANCE_SECONDS)
+        };
+        assert_eq!(verify(SECRET, body, SIGNED_AT + 10), Ok(()));
+        assert_eq!(verify(b"another-secret", body, SIGNED_AT), Err(SignatureError::Mismatch));
+        assert_eq!(verify(SECRET, br#"{"event":"forged"}"#, SIGNED_AT), Err(SignatureError::Mismatch));
+    }
+
+    #[test]
+    fn test_replays_outside_tolerance_are_refused() {
+        let body = b"{}";
+        let signature = WebhookSigner::new(SECRET).sign(SIGNED_AT, body);
+        let timestamp = SIGNED_AT.to_string();
+        let at = |now: i64| verify_signature(SECRET, &timestamp, &signature, body, now, DEFAULT_TOLERANCE_SECONDS);
+
+        assert_eq!(at(SIGNED_AT + DEFAULT_TOLERANCE_SECONDS), Ok(()));
+        assert_eq!(at(SIGNED_AT + DEFAULT_TOLERANCE_SECONDS + 1), Err(SignatureError::OutsideTolerance));
+        assert_eq!(at(SIGNED_AT - DEFAULT_TOLERANCE_SECONDS - 1), Err(SignatureError::OutsideTolerance));
+
+        // Re-stamping a captured request breaks its signature
+        let restamped = (SIGNED_AT + 600).to_string();
+        assert_eq!(
+            verify_signature(SECRET, &restamped, &signature, body, SIGNED_AT + 600, DEFAULT_TOLERANCE_SECONDS),
+            Err(SignatureError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn test_malformed_headers_are_refused() {
+        let signature = WebhookSigner::new(SECRET).sign(SIGNED_AT, b"{}");
+        let check = |timestamp: &str, signature: &str| {
+            verify_signature(SECRET, timestamp, signature, b"{}", SIGNED_AT, DEFAULT_TOLERANCE_SECONDS)
+        };
+
+        assert_eq!(check("yesterday", &signature), Err(SignatureError::Malformed));
+        assert_eq!(check(&SIGNED_AT.to_string(), &signature[7..]), Err(SignatureError::Malformed));
+        assert_eq!(check(&SIGNED_AT.to_string(), "sha256=zz"), Err(SignatureError::Malformed));
+    }
+}
@@ -0,0 +1,390 @@
+//! Fixtures for tests that drive the auth stack: an in-memory database with
+//! every migration applied, the real middleware chain and routes, users in
+//! any role, and a clock tests move by hand.
+
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, web};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::SqlitePool;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::handlers::auth_handler::AppState;
+use crate::handlers::well_known_handler::WellKnown;
+use crate::middleware::admin_reads::AdminReadSampling;
+use crate::middleware::maintenance::MaintenanceState;
+use crate::middleware::origin_guard::{CorsPolicy, CorsRejections};
+use crate::middleware::security::RateLimits;
+use crate::models::auth::SecurityConfig;
+use crate::models::context::RequestContext;
+use crate::models::security_txt::SecurityTxt;
+use crate::models::user::{DeviceApprovalRequest, LoginRequest, UserRole};
+use crate::services::api_key_service::{ApiKey, ApiKeyScope, ApiKeys};
+use crate::services::audit_bundle::{test_signer, AuditSigning};
+use crate::services::auth_service::AuthService;
+use crate::services::audit_archive_service::AuditArchiveService;
+use crate::services::backup_service::BackupService;
+use crate::services::config_snapshot_service::ConfigSnapshotService;
+use crate::services::csp_report_service::CspReportService;
+use crate::services::feature_flags::{FeatureDefaults, FeatureFlags};
+use crate::services::geoip_service::GeoIpService;
+use crate::services::health_monitor::HealthMonitor;
+use crate::services::client_app_service::{ClientApp, ClientRegistry};
+use crate::services::lockdown_service::LockdownService;
+use crate::services::password_service::PasswordService;
+use crate::services::security_posture::SecurityPostureCheck;
+use crate::services::system_message_service::SystemMessageService;
+use crate::services::throttle_state::ThrottleState;
+use crate::services::token_service::TokenService;
+use crate::services::tracer::Tracer;
+use crate::services::two_fa_service::{TwoFAService, DEFAULT_ISSUER};
+use crate::services::webhook_service::{RetryPolicy, WebhookService};
+use crate::utils::clock::Clock;
+use crate::utils::database::test_pool;
+
+/// Password given to fixture users unless a test picks its own
+pub const TEST_PASSWORD: &str = "TestPassw0rd987!";
+
+/// API key of the test app's HR integration, allowed `hr_events`
+pub const TEST_API_KEY: &str = "test-hr-api-key";
+
+/// Source tag the test app's HR integration is audited under
+pub const TEST_API_KEY_SOURCE: &str = "workday";
+
+/// Per-client quota for the test app; high enough that only tests aimed at
+/// rate limiting ever reach it
+const TEST_RATE_LIMIT_PER_MINUTE: u32 = 1000;
+
+/// Browser origin the test app allows, as `CORS_ORIGINS`
+pub const TEST_ORIGIN: &str = "https://kenya.fsfvi.ai";
+
+/// Browser origin the test app allows on the admin scope, as `CORS_ADMIN_ORIGINS`
+pub const TEST_ADMIN_ORIGIN: &str = "https://admin.kenya.fsfvi.ai";
+
+fn test_cors_policy() -> Arc<CorsPolicy> {
+    let policy = CorsPolicy::new(vec![TEST_ORIGIN.to_string()], false).unwrap();
+    Arc::new(policy.with_admin_origins(vec![TEST_ADMIN_ORIGIN.to_string()]).unwrap())
+}
+
+/// A clock that only moves when told to. Starts at the real current time.
+pub struct MockClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self { now: Mutex::new(Utc::now()) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// A user created by the fixtures, with what a test needs to sign in as them
+#[derive(Clone)]
+pub struct TestUser {
+    pub id: Uuid,
+    pub username: String,
+    pub password: String,
+    /// Present when the user was created with 2FA enabled
+    pub two_fa_secret: Option<String>,
+    pub backup_codes: Vec<String>,
+    /// The server's clock, so codes match the window it checks
+    clock: Arc<dyn Clock>,
+}
+
+impl TestUser {
+    /// A login request with the user's password and, for 2FA users, a current code
+    pub fn login_request(&self) -> LoginRequest {
+        LoginRequest {
+            username: self.username.clone(),
+            password: self.password.clone(),
+            two_fa_code: self.totp().map(|code| code.parse().unwrap()),
+            website: None,
+            form_issued_at: None,
+            sign_out_other_sessions: false,
+            client_id: None,
+        }
+    }
+
+    /// The authenticator code for the server's current time
+    pub fn totp(&self) -> Option<String> {
+        self.two_fa_secret
+            .as_ref()
+            .map(|secret| two_fa_service(self.clock.clone()).generate_totp(secret, None).unwrap())
+    }
+}
+
+fn two_fa_service(clock: Arc<dyn Clock>) -> TwoFAService {
+    TwoFAService::new(DEFAULT_ISSUER.to_string(), clock)
+}
+
+/// Insert a user straight into the database, already onboarded
+pub async fn insert_user(
+    pool: &SqlitePool,
+    clock: Arc<dyn Clock>,
+    username: &str,
+    role: UserRole,
+    password: &str,
+    two_fa: bool,
+) -> TestUser {
+    let user_id = Uuid::new_v4();
+    let now = clock.now();
+    let password_hash = PasswordService::new().hash_password(password).unwrap();
+
+    let two_fa_service = two_fa_service(clock.clone());
+    let (two_fa_secret, backup_codes) = if two_fa {
+        (Some(two_fa_service.generate_secret()), two_fa_service.generate_backup_codes(10))
+    } else {
+        (None, Vec::new())
+    };
+    let hashed_backup_codes = two_fa.then(|| two_fa_service.hash_backup_codes(&backup_codes).unwrap());
+
+    sqlx::query(
+        r#"
+        INSERT INTO users (id, username, password_hash, role, is_temporary_password,
+                         created_at, updated_at, login_attempts, is_locked, two_fa_enabled,
+                         two_fa_secret, two_fa_backup_codes, two_fa_enabled_at, onboarded_at)
+        VALUES (?, ?, ?, ?, false, ?, ?, 0, false, ?, ?, ?, ?, ?)
+        "#
+    )
+    .bind(user_id)
+    .bind(username)
+    .bind(password_hash)
+    .bind(role.as_str())
+    .bind(now)
+    .bind(now)
+    .bind(two_fa)
+    .bind(&two_fa_secret)
+    .bind(hashed_backup_codes)
+    .bind(two_fa.then_some(now))
+    .bind(now)
+    .execute(pool)
+    .await
+    .unwrap();
+
+    TestUser {
+        id: user_id,
+        username: username.to_string(),
+        password: password.to_string(),
+        two_fa_secret,
+        backup_codes,
+        clock,
+    }
+}
+
+/// Application state over `pool`, wired the way `main` wires it
+pub fn app_state(
+    pool: &SqlitePool,
+    config: SecurityConfig,
+    clock: Arc<dyn Clock>,
+    tracer: Arc<Tracer>,
+    server_timing: bool,
+) -> web::Data<AppState> {
+    let throttle = Arc::new(ThrottleState::default());
+    let security_txt_expires = clock.now() + Duration::days(180);
+    let backup_dir = std::env::temp_dir().join(format!("kenya_fsfvi_test_backups-{}", Uuid::new_v4()));
+    let archive_dir = std::env::temp_dir().join(format!("kenya_fsfvi_test_audit_archives-{}", Uuid::new_v4()));
+    let features = Arc::new(FeatureFlags::new(pool.clone(), clock.clone(), FeatureDefaults::default()));
+    let lockdown = Arc::new(LockdownService::new(pool.clone(), clock.clone()));
+    let clients = Arc::new(ClientRegistry::new(pool.clone(), clock.clone()));
+    web::Data::new(AppState {
+        auth_service: AuthService::new(
+            pool.clone(),
+            PasswordService::new(),
+            TokenService::new(config, clock.clone()),
+            Arc::new(GeoIpService::disabled()),
+            clock.clone(),
+        )
+        .with_throttle_state(throttle.clone())
+        .with_feature_flags(features.clone())
+        .with_lockdown(lockdown.clone())
+        .with_client_registry(clients.clone()),
+        maintenance: Arc::new(MaintenanceState::new(false)),
+        throttle,
+        csp_reports: CspReportService::new(pool.clone()),
+        system_messages: SystemMessageService::new(pool.clone(), clock.clone()),
+        cors_rejections: Arc::new(CorsRejections::default()),
+        degraded: None,
+        well_known: WellKnown {
+            security_txt: Some(
+                SecurityTxt {
+                    contacts: vec!["mailto:security@kenya.fsfvi.ai".to_string()],
+                    expires: security_txt_expires,
+                    policy: Some("https://kenya.fsfvi.ai/security-policy".to_string()),
+                    preferred_languages: vec!["en".to_string(), "sw".to_string()],
+                }
+                .render(),
+            ),
+            change_password_url: "https://kenya.fsfvi.ai/change-password".to_string(),
+        },
+        backups: BackupService::new(pool.clone(), backup_dir, 3, clock.clone()),
+        audit_archives: AuditArchiveService::new(pool.clone(), archive_dir, clock.clone()).with_reads_enabled(true),
+        webhooks: Arc::new(WebhookService::new(pool.clone(), Vec::new(), RetryPolicy::default(), clock.clone())),
+        config_snapshots: ConfigSnapshotService::new(pool.clone(), clock.clone(), AppConfig::test_config().snapshot()),
+        features,
+        audit_signing: AuditSigning::Ready(test_signer()),
+        admin_reads: AdminReadSampling::default(),
+        health: Arc::new(HealthMonitor::new(clock)),
+        posture: SecurityPostureCheck::new(AppConfig::test_config()),
+        lockdown,
+        clients,
+        api_keys: ApiKeys::new(&[ApiKey {
+            name: TEST_API_KEY_SOURCE.to_string(),
+            secret: TEST_API_KEY.to_string(),
+            scopes: vec![ApiKeyScope::HrEvents],
+        }]),
+        tracer,
+        server_timing,
+    })
+}
+
+/// The whole server over a fresh in-memory database
+pub struct TestApp<S> {
+    pub service: S,
+    pub data: web::Data<AppState>,
+    pub pool: SqlitePool,
+    pub clock: Arc<MockClock>,
+}
+
+impl TestApp<()> {
+    pub async fn spawn() -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>> {
+        Self::spawn_with(SecurityConfig::default()).await
+    }
+
+    pub async fn spawn_with(
+        config: SecurityConfig,
+    ) -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>> {
+        Self::spawn_on(test_pool().await, config).await
+    }
+
+    /// The whole server over `pool`, for tests that need a file-backed database
+    pub async fn spawn_on(
+        pool: SqlitePool,
+        config: SecurityConfig,
+    ) -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>> {
+        Self::spawn_traced_on(pool, config, Arc::new(Tracer::disabled()), false).await
+    }
+
+    /// The whole server with its requests traced by `tracer`
+    pub async fn spawn_traced(
+        tracer: Arc<Tracer>,
+    ) -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>> {
+        Self::spawn_traced_on(test_pool().await, SecurityConfig::default(), tracer, false).await
+    }
+
+    /// The whole server, sending the `Server-Timing` header of the measured endpoints
+    pub async fn spawn_with_server_timing() -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>> {
+        Self::spawn_traced_on(test_pool().await, SecurityConfig::default(), Arc::new(Tracer::disabled()), true).await
+    }
+
+    async fn spawn_traced_on(
+        pool: SqlitePool,
+        config: SecurityConfig,
+        tracer: Arc<Tracer>,
+        server_timing: bool,
+    ) -> TestApp<impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>> {
+        let clock = Arc::new(MockClock::new());
+        let data = app_state(&pool, config, clock.clone(), tracer, server_timing);
+        let rate_limits = Arc::new(RateLimits::new(TEST_RATE_LIMIT_PER_MINUTE));
+        let service = test::init_service(crate::build_app(data.clone(), rate_limits, test_cors_policy())).await;
+
+        TestApp { service, data, pool, clock }
+    }
+}
+
+impl<S, B> TestApp<S>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    pub fn auth_service(&self) -> &AuthService {
+        &self.data.auth_service
+    }
+
+    /// The middleware the app's scopes were built with, for building them again
+    pub fn scope_layers(&self) -> crate::ScopeLayers {
+        crate::ScopeLayers::new(&self.data, Arc::new(RateLimits::new(TEST_RATE_LIMIT_PER_MINUTE)), test_cors_policy())
+    }
+
+    pub async fn call(&self, request: test::TestRequest) -> ServiceResponse<B> {
+        test::call_service(&self.service, request.to_request()).await
+    }
+
+    /// Send the request and parse the JSON body
+    pub async fn call_json(&self, request: test::TestRequest) -> serde_json::Value {
+        test::call_and_read_body_json(&self.service, request.to_request()).await
+    }
+
+    /// Send a request that should be refused and return its status and `error_type`
+    pub async fn call_error(&self, request: test::TestRequest) -> (u16, String) {
+        let response = self.call(request).await;
+        let status = response.status().as_u16();
+        let body: serde_json::Value = test::read_body_json(response).await;
+        (status, body["error_type"].as_str().unwrap_or_default().to_string())
+    }
+
+    pub async fn create_user(&self, username: &str, role: UserRole, password: &str, two_fa: bool) -> TestUser {
+        insert_user(&self.pool, self.clock.clone(), username, role, password, two_fa).await
+    }
+
+    /// Sign in through the auth service and return the session token
+    pub async fn login_as(&self, user: &TestUser, ip_address: &str) -> String {
+        let ctx = RequestContext::new(ip_address, Some("test-agent"));
+        self.data.auth_service.authenticate(&ctx, user.login_request()).await.unwrap().token
+    }
+
+    /// Sign a kiosk in as the user, approved on their behalf, and return the kiosk's token
+    pub async fn kiosk_login_as(&self, user: &TestUser, ip_address: &str) -> String {
+        let ctx = RequestContext::new(ip_address, Some("kiosk-agent"));
+        let client = ClientApp::default_app();
+        let auth_service = &self.data.auth_service;
+        let started = auth_service.start_device_authorization(&client).await.unwrap();
+        let approval = DeviceApprovalRequest { user_code: started.user_code, approve: true };
+        auth_service.decide_device_authorization(&ctx, user.id, &user.username, approval).await.unwrap();
+        auth_service.poll_device_authorization(&ctx, &started.polling_token, &client).await.unwrap().token
+    }
+
+    /// Re-enter the password on the session so step-up protected endpoints
+    /// accept it, returning the token that replaces `token`
+    pub async fn step_up(&self, token: &str, user: &TestUser) -> String {
+        let request = bearer(test::TestRequest::post().uri("/api/auth/step-up"), token)
+            .set_json(serde_json::json!({ "password": user.password, "two_fa_code": user.totp() }));
+        let body = self.call_json(request).await;
+        assert_eq!(body["success"], true, "{}", body);
+        body["data"]["token"].as_str().unwrap().to_string()
+    }
+}
+
+/// Next Server-Sent Events message of a streaming body, skipping keep-alive
+/// comments, or `None` once the stream ends. Panics if nothing arrives in time.
+pub async fn next_event<B: MessageBody>(body: &mut Pin<Box<B>>) -> Option<String> {
+    loop {
+        let chunk = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            std::future::poll_fn(|cx| body.as_mut().poll_next(cx)),
+        )
+        .await
+        .expect("no event within 5 seconds")?;
+        let text = String::from_utf8(chunk.unwrap_or_else(|_| panic!("event stream failed")).to_vec()).unwrap();
+        if !text.starts_with(':') {
+            return Some(text);
+        }
+    }
+}
+
+/// Add `Authorization: Bearer <token>` to a request
+pub fn bearer(request: test::TestRequest, token: &str) -> test::TestRequest {
+    request.insert_header(("Authorization", format!("Bearer {}", token)))
+}
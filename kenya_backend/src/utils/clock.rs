@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+
+/// Source of the current time for token, session, lockout and TOTP decisions.
+///
+/// Services take it in their constructors so tests can move time forward instead of
+/// sleeping through expiries.
+///
+/// It is the only source: timestamps are generated here, stored as UTC, and
+/// queries compare against bound values computed from it, never against the
+/// database's own `datetime('now')`. Mixing the two lets a host whose clock
+/// disagrees with the database's keep lockouts forever or end sessions early.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
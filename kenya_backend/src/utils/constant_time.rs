@@ -0,0 +1,30 @@
+// Comparisons of secret material that take the same time wherever the inputs differ
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Whether two secrets are equal, in time that doesn't depend on where they
+/// differ or how long they are. Use it for every comparison involving a
+/// code, token or key a client supplied; `==` on those leaks the length of
+/// the matching prefix.
+pub fn ct_eq_str(a: &str, b: &str) -> bool {
+    // Hashing first makes both sides the same length, so the length of the
+    // stored secret isn't revealed either
+    let a = Sha256::digest(a.as_bytes());
+    let b = Sha256::digest(b.as_bytes());
+    a.ct_eq(&b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_eq_str() {
+        assert!(ct_eq_str("AB12CD34", "AB12CD34"));
+        assert!(ct_eq_str("", ""));
+        assert!(!ct_eq_str("AB12CD34", "AB12CD35"));
+        assert!(!ct_eq_str("AB12CD34", "AB12CD3"));
+        assert!(!ct_eq_str("AB12CD34", "ab12cd34"));
+        assert!(!ct_eq_str("", "AB12CD34"));
+    }
+}
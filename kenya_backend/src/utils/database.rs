@@ -0,0 +1,336 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::SqlitePool;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::utils::clock::Clock;
+use crate::utils::legacy_schema::{self, LegacyShape};
+
+/// Schema migrations in the order they must be applied
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("001_initial", include_str!("../../migrations/001_initial.sql")),
+    ("002_account_status", include_str!("../../migrations/002_account_status.sql")),
+    (
+        "003_security_event_acknowledgement",
+        include_str!("../../migrations/003_security_event_acknowledgement.sql"),
+    ),
+    ("004_security_event_severity", include_str!("../../migrations/004_security_event_severity.sql")),
+    ("005_login_attempt_geoip", include_str!("../../migrations/005_login_attempt_geoip.sql")),
+    ("006_login_attempt_coordinates", include_str!("../../migrations/006_login_attempt_coordinates.sql")),
+    ("007_notifications", include_str!("../../migrations/007_notifications.sql")),
+    ("008_break_glass", include_str!("../../migrations/008_break_glass.sql")),
+    ("009_sessions", include_str!("../../migrations/009_sessions.sql")),
+    ("010_csp_reports", include_str!("../../migrations/010_csp_reports.sql")),
+    ("011_permissions", include_str!("../../migrations/011_permissions.sql")),
+    ("012_onboarding", include_str!("../../migrations/012_onboarding.sql")),
+    ("013_ip_address_lookup", include_str!("../../migrations/013_ip_address_lookup.sql")),
+    ("014_two_fa_fingerprint", include_str!("../../migrations/014_two_fa_fingerprint.sql")),
+    ("015_session_metrics", include_str!("../../migrations/015_session_metrics.sql")),
+    ("016_webhook_dead_letters", include_str!("../../migrations/016_webhook_dead_letters.sql")),
+    ("017_org_security_policies", include_str!("../../migrations/017_org_security_policies.sql")),
+    ("018_terms_acceptances", include_str!("../../migrations/018_terms_acceptances.sql")),
+    ("019_config_snapshots", include_str!("../../migrations/019_config_snapshots.sql")),
+    ("020_pending_admin_actions", include_str!("../../migrations/020_pending_admin_actions.sql")),
+    ("021_feature_flags", include_str!("../../migrations/021_feature_flags.sql")),
+    ("022_data_exports", include_str!("../../migrations/022_data_exports.sql")),
+    ("023_account_notes", include_str!("../../migrations/023_account_notes.sql")),
+    ("024_password_resets", include_str!("../../migrations/024_password_resets.sql")),
+    ("025_session_token_ids", include_str!("../../migrations/025_session_token_ids.sql")),
+    ("026_system_messages", include_str!("../../migrations/026_system_messages.sql")),
+    ("027_session_token_kid", include_str!("../../migrations/027_session_token_kid.sql")),
+    ("028_login_challenges", include_str!("../../migrations/028_login_challenges.sql")),
+    ("029_lockdowns", include_str!("../../migrations/029_lockdowns.sql")),
+    ("030_client_apps", include_str!("../../migrations/030_client_apps.sql")),
+    ("031_ip_privacy", include_str!("../../migrations/031_ip_privacy.sql")),
+    ("032_integration_events", include_str!("../../migrations/032_integration_events.sql")),
+    ("033_device_authorizations", include_str!("../../migrations/033_device_authorizations.sql")),
+    ("034_user_directory_indexes", include_str!("../../migrations/034_user_directory_indexes.sql")),
+    ("035_audit_archives", include_str!("../../migrations/035_audit_archives.sql")),
+    ("036_legacy_password_hashes", include_str!("../../migrations/036_legacy_password_hashes.sql")),
+    ("037_bootstrap_lock", include_str!("../../migrations/037_bootstrap_lock.sql")),
+    ("038_secret_rotation", include_str!("../../migrations/038_secret_rotation.sql")),
+    ("039_audit_metadata_markers", include_str!("../../migrations/039_audit_metadata_markers.sql")),
+    ("040_user_alert_dismissals", include_str!("../../migrations/040_user_alert_dismissals.sql")),
+    ("041_pending_notifications", include_str!("../../migrations/041_pending_notifications.sql")),
+];
+
+/// Versions of the migrations this binary ships, oldest first
+pub fn migration_versions() -> impl Iterator<Item = &'static str> {
+    MIGRATIONS.iter().map(|(version, _)| *version)
+}
+
+/// Run any schema migrations the database hasn't seen yet, after bringing
+/// a known legacy schema forward
+pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // Before schema_migrations is created, since whether it exists tells the shapes apart
+    let legacy = legacy_schema::detect(pool).await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version TEXT PRIMARY KEY NOT NULL,
+            applied_at TEXT NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await?;
+
+    if let Some(shape) = legacy {
+        log::warn!(
+            "Database has the legacy schema of {}; applying migration {} to bring it forward",
+            shape,
+            shape.migration().0
+        );
+    }
+    let legacy_migrations = legacy.map(LegacyShape::migration);
+
+    for (version, migration_sql) in legacy_migrations.iter().chain(MIGRATIONS) {
+        let applied: Option<String> = sqlx::query_scalar("SELECT version FROM schema_migrations WHERE version = ?")
+            .bind(version)
+            .fetch_optional(pool)
+            .await?;
+        if applied.is_some() {
+            continue;
+        }
+
+        log::info!("Applying migration {}", version);
+        let mut tx = pool.begin().await?;
+
+        for statement in split_statements(migration_sql) {
+            sqlx::query(&statement).execute(&mut *tx).await?;
+        }
+
+        sqlx::query("INSERT INTO schema_migrations (version, applied_at) VALUES (?, ?)")
+            .bind(version)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// Split a migration into its statements on `;`, keeping each
+/// `CREATE TRIGGER ... BEGIN ... END` whole although its body holds `;` too.
+/// A `;` inside a `--` comment or a string literal doesn't end a statement.
+fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut chars = sql.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => in_string = !in_string,
+            '-' if !in_string && chars.peek() == Some(&'-') => {
+                // The comment runs to the end of the line, `;` and all
+                current.push(c);
+                for c in chars.by_ref() {
+                    current.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            ';' if !in_string && !is_open_trigger(&current) => {
+                push_statement(&mut statements, &current);
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    push_statement(&mut statements, &current);
+    statements
+}
+
+/// Add `statement` unless it is only whitespace and comments
+fn push_statement(statements: &mut Vec<String>, statement: &str) {
+    let statement = statement.trim();
+    if !without_comments(statement).is_empty() {
+        statements.push(statement.to_string());
+    }
+}
+
+/// `statement` with its whole-line `--` comments removed, trimmed
+fn without_comments(statement: &str) -> String {
+    statement
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Whether `statement` is a trigger whose body hasn't reached its `END` yet
+fn is_open_trigger(statement: &str) -> bool {
+    let code = without_comments(statement).to_uppercase();
+    code.starts_with("CREATE TRIGGER") && !code.ends_with("END")
+}
+
+/// Skew between the application clock and the database's, above which
+/// startup logs a warning
+pub const CLOCK_SKEW_WARNING_SECONDS: i64 = 5;
+
+/// How far `clock` runs ahead of the database's `'now'` (negative when it
+/// runs behind). Only the application clock decides expiries and windows;
+/// this exists to report a host whose clock disagrees with the database's.
+pub async fn clock_skew(pool: &SqlitePool, clock: &dyn Clock) -> Result<Duration, sqlx::Error> {
+    let database_now: String = sqlx::query_scalar("SELECT strftime('%Y-%m-%dT%H:%M:%fZ', 'now')")
+        .fetch_one(pool)
+        .await?;
+    let database_now = DateTime::parse_from_rfc3339(&database_now)
+        .map_err(|e| sqlx::Error::Decode(Box::new(e)))?
+        .with_timezone(&Utc);
+    Ok(clock.now() - database_now)
+}
+
+/// Where read-only queries go: a replica when `DATABASE_READ_URL` is set,
+/// otherwise the primary. The replica lags behind the primary, so only
+/// reads that can show slightly stale data belong here - dashboards,
+/// listings and exports. Writes, and reads that decide anything (sign-in,
+/// session checks, a row just written), stay on the primary.
+#[derive(Clone)]
+pub struct ReadPool {
+    primary: SqlitePool,
+    replica: Option<SqlitePool>,
+    /// Queries sent, counted so tests can check how many a listing takes
+    queries: Arc<AtomicU64>,
+}
+
+impl ReadPool {
+    /// Send every read to the primary
+    pub fn primary_only(primary: SqlitePool) -> Self {
+        Self { primary, replica: None, queries: Arc::default() }
+    }
+
+    /// Send reads to `replica`, falling back to `primary` when it fails them
+    pub fn with_replica(primary: SqlitePool, replica: SqlitePool) -> Self {
+        Self { primary, replica: Some(replica), queries: Arc::default() }
+    }
+
+    /// Run `query` on the replica, and again on the primary if the replica
+    /// returns an error. A query the primary fails too returns that error.
+    pub async fn run<'p, T, F, Fut>(&'p self, query: F) -> Result<T, sqlx::Error>
+    where
+        F: Fn(&'p SqlitePool) -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        if let Some(replica) = &self.replica {
+            match query(replica).await {
+                Ok(rows) => return Ok(rows),
+                Err(e) => log::warn!("Read replica query failed, retrying on the primary: {}", e),
+            }
+        }
+        query(&self.primary).await
+    }
+
+    /// Queries run through this pool and its clones, each counted once
+    /// whether or not it was retried on the primary
+    #[cfg(test)]
+    pub fn queries_run(&self) -> u64 {
+        self.queries.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+pub async fn test_pool() -> SqlitePool {
+    // A single connection keeps every query on the same in-memory database
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("Failed to open in-memory database");
+    run_migrations(&pool).await.expect("Failed to run migrations");
+    pool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClock;
+
+    #[actix_web::test]
+    async fn test_clock_skew_is_measured_against_the_database() {
+        let pool = test_pool().await;
+        let clock = MockClock::new();
+        assert!(clock_skew(&pool, &clock).await.unwrap().num_seconds().abs() < CLOCK_SKEW_WARNING_SECONDS);
+
+        // A host running ten seconds fast, then ten seconds slow
+        clock.advance(Duration::seconds(10));
+        let ahead = clock_skew(&pool, &clock).await.unwrap();
+        assert!(ahead > Duration::seconds(CLOCK_SKEW_WARNING_SECONDS) && ahead <= Duration::seconds(11));
+        clock.advance(Duration::seconds(-20));
+        let behind = clock_skew(&pool, &clock).await.unwrap();
+        assert!(behind < Duration::seconds(-CLOCK_SKEW_WARNING_SECONDS) && behind >= Duration::seconds(-11));
+    }
+
+    #[actix_web::test]
+    async fn test_migrations_are_recorded_and_idempotent() {
+        let pool = test_pool().await;
+
+        // A second run must skip everything already applied
+        run_migrations(&pool).await.unwrap();
+
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_migrations")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+
+    #[actix_web::test]
+    async fn test_reads_go_to_the_replica_and_fall_back_to_the_primary() {
+        let primary = test_pool().await;
+        let replica = test_pool().await;
+        sqlx::query("INSERT INTO feature_flags (name, enabled, updated_at) VALUES ('replica-only', TRUE, ?)")
+            .bind(Utc::now())
+            .execute(&replica)
+            .await
+            .unwrap();
+        async fn count(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+            sqlx::query_scalar("SELECT COUNT(*) FROM feature_flags").fetch_one(pool).await
+        }
+
+        assert_eq!(ReadPool::primary_only(primary.clone()).run(count).await.unwrap(), 0);
+        let reads = ReadPool::with_replica(primary.clone(), replica.clone());
+        assert_eq!(reads.run(count).await.unwrap(), 1);
+
+        // A replica that stops answering leaves the reads on the primary
+        replica.close().await;
+        assert_eq!(reads.run(count).await.unwrap(), 0);
+        primary.close().await;
+        assert!(reads.run(count).await.is_err());
+    }
+
+    #[test]
+    fn test_trigger_bodies_stay_in_one_statement() {
+        let sql = "-- A table\nCREATE TABLE t (id TEXT);\n\n\
+                   -- Guarded\nCREATE TRIGGER t_guard BEFORE DELETE ON t\nBEGIN\n    SELECT RAISE(ABORT, 'no');\n    SELECT 1;\nEND;\n\n\
+                   CREATE INDEX t_id ON t(id);\n";
+        let statements = split_statements(sql);
+        assert_eq!(statements.len(), 3);
+        assert!(statements[1].starts_with("-- Guarded\nCREATE TRIGGER"));
+        assert!(statements[1].ends_with("SELECT 1;\nEND"));
+        assert_eq!(statements[2], "CREATE INDEX t_id ON t(id)");
+    }
+
+    #[test]
+    fn test_semicolons_in_comments_and_strings_do_not_split() {
+        let sql = "-- Kept for a day; then purged\nCREATE TABLE t (id TEXT, note TEXT DEFAULT 'a;b');\n\n\
+                   INSERT INTO t (id) VALUES ('x'); -- trailing; comment\n\
+                   -- Closing remark; nothing after it\n";
+        let statements = split_statements(sql);
+        assert_eq!(
+            statements,
+            vec![
+                "-- Kept for a day; then purged\nCREATE TABLE t (id TEXT, note TEXT DEFAULT 'a;b')".to_string(),
+                "INSERT INTO t (id) VALUES ('x')".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+// Bounded retries for statements SQLite turns away as busy or locked
+use rand::Rng;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Tries a statement gets, the first included, before its error is returned
+pub const BUSY_RETRY_ATTEMPTS: u32 = 3;
+
+/// Seconds a client is told to wait after the retries ran out
+pub const BUSY_RETRY_AFTER_SECONDS: u64 = 1;
+
+/// Bounds of the jittered pause between tries, in milliseconds
+const BACKOFF_MIN_MS: u64 = 50;
+const BACKOFF_MAX_MS: u64 = 200;
+
+/// Whether `error` is SQLite refusing the statement because another
+/// connection holds the lock (`SQLITE_BUSY`, `SQLITE_LOCKED` or one of their
+/// extended codes). Such a statement had no effect, so running it again is safe.
+pub fn is_busy(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(db_error) => db_error
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .map(|code| matches!(code & 0xff, 5 | 6))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Busy retries since startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BusyRetryCounts {
+    /// Tries repeated after a busy or locked error
+    pub retries: u64,
+    /// Statements still busy after the last try
+    pub exhausted: u64,
+}
+
+/// Runs single statements, or whole transactions, again when SQLite reports
+/// them busy. Only wrap work that is safe to repeat: one autocommit
+/// statement, or a transaction that rolls back entirely on error. Never
+/// wrap a sequence of separately committed writes.
+#[derive(Debug, Default)]
+pub struct BusyRetry {
+    retries: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+impl BusyRetry {
+    /// Run `statement`, trying up to `BUSY_RETRY_ATTEMPTS` times while it fails as busy
+    pub async fn run<T, F, Fut>(&self, mut statement: F) -> Result<T, sqlx::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match statement().await {
+                Err(e) if is_busy(&e) && attempt < BUSY_RETRY_ATTEMPTS => {
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    attempt += 1;
+                    let pause = rand::thread_rng().gen_range(BACKOFF_MIN_MS..=BACKOFF_MAX_MS);
+                    tokio::time::sleep(Duration::from_millis(pause)).await;
+                }
+                Err(e) if is_busy(&e) => {
+                    self.exhausted.fetch_add(1, Ordering::Relaxed);
+                    log::warn!("Database still busy after {} tries: {}", BUSY_RETRY_ATTEMPTS, e);
+                    return Err(e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn counts(&self) -> BusyRetryCounts {
+        BusyRetryCounts {
+            retries: self.retries.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use sqlx::SqlitePool;
+
+    /// A pool over `path` whose connections give up on a lock at once
+    async fn impatient_pool(path: &std::path::Path) -> SqlitePool {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .busy_timeout(Duration::ZERO);
+        SqlitePoolOptions::new().max_connections(2).connect_with(options).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_busy_statement_is_retried_until_the_lock_clears() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = impatient_pool(&dir.path().join("busy.db")).await;
+        sqlx::query("CREATE TABLE t (n INTEGER)").execute(&pool).await.unwrap();
+        let retry = BusyRetry::default();
+
+        // Another connection holds the write lock for a little while
+        let mut holder = pool.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *holder).await.unwrap();
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            sqlx::query("COMMIT").execute(&mut *holder).await.unwrap();
+        };
+        let insert = retry.run(|| sqlx::query("INSERT INTO t (n) VALUES (1)").execute(&pool));
+        let (inserted, ()) = tokio::join!(insert, release);
+
+        assert_eq!(inserted.unwrap().rows_affected(), 1);
+        let counts = retry.counts();
+        assert!(counts.retries >= 1, "{counts:?}");
+        assert_eq!(counts.exhausted, 0);
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM t").fetch_one(&pool).await.unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_retries_are_bounded_and_other_errors_pass_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = impatient_pool(&dir.path().join("locked.db")).await;
+        sqlx::query("CREATE TABLE t (n INTEGER)").execute(&pool).await.unwrap();
+        let retry = BusyRetry::default();
+
+        let mut holder = pool.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *holder).await.unwrap();
+        let mut tries = 0;
+        let error = retry
+            .run(|| {
+                tries += 1;
+                sqlx::query("INSERT INTO t (n) VALUES (1)").execute(&pool)
+            })
+            .await
+            .unwrap_err();
+        assert!(is_busy(&error));
+        assert_eq!(tries, BUSY_RETRY_ATTEMPTS);
+        assert_eq!(retry.counts(), BusyRetryCounts { retries: 2, exhausted: 1 });
+        sqlx::query("ROLLBACK").execute(&mut *holder).await.unwrap();
+
+        let mut tries = 0;
+        let error = retry
+            .run(|| {
+                tries += 1;
+                sqlx::query("INSERT INTO missing (n) VALUES (1)").execute(&pool)
+            })
+            .await
+            .unwrap_err();
+        assert!(!is_busy(&error));
+        assert_eq!(tries, 1);
+        assert_eq!(retry.counts(), BusyRetryCounts { retries: 2, exhausted: 1 });
+    }
+}
@@ -0,0 +1,204 @@
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Migration that brings every known legacy schema forward. It runs before
+/// the regular migrations and is recorded in `schema_migrations` like them.
+const PILOT_TWO_FA_MIGRATION: (&str, &str) =
+    ("legacy_001_pilot_two_fa", include_str!("../../migrations/legacy/001_pilot_two_fa.sql"));
+
+/// The 2FA columns of `users` that the pilot schemas lack
+const TWO_FA_COLUMNS: [&str; 4] = ["two_fa_enabled", "two_fa_secret", "two_fa_backup_codes", "two_fa_enabled_at"];
+
+/// A schema written before this binary's migrations, that they can't bring
+/// forward on their own: `001_initial` creates its tables only when missing,
+/// so the 2FA columns never arrive and the first query that reads them fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyShape {
+    /// Created by `build_script.ps1` from `create_db.sql`, with no migrations recorded
+    BuildScript,
+    /// Created by the first `001_initial`, recorded as applied although it
+    /// lacked the 2FA columns
+    EarlyPilot,
+}
+
+impl LegacyShape {
+    /// Migration that adapts this shape, as its version and SQL
+    pub fn migration(self) -> (&'static str, &'static str) {
+        match self {
+            LegacyShape::BuildScript | LegacyShape::EarlyPilot => PILOT_TWO_FA_MIGRATION,
+        }
+    }
+}
+
+impl fmt::Display for LegacyShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LegacyShape::BuildScript => f.write_str("a users table created by create_db.sql without the 2FA columns"),
+            LegacyShape::EarlyPilot => f.write_str("an early pilot 001_initial without the 2FA columns"),
+        }
+    }
+}
+
+/// Versions of the legacy migrations, which a database may have recorded
+/// besides the regular ones
+pub fn legacy_migration_versions() -> impl Iterator<Item = &'static str> {
+    [PILOT_TWO_FA_MIGRATION.0].into_iter()
+}
+
+/// Recognize a known legacy schema, before `schema_migrations` is touched.
+/// `None` for an empty database or one already current. A `users` table
+/// with only some of the 2FA columns is no shape this binary knows; that
+/// is logged and left to the schema check to report.
+pub async fn detect(pool: &SqlitePool) -> Result<Option<LegacyShape>, sqlx::Error> {
+    let columns: HashSet<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('users')")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+    if columns.is_empty() {
+        return Ok(None);
+    }
+    let missing: Vec<&str> = TWO_FA_COLUMNS.into_iter().filter(|column| !columns.contains(*column)).collect();
+    if missing.is_empty() {
+        return Ok(None);
+    }
+    if missing.len() < TWO_FA_COLUMNS.len() {
+        log::error!(
+            "The users table lacks {} but has the other 2FA columns; no known legacy schema looks like this",
+            missing.join(", ")
+        );
+        return Ok(None);
+    }
+
+    let versioned: Option<String> =
+        sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'")
+            .fetch_optional(pool)
+            .await?;
+    Ok(Some(if versioned.is_some() { LegacyShape::EarlyPilot } else { LegacyShape::BuildScript }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstrap::AuthServiceBuilder;
+    use crate::config::AppConfig;
+    use crate::models::context::RequestContext;
+    use crate::models::user::{LoginRequest, TwoFASetupRequest};
+    use crate::services::password_service::PasswordService;
+    use crate::services::two_fa_service::TwoFAService;
+    use crate::test_support::{MockClock, TEST_PASSWORD};
+    use crate::utils::database::run_migrations;
+    use crate::utils::schema_check::check_schema;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    const PILOT_ROWS: &str = include_str!("../../test-data/legacy/pilot_rows.sql");
+
+    /// Each known shape, as the schema it was created with
+    const FIXTURES: [(LegacyShape, &str); 2] = [
+        (LegacyShape::BuildScript, include_str!("../../create_db.sql")),
+        (LegacyShape::EarlyPilot, include_str!("../../test-data/legacy/early_pilot.sql")),
+    ];
+
+    async fn legacy_pool(schema: &str) -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::raw_sql(schema).execute(&pool).await.unwrap();
+        sqlx::raw_sql(PILOT_ROWS).execute(&pool).await.unwrap();
+        pool
+    }
+
+    #[actix_web::test]
+    async fn test_legacy_shapes_are_recognized() {
+        for (shape, schema) in FIXTURES {
+            let pool = legacy_pool(schema).await;
+            assert_eq!(detect(&pool).await.unwrap(), Some(shape));
+        }
+
+        // Nothing to adapt in an empty or a current database
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        assert_eq!(detect(&pool).await.unwrap(), None);
+        run_migrations(&pool).await.unwrap();
+        assert_eq!(detect(&pool).await.unwrap(), None);
+
+        // Nor in one missing only some of the columns, which no release ever made
+        sqlx::query("ALTER TABLE users DROP COLUMN two_fa_enabled_at").execute(&pool).await.unwrap();
+        assert_eq!(detect(&pool).await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn test_legacy_databases_migrate_and_sign_in_with_two_fa() {
+        let pilot_id = Uuid::parse_str("6d1f0c2a-3b7e-4f59-9a41-2c8e5d7b1f03").unwrap();
+        for (shape, schema) in FIXTURES {
+            let pool = legacy_pool(schema).await;
+            run_migrations(&pool).await.unwrap();
+            assert!(check_schema(&pool).await.is_ok(), "{:?}", shape);
+            assert_eq!(detect(&pool).await.unwrap(), None);
+            let recorded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_migrations WHERE version = ?")
+                .bind(shape.migration().0)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(recorded, 1);
+
+            // Timestamps are in the form the application writes, and the pilot
+            // counts as onboarded from when its password was set
+            let (created_at, onboarded_at): (String, Option<String>) =
+                sqlx::query_as("SELECT created_at, onboarded_at FROM users WHERE id = ?")
+                    .bind(pilot_id)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            assert_eq!(created_at, "2024-02-12T09:31:04+00:00");
+            assert_eq!(onboarded_at.as_deref(), Some("2024-02-12T09:45:10+00:00"));
+            let timestamp: String =
+                sqlx::query_scalar("SELECT timestamp FROM login_attempts").fetch_one(&pool).await.unwrap();
+            assert_eq!(timestamp, "2024-03-01T08:15:00+00:00");
+
+            // A second start finds nothing left to do
+            run_migrations(&pool).await.unwrap();
+
+            let password_hash = PasswordService::new().hash_password(TEST_PASSWORD).unwrap();
+            sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+                .bind(password_hash)
+                .bind(pilot_id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            let config = AppConfig::test_config();
+            let clock = Arc::new(MockClock::new());
+            let service = AuthServiceBuilder::new(pool.clone(), &config).with_clock(clock.clone()).build();
+            let ctx = RequestContext::new("197.232.61.4", Some("test-agent"));
+            let login = |two_fa_code: Option<String>| LoginRequest {
+                username: "pilot_officer".to_string(),
+                password: TEST_PASSWORD.to_string(),
+                two_fa_code: two_fa_code.map(|code| code.parse().unwrap()),
+                website: None,
+                form_issued_at: None,
+                sign_out_other_sessions: false,
+                client_id: None,
+            };
+
+            service.authenticate(&ctx, login(None)).await.unwrap();
+            let prepared = service.prepare_two_fa_setup(pilot_id).await.unwrap();
+            let authenticator = TwoFAService::new(config.two_fa_issuer.clone(), clock.clone());
+            let totp_code = authenticator.generate_totp(&prepared.secret, None).unwrap();
+            let enrolled = service.setup_two_fa(pilot_id, TwoFASetupRequest { totp_code }).await.unwrap();
+            assert!(enrolled.enabled, "{:?}", shape);
+
+            // The next sign-in takes a code from the following window
+            clock.advance(chrono::Duration::seconds(30));
+            let code = authenticator.generate_totp(&prepared.secret, None).unwrap();
+            let response = service.authenticate(&ctx, login(Some(code))).await.unwrap();
+            assert!(service.validate_session(&response.token).await.unwrap().two_fa_enabled);
+        }
+    }
+}
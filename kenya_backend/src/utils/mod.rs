@@ -1 +1,9 @@
-// Utility functions for the Kenya FSFVI backend
\ No newline at end of file
+// Utility functions for the Kenya FSFVI backend
+pub mod database;
+pub mod schema_check;
+pub mod legacy_schema;
+pub mod self_test;
+pub mod clock;
+pub mod sanitize;
+pub mod constant_time;
+pub mod db_retry;
@@ -0,0 +1,99 @@
+// Cleaning of client-supplied text before it is stored or exported
+use serde_json::Value;
+
+use crate::models::auth::MAX_USER_AGENT_LENGTH;
+
+/// Longest stored username, e.g. the name typed into a failed login
+pub const DEFAULT_MAX_USERNAME_CHARS: usize = 128;
+
+/// Longest stored free-text value: descriptions, failure reasons, notification text
+pub const DEFAULT_MAX_TEXT_CHARS: usize = 1024;
+
+/// Caps, in characters, on free text written to the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextLimits {
+    pub username: usize,
+    pub user_agent: usize,
+    pub text: usize,
+}
+
+impl Default for TextLimits {
+    fn default() -> Self {
+        Self {
+            username: DEFAULT_MAX_USERNAME_CHARS,
+            user_agent: MAX_USER_AGENT_LENGTH,
+            text: DEFAULT_MAX_TEXT_CHARS,
+        }
+    }
+}
+
+/// `input` with control characters (including the escape that starts ANSI
+/// sequences) and bidirectional overrides removed, cut to `max_len` characters
+pub fn text(input: &str, max_len: usize) -> String {
+    input.chars().filter(|c| !is_unsafe(*c)).take(max_len).collect()
+}
+
+/// Like `text`, keeping line breaks, for free text written over several lines
+pub fn multiline_text(input: &str, max_len: usize) -> String {
+    input.chars().filter(|c| *c == '\n' || !is_unsafe(*c)).take(max_len).collect()
+}
+
+/// Like `text`, for raw bytes: invalid UTF-8 becomes U+FFFD
+pub fn bytes(input: &[u8], max_len: usize) -> String {
+    text(&String::from_utf8_lossy(input), max_len)
+}
+
+/// `value` with every string in it, keys included, passed through `text`
+pub fn json(value: Value, max_len: usize) -> Value {
+    match value {
+        Value::String(s) => Value::String(text(&s, max_len)),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| json(item, max_len)).collect()),
+        Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| (text(&k, max_len), json(v, max_len))).collect()),
+        other => other,
+    }
+}
+
+/// One CSV cell: a leading `=`, `+`, `-` or `@` is prefixed with `'` so
+/// spreadsheets don't read the value as a formula, and the result is quoted
+pub fn csv_field(value: &str) -> String {
+    let neutralized = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    format!("\"{}\"", neutralized.replace('"', "\"\""))
+}
+
+fn is_unsafe(c: char) -> bool {
+    c.is_control() || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_text_strips_control_characters_and_caps_length() {
+        assert_eq!(text("\u{1b}[31madmin\u{1b}[0m\r\n\0", 64), "[31madmin[0m");
+        assert_eq!(text("evil\u{202E}txt.exe", 64), "eviltxt.exe");
+        assert_eq!(text("Wanjiků Mũthoni", 6), "Wanjik");
+        assert_eq!(bytes(b"agent\xff\xfe/1.0", 64), "agent\u{FFFD}\u{FFFD}/1.0");
+        assert_eq!(multiline_text("On leave\r\n\u{1b}[2Juntil March", 64), "On leave\n[2Juntil March");
+    }
+
+    #[test]
+    fn test_json_cleans_nested_strings() {
+        let cleaned = json(json!({ "user\u{7}name": ["ok", "\u{1b}]0;pwned\u{7}"], "attempts": 3 }), 8);
+        assert_eq!(cleaned, json!({ "username": ["ok", "]0;pwned"], "attempts": 3 }));
+    }
+
+    #[test]
+    fn test_csv_field_neutralizes_formulas_and_escapes_quotes() {
+        assert_eq!(csv_field("=HYPERLINK(\"http://x\")"), "\"'=HYPERLINK(\"\"http://x\"\")\"");
+        for leading in ["+1", "-1", "@SUM(A1)"] {
+            assert!(csv_field(leading).starts_with("\"'"));
+        }
+        assert_eq!(csv_field("Mozilla/5.0, \"quoted\""), "\"Mozilla/5.0, \"\"quoted\"\"\"");
+    }
+}
@@ -0,0 +1,341 @@
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::utils::database::migration_versions;
+use crate::utils::legacy_schema::legacy_migration_versions;
+
+/// Columns each table must have for the `FromRow` structs and queries in this
+/// binary. Keep in step with the models when a migration adds a column.
+const REQUIRED_COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "users",
+        &[
+            "id", "username", "password_hash", "role", "is_temporary_password", "created_at",
+            "updated_at", "last_login", "login_attempts", "is_locked", "lockout_expiry", "is_active",
+            "password_changed_at", "session_token", "session_expires_at", "two_fa_enabled",
+            "two_fa_secret", "two_fa_backup_codes", "two_fa_enabled_at", "token_version",
+            "onboarded_at", "two_fa_fingerprint", "organization", "email", "email_verified_at",
+            "hash_scheme",
+        ],
+    ),
+    (
+        "login_attempts",
+        &[
+            "id", "user_id", "username", "ip_address", "user_agent", "success", "failure_reason",
+            "timestamp", "country_code", "city", "asn", "asn_org", "latitude", "longitude", "raw_ip",
+            "region",
+        ],
+    ),
+    (
+        "security_events",
+        &[
+            "id", "user_id", "event_type", "description", "ip_address", "user_agent", "success",
+            "timestamp", "metadata", "severity", "acknowledged_by", "acknowledged_at", "resolution_note",
+            "raw_ip",
+        ],
+    ),
+    (
+        "notifications",
+        &["id", "user_id", "kind", "message", "metadata", "created_at", "delivered_at"],
+    ),
+    (
+        "break_glass_credentials",
+        &["user_id", "passphrase_hash", "provisioned_at", "used_at"],
+    ),
+    (
+        "sessions",
+        &[
+            "id", "user_id", "jti", "ip_address", "user_agent", "created_at", "last_activity_at",
+            "expires_at", "step_up_at", "revoked_at", "revoked_by", "revoke_reason", "token_family",
+            "token_kid", "auth_epoch", "client_id",
+        ],
+    ),
+    (
+        "revoked_tokens",
+        &["jti", "session_id", "user_id", "revoked_at", "revoked_by"],
+    ),
+    (
+        "csp_reports",
+        &[
+            "id", "fingerprint", "document_uri", "violated_directive", "effective_directive",
+            "blocked_uri", "source_file", "line_number", "column_number", "disposition",
+            "user_agent", "ip_address", "first_seen_at", "last_seen_at", "count",
+        ],
+    ),
+    (
+        "user_permissions",
+        &["user_id", "permission", "granted", "updated_by", "updated_at"],
+    ),
+    (
+        "webhook_dead_letters",
+        &[
+            "delivery_id", "destination", "event_type", "url", "payload", "attempts", "last_status",
+            "last_error", "first_attempt_at", "failed_at",
+        ],
+    ),
+    (
+        "terms_acceptances",
+        &["user_id", "version", "accepted_at", "ip_address", "user_agent"],
+    ),
+    (
+        "config_snapshots",
+        &["id", "config_hash", "config", "recorded_at"],
+    ),
+    (
+        "pending_admin_actions",
+        &[
+            "id", "action", "target_user_id", "admin_id", "requested_by", "token_hash", "created_at",
+            "expires_at", "used_at",
+        ],
+    ),
+    (
+        "feature_flags",
+        &["name", "enabled", "updated_at", "updated_by"],
+    ),
+    (
+        "data_exports",
+        &[
+            "id", "user_id", "requested_by", "token_hash", "created_at", "expires_at", "completed_at",
+            "chunks", "size_bytes", "downloads", "last_downloaded_at",
+        ],
+    ),
+    (
+        "data_export_chunks",
+        &["export_id", "seq", "content"],
+    ),
+    (
+        "account_notes",
+        &["id", "user_id", "author_id", "note", "created_at", "pinned", "struck_at", "struck_by"],
+    ),
+    (
+        "user_tags",
+        &["user_id", "tag"],
+    ),
+    (
+        "password_resets",
+        &["id", "user_id", "channel", "issued_by", "token_hash", "created_at", "expires_at", "used_at"],
+    ),
+    (
+        "login_challenges",
+        &[
+            "id", "user_id", "token_digest", "session_id", "sign_out_other_sessions", "failed_codes",
+            "created_at", "expires_at", "used_at", "email_code_digest", "client_id",
+        ],
+    ),
+    (
+        "client_apps",
+        &[
+            "id", "name", "allowed_origins", "token_audience", "rate_limit_tier", "enabled", "created_by",
+            "created_at", "updated_at",
+        ],
+    ),
+    (
+        "processed_integration_events",
+        &["source", "event_id", "event_type", "user_id", "outcome", "processed_at"],
+    ),
+    (
+        "device_authorizations",
+        &["id", "user_code", "polling_token_hash", "client_id", "status", "user_id", "created_at", "expires_at", "decided_at"],
+    ),
+    (
+        "lockdowns",
+        &[
+            "id", "reason", "duration_minutes", "requested_by", "requested_at", "request_expires_at",
+            "approved_by", "auth_epoch", "started_at", "ends_at", "ended_at", "ended_by",
+        ],
+    ),
+    (
+        "system_messages",
+        &["id", "body", "severity", "starts_at", "ends_at", "created_by", "created_at", "updated_at"],
+    ),
+    (
+        "org_security_policies",
+        &[
+            "organization", "session_timeout_minutes", "max_failed_attempts", "require_two_fa",
+            "password_max_age_days", "updated_by", "updated_at",
+        ],
+    ),
+    (
+        "audit_archives",
+        &["month", "path", "security_events", "login_attempts", "size_bytes", "sha256", "archived_at"],
+    ),
+    ("bootstrap_lock", &["name", "holder", "expires_at"]),
+    (
+        "secret_stages",
+        &[
+            "id", "kind", "fingerprint", "token_hash", "staged_by", "staged_at", "expires_at", "confirmed_by",
+            "confirmed_at", "activated_at",
+        ],
+    ),
+    ("active_secrets", &["kind", "fingerprint", "activated_at"]),
+    ("user_alert_dismissals", &["user_id", "event_id", "dismissed_at"]),
+    ("pending_notifications", &["id", "destination", "event_type", "data", "created_at"]),
+];
+
+/// One way the database differs from what this binary expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaMismatch {
+    MissingTable(&'static str),
+    MissingColumn { table: &'static str, column: &'static str },
+    /// A migration this binary ships that the database has not recorded
+    MissingMigration(&'static str),
+    /// A migration the database has recorded that this binary doesn't know,
+    /// usually because a newer release already ran against it
+    UnknownMigration(String),
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaMismatch::MissingTable(table) => write!(f, "table {} is missing", table),
+            SchemaMismatch::MissingColumn { table, column } => write!(f, "column {}.{} is missing", table, column),
+            SchemaMismatch::MissingMigration(version) => write!(f, "migration {} has not been applied", version),
+            SchemaMismatch::UnknownMigration(version) => write!(
+                f,
+                "migration {} was applied by a newer release and is unknown to this binary",
+                version
+            ),
+        }
+    }
+}
+
+/// Startup schema check failure
+#[derive(Debug)]
+pub enum SchemaCheckError {
+    Database(sqlx::Error),
+    Drift(Vec<SchemaMismatch>),
+}
+
+impl fmt::Display for SchemaCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaCheckError::Database(e) => write!(f, "Schema check could not read the database: {}", e),
+            SchemaCheckError::Drift(mismatches) => {
+                let details: Vec<String> = mismatches.iter().map(|m| m.to_string()).collect();
+                write!(f, "Database schema does not match this binary: {}", details.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaCheckError {}
+
+impl From<sqlx::Error> for SchemaCheckError {
+    fn from(e: sqlx::Error) -> Self {
+        SchemaCheckError::Database(e)
+    }
+}
+
+/// Compare the migrated database against the migrations and columns this binary needs
+pub async fn check_schema(pool: &SqlitePool) -> Result<(), SchemaCheckError> {
+    let mut mismatches = Vec::new();
+
+    let applied: HashSet<String> = sqlx::query_scalar("SELECT version FROM schema_migrations")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+    let expected: Vec<&'static str> = migration_versions().collect();
+
+    mismatches.extend(
+        expected
+            .iter()
+            .filter(|version| !applied.contains(**version))
+            .map(|version| SchemaMismatch::MissingMigration(version)),
+    );
+    let legacy: Vec<&'static str> = legacy_migration_versions().collect();
+    let mut unknown: Vec<&String> = applied
+        .iter()
+        .filter(|version| !expected.contains(&version.as_str()) && !legacy.contains(&version.as_str()))
+        .collect();
+    unknown.sort();
+    mismatches.extend(unknown.into_iter().map(|version| SchemaMismatch::UnknownMigration(version.clone())));
+
+    for (table, columns) in REQUIRED_COLUMNS {
+        let present: HashSet<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?)")
+            .bind(table)
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        if present.is_empty() {
+            mismatches.push(SchemaMismatch::MissingTable(table));
+            continue;
+        }
+        mismatches.extend(
+            columns
+                .iter()
+                .filter(|column| !present.contains(**column))
+                .map(|column| SchemaMismatch::MissingColumn { table, column }),
+        );
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaCheckError::Drift(mismatches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database::test_pool;
+
+    async fn drift(pool: &SqlitePool) -> Vec<SchemaMismatch> {
+        match check_schema(pool).await {
+            Ok(()) => Vec::new(),
+            Err(SchemaCheckError::Drift(mismatches)) => mismatches,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_freshly_migrated_schema_passes() {
+        let pool = test_pool().await;
+        assert!(check_schema(&pool).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_missing_column_and_table_are_named() {
+        let pool = test_pool().await;
+        sqlx::query("ALTER TABLE users DROP COLUMN two_fa_secret").execute(&pool).await.unwrap();
+        sqlx::query("DROP TABLE notifications").execute(&pool).await.unwrap();
+
+        let mismatches = drift(&pool).await;
+        assert_eq!(
+            mismatches,
+            vec![
+                SchemaMismatch::MissingColumn { table: "users", column: "two_fa_secret" },
+                SchemaMismatch::MissingTable("notifications"),
+            ]
+        );
+
+        let message = SchemaCheckError::Drift(mismatches).to_string();
+        assert!(message.contains("users.two_fa_secret"));
+        assert!(message.contains("table notifications"));
+    }
+
+    #[actix_web::test]
+    async fn test_migration_version_mismatch_is_reported() {
+        let pool = test_pool().await;
+        sqlx::query("DELETE FROM schema_migrations WHERE version = '007_notifications'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO schema_migrations (version, applied_at) VALUES ('099_from_the_future', '2030-01-01T00:00:00Z')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            drift(&pool).await,
+            vec![
+                SchemaMismatch::MissingMigration("007_notifications"),
+                SchemaMismatch::UnknownMigration("099_from_the_future".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::user::{User, UserRole};
+use crate::services::password_service::PasswordService;
+use crate::services::token_service::TokenService;
+use crate::services::two_fa_service::TwoFAService;
+
+/// Startup self-test failure, naming the primitive that misbehaved
+#[derive(Debug)]
+pub enum SelfTestError {
+    Jwt(String),
+    PasswordHashing(String),
+    Totp(String),
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestError::Jwt(msg) => write!(f, "JWT self-test failed: {}", msg),
+            SelfTestError::PasswordHashing(msg) => write!(f, "Password hashing self-test failed: {}", msg),
+            SelfTestError::Totp(msg) => write!(f, "TOTP self-test failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SelfTestError {}
+
+/// Short, non-reversible fingerprint of a secret (first 8 hex chars of SHA-256).
+///
+/// Safe to log: operators compare fingerprints across instances to spot
+/// mismatched secrets without ever seeing the secret itself.
+pub fn secret_fingerprint(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .take(4)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Exercise every crypto primitive the server depends on with throwaway data
+pub fn run_crypto_self_test(
+    password_service: &PasswordService,
+    token_service: &TokenService,
+    two_fa_service: &TwoFAService,
+) -> Result<(), SelfTestError> {
+    check_jwt(token_service)?;
+    check_password_hashing(password_service)?;
+    check_totp(two_fa_service)?;
+    Ok(())
+}
+
+fn check_jwt(token_service: &TokenService) -> Result<(), SelfTestError> {
+    let now = Utc::now();
+    let user = User {
+        id: Uuid::new_v4(),
+        username: "self_test".to_string(),
+        password_hash: String::new(),
+        role: UserRole::KenyaGovernment,
+        is_temporary_password: false,
+        created_at: now,
+        updated_at: now,
+        last_login: None,
+        login_attempts: 0,
+        is_locked: false,
+        lockout_expiry: None,
+        is_active: true,
+        password_changed_at: None,
+        session_token: None,
+        session_expires_at: None,
+        two_fa_enabled: false,
+        two_fa_secret: None,
+        two_fa_backup_codes: None,
+        two_fa_enabled_at: None,
+        token_version: 0,
+        onboarded_at: None,
+        organization: None,
+    };
+    let session_id = TokenService::generate_session_id();
+
+    let token = token_service
+        .generate_token(&user, &session_id)
+        .map_err(|e| SelfTestError::Jwt(e.to_string()))?;
+    let validation = token_service
+        .validate_token(&token)
+        .map_err(|e| SelfTestError::Jwt(e.to_string()))?;
+
+    if validation.user_id != user.id || validation.session_id != session_id {
+        return Err(SelfTestError::Jwt("decoded claims do not match issued claims".to_string()));
+    }
+
+    Ok(())
+}
+
+fn check_password_hashing(password_service: &PasswordService) -> Result<(), SelfTestError> {
+    let password = password_service.generate_temporary_password();
+    let hash = password_service
+        .hash_password(&password)
+        .map_err(|e| SelfTestError::PasswordHashing(e.to_string()))?;
+    if !hash.starts_with("$argon2") {
+        return Err(SelfTestError::PasswordHashing("new hashes are not Argon2".to_string()));
+    }
+
+    match password_service.verify_password(&password, &hash) {
+        Ok(true) => {}
+        Ok(false) => return Err(SelfTestError::PasswordHashing("correct password rejected".to_string())),
+        Err(e) => return Err(SelfTestError::PasswordHashing(e.to_string())),
+    }
+
+    let wrong_password = format!("{}x", password);
+    if password_service.verify_password(&wrong_password, &hash).unwrap_or(false) {
+        return Err(SelfTestError::PasswordHashing("wrong password accepted".to_string()));
+    }
+
+    Ok(())
+}
+
+fn check_totp(two_fa_service: &TwoFAService) -> Result<(), SelfTestError> {
+    let secret = two_fa_service.generate_secret();
+    let code = two_fa_service
+        .generate_totp(&secret, None)
+        .map_err(|e| SelfTestError::Totp(e.to_string()))?;
+
+    match two_fa_service.verify_totp(&secret, &code) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(SelfTestError::Totp("freshly generated code rejected".to_string())),
+        Err(e) => Err(SelfTestError::Totp(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth::SecurityConfig;
+    use crate::utils::clock::SystemClock;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fingerprint_is_stable_and_short() {
+        let first = secret_fingerprint("kenya-test-secret");
+        let second = secret_fingerprint("kenya-test-secret");
+
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 8);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        // Known SHA-256 prefix so the format never drifts between releases
+        assert_eq!(secret_fingerprint("abc"), "ba7816bf");
+    }
+
+    #[test]
+    fn test_fingerprint_differs_between_secrets() {
+        assert_ne!(secret_fingerprint("secret-one"), secret_fingerprint("secret-two"));
+    }
+
+    #[test]
+    fn test_self_test_passes_with_working_services() {
+        let password_service = PasswordService::new();
+        let token_service = TokenService::new(SecurityConfig::default(), Arc::new(SystemClock));
+        let two_fa_service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
+
+        assert!(run_crypto_self_test(&password_service, &token_service, &two_fa_service).is_ok());
+    }
+
+    #[test]
+    fn test_self_test_catches_a_misconfigured_hasher() {
+        let params = argon2::Params::new(argon2::Params::DEFAULT_M_COST, 2, 1, Some(128)).unwrap();
+        let password_service = PasswordService::new().with_argon2_params(params);
+        let token_service = TokenService::new(SecurityConfig::default(), Arc::new(SystemClock));
+        let two_fa_service = TwoFAService::new("TestApp".to_string(), Arc::new(SystemClock));
+
+        let result = run_crypto_self_test(&password_service, &token_service, &two_fa_service);
+        assert!(matches!(result, Err(SelfTestError::PasswordHashing(_))));
+        assert_eq!(password_service.hashing_failures(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+#!/usr/bin/env python3
+"""Generate geoip-test.mmdb, the GeoIP fixture used by the unit tests.
+
+Writes a tiny IPv4 MaxMind DB with City and ASN fields merged into one record
+per network, so a single file can stand in for both GeoLite2 databases.
+Standard library only: run `python3 generate_geoip_fixture.py` in this directory.
+"""
+
+import ipaddress
+import struct
+
+NETWORKS = [
+    ("197.232.0.0/16", {
+        "city": {"names": {"en": "Nairobi"}},
+        "country": {"iso_code": "KE", "names": {"en": "Kenya"}},
+        "subdivisions": [{"iso_code": "30", "names": {"en": "Nairobi County"}}],
+        "location": {"latitude": -1.2833, "longitude": 36.8167},
+        "autonomous_system_number": 33771,
+        "autonomous_system_organization": "Safaricom Limited",
+    }),
+    ("81.2.69.0/24", {
+        "city": {"names": {"en": "London"}},
+        "country": {"iso_code": "GB", "names": {"en": "United Kingdom"}},
+        "subdivisions": [{"iso_code": "ENG", "names": {"en": "England"}}],
+        "location": {"latitude": 51.5142, "longitude": -0.0931},
+        "autonomous_system_number": 20712,
+        "autonomous_system_organization": "Andrews & Arnold Ltd",
+    }),
+]
+
+
+def control(type_id, size):
+    assert size < 285
+    size_field, size_bytes = (size, b"") if size < 29 else (29, bytes([size - 29]))
+    if type_id <= 7:
+        return bytes([(type_id << 5) | size_field]) + size_bytes
+    return bytes([size_field, type_id - 7]) + size_bytes
+
+
+def encode(value):
+    if isinstance(value, str):
+        raw = value.encode("utf-8")
+        return control(2, len(raw)) + raw
+    if isinstance(value, float):
+        return control(3, 8) + struct.pack(">d", value)
+    if isinstance(value, int):
+        raw = value.to_bytes((value.bit_length() + 7) // 8, "big")
+        return control(6, len(raw)) + raw
+    if isinstance(value, dict):
+        out = control(7, len(value))
+        for key, item in value.items():
+            out += encode(key) + encode(item)
+        return out
+    if isinstance(value, list):
+        return control(11, len(value)) + b"".join(encode(item) for item in value)
+    raise TypeError(value)
+
+
+def encode_uint16(value):
+    raw = value.to_bytes((value.bit_length() + 7) // 8, "big")
+    return control(5, len(raw)) + raw
+
+
+def encode_uint64(value):
+    raw = value.to_bytes((value.bit_length() + 7) // 8, "big")
+    return control(9, len(raw)) + raw
+
+
+def main():
+    data = b""
+    root = [None, None]
+    for cidr, record in NETWORKS:
+        offset = len(data)
+        data += encode(record)
+        network = ipaddress.ip_network(cidr)
+        bits = int(network.network_address)
+        node = root
+        for depth in range(network.prefixlen):
+            bit = (bits >> (31 - depth)) & 1
+            if depth == network.prefixlen - 1:
+                node[bit] = ("data", offset)
+            else:
+                if not isinstance(node[bit], list):
+                    node[bit] = [None, None]
+                node = node[bit]
+
+    nodes = []
+
+    def number(node):
+        nodes.append(node)
+        for child in node:
+            if isinstance(child, list):
+                number(child)
+
+    number(root)
+    index = {id(node): i for i, node in enumerate(nodes)}
+    node_count = len(nodes)
+
+    def record_value(child):
+        if child is None:
+            return node_count
+        if isinstance(child, list):
+            return index[id(child)]
+        return node_count + 16 + child[1]
+
+    tree = b""
+    for node in nodes:
+        for child in node:
+            tree += struct.pack(">I", record_value(child))[1:]
+
+    metadata = control(7, 9)
+    metadata += encode("node_count") + encode(node_count)
+    metadata += encode("record_size") + encode_uint16(24)
+    metadata += encode("ip_version") + encode_uint16(4)
+    metadata += encode("database_type") + encode("Kenya-FSFVI-GeoIP-Test")
+    metadata += encode("languages") + encode(["en"])
+    metadata += encode("binary_format_major_version") + encode_uint16(2)
+    metadata += encode("binary_format_minor_version") + encode_uint16(0)
+    metadata += encode("build_epoch") + encode_uint64(1700000000)
+    metadata += encode("description") + encode({"en": "Test fixture for kenya_backend GeoIP lookups"})
+
+    with open("geoip-test.mmdb", "wb") as f:
+        f.write(tree + b"\x00" * 16 + data + b"\xab\xcd\xefMaxMind.com" + metadata)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,52 @@
+-- Schema of an early pilot database: the first 001_initial, before the 2FA
+-- columns, applied and recorded by the migration runner of the time
+CREATE TABLE schema_migrations (
+    version TEXT PRIMARY KEY NOT NULL,
+    applied_at TEXT NOT NULL
+);
+INSERT INTO schema_migrations (version, applied_at) VALUES ('001_initial', '2024-02-12 09:30:00');
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY NOT NULL,
+    username TEXT UNIQUE NOT NULL,
+    password_hash TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'kenya_government',
+    is_temporary_password BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    last_login TEXT,
+    login_attempts INTEGER NOT NULL DEFAULT 0,
+    is_locked BOOLEAN NOT NULL DEFAULT FALSE,
+    lockout_expiry TEXT,
+    password_changed_at TEXT,
+    session_token TEXT,
+    session_expires_at TEXT
+);
+
+CREATE TABLE login_attempts (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT,
+    username TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    success BOOLEAN NOT NULL,
+    failure_reason TEXT,
+    timestamp TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE TABLE security_events (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT,
+    event_type TEXT NOT NULL,
+    description TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    success BOOLEAN NOT NULL,
+    timestamp TEXT NOT NULL,
+    metadata TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX idx_users_username ON users(username);
+CREATE INDEX idx_login_attempts_timestamp ON login_attempts(timestamp);
@@ -0,0 +1,14 @@
+-- Rows as the pilots wrote them, with datetime('now') timestamps. The
+-- password hash is a placeholder; tests set one for the test password. IDs
+-- are UUID blobs, as sqlx binds them.
+INSERT INTO users (id, username, password_hash, role, created_at, updated_at, last_login, password_changed_at)
+VALUES (X'6d1f0c2a3b7e4f599a412c8e5d7b1f03', 'pilot_officer', 'replaced-by-the-test', 'kenya_government',
+        '2024-02-12 09:31:04', '2024-03-01 08:15:00', '2024-03-01 08:15:00', '2024-02-12 09:45:10');
+
+INSERT INTO login_attempts (id, user_id, username, ip_address, user_agent, success, failure_reason, timestamp)
+VALUES (X'a3c9e1f25d4b4c7a8e160b9f2d3c4e51', X'6d1f0c2a3b7e4f599a412c8e5d7b1f03', 'pilot_officer',
+        '197.232.61.4', 'Mozilla/5.0', TRUE, NULL, '2024-03-01 08:15:00');
+
+INSERT INTO security_events (id, user_id, event_type, description, ip_address, user_agent, success, timestamp, metadata)
+VALUES (X'f0e4b7d29c814a3eb5f67d2a1c8e9b40', X'6d1f0c2a3b7e4f599a412c8e5d7b1f03', 'LOGIN_ATTEMPT',
+        'Successful login', '197.232.61.4', 'Mozilla/5.0', TRUE, '2024-03-01 08:15:00', NULL);